@@ -101,7 +101,11 @@ pub fn run_wallet_daemon(force_testing_mode: bool) -> Result<()> {
         };
         println!("config tor: {:?}", tor);
 
-        let client_shim = ClientShim::new(endpoint, None, tor);
+        let mut client_shim = ClientShim::new(endpoint, None, tor);
+        let events_endpoint: String = conf_rs.get("events_endpoint").unwrap();
+        if !events_endpoint.is_empty() {
+            client_shim.events_endpoint = Some(events_endpoint);
+        }
 
         let wallet_data_loc = if testing_mode {
             println!("Testing mode enabled.");
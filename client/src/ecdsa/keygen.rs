@@ -4,7 +4,7 @@ use kms::ecdsa::two_party::*;
 use super::super::utilities::requests;
 use super::super::ClientShim;
 use super::super::Result;
-use crate::wallet::shared_key::SharedKey;
+use crate::wallet::shared_key::{KeyShare, SharedKey};
 use shared_lib::structs::{KeyGenMsg1, KeyGenMsg2, Protocol, KeyGenReply1, KeyGenReply2};
 use uuid::Uuid;
 
@@ -60,10 +60,11 @@ pub fn get_master_key(
 
     Ok(SharedKey {
         id: key_gen_reply_1.user_id,
-        share: master_key,
+        share: KeyShare::Ecdsa(master_key),
         value: value.to_owned(),
         statechain_id: None,
         tx_backup_psm: None,
+        tx_kickoff_hex: None,
         proof_key: None,
         smt_proof: None,
         unspent: true,
@@ -90,6 +90,18 @@ impl From<bitcoin::secp256k1::Error> for CError {
     }
 }
 
+impl From<bitcoin::consensus::encode::Error> for CError {
+    fn from(e: bitcoin::consensus::encode::Error) -> CError {
+        CError::Generic(e.to_string())
+    }
+}
+
+impl From<bitcoin::util::psbt::Error> for CError {
+    fn from(e: bitcoin::util::psbt::Error) -> CError {
+        CError::Generic(e.to_string())
+    }
+}
+
 impl From<DaemonError> for CError {
     fn from(e: DaemonError) -> CError {
         CError::Generic(format!("{:?}", e))
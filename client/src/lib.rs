@@ -37,10 +37,12 @@ extern crate itertools;
 extern crate pyo3;
 extern crate rand;
 extern crate shared_lib;
+extern crate tungstenite;
 extern crate uuid;
 pub mod daemon;
 pub mod ecdsa;
 pub mod error;
+pub mod musig;
 pub mod state_entity;
 pub mod wallet;
 
@@ -51,8 +53,12 @@ use serde::{Deserialize, Serialize};
 use pyo3::prelude::*;
 use pyo3::{py_run, PyCell, PyObjectProtocol};
 
+use bitcoin::secp256k1::SecretKey;
 use config::Config as ConfigRs;
 use error::CError;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
 
 pub type Result<T> = std::result::Result<T, CError>;
 
@@ -64,6 +70,9 @@ pub mod tor {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub endpoint: String,
+    /// WebSocket endpoint of the state entity event stream. Empty to poll for swap and
+    /// transfer progress.
+    pub events_endpoint: String,
     pub electrum_server: String,
     pub testing_mode: bool,
     pub tor: Tor,
@@ -75,6 +84,7 @@ impl Config {
         let tor = Tor::from_config(&cfg);
         Ok(Config {
             endpoint: cfg.get("endpoint")?,
+            events_endpoint: cfg.get("events_endpoint")?,
             electrum_server: cfg.get("electrum_server")?,
             testing_mode: cfg.get("testing_mode")?,
             tor,
@@ -86,6 +96,7 @@ impl Default for Config {
     fn default() -> Config {
         Config {
             endpoint: "http://localhost:8000".to_string(),
+            events_endpoint: String::default(),
             electrum_server: "127.0.0.1:60401".to_string(),
             testing_mode: true,
             tor: Tor::default(),
@@ -221,14 +232,24 @@ pub struct ClientShim {
     pub tor: Option<Tor>,
     pub auth_token: Option<String>,
     pub endpoint: String,
+    /// Proof keys of sessions by shared key ID. Requests within a session are signed by its
+    /// proof key.
+    pub session_keys: Arc<Mutex<HashMap<Uuid, SecretKey>>>,
+    /// WebSocket endpoint of the event stream, if swap and transfer progress is pushed rather
+    /// than polled
+    pub events_endpoint: Option<String>,
 }
 
 impl ClientShim {
     pub fn from_config(config: &Config) -> ClientShim {
-        match config.tor.enable {
+        let mut cs = match config.tor.enable {
             true => Self::new(config.endpoint.to_owned(), None, Some(config.tor.clone())),
             false => Self::new(config.endpoint.to_owned(), None, None),
+        };
+        if !config.events_endpoint.is_empty() {
+            cs.events_endpoint = Some(config.events_endpoint.to_owned());
         }
+        cs
     }
 
     pub fn new(endpoint: String, auth_token: Option<String>, tor: Option<Tor>) -> ClientShim {
@@ -238,10 +259,29 @@ impl ClientShim {
             tor,
             auth_token,
             endpoint,
+            session_keys: Arc::new(Mutex::new(HashMap::new())),
+            events_endpoint: None,
         };
         cs
     }
 
+    /// Sign requests within the session shared_key_id with proof_key
+    pub fn add_session_key(&self, shared_key_id: &Uuid, proof_key: &SecretKey) {
+        self.session_keys
+            .lock()
+            .unwrap()
+            .insert(*shared_key_id, *proof_key);
+    }
+
+    /// Proof keys of the sessions shared_key_ids, if all are known
+    pub fn get_session_keys(&self, shared_key_ids: &[Uuid]) -> Option<Vec<SecretKey>> {
+        let session_keys = self.session_keys.lock().unwrap();
+        shared_key_ids
+            .iter()
+            .map(|id| session_keys.get(id).cloned())
+            .collect()
+    }
+
     pub fn new_client(tor: Option<&Tor>) -> reqwest::blocking::Client {
         match tor {
             None => reqwest::blocking::Client::new(),
@@ -0,0 +1,107 @@
+//! MuSig2
+//!
+//! MuSig2 key generation and co-signing with the State Entity for Taproot statecoins
+
+use super::utilities::requests;
+use super::ClientShim;
+use super::Result;
+use crate::error::CError;
+use crate::wallet::shared_key::{KeyShare, MuSigShare, SharedKey};
+use shared_lib::{
+    musig::{nonce_gen, MuSigSession},
+    structs::{
+        MuSigKeyGenMsg, MuSigKeyGenReply, MuSigSignMsg1, MuSigSignMsg2, MuSigSignReply1,
+        Protocol,
+    },
+};
+
+use curv::cryptographic_primitives::proofs::sigma_dlog::{DLogProof, ProveDLog};
+use curv::FE;
+use uuid::Uuid;
+
+const MUSIG_PATH_PRE: &str = "musig";
+
+/// Generate the shared key P = O + S with the State Entity, where O is the public key of
+/// secret_key. In transfer the State Entity key share S is the share set by transfer_receiver.
+pub fn get_musig_key(
+    shared_key_id: &Uuid,
+    client_shim: &ClientShim,
+    secret_key: &FE,
+    value: &u64,
+    protocol: Protocol,
+) -> Result<SharedKey> {
+    let dlog_proof = DLogProof::prove(secret_key);
+    let owner_pub = dlog_proof.pk;
+
+    let key_gen_reply: MuSigKeyGenReply = requests::postb(
+        client_shim,
+        &format!("{}/keygen", MUSIG_PATH_PRE),
+        MuSigKeyGenMsg {
+            shared_key_id: *shared_key_id,
+            protocol,
+            dlog_proof,
+        },
+    )?;
+
+    if DLogProof::verify(&key_gen_reply.dlog_proof).is_err() {
+        return Err(CError::StateEntityError(String::from(
+            "MuSig key generation: State Entity key share proof of knowledge is invalid.",
+        )));
+    }
+
+    Ok(SharedKey {
+        id: *shared_key_id,
+        share: KeyShare::Taproot(MuSigShare {
+            private: *secret_key,
+            public: owner_pub + key_gen_reply.dlog_proof.pk,
+        }),
+        value: value.to_owned(),
+        statechain_id: None,
+        tx_backup_psm: None,
+        tx_kickoff_hex: None,
+        proof_key: None,
+        smt_proof: None,
+        unspent: true,
+        funding_txid: String::default(),
+    })
+}
+
+/// Co-sign the BIP341 sighash message with shared key. Returns the key path spend witness.
+pub fn sign(
+    client_shim: &ClientShim,
+    message: &[u8; 32],
+    share: &MuSigShare,
+    protocol: Protocol,
+    shared_key_id: &Uuid,
+) -> Result<Vec<Vec<u8>>> {
+    let (sec_nonce, pub_nonce) = nonce_gen();
+
+    let sign_reply1: MuSigSignReply1 = requests::postb(
+        client_shim,
+        &format!("{}/sign/first", MUSIG_PATH_PRE),
+        &MuSigSignMsg1 {
+            shared_key_id: *shared_key_id,
+            pub_nonce,
+        },
+    )?;
+
+    let session = MuSigSession::new(
+        &share.public,
+        &pub_nonce.aggregate(&sign_reply1.pub_nonce),
+        message,
+    );
+    let partial_sig = session.partial_sign(&sec_nonce, &share.private);
+
+    let witness = requests::postb::<&MuSigSignMsg2, Vec<Vec<u8>>>(
+        client_shim,
+        &format!("{}/sign/second", MUSIG_PATH_PRE),
+        &MuSigSignMsg2 {
+            shared_key_id: *shared_key_id,
+            protocol,
+            message: hex::encode(message),
+            partial_sig,
+        },
+    )?;
+
+    Ok(witness)
+}
@@ -4,7 +4,8 @@
 
 use super::super::Result;
 use shared_lib::structs::{
-    SmtProofMsgAPI, StateChainDataAPI, StateEntityFeeInfoAPI, TransferBatchDataAPI,
+    SmtProofMsgAPI, StateChainClosureAPI, StateChainDataAPI, StateEntityFeeInfoAPI,
+    TransferBatchDataAPI,
 };
 use shared_lib::Root;
 
@@ -27,6 +28,17 @@ pub fn get_statechain(
     requests::get(client_shim, &format!("info/statechain/{}", statechain_id))
 }
 
+/// Get the backup tx closure of a state chain, if its funding output has been spent by a backup tx
+pub fn get_statechain_closure(
+    client_shim: &ClientShim,
+    statechain_id: &Uuid,
+) -> Result<Option<StateChainClosureAPI>> {
+    requests::get(
+        client_shim,
+        &format!("info/statechain/closure/{}", statechain_id),
+    )
+}
+
 /// Get state entity's sparse merkle tree root
 pub fn get_smt_root(client_shim: &ClientShim) -> Result<Option<Root>> {
     requests::get(&client_shim, &format!("info/root"))
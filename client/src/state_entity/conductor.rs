@@ -7,9 +7,10 @@ use super::super::Result;
 use crate::error::{CError, WalletErrorType};
 use crate::state_entity::{
     api::{get_statechain, get_transfer_batch_status},
+    events::EventStream,
     transfer,
 };
-use crate::wallet::wallet::Wallet;
+use crate::wallet::{key_paths::KeyDerivation, wallet::Wallet};
 use crate::{utilities::requests, ClientShim};
 use shared_lib::{state_chain::StateChainSig, structs::*};
 
@@ -23,17 +24,39 @@ use std::str::FromStr;
 use std::{thread, time};
 use uuid::Uuid;
 
-// Register a state chain for participation in a swap (request a swap)
-// with swap_size participants
-pub fn swap_register_utxo(wallet: &Wallet, statechain_id: &Uuid, swap_size: &u64) -> Result<()> {
-    // First sign state chain
+// Longest wait (seconds) for an event before polling again
+const EVENT_TIMEOUT: u64 = 30;
+// Interval (seconds) between polls without an event stream
+const POLL_INTERVAL: u64 = 3;
+
+// Wait before polling again: until the next event if subscribed to events, otherwise for the
+// poll interval. Falls back to polling if the event stream closes.
+fn wait_for_update(events: &mut Option<EventStream>) {
+    if let Some(stream) = events.as_ref() {
+        match stream.next_event(time::Duration::from_secs(EVENT_TIMEOUT)) {
+            Ok(_) => return,
+            Err(e) => warn!("{}, polling instead", e),
+        }
+        *events = None;
+    }
+    thread::sleep(time::Duration::from_secs(POLL_INTERVAL));
+}
+
+// Key derivation of the current proof key of a state chain owned by the wallet
+fn get_statechain_proof_key(wallet: &Wallet, statechain_id: &Uuid) -> Result<KeyDerivation> {
     let statechain_data: StateChainDataAPI = get_statechain(&wallet.client_shim, &statechain_id)?;
     let state_chain = statechain_data.chain;
-    // Get proof key for signing
-    let proof_key_derivation = &wallet
+    wallet
         .se_proof_keys
         .get_key_derivation(&PublicKey::from_str(&state_chain.last().unwrap().data).unwrap())
-        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))
+}
+
+// Register a state chain for participation in a swap (request a swap)
+// with swap_size participants
+pub fn swap_register_utxo(wallet: &Wallet, statechain_id: &Uuid, swap_size: &u64) -> Result<()> {
+    // First sign state chain with its proof key
+    let proof_key_derivation = &get_statechain_proof_key(wallet, statechain_id)?;
     let statechain_sig = StateChainSig::new(
         &proof_key_derivation.private_key.key,
         &String::from("SWAP"),
@@ -147,6 +170,15 @@ pub fn swap_second_message(
     )
 }
 
+// The conductor removes a swap that times out before the transfers begin. If this
+// statecoin was not at fault it has been returned to the pool of swap requests.
+fn swap_timed_out(swap_id: &Uuid) -> CError {
+    CError::SwapError(format!(
+        "swap {} timed out - poll utxo to check for a new swap",
+        swap_id
+    ))
+}
+
 // Loop throught the state chain ids
 // Do transfer_receiver returning TransferFinalizedData if message is mine
 fn do_transfer_receiver(
@@ -155,6 +187,7 @@ fn do_transfer_receiver(
     commit: &String,
     statechain_ids: &Vec<Uuid>,
     rec_se_addr: &SCEAddress, //my receiver address
+    events: &mut Option<EventStream>,
 ) -> Result<transfer::TransferFinalizeData> {
     for statechain_id in statechain_ids {
         loop {
@@ -178,7 +211,7 @@ fn do_transfer_receiver(
                 }
                 Err(_) => (),
             };
-            thread::sleep(time::Duration::from_secs(3));
+            wait_for_update(events);
         }
     }
     Err(CError::SwapError(
@@ -196,6 +229,16 @@ pub fn do_swap(
         return Err(CError::SwapError("tor not enabled".to_string()));
     }
 
+    // Subscribe to events of the state chain, such as the start of its swap
+    let statechain_proof_key = get_statechain_proof_key(wallet, statechain_id)?
+        .private_key
+        .key;
+    let mut events = EventStream::subscribe_with(
+        &wallet.client_shim,
+        &[statechain_proof_key],
+        &[*statechain_id],
+    );
+
     swap_register_utxo(wallet, statechain_id, swap_size)?;
     let swap_id;
     //Wait for swap to commence
@@ -208,7 +251,7 @@ pub fn do_swap(
             }
             None => (),
         }
-        thread::sleep(time::Duration::from_secs(3));
+        wait_for_update(&mut events);
     }
     //Wait for swap info to become available
     let info: SwapInfo;
@@ -221,13 +264,20 @@ pub fn do_swap(
             }
             None => (),
         }
-        thread::sleep(time::Duration::from_secs(3));
+        wait_for_update(&mut events);
     }
 
-    let proof_key = wallet.se_proof_keys.get_new_key()?;
+    let (proof_key, proof_key_priv) = wallet.se_proof_keys.get_new_key_priv()?;
 
     let proof_key = bitcoin::secp256k1::PublicKey::from_slice(&proof_key.to_bytes().as_slice())?;
 
+    // Subscribe to the swap's phase changes and batch results
+    events = EventStream::subscribe_with(
+        &wallet.client_shim,
+        &[statechain_proof_key],
+        &[swap_id, *statechain_id],
+    );
+
     let address = SCEAddress {
         tx_backup_addr: None,
         proof_key,
@@ -253,9 +303,9 @@ pub fn do_swap(
                 }
                 _ => (),
             },
-            None => (),
+            None => return Err(swap_timed_out(&swap_id)),
         };
-        thread::sleep(time::Duration::from_secs(3));
+        wait_for_update(&mut events);
     }
 
     let bss = swap_get_blinded_spend_signature(&wallet.client_shim, &swap_id, &statechain_id)?;
@@ -266,6 +316,11 @@ pub fn do_swap(
 
     let receiver_addr = swap_second_message(&wallet, &swap_id, &my_bst_data, &bss)?;
 
+    // Subscribe to transfer messages addressed to the new proof key. This is a separate
+    // subscription so that the new proof key is not linked to the swapped state chain.
+    let mut receiver_events =
+        EventStream::subscribe_with(&wallet.client_shim, &[proof_key_priv.key], &[]);
+
     //Wait until swap is in phase4 then transfer sender
     loop {
         match swap_poll_swap(&wallet.client_shim, &swap_id)? {
@@ -275,9 +330,9 @@ pub fn do_swap(
                 }
                 _ => (),
             },
-            None => (),
+            None => return Err(swap_timed_out(&swap_id)),
         };
-        thread::sleep(time::Duration::from_secs(3));
+        wait_for_update(&mut events);
     }
 
     let _ = transfer::transfer_sender(&mut wallet, statechain_id, receiver_addr)?;
@@ -293,6 +348,7 @@ pub fn do_swap(
         &commit,
         &info.swap_token.statechain_ids,
         &address,
+        &mut receiver_events,
     )?;
 
     //Wait until swap is in phase End
@@ -306,7 +362,7 @@ pub fn do_swap(
             },
             None => break,
         };
-        thread::sleep(time::Duration::from_secs(3));
+        wait_for_update(&mut events);
     }
 
     //Confirm batch transfer status and finalize the transfer in the wallet
@@ -8,33 +8,102 @@
 // 2. Co-op sign back-up tx
 // 3. Broadcast funding tx and wait for SE verification
 // 4. Verify funding txid and proof key in SM
+// deposit_batch() repeats 0-2 for each coin, funding every coin from one funding tx
+// deposit_batch_with_type() generates MuSig2 shared keys for Taproot statecoins, funded by P2TR
+// outputs, in place of 2P-ECDSA shared keys
+// If the State Entity uses relative locktimes, 2 first co-signs a kick-off tx spending the funding
+// tx output and the back-up tx spends the kick-off tx.
+// deposit_psbt_init() performs 0-2 and returns the unsigned funding tx as a PSBT for signing by an
+// external wallet. deposit_psbt_complete() then takes the signed PSBT and performs 3-4.
 
 use super::super::Result;
 extern crate shared_lib;
-use shared_lib::structs::{DepositMsg1, DepositMsg2, PrepareSignTxMsg, Protocol, UserID, StatechainID};
-use shared_lib::util::{tx_backup_build, tx_funding_build, FEE, transaction_serialise};
+use shared_lib::blinded_token::{BSTRequestorData, BlindedSpendSignature, BlindedSpendToken};
+use shared_lib::pow;
+use shared_lib::state_chain::smt_key;
+use shared_lib::structs::{
+    DepositBatchMsg, DepositMsg1, DepositMsg2, DepositTokenInit, DepositTokenMsg, KeyType,
+    PowChallenge, PowSolution, PrepareSignTxMsg, Protocol, StateEntityFeeInfoAPI, StatechainID, UserID,
+};
+use shared_lib::util::{
+    transaction_deserialise, transaction_serialise, tx_backup_build, tx_backup_build_relative,
+    tx_funding_build, tx_funding_build_batch, tx_kickoff_build, FEE,
+};
 
 use super::api::{get_smt_proof, get_smt_root, get_statechain_fee_info};
 use crate::error::{CError, WalletErrorType};
 use crate::state_entity::util::{cosign_tx_input, verify_statechain_smt};
 use crate::utilities::requests;
+use crate::ClientShim;
 use crate::wallet::wallet::{to_bitcoin_public_key, Wallet};
 
-use bitcoin::{consensus, PublicKey, Transaction};
-use curv::elliptic::curves::traits::ECPoint;
+use bitcoin::{
+    consensus, util::psbt::PartiallySignedTransaction, Address, OutPoint, PublicKey, Script,
+    Transaction, TxIn, TxOut,
+};
+use curv::{elliptic::curves::traits::ECPoint, PK};
+use std::str::FromStr;
 use uuid::Uuid;
 
 /// Message to server initiating state entity protocol.
-/// Shared wallet ID returned
+/// Shared wallet ID returned. Requests within the session are signed by the wallet's proof_key.
 pub fn session_init(wallet: &mut Wallet, proof_key: &String) -> Result<UserID> {
-    requests::postb(
+    session_init_with_token(wallet, proof_key, None, &KeyType::Ecdsa)
+}
+
+/// Message to server initiating state entity protocol with a deposit token from
+/// get_deposit_token(), for state entities requiring prepaid deposits. Any proof of work required
+/// by the state entity is solved first. key_type is the type of shared key of the deposit.
+pub fn session_init_with_token(
+    wallet: &mut Wallet,
+    proof_key: &String,
+    token: Option<BlindedSpendToken>,
+    key_type: &KeyType,
+) -> Result<UserID> {
+    let challenge: PowChallenge = requests::get(&wallet.client_shim, "deposit/challenge")?;
+    let pow_solution = match challenge.difficulty {
+        0 => None,
+        difficulty => Some(PowSolution {
+            nonce: pow::solve(&challenge.challenge, proof_key, difficulty),
+            challenge: challenge.challenge,
+        }),
+    };
+    let shared_key_id: UserID = requests::postb(
         &wallet.client_shim,
         &format!("deposit/init"),
         &DepositMsg1 {
             auth: "auth".to_string(),
             proof_key: proof_key.to_owned(),
+            pow_solution,
+            token,
+            key_type: *key_type,
+        },
+    )?;
+    let proof_key =
+        PublicKey::from_str(proof_key).map_err(|e| CError::Generic(e.to_string()))?;
+    wallet.add_session_key(&shared_key_id.id, &proof_key)?;
+    Ok(shared_key_id)
+}
+
+/// Get a deposit token from the state entity, paid for by payment_txid. The payment must be made
+/// to the fee address returned by the state entity's deposit/token/init and have the state
+/// entity's required number of confirmations.
+pub fn get_deposit_token(
+    client_shim: &ClientShim,
+    payment_txid: &String,
+) -> Result<BlindedSpendToken> {
+    let token_init: DepositTokenInit = requests::get(client_shim, "deposit/token/init")?;
+    let requestor_data = BSTRequestorData::setup(token_init.r_prime, &Uuid::new_v4().to_string())?;
+    let signature: BlindedSpendSignature = requests::postb(
+        client_shim,
+        "deposit/token/sign",
+        &DepositTokenMsg {
+            token_id: token_init.token_id,
+            payment_txid: payment_txid.clone(),
+            e_prime: requestor_data.get_e_prime(),
         },
-    )
+    )?;
+    Ok(requestor_data.make_blind_spend_token(requestor_data.unblind_signature(signature)))
 }
 
 /// Deposit coins into state entity. Returns shared_key_id, statechain_id, funding txid,
@@ -43,55 +112,386 @@ pub fn deposit(
     wallet: &mut Wallet,
     amount: &u64,
 ) -> Result<(Uuid, Uuid, String, Transaction, PrepareSignTxMsg, PublicKey)> {
+    Ok(deposit_batch(wallet, &vec![*amount])?.remove(0))
+}
+
+/// Deposit several coins into state entity from a single funding transaction. Each coin has its
+/// own session, shared key and backup tx spending its output of the funding tx. Returns
+/// shared_key_id, statechain_id, funding txid, signed backup tx, back up transacion data and
+/// proof_key for each coin.
+pub fn deposit_batch(
+    wallet: &mut Wallet,
+    amounts: &Vec<u64>,
+) -> Result<Vec<(Uuid, Uuid, String, Transaction, PrepareSignTxMsg, PublicKey)>> {
+    deposit_batch_with_type(wallet, amounts, &KeyType::Ecdsa)
+}
+
+/// Deposit several coins with shared keys of type key_type into state entity from a single
+/// funding transaction. Taproot coins are funded by P2TR outputs. Returns as deposit_batch.
+pub fn deposit_batch_with_type(
+    wallet: &mut Wallet,
+    amounts: &Vec<u64>,
+    key_type: &KeyType,
+) -> Result<Vec<(Uuid, Uuid, String, Transaction, PrepareSignTxMsg, PublicKey)>> {
+    if amounts.len() == 0 {
+        return Err(CError::Generic(String::from(
+            "Deposit: No amounts to deposit.",
+        )));
+    }
+
     // Get state entity fee info
     let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
 
     // Ensure funds cover fees before initiating protocol
-    if FEE + se_fee_info.deposit >= *amount {
-        return Err(CError::WalletError(WalletErrorType::NotEnoughFunds));
+    for amount in amounts {
+        if FEE + se_fee_info.deposit >= *amount {
+            return Err(CError::WalletError(WalletErrorType::NotEnoughFunds));
+        }
     }
+    let total_amount: u64 = amounts.iter().sum();
 
     //calculate SE fee amount from rate
-    let deposit_fee = (amount * se_fee_info.deposit) / 10000 as u64;
-    let withdraw_fee = (amount * se_fee_info.withdraw) / 10000 as u64;
+    let deposit_fee = (total_amount * se_fee_info.deposit) / 10000 as u64;
 
     // Greedy coin selection.
-    let (inputs, addrs, amounts) =
-        wallet.coin_selection_greedy(&(amount + deposit_fee + FEE))?;
-
-    // Generate proof key
-    let proof_key = wallet.se_proof_keys.get_new_key()?;
+    let (inputs, addrs, input_amounts) =
+        wallet.coin_selection_greedy(&(total_amount + deposit_fee + FEE))?;
 
-    // Init. session - Receive shared wallet ID
-    let shared_key_id: UserID = session_init(wallet, &proof_key.to_string())?;
-
-    // 2P-ECDSA with state entity to create a Shared key
-    let shared_key = wallet.gen_shared_key(&shared_key_id.id, amount)?;
+    // Init. a session and generate a shared key for each coin
+    let mut deposits = vec![];
+    let mut p_outputs = vec![];
+    let mut p_script_pubkeys = vec![];
+    for amount in amounts {
+        let (shared_key_id, pk, proof_key, p_script_pubkey) =
+            deposit_session_init(wallet, amount, key_type)?;
+        p_outputs.push((p2wpkh_address(wallet, &pk)?.to_string(), *amount));
+        p_script_pubkeys.push(p_script_pubkey);
+        deposits.push((shared_key_id, pk, proof_key));
+    }
 
-    // Create funding tx
-    let pk = shared_key.share.public.q.get_element(); // co-owned key address to send funds to (P_addr)
-    let p_addr =
-        bitcoin::Address::p2wpkh(&to_bitcoin_public_key(pk), wallet.get_bitcoin_network())?;
+    // Create funding tx. The i'th coin is funded by output i.
     let change_addr = wallet.keys.get_new_address()?.to_string();
-    let change_amount = amounts.iter().sum::<u64>() - amount - deposit_fee - FEE;
-    let tx_0 = tx_funding_build(
+    let change_amount = input_amounts.iter().sum::<u64>() - total_amount - deposit_fee - FEE;
+    let mut tx_0 = tx_funding_build_batch(
         &inputs,
-        &p_addr.to_string(),
-        amount,
+        &p_outputs,
         &deposit_fee,
         &se_fee_info.address,
         &change_addr,
         &change_amount,
     )?;
+    set_shared_key_outputs(&mut tx_0, p_script_pubkeys);
 
     let tx_funding_signed = wallet.sign_tx(
         &tx_0,
         &(0..inputs.len()).collect(), // inputs to sign are all inputs is this case
         &addrs,
-        &amounts,
+        &input_amounts,
     );
 
-    //get initial locktime
+    let init_locktime = get_init_locktime(wallet, &se_fee_info)?;
+
+    let mut backups = vec![];
+    for (vout, ((shared_key_id, pk, proof_key), amount)) in
+        deposits.iter().zip(amounts.iter()).enumerate()
+    {
+        let funding_outpoint = OutPoint {
+            txid: tx_funding_signed.txid(),
+            vout: vout as u32,
+        };
+        let (tx_backup_signed, tx_backup_psm) = deposit_cosign_backup_tx(
+            wallet,
+            shared_key_id,
+            pk,
+            proof_key,
+            &funding_outpoint,
+            amount,
+            &init_locktime,
+            &se_fee_info,
+        )?;
+        backups.push((funding_outpoint, tx_backup_signed, tx_backup_psm));
+    }
+
+    // Broadcast funding transcation
+    let funding_txid = wallet
+        .electrumx_client
+        .instance
+        .broadcast_transaction(hex::encode(consensus::serialize(&tx_funding_signed)))?;
+
+    // Wait for server confirmation of funding tx and receive new StateChains' ids
+    let statechain_ids: Vec<StatechainID> = requests::postb(
+        &wallet.client_shim,
+        &format!("deposit/confirm-batch"),
+        &DepositBatchMsg {
+            shared_key_ids: deposits.iter().map(|(id, _, _)| *id).collect(),
+        },
+    )?;
+
+    let mut result = vec![];
+    for (((shared_key_id, _, proof_key), (funding_outpoint, tx_backup_signed, tx_backup_psm)), statechain_id) in
+        deposits.into_iter().zip(backups.into_iter()).zip(statechain_ids.into_iter())
+    {
+        deposit_verify_and_store(
+            wallet,
+            &shared_key_id,
+            &statechain_id.id,
+            &funding_outpoint,
+            &proof_key,
+            &tx_backup_psm,
+        )?;
+
+        result.push((
+            shared_key_id,
+            statechain_id.id,
+            funding_txid.clone(),
+            tx_backup_signed,
+            tx_backup_psm,
+            proof_key,
+        ));
+    }
+
+    Ok(result)
+}
+
+/// Begin a deposit funded by an external (e.g. hardware or cold) wallet. funding_utxos are the
+/// outpoints and outputs being spent by the funding tx and must all be segwit so that the
+/// funding txid is fixed before they are signed. The backup tx is co-signed with the State
+/// Entity and then the unsigned funding tx is returned as a base64 encoded BIP174 PSBT for
+/// signing. Returns shared_key_id, unsigned funding PSBT and signed backup tx.
+pub fn deposit_psbt_init(
+    wallet: &mut Wallet,
+    amount: &u64,
+    funding_utxos: &Vec<(OutPoint, TxOut)>,
+    change_addr: &String,
+) -> Result<(Uuid, String, Transaction)> {
+    if funding_utxos.len() == 0 {
+        return Err(CError::Generic(String::from(
+            "Deposit: No funding inputs.",
+        )));
+    }
+    for (outpoint, txout) in funding_utxos {
+        if !txout.script_pubkey.is_witness_program() {
+            return Err(CError::Generic(format!(
+                "Deposit: Funding input {} is not segwit.",
+                outpoint
+            )));
+        }
+    }
+
+    // Get state entity fee info
+    let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
+
+    // Ensure funds cover fees before initiating protocol
+    if FEE + se_fee_info.deposit >= *amount {
+        return Err(CError::WalletError(WalletErrorType::NotEnoughFunds));
+    }
+
+    //calculate SE fee amount from rate
+    let deposit_fee = (amount * se_fee_info.deposit) / 10000 as u64;
+
+    let input_total: u64 = funding_utxos.iter().map(|(_, txout)| txout.value).sum();
+    if input_total < amount + deposit_fee + 2 * FEE {
+        return Err(CError::WalletError(WalletErrorType::NotEnoughFunds));
+    }
+
+    let (shared_key_id, pk, proof_key, p_script_pubkey) =
+        deposit_session_init(wallet, amount, &KeyType::Ecdsa)?;
+
+    // Create unsigned funding tx
+    let inputs: Vec<TxIn> = funding_utxos
+        .iter()
+        .map(|(outpoint, _)| TxIn {
+            previous_output: *outpoint,
+            sequence: 0xFFFFFFFF,
+            witness: Vec::new(),
+            script_sig: bitcoin::Script::default(),
+        })
+        .collect();
+    let change_amount = input_total - amount - deposit_fee - FEE;
+    let mut tx_funding_unsigned = tx_funding_build(
+        &inputs,
+        &p2wpkh_address(wallet, &pk)?.to_string(),
+        amount,
+        &deposit_fee,
+        &se_fee_info.address,
+        change_addr,
+        &change_amount,
+    )?;
+    set_shared_key_outputs(&mut tx_funding_unsigned, vec![p_script_pubkey]);
+
+    // Co-sign backup tx before the funding tx is signed
+    let init_locktime = get_init_locktime(wallet, &se_fee_info)?;
+    let funding_outpoint = OutPoint {
+        txid: tx_funding_unsigned.txid(),
+        vout: 0,
+    };
+    let (tx_backup_signed, tx_backup_psm) = deposit_cosign_backup_tx(
+        wallet,
+        &shared_key_id,
+        &pk,
+        &proof_key,
+        &funding_outpoint,
+        amount,
+        &init_locktime,
+        &se_fee_info,
+    )?;
+
+    // Store backup tx data so that the deposit can be completed once the PSBT is signed
+    {
+        let shared_key = wallet.get_shared_key_mut(&shared_key_id)?;
+        shared_key.tx_backup_psm = Some(tx_backup_psm);
+    }
+
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx_funding_unsigned)?;
+    for (input, (_, txout)) in psbt.inputs.iter_mut().zip(funding_utxos.iter()) {
+        input.witness_utxo = Some(txout.clone());
+    }
+
+    Ok((
+        shared_key_id,
+        base64::encode(consensus::serialize(&psbt)),
+        tx_backup_signed,
+    ))
+}
+
+/// Complete a deposit begun with deposit_psbt_init given the base64 encoded funding PSBT
+/// signed by the external wallet. Broadcasts the funding tx and waits for the State Entity to
+/// confirm the deposit. Returns statechain_id and funding txid.
+pub fn deposit_psbt_complete(
+    wallet: &mut Wallet,
+    shared_key_id: &Uuid,
+    signed_psbt: &String,
+) -> Result<(Uuid, String)> {
+    let tx_backup_psm = match &wallet.get_shared_key(shared_key_id)?.tx_backup_psm {
+        Some(psm) => psm.clone(),
+        None => {
+            return Err(CError::Generic(format!(
+                "Deposit: No backup tx for shared key {}. Call deposit_psbt_init first.",
+                shared_key_id
+            )))
+        }
+    };
+    let proof_key = match &tx_backup_psm.proof_key {
+        Some(proof_key) => PublicKey::from_str(proof_key)
+            .map_err(|e| CError::Generic(format!("Deposit: Invalid proof key: {}", e)))?,
+        None => {
+            return Err(CError::Generic(String::from(
+                "Deposit: Backup tx data missing proof key.",
+            )))
+        }
+    };
+    // In relative locktime mode the backup tx spends the kick-off tx which spends the funding tx
+    let funding_outpoint = match &wallet.get_shared_key(shared_key_id)?.tx_kickoff_hex {
+        Some(tx_kickoff_hex) => transaction_deserialise(tx_kickoff_hex)?.input[0].previous_output,
+        None => transaction_deserialise(&tx_backup_psm.tx_hex)?.input[0].previous_output,
+    };
+
+    let psbt_bytes = base64::decode(signed_psbt)
+        .map_err(|e| CError::Generic(format!("Deposit: Invalid PSBT encoding: {}", e)))?;
+    let psbt: PartiallySignedTransaction = consensus::deserialize(&psbt_bytes)?;
+    let tx_funding_signed = finalize_psbt(psbt)?;
+
+    // Signing must not have changed the funding tx which the backup tx spends
+    if tx_funding_signed.txid() != funding_outpoint.txid {
+        return Err(CError::Generic(String::from(
+            "Deposit: Signed PSBT is not the funding transaction of this deposit.",
+        )));
+    }
+
+    // Broadcast funding transcation
+    let funding_txid = wallet
+        .electrumx_client
+        .instance
+        .broadcast_transaction(hex::encode(consensus::serialize(&tx_funding_signed)))?;
+
+    // Wait for server confirmation of funding tx and receive new StateChain's id
+    let statechain_id: StatechainID = requests::postb(
+        &wallet.client_shim,
+        &format!("deposit/confirm"),
+        &DepositMsg2 {
+            shared_key_id: *shared_key_id,
+        },
+    )?;
+
+    deposit_verify_and_store(
+        wallet,
+        shared_key_id,
+        &statechain_id.id,
+        &funding_outpoint,
+        &proof_key,
+        &tx_backup_psm,
+    )?;
+
+    Ok((statechain_id.id, funding_txid))
+}
+
+/// Finalize a signed PSBT and extract the signed transaction. Inputs which the signer has not
+/// finalized are finalized here if they are P2WPKH with a single partial signature.
+pub fn finalize_psbt(mut psbt: PartiallySignedTransaction) -> Result<Transaction> {
+    for (index, input) in psbt.inputs.iter_mut().enumerate() {
+        if input.final_script_witness.is_some() {
+            continue;
+        }
+        let is_p2wpkh = match &input.witness_utxo {
+            Some(txout) => txout.script_pubkey.is_v0_p2wpkh(),
+            None => false,
+        };
+        if !is_p2wpkh || input.partial_sigs.len() != 1 {
+            return Err(CError::Generic(format!(
+                "Deposit: PSBT input {} not signed.",
+                index
+            )));
+        }
+        let (pubkey, sig) = input.partial_sigs.iter().next().unwrap();
+        input.final_script_witness = Some(vec![sig.clone(), pubkey.to_bytes()]);
+    }
+    Ok(psbt.extract_tx())
+}
+
+/// Generate a proof key, initiate a deposit session and generate its shared key. Returns
+/// shared_key_id, shared public key, proof key and shared key script pubkey.
+fn deposit_session_init(
+    wallet: &mut Wallet,
+    amount: &u64,
+    key_type: &KeyType,
+) -> Result<(Uuid, PK, PublicKey, Script)> {
+    // Generate proof key
+    let proof_key = wallet.se_proof_keys.get_new_key()?;
+
+    // Init. session - Receive shared wallet ID
+    let shared_key_id: UserID =
+        session_init_with_token(wallet, &proof_key.to_string(), None, key_type)?;
+
+    // 2P-ECDSA or MuSig2 with state entity to create a Shared key
+    let network = wallet.get_bitcoin_network();
+    let shared_key = wallet.gen_shared_key_with_type(&shared_key_id.id, amount, key_type)?;
+
+    // co-owned key output to send funds to (P_addr)
+    let pk = shared_key.public_key().get_element();
+    let p_script_pubkey = shared_key.script_pubkey(network)?;
+
+    Ok((shared_key_id.id, pk, proof_key, p_script_pubkey))
+}
+
+/// P2WPKH address of the shared key pk
+fn p2wpkh_address(wallet: &Wallet, pk: &PK) -> Result<Address> {
+    Ok(bitcoin::Address::p2wpkh(
+        &to_bitcoin_public_key(*pk),
+        wallet.get_bitcoin_network(),
+    )?)
+}
+
+/// Set the script pubkeys of the shared key outputs of a funding tx, which are its first outputs.
+/// The tx builders take P2WPKH addresses and bitcoin 0.25 cannot encode P2TR addresses, so the
+/// outputs of Taproot shared keys are set here.
+fn set_shared_key_outputs(tx: &mut Transaction, p_script_pubkeys: Vec<Script>) {
+    for (output, p_script_pubkey) in tx.output.iter_mut().zip(p_script_pubkeys.into_iter()) {
+        output.script_pubkey = p_script_pubkey;
+    }
+}
+
+/// Initial backup tx locktime from the current chain tip
+pub fn get_init_locktime(wallet: &Wallet, se_fee_info: &StateEntityFeeInfoAPI) -> Result<u32> {
     let chaintip = wallet
         .electrumx_client
         .instance
@@ -99,23 +499,64 @@ pub fn deposit(
     debug!("Deposit: Got current best block height: {}", chaintip.height.to_string());
     let init_locktime: u32 = (chaintip.height as u32) + (se_fee_info.initlock as u32);
     debug!("Deposit: Set initial locktime: {}", init_locktime.to_string());
+    Ok(init_locktime)
+}
 
+/// Build the backup tx spending funding_outpoint and co-sign it with the State Entity.
+/// Returns signed backup tx and back up transacion data.
+fn deposit_cosign_backup_tx(
+    wallet: &mut Wallet,
+    shared_key_id: &Uuid,
+    pk: &PK,
+    proof_key: &PublicKey,
+    funding_outpoint: &OutPoint,
+    amount: &u64,
+    init_locktime: &u32,
+    se_fee_info: &StateEntityFeeInfoAPI,
+) -> Result<(Transaction, PrepareSignTxMsg)> {
     // Make unsigned backup tx
     let backup_receive_addr = wallet.se_backup_keys.get_new_address()?;
-    let tx_backup_unsigned =
-        tx_backup_build(&tx_funding_signed.txid(), &backup_receive_addr, &amount, &init_locktime, &withdraw_fee, &se_fee_info.address)?;
+    let (tx_backup_unsigned, backup_input_amount) = if se_fee_info.relative_locktime {
+        // Co-sign kick-off tx spending the funding output. The backup tx spends the kick-off tx
+        // with a relative locktime of initlock blocks.
+        let tx_kickoff_signed =
+            deposit_cosign_kickoff_tx(wallet, shared_key_id, pk, funding_outpoint, amount)?;
+        let kickoff_amount = tx_kickoff_signed.output[0].value;
+
+        //calculate SE fee amount from rate
+        let withdraw_fee = (kickoff_amount * se_fee_info.withdraw) / 10000 as u64;
+        let tx_backup_unsigned = tx_backup_build_relative(
+            &tx_kickoff_signed,
+            &backup_receive_addr,
+            &se_fee_info.initlock,
+            &withdraw_fee,
+            &se_fee_info.backup_fee_address,
+        )?;
+        (tx_backup_unsigned, kickoff_amount)
+    } else {
+        //calculate SE fee amount from rate
+        let withdraw_fee = (amount * se_fee_info.withdraw) / 10000 as u64;
+        let tx_backup_unsigned = tx_backup_build(
+            funding_outpoint,
+            &backup_receive_addr,
+            amount,
+            init_locktime,
+            &withdraw_fee,
+            &se_fee_info.backup_fee_address,
+        )?;
+        (tx_backup_unsigned, *amount)
+    };
 
     // Co-sign tx backup tx
     let tx_backup_psm = PrepareSignTxMsg {
-        shared_key_id: shared_key_id.id,
+        shared_key_id: *shared_key_id,
         protocol: Protocol::Deposit,
         tx_hex: transaction_serialise(&tx_backup_unsigned),
-        input_addrs: vec![pk],
-        input_amounts: vec![*amount],
+        input_addrs: vec![*pk],
+        input_amounts: vec![backup_input_amount],
         proof_key: Some(proof_key.to_string()),
     };
 
-
     let witness = cosign_tx_input(wallet, &tx_backup_psm)?;
 
     // Add witness to back up tx
@@ -124,24 +565,61 @@ pub fn deposit(
 
     // TODO: check signature is valid?
 
-    // Broadcast funding transcation
-    let funding_txid = wallet
-        .electrumx_client
-        .instance
-        .broadcast_transaction(hex::encode(consensus::serialize(&tx_funding_signed)))?;
+    Ok((tx_backup_signed, tx_backup_psm))
+}
 
-    // Wait for server confirmation of funding tx and receive new StateChain's id
-    let statechain_id: StatechainID = requests::postb(
-        &wallet.client_shim,
-        &format!("deposit/confirm"),
-        &DepositMsg2 {
-            shared_key_id: shared_key_id.id,
-        },
-    )?;
+/// Build the kick-off tx spending funding_outpoint back to the shared key and co-sign it with
+/// the State Entity. The signed kick-off tx is stored in the deposit's shared key.
+fn deposit_cosign_kickoff_tx(
+    wallet: &mut Wallet,
+    shared_key_id: &Uuid,
+    pk: &PK,
+    funding_outpoint: &OutPoint,
+    amount: &u64,
+) -> Result<Transaction> {
+    let p_script_pubkey = wallet
+        .get_shared_key(shared_key_id)?
+        .script_pubkey(wallet.get_bitcoin_network())?;
+    let mut tx_kickoff_unsigned =
+        tx_kickoff_build(funding_outpoint, &p2wpkh_address(wallet, pk)?, amount)?;
+    set_shared_key_outputs(&mut tx_kickoff_unsigned, vec![p_script_pubkey]);
+
+    let tx_kickoff_psm = PrepareSignTxMsg {
+        shared_key_id: *shared_key_id,
+        protocol: Protocol::KickOff,
+        tx_hex: transaction_serialise(&tx_kickoff_unsigned),
+        input_addrs: vec![*pk],
+        input_amounts: vec![*amount],
+        proof_key: None,
+    };
+
+    let witness = cosign_tx_input(wallet, &tx_kickoff_psm)?;
 
+    let mut tx_kickoff_signed = tx_kickoff_unsigned.clone();
+    tx_kickoff_signed.input[0].witness = witness;
+
+    {
+        let shared_key = wallet.get_shared_key_mut(shared_key_id)?;
+        shared_key.tx_kickoff_hex = Some(transaction_serialise(&tx_kickoff_signed));
+    }
+
+    Ok(tx_kickoff_signed)
+}
+
+/// Verify proof key inclusion in SE sparse merkle tree and add proof and state chain id to
+/// the deposit's shared key
+fn deposit_verify_and_store(
+    wallet: &mut Wallet,
+    shared_key_id: &Uuid,
+    statechain_id: &Uuid,
+    funding_outpoint: &OutPoint,
+    proof_key: &PublicKey,
+    tx_backup_psm: &PrepareSignTxMsg,
+) -> Result<()> {
     // Verify proof key inclusion in SE sparse merkle tree
+    let funding_smt_key = smt_key(funding_outpoint);
     let root = get_smt_root(&wallet.client_shim)?.unwrap();
-    let proof = get_smt_proof(&wallet.client_shim, &root, &funding_txid)?;
+    let proof = get_smt_proof(&wallet.client_shim, &root, &funding_smt_key)?;
     assert!(verify_statechain_smt(
         &Some(root.hash()),
         &proof_key.to_string(),
@@ -150,18 +628,79 @@ pub fn deposit(
 
     // Add proof and state chain id to Shared key
     {
-        let shared_key = wallet.get_shared_key_mut(&shared_key_id.id)?;
-        shared_key.statechain_id = Some(statechain_id.id);
+        let shared_key = wallet.get_shared_key_mut(shared_key_id)?;
+        shared_key.statechain_id = Some(*statechain_id);
         shared_key.tx_backup_psm = Some(tx_backup_psm.to_owned());
-        shared_key.add_proof_data(&proof_key.to_string(), &root, &proof, &funding_txid);
+        shared_key.add_proof_data(&proof_key.to_string(), &root, &proof, &funding_smt_key);
     }
 
-    Ok((
-        shared_key_id.id,
-        statechain_id.id,
-        funding_txid,
-        tx_backup_signed,
-        tx_backup_psm,
-        proof_key,
-    ))
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::Txid;
+
+    fn unsigned_funding_psbt() -> (PartiallySignedTransaction, PublicKey) {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[0xcd; 32]).unwrap();
+        let pubkey = PublicKey {
+            compressed: true,
+            key: bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &secret_key),
+        };
+        let address = Address::p2wpkh(&pubkey, bitcoin::Network::Regtest).unwrap();
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_str(
+                        "e0a97cb38e7e73617ef75a57eaf2841eb06833407c0eae08029bd04ea7e6115a",
+                    )
+                    .unwrap(),
+                    vout: 1,
+                },
+                sequence: 0xFFFFFFFF,
+                witness: Vec::new(),
+                script_sig: bitcoin::Script::default(),
+            }],
+            output: vec![TxOut {
+                script_pubkey: address.script_pubkey(),
+                value: 9000,
+            }],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            script_pubkey: address.script_pubkey(),
+            value: 10000,
+        });
+        (psbt, pubkey)
+    }
+
+    #[test]
+    fn test_finalize_psbt() {
+        let (psbt, pubkey) = unsigned_funding_psbt();
+        let txid = psbt.global.unsigned_tx.txid();
+
+        // Unsigned input
+        assert!(finalize_psbt(psbt.clone()).is_err());
+
+        // Partially signed P2WPKH input is finalized
+        let mut psbt_signed = psbt.clone();
+        let sig = vec![0x30, 0x01, 0x01];
+        psbt_signed.inputs[0]
+            .partial_sigs
+            .insert(pubkey, sig.clone());
+        let tx = finalize_psbt(psbt_signed).unwrap();
+        assert_eq!(tx.txid(), txid);
+        assert_eq!(tx.input[0].witness, vec![sig, pubkey.to_bytes()]);
+
+        // Already finalized input is left unchanged
+        let mut psbt_final = psbt.clone();
+        psbt_final.inputs[0].final_script_witness = Some(vec![vec![1], vec![2]]);
+        let tx = finalize_psbt(psbt_final).unwrap();
+        assert_eq!(tx.input[0].witness, vec![vec![1], vec![2]]);
+    }
 }
@@ -0,0 +1,130 @@
+//! Events
+//!
+//! Subscribe to swap and transfer events pushed by the state entity over a WebSocket, instead of
+//! polling for swap phase changes, transfer messages and batch results
+
+use super::super::Result;
+use crate::error::CError;
+use crate::ClientShim;
+use shared_lib::events::{EventSubscribeMsg, StateEntityEvent};
+
+use bitcoin::secp256k1::SecretKey;
+use std::net::TcpStream;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use tungstenite::Message;
+use uuid::Uuid;
+
+/// Events of a subscription, read from the connection on a background thread
+pub struct EventStream {
+    receiver: Receiver<StateEntityEvent>,
+}
+
+fn ws_error<E: std::fmt::Display>(e: E) -> CError {
+    CError::StateEntityError(format!("Event stream error: {}", e))
+}
+
+impl EventStream {
+    /// Connect to the event stream at endpoint (ws://host:port) and subscribe to ids and to
+    /// events addressed to the public keys of keys. The subscription is signed by keys, which
+    /// must include the owner of each statechain, swap or batch in ids.
+    pub fn subscribe(endpoint: &str, keys: &[SecretKey], ids: &[Uuid]) -> Result<EventStream> {
+        let address = endpoint
+            .trim_start_matches("ws://")
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        let stream = TcpStream::connect(address)?;
+        let (mut socket, _) = tungstenite::client(endpoint, stream).map_err(ws_error)?;
+
+        let msg = EventSubscribeMsg::new(keys, ids)?;
+        socket
+            .write_message(Message::Text(serde_json::to_string(&msg)?))
+            .map_err(ws_error)?;
+        // The state entity closes the connection if the subscription is not authorised
+        loop {
+            match socket.read_message().map_err(ws_error)? {
+                Message::Text(text) => match serde_json::from_str(&text)? {
+                    StateEntityEvent::Subscribed { .. } => break,
+                    _ => (),
+                },
+                Message::Close(_) => {
+                    return Err(CError::StateEntityError(String::from(
+                        "Event subscription rejected",
+                    )))
+                }
+                _ => (),
+            }
+        }
+
+        let (sender, receiver) = channel();
+        thread::spawn(move || loop {
+            match socket.read_message() {
+                Ok(Message::Text(text)) => match serde_json::from_str(&text) {
+                    Ok(event) => {
+                        if sender.send(event).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => warn!("Invalid event: {}", e),
+                },
+                Ok(_) => (),
+                Err(_) => return,
+            }
+        });
+        Ok(EventStream { receiver })
+    }
+
+    /// Subscribe with the event endpoint of client_shim. Returns None if the client has no event
+    /// endpoint or uses Tor, since the event stream is not sent through the Tor proxy, or if
+    /// the subscription fails, in which case the caller falls back to polling.
+    pub fn subscribe_with(
+        client_shim: &ClientShim,
+        keys: &[SecretKey],
+        ids: &[Uuid],
+    ) -> Option<EventStream> {
+        let endpoint = match &client_shim.events_endpoint {
+            Some(e) if !client_shim.has_tor() => e,
+            _ => return None,
+        };
+        match Self::subscribe(endpoint, keys, ids) {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                warn!("Failed to subscribe to events, polling instead: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Wait up to timeout for the next event. Returns None on timeout and an error if the
+    /// connection has closed.
+    pub fn next_event(&self, timeout: Duration) -> Result<Option<StateEntityEvent>> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(CError::StateEntityError(String::from(
+                "Event stream closed",
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Tor;
+
+    #[test]
+    fn test_subscribe_with() {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let mut client_shim = ClientShim::new("http://localhost:8000".to_string(), None, None);
+        // No event endpoint
+        assert!(EventStream::subscribe_with(&client_shim, &[key], &[]).is_none());
+
+        // Event streams are not sent through Tor
+        client_shim.events_endpoint = Some("ws://localhost:8002".to_string());
+        client_shim.tor = Some(Tor::default());
+        assert!(EventStream::subscribe_with(&client_shim, &[key], &[]).is_none());
+    }
+}
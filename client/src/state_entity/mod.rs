@@ -4,6 +4,8 @@ pub mod api;
 pub mod conductor;
 pub mod confirm_proofs;
 pub mod deposit;
+pub mod events;
+pub mod refresh;
 pub mod transfer;
 pub mod util;
 pub mod withdraw;
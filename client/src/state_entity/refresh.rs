@@ -0,0 +1,154 @@
+//! Refresh
+//!
+//! Refresh a statecoin to reset its back up tx locktime
+
+// refresh():
+// 0. Sign state chain and request refresh
+// 1. Co-sign refresh tx spending the funding output to a new output of the shared key
+// 2. Co-sign new back up tx spending the refresh tx with a new initial locktime
+// 3. Broadcast refresh tx and wait for SE verification
+// 4. Verify new funding txid and proof key in SMT
+
+use super::super::Result;
+extern crate shared_lib;
+use shared_lib::{
+    state_chain::{smt_key, StateChainSig},
+    structs::{PrepareSignTxMsg, Protocol, RefreshMsg1, RefreshMsg2, StateChainDataAPI},
+    util::{transaction_serialise, tx_backup_build, tx_kickoff_build},
+};
+
+use super::api::{get_smt_proof, get_smt_root, get_statechain, get_statechain_fee_info};
+use super::deposit::get_init_locktime;
+use crate::error::{CError, WalletErrorType};
+use crate::state_entity::util::{cosign_tx_input, verify_statechain_smt};
+use crate::utilities::requests;
+use crate::wallet::wallet::{to_bitcoin_public_key, Wallet};
+
+use bitcoin::{consensus, OutPoint, PublicKey, Transaction};
+use curv::elliptic::curves::traits::ECPoint;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Refresh a statecoin, keeping its statechain_id and proof key. Returns refresh txid and
+/// signed back up tx.
+pub fn refresh(wallet: &mut Wallet, statechain_id: &Uuid) -> Result<(String, Transaction)> {
+    let (shared_key_id, pk, p_script_pubkey) = {
+        let shared_key = wallet.get_shared_key_by_statechain_id(statechain_id)?;
+        (
+            shared_key.id,
+            shared_key.public_key().get_element(),
+            shared_key.script_pubkey(wallet.get_bitcoin_network())?,
+        )
+    };
+
+    // Sign state chain over the current proof key
+    let statechain_data: StateChainDataAPI = get_statechain(&wallet.client_shim, statechain_id)?;
+    if statechain_data.amount == 0 {
+        return Err(CError::StateEntityError(format!(
+            "Refresh: StateChain {} is already withdrawn.",
+            statechain_id
+        )));
+    }
+    let proof_key = statechain_data.chain.last().unwrap().data.clone();
+    let proof_key_derivation = wallet
+        .se_proof_keys
+        .get_key_derivation(&PublicKey::from_str(&proof_key).unwrap())
+        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+    let statechain_sig = StateChainSig::new(
+        &proof_key_derivation.private_key.key,
+        &String::from("REFRESH"),
+        &proof_key,
+    )?;
+
+    // Alert SE of desire to refresh and receive authorisation if state chain signature verifies
+    requests::postb(
+        &wallet.client_shim,
+        &format!("refresh/init"),
+        &RefreshMsg1 {
+            shared_key_id,
+            statechain_sig,
+        },
+    )?;
+
+    let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
+
+    // Co-sign refresh tx. It has the same form as a kick-off tx: one output paying to P.
+    let p_addr =
+        bitcoin::Address::p2wpkh(&to_bitcoin_public_key(pk), wallet.get_bitcoin_network())?;
+    let mut tx_refresh_unsigned =
+        tx_kickoff_build(&statechain_data.utxo, &p_addr, &statechain_data.amount)?;
+    tx_refresh_unsigned.output[0].script_pubkey = p_script_pubkey;
+    let tx_refresh_psm = PrepareSignTxMsg {
+        shared_key_id,
+        protocol: Protocol::Refresh,
+        tx_hex: transaction_serialise(&tx_refresh_unsigned),
+        input_addrs: vec![pk],
+        input_amounts: vec![statechain_data.amount],
+        proof_key: None,
+    };
+    let witness = cosign_tx_input(wallet, &tx_refresh_psm)?;
+    let mut tx_refresh_signed = tx_refresh_unsigned.clone();
+    tx_refresh_signed.input[0].witness = witness;
+
+    // Co-sign new back up tx spending the refresh tx
+    let refresh_outpoint = OutPoint {
+        txid: tx_refresh_signed.txid(),
+        vout: 0,
+    };
+    let refresh_amount = tx_refresh_signed.output[0].value;
+    let withdraw_fee = (refresh_amount * se_fee_info.withdraw) / 10000 as u64;
+    let backup_receive_addr = wallet.se_backup_keys.get_new_address()?;
+    let tx_backup_unsigned = tx_backup_build(
+        &refresh_outpoint,
+        &backup_receive_addr,
+        &refresh_amount,
+        &get_init_locktime(wallet, &se_fee_info)?,
+        &withdraw_fee,
+        &se_fee_info.backup_fee_address,
+    )?;
+    let tx_backup_psm = PrepareSignTxMsg {
+        shared_key_id,
+        protocol: Protocol::RefreshBackup,
+        tx_hex: transaction_serialise(&tx_backup_unsigned),
+        input_addrs: vec![pk],
+        input_amounts: vec![refresh_amount],
+        proof_key: Some(proof_key.clone()),
+    };
+    let witness = cosign_tx_input(wallet, &tx_backup_psm)?;
+    let mut tx_backup_signed = tx_backup_unsigned.clone();
+    tx_backup_signed.input[0].witness = witness;
+
+    // Broadcast refresh transaction
+    let refresh_txid = wallet
+        .electrumx_client
+        .instance
+        .broadcast_transaction(hex::encode(consensus::serialize(&tx_refresh_signed)))?;
+    debug!("Refresh: Refresh tx broadcast. txid: {}", refresh_txid);
+
+    // Wait for server confirmation of refresh tx
+    requests::postb(
+        &wallet.client_shim,
+        &format!("refresh/confirm"),
+        &RefreshMsg2 { shared_key_id },
+    )?;
+
+    // Verify proof key inclusion in SE sparse merkle tree under the new funding outpoint
+    let funding_smt_key = smt_key(&refresh_outpoint);
+    let root = get_smt_root(&wallet.client_shim)?.unwrap();
+    let proof = get_smt_proof(&wallet.client_shim, &root, &funding_smt_key)?;
+    assert!(verify_statechain_smt(
+        &Some(root.hash()),
+        &proof_key,
+        &proof
+    ));
+
+    // Update shared key with new back up tx and proof
+    {
+        let shared_key = wallet.get_shared_key_mut(&shared_key_id)?;
+        shared_key.value = refresh_amount;
+        shared_key.tx_backup_psm = Some(tx_backup_psm);
+        shared_key.add_proof_data(&proof_key, &root, &proof, &funding_smt_key);
+    }
+
+    Ok((refresh_txid, tx_backup_signed))
+}
@@ -15,6 +15,8 @@
 //      c. calucaulte t2 = t1*o2_inv
 //      d. Send t2, O2 to state entity
 //      e. Verify o2*S2 = P
+// Taproot statecoins have the additive shared key P = O + S, so t1 = o1 + x1, t2 = t1 - o2 and
+// the receiver verifies O2 + S2 = P.
 
 use super::super::Result;
 
@@ -25,7 +27,7 @@ use crate::state_entity::{
 };
 use crate::wallet::{key_paths::funding_txid_to_int, wallet::Wallet};
 use crate::{utilities::requests, ClientShim};
-use shared_lib::{ecies::WalletDecryptable, ecies::SelfEncryptable, state_chain::StateChainSig, structs::*, util::{transaction_serialise, transaction_deserialise}};
+use shared_lib::{ecies::WalletDecryptable, ecies::SelfEncryptable, musig::fe_negate, state_chain::{smt_key, StateChainSig}, structs::*, util::{blocks_from_sequence, sequence_from_blocks, transaction_serialise, transaction_deserialise}};
 
 use bitcoin::{Address, PublicKey};
 use curv::elliptic::curves::traits::{ECPoint, ECScalar};
@@ -93,9 +95,25 @@ pub fn transfer_sender(
         },
         None => (),
     };
+    // The SE fee output pays the SE's current backup fee address
+    match tx.output.get_mut(1) {
+        Some(v) => {
+            v.script_pubkey = Address::from_str(&se_fee_info.backup_fee_address)?.script_pubkey()
+        }
+        None => (),
+    };
     prepare_sign_msg.proof_key = Some(receiver_addr.proof_key.clone().to_string());
-    //set updated decremented locktime
-    tx.lock_time = statechain_data.locktime - se_fee_info.interval;
+    //set updated decremented locktime. The backup tx of a cancelled transfer may have a lower
+    //locktime than the current backup tx.
+    let locktime = match statechain_data.aborted_locktime {
+        Some(aborted_locktime) => std::cmp::min(statechain_data.locktime, aborted_locktime),
+        None => statechain_data.locktime,
+    };
+    if se_fee_info.relative_locktime {
+        tx.input[0].sequence = sequence_from_blocks(&(locktime - se_fee_info.interval))?;
+    } else {
+        tx.lock_time = locktime - se_fee_info.interval;
+    }
     prepare_sign_msg.tx_hex = transaction_serialise(&tx);
 
     // Sign new back up tx
@@ -108,11 +126,15 @@ pub fn transfer_sender(
 
     // Get o1 priv key
     let shared_key = wallet.get_shared_key(&shared_key_id)?;
-    let o1 = shared_key.share.private.get_private_key();
+    let o1 = shared_key.private_key();
+    let key_type = shared_key.key_type();
 
-    // t1 = o1x1
+    // t1 = o1x1, or o1 + x1 for the additive Taproot shared key
     let x1 = transfer_msg2.x1.get_fe()?;
-    let t1 = o1 * x1;
+    let t1 = match key_type {
+        KeyType::Ecdsa => o1 * x1,
+        KeyType::Taproot => o1 + x1,
+    };
     let t1_encryptable = FESer::from_fe(&t1);
 
     let mut transfer_msg3 = TransferMsg3 {
@@ -122,6 +144,7 @@ pub fn transfer_sender(
         statechain_id: statechain_id.to_owned(),
         tx_backup_psm: prepare_sign_msg.to_owned(),
         rec_se_addr: receiver_addr,
+        key_type,
     };
 
     //encrypt then make immutable
@@ -147,6 +170,42 @@ pub fn transfer_sender(
     Ok(transfer_msg3)
 }
 
+/// Cancel a transfer started by transfer_sender which the receiver has not completed. The
+/// statecoin can then be transferred again.
+pub fn transfer_cancel(wallet: &mut Wallet, transfer_msg3: &TransferMsg3) -> Result<()> {
+    // Sign cancel of the transfer to the receiver's proof key
+    let statechain_data: StateChainDataAPI =
+        get_statechain(&wallet.client_shim, &transfer_msg3.statechain_id)?;
+    let proof_key_derivation = wallet
+        .se_proof_keys
+        .get_key_derivation(&PublicKey::from_str(&statechain_data.chain.last().unwrap().data).unwrap());
+    let statechain_sig = StateChainSig::new(
+        &proof_key_derivation
+            .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?
+            .private_key
+            .key,
+        &String::from("CANCEL"),
+        &transfer_msg3.statechain_sig.data,
+    )?;
+
+    requests::postb(
+        &wallet.client_shim,
+        &format!("transfer/cancel"),
+        &TransferCancelMsg {
+            shared_key_id: transfer_msg3.shared_key_id,
+            statechain_sig,
+        },
+    )?;
+
+    // Mark funds as unspent in wallet
+    {
+        let mut shared_key = wallet.get_shared_key_mut(&transfer_msg3.shared_key_id)?;
+        shared_key.unspent = true;
+    }
+
+    Ok(())
+}
+
 // Get the transfer message 3
 // created by the sender and stored in the SE database
 pub fn transfer_get_msg(wallet: &mut Wallet, statechain_id: &Uuid) -> Result<TransferMsg3> {
@@ -201,10 +260,34 @@ pub fn transfer_receiver(
         .instance
         .get_tip_header()?;
     debug!("Transfer receiver: Got current best block height: {}", chaintip.height.to_string());
-    if tx_backup.lock_time <= (chaintip.height as u32) {
-            return Err(CError::Generic(format!(
-                "Error: backup tx locktime ({:?}) expired, blockheight {:?}",tx_backup.lock_time,chaintip.height
-            )));
+    match &statechain_data.tx_kickoff_hex {
+        // Relative locktime: backup tx must spend the kick-off tx and still have a locktime
+        Some(tx_kickoff_hex) => {
+            let tx_kickoff = transaction_deserialise(tx_kickoff_hex)?;
+            if tx_backup.input[0].previous_output.txid != tx_kickoff.txid()
+                || tx_kickoff.input[0].previous_output != statechain_data.utxo
+            {
+                return Err(CError::Generic(String::from(
+                    "Error: backup tx does not spend the statecoin kick-off tx",
+                )));
+            }
+            match blocks_from_sequence(&tx_backup.input[0].sequence) {
+                Some(blocks) if blocks > 0 => (),
+                _ => {
+                    return Err(CError::Generic(format!(
+                        "Error: backup tx relative locktime (nSequence {:?}) expired",
+                        tx_backup.input[0].sequence
+                    )))
+                }
+            }
+        }
+        None => {
+            if tx_backup.lock_time <= (chaintip.height as u32) {
+                    return Err(CError::Generic(format!(
+                        "Error: backup tx locktime ({:?}) expired, blockheight {:?}",tx_backup.lock_time,chaintip.height
+                    )));
+            }
+        }
     }
 
     // Check validity of the backup transaction
@@ -269,7 +352,10 @@ pub fn transfer_receiver(
     let g: GE = ECPoint::generator();
     let o2_pub: GE = g * o2;
 
-    let t2 = t1 * (o2.invert());
+    let t2 = match transfer_msg3.key_type {
+        KeyType::Ecdsa => t1 * (o2.invert()),
+        KeyType::Taproot => t1 + fe_negate(&o2),
+    };
     let t2_encryptable = FESer::from_fe(&t2);
 
     // get SE/lockbox public key share
@@ -306,6 +392,7 @@ pub fn transfer_receiver(
         proof_key: transfer_msg3.rec_se_addr.proof_key.clone().to_string(),
         statechain_id: transfer_msg3.statechain_id,
         tx_backup_psm,
+        key_type: transfer_msg3.key_type,
     };
 
     // In batch case this step is performed once all other transfers in the batch are complete.
@@ -326,6 +413,8 @@ pub struct TransferFinalizeData {
     pub proof_key: String,
     pub statechain_id: Uuid,
     pub tx_backup_psm: PrepareSignTxMsg,
+    #[serde(default)]
+    pub key_type: KeyType,
 }
 
 /// Finalize protocol run by generating new shared key and updating wallet.
@@ -335,21 +424,32 @@ pub fn transfer_receiver_finalize(
     wallet: &mut Wallet,
     finalize_data: TransferFinalizeData,
 ) -> Result<()> {
+    // Sign requests within the new session with the receiver's proof key
+    let proof_key = PublicKey::from_str(&finalize_data.proof_key)
+        .map_err(|e| CError::Generic(e.to_string()))?;
+    wallet.add_session_key(&finalize_data.new_shared_key_id, &proof_key)?;
+
     // Make shared key with new private share
     wallet.gen_shared_key_fixed_secret_key(
         &finalize_data.new_shared_key_id,
         &finalize_data.o2.get_element(),
         &finalize_data.statechain_data.amount,
+        &finalize_data.key_type,
     )?;
 
-    // Check shared key master public key == private share * SE public share
-    if (finalize_data.s2_pub * finalize_data.o2).get_element()
-        != wallet
-            .get_shared_key(&finalize_data.new_shared_key_id)?
-            .share
-            .public
-            .q
-            .get_element()
+    // Check shared key master public key == private share * SE public share, or
+    // private share.G + SE public share for Taproot keys, and is the key of the statecoin
+    let g: GE = ECPoint::generator();
+    let expected_pub = match finalize_data.key_type {
+        KeyType::Ecdsa => finalize_data.s2_pub * finalize_data.o2,
+        KeyType::Taproot => g * finalize_data.o2 + finalize_data.s2_pub,
+    };
+    let shared_pub = wallet
+        .get_shared_key(&finalize_data.new_shared_key_id)?
+        .public_key()
+        .get_element();
+    if expected_pub.get_element() != shared_pub
+        || finalize_data.tx_backup_psm.input_addrs.get(0) != Some(&shared_pub)
     {
         return Err(CError::StateEntityError(String::from(
             "Transfer failed. Incorrect master public key generated.",
@@ -361,7 +461,7 @@ pub fn transfer_receiver_finalize(
 
     // Verify proof key inclusion in SE sparse merkle tree
     let root = get_smt_root(&wallet.client_shim)?.unwrap();
-    let funding_txid = &finalize_data.statechain_data.utxo.txid.to_string();
+    let funding_txid = &smt_key(&finalize_data.statechain_data.utxo);
     let proof = get_smt_proof(&wallet.client_shim, &root, funding_txid)?;
     assert!(verify_statechain_smt(
         &Some(root.hash()),
@@ -374,6 +474,7 @@ pub fn transfer_receiver_finalize(
         let shared_key = wallet.get_shared_key_mut(&finalize_data.new_shared_key_id)?;
         shared_key.statechain_id = Some(finalize_data.statechain_id);
         shared_key.tx_backup_psm = Some(finalize_data.tx_backup_psm.clone());
+        shared_key.tx_kickoff_hex = finalize_data.statechain_data.tx_kickoff_hex.clone();
         shared_key.add_proof_data(&rec_proof_key, &root, &proof, funding_txid);
     }
 
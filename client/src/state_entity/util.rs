@@ -6,9 +6,10 @@ extern crate shared_lib;
 
 use super::super::utilities::requests;
 use super::super::Result;
-use crate::ecdsa;
-use crate::wallet::wallet::Wallet;
+use crate::wallet::{shared_key::KeyShare, wallet::Wallet};
+use crate::{ecdsa, musig};
 
+use shared_lib::musig::get_shared_key_taproot_sighash;
 use shared_lib::structs::PrepareSignTxMsg;
 use shared_lib::util::{transaction_deserialise, get_sighash};
 
@@ -26,6 +27,15 @@ use std::convert::TryInto;
 pub fn cosign_tx_input(
     wallet: &mut Wallet,
     prepare_sign_msg: &PrepareSignTxMsg,
+) -> Result<Vec<Vec<u8>>> {
+    cosign_tx_input_index(wallet, prepare_sign_msg, 0)
+}
+
+/// Sign the transaction input at input_index with state entity shared wallet. Return signature witness.
+pub fn cosign_tx_input_index(
+    wallet: &mut Wallet,
+    prepare_sign_msg: &PrepareSignTxMsg,
+    input_index: usize,
 ) -> Result<Vec<Vec<u8>>> {
     // message 1 - send tx data for validation.
     requests::postb(
@@ -36,26 +46,46 @@ pub fn cosign_tx_input(
 
     let tx = transaction_deserialise(&prepare_sign_msg.tx_hex)?;
 
-    // get sighash as message to be signed
-    let sig_hash = get_sighash(
-        &tx,
-        &0,
-        &prepare_sign_msg.input_addrs[0],
-        &prepare_sign_msg.input_amounts[0],
-        &wallet.network,
-    );
-
     let shared_key = wallet.get_shared_key(&prepare_sign_msg.shared_key_id)?;
-    let mk = &shared_key.share;
 
     // co-sign transaction
-    let witness = ecdsa::sign(
-        &wallet.client_shim,
-        BigInt::from_hex(&hex::encode(&sig_hash[..])),
-        &mk,
-        prepare_sign_msg.protocol,
-        &shared_key.id,
-    )?;
+    let witness = match &shared_key.share {
+        KeyShare::Ecdsa(mk) => {
+            // get sighash as message to be signed
+            let sig_hash = get_sighash(
+                &tx,
+                &input_index,
+                &prepare_sign_msg.input_addrs[input_index],
+                &prepare_sign_msg.input_amounts[input_index],
+                &wallet.network,
+            );
+
+            ecdsa::sign(
+                &wallet.client_shim,
+                BigInt::from_hex(&hex::encode(&sig_hash[..])),
+                &mk,
+                prepare_sign_msg.protocol,
+                &shared_key.id,
+            )?
+        }
+        KeyShare::Taproot(musig_share) => {
+            // BIP341 sighash commits to the spent outputs of every input
+            let sig_hash = get_shared_key_taproot_sighash(
+                &tx,
+                &input_index,
+                &prepare_sign_msg.input_addrs,
+                &prepare_sign_msg.input_amounts,
+            )?;
+
+            musig::sign(
+                &wallet.client_shim,
+                &sig_hash,
+                musig_share,
+                prepare_sign_msg.protocol,
+                &shared_key.id,
+            )?
+        }
+    };
 
     Ok(witness)
 }
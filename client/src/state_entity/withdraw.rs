@@ -12,13 +12,16 @@ use super::super::Result;
 extern crate shared_lib;
 use shared_lib::{
     state_chain::StateChainSig,
-    structs::{PrepareSignTxMsg, Protocol, StateChainDataAPI, WithdrawMsg1, WithdrawMsg2},
-    util::{transaction_serialise, tx_withdraw_build},
+    structs::{
+        PrepareSignTxMsg, Protocol, StateChainDataAPI, WithdrawMsg1, WithdrawMsg2,
+        WithdrawOutput,
+    },
+    util::{transaction_serialise, tx_withdraw_build_outputs, withdraw_outputs_commitment, FEE},
 };
 
 use super::api::{get_statechain, get_statechain_fee_info};
 use crate::error::{CError, WalletErrorType};
-use crate::state_entity::util::cosign_tx_input;
+use crate::state_entity::util::cosign_tx_input_index;
 use crate::utilities::requests;
 use crate::wallet::wallet::Wallet;
 
@@ -30,90 +33,149 @@ use uuid::Uuid;
 
 /// Withdraw coins from state entity. Returns signed withdraw transaction, statechain_id and withdrawn amount.
 pub fn withdraw(wallet: &mut Wallet, statechain_id: &Uuid) -> Result<(String, Uuid, u64)> {
-    // first get required shared key data
-    let shared_key_id;
-    let pk;
-    {
-        let shared_key = wallet.get_shared_key_by_statechain_id(statechain_id)?;
-        pk = shared_key.share.public.q.get_element();
-        shared_key_id = shared_key.id.clone();
-    }
+    let (withdraw_txid, _, amount) = batch_withdraw(wallet, &vec![statechain_id.clone()])?;
+    Ok((withdraw_txid, statechain_id.clone(), amount))
+}
 
+/// Withdraw several coins from state entity in a single transaction paying to a new wallet
+/// address. Returns signed withdraw transaction, statechain_ids and withdrawn amount.
+pub fn batch_withdraw(
+    wallet: &mut Wallet,
+    statechain_ids: &Vec<Uuid>,
+) -> Result<(String, Vec<Uuid>, u64)> {
     // Generate receiving address of withdrawn funds
     let rec_se_address = wallet.keys.get_new_address()?;
 
-    // Sign state chain
-    let statechain_data: StateChainDataAPI = get_statechain(&wallet.client_shim, &statechain_id)?;
-    if statechain_data.amount == 0 {
-        return Err(CError::StateEntityError(String::from(
-            "Withdraw: StateChain is already withdrawn.",
+    // Pay everything less fees to the receiving address
+    let (total_amount, withdraw_fee) = get_withdraw_amounts(wallet, statechain_ids)?;
+    if withdraw_fee + FEE >= total_amount {
+        return Err(CError::Generic(String::from(
+            "Withdraw: Not enough value to cover fees.",
         )));
     }
-    let state_chain = statechain_data.chain;
-    // get proof key for signing
-    let proof_key_derivation = wallet
-        .se_proof_keys
-        .get_key_derivation(&PublicKey::from_str(&state_chain.last().unwrap().data).unwrap())
-        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound));
-    let statechain_sig = StateChainSig::new(
-        &proof_key_derivation.unwrap().private_key.key,
-        &String::from("WITHDRAW"),
-        &rec_se_address.to_string(),
-    )?;
+    let outputs = vec![WithdrawOutput {
+        address: rec_se_address.to_string(),
+        amount: total_amount - withdraw_fee - FEE,
+    }];
+
+    withdraw_to_outputs(wallet, statechain_ids, &outputs)
+}
+
+/// Withdraw several coins from state entity in a single transaction paying to the given outputs,
+/// e.g. several recipients or a channel funding output. The State Entity fee output is added to
+/// the outputs. Every input is co-signed before the State Entity closes any of the statechains.
+/// Returns signed withdraw transaction, statechain_ids and withdrawn amount.
+pub fn withdraw_to_outputs(
+    wallet: &mut Wallet,
+    statechain_ids: &Vec<Uuid>,
+    outputs: &Vec<WithdrawOutput>,
+) -> Result<(String, Vec<Uuid>, u64)> {
+    if statechain_ids.len() == 0 {
+        return Err(CError::Generic(String::from(
+            "Withdraw: No StateChains to withdraw.",
+        )));
+    }
+
+    // Statechains are signed over the withdraw outputs
+    let outputs_commitment = withdraw_outputs_commitment(outputs);
+
+    // first get required shared key data and sign state chains
+    let mut shared_key_ids = vec![];
+    let mut input_addrs = vec![];
+    let mut statechain_sigs = vec![];
+    for statechain_id in statechain_ids {
+        {
+            let shared_key = wallet.get_shared_key_by_statechain_id(statechain_id)?;
+            input_addrs.push(shared_key.public_key().get_element());
+            shared_key_ids.push(shared_key.id.clone());
+        }
+
+        // Sign state chain
+        let statechain_data: StateChainDataAPI =
+            get_statechain(&wallet.client_shim, &statechain_id)?;
+        if statechain_data.amount == 0 {
+            return Err(CError::StateEntityError(format!(
+                "Withdraw: StateChain {} is already withdrawn.",
+                statechain_id
+            )));
+        }
+        let state_chain = statechain_data.chain;
+        // get proof key for signing
+        let proof_key_derivation = wallet
+            .se_proof_keys
+            .get_key_derivation(&PublicKey::from_str(&state_chain.last().unwrap().data).unwrap())
+            .ok_or(CError::WalletError(WalletErrorType::KeyNotFound));
+        statechain_sigs.push(StateChainSig::new(
+            &proof_key_derivation.unwrap().private_key.key,
+            &String::from("WITHDRAW"),
+            &outputs_commitment,
+        )?);
+    }
 
-    // Alert SE of desire of withdraw and receive authorisation if state chain signature verifies
+    // Alert SE of desire of withdraw and receive authorisation if state chain signatures verify
     requests::postb(
         &wallet.client_shim,
         &format!("withdraw/init"),
         &WithdrawMsg1 {
-            shared_key_id: shared_key_id.clone(),
-            statechain_sig,
+            shared_key_ids: shared_key_ids.clone(),
+            statechain_sigs,
+            outputs: outputs.clone(),
         },
     )?;
 
-    // Get state chain info
-    let sc_info = get_statechain(&wallet.client_shim, &statechain_id)?;
     // Get state entity withdraw fee info
     let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
 
-    //calculate SE fee amount from rate
-    let withdraw_fee = (sc_info.amount * se_fee_info.withdraw) / 10000 as u64;
+    // Get state chain info
+    let mut funding_outpoints = vec![];
+    let mut input_amounts = vec![];
+    for statechain_id in statechain_ids {
+        let sc_info = get_statechain(&wallet.client_shim, &statechain_id)?;
+        funding_outpoints.push(sc_info.utxo);
+        input_amounts.push(sc_info.amount);
+    }
+    let total_amount: u64 = input_amounts.iter().sum();
+
+    //calculate SE fee amount from rate. A single fee is paid on the total withdrawn.
+    let withdraw_fee = (total_amount * se_fee_info.withdraw) / 10000 as u64;
 
-    // Construct withdraw tx
-    let tx_withdraw_unsigned = tx_withdraw_build(
-        &sc_info.utxo.txid,
-        &rec_se_address,
-        &(sc_info.amount + se_fee_info.deposit),
+    // Construct withdraw tx with one input per statecoin
+    let tx_withdraw_unsigned = tx_withdraw_build_outputs(
+        &funding_outpoints,
+        outputs,
         &withdraw_fee,
         &se_fee_info.address,
     )?;
 
-    // co-sign withdraw tx
-    let tx_w_prepare_sign_msg = PrepareSignTxMsg {
-        shared_key_id: shared_key_id.to_owned(),
-        protocol: Protocol::Withdraw,
-        tx_hex: transaction_serialise(&tx_withdraw_unsigned),
-        input_addrs: vec![pk],
-        input_amounts: vec![sc_info.amount],
-        proof_key: None,
-    };
-    cosign_tx_input(wallet, &tx_w_prepare_sign_msg)?;
-
-    let witness: Vec<Vec<u8>> = requests::postb(
+    // co-sign every withdraw tx input
+    for (input_index, shared_key_id) in shared_key_ids.iter().enumerate() {
+        let tx_w_prepare_sign_msg = PrepareSignTxMsg {
+            shared_key_id: shared_key_id.to_owned(),
+            protocol: Protocol::Withdraw,
+            tx_hex: transaction_serialise(&tx_withdraw_unsigned),
+            input_addrs: input_addrs.clone(),
+            input_amounts: input_amounts.clone(),
+            proof_key: None,
+        };
+        cosign_tx_input_index(wallet, &tx_w_prepare_sign_msg, input_index)?;
+    }
+
+    let witnesses: Vec<Vec<Vec<u8>>> = requests::postb(
         &wallet.client_shim,
         &format!("/withdraw/confirm"),
         &WithdrawMsg2 {
-            shared_key_id: shared_key_id.to_owned(),
-            address: rec_se_address.to_string(),
+            shared_key_ids: shared_key_ids.clone(),
         },
     )?;
 
     let mut tx_withdraw_signed = tx_withdraw_unsigned.clone();
-    tx_withdraw_signed.input[0].witness = witness;
+    for (input, witness) in tx_withdraw_signed.input.iter_mut().zip(witnesses.into_iter()) {
+        input.witness = witness;
+    }
 
     // Mark funds as withdrawn in wallet
-    {
-        let mut shared_key = wallet.get_shared_key_mut(&shared_key_id)?;
+    for shared_key_id in &shared_key_ids {
+        let mut shared_key = wallet.get_shared_key_mut(shared_key_id)?;
         shared_key.unspent = false;
     }
 
@@ -126,7 +188,17 @@ pub fn withdraw(wallet: &mut Wallet, statechain_id: &Uuid) -> Result<(String, Uu
 
     Ok((
         withdraw_txid,
-        statechain_id.clone(),
-        statechain_data.amount - se_fee_info.withdraw,
+        statechain_ids.clone(),
+        outputs.iter().map(|output| output.amount).sum(),
     ))
 }
+
+/// Get total value of statecoins and State Entity fee for withdrawing them
+fn get_withdraw_amounts(wallet: &Wallet, statechain_ids: &Vec<Uuid>) -> Result<(u64, u64)> {
+    let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
+    let mut total_amount = 0;
+    for statechain_id in statechain_ids {
+        total_amount += get_statechain(&wallet.client_shim, &statechain_id)?.amount;
+    }
+    Ok((total_amount, (total_amount * se_fee_info.withdraw) / 10000 as u64))
+}
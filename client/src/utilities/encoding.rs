@@ -3,7 +3,7 @@
 //! Bech32 encoding for statecoin addresses and messages
 
 use bech32::{self, FromBase32, ToBase32};
-use shared_lib::structs::{SCEAddress,TransferMsg3,FESer,PrepareSignTxMsg};
+use shared_lib::structs::{SCEAddress,TransferMsg3,FESer,KeyType,PrepareSignTxMsg};
 use shared_lib::state_chain::StateChainSig;
 use bitcoin::secp256k1;
 use bitcoin::{Address, Network, PublicKey};
@@ -50,6 +50,13 @@ pub struct CompactTransfer {
     pub sig: String,
     pub statechain_id: Uuid,
     pub tx_backup_psm: PrepareSignTxMsg,
+    // omitted for ECDSA keys so that their messages keep the original encoding
+    #[serde(default, skip_serializing_if = "is_ecdsa")]
+    pub key_type: KeyType,
+}
+
+fn is_ecdsa(key_type: &KeyType) -> bool {
+    *key_type == KeyType::Ecdsa
 }
 
 // Encode a mercury transaction message in bech32 format
@@ -61,6 +68,7 @@ pub fn encode_message(message: TransferMsg3) -> Result<String> {
 		sig: message.statechain_sig.sig.clone(),
 		statechain_id: message.statechain_id.clone(),
 		tx_backup_psm: message.tx_backup_psm.clone(),
+		key_type: message.key_type,
 	};
 
 	let encoded_msg = rmp_serde::to_vec(&compact).unwrap();
@@ -98,6 +106,7 @@ pub fn decode_message(message: String, network: &String) -> Result<TransferMsg3>
 	    	tx_backup_addr,
 	    	proof_key: decoded_struct.proof_key.key
 	    },
+	    key_type: decoded_struct.key_type,
 	};
 
 	Ok(transfer_msg3)
@@ -138,4 +147,19 @@ mod tests {
 
         assert_eq!(b32enc.to_string(),mmessage);
     }
+
+    #[test]
+    fn test_message_encoding_key_type() {
+        let mut transfer_msg_3 =
+            serde_json::from_str::<TransferMsg3>(&TRANSFER_MSG_3.to_string()).unwrap();
+        let network = "regtest".to_string();
+
+        // ECDSA messages omit the key type
+        let b32enc = encode_message(transfer_msg_3.clone()).unwrap();
+        assert_eq!(decode_message(b32enc, &network).unwrap().key_type, KeyType::Ecdsa);
+
+        transfer_msg_3.key_type = KeyType::Taproot;
+        let b32enc = encode_message(transfer_msg_3).unwrap();
+        assert_eq!(decode_message(b32enc, &network).unwrap().key_type, KeyType::Taproot);
+    }
 }
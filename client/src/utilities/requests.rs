@@ -9,6 +9,8 @@ use std::time::Instant;
 use super::super::ClientShim;
 use super::super::Result;
 use crate::error::CError;
+use reqwest::header::CONTENT_TYPE;
+use shared_lib::request_auth::{session_ids, RequestAuth, SESSION_HEADERS};
 
 pub fn postb<T, V>(client_shim: &ClientShim, path: &str, body: T) -> Result<V>
 where
@@ -34,8 +36,21 @@ where
         b = b.bearer_auth(client_shim.auth_token.clone().unwrap());
     }
 
+    // Sign requests within sessions with the sessions' proof keys
+    let body = serde_json::to_vec(&body)?;
+    let shared_key_ids = session_ids(&serde_json::from_slice(&body)?);
+    if !shared_key_ids.is_empty() {
+        if let Some(keys) = client_shim.get_session_keys(&shared_key_ids) {
+            let auth = RequestAuth::new_multi(&keys, path, &body)?;
+            b = b
+                .header(SESSION_HEADERS.signature, auth.signature)
+                .header(SESSION_HEADERS.timestamp, auth.timestamp.to_string())
+                .header(SESSION_HEADERS.nonce, auth.nonce);
+        }
+    }
+
     // catch reqwest errors
-    let value = match b.json(&body).send() {
+    let value = match b.header(CONTENT_TYPE, "application/json").body(body).send() {
         Ok(v) => {
             //Reject responses that are too long
             match v.content_length() {
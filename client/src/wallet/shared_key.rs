@@ -2,15 +2,18 @@
 //!
 //! Key shares of co-owned keys between user and server.
 
-use super::super::{ecdsa, ClientShim, Result};
+use super::super::{ecdsa, musig, ClientShim, Result};
+use super::wallet::to_bitcoin_public_key;
 use shared_lib::{
-    structs::{PrepareSignTxMsg, Protocol},
+    musig::shared_key_script_pubkey,
+    structs::{KeyType, PrepareSignTxMsg, Protocol},
     Root,
 };
 
 use bitcoin::secp256k1::key::SecretKey;
-use curv::elliptic::curves::traits::ECScalar;
-use curv::FE;
+use bitcoin::{Address, Network, Script};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::{FE, GE};
 use kms::ecdsa::two_party::MasterKey2;
 use monotree::Proof;
 use uuid::Uuid;
@@ -20,17 +23,35 @@ pub struct InclusionProofSMT {
     pub root: Root,
     pub proof: Option<Proof>,
 }
+
+/// Owner key share and shared public key P of a MuSig2 shared key
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MuSigShare {
+    pub private: FE,
+    pub public: GE,
+}
+
+/// Owner key share of a shared key. Untagged so that wallets saved before Taproot keys were
+/// added still load.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyShare {
+    Ecdsa(MasterKey2),
+    Taproot(MuSigShare),
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SharedKey {
     pub id: Uuid,
-    pub share: MasterKey2,
+    pub share: KeyShare,
     pub value: u64, //Satoshis
     pub statechain_id: Option<Uuid>,
     pub tx_backup_psm: Option<PrepareSignTxMsg>, // back up transaction data
+    pub tx_kickoff_hex: Option<String>, // signed kick-off transaction in relative locktime mode
     pub proof_key: Option<String>,
     pub smt_proof: Option<InclusionProofSMT>,
     pub unspent: bool,
-    pub funding_txid: String,
+    pub funding_txid: String, // sparse merkle tree key of the funding outpoint
 }
 
 impl SharedKey {
@@ -40,10 +61,53 @@ impl SharedKey {
         secret_key: &SecretKey,
         value: &u64,
         protocol: Protocol,
+        key_type: &KeyType,
     ) -> Result<SharedKey> {
         let mut key_share_priv: FE = ECScalar::zero(); // convert to curv lib
         key_share_priv.set_element(*secret_key);
-        ecdsa::get_master_key(id, client_shim, &key_share_priv, value, protocol)
+        match key_type {
+            KeyType::Ecdsa => {
+                ecdsa::get_master_key(id, client_shim, &key_share_priv, value, protocol)
+            }
+            KeyType::Taproot => {
+                musig::get_musig_key(id, client_shim, &key_share_priv, value, protocol)
+            }
+        }
+    }
+
+    pub fn key_type(&self) -> KeyType {
+        match self.share {
+            KeyShare::Ecdsa(_) => KeyType::Ecdsa,
+            KeyShare::Taproot(_) => KeyType::Taproot,
+        }
+    }
+
+    /// Shared public key P
+    pub fn public_key(&self) -> GE {
+        match &self.share {
+            KeyShare::Ecdsa(master_key) => master_key.public.q,
+            KeyShare::Taproot(musig_share) => musig_share.public,
+        }
+    }
+
+    /// Owner private key share o
+    pub fn private_key(&self) -> FE {
+        match &self.share {
+            KeyShare::Ecdsa(master_key) => master_key.private.get_private_key(),
+            KeyShare::Taproot(musig_share) => musig_share.private,
+        }
+    }
+
+    /// Script pubkey of outputs paying to the shared key: P2WPKH for 2P-ECDSA keys and key path
+    /// only P2TR for Taproot keys
+    pub fn script_pubkey(&self, network: Network) -> Result<Script> {
+        let pk = self.public_key().get_element();
+        match self.key_type() {
+            KeyType::Ecdsa => {
+                Ok(Address::p2wpkh(&to_bitcoin_public_key(pk), network)?.script_pubkey())
+            }
+            KeyType::Taproot => Ok(shared_key_script_pubkey(&pk)?),
+        }
     }
 
     pub fn add_proof_data(
@@ -7,7 +7,7 @@ use shared_lib::{
     ecies,
     ecies::{SelfEncryptable, WalletDecryptable},
     mocks::mock_electrum::MockElectrum,
-    structs::{Protocol, SCEAddress},
+    structs::{KeyType, Protocol, SCEAddress},
     util::{transaction_deserialise, get_sighash},
 };
 
@@ -273,6 +273,15 @@ impl Wallet {
             wallet.shared_keys = shared_keys;
         }
 
+        // sign requests within the sessions of loaded shared keys
+        for shared_key in &wallet.shared_keys {
+            if let Some(proof_key) = &shared_key.proof_key {
+                if let Ok(proof_key) = PublicKey::from_str(proof_key) {
+                    wallet.add_session_key(&shared_key.id, &proof_key)?;
+                }
+            }
+        }
+
         debug!("(wallet id: {}) Loaded wallet to memory", wallet.id);
         Ok(wallet)
     }
@@ -409,6 +418,16 @@ impl Wallet {
 
     /// create new 2P-ECDSA key with state entity
     pub fn gen_shared_key(&mut self, id: &Uuid, value: &u64) -> Result<&SharedKey> {
+        self.gen_shared_key_with_type(id, value, &KeyType::Ecdsa)
+    }
+
+    /// create new 2P-ECDSA or MuSig2 key with state entity
+    pub fn gen_shared_key_with_type(
+        &mut self,
+        id: &Uuid,
+        value: &u64,
+        key_type: &KeyType,
+    ) -> Result<&SharedKey> {
         let key_share_pub = self.se_key_shares.get_new_key()?;
         let key_share_priv = self
             .se_key_shares
@@ -423,17 +442,19 @@ impl Wallet {
             &key_share_priv,
             value,
             Protocol::Deposit,
+            key_type,
         )?;
         self.shared_keys.push(shared_key);
         Ok(self.shared_keys.last().unwrap())
     }
 
-    /// create new 2P-ECDSA key with pre-definfed private key
+    /// create new 2P-ECDSA or MuSig2 key with pre-definfed private key
     pub fn gen_shared_key_fixed_secret_key(
         &mut self,
         id: &Uuid,
         secret_key: &SecretKey,
         value: &u64,
+        key_type: &KeyType,
     ) -> Result<()> {
         self.shared_keys.push(SharedKey::new(
             id,
@@ -441,10 +462,22 @@ impl Wallet {
             secret_key,
             value,
             Protocol::Transfer,
+            key_type,
         )?);
         Ok(())
     }
 
+    /// Sign requests within the session id with the private key of proof_key
+    pub fn add_session_key(&self, id: &Uuid, proof_key: &PublicKey) -> Result<()> {
+        let proof_key_derivation = self
+            .se_proof_keys
+            .get_key_derivation(proof_key)
+            .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+        self.client_shim
+            .add_session_key(id, &proof_key_derivation.private_key.key);
+        Ok(())
+    }
+
     /// Get shared key by id. Return None if no shared key with given id.
     pub fn get_shared_key(&self, id: &Uuid) -> Result<&SharedKey> {
         for shared in &self.shared_keys {
@@ -13,6 +13,7 @@ extern crate curv;
 extern crate monotree;
 
 extern crate client_lib;
+extern crate lockbox_lib;
 extern crate server_lib;
 extern crate shared_lib;
 
@@ -26,8 +27,9 @@ use client_lib::*;
 use bitcoin::{PublicKey, Transaction};
 use floating_duration::TimeFormat;
 use monotree::database::{Database as monotreeDatabase, MemoryDB};
+use rocket::config::{Config as RocketConfig, Environment};
 use rocket::error::LaunchError;
-use server_lib::{server, Database, MockDatabase, PGDatabase};
+use server_lib::{server, Database, MockDatabase, StorageDatabase};
 use shared_lib::{
     commitment::make_commitment,
     mainstay,
@@ -96,7 +98,7 @@ pub trait SpawnServer {
     ) -> thread::JoinHandle<SpawnError>;
 }
 
-impl SpawnServer for PGDatabase {
+impl SpawnServer for StorageDatabase {
     /// Spawn a StateChain Entity server in testing mode if there isn't one running already.
     /// Returns Ok(()) if a new server was spawned, otherwise returns an error.
     fn spawn_server(
@@ -108,10 +110,10 @@ impl SpawnServer for PGDatabase {
 
         // Rocket server is blocking, so we spawn a new thread.
         let handle = thread::spawn(|| {
-            match server::get_server::<Self, PGDatabase>(
+            match server::get_server::<Self, StorageDatabase>(
                 mainstay_config,
                 self,
-                PGDatabase::get_new(),
+                StorageDatabase::get_new(),
             ) {
                 Ok(s) => {
                     let try_launch = s.launch();
@@ -413,5 +415,41 @@ pub fn batch_transfer_verify_amounts(
 }
 
 pub fn start_server() -> thread::JoinHandle<SpawnError> {
-    PGDatabase::get_new().spawn_server(None)
+    StorageDatabase::get_new().spawn_server(None)
+}
+
+/// Port of the lockbox spawned by spawn_lockbox
+pub const LOCKBOX_PORT: u16 = 8001;
+
+/// Spawn a local lockbox with an empty key store and point the server at it, with requests
+/// signed and secrets encrypted using new keys. The server reads the lockbox config on start up
+/// so this must be called before the server is spawned.
+pub fn spawn_lockbox() -> thread::JoinHandle<SpawnError> {
+    let (se_key, se_pubkey) = shared_lib::util::keygen::generate_keypair();
+    let (lockbox_key, lockbox_pubkey) = shared_lib::util::keygen::generate_keypair();
+    env::set_var("MERC_LOCKBOX", format!("http://localhost:{}", LOCKBOX_PORT));
+    env::set_var("MERC_LOCKBOX_KEY", se_key.to_wif());
+    env::set_var("MERC_LOCKBOX_PUBKEY", lockbox_pubkey.to_string());
+
+    let handle = thread::spawn(move || {
+        let config = lockbox_lib::server::Config {
+            key_store_dir: env::temp_dir().join(format!("lockbox-{}", Uuid::new_v4())),
+            key: lockbox_key,
+            se_pubkey: se_pubkey.key,
+        };
+        let rocket_config = RocketConfig::build(Environment::Development)
+            .port(LOCKBOX_PORT)
+            .finalize()
+            .unwrap();
+        match lockbox_lib::server::get_server(Some(rocket_config), config) {
+            Ok(s) => {
+                let try_launch = s.launch();
+                let _ = try_launch.kind(); // LaunchError needs to be accessed here for this to work. Be carfeul modifying this code.
+                try_launch.into()
+            }
+            Err(_) => SpawnError::GetServer,
+        }
+    });
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    handle
 }
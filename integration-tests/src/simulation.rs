@@ -22,6 +22,8 @@ pub fn run_simulation() {
     let mainstay_config = mainstay::MainstayConfig::mock_from_url(&mockito::server_url());
     let mut db = MockDatabase::new();
     db.expect_set_connection_from_config().returning(|_| Ok(()));
+    db.expect_get_swap_registrations().returning(|| Ok(vec![]));
+    db.expect_get_swaps().returning(|| Ok(vec![]));
     let _ = db.spawn_server(Some(mainstay_config));
 
     // Begin with a few clients
@@ -8,7 +8,7 @@ mod tests {
     extern crate shared_lib;
     extern crate time_test;
 
-    use shared_lib::structs::Protocol;
+    use shared_lib::structs::{KeyType, Protocol};
 
     use curv::elliptic::curves::traits::ECScalar;
     use curv::FE;
@@ -46,6 +46,41 @@ mod tests {
         assert!(err.is_err());
     }
 
+    #[test]
+    #[serial]
+    fn test_failed_session_auth() {
+        time_test!();
+        let _handle = start_server();
+        let mut wallet = gen_wallet();
+        let proof_key = wallet.se_proof_keys.get_new_key().unwrap();
+        let init_res =
+            client_lib::state_entity::deposit::session_init(&mut wallet, &proof_key.to_string())
+                .unwrap();
+        // Requests without the session's proof key are rejected
+        let client_shim = ClientShim::new("http://localhost:8000".to_string(), None, None);
+        let secret_key: FE = ECScalar::new_random();
+        let err = ecdsa::get_master_key(
+            &init_res.id,
+            &client_shim,
+            &secret_key,
+            &1000,
+            Protocol::Deposit,
+        );
+        assert!(err.is_err());
+        // and so are requests signed by another key
+        let (other_key, _) = shared_lib::util::keygen::generate_keypair();
+        client_shim.add_session_key(&init_res.id, &other_key.key);
+        let err = ecdsa::get_master_key(
+            &init_res.id,
+            &client_shim,
+            &secret_key,
+            &1000,
+            Protocol::Deposit,
+        );
+        assert!(err.is_err());
+        assert!(wallet.gen_shared_key(&init_res.id, &1000).is_ok());
+    }
+
     #[test]
     #[serial]
     fn test_deposit() {
@@ -133,15 +168,11 @@ mod tests {
             wallets[0]
                 .get_shared_key(shared_key_id)
                 .unwrap()
-                .share
-                .public
-                .q,
+                .public_key(),
             wallets[1]
                 .get_shared_key(&new_shared_key_id)
                 .unwrap()
-                .share
-                .public
-                .q
+                .public_key()
         );
 
         // check shared key is marked spent in sender and unspent in receiver
@@ -178,6 +209,48 @@ mod tests {
         );
     }
 
+    #[test]
+    #[serial]
+    fn test_transfer_taproot() {
+        time_test!();
+        let _handle = start_server();
+        let mut wallets = vec![];
+        wallets.push(gen_wallet()); // sender
+        wallets.push(gen_wallet()); // receiver
+
+        let deposit = state_entity::deposit::deposit_batch_with_type(
+            &mut wallets[0],
+            &vec![10000],
+            &KeyType::Taproot,
+        )
+        .unwrap()
+        .remove(0);
+        let (shared_key_id, statechain_id) = (deposit.0, deposit.1);
+        assert_eq!(
+            wallets[0].get_shared_key(&shared_key_id).unwrap().key_type(),
+            KeyType::Taproot
+        );
+
+        // Backup tx is a key path spend of the P2TR funding output
+        assert_eq!(deposit.3.input[0].witness.len(), 1);
+        assert_eq!(deposit.3.input[0].witness[0].len(), 64);
+
+        let receiver_addr = wallets[1].get_new_state_entity_address().unwrap();
+        let new_shared_key_id = run_transfer(&mut wallets, 0, 1, &receiver_addr, &statechain_id);
+
+        // Additive key update keeps the shared public key
+        let new_shared_key = wallets[1].get_shared_key(&new_shared_key_id).unwrap();
+        assert_eq!(new_shared_key.key_type(), KeyType::Taproot);
+        assert_eq!(
+            wallets[0].get_shared_key(&shared_key_id).unwrap().public_key(),
+            new_shared_key.public_key()
+        );
+
+        // Receiver can sign with the new shared key
+        run_withdraw(&mut wallets[1], &statechain_id);
+        assert!(!wallets[1].get_shared_key(&new_shared_key_id).unwrap().unspent);
+    }
+
     #[test]
     #[serial]
     fn test_double_transfer() {
@@ -244,29 +317,21 @@ mod tests {
             wallets[0]
                 .get_shared_key(&shared_key_id0)
                 .unwrap()
-                .share
-                .public
-                .q,
+                .public_key(),
             wallets[1]
                 .get_shared_key(shared_key_id1)
                 .unwrap()
-                .share
-                .public
-                .q
+                .public_key()
         );
         assert_eq!(
             wallets[1]
                 .get_shared_key(shared_key_id1)
                 .unwrap()
-                .share
-                .public
-                .q,
+                .public_key(),
             wallets[2]
                 .get_shared_key(&new_shared_key_id2)
                 .unwrap()
-                .share
-                .public
-                .q
+                .public_key()
         );
 
         // check shared key is marked spent in wallets 0, 1 and unspent in 2
@@ -387,7 +452,7 @@ mod tests {
         let shared_key_rebuilt = wallet_rebuilt.shared_keys.get(0).unwrap();
 
         assert_eq!(shared_key.id, shared_key_rebuilt.id);
-        assert_eq!(shared_key.share.public, shared_key_rebuilt.share.public);
+        assert_eq!(shared_key.public_key(), shared_key_rebuilt.public_key());
         assert_eq!(shared_key.proof_key, shared_key_rebuilt.proof_key);
         assert_eq!(
             shared_key.smt_proof.clone().unwrap().root,
@@ -398,6 +463,37 @@ mod tests {
             shared_key_rebuilt.smt_proof.clone().unwrap().proof
         );
     }
+
+    #[test]
+    //The server only reads the lockbox URL on start up, so this test must be the first to start
+    //a server in the process. Run with: cargo test test_transfer_lockbox -- --ignored
+    #[ignore]
+    #[serial]
+    fn test_transfer_lockbox() {
+        time_test!();
+        let _lockbox_handle = spawn_lockbox();
+        let _handle = start_server();
+        let mut wallets = vec![];
+        wallets.push(gen_wallet_with_deposit(10000)); // sender
+        wallets.push(gen_wallet()); // receiver
+
+        let state_chains_info = wallets[0].get_state_chains_info().unwrap();
+        let shared_key_id = state_chains_info.0.last().unwrap();
+        let (statechain_id, _, _, _, _) = wallets[0].get_shared_key_info(shared_key_id).unwrap();
+
+        let receiver_addr = wallets[1].get_new_state_entity_address().unwrap();
+        let new_shared_key_id = run_transfer(&mut wallets, 0, 1, &receiver_addr, &statechain_id);
+
+        // Key update in the lockbox keeps the shared public key
+        assert_eq!(
+            wallets[0].get_shared_key(shared_key_id).unwrap().public_key(),
+            wallets[1].get_shared_key(&new_shared_key_id).unwrap().public_key()
+        );
+
+        // Receiver can sign with the new shared key
+        run_withdraw(&mut wallets[1], &statechain_id);
+        assert!(!wallets[1].get_shared_key(&new_shared_key_id).unwrap().unspent);
+    }
 }
 
 #[cfg(feature = "mockdb")]
@@ -425,6 +521,8 @@ mod tests {
         let mut db = MockDatabase::new();
         let wallet = gen_wallet();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_swap_registrations().returning(|| Ok(vec![]));
+        db.expect_get_swaps().returning(|| Ok(vec![]));
         db.expect_reset().returning(|| Ok(()));
         let invalid_scid = Uuid::new_v4();
         db.expect_get_statechain_amount().returning(|_x| {
@@ -0,0 +1,177 @@
+//! Auth
+//!
+//! Authentication of state entity requests. Request bodies are only deserialized after the
+//! signature in the request headers has been verified. Responses to signed requests are signed
+//! with the lockbox key.
+
+use shared_lib::request_auth::{
+    ReplayGuard, RequestAuth, LOCKBOX_HEADERS, LOCKBOX_RESPONSE_HEADERS,
+};
+
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+use rocket::data::{self, Data, FromDataSimple};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::{Request, Response, State};
+use serde::de::DeserializeOwned;
+use std::io::{Cursor, Read};
+use std::sync::Mutex;
+
+// Maximum request body size
+const LIMIT: u64 = 1000000;
+
+pub struct Authenticator {
+    /// State entity key requests must be signed with. None to accept unsigned requests.
+    se_pubkey: Option<PublicKey>,
+    replay_guard: Mutex<ReplayGuard>,
+}
+
+impl Authenticator {
+    pub fn new(se_pubkey: Option<PublicKey>) -> Authenticator {
+        if se_pubkey.is_none() {
+            warn!("AUTH: No state entity public key set. Requests are not authenticated.");
+        }
+        Authenticator {
+            se_pubkey,
+            replay_guard: Mutex::new(ReplayGuard::default()),
+        }
+    }
+
+    /// Check the request to path is signed by the state entity and not replayed
+    pub fn check(&self, auth: Option<RequestAuth>, path: &str, body: &[u8]) -> Result<(), String> {
+        let se_pubkey = match &self.se_pubkey {
+            Some(k) => k,
+            None => return Ok(()),
+        };
+        let auth = auth.ok_or(String::from("Request not signed"))?;
+        auth.verify(se_pubkey, path, body)
+            .map_err(|e| format!("Invalid request signature: {}", e))?;
+        self.replay_guard
+            .lock()
+            .map_err(|e| e.to_string())?
+            .check(&auth)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// JSON request body from an authenticated state entity request
+pub struct Authenticated<T>(pub T);
+
+impl<T> Authenticated<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromDataSimple for Authenticated<T> {
+    type Error = String;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, String> {
+        let mut body = Vec::new();
+        if let Err(e) = data.open().take(LIMIT).read_to_end(&mut body) {
+            return Outcome::Failure((Status::InternalServerError, e.to_string()));
+        }
+
+        let authenticator = match request.guard::<State<Authenticator>>() {
+            Outcome::Success(a) => a,
+            _ => {
+                return Outcome::Failure((
+                    Status::InternalServerError,
+                    String::from("Authenticator not managed"),
+                ))
+            }
+        };
+
+        let headers = request.headers();
+        let auth = match RequestAuth::from_headers(
+            headers.get_one(LOCKBOX_HEADERS.signature),
+            headers.get_one(LOCKBOX_HEADERS.timestamp),
+            headers.get_one(LOCKBOX_HEADERS.nonce),
+        ) {
+            Ok(a) => a,
+            Err(e) => return Outcome::Failure((Status::Unauthorized, e.to_string())),
+        };
+        if let Err(e) = authenticator.check(auth, request.uri().path(), &body) {
+            warn!("AUTH: Request to {} rejected: {}", request.uri().path(), e);
+            return Outcome::Failure((Status::Unauthorized, e));
+        }
+
+        match serde_json::from_slice(&body) {
+            Ok(v) => Outcome::Success(Authenticated(v)),
+            Err(e) => Outcome::Failure((Status::BadRequest, e.to_string())),
+        }
+    }
+}
+
+/// Signs the response to each signed request with the lockbox key, bound to the request's path
+/// and nonce
+pub struct ResponseSigner {
+    key: SecretKey,
+}
+
+impl ResponseSigner {
+    pub fn new(key: SecretKey) -> ResponseSigner {
+        ResponseSigner { key }
+    }
+}
+
+impl Fairing for ResponseSigner {
+    fn info(&self) -> Info {
+        Info {
+            name: "Lockbox response signer",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let request_nonce = match request.headers().get_one(LOCKBOX_HEADERS.nonce) {
+            Some(n) => n,
+            None => return,
+        };
+        let body = response.body_bytes().unwrap_or_default();
+        match RequestAuth::new_response(&self.key, request.uri().path(), request_nonce, &body) {
+            Ok(auth) => {
+                response.set_raw_header(LOCKBOX_RESPONSE_HEADERS.signature, auth.signature);
+                response.set_raw_header(
+                    LOCKBOX_RESPONSE_HEADERS.timestamp,
+                    auth.timestamp.to_string(),
+                );
+                response.set_raw_header(LOCKBOX_RESPONSE_HEADERS.nonce, auth.nonce);
+            }
+            Err(e) => warn!("AUTH: Failed to sign response to {}: {}", request.uri().path(), e),
+        }
+        response.set_sized_body(Cursor::new(body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+    #[test]
+    fn test_authenticator() {
+        let key = SecretKey::from_slice(&[0xcd; 32]).unwrap();
+        let se_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &key);
+        let body = b"{}";
+
+        // Unsigned requests are accepted without a state entity key
+        assert!(Authenticator::new(None).check(None, "/ecdsa/sign/first", body).is_ok());
+
+        let authenticator = Authenticator::new(Some(se_pubkey));
+        assert!(authenticator.check(None, "/ecdsa/sign/first", body).is_err());
+
+        let auth = RequestAuth::new(&key, "ecdsa/sign/first", body).unwrap();
+        assert!(authenticator
+            .check(Some(auth.clone()), "/ecdsa/sign/second", body)
+            .is_err());
+        assert!(authenticator
+            .check(Some(auth.clone()), "/ecdsa/sign/first", body)
+            .is_ok());
+        // Replay
+        assert!(authenticator
+            .check(Some(auth), "/ecdsa/sign/first", body)
+            .is_err());
+    }
+}
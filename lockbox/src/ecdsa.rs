@@ -0,0 +1,452 @@
+//! Ecdsa
+//!
+//! Lockbox side of the 2P-ECDSA key generation, signing and transfer key update protocols.
+//! Requests come from the state entity server, which has already authenticated the user and
+//! validated the message being signed.
+
+use super::Result;
+use crate::auth::Authenticated;
+use crate::error::LockboxError;
+use crate::keystore::{KeyStore, KeyUpdate, LockboxKey};
+use shared_lib::{
+    ecies::SelfEncryptable,
+    structs::{
+        KUAttest, KUFinalize, KUReceiveMsg, KUSendMsg, KeyGenMsg1, KeyGenMsg2, Protocol,
+        SignMsg1, SignMsg2,
+    },
+};
+
+use bitcoin::secp256k1::{key::SecretKey, Signature};
+use bitcoin::{network::constants::Network, util::key::PrivateKey};
+use curv::{
+    arithmetic::traits::Converter,
+    elliptic::curves::traits::{ECPoint, ECScalar},
+    {BigInt, FE, GE},
+};
+use kms::ecdsa::two_party::MasterKey1;
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party1};
+use rocket::State;
+use rocket_contrib::json::Json;
+use std::path::Path;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+pub struct Lockbox {
+    key_store: Mutex<KeyStore>,
+    /// Key that secret request fields are encrypted to
+    key: Option<PrivateKey>,
+}
+
+// BigInt conversion drops leading zeros
+fn to_32_bytes(n: &BigInt) -> Vec<u8> {
+    let v = BigInt::to_vec(n);
+    if v.len() >= 32 {
+        return v;
+    }
+    let mut padded = vec![0; 32 - v.len()];
+    padded.extend(v);
+    padded
+}
+
+impl Lockbox {
+    pub fn new(key_store_dir: &Path, key: Option<PrivateKey>) -> Result<Lockbox> {
+        Ok(Lockbox {
+            key_store: Mutex::new(KeyStore::new(key_store_dir)?),
+            key,
+        })
+    }
+
+    /// ID of the key store, which identifies the nodes holding the same keys
+    pub fn key_store_id(&self) -> Result<String> {
+        self.key_store.lock()?.id()
+    }
+
+    pub fn first_message(
+        &self,
+        key_gen_msg1: KeyGenMsg1,
+    ) -> Result<(Uuid, party_one::KeyGenFirstMsg)> {
+        let user_id = key_gen_msg1.shared_key_id;
+        let key_store = self.key_store.lock()?;
+
+        // A transfer receiver's key exists already, holding the s2 set by the key update
+        let mut key = key_store.get_key(&user_id)?.unwrap_or_default();
+        if key.master_key.is_some() {
+            return Err(LockboxError::Generic(format!(
+                "Key Generation already completed for ID {}",
+                user_id
+            )));
+        }
+
+        let (key_gen_first_msg, comm_witness, ec_key_pair) =
+            if key_gen_msg1.protocol == Protocol::Deposit {
+                MasterKey1::key_gen_first_message()
+            } else {
+                let s2 = key.s2.ok_or(LockboxError::KeyUpdateError(format!(
+                    "No key update for ID {}",
+                    user_id
+                )))?;
+                MasterKey1::key_gen_first_message_predefined(s2)
+            };
+
+        key.comm_witness = Some(comm_witness);
+        key.ec_key_pair = Some(ec_key_pair);
+        key_store.put_key(&user_id, &key)?;
+
+        info!("KEYGEN: First message. Shared Key ID: {}", user_id);
+        Ok((user_id, key_gen_first_msg))
+    }
+
+    pub fn second_message(&self, key_gen_msg2: KeyGenMsg2) -> Result<party1::KeyGenParty1Message2> {
+        let user_id = key_gen_msg2.shared_key_id;
+        let key_store = self.key_store.lock()?;
+        let mut key = key_store.get_key_required(&user_id)?;
+
+        let (comm_witness, ec_key_pair) = match (key.comm_witness.take(), key.ec_key_pair.take()) {
+            (Some(comm_witness), Some(ec_key_pair)) => (comm_witness, ec_key_pair),
+            _ => {
+                return Err(LockboxError::Generic(format!(
+                    "Key Generation first message not completed for ID {}",
+                    user_id
+                )))
+            }
+        };
+
+        let party2_public: GE = key_gen_msg2.dlog_proof.pk.clone();
+        let party1_public: GE = comm_witness.public_share.clone();
+
+        let (kg_party_one_second_message, paillier_key_pair, party_one_private) =
+            MasterKey1::key_gen_second_message(
+                comm_witness,
+                &ec_key_pair,
+                &key_gen_msg2.dlog_proof,
+            );
+
+        key.master_key = Some(MasterKey1::set_master_key(
+            &BigInt::from(0),
+            party_one_private,
+            &party1_public,
+            &party2_public,
+            paillier_key_pair,
+        ));
+        key.s2 = None;
+        key_store.put_key(&user_id, &key)?;
+
+        info!("KEYGEN: Complete. Shared Key ID: {}", user_id);
+        Ok(kg_party_one_second_message)
+    }
+
+    pub fn sign_first(&self, sign_msg1: SignMsg1) -> Result<party_one::EphKeyGenFirstMsg> {
+        let user_id = sign_msg1.shared_key_id;
+        let key_store = self.key_store.lock()?;
+        let mut key = key_store.get_key_required(&user_id)?;
+
+        let (sign_party_one_first_message, eph_ec_key_pair_party1) =
+            MasterKey1::sign_first_message();
+
+        key.eph_ec_key_pair_party1 = Some(eph_ec_key_pair_party1);
+        key.eph_key_gen_first_message_party_two =
+            Some(sign_msg1.eph_key_gen_first_message_party_two);
+        key_store.put_key(&user_id, &key)?;
+
+        Ok(sign_party_one_first_message)
+    }
+
+    /// Sign and return the witness of the input spending the shared key's output
+    pub fn sign_second(&self, sign_msg2: SignMsg2) -> Result<Vec<Vec<u8>>> {
+        let user_id = sign_msg2.shared_key_id;
+        let key_store = self.key_store.lock()?;
+        let mut key = key_store.get_key_required(&user_id)?;
+
+        let master_key = key.master_key.as_ref().ok_or(LockboxError::SigningError(format!(
+            "Key Generation not completed for ID {}",
+            user_id
+        )))?;
+        // Ephemeral keys are used for one signature only
+        let (eph_ec_key_pair_party1, eph_key_gen_first_message_party_two) = match (
+            key.eph_ec_key_pair_party1.take(),
+            key.eph_key_gen_first_message_party_two.take(),
+        ) {
+            (Some(eph_ec_key_pair_party1), Some(eph_first_message)) => {
+                (eph_ec_key_pair_party1, eph_first_message)
+            }
+            _ => {
+                return Err(LockboxError::SigningError(format!(
+                    "Sign first message not completed for ID {}",
+                    user_id
+                )))
+            }
+        };
+
+        let signature = match master_key.sign_second_message(
+            &sign_msg2.sign_second_msg_request.party_two_sign_message,
+            &eph_key_gen_first_message_party_two,
+            &eph_ec_key_pair_party1,
+            &sign_msg2.sign_second_msg_request.message,
+        ) {
+            Ok(sig) => sig,
+            Err(_) => {
+                return Err(LockboxError::SigningError(String::from(
+                    "Signature validation failed.",
+                )))
+            }
+        };
+
+        // Make signature witness
+        let mut v = to_32_bytes(&signature.r);
+        v.extend(to_32_bytes(&signature.s));
+        let mut sig_vec = Signature::from_compact(&v[..])?.serialize_der().to_vec();
+        sig_vec.push(01);
+        let pk_vec = master_key.public.q.get_element().serialize().to_vec();
+
+        key_store.put_key(&user_id, &key)?;
+
+        info!("SIGN: Signed. Shared Key ID: {}", user_id);
+        Ok(vec![sig_vec, pk_vec])
+    }
+
+    /// Compute the receiver's private key share from the sender's share and the transfer
+    /// messages. The new share is held until the transfer is finalized.
+    pub fn keyupdate_first(&self, mut ku_send: KUSendMsg) -> Result<KUReceiveMsg> {
+        if let Some(key) = &self.key {
+            if let Err(e) = ku_send.decrypt(key) {
+                return Err(LockboxError::KeyUpdateError(format!(
+                    "Failed to decrypt x1: {}",
+                    e
+                )));
+            }
+        }
+        let x1 = ku_send
+            .x1
+            .get_fe()
+            .map_err(|e| LockboxError::KeyUpdateError(format!("Failed to get FE from x1: {}", e)))?;
+
+        let user_id = ku_send.user_id;
+        let statechain_id = ku_send.statechain_id;
+        let key_store = self.key_store.lock()?;
+        let key = key_store.get_key_required(&user_id)?;
+
+        let master_key = key.master_key.ok_or(LockboxError::KeyUpdateError(format!(
+            "Key Generation not completed for ID {}",
+            user_id
+        )))?;
+
+        let s1 = master_key.private.get_private_key();
+        let s1_priv = PrivateKey {
+            compressed: true,
+            network: Network::Regtest,
+            key: SecretKey::from_slice(&to_32_bytes(&s1.to_big_int()))?,
+        };
+
+        let mut t2 = ku_send.t2;
+        if let Err(e) = t2.decrypt(&s1_priv) {
+            return Err(LockboxError::KeyUpdateError(format!(
+                "Failed to decrypt t2: {}",
+                e
+            )));
+        }
+        let t2 = t2
+            .get_fe()
+            .map_err(|e| LockboxError::KeyUpdateError(format!("Failed to get FE from t2: {}", e)))?;
+
+        let s2: FE = t2 * (x1.invert()) * s1;
+
+        // Check P1 = o1_pub*s1 === p2 = o2_pub*s2
+        if master_key.public.p2 * s1 != ku_send.o2_pub * s2 {
+            error!("KEYUPDATE: Protocol failed. P1 != P2. Shared Key ID: {}", user_id);
+            return Err(LockboxError::KeyUpdateError(String::from("P1 != P2")));
+        }
+
+        key_store.put_key_update(
+            &statechain_id,
+            &KeyUpdate {
+                shared_key_id: user_id,
+                s2,
+            },
+        )?;
+
+        info!("KEYUPDATE: First message. State Chain ID: {}", statechain_id);
+        let g: GE = ECPoint::generator();
+        Ok(KUReceiveMsg { s2_pub: g * s2 })
+    }
+
+    /// Give the key update share to the receiver's new shared key and delete the sender's key
+    pub fn keyupdate_second(&self, ku_finalize: KUFinalize) -> Result<KUAttest> {
+        let statechain_id = ku_finalize.statechain_id;
+        let key_store = self.key_store.lock()?;
+
+        let key_update = key_store.get_key_update(&statechain_id)?.ok_or(
+            LockboxError::KeyUpdateError(format!(
+                "No key update for State Chain ID {}",
+                statechain_id
+            )),
+        )?;
+
+        key_store.put_key(
+            &ku_finalize.shared_key_id,
+            &LockboxKey {
+                s2: Some(key_update.s2),
+                ..Default::default()
+            },
+        )?;
+        key_store.remove_key(&key_update.shared_key_id)?;
+        key_store.remove_key_update(&statechain_id)?;
+
+        info!(
+            "KEYUPDATE: Complete. Deleted Shared Key ID: {}. New Shared Key ID: {}",
+            key_update.shared_key_id, ku_finalize.shared_key_id
+        );
+        // There is no enclave to attest to the deletion, so the attestation is the deleted ID
+        Ok(KUAttest {
+            statechain_id,
+            attestation: key_update.shared_key_id.to_string(),
+        })
+    }
+}
+
+#[post("/ecdsa/keygen/first", format = "json", data = "<key_gen_msg1>")]
+pub fn first_message(
+    lockbox: State<Lockbox>,
+    key_gen_msg1: Authenticated<KeyGenMsg1>,
+) -> Result<Json<(Uuid, party_one::KeyGenFirstMsg)>> {
+    Ok(Json(lockbox.first_message(key_gen_msg1.into_inner())?))
+}
+
+#[post("/ecdsa/keygen/second", format = "json", data = "<key_gen_msg2>")]
+pub fn second_message(
+    lockbox: State<Lockbox>,
+    key_gen_msg2: Authenticated<KeyGenMsg2>,
+) -> Result<Json<party1::KeyGenParty1Message2>> {
+    Ok(Json(lockbox.second_message(key_gen_msg2.into_inner())?))
+}
+
+#[post("/ecdsa/sign/first", format = "json", data = "<sign_msg1>")]
+pub fn sign_first(
+    lockbox: State<Lockbox>,
+    sign_msg1: Authenticated<SignMsg1>,
+) -> Result<Json<party_one::EphKeyGenFirstMsg>> {
+    Ok(Json(lockbox.sign_first(sign_msg1.into_inner())?))
+}
+
+#[post("/ecdsa/sign/second", format = "json", data = "<sign_msg2>")]
+pub fn sign_second(lockbox: State<Lockbox>, sign_msg2: Authenticated<SignMsg2>) -> Result<Json<Vec<Vec<u8>>>> {
+    Ok(Json(lockbox.sign_second(sign_msg2.into_inner())?))
+}
+
+#[post("/ecdsa/keyupdate/first", format = "json", data = "<ku_send>")]
+pub fn keyupdate_first(
+    lockbox: State<Lockbox>,
+    ku_send: Authenticated<KUSendMsg>,
+) -> Result<Json<KUReceiveMsg>> {
+    Ok(Json(lockbox.keyupdate_first(ku_send.into_inner())?))
+}
+
+#[post("/ecdsa/keyupdate/second", format = "json", data = "<ku_finalize>")]
+pub fn keyupdate_second(
+    lockbox: State<Lockbox>,
+    ku_finalize: Authenticated<KUFinalize>,
+) -> Result<Json<KUAttest>> {
+    Ok(Json(lockbox.keyupdate_second(ku_finalize.into_inner())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curv::cryptographic_primitives::proofs::sigma_dlog::{DLogProof, ProveDLog};
+
+    fn test_lockbox() -> (Lockbox, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("lockbox-test-{}", Uuid::new_v4()));
+        (Lockbox::new(&dir, None).unwrap(), dir)
+    }
+
+    #[test]
+    fn test_keygen() {
+        let (lockbox, dir) = test_lockbox();
+        let shared_key_id = Uuid::new_v4();
+
+        // Transfer keygen requires a key update
+        assert!(lockbox
+            .first_message(KeyGenMsg1 {
+                shared_key_id,
+                protocol: Protocol::Transfer,
+            })
+            .is_err());
+
+        let (id, _) = lockbox
+            .first_message(KeyGenMsg1 {
+                shared_key_id,
+                protocol: Protocol::Deposit,
+            })
+            .unwrap();
+        assert_eq!(id, shared_key_id);
+
+        let secret_key: FE = ECScalar::new_random();
+        let kg_party_one_second_message = lockbox
+            .second_message(KeyGenMsg2 {
+                shared_key_id,
+                dlog_proof: DLogProof::prove(&secret_key),
+            })
+            .unwrap();
+
+        let key = lockbox
+            .key_store
+            .lock()
+            .unwrap()
+            .get_key_required(&shared_key_id)
+            .unwrap();
+        let master_key = key.master_key.unwrap();
+        assert_eq!(
+            master_key.public.p1,
+            kg_party_one_second_message.ecdh_second_message.comm_witness.public_share
+        );
+        assert!(key.comm_witness.is_none());
+
+        // Key generation can only be run once
+        assert!(lockbox
+            .first_message(KeyGenMsg1 {
+                shared_key_id,
+                protocol: Protocol::Deposit,
+            })
+            .is_err());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_keyupdate_second() {
+        let (lockbox, dir) = test_lockbox();
+        let statechain_id = Uuid::new_v4();
+        let shared_key_id = Uuid::new_v4();
+        let new_shared_key_id = Uuid::new_v4();
+        let finalize = KUFinalize {
+            statechain_id,
+            shared_key_id: new_shared_key_id,
+        };
+
+        // No key update for statechain
+        assert!(lockbox.keyupdate_second(finalize.clone()).is_err());
+
+        let s2: FE = ECScalar::new_random();
+        {
+            let key_store = lockbox.key_store.lock().unwrap();
+            key_store.put_key(&shared_key_id, &LockboxKey::default()).unwrap();
+            key_store
+                .put_key_update(&statechain_id, &KeyUpdate { shared_key_id, s2 })
+                .unwrap();
+        }
+
+        let attest = lockbox.keyupdate_second(finalize).unwrap();
+        assert_eq!(attest.statechain_id, statechain_id);
+        assert_eq!(attest.attestation, shared_key_id.to_string());
+
+        let key_store = lockbox.key_store.lock().unwrap();
+        assert!(key_store.get_key(&shared_key_id).unwrap().is_none());
+        assert!(key_store.get_key_update(&statechain_id).unwrap().is_none());
+        assert_eq!(
+            key_store.get_key_required(&new_shared_key_id).unwrap().s2,
+            Some(s2)
+        );
+        drop(key_store);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,92 @@
+//! # Error
+//!
+//! Custom Error types for lockbox
+
+use rocket::http::{ContentType, Status};
+use rocket::response::Responder;
+use rocket::{Request, Response};
+use std::error;
+use std::fmt;
+use std::io::Cursor;
+
+/// Lockbox specific errors
+#[derive(Debug)]
+pub enum LockboxError {
+    /// Generic error from string error message
+    Generic(String),
+    /// No key stored for ID
+    NoKeyForID(String),
+    /// Key store read or write failed
+    KeyStoreError(String),
+    /// Error in co-signing
+    SigningError(String),
+    /// Key update failed
+    KeyUpdateError(String),
+}
+
+impl From<String> for LockboxError {
+    fn from(e: String) -> LockboxError {
+        LockboxError::Generic(e)
+    }
+}
+impl From<std::io::Error> for LockboxError {
+    fn from(e: std::io::Error) -> LockboxError {
+        LockboxError::KeyStoreError(e.to_string())
+    }
+}
+impl From<serde_json::Error> for LockboxError {
+    fn from(e: serde_json::Error) -> LockboxError {
+        LockboxError::KeyStoreError(e.to_string())
+    }
+}
+impl From<bitcoin::secp256k1::Error> for LockboxError {
+    fn from(e: bitcoin::secp256k1::Error) -> LockboxError {
+        LockboxError::SigningError(e.to_string())
+    }
+}
+impl From<Box<dyn std::error::Error>> for LockboxError {
+    fn from(e: Box<dyn std::error::Error>) -> LockboxError {
+        LockboxError::Generic(e.to_string())
+    }
+}
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, crate::keystore::KeyStore>>>
+    for LockboxError
+{
+    fn from(
+        e: std::sync::PoisonError<std::sync::MutexGuard<'_, crate::keystore::KeyStore>>,
+    ) -> LockboxError {
+        LockboxError::Generic(e.to_string())
+    }
+}
+
+impl fmt::Display for LockboxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockboxError::Generic(ref e) => write!(f, "Error: {}", e),
+            LockboxError::NoKeyForID(ref id) => write!(f, "Key Store Error: No key for ID {}", id),
+            LockboxError::KeyStoreError(ref e) => write!(f, "Key Store Error: {}", e),
+            LockboxError::SigningError(ref e) => write!(f, "Signing Error: {}", e),
+            LockboxError::KeyUpdateError(ref e) => write!(f, "Key Update Error: {}", e),
+        }
+    }
+}
+
+impl error::Error for LockboxError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            _ => None,
+        }
+    }
+}
+
+// Errors are returned with a 500 status so that the server does not try to decode them as a
+// response.
+impl Responder<'static> for LockboxError {
+    fn respond_to(self, _: &Request) -> ::std::result::Result<Response<'static>, Status> {
+        Response::build()
+            .status(Status::InternalServerError)
+            .header(ContentType::Plain)
+            .sized_body(Cursor::new(format!("{}", self)))
+            .ok()
+    }
+}
@@ -0,0 +1,183 @@
+//! Key Store
+//!
+//! On-disk store of the lockbox's shares of shared keys. Each record is a JSON file in the key
+//! store directory named by the ID it is stored under.
+
+use super::Result;
+use crate::error::LockboxError;
+
+use curv::FE;
+use kms::ecdsa::two_party::MasterKey1;
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party_two};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Lockbox share of a shared key and the state of the protocol run using it
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct LockboxKey {
+    /// Predefined private key share set by a transfer key update
+    pub s2: Option<FE>,
+    pub comm_witness: Option<party_one::CommWitness>,
+    pub ec_key_pair: Option<party_one::EcKeyPair>,
+    pub master_key: Option<MasterKey1>,
+    pub eph_ec_key_pair_party1: Option<party_one::EphEcKeyPair>,
+    pub eph_key_gen_first_message_party_two: Option<party_two::EphKeyGenFirstMsg>,
+}
+
+/// Key share computed in the first round of a transfer key update, waiting for the transfer
+/// to be finalized
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyUpdate {
+    /// Shared key ID of the sender
+    pub shared_key_id: Uuid,
+    pub s2: FE,
+}
+
+#[derive(Debug)]
+pub struct KeyStore {
+    dir: PathBuf,
+}
+
+impl KeyStore {
+    /// Open the key store in dir, creating the directory and the key store ID if they do not
+    /// exist
+    pub fn new(dir: &Path) -> Result<KeyStore> {
+        fs::create_dir_all(dir)?;
+        let key_store = KeyStore {
+            dir: dir.to_path_buf(),
+        };
+        // Nodes sharing the directory may start at the same time: only one creates the ID
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(key_store.id_path())
+        {
+            Ok(mut file) => file.write_all(Uuid::new_v4().to_string().as_bytes())?,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+            Err(e) => return Err(e.into()),
+        }
+        Ok(key_store)
+    }
+
+    fn id_path(&self) -> PathBuf {
+        self.dir.join("keystore-id")
+    }
+
+    /// Random ID of the key store, shared by all nodes using the same directory
+    pub fn id(&self) -> Result<String> {
+        Ok(fs::read_to_string(self.id_path())?.trim().to_string())
+    }
+
+    fn path(&self, prefix: &str, id: &Uuid) -> PathBuf {
+        self.dir.join(format!("{}-{}.json", prefix, id))
+    }
+
+    fn get<T: DeserializeOwned>(&self, prefix: &str, id: &Uuid) -> Result<Option<T>> {
+        let path = self.path(prefix, id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    // Write to a temporary file and rename so that a crash does not leave a partial record
+    fn put<T: Serialize>(&self, prefix: &str, id: &Uuid, item: &T) -> Result<()> {
+        let path = self.path(prefix, id);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(item)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn remove(&self, prefix: &str, id: &Uuid) -> Result<()> {
+        let path = self.path(prefix, id);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_key(&self, shared_key_id: &Uuid) -> Result<Option<LockboxKey>> {
+        self.get("key", shared_key_id)
+    }
+
+    /// Get key or return NoKeyForID error
+    pub fn get_key_required(&self, shared_key_id: &Uuid) -> Result<LockboxKey> {
+        self.get_key(shared_key_id)?
+            .ok_or(LockboxError::NoKeyForID(shared_key_id.to_string()))
+    }
+
+    pub fn put_key(&self, shared_key_id: &Uuid, key: &LockboxKey) -> Result<()> {
+        self.put("key", shared_key_id, key)
+    }
+
+    pub fn remove_key(&self, shared_key_id: &Uuid) -> Result<()> {
+        self.remove("key", shared_key_id)
+    }
+
+    pub fn get_key_update(&self, statechain_id: &Uuid) -> Result<Option<KeyUpdate>> {
+        self.get("keyupdate", statechain_id)
+    }
+
+    pub fn put_key_update(&self, statechain_id: &Uuid, key_update: &KeyUpdate) -> Result<()> {
+        self.put("keyupdate", statechain_id, key_update)
+    }
+
+    pub fn remove_key_update(&self, statechain_id: &Uuid) -> Result<()> {
+        self.remove("keyupdate", statechain_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curv::elliptic::curves::traits::ECScalar;
+
+    #[test]
+    fn test_key_store() {
+        let dir = std::env::temp_dir().join(format!("lockbox-test-{}", Uuid::new_v4()));
+        let key_store = KeyStore::new(&dir).unwrap();
+        let shared_key_id = Uuid::new_v4();
+
+        assert!(key_store.get_key(&shared_key_id).unwrap().is_none());
+        match key_store.get_key_required(&shared_key_id) {
+            Err(LockboxError::NoKeyForID(id)) => assert_eq!(id, shared_key_id.to_string()),
+            _ => assert!(false, "expected NoKeyForID error"),
+        }
+
+        let s2: FE = ECScalar::new_random();
+        let key = LockboxKey {
+            s2: Some(s2),
+            ..Default::default()
+        };
+        key_store.put_key(&shared_key_id, &key).unwrap();
+        // Records persist across instances
+        let key_store = KeyStore::new(&dir).unwrap();
+        assert_eq!(key_store.get_key_required(&shared_key_id).unwrap().s2, Some(s2));
+
+        key_store.remove_key(&shared_key_id).unwrap();
+        assert!(key_store.get_key(&shared_key_id).unwrap().is_none());
+        // Removing a missing key is not an error
+        key_store.remove_key(&shared_key_id).unwrap();
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_key_store_id() {
+        let dir = std::env::temp_dir().join(format!("lockbox-test-{}", Uuid::new_v4()));
+        let id = KeyStore::new(&dir).unwrap().id().unwrap();
+        assert!(Uuid::parse_str(&id).is_ok());
+        // Nodes opening the same directory share the ID
+        assert_eq!(KeyStore::new(&dir).unwrap().id().unwrap(), id);
+
+        let other_dir = std::env::temp_dir().join(format!("lockbox-test-{}", Uuid::new_v4()));
+        assert_ne!(KeyStore::new(&other_dir).unwrap().id().unwrap(), id);
+
+        let _ = fs::remove_dir_all(dir);
+        let _ = fs::remove_dir_all(other_dir);
+    }
+}
@@ -0,0 +1,28 @@
+#![recursion_limit = "128"]
+#![feature(proc_macro_hygiene, decl_macro)]
+#[macro_use]
+extern crate rocket;
+extern crate rocket_contrib;
+extern crate uuid;
+#[macro_use]
+extern crate log;
+extern crate bitcoin;
+
+extern crate curv;
+extern crate kms;
+extern crate multi_party_ecdsa;
+
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+
+extern crate shared_lib;
+
+pub mod auth;
+pub mod ecdsa;
+pub mod error;
+pub mod keystore;
+pub mod server;
+
+pub type Result<T> = std::result::Result<T, error::LockboxError>;
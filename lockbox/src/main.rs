@@ -0,0 +1,11 @@
+#![feature(proc_macro_hygiene, decl_macro)]
+
+extern crate lockbox_lib;
+use lockbox_lib::server::{self, Config};
+
+fn main() {
+    env_logger::init();
+    server::get_server(None, Config::from_env().unwrap())
+        .unwrap()
+        .launch();
+}
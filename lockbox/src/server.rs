@@ -0,0 +1,89 @@
+//! Server
+//!
+//! Lockbox stand-in serving the lockbox API over HTTP, for running the state entity with
+//! `lockbox` set without an enclave.
+
+use super::auth::{Authenticator, ResponseSigner};
+use super::ecdsa::{self, Lockbox};
+use super::Result;
+use crate::error::LockboxError;
+
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::util::key::PrivateKey;
+use rocket::config::Config as RocketConfig;
+use rocket::http::Status;
+use rocket::{Rocket, State};
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Lockbox config
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Key store directory
+    pub key_store_dir: PathBuf,
+    /// Key that secret request fields are encrypted to and responses are signed with
+    pub key: PrivateKey,
+    /// State entity key requests must be signed with
+    pub se_pubkey: PublicKey,
+}
+
+impl Config {
+    /// Read config from the env vars LOCKBOX_KEY_STORE, LOCKBOX_KEY (WIF) and
+    /// LOCKBOX_SE_PUBKEY (hex)
+    pub fn from_env() -> Result<Config> {
+        let key = PrivateKey::from_wif(&required_var("LOCKBOX_KEY")?)
+            .map_err(|e| LockboxError::Generic(format!("Invalid LOCKBOX_KEY: {}", e)))?;
+        let se_pubkey = PublicKey::from_str(&required_var("LOCKBOX_SE_PUBKEY")?)
+            .map_err(|e| LockboxError::Generic(format!("Invalid LOCKBOX_SE_PUBKEY: {}", e)))?;
+        Ok(Config {
+            key_store_dir: PathBuf::from(
+                env::var("LOCKBOX_KEY_STORE").unwrap_or(String::from("lockbox_key_store")),
+            ),
+            key,
+            se_pubkey,
+        })
+    }
+}
+
+// The lockbox does not start without its keys, so that requests are always authenticated
+fn required_var(name: &str) -> Result<String> {
+    env::var(name).map_err(|_| LockboxError::Generic(format!("{} not set", name)))
+}
+
+/// Health check used by the state entity to fail over between nodes. Returns the key store
+/// ID, so that the state entity only fails over to nodes sharing the key store.
+#[get("/ping")]
+pub fn ping(lockbox: State<Lockbox>) -> std::result::Result<String, Status> {
+    lockbox
+        .key_store_id()
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Create the lockbox server. Without a Rocket config, Rocket.toml and ROCKET_* env vars are
+/// used.
+pub fn get_server(rocket_config: Option<RocketConfig>, config: Config) -> Result<Rocket> {
+    let lockbox = Lockbox::new(&config.key_store_dir, Some(config.key))?;
+
+    let rocket = match rocket_config {
+        Some(c) => rocket::custom(c),
+        None => rocket::ignite(),
+    };
+
+    Ok(rocket
+        .mount(
+            "/",
+            routes![
+                ping,
+                ecdsa::first_message,
+                ecdsa::second_message,
+                ecdsa::sign_first,
+                ecdsa::sign_second,
+                ecdsa::keyupdate_first,
+                ecdsa::keyupdate_second,
+            ],
+        )
+        .manage(lockbox)
+        .manage(Authenticator::new(Some(config.se_pubkey)))
+        .attach(ResponseSigner::new(config.key.key)))
+}
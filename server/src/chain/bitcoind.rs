@@ -0,0 +1,173 @@
+//! Bitcoind
+//!
+//! Chain backend using the RPC interface of a Bitcoin Core node. Confirmed txs are found with
+//! -txindex, or without it only if they are in the last RECENT_BLOCKS blocks.
+
+use super::{ChainBackend, Result, TxStatus};
+use crate::error::SEError;
+use bitcoin::{consensus, BlockHash, OutPoint, Transaction, Txid};
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+use std::collections::{BTreeMap, HashMap};
+
+/// Number of recent blocks whose txids are indexed to find confirmed txs without -txindex
+const RECENT_BLOCKS: u64 = 144;
+
+pub struct BitcoindChain {
+    rpc: Client,
+    // Hashes and txids of the recent blocks of the best chain by height, and the height of the
+    // block containing each of the txids. Without -txindex bitcoind only returns confirmed txs
+    // given the block containing them.
+    recent_blocks: BTreeMap<u64, (BlockHash, Vec<Txid>)>,
+    recent_txs: HashMap<Txid, u64>,
+}
+
+impl BitcoindChain {
+    /// Connect to bitcoind with an RPC path of the form user:pass@host:port
+    pub fn new(rpc_path: &String) -> Result<BitcoindChain> {
+        let rpc_path_parts: Vec<&str> = rpc_path.split('@').collect();
+        if rpc_path_parts.len() != 2 {
+            return Err(SEError::Generic(String::from("Invalid bitcoind RPC path")));
+        };
+
+        let rpc_cred: Vec<&str> = rpc_path_parts[0].split(':').collect();
+        if rpc_cred.len() != 2 {
+            return Err(SEError::Generic(String::from(
+                "Invalid bitcoind RPC credentials",
+            )));
+        };
+
+        let rpc = Client::new(
+            rpc_path_parts[1].to_string(),
+            Auth::UserPass(rpc_cred[0].to_string(), rpc_cred[1].to_string()),
+        )?;
+        Ok(BitcoindChain {
+            rpc,
+            recent_blocks: BTreeMap::new(),
+            recent_txs: HashMap::new(),
+        })
+    }
+
+    /// Index the txids of the last RECENT_BLOCKS blocks. Blocks are fetched from the tip down
+    /// until a block which is already indexed, so that only new or reorged blocks are fetched.
+    fn update_recent_blocks(&mut self) -> Result<u64> {
+        let height = self.rpc.get_block_count()?;
+        let start = (height + 1).saturating_sub(RECENT_BLOCKS);
+
+        // Remove blocks which are too old or above the tip after a reorg
+        let mut removed = self.recent_blocks.split_off(&(height + 1));
+        let kept = self.recent_blocks.split_off(&start);
+        removed.append(&mut self.recent_blocks);
+        self.recent_blocks = kept;
+        for (block_height, (_, txids)) in removed {
+            self.remove_txids(block_height, &txids);
+        }
+
+        for block_height in (start..=height).rev() {
+            let block_hash = self.rpc.get_block_hash(block_height)?;
+            if let Some((indexed_hash, txids)) = self.recent_blocks.get(&block_height) {
+                if *indexed_hash == block_hash {
+                    break;
+                }
+                let txids = txids.clone();
+                self.remove_txids(block_height, &txids);
+            }
+            let txids = self.rpc.get_block_info(&block_hash)?.tx;
+            for txid in &txids {
+                self.recent_txs.insert(*txid, block_height);
+            }
+            self.recent_blocks.insert(block_height, (block_hash, txids));
+        }
+        Ok(height)
+    }
+
+    // Remove the txids of the block at block_height from the index. A tx which was reorged into
+    // a later block stays indexed at that block.
+    fn remove_txids(&mut self, block_height: u64, txids: &Vec<Txid>) {
+        for txid in txids {
+            if self.recent_txs.get(txid) == Some(&block_height) {
+                self.recent_txs.remove(txid);
+            }
+        }
+    }
+
+    /// Height and hash of the block in the recent blocks containing txid
+    fn find_recent_tx(&mut self, txid: &Txid) -> Result<Option<(u64, u64, BlockHash)>> {
+        let height = self.update_recent_blocks()?;
+        Ok(self.recent_txs.get(txid).map(|block_height| {
+            (height, *block_height, self.recent_blocks[block_height].0)
+        }))
+    }
+}
+
+impl ChainBackend for BitcoindChain {
+    fn get_block_height(&mut self) -> Result<u64> {
+        Ok(self.rpc.get_block_count()?)
+    }
+
+    fn get_block_hash(&mut self, height: u64) -> Result<BlockHash> {
+        Ok(self.rpc.get_block_hash(height)?)
+    }
+
+    fn get_tx_status(&mut self, txid: &Txid) -> Result<TxStatus> {
+        match self.rpc.get_raw_transaction_info(txid, None) {
+            Ok(info) => match info.confirmations {
+                Some(confirmations) if confirmations > 0 => Ok(TxStatus::Confirmed(confirmations)),
+                _ => Ok(TxStatus::Unconfirmed),
+            },
+            // Without -txindex confirmed txs are only found in the recent blocks. Older txs are
+            // Unknown.
+            Err(_) => match self.find_recent_tx(txid)? {
+                Some((height, block_height, _)) => {
+                    Ok(TxStatus::Confirmed((height + 1 - block_height) as u32))
+                }
+                None => Ok(TxStatus::Unknown),
+            },
+        }
+    }
+
+    fn get_tx_block(&mut self, txid: &Txid) -> Result<Option<(u64, BlockHash)>> {
+        match self.rpc.get_raw_transaction_info(txid, None) {
+            Ok(info) => match (info.confirmations, info.blockhash) {
+                (Some(confirmations), Some(block_hash)) if confirmations > 0 => {
+                    let header = self.rpc.get_block_header_info(&block_hash)?;
+                    Ok(Some((header.height as u64, block_hash)))
+                }
+                _ => Ok(None),
+            },
+            Err(_) => Ok(self
+                .find_recent_tx(txid)?
+                .map(|(_, block_height, block_hash)| (block_height, block_hash))),
+        }
+    }
+
+    fn broadcast_transaction(&mut self, tx: &Transaction) -> Result<Txid> {
+        Ok(self.rpc.send_raw_transaction(&consensus::serialize(tx))?)
+    }
+
+    fn get_transaction(&mut self, txid: &Txid) -> Result<Transaction> {
+        match self.rpc.get_raw_transaction(txid, None) {
+            Ok(tx) => Ok(tx),
+            // Without -txindex confirmed txs are fetched from the block containing them
+            Err(e) => match self.find_recent_tx(txid)? {
+                Some((_, _, block_hash)) => {
+                    Ok(self.rpc.get_raw_transaction(txid, Some(&block_hash))?)
+                }
+                None => Err(e.into()),
+            },
+        }
+    }
+
+    /// The UTXO set excluding the mempool: outputs spent by unconfirmed txs are unspent
+    fn is_outpoint_spent(&mut self, outpoint: &OutPoint) -> Result<bool> {
+        Ok(self
+            .rpc
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?
+            .is_none())
+    }
+
+    fn estimate_fee_rate(&mut self, conf_target: u16) -> Result<Option<u64>> {
+        // bitcoind estimates are in BTC/kvbyte
+        let estimate = self.rpc.estimate_smart_fee(conf_target, None)?;
+        Ok(estimate.fee_rate.map(|fee_rate| fee_rate.as_sat() / 1000))
+    }
+}
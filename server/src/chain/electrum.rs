@@ -0,0 +1,174 @@
+//! Electrum
+//!
+//! Chain backend querying an Electrum server
+
+use super::{ChainBackend, Result, TxStatus};
+use crate::error::SEError;
+use bitcoin::{consensus, Address, BlockHash, BlockHeader, Network, OutPoint, Transaction, Txid};
+use electrumx_client::{electrumx_client::ElectrumxClient, interface::Electrumx};
+use std::str::FromStr;
+
+pub struct ElectrumChain {
+    server: String,
+    network: Network,
+}
+
+impl ElectrumChain {
+    pub fn new(server: &String, network: Network) -> ElectrumChain {
+        ElectrumChain {
+            server: server.clone(),
+            network,
+        }
+    }
+
+    // Connect per request so that an unavailable server does not prevent start up
+    fn client(&self) -> Result<Box<dyn Electrumx>> {
+        match ElectrumxClient::new(self.server.clone()) {
+            Ok(client) => Ok(Box::new(client)),
+            Err(e) => Err(SEError::Generic(format!(
+                "Error connecting to Electrum server {}: {}",
+                self.server, e
+            ))),
+        }
+    }
+}
+
+// Electrum servers return the bitcoind error for a tx which is neither in the mempool nor in
+// a block, e.g. "No such mempool or blockchain transaction."
+fn is_not_found_error(error: &str) -> bool {
+    let error = error.to_lowercase();
+    error.contains("no such mempool or blockchain transaction") || error.contains("not found")
+}
+
+impl ChainBackend for ElectrumChain {
+    fn get_block_height(&mut self) -> Result<u64> {
+        Ok(self.client()?.get_tip_header()?.height as u64)
+    }
+
+    fn get_block_hash(&mut self, height: u64) -> Result<BlockHash> {
+        let header_hex = self.client()?.get_block_header(height as usize)?;
+        let header: BlockHeader = consensus::deserialize(
+            &hex::decode(&header_hex).map_err(|e| SEError::Generic(e.to_string()))?,
+        )
+        .map_err(|e| SEError::Generic(e.to_string()))?;
+        Ok(header.block_hash())
+    }
+
+    fn get_tx_status(&mut self, txid: &Txid) -> Result<TxStatus> {
+        let res = match self
+            .client()?
+            .get_transaction_conf_status(txid.to_string(), false)
+        {
+            Ok(res) => res,
+            Err(e) if is_not_found_error(&e.to_string()) => return Ok(TxStatus::Unknown),
+            Err(e) => return Err(e.into()),
+        };
+        match res.confirmations {
+            Some(confirmations) if confirmations > 0 => Ok(TxStatus::Confirmed(confirmations)),
+            _ => Ok(TxStatus::Unconfirmed),
+        }
+    }
+
+    /// Electrum servers only report the confirmations of a tx, so the block is found from the
+    /// tip height. The tip is read again to check that no block was found in between.
+    fn get_tx_block(&mut self, txid: &Txid) -> Result<Option<(u64, BlockHash)>> {
+        loop {
+            let height = self.get_block_height()?;
+            let confirmations = match self.get_tx_status(txid)? {
+                TxStatus::Confirmed(confirmations) => confirmations,
+                _ => return Ok(None),
+            };
+            let block_height = (height + 1).saturating_sub(confirmations as u64);
+            let block_hash = self.get_block_hash(block_height)?;
+            if self.get_block_height()? == height {
+                return Ok(Some((block_height, block_hash)));
+            }
+        }
+    }
+
+    fn broadcast_transaction(&mut self, tx: &Transaction) -> Result<Txid> {
+        let txid = self
+            .client()?
+            .broadcast_transaction(hex::encode(consensus::serialize(tx)))?;
+        Txid::from_str(&txid).map_err(|e| SEError::Generic(e.to_string()))
+    }
+
+    fn get_transaction(&mut self, txid: &Txid) -> Result<Transaction> {
+        let tx_hex = self.client()?.get_transaction(txid.to_string(), false)?;
+        consensus::deserialize(&hex::decode(&tx_hex).map_err(|e| SEError::Generic(e.to_string()))?)
+            .map_err(|e| SEError::Generic(e.to_string()))
+    }
+
+    /// Electrum servers index outputs by address. Unspent outputs are no longer listed once
+    /// they are spent in the mempool, so an outpoint missing from them is only spent if a
+    /// confirmed tx in the history of the address it pays spends it.
+    fn is_outpoint_spent(&mut self, outpoint: &OutPoint) -> Result<bool> {
+        let tx = self.get_transaction(&outpoint.txid)?;
+        let mut client = self.client()?;
+        let output = match tx.output.get(outpoint.vout as usize) {
+            Some(o) => o,
+            None => {
+                return Err(SEError::Generic(format!(
+                    "Outpoint {} not found.",
+                    outpoint
+                )))
+            }
+        };
+        let address = match Address::from_script(&output.script_pubkey, self.network) {
+            Some(a) => a,
+            None => {
+                return Err(SEError::Generic(format!(
+                    "Outpoint {} does not pay an address.",
+                    outpoint
+                )))
+            }
+        };
+        let unspent = client.get_list_unspent(&address.to_string())?;
+        if unspent
+            .iter()
+            .any(|u| u.tx_hash == outpoint.txid.to_string() && u.tx_pos as u32 == outpoint.vout)
+        {
+            return Ok(false);
+        }
+        // Mempool txs have a height of 0, or -1 if they spend unconfirmed outputs
+        for entry in client.get_history(&address.to_string())? {
+            if entry.height <= 0 || entry.tx_hash == outpoint.txid.to_string() {
+                continue;
+            }
+            let txid =
+                Txid::from_str(&entry.tx_hash).map_err(|e| SEError::Generic(e.to_string()))?;
+            if self
+                .get_transaction(&txid)?
+                .input
+                .iter()
+                .any(|input| input.previous_output == *outpoint)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn estimate_fee_rate(&mut self, conf_target: u16) -> Result<Option<u64>> {
+        // BTC/kvbyte, negative if the server has no estimate
+        let fee_rate = self.client()?.estimate_fee(conf_target as usize)?;
+        if fee_rate < 0.0 {
+            return Ok(None);
+        }
+        Ok(Some((fee_rate * 100_000_000.0 / 1000.0) as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_not_found_error() {
+        assert!(is_not_found_error(
+            "No such mempool or blockchain transaction. Use gettransaction for wallet transactions."
+        ));
+        assert!(is_not_found_error("Transaction not found"));
+        assert!(!is_not_found_error("Connection refused"));
+    }
+}
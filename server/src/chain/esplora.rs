@@ -0,0 +1,142 @@
+//! Esplora
+//!
+//! Chain backend using the REST API of an Esplora server
+
+use super::{ChainBackend, Result, TxStatus};
+use crate::error::SEError;
+use bitcoin::{consensus, BlockHash, OutPoint, Transaction, Txid};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Deserialize)]
+struct EsploraTxStatus {
+    confirmed: bool,
+    block_height: Option<u64>,
+    block_hash: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EsploraOutspend {
+    spent: bool,
+    status: Option<EsploraTxStatus>,
+}
+
+pub struct EsploraChain {
+    client: reqwest::blocking::Client,
+    url: String,
+}
+
+impl EsploraChain {
+    pub fn new(url: &String) -> EsploraChain {
+        EsploraChain {
+            client: reqwest::blocking::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn get(&self, path: &str) -> Result<reqwest::blocking::Response> {
+        Ok(self
+            .client
+            .get(&format!("{}/{}", self.url, path))
+            .send()?
+            .error_for_status()?)
+    }
+}
+
+impl ChainBackend for EsploraChain {
+    fn get_block_height(&mut self) -> Result<u64> {
+        let height = self.get("blocks/tip/height")?.text()?;
+        height
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| SEError::Generic(format!("Invalid block height {}: {}", height, e)))
+    }
+
+    fn get_block_hash(&mut self, height: u64) -> Result<BlockHash> {
+        let block_hash = self.get(&format!("block-height/{}", height))?.text()?;
+        BlockHash::from_str(block_hash.trim())
+            .map_err(|e| SEError::Generic(format!("Invalid block hash {}: {}", block_hash, e)))
+    }
+
+    fn get_tx_status(&mut self, txid: &Txid) -> Result<TxStatus> {
+        let res = self
+            .client
+            .get(&format!("{}/tx/{}/status", self.url, txid))
+            .send()?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(TxStatus::Unknown);
+        }
+        let status: EsploraTxStatus = res.error_for_status()?.json()?;
+        match (status.confirmed, status.block_height) {
+            (true, Some(block_height)) => {
+                let height = self.get_block_height()?;
+                Ok(TxStatus::Confirmed((height + 1).saturating_sub(block_height) as u32))
+            }
+            _ => Ok(TxStatus::Unconfirmed),
+        }
+    }
+
+    fn get_tx_block(&mut self, txid: &Txid) -> Result<Option<(u64, BlockHash)>> {
+        let res = self
+            .client
+            .get(&format!("{}/tx/{}/status", self.url, txid))
+            .send()?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let status: EsploraTxStatus = res.error_for_status()?.json()?;
+        match (status.confirmed, status.block_height, status.block_hash) {
+            (true, Some(block_height), Some(block_hash)) => Ok(Some((
+                block_height,
+                BlockHash::from_str(&block_hash).map_err(|e| {
+                    SEError::Generic(format!("Invalid block hash {}: {}", block_hash, e))
+                })?,
+            ))),
+            _ => Ok(None),
+        }
+    }
+
+    fn broadcast_transaction(&mut self, tx: &Transaction) -> Result<Txid> {
+        let txid = self
+            .client
+            .post(&format!("{}/tx", self.url))
+            .body(hex::encode(consensus::serialize(tx)))
+            .send()?
+            .error_for_status()?
+            .text()?;
+        Txid::from_str(txid.trim()).map_err(|e| SEError::Generic(e.to_string()))
+    }
+
+    fn get_transaction(&mut self, txid: &Txid) -> Result<Transaction> {
+        let tx_hex = self.get(&format!("tx/{}/hex", txid))?.text()?;
+        consensus::deserialize(
+            &hex::decode(tx_hex.trim()).map_err(|e| SEError::Generic(e.to_string()))?,
+        )
+        .map_err(|e| SEError::Generic(e.to_string()))
+    }
+
+    fn is_outpoint_spent(&mut self, outpoint: &OutPoint) -> Result<bool> {
+        let outspend: EsploraOutspend = self
+            .get(&format!("tx/{}/outspend/{}", outpoint.txid, outpoint.vout))?
+            .json()?;
+        Ok(outspend.spent && outspend.status.map_or(false, |s| s.confirmed))
+    }
+
+    fn estimate_fee_rate(&mut self, conf_target: u16) -> Result<Option<u64>> {
+        // Map of confirmation target to sat/vbyte estimate
+        let estimates: HashMap<String, f64> = self.get("fee-estimates")?.json()?;
+        let mut targets: Vec<(u16, f64)> = estimates
+            .iter()
+            .filter_map(|(target, fee_rate)| target.parse::<u16>().ok().map(|t| (t, *fee_rate)))
+            .collect();
+        targets.sort_by_key(|(target, _)| *target);
+        // Use the estimate for the largest target not exceeding conf_target
+        Ok(targets
+            .iter()
+            .filter(|(target, _)| *target <= conf_target)
+            .last()
+            .or(targets.first())
+            .map(|(_, fee_rate)| fee_rate.ceil() as u64))
+    }
+}
@@ -0,0 +1,234 @@
+//! Memory
+//!
+//! In-memory chain backend for tests. Chain state is shared between clones, so a test can keep
+//! a clone to script the chain and inspect broadcast transactions.
+
+use super::{ChainBackend, Result, TxStatus};
+use crate::error::SEError;
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+struct MemoryChainState {
+    height: u64,
+    txs: HashMap<Txid, TxStatus>,
+    default_tx_status: TxStatus,
+    spent: HashSet<OutPoint>,
+    // Spending txids of outpoints spent by broadcast txs
+    spending: HashMap<OutPoint, Txid>,
+    broadcast: Vec<Transaction>,
+    fee_rate: Option<u64>,
+    // Hashes of blocks replaced by reorgs. Other blocks have a hash derived from their height.
+    block_hashes: HashMap<u64, BlockHash>,
+    reorgs: u32,
+}
+
+#[derive(Clone)]
+pub struct MemoryChain {
+    state: Arc<Mutex<MemoryChainState>>,
+}
+
+impl MemoryChain {
+    pub fn new() -> MemoryChain {
+        MemoryChain {
+            state: Arc::new(Mutex::new(MemoryChainState {
+                height: 0,
+                txs: HashMap::new(),
+                default_tx_status: TxStatus::Unknown,
+                spent: HashSet::new(),
+                spending: HashMap::new(),
+                broadcast: Vec::new(),
+                fee_rate: None,
+                block_hashes: HashMap::new(),
+                reorgs: 0,
+            })),
+        }
+    }
+
+    pub fn set_height(&self, height: u64) {
+        self.state.lock().unwrap().height = height;
+    }
+
+    pub fn set_tx_status(&self, txid: &Txid, status: TxStatus) {
+        self.state.lock().unwrap().txs.insert(*txid, status);
+    }
+
+    /// Status of txs that have not been broadcast or given a status
+    pub fn set_default_tx_status(&self, status: TxStatus) {
+        self.state.lock().unwrap().default_tx_status = status;
+    }
+
+    /// Mark an outpoint as spent by a confirmed tx
+    pub fn set_spent(&self, outpoint: &OutPoint) {
+        self.state.lock().unwrap().spent.insert(*outpoint);
+    }
+
+    pub fn set_fee_rate(&self, fee_rate: Option<u64>) {
+        self.state.lock().unwrap().fee_rate = fee_rate;
+    }
+
+    /// Transactions broadcast so far, in order
+    pub fn get_broadcast_txs(&self) -> Vec<Transaction> {
+        self.state.lock().unwrap().broadcast.clone()
+    }
+
+    /// Replace the last depth blocks with new blocks of the same height. Txs confirmed in the
+    /// replaced blocks return to the mempool.
+    pub fn reorg(&self, depth: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.reorgs += 1;
+        let reorgs = state.reorgs;
+        let tip = state.height;
+        for height in (tip + 1).saturating_sub(depth as u64)..=tip {
+            let mut data = height.to_le_bytes().to_vec();
+            data.extend_from_slice(&reorgs.to_le_bytes());
+            state.block_hashes.insert(height, BlockHash::hash(&data));
+        }
+        for status in state.txs.values_mut() {
+            if let TxStatus::Confirmed(confirmations) = *status {
+                if confirmations <= depth {
+                    *status = TxStatus::Unconfirmed;
+                }
+            }
+        }
+    }
+
+    /// Mine a block confirming all unconfirmed txs
+    pub fn mine_block(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.height += 1;
+        for status in state.txs.values_mut() {
+            *status = match *status {
+                TxStatus::Unconfirmed => TxStatus::Confirmed(1),
+                TxStatus::Confirmed(confirmations) => TxStatus::Confirmed(confirmations + 1),
+                TxStatus::Unknown => TxStatus::Unknown,
+            }
+        }
+    }
+}
+
+impl ChainBackend for MemoryChain {
+    fn get_block_height(&mut self) -> Result<u64> {
+        Ok(self.state.lock().unwrap().height)
+    }
+
+    fn get_block_hash(&mut self, height: u64) -> Result<BlockHash> {
+        let state = self.state.lock().unwrap();
+        Ok(match state.block_hashes.get(&height) {
+            Some(block_hash) => *block_hash,
+            None => BlockHash::hash(&height.to_le_bytes()),
+        })
+    }
+
+    fn get_tx_status(&mut self, txid: &Txid) -> Result<TxStatus> {
+        let state = self.state.lock().unwrap();
+        Ok(*state.txs.get(txid).unwrap_or(&state.default_tx_status))
+    }
+
+    /// Txs are confirmed in the block at the height given by their confirmations
+    fn get_tx_block(&mut self, txid: &Txid) -> Result<Option<(u64, BlockHash)>> {
+        let confirmations = match self.get_tx_status(txid)? {
+            TxStatus::Confirmed(confirmations) => confirmations,
+            _ => return Ok(None),
+        };
+        let height = (self.get_block_height()? + 1).saturating_sub(confirmations as u64);
+        Ok(Some((height, self.get_block_hash(height)?)))
+    }
+
+    fn broadcast_transaction(&mut self, tx: &Transaction) -> Result<Txid> {
+        let mut state = self.state.lock().unwrap();
+        let txid = tx.txid();
+        state.txs.entry(txid).or_insert(TxStatus::Unconfirmed);
+        for input in &tx.input {
+            state.spending.insert(input.previous_output, txid);
+        }
+        state.broadcast.push(tx.clone());
+        Ok(txid)
+    }
+
+    fn get_transaction(&mut self, txid: &Txid) -> Result<Transaction> {
+        let state = self.state.lock().unwrap();
+        match state.broadcast.iter().find(|tx| tx.txid() == *txid) {
+            Some(tx) => Ok(tx.clone()),
+            None => Err(SEError::Generic(format!("Transaction {} not found.", txid))),
+        }
+    }
+
+    /// Outpoints spent by a broadcast tx are spent once it is confirmed
+    fn is_outpoint_spent(&mut self, outpoint: &OutPoint) -> Result<bool> {
+        let spending_txid = {
+            let state = self.state.lock().unwrap();
+            if state.spent.contains(outpoint) {
+                return Ok(true);
+            }
+            match state.spending.get(outpoint) {
+                Some(txid) => *txid,
+                None => return Ok(false),
+            }
+        };
+        match self.get_tx_status(&spending_txid)? {
+            TxStatus::Confirmed(_) => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    fn estimate_fee_rate(&mut self, _conf_target: u16) -> Result<Option<u64>> {
+        Ok(self.state.lock().unwrap().fee_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::encode;
+
+    #[test]
+    fn test_memory_chain() {
+        let tx_raw = hex::decode("020000000001010a742dc732ef1ea6a71c042b7fa212457b52438ba5c3b8552b8a4fd74e86a0f601000000171600147a91e5a412a6a826897067654fffb1557741285efeffffff0240860f240100000017a9140dbb4870526bb96a42ebe19dc86d84a34addc5d48700e1f5050000000017a9141040c0c1b81e2e00aec47ef01c2d3a6116ca513d8702483045022100e7d13322ee719ae8fb7775cafec98137d8d3c42e340cda7750679a06308744f602206154f097a7bc625c688db343633cdafa9e48455d90630d21edf8e036faa0ddbf0121034ea2ae3c24aea00b262c557675d82b66d9aa0f2bc14dfa7d82d1983efb0456c984000000").unwrap();
+        let tx: Transaction = encode::deserialize(&tx_raw).unwrap();
+
+        let chain = MemoryChain::new();
+        // Clones share chain state
+        let mut backend = chain.clone();
+        chain.set_height(100);
+        assert_eq!(backend.get_block_height().unwrap(), 100);
+
+        assert_eq!(backend.get_tx_status(&tx.txid()).unwrap(), TxStatus::Unknown);
+        assert!(!backend.is_outpoint_spent(&tx.input[0].previous_output).unwrap());
+
+        assert!(backend.get_transaction(&tx.txid()).is_err());
+        assert_eq!(backend.broadcast_transaction(&tx).unwrap(), tx.txid());
+        assert_eq!(chain.get_broadcast_txs(), vec![tx.clone()]);
+        assert_eq!(backend.get_transaction(&tx.txid()).unwrap(), tx);
+        assert_eq!(backend.get_tx_status(&tx.txid()).unwrap(), TxStatus::Unconfirmed);
+        // Spent in the mempool only
+        assert!(!backend.is_outpoint_spent(&tx.input[0].previous_output).unwrap());
+
+        chain.mine_block();
+        assert!(backend.is_outpoint_spent(&tx.input[0].previous_output).unwrap());
+        chain.mine_block();
+        assert_eq!(backend.get_block_height().unwrap(), 102);
+        assert_eq!(backend.get_tx_status(&tx.txid()).unwrap(), TxStatus::Confirmed(2));
+
+        chain.set_default_tx_status(TxStatus::Confirmed(3));
+        assert_eq!(backend.get_tx_status(&Txid::default()).unwrap(), TxStatus::Confirmed(3));
+
+        // Reorg of the last block returns txs confirmed in it to the mempool
+        let block_hash = backend.get_block_hash(102).unwrap();
+        let tx_block = Some((101, backend.get_block_hash(101).unwrap()));
+        assert_eq!(backend.get_tx_block(&tx.txid()).unwrap(), tx_block);
+        chain.reorg(1);
+        assert_eq!(backend.get_block_height().unwrap(), 102);
+        assert_ne!(backend.get_block_hash(102).unwrap(), block_hash);
+        assert_eq!(backend.get_tx_status(&tx.txid()).unwrap(), TxStatus::Confirmed(2));
+        chain.reorg(2);
+        assert_eq!(backend.get_tx_status(&tx.txid()).unwrap(), TxStatus::Unconfirmed);
+        assert_eq!(backend.get_tx_block(&tx.txid()).unwrap(), None);
+        assert!(!backend.is_outpoint_spent(&tx.input[0].previous_output).unwrap());
+
+        assert_eq!(backend.estimate_fee_rate(6).unwrap(), None);
+        chain.set_fee_rate(Some(20));
+        assert_eq!(backend.estimate_fee_rate(6).unwrap(), Some(20));
+    }
+}
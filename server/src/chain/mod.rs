@@ -0,0 +1,83 @@
+//! # Chain
+//!
+//! Chain backends used by the watcher and transaction confirmation checks. The backend is
+//! selected by the chain_backend config option.
+
+pub mod bitcoind;
+pub mod electrum;
+pub mod esplora;
+pub mod memory;
+
+pub use super::Result;
+use crate::config::Config;
+use crate::error::SEError;
+use bitcoin::{BlockHash, Network, OutPoint, Transaction, Txid};
+
+use self::bitcoind::BitcoindChain;
+use self::electrum::ElectrumChain;
+use self::esplora::EsploraChain;
+use self::memory::MemoryChain;
+
+/// Status of a transaction on a chain backend
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxStatus {
+    /// Not in the mempool or chain
+    Unknown,
+    /// In the mempool
+    Unconfirmed,
+    /// Confirmed with some number of confirmations
+    Confirmed(u32),
+}
+
+/// Interface to the Bitcoin network
+pub trait ChainBackend {
+    /// Current block height
+    fn get_block_height(&mut self) -> Result<u64>;
+
+    /// Hash of the block at height in the current best chain
+    fn get_block_hash(&mut self, height: u64) -> Result<BlockHash>;
+
+    /// Mempool or confirmation status of a transaction
+    fn get_tx_status(&mut self, txid: &Txid) -> Result<TxStatus>;
+
+    /// Broadcast a transaction. Returns its txid.
+    fn broadcast_transaction(&mut self, tx: &Transaction) -> Result<Txid>;
+
+    /// Get a transaction in the mempool or chain
+    fn get_transaction(&mut self, txid: &Txid) -> Result<Transaction>;
+
+    /// Whether an outpoint has been spent by a confirmed transaction
+    fn is_outpoint_spent(&mut self, outpoint: &OutPoint) -> Result<bool>;
+
+    /// Fee rate (sat/vbyte) estimated to confirm a transaction within conf_target blocks.
+    /// None if the backend has no estimate.
+    fn estimate_fee_rate(&mut self, conf_target: u16) -> Result<Option<u64>>;
+
+    /// Height and hash of the block containing a tx, as reported by the backend. None if it is
+    /// not confirmed.
+    fn get_tx_block(&mut self, txid: &Txid) -> Result<Option<(u64, BlockHash)>>;
+}
+
+pub type Chain = Box<dyn ChainBackend + Send>;
+
+/// Get the chain backend selected in config
+pub fn get_chain_backend(config: &Config) -> Result<Chain> {
+    if cfg!(any(test, feature = "mockbitcoinrpc")) {
+        return Ok(Box::new(MemoryChain::new()));
+    }
+
+    let network = config
+        .network
+        .parse::<Network>()
+        .map_err(|_| SEError::Generic(format!("Invalid network: {}", config.network)))?;
+    match config.chain_backend.as_str() {
+        "electrum" => Ok(Box::new(ElectrumChain::new(&config.electrum_server, network))),
+        "bitcoind" => Ok(Box::new(BitcoindChain::new(&config.bitcoind)?)),
+        "esplora" => Ok(Box::new(EsploraChain::new(&config.esplora_url))),
+        "memory" => Ok(Box::new(MemoryChain::new())),
+        backend => Err(SEError::Generic(format!(
+            "Unknown chain backend: {}",
+            backend
+        ))),
+    }
+}
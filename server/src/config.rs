@@ -13,6 +13,10 @@ use std::str::FromStr;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 /// Storage specific config
 pub struct StorageConfig {
+    /// Storage backend: "postgres" or "sqlite"
+    pub db_backend: String,
+    /// SQLite database file
+    pub db_path: String,
     /// Storage write host
     pub db_host_w: String,
     /// Storage write port
@@ -38,6 +42,8 @@ pub struct StorageConfig {
 impl Default for StorageConfig {
     fn default() -> StorageConfig {
         StorageConfig {
+            db_backend: String::from("postgres"),
+            db_path: String::from("mercury.sqlite"),
             db_host_w: String::from(""),
             db_port_w: String::from(""),
             db_user_w: String::from(""),
@@ -74,14 +80,29 @@ impl Default for RocketConfig {
 }
 
 /// Config struct storing all StataChain Entity config
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     /// Log file location. If not present print to stdout
     pub log_file: String,
     /// Electrum Server Address
     pub electrum_server: String,
-    /// Lockbox server address
+    /// Chain backend for the watcher and confirmation checks (electrum, bitcoind, esplora or memory)
+    pub chain_backend: String,
+    /// Esplora REST API URL
+    pub esplora_url: String,
+    /// Lockbox node URLs: a comma separated list of shards, each a `|` separated list of nodes
+    /// sharing a key store. Nodes after the first are only used once they report the first
+    /// node's key store ID. Empty to keep keys in the database.
     pub lockbox: String,
+    /// Lockbox request timeout (seconds)
+    pub lockbox_timeout: u64,
+    /// Number of times a failed lockbox request is retried
+    pub lockbox_retries: u32,
+    /// WIF private key signing requests to the lockbox. Empty to send unsigned requests.
+    pub lockbox_key: String,
+    /// Lockbox public key (hex) that secret request fields are encrypted to. Empty to send
+    /// them in plain text.
+    pub lockbox_pubkey: String,
     /// Bitcoin network name (testnet, regtest, mainnet)
     pub network: String,
     /// Testing mode
@@ -90,8 +111,14 @@ pub struct Config {
     pub lockheight_init: u32,
     /// Transfer nlocktime decrement
     pub lh_decrement: u32,
+    /// Backup txs spend a pre-signed kick-off tx with a relative (BIP68 nSequence) locktime.
+    /// lockheight_init and lh_decrement are then numbers of blocks after the kick-off tx confirms.
+    pub relative_locktime: bool,
     /// Required confirmations for deposit
     pub required_confirmation: u32,
+    /// Confirmations after which funding and backup txs are final. Until then the blocks
+    /// containing them are re-verified to detect reorgs.
+    pub reorg_depth: u32,
     /// Receive address for fee payments
     pub fee_address: String,
     /// Despoit fee (basis points)
@@ -100,12 +127,61 @@ pub struct Config {
     pub fee_withdraw: u64,
     /// Time to allow batch transfer to take
     pub batch_lifetime: u64,
+    /// Time after which a transfer not completed by the receiver is cancelled (seconds). 0 to disable.
+    pub transfer_expiry: u64,
+    /// Interval between garbage collection passes (seconds). 0 to disable garbage collection.
+    pub gc_interval: u64,
+    /// Age after which user sessions without a statechain are removed with their ECDSA key
+    /// material (seconds). Must allow time for slow deposit confirmation. 0 to disable.
+    pub session_expiry: u64,
+    /// Age after which user sessions without a statechain but with a signed backup tx are removed
+    /// (seconds). Their funding tx may still be broadcast, so this should be far longer than
+    /// session_expiry. Sessions whose funding tx is known to the chain backend are never removed.
+    /// 0 to keep them.
+    pub signed_session_expiry: u64,
+    /// Age after which batch transfers are moved to the archive table (seconds). Should be well
+    /// over batch_lifetime. 0 to disable.
+    pub batch_archive_age: u64,
+    /// Port of the WebSocket stream pushing swap and transfer events to subscribed clients.
+    /// 0 to disable.
+    pub events_port: u16,
+    /// Maximum number of concurrent event stream connections. Further connections are closed.
+    pub events_max_connections: u32,
+    /// Maximum number of deposits initiated per minute. 0 for no limit. Requests for proof of
+    /// work challenges and deposit tokens are limited separately to the same rate.
+    pub deposit_rate_limit: u32,
+    /// Maximum number of deposits initiated per minute from one IP address. 0 for no limit.
+    /// Requests for proof of work challenges and deposit tokens are limited separately to the
+    /// same rate.
+    pub deposit_rate_limit_ip: u32,
+    /// Leading zero bits of the proof of work required to initiate a deposit. 0 to disable.
+    pub deposit_pow_difficulty: u32,
+    /// Payment (satoshis) to fee_address required for a deposit token. Deposits can only be
+    /// initiated with a token if set. 0 to disable.
+    pub deposit_token_fee: u64,
+    /// Hex private key blind signing deposit tokens. Random if empty, in which case tokens are
+    /// invalidated by a restart.
+    pub deposit_token_key: String,
+    /// Hex public key signing requests to the admin API. The admin API is disabled if empty.
+    pub admin_pubkey: String,
     /// Length of punishment for unresponsivve/misbehaving batch-transfer utxo
     pub punishment_duration: u64,
     /// Watch-only
     pub watch_only: bool,
     /// bitcoind node connecton
     pub bitcoind: String,
+    /// P2WPKH address paid by the State Entity fee output of backup txs, which the watcher spends
+    /// to fee bump them (CPFP). Its key is hot, so it must not be fee_address. Empty to pay backup
+    /// tx fees to fee_address, which disables fee bumping.
+    pub cpfp_address: String,
+    /// WIF private key of cpfp_address. Set with the MERC_CPFP_KEY environment variable rather
+    /// than in Settings.toml. Empty to use cpfp_key_file.
+    pub cpfp_key: String,
+    /// File containing the WIF private key of cpfp_address, used if cpfp_key is empty. Fee
+    /// bumping is disabled if both are empty.
+    pub cpfp_key_file: String,
+    /// Confirmation target (blocks) of the fee rate estimate used for CPFP fee bumping
+    pub cpfp_target: u16,
     /// Storage config
     pub storage: StorageConfig,
     /// Mainstay config
@@ -119,19 +195,44 @@ impl Default for Config {
         Config {
             log_file: String::from(""),
             electrum_server: String::from("127.0.0.1:60401"),
+            chain_backend: String::from("electrum"),
+            esplora_url: String::from(""),
             lockbox: String::from(""),
+            lockbox_timeout: 10,
+            lockbox_retries: 2,
+            lockbox_key: String::from(""),
+            lockbox_pubkey: String::from(""),
             network: String::from("regtest"),
             testing_mode: true,
             lockheight_init: 10000,
             lh_decrement: 100,
+            relative_locktime: false,
             required_confirmation: 3,
+            reorg_depth: 6,
             fee_address: String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x"),
             fee_deposit: 40,
             fee_withdraw: 40,
             batch_lifetime: 3600,     // 1 hour
+            transfer_expiry: 86400,   // 1 day
+            gc_interval: 600,         // 10 minutes
+            session_expiry: 604800,   // 1 week
+            signed_session_expiry: 0,
+            batch_archive_age: 604800, // 1 week
+            events_port: 0,
+            events_max_connections: 1000,
+            deposit_rate_limit: 0,
+            deposit_rate_limit_ip: 0,
+            deposit_pow_difficulty: 0,
+            deposit_token_fee: 0,
+            deposit_token_key: String::from(""),
+            admin_pubkey: String::from(""),
             punishment_duration: 360, // 1 minute
             watch_only: false,
             bitcoind: String::from(""),
+            cpfp_address: String::from(""),
+            cpfp_key: String::from(""),
+            cpfp_key_file: String::from(""),
+            cpfp_target: 6,
             storage: StorageConfig::default(),
             mainstay: Some(MainstayConfig::default()),
             rocket: RocketConfig::default(),
@@ -165,6 +266,13 @@ impl Config {
         // CO_CLIENTCHAIN__HOST=127.0.0.1:5555
         // CO_CLIENTCHAIN__GENESIS_HASH=706f6...
 
+        if let Ok(v) = env::var("MERC_DB_BACKEND") {
+            let _ = conf_rs.set("storage.db_backend", v)?;
+        }
+        if let Ok(v) = env::var("MERC_DB_PATH") {
+            let _ = conf_rs.set("storage.db_path", v)?;
+        }
+
         if let Ok(v) = env::var("MERC_DB_HOST_W") {
             let _ = conf_rs.set("storage.db_host_w", v)?;
         }
@@ -223,4 +331,13 @@ impl Config {
 
         Ok(conf_rs.try_into()?)
     }
+
+    /// Address paid by the State Entity fee output of backup txs
+    pub fn backup_fee_address(&self) -> &String {
+        if self.cpfp_address.is_empty() {
+            &self.fee_address
+        } else {
+            &self.cpfp_address
+        }
+    }
 }
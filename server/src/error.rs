@@ -13,6 +13,7 @@ use rocket::http::{ContentType, Status};
 use rocket::response::Responder;
 use rocket::{Request, Response};
 use reqwest::Error as ReqwestError;
+use rusqlite::Error as SqliteError;
 use std::error;
 use std::fmt;
 use std::io::Cursor;
@@ -79,6 +80,11 @@ impl From<PostgresError> for SEError {
         SEError::Generic(e.to_string())
     }
 }
+impl From<SqliteError> for SEError {
+    fn from(e: SqliteError) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
 impl From<ConfigError> for SEError {
     fn from(e: ConfigError) -> SEError {
         SEError::Generic(e.to_string())
@@ -91,6 +97,18 @@ impl From<ReqwestError> for SEError {
     }
 }
 
+impl From<bitcoincore_rpc::Error> for SEError {
+    fn from(e: bitcoincore_rpc::Error) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, crate::chain::Chain>>> for SEError {
+    fn from(e: std::sync::PoisonError<std::sync::MutexGuard<'_, crate::chain::Chain>>) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
 impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, crate::protocol::conductor::Scheduler>>>
     for SEError
 {
@@ -101,6 +119,26 @@ impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, crate::protocol::cond
     }
 }
 
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, shared_lib::request_auth::ReplayGuard>>>
+    for SEError
+{
+    fn from(
+        e: std::sync::PoisonError<std::sync::MutexGuard<'_, shared_lib::request_auth::ReplayGuard>>,
+    ) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, crate::protocol::deposit::DepositGate>>>
+    for SEError
+{
+    fn from(
+        e: std::sync::PoisonError<std::sync::MutexGuard<'_, crate::protocol::deposit::DepositGate>>,
+    ) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
 impl From<Box<dyn std::error::Error>>
     for SEError
 {
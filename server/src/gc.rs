@@ -0,0 +1,360 @@
+//! Garbage collection
+//!
+//! Removal of data left behind by abandoned protocols. User sessions of deposits that were never
+//! confirmed are removed with their ECDSA key material session_expiry seconds after they were
+//! created. Sessions with a signed backup tx may still be funded, for example from a cold wallet,
+//! so they are only removed after signed_session_expiry, and never while their funding tx is
+//! known to the chain backend. Batch
+//! transfers are moved to an archive table batch_archive_age seconds after they started, and
+//! backup txs of statechains that no longer exist or have been withdrawn are removed. Incomplete
+//! transfers are expired separately after transfer_expiry.
+
+pub use super::Result;
+use crate::chain::TxStatus;
+use crate::protocol::transfer::abort_transfer;
+use crate::server::StateChainEntity;
+use crate::Database;
+use bitcoin::Transaction;
+use chrono::Duration;
+use monotree::database::Database as MonotreeDatabase;
+use shared_lib::state_chain::get_time_now;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Items removed by a garbage collection pass
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GcCounts {
+    /// Expired user sessions
+    pub sessions: u64,
+    /// Expired user sessions that had ECDSA key material
+    pub ecdsa: u64,
+    /// Archived batch transfers
+    pub batches: u64,
+    /// Removed backup txs
+    pub backup_txs: u64,
+}
+
+impl<T: Database + Send + Sync + 'static, D: MonotreeDatabase + Send + Sync + 'static>
+    StateChainEntity<T, D>
+{
+    /// Run a garbage collection pass
+    pub fn collect_garbage(&self) -> Result<GcCounts> {
+        let mut counts = GcCounts::default();
+
+        if self.config.session_expiry > 0 {
+            let now = get_time_now();
+            let created_before = now - Duration::seconds(self.config.session_expiry as i64);
+            let signed_expired: HashSet<Uuid> = match self.config.signed_session_expiry {
+                0 => HashSet::new(),
+                expiry => self
+                    .database
+                    .get_unfunded_user_sessions(&(now - Duration::seconds(expiry as i64)))?
+                    .into_iter()
+                    .collect(),
+            };
+            for user_id in self.database.get_unfunded_user_sessions(&created_before)? {
+                // The funding tx can still be broadcast, and its coins are locked without the key
+                if let Some(tx_backup) = self.get_signed_backup_tx(&user_id) {
+                    if !signed_expired.contains(&user_id) {
+                        debug!("GC: Unfunded user session kept: backup tx signed. ID: {}", user_id);
+                        continue;
+                    }
+                    if self.is_funding_tx_known(&tx_backup, &user_id)? {
+                        warn!(
+                            "GC: Unfunded user session kept: funding tx broadcast. ID: {}",
+                            user_id
+                        );
+                        continue;
+                    }
+                }
+                if self.database.remove_ecdsa(&user_id)? {
+                    counts.ecdsa += 1;
+                }
+                self.database.remove_user_session(&user_id)?;
+                counts.sessions += 1;
+                info!("GC: Unfunded user session expired. ID: {}", user_id);
+            }
+        }
+
+        if self.config.batch_archive_age > 0 {
+            let started_before =
+                get_time_now() - Duration::seconds(self.config.batch_archive_age as i64);
+            for batch_id in self
+                .database
+                .get_transfer_batches_started_before(&started_before)?
+            {
+                self.archive_transfer_batch(batch_id)?;
+                counts.batches += 1;
+            }
+        }
+
+        counts.backup_txs = self.database.remove_orphaned_backup_txs()?;
+        if counts.backup_txs > 0 {
+            info!("GC: Removed {} orphaned backup txs", counts.backup_txs);
+        }
+        Ok(counts)
+    }
+
+    // The signed backup tx of a session that was not ended by a withdrawal
+    fn get_signed_backup_tx(&self, user_id: &Uuid) -> Option<Transaction> {
+        if self.database.has_withdraw_sc_sig(*user_id).is_ok() {
+            return None;
+        }
+        match self.database.get_backup_transaction_and_proof_key(*user_id) {
+            Ok((tx, _)) if tx.input.len() > 0 && tx.input[0].witness.len() > 0 => Some(tx),
+            _ => None,
+        }
+    }
+
+    // Whether the funding tx spent by a session's backup tx is known to the chain backend. In
+    // relative locktime mode the backup tx spends the kick-off tx, which spends the funding tx.
+    // The funding tx is treated as known if the chain backend cannot be queried.
+    fn is_funding_tx_known(&self, tx_backup: &Transaction, user_id: &Uuid) -> Result<bool> {
+        let mut funding_txid = tx_backup.input[0].previous_output.txid;
+        if let Ok(tx_kickoff) = self.database.get_user_kickoff_tx(*user_id) {
+            if tx_kickoff.txid() == funding_txid && tx_kickoff.input.len() > 0 {
+                funding_txid = tx_kickoff.input[0].previous_output.txid;
+            }
+        }
+        let mut chain = self.chain.lock()?;
+        match chain.get_tx_status(&funding_txid) {
+            Ok(TxStatus::Unknown) => Ok(false),
+            Ok(_) => Ok(true),
+            Err(e) => {
+                warn!("GC: Failed to get status of funding tx {}: {}", funding_txid, e);
+                Ok(true)
+            }
+        }
+    }
+
+    // Archive a batch transfer. Transfers left over from a batch that was never finalized are
+    // removed, and its swap is removed from the scheduler if it is still there.
+    fn archive_transfer_batch(&self, batch_id: Uuid) -> Result<()> {
+        let tbd = self.database.get_transfer_batch_data(batch_id)?;
+        if !tbd.finalized {
+            for statechain_id in &tbd.state_chains {
+                // The statechain may have started another transfer since the batch
+                if let Ok(td) = self.database.get_transfer_data(*statechain_id) {
+                    if td.statechain_sig.is_transfer_batch(Some(&batch_id)) {
+                        abort_transfer(
+                            &*self.database,
+                            statechain_id,
+                            self.config.lh_decrement,
+                            self.config.relative_locktime,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        let mut guard = self.scheduler.lock()?;
+        if guard.remove_swap_info(&batch_id).is_some() {
+            guard.persist(&*self.database)?;
+        }
+        drop(guard);
+
+        self.database.archive_transfer_batch(&batch_id)?;
+        info!(
+            "GC: Transfer batch archived. ID: {}. Finalized: {}.",
+            batch_id, tbd.finalized
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::memory::MemoryChain;
+    use crate::error::SEError;
+    use crate::protocol::util::tests::{test_sc_entity, BACKUP_TX_NOT_SIGNED, BACKUP_TX_SIGNED};
+    use crate::structs::{TransferBatchData, TransferData};
+    use crate::MockDatabase;
+    use bitcoin::Transaction;
+    use chrono::NaiveDateTime;
+    use curv::elliptic::curves::traits::ECScalar;
+    use curv::FE;
+    use mockall::predicate;
+    use shared_lib::state_chain::StateChainSig;
+    use std::collections::HashSet;
+
+    fn transfer_data(statechain_id: Uuid, purpose: String) -> TransferData {
+        TransferData {
+            statechain_id,
+            statechain_sig: StateChainSig {
+                purpose,
+                data: String::default(),
+                sig: String::default(),
+            },
+            x1: FE::new_random(),
+        }
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let session_expiry = 3600;
+        let signed_session_expiry = 30 * 86400;
+        let keygen_user_id = Uuid::new_v4();
+        let init_user_id = Uuid::new_v4();
+        let signed_user_id = Uuid::new_v4();
+        let funded_user_id = Uuid::new_v4();
+        let signed_expired_user_id = Uuid::new_v4();
+        let withdrawn_user_id = Uuid::new_v4();
+        let batch_id = Uuid::new_v4();
+        let batch_statechain_id = Uuid::new_v4();
+        let transferred_statechain_id = Uuid::new_v4();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_unfunded_user_sessions()
+            .withf(move |created_before: &NaiveDateTime| {
+                *created_before <= get_time_now() - Duration::seconds(session_expiry)
+                    && *created_before > get_time_now() - Duration::seconds(signed_session_expiry)
+            })
+            .times(1)
+            .returning(move |_| {
+                Ok(vec![
+                    keygen_user_id,
+                    init_user_id,
+                    signed_user_id,
+                    funded_user_id,
+                    signed_expired_user_id,
+                    withdrawn_user_id,
+                ])
+            });
+        db.expect_get_unfunded_user_sessions()
+            .withf(move |created_before: &NaiveDateTime| {
+                *created_before <= get_time_now() - Duration::seconds(signed_session_expiry)
+            })
+            .times(1)
+            .returning(move |_| Ok(vec![funded_user_id, signed_expired_user_id]));
+        // Three sessions have a signed backup tx. One was signed recently, for example for a
+        // deposit funded from a cold wallet, and is kept although its funding tx is unknown. Of
+        // the two older ones, one has its funding tx broadcast.
+        let tx_funded = serde_json::from_str::<Transaction>(BACKUP_TX_SIGNED).unwrap();
+        let mut tx_signed = tx_funded.clone();
+        tx_signed.input[0].previous_output.txid = tx_funded.txid();
+        let chain = MemoryChain::new();
+        chain.set_tx_status(&tx_funded.input[0].previous_output.txid, TxStatus::Unconfirmed);
+        let tx_signed_expired = tx_signed.clone();
+        db.expect_get_backup_transaction_and_proof_key()
+            .with(predicate::eq(signed_user_id))
+            .returning(move |_| Ok((tx_signed.clone(), String::default())));
+        db.expect_get_backup_transaction_and_proof_key()
+            .with(predicate::eq(funded_user_id))
+            .returning(move |_| Ok((tx_funded.clone(), String::default())));
+        db.expect_get_backup_transaction_and_proof_key()
+            .with(predicate::eq(signed_expired_user_id))
+            .returning(move |_| Ok((tx_signed_expired.clone(), String::default())));
+        db.expect_get_backup_transaction_and_proof_key()
+            .returning(|_| Err(SEError::Generic(String::from("No backup tx"))));
+        // The session of a withdrawn statechain has no statechain ID
+        db.expect_has_withdraw_sc_sig()
+            .with(predicate::eq(withdrawn_user_id))
+            .returning(|_| Ok(()));
+        db.expect_has_withdraw_sc_sig()
+            .returning(|_| Err(SEError::Generic(String::from("No withdraw sig"))));
+        db.expect_get_user_kickoff_tx()
+            .returning(|_| Err(SEError::Generic(String::from("No kick-off tx"))));
+        // Only one session reached key generation
+        db.expect_remove_ecdsa()
+            .with(predicate::eq(keygen_user_id))
+            .returning(|_| Ok(true));
+        db.expect_remove_ecdsa()
+            .with(predicate::eq(init_user_id))
+            .returning(|_| Ok(false));
+        db.expect_remove_ecdsa()
+            .with(predicate::eq(signed_expired_user_id))
+            .returning(|_| Ok(true));
+        db.expect_remove_ecdsa()
+            .with(predicate::eq(withdrawn_user_id))
+            .returning(|_| Ok(true));
+        db.expect_remove_user_session()
+            .with(predicate::in_iter(vec![
+                keygen_user_id,
+                init_user_id,
+                signed_expired_user_id,
+                withdrawn_user_id,
+            ]))
+            .times(4)
+            .returning(|_| Ok(()));
+
+        db.expect_get_transfer_batches_started_before()
+            .times(1)
+            .returning(move |_| Ok(vec![batch_id]));
+        db.expect_get_transfer_batch_data()
+            .with(predicate::eq(batch_id))
+            .returning(move |_| {
+                let mut state_chains = HashSet::new();
+                state_chains.insert(batch_statechain_id);
+                state_chains.insert(transferred_statechain_id);
+                Ok(TransferBatchData {
+                    state_chains,
+                    punished_state_chains: vec![],
+                    start_time: get_time_now(),
+                    finalized: false,
+                })
+            });
+        // The batch transfer is removed, but not a later transfer of another statechain
+        db.expect_get_transfer_data()
+            .with(predicate::eq(batch_statechain_id))
+            .returning(move |id| {
+                Ok(transfer_data(id, format!("TRANSFER_BATCH:{}", batch_id)))
+            });
+        db.expect_get_transfer_data()
+            .with(predicate::eq(transferred_statechain_id))
+            .returning(|id| Ok(transfer_data(id, String::from("TRANSFER"))));
+        let mut tx_backup = serde_json::from_str::<Transaction>(BACKUP_TX_NOT_SIGNED).unwrap();
+        tx_backup.lock_time = 1000;
+        db.expect_get_backup_transaction()
+            .with(predicate::eq(batch_statechain_id))
+            .returning(move |_| Ok(tx_backup.clone()));
+        db.expect_get_aborted_locktime().returning(|_| Ok(None));
+        db.expect_update_aborted_locktime()
+            .with(predicate::eq(batch_statechain_id), predicate::eq(900))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        db.expect_remove_transfer_data()
+            .with(predicate::eq(batch_statechain_id))
+            .times(1)
+            .returning(|_| Ok(()));
+        db.expect_archive_transfer_batch()
+            .with(predicate::eq(batch_id))
+            .times(1)
+            .returning(|_| Ok(()));
+        db.expect_remove_orphaned_backup_txs().returning(|| Ok(3));
+
+        let mut sc_entity = test_sc_entity(db);
+        *sc_entity.chain.lock().unwrap() = Box::new(chain);
+        sc_entity.config.session_expiry = session_expiry as u64;
+        sc_entity.config.signed_session_expiry = signed_session_expiry as u64;
+        sc_entity.config.batch_archive_age = 86400;
+        sc_entity.config.lh_decrement = 100;
+        sc_entity.config.relative_locktime = false;
+
+        assert_eq!(
+            sc_entity.collect_garbage().unwrap(),
+            GcCounts {
+                sessions: 4,
+                ecdsa: 3,
+                batches: 1,
+                backup_txs: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_collect_garbage_disabled() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_unfunded_user_sessions().times(0);
+        db.expect_get_transfer_batches_started_before().times(0);
+        db.expect_remove_orphaned_backup_txs()
+            .returning(|| Err(SEError::Generic(String::from("DB error"))));
+
+        let mut sc_entity = test_sc_entity(db);
+        sc_entity.config.session_expiry = 0;
+        sc_entity.config.batch_archive_age = 0;
+        assert!(sc_entity.collect_garbage().is_err());
+    }
+}
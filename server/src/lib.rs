@@ -21,7 +21,9 @@ extern crate hex;
 extern crate jsonwebtoken as jwt;
 extern crate log4rs;
 extern crate rusoto_dynamodb;
+extern crate rusqlite;
 extern crate serde_dynamodb;
+extern crate tungstenite;
 
 extern crate curv;
 extern crate electrumx_client;
@@ -46,8 +48,10 @@ extern crate mockito;
 
 extern crate shared_lib;
 
+pub mod chain;
 pub mod config;
 pub mod error;
+pub mod gc;
 pub mod protocol;
 pub mod server;
 pub mod storage;
@@ -62,7 +66,7 @@ use rocket_contrib::databases::r2d2_postgres::PostgresConnectionManager;
 use crate::protocol::transfer::TransferFinalizeData;
 use crate::storage::db::Alpha;
 use bitcoin::hashes::sha256d;
-use bitcoin::Transaction;
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
 use chrono::NaiveDateTime;
 use curv::{FE, GE};
 use kms::ecdsa::two_party::*;
@@ -71,8 +75,17 @@ use mockall::*;
 use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_one::Party1Private;
 use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party_two};
 use rocket_contrib::databases::postgres;
-use shared_lib::{state_chain::*, structs::TransferMsg3, Root};
+use shared_lib::{
+    state_chain::*,
+    structs::{
+        KeyType, StateChainClosureAPI, StateChainStatus, TransferMsg3, UserSessionAPI,
+        WithdrawOutput,
+    },
+    swap_data::SwapInfo,
+    Root,
+};
 use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
 use uuid::Uuid;
 
 #[database("postgres_w")]
@@ -91,6 +104,17 @@ pub struct PGDatabase {
     pub pool: Option<r2d2::Pool<PostgresConnectionManager>>,
     pub smt: PGDatabaseSmt,
 }
+/// SQLite database struct for Mercury. Contains the connection to an embedded database file and
+/// SMT DB items.
+pub struct SqliteDatabase {
+    pub connection: Option<Mutex<rusqlite::Connection>>,
+    pub smt: PGDatabaseSmt,
+}
+/// Database of the backend selected by the storage config
+pub enum StorageDatabase {
+    Postgres(PGDatabase),
+    Sqlite(SqliteDatabase),
+}
 
 use structs::*;
 
@@ -102,7 +126,15 @@ pub trait Database {
     fn from_pool(pool: r2d2::Pool<PostgresConnectionManager>) -> Self;
     fn get_user_auth(&self, user_id: Uuid) -> Result<Uuid>;
     fn has_withdraw_sc_sig(&self, user_id: Uuid) -> Result<()>;
-    fn update_withdraw_sc_sig(&self, user_id: &Uuid, sig: StateChainSig) -> Result<()>;
+    fn update_withdraw_sc_sig(
+        &self,
+        user_id: &Uuid,
+        sig: StateChainSig,
+        statechain_ids: Vec<Uuid>,
+        outputs: Vec<WithdrawOutput>,
+    ) -> Result<()>;
+    fn get_withdraw_batch(&self, user_id: Uuid) -> Result<Vec<Uuid>>;
+    fn get_withdraw_outputs(&self, user_id: Uuid) -> Result<Vec<WithdrawOutput>>;
     fn update_withdraw_tx_sighash(
         &self,
         user_id: &Uuid,
@@ -112,10 +144,26 @@ pub trait Database {
     fn update_sighash(&self, user_id: &Uuid, sig_hash: Hash) -> Result<()>;
     fn update_s1_pubkey(&self, user_id: &Uuid, pubkey: &GE) -> Result<()>;
     fn get_s1_pubkey(&self, user_id: &Uuid) -> Result<GE>;
+    fn update_key_type(&self, user_id: &Uuid, key_type: &KeyType) -> Result<()>;
+    // Sessions created without a key type are ECDSA
+    fn get_key_type(&self, user_id: Uuid) -> Result<KeyType>;
+    fn update_musig_key(&self, user_id: &Uuid, key: &MuSigKey) -> Result<()>;
+    fn get_musig_key(&self, user_id: Uuid) -> Result<MuSigKey>;
+    fn update_musig_nonces(&self, user_id: &Uuid, nonces: &Option<MuSigNonces>) -> Result<()>;
+    fn get_musig_nonces(&self, user_id: Uuid) -> Result<Option<MuSigNonces>>;
     fn update_user_backup_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()>;
     fn get_user_backup_tx(&self, user_id: Uuid) -> Result<Transaction>;
     fn update_backup_tx(&self, statechain_id: &Uuid, tx: Transaction) -> Result<()>;
+    fn update_user_kickoff_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()>;
+    fn get_user_kickoff_tx(&self, user_id: Uuid) -> Result<Transaction>;
     fn get_withdraw_confirm_data(&self, user_id: Uuid) -> Result<WithdrawConfirmData>;
+    fn has_refresh_sc_sig(&self, user_id: Uuid) -> Result<()>;
+    fn update_refresh_sc_sig(&self, user_id: &Uuid, sig: StateChainSig) -> Result<()>;
+    fn update_user_refresh_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()>;
+    fn get_user_refresh_tx(&self, user_id: Uuid) -> Result<Transaction>;
+    fn get_refresh_confirm_data(&self, user_id: Uuid) -> Result<RefreshConfirmData>;
+    // Remove refresh tx and StateChainSig from user session once a refresh is complete
+    fn remove_refresh_data(&self, user_id: &Uuid) -> Result<()>;
     /// Update root value in DB. Update root with ID or insert new DB item.
     fn root_update(&self, rt: &Root) -> Result<i64>;
     /// Insert a Root into root table
@@ -158,7 +206,21 @@ pub trait Database {
     ) -> Result<()>;
     fn get_current_backup_txs(&self, locktime: i64) -> Result<Vec<BackupTxID>>;
     fn remove_backup_tx(&self, statechain_id: &Uuid) -> Result<()>;
+    /// Store the watcher's CPFP child tx fee bumping a statechain's broadcast backup tx
+    fn update_cpfp_tx(&self, statechain_id: &Uuid, tx: &Transaction) -> Result<()>;
+    fn get_cpfp_tx(&self, statechain_id: &Uuid) -> Result<Option<Transaction>>;
+    /// Store the height and hash of the block containing a statechain's confirmed backup tx.
+    /// None if it is no longer confirmed.
+    fn update_backup_confirmation(
+        &self,
+        statechain_id: &Uuid,
+        block: &Option<(u64, BlockHash)>,
+    ) -> Result<()>;
+    fn get_backup_confirmation(&self, statechain_id: &Uuid) -> Result<Option<(u64, BlockHash)>>;
     fn get_backup_transaction(&self, statechain_id: Uuid) -> Result<Transaction>;
+    /// Store the signed kick-off tx spent by a statechain's backup txs in relative locktime mode
+    fn create_kickoff_transaction(&self, statechain_id: &Uuid, tx_kickoff: &Transaction) -> Result<()>;
+    fn get_kickoff_transaction(&self, statechain_id: Uuid) -> Result<Transaction>;
     fn get_backup_transaction_and_proof_key(&self, user_id: Uuid) -> Result<(Transaction, String)>;
     fn get_proof_key(&self, user_id: Uuid) -> Result<String>;
     fn get_sc_locked_until(&self, statechain_id: Uuid) -> Result<NaiveDateTime>;
@@ -182,6 +244,71 @@ pub trait Database {
     ) -> Result<()>;
     fn get_transfer_data(&self, statechain_id: Uuid) -> Result<TransferData>;
     fn remove_transfer_data(&self, statechain_id: &Uuid) -> Result<()>;
+    /// Record a backup tx signed for the owner with proof_key in the statechain's history
+    fn add_backup_tx_history(
+        &self,
+        statechain_id: &Uuid,
+        tx: &Transaction,
+        proof_key: &String,
+    ) -> Result<()>;
+    /// Backup txs signed for a statechain, oldest first
+    fn get_backup_tx_history(&self, statechain_id: Uuid) -> Result<Vec<BackupTxRecord>>;
+    /// IDs of statechains that have not been withdrawn or closed
+    fn get_active_statechain_ids(&self) -> Result<Vec<Uuid>>;
+    /// Track the confirmation of a statechain's funding tx until it is final
+    fn update_funding_confirmation(
+        &self,
+        statechain_id: &Uuid,
+        confirmation: &FundingConfirmation,
+    ) -> Result<()>;
+    fn get_funding_confirmation(&self, statechain_id: Uuid) -> Result<Option<FundingConfirmation>>;
+    fn remove_funding_confirmation(&self, statechain_id: &Uuid) -> Result<()>;
+    /// IDs of statechains with a funding tx confirmation that is not yet final
+    fn get_funding_confirmation_ids(&self) -> Result<Vec<Uuid>>;
+    /// Funding tx output spent by the statechain's kick-off tx or backup txs
+    fn update_funding_outpoint(&self, statechain_id: &Uuid, outpoint: &OutPoint) -> Result<()>;
+    fn get_funding_outpoint(&self, statechain_id: Uuid) -> Result<Option<OutPoint>>;
+    /// Mark a statechain as refreshed by a signed refresh tx which has not been confirmed
+    fn update_refresh_pending(&self, statechain_id: &Uuid, txid: &Txid) -> Result<()>;
+    fn get_refresh_pending(&self, statechain_id: Uuid) -> Result<Option<Txid>>;
+    fn remove_refresh_pending(&self, statechain_id: &Uuid) -> Result<()>;
+    /// Record the backup tx locktime (or relative locktime) of a transfer which was cancelled
+    /// or expired after its backup tx may have been signed
+    fn update_aborted_locktime(&self, statechain_id: &Uuid, locktime: u32) -> Result<()>;
+    fn get_aborted_locktime(&self, statechain_id: Uuid) -> Result<Option<u32>>;
+    fn remove_aborted_locktime(&self, statechain_id: &Uuid) -> Result<()>;
+    fn update_statechain_closure(
+        &self,
+        statechain_id: &Uuid,
+        closure: &StateChainClosureAPI,
+    ) -> Result<()>;
+    fn get_statechain_closure(&self, statechain_id: Uuid) -> Result<Option<StateChainClosureAPI>>;
+    /// Get a page of statechains matching filter, ordered by ID, and the total number of matches
+    fn get_statechain_listings(
+        &self,
+        filter: &StateChainFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(Vec<StateChainListing>, u64)>;
+    fn get_statechain_listing(&self, statechain_id: Uuid) -> Result<StateChainListing>;
+    /// Get IDs of the statechains with current proof key or funding txid equal to query
+    fn search_statechains(&self, query: &String) -> Result<Vec<Uuid>>;
+    /// Get a page of user sessions, ordered by ID, and the total number of sessions
+    fn get_user_sessions(&self, offset: u64, limit: u64) -> Result<(Vec<UserSessionAPI>, u64)>;
+    /// Get IDs of the statechains with a transfer started before time
+    fn get_transfers_started_before(&self, time: &NaiveDateTime) -> Result<Vec<Uuid>>;
+    /// Get IDs of the user sessions created before created_before without a statechain
+    fn get_unfunded_user_sessions(&self, created_before: &NaiveDateTime) -> Result<Vec<Uuid>>;
+    fn remove_user_session(&self, user_id: &Uuid) -> Result<()>;
+    /// Remove the ECDSA key material of a user session. Returns false if there was none.
+    fn remove_ecdsa(&self, user_id: &Uuid) -> Result<bool>;
+    /// Get IDs of the batch transfers started before time
+    fn get_transfer_batches_started_before(&self, time: &NaiveDateTime) -> Result<Vec<Uuid>>;
+    /// Move a batch transfer to the archive table
+    fn archive_transfer_batch(&self, batch_id: &Uuid) -> Result<()>;
+    /// Remove backup txs of statechains that do not exist or have been withdrawn. Returns the
+    /// number removed.
+    fn remove_orphaned_backup_txs(&self) -> Result<u64>;
     fn transfer_is_completed(&self, statechain_id: Uuid) -> bool;
     fn get_ecdsa_master(&self, user_id: Uuid) -> Result<Option<String>>;
     fn get_ecdsa_witness_keypair(
@@ -224,6 +351,8 @@ pub trait Database {
     // Create DB entry for newly generated ID signalling that user has passed some
     // verification. For now use ID as 'password' to interact with state entity
     fn create_user_session(&self, user_id: &Uuid, auth: &String, proof_key: &String) -> Result<()>;
+    // Mark a deposit token or token payment as spent. Returns false if it was already spent.
+    fn spend_token(&self, token: &String) -> Result<bool>;
     // Create new UserSession to allow new owner to generate shared wallet
     fn transfer_init_user_session(
         &self,
@@ -247,10 +376,33 @@ pub trait Database {
     fn get_ecdsa_master_key_input(&self, user_id: Uuid) -> Result<ECDSAMasterKeyInput>;
     fn update_ecdsa_master(&self, user_id: &Uuid, master_key: MasterKey1) -> Result<()>;
     fn get_sighash(&self, user_id: Uuid) -> Result<sha256d::Hash>;
+    /// Insert or update the swap registration of a statechain
+    fn update_swap_registration(
+        &self,
+        statechain_id: &Uuid,
+        amount: u64,
+        swap_size: u64,
+    ) -> Result<()>;
+    /// Remove the swap registration of a statechain if it exists
+    fn remove_swap_registration(&self, statechain_id: &Uuid) -> Result<()>;
+    /// Get all statechains registered for a swap and not yet included in a swap
+    fn get_swap_registrations(&self) -> Result<Vec<SwapRegistration>>;
+    /// Insert or update the state of a swap
+    fn update_swap(&self, swap_data: &SwapData) -> Result<()>;
+    /// Remove a swap if it exists
+    fn remove_swap(&self, swap_id: &Uuid) -> Result<()>;
+    /// Get the state of all swaps in progress
+    fn get_swaps(&self) -> Result<Vec<SwapData>>;
 }
 
 pub mod structs {
     use super::*;
+    use bisetmap::BisetMap;
+    use shared_lib::{
+        blinded_token::BlindedSpendSignature,
+        musig::{PubNonce, SecNonce},
+        structs::SCEAddress,
+    };
 
     #[derive(Clone)]
     pub struct StateChainAmount {
@@ -276,6 +428,24 @@ pub mod structs {
         pub id: Uuid,
     }
 
+    /// Block containing a statechain's funding (deposit or refresh) tx, tracked until the tx is
+    /// buried reorg_depth blocks deep
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct FundingConfirmation {
+        pub txid: bitcoin::Txid,
+        /// Height and hash of the block containing the tx. None while it is reorged out.
+        pub block: Option<(u64, BlockHash)>,
+        /// Lock of the statechain to restore once a reorged out tx confirms again
+        pub locked_until: Option<NaiveDateTime>,
+    }
+
+    /// A backup tx and the proof key of the owner it was signed for
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct BackupTxRecord {
+        pub tx: Transaction,
+        pub proof_key: String,
+    }
+
     #[derive(Debug)]
     pub struct StateChainOwner {
         pub locked_until: NaiveDateTime,
@@ -289,12 +459,32 @@ pub mod structs {
         pub statechain_id: Uuid,
     }
 
+    pub struct RefreshConfirmData {
+        pub tx_refresh: Transaction,
+        pub refresh_sc_sig: StateChainSig,
+        pub statechain_id: Uuid,
+    }
+
     pub struct TransferData {
         pub statechain_id: Uuid,
         pub statechain_sig: StateChainSig,
         pub x1: FE,
     }
 
+    /// State Entity key share and the owner's public key share of a MuSig2 shared key
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct MuSigKey {
+        pub secret: FE,
+        pub owner_pub: GE,
+    }
+
+    /// State Entity secret nonces and the owner's public nonces of the next MuSig2 signature
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct MuSigNonces {
+        pub sec_nonce: SecNonce,
+        pub owner_nonce: PubNonce,
+    }
+
     pub struct ECDSAKeypair {
         pub party_1_private: Party1Private,
         pub party_2_public: GE,
@@ -313,6 +503,45 @@ pub mod structs {
         pub eph_key_gen_first_message_party_two: party_two::EphKeyGenFirstMsg,
     }
 
+    /// Statechain data listed by the explorer
+    #[derive(Clone, Debug)]
+    pub struct StateChainListing {
+        pub id: Uuid,
+        pub amount: i64,
+        pub chain: StateChain,
+        pub locked_until: NaiveDateTime,
+        pub funding_txid: Option<String>,
+        /// True if closed by a confirmed backup tx
+        pub closed: bool,
+        pub tx_backup: Option<Transaction>,
+    }
+
+    /// Explorer statechain query. Statechains in in_swap have status InSwap.
+    #[derive(Clone, Debug, Default)]
+    pub struct StateChainFilter {
+        pub min_amount: Option<u64>,
+        pub max_amount: Option<u64>,
+        pub status: Option<StateChainStatus>,
+        pub in_swap: Vec<Uuid>,
+    }
+
+    pub struct SwapRegistration {
+        pub statechain_id: Uuid,
+        pub amount: u64,
+        pub swap_size: u64,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct SwapData {
+        pub swap_info: SwapInfo,
+        pub out_addr_map: Option<BisetMap<SCEAddress, Option<Uuid>>>,
+        pub bst_e_prime_map: Option<HashMap<Uuid, FE>>,
+        pub bst_sig_map: Option<HashMap<Uuid, BlindedSpendSignature>>,
+        pub tb_sig_map: Option<HashSet<StateChainSig>>,
+        pub bst_retrieved: Option<HashSet<Uuid>>,
+        pub phase_start: Option<NaiveDateTime>,
+    }
+
     pub struct ECDSAMasterKeyInput {
         pub party2_public: GE,
         pub paillier_key_pair: party_one::PaillierKeyPair,
@@ -1,13 +1,13 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
 extern crate server_lib;
-use server_lib::{server, Database, PGDatabase};
+use server_lib::{server, Database, StorageDatabase};
 
 fn main() {
-    server::get_server::<PGDatabase, PGDatabase>(
+    server::get_server::<StorageDatabase, StorageDatabase>(
         None,
-        PGDatabase::get_new(),
-        PGDatabase::get_new(),
+        StorageDatabase::get_new(),
+        StorageDatabase::get_new(),
     )
     .unwrap()
     .launch();
@@ -0,0 +1,447 @@
+//! Admin
+//!
+//! Admin API trait and implementation for StateChainEntity. Operator inspection and repair of
+//! live state: user sessions, statechain transfer and lock state, stuck batch transfers, the
+//! conductor's scheduler and SMT root attestation. Requests must be signed by the key of the
+//! admin_pubkey config item, and the routes are not mounted if it is not set.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use super::auth::{AdminAuth, AdminSigned};
+use super::transfer::abort_transfer;
+use crate::error::SEError;
+use crate::server::StateChainEntity;
+use crate::storage::Storage;
+use crate::Database;
+use shared_lib::{
+    events::StateEntityEvent,
+    mainstay::Attestable,
+    request_auth::RequestAuth,
+    state_chain::{get_locked_until, get_time_now},
+    structs::*,
+    Root,
+};
+
+use bitcoin::secp256k1::PublicKey;
+use cfg_if::cfg_if;
+use rocket::State;
+use rocket_contrib::json::Json;
+use std::str::FromStr;
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
+    }
+}
+
+// User sessions per page if not specified
+const DEFAULT_PER_PAGE: u64 = 20;
+// Maximum user sessions per page
+const MAX_PER_PAGE: u64 = 100;
+
+/// StateChain Entity Admin API calls
+pub trait Admin {
+    /// API: List user sessions, ordered by ID, per_page at a time
+    fn list_user_sessions(&self, page: u64, per_page: u64) -> Result<UserSessionListAPI>;
+
+    /// API: Get a statechain's owner, lock, backup tx, transfer in progress and swap
+    fn get_admin_statechain(&self, statechain_id: Uuid) -> Result<AdminStateChainAPI>;
+
+    /// API: Lock a statechain for a number of seconds, or unlock it
+    fn lock_statechain(&self, lock_msg: AdminLockMsg) -> Result<()>;
+
+    /// API: End a batch transfer before its lifetime. Its transfers are cancelled and its swap
+    /// is removed from the scheduler.
+    fn cancel_transfer_batch(&self, cancel_msg: AdminCancelBatchMsg) -> Result<()>;
+
+    /// API: Get the contents of the conductor's scheduler
+    fn get_scheduler_state(&self) -> Result<serde_json::Value>;
+
+    /// API: Attest the current SMT root to the mainstay slot again
+    fn reattest_smt_root(&self) -> Result<Root>;
+}
+
+impl Admin for SCE {
+    fn list_user_sessions(&self, page: u64, per_page: u64) -> Result<UserSessionListAPI> {
+        let per_page = per_page.min(MAX_PER_PAGE);
+        let (sessions, total) = self
+            .database
+            .get_user_sessions(page.saturating_mul(per_page), per_page)?;
+        Ok(UserSessionListAPI {
+            sessions,
+            page,
+            per_page,
+            total,
+        })
+    }
+
+    fn get_admin_statechain(&self, statechain_id: Uuid) -> Result<AdminStateChainAPI> {
+        let owner = self.database.get_statechain_owner(statechain_id)?;
+        let amount = self.database.get_statechain_amount(statechain_id)?.amount;
+        // Backup and transfer data are not present at all stages of a statechain
+        let tx_backup = self.database.get_backup_transaction(statechain_id).ok();
+        let transfer_sig = self
+            .database
+            .get_transfer_data(statechain_id)
+            .ok()
+            .map(|transfer_data| transfer_data.statechain_sig);
+        let swap_id = self.scheduler.lock()?.get_swap_id(&statechain_id);
+        Ok(AdminStateChainAPI {
+            statechain_id,
+            owner_id: owner.owner_id,
+            amount: amount as u64,
+            chain: owner.chain.chain,
+            locked_until: owner.locked_until,
+            tx_backup,
+            transfer_sig,
+            swap_id,
+        })
+    }
+
+    fn lock_statechain(&self, lock_msg: AdminLockMsg) -> Result<()> {
+        let statechain_id = lock_msg.statechain_id;
+        // Check the statechain exists
+        self.database.get_sc_locked_until(statechain_id)?;
+        let locked_until = match lock_msg.seconds {
+            0 => get_time_now(),
+            seconds => get_locked_until(seconds as i64)?,
+        };
+        self.database
+            .update_locked_until(&statechain_id, &locked_until)?;
+        info!(
+            "ADMIN: State Chain ID: {} locked until {}.",
+            statechain_id, locked_until
+        );
+        Ok(())
+    }
+
+    fn cancel_transfer_batch(&self, cancel_msg: AdminCancelBatchMsg) -> Result<()> {
+        let batch_id = cancel_msg.batch_id;
+        let tbd = self.database.get_transfer_batch_data(batch_id)?;
+        if tbd.finalized {
+            return Err(SEError::Generic(format!(
+                "Transfer batch {} already finalized",
+                batch_id
+            )));
+        }
+        if !tbd.punished_state_chains.is_empty() {
+            return Err(SEError::TransferBatchEnded(String::from("Already ended")));
+        }
+
+        let locked_until = match cancel_msg.punish {
+            true => get_locked_until(self.config.punishment_duration as i64)?,
+            false => get_time_now(),
+        };
+        let state_chains: Vec<Uuid> = tbd.state_chains.into_iter().collect();
+        for statechain_id in &state_chains {
+            // Transfer data may not exist
+            if self.database.get_transfer_data(*statechain_id).is_ok() {
+                abort_transfer(
+                    &*self.database,
+                    statechain_id,
+                    self.config.lh_decrement,
+                    self.config.relative_locktime,
+                )?;
+            }
+            self.database
+                .update_locked_until(statechain_id, &locked_until)?;
+        }
+        // The statechains are recorded as punished to mark the batch as ended
+        self.database
+            .update_punished(&batch_id, state_chains.clone())?;
+
+        let mut guard = self.scheduler.lock()?;
+        if guard.remove_swap_info(&batch_id).is_some() {
+            guard.persist(&*self.database)?;
+            self.events.publish(
+                &batch_id.to_string(),
+                StateEntityEvent::SwapStatus {
+                    swap_id: batch_id,
+                    status: None,
+                },
+            );
+        }
+        self.events.publish(
+            &batch_id.to_string(),
+            StateEntityEvent::TransferBatchEnded {
+                batch_id,
+                punished_state_chains: match cancel_msg.punish {
+                    true => state_chains,
+                    false => vec![],
+                },
+            },
+        );
+
+        info!(
+            "ADMIN: Transfer batch cancelled. ID: {}. Punished: {}.",
+            batch_id, cancel_msg.punish
+        );
+        Ok(())
+    }
+
+    fn get_scheduler_state(&self) -> Result<serde_json::Value> {
+        serde_json::to_value(&*self.scheduler.lock()?)
+            .map_err(|e| SEError::Generic(format!("Failed to serialize scheduler: {}", e)))
+    }
+
+    fn reattest_smt_root(&self) -> Result<Root> {
+        let root = match self.get_smt_root()? {
+            Some(root) => root,
+            None => return Err(SEError::Generic(String::from("No SMT root"))),
+        };
+        match &self.config.mainstay {
+            Some(c) => root
+                .attest(c)
+                .map_err(|e| SEError::Generic(format!("Mainstay attestation error: {}", e)))?,
+            None => return Err(SEError::Generic(String::from("Mainstay not configured"))),
+        };
+        info!("ADMIN: SMT root {:?} attested.", root.id());
+        Ok(root)
+    }
+}
+
+impl SCE {
+    /// Check a request to path is signed by the admin key and is not a replay
+    pub fn check_admin_auth(&self, auth: &RequestAuth, path: &str, body: &[u8]) -> Result<()> {
+        let admin_pubkey = match PublicKey::from_str(&self.config.admin_pubkey) {
+            Ok(k) => k,
+            Err(_) => return Err(SEError::AuthError),
+        };
+        if let Err(e) = auth.verify(&admin_pubkey, path, body) {
+            warn!("ADMIN: Invalid signature: {}", e);
+            return Err(SEError::AuthError);
+        }
+        if let Err(e) = self.request_nonces.lock()?.check(auth) {
+            warn!("ADMIN: {}", e);
+            return Err(SEError::AuthError);
+        }
+        Ok(())
+    }
+}
+
+/// # List user sessions, a page at a time
+#[get("/admin/sessions?<page>&<per_page>", format = "json")]
+pub fn list_user_sessions(
+    sc_entity: State<SCE>,
+    _auth: AdminAuth,
+    page: Option<u64>,
+    per_page: Option<u64>,
+) -> Result<Json<UserSessionListAPI>> {
+    match sc_entity.list_user_sessions(page.unwrap_or(0), per_page.unwrap_or(DEFAULT_PER_PAGE)) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+/// # Get a statechain's owner, lock, backup tx, transfer in progress and swap
+#[get("/admin/statechain/<statechain_id>", format = "json")]
+pub fn get_admin_statechain(
+    sc_entity: State<SCE>,
+    _auth: AdminAuth,
+    statechain_id: String,
+) -> Result<Json<AdminStateChainAPI>> {
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain ID: {}", e)))?;
+    match sc_entity.get_admin_statechain(statechain_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+/// # Lock a statechain for a number of seconds, or unlock it if 0
+#[post("/admin/statechain/lock", format = "json", data = "<lock_msg>")]
+pub fn lock_statechain(
+    sc_entity: State<SCE>,
+    lock_msg: AdminSigned<AdminLockMsg>,
+) -> Result<Json<()>> {
+    match sc_entity.lock_statechain(lock_msg.into_inner()) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+/// # End a stuck batch transfer, cancelling its transfers and removing its swap
+#[post("/admin/transfer/batch/cancel", format = "json", data = "<cancel_msg>")]
+pub fn cancel_transfer_batch(
+    sc_entity: State<SCE>,
+    cancel_msg: AdminSigned<AdminCancelBatchMsg>,
+) -> Result<Json<()>> {
+    match sc_entity.cancel_transfer_batch(cancel_msg.into_inner()) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+/// # Get the contents of the conductor's scheduler
+#[get("/admin/scheduler", format = "json")]
+pub fn get_scheduler_state(
+    sc_entity: State<SCE>,
+    _auth: AdminAuth,
+) -> Result<Json<serde_json::Value>> {
+    match sc_entity.get_scheduler_state() {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+/// # Attest the current SMT root to the mainstay slot again
+#[post("/admin/smt/attest", format = "json")]
+pub fn reattest_smt_root(sc_entity: State<SCE>, _auth: AdminAuth) -> Result<Json<Root>> {
+    match sc_entity.reattest_smt_root() {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::{test_sc_entity, BACKUP_TX_NOT_SIGNED};
+    use crate::structs::{TransferBatchData, TransferData};
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::Transaction;
+    use chrono::NaiveDateTime;
+    use curv::elliptic::curves::traits::ECScalar;
+    use curv::FE;
+    use mockall::predicate;
+    use shared_lib::state_chain::StateChainSig;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_check_admin_auth() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let other_key = SecretKey::from_slice(&[4; 32]).unwrap();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db);
+        let auth = RequestAuth::new(&key, "admin/scheduler", &[]).unwrap();
+
+        // Admin API disabled
+        assert!(sc_entity
+            .check_admin_auth(&auth, "/admin/scheduler", &[])
+            .is_err());
+
+        sc_entity.config.admin_pubkey = PublicKey::from_secret_key(&secp, &key).to_string();
+        // Signed by another key or for another path
+        let other_auth = RequestAuth::new(&other_key, "admin/scheduler", &[]).unwrap();
+        assert!(sc_entity
+            .check_admin_auth(&other_auth, "/admin/scheduler", &[])
+            .is_err());
+        assert!(sc_entity
+            .check_admin_auth(&auth, "/admin/smt/attest", &[])
+            .is_err());
+
+        assert!(sc_entity
+            .check_admin_auth(&auth, "/admin/scheduler", &[])
+            .is_ok());
+        // Replayed
+        assert!(sc_entity
+            .check_admin_auth(&auth, "/admin/scheduler", &[])
+            .is_err());
+    }
+
+    #[test]
+    fn test_cancel_transfer_batch() {
+        let batch_id = Uuid::new_v4();
+        let finalized_batch_id = Uuid::new_v4();
+        let state_chains: HashSet<Uuid> = vec![Uuid::new_v4(), Uuid::new_v4()]
+            .into_iter()
+            .collect();
+        let state_chains_clone = state_chains.clone();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_transfer_batch_data()
+            .with(predicate::eq(batch_id))
+            .times(1)
+            .returning(move |_| {
+                Ok(TransferBatchData {
+                    state_chains: state_chains_clone.clone(),
+                    punished_state_chains: vec![],
+                    start_time: get_time_now(),
+                    finalized: false,
+                })
+            });
+        db.expect_get_transfer_batch_data()
+            .with(predicate::eq(finalized_batch_id))
+            .returning(|_| {
+                Ok(TransferBatchData {
+                    state_chains: HashSet::new(),
+                    punished_state_chains: vec![],
+                    start_time: get_time_now(),
+                    finalized: true,
+                })
+            });
+        db.expect_get_transfer_data().returning(|id| {
+            Ok(TransferData {
+                statechain_id: id,
+                statechain_sig: StateChainSig {
+                    purpose: String::from("TRANSFER_BATCH"),
+                    data: String::default(),
+                    sig: String::default(),
+                },
+                x1: FE::new_random(),
+            })
+        });
+        let mut tx_backup = serde_json::from_str::<Transaction>(BACKUP_TX_NOT_SIGNED).unwrap();
+        tx_backup.lock_time = 1000;
+        db.expect_get_backup_transaction()
+            .returning(move |_| Ok(tx_backup.clone()));
+        db.expect_get_aborted_locktime().returning(|_| Ok(None));
+        // Receivers may hold backup txs of the cancelled transfers
+        db.expect_update_aborted_locktime()
+            .withf(|_, locktime| *locktime == 900)
+            .times(2)
+            .returning(|_, _| Ok(()));
+        db.expect_remove_transfer_data()
+            .times(2)
+            .returning(|_| Ok(()));
+        // Unlocked rather than punished
+        db.expect_update_locked_until()
+            .withf(|_, locked_until: &NaiveDateTime| *locked_until <= get_time_now())
+            .times(2)
+            .returning(|_, _| Ok(()));
+        db.expect_update_punished()
+            .with(
+                predicate::eq(batch_id),
+                predicate::function(move |punished: &Vec<Uuid>| {
+                    punished.len() == 2 && punished.iter().all(|id| state_chains.contains(id))
+                }),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut sc_entity = test_sc_entity(db);
+        sc_entity.config.lh_decrement = 100;
+        sc_entity.config.relative_locktime = false;
+
+        let events = sc_entity.events.subscribe(&[batch_id.to_string()]);
+        sc_entity
+            .cancel_transfer_batch(AdminCancelBatchMsg {
+                batch_id,
+                punish: false,
+            })
+            .unwrap();
+        assert_eq!(
+            events.try_recv().unwrap(),
+            StateEntityEvent::TransferBatchEnded {
+                batch_id,
+                punished_state_chains: vec![],
+            }
+        );
+
+        assert!(sc_entity
+            .cancel_transfer_batch(AdminCancelBatchMsg {
+                batch_id: finalized_batch_id,
+                punish: true,
+            })
+            .is_err());
+    }
+}
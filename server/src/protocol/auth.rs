@@ -0,0 +1,243 @@
+//! Auth
+//!
+//! Authentication of session and admin requests. Requests within a session must be signed by the
+//! proof key of each session they are made in, and admin requests by the admin key. The sessions
+//! of a request are read from its deserialized message, so that a request is always
+//! authenticated against the sessions its handler acts on.
+
+use shared_lib::request_auth::{RequestAuth, SessionRequest, ADMIN_HEADERS, SESSION_HEADERS};
+
+use crate::server::StateChainEntity;
+use cfg_if::cfg_if;
+use okapi::openapi3::RequestBody;
+use rocket::data::{self, Data, FromDataSimple};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest};
+use rocket::{Request, State};
+use rocket_contrib::json::Json;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::OpenApiFromData;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use std::io::Read;
+
+//Generics cannot be used in Rocket State, therefore we define the concrete
+//type of StateChainEntity here
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
+    }
+}
+
+// Maximum request body size
+const LIMIT: u64 = 1000000;
+
+/// JSON request body signed by the proof keys of the sessions returned by its
+/// SessionRequest::session_ids
+pub struct SessionSigned<T>(pub T);
+
+impl<T> SessionSigned<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned + SessionRequest> FromDataSimple for SessionSigned<T> {
+    type Error = String;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, String> {
+        let mut body = Vec::new();
+        if let Err(e) = data.open().take(LIMIT).read_to_end(&mut body) {
+            return Outcome::Failure((Status::InternalServerError, e.to_string()));
+        }
+
+        let msg: T = match serde_json::from_slice(&body) {
+            Ok(v) => v,
+            Err(e) => return Outcome::Failure((Status::BadRequest, e.to_string())),
+        };
+        let user_ids = msg.session_ids();
+        if user_ids.is_empty() {
+            return Outcome::Failure((Status::BadRequest, String::from("No shared key ID")));
+        }
+
+        let sc_entity = match request.guard::<State<SCE>>() {
+            Outcome::Success(s) => s,
+            _ => {
+                return Outcome::Failure((
+                    Status::InternalServerError,
+                    String::from("StateChainEntity not managed"),
+                ))
+            }
+        };
+
+        let headers = request.headers();
+        let auth = match RequestAuth::from_headers(
+            headers.get_one(SESSION_HEADERS.signature),
+            headers.get_one(SESSION_HEADERS.timestamp),
+            headers.get_one(SESSION_HEADERS.nonce),
+        ) {
+            Ok(Some(a)) => a,
+            Ok(None) => {
+                return Outcome::Failure((Status::Unauthorized, String::from("Request not signed")))
+            }
+            Err(e) => return Outcome::Failure((Status::Unauthorized, e.to_string())),
+        };
+        if let Err(e) =
+            sc_entity.check_session_auth(&user_ids, &auth, request.uri().path(), &body)
+        {
+            warn!("AUTH: Request to {} rejected: {}", request.uri().path(), e);
+            return Outcome::Failure((Status::Unauthorized, e.to_string()));
+        }
+
+        Outcome::Success(SessionSigned(msg))
+    }
+}
+
+// The request body is documented as its JSON type
+impl<'r, T: JsonSchema + DeserializeOwned + SessionRequest> OpenApiFromData<'r>
+    for SessionSigned<T>
+{
+    fn request_body(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+        Json::<T>::request_body(gen)
+    }
+}
+
+// Check the admin authentication headers of request sign body
+fn check_admin_request(request: &Request, body: &[u8]) -> Result<(), (Status, String)> {
+    let sc_entity = match request.guard::<State<SCE>>() {
+        Outcome::Success(s) => s,
+        _ => {
+            return Err((
+                Status::InternalServerError,
+                String::from("StateChainEntity not managed"),
+            ))
+        }
+    };
+
+    let headers = request.headers();
+    let auth = match RequestAuth::from_headers(
+        headers.get_one(ADMIN_HEADERS.signature),
+        headers.get_one(ADMIN_HEADERS.timestamp),
+        headers.get_one(ADMIN_HEADERS.nonce),
+    ) {
+        Ok(Some(a)) => a,
+        Ok(None) => return Err((Status::Unauthorized, String::from("Request not signed"))),
+        Err(e) => return Err((Status::Unauthorized, e.to_string())),
+    };
+    // The signed path includes the query string
+    let path = request.uri().to_string();
+    if let Err(e) = sc_entity.check_admin_auth(&auth, &path, body) {
+        warn!("AUTH: Admin request to {} rejected: {}", path, e);
+        return Err((Status::Unauthorized, e.to_string()));
+    }
+    Ok(())
+}
+
+/// Admin request without a body, signed by the admin key
+pub struct AdminAuth;
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminAuth {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, String> {
+        match check_admin_request(request, &[]) {
+            Ok(()) => Outcome::Success(AdminAuth),
+            Err(e) => Outcome::Failure(e),
+        }
+    }
+}
+
+/// JSON admin request body signed by the admin key
+pub struct AdminSigned<T>(pub T);
+
+impl<T> AdminSigned<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromDataSimple for AdminSigned<T> {
+    type Error = String;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, String> {
+        let mut body = Vec::new();
+        if let Err(e) = data.open().take(LIMIT).read_to_end(&mut body) {
+            return Outcome::Failure((Status::InternalServerError, e.to_string()));
+        }
+        if let Err(e) = check_admin_request(request, &body) {
+            return Outcome::Failure(e);
+        }
+
+        match serde_json::from_slice(&body) {
+            Ok(v) => Outcome::Success(AdminSigned(v)),
+            Err(e) => Outcome::Failure((Status::BadRequest, e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use rocket::http::{ContentType, Header};
+    use rocket::local::Client;
+    use shared_lib::structs::WithdrawMsg2;
+    use uuid::Uuid;
+
+    #[post("/withdraw/confirm", format = "json", data = "<withdraw_msg2>")]
+    fn confirm(withdraw_msg2: SessionSigned<WithdrawMsg2>) -> Json<Vec<Uuid>> {
+        Json(withdraw_msg2.into_inner().shared_key_ids)
+    }
+
+    #[test]
+    fn test_session_signed() {
+        let own_id = Uuid::new_v4();
+        let victim_id = Uuid::new_v4();
+        let (privkey, pubkey) = shared_lib::util::keygen::generate_keypair();
+        let (_, victim_pubkey) = shared_lib::util::keygen::generate_keypair();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_proof_key().returning(move |id| {
+            if id == own_id {
+                Ok(pubkey.to_string())
+            } else {
+                Ok(victim_pubkey.to_string())
+            }
+        });
+        let rocket = rocket::ignite()
+            .manage(test_sc_entity(db))
+            .mount("/", routes![confirm]);
+        let client = Client::new(rocket).expect("valid rocket instance");
+
+        let post = |body: String| {
+            let auth = RequestAuth::new(&privkey.key, "/withdraw/confirm", body.as_bytes()).unwrap();
+            client
+                .post("/withdraw/confirm")
+                .header(ContentType::JSON)
+                .header(Header::new(SESSION_HEADERS.signature, auth.signature))
+                .header(Header::new(SESSION_HEADERS.timestamp, auth.timestamp.to_string()))
+                .header(Header::new(SESSION_HEADERS.nonce, auth.nonce))
+                .body(body)
+                .dispatch()
+        };
+
+        let mut response = post(format!("{{\"shared_key_ids\":[\"{}\"]}}", own_id));
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string().unwrap(), format!("[\"{}\"]", own_id));
+
+        // The request is authenticated against the sessions the handler acts on, not the
+        // signer's own session named in an extra field
+        let response = post(format!(
+            "{{\"shared_key_id\":\"{}\",\"shared_key_ids\":[\"{}\"]}}",
+            own_id, victim_id
+        ));
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
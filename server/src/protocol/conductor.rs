@@ -4,12 +4,14 @@
 
 pub use super::super::Result;
 use crate::error::SEError;
+use crate::protocol::events::EventBus;
 use crate::server::REG_SWAP_UTXOS;
 use shared_lib::{
     blinded_token::{
         BSTSenderData, BlindedSpendSignature, BlindedSpendToken, BlindedSpentTokenMessage,
     },
-    state_chain::StateChainSig,
+    events::StateEntityEvent,
+    state_chain::{get_time_now, is_locked, StateChainSig},
     structs::*,
     swap_data::*,
 };
@@ -19,9 +21,10 @@ use crate::server::StateChainEntity;
 use crate::protocol::transfer_batch::BatchTransfer;
 use crate::protocol::withdraw::Withdraw;
 use crate::storage::Storage;
-use crate::Database;
+use crate::{structs::SwapData, Database};
 use bisetmap::BisetMap;
 use cfg_if::cfg_if;
+use chrono::NaiveDateTime;
 use curv::FE;
 use mockall::predicate::*;
 use mockall::*;
@@ -30,8 +33,9 @@ use rocket_contrib::json::Json;
 use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 use std::str::FromStr;
+use std::sync::Arc;
 #[cfg(test)]
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
 use uuid::Uuid;
 use rocket_okapi::openapi;
 use rocket_okapi::JsonSchema;
@@ -51,8 +55,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
     }
 }
 
@@ -121,6 +125,11 @@ pub trait Conductor {
     // list of those StateChains that have caused recent failures. Participants that completed their
     // transfers can reveal the nonce to the their Comm(statechain_id, nonce) and thus prove which
     // StateChain they own and should not take any responsibility for the failure.
+    //
+    // If swap_token.time_out passes before Phase 1, 2 or 3 is complete the Conductor aborts the swap.
+    // StateChains that did not send their first message, did not retrieve their blinded spend token
+    // or, in Phase 3, did not start the batch transfer are punished. All other participants are
+    // returned to the pool of swap requests.
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -145,6 +154,31 @@ pub struct Scheduler {
     bst_sig_map: HashMap<Uuid, HashMap<Uuid, BlindedSpendSignature>>,
     //map of swap_id to transfer batch sigs
     tb_sig_map: HashMap<Uuid, HashSet<StateChainSig>>,
+    //map of swap_id to state chain ids that have retrieved their blinded spend signature
+    bst_retrieved_map: HashMap<Uuid, HashSet<Uuid>>,
+    //swap id to time at which the swap entered its current phase
+    phase_start_map: HashMap<Uuid, NaiveDateTime>,
+    //state chain ids with registrations not yet written to the database
+    #[serde(skip)]
+    registrations_updated: HashSet<Uuid>,
+    //swap ids with swap data not yet written to the database
+    #[serde(skip)]
+    swaps_updated: HashSet<Uuid>,
+    //swap status changes are published to subscribers of the swap id
+    #[serde(skip)]
+    events: Option<Arc<EventBus>>,
+}
+
+fn publish_swap_status(events: &Option<Arc<EventBus>>, swap_id: &Uuid, status: Option<SwapStatus>) {
+    if let Some(events) = events {
+        events.publish(
+            &swap_id.to_string(),
+            StateEntityEvent::SwapStatus {
+                swap_id: swap_id.to_owned(),
+                status,
+            },
+        );
+    }
 }
 
 impl Scheduler {
@@ -160,6 +194,97 @@ impl Scheduler {
             bst_e_prime_map: HashMap::new(),
             bst_sig_map: HashMap::new(),
             tb_sig_map: HashMap::new(),
+            bst_retrieved_map: HashMap::new(),
+            phase_start_map: HashMap::new(),
+            registrations_updated: HashSet::new(),
+            swaps_updated: HashSet::new(),
+            events: None,
+        }
+    }
+
+    /// Publish swap phase changes to subscribers of events
+    pub fn set_event_bus(&mut self, events: Arc<EventBus>) {
+        self.events = Some(events);
+    }
+
+    /// Rebuild the scheduler from the swap registrations and swaps stored in the database
+    pub fn load<T: Database>(db: &T) -> Result<Self> {
+        let mut scheduler = Self::new();
+        for reg in db.get_swap_registrations()? {
+            scheduler.register_amount_swap_size(&reg.statechain_id, reg.amount, reg.swap_size);
+        }
+        for swap_data in db.get_swaps()? {
+            scheduler.insert_swap_data(swap_data);
+        }
+        scheduler.registrations_updated.clear();
+        scheduler.swaps_updated.clear();
+        info!(
+            "SCHEDULER: Loaded {} swap registrations and {} swaps",
+            scheduler.statechain_amount_map.len(),
+            scheduler.swap_info_map.len()
+        );
+        Ok(scheduler)
+    }
+
+    /// Write all registrations and swaps that have changed since the last call through to the database
+    pub fn persist<T: Database>(&mut self, db: &T) -> Result<()> {
+        let registrations: Vec<Uuid> = self.registrations_updated.iter().cloned().collect();
+        for id in registrations {
+            let amount = self.statechain_amount_map.get(&id);
+            let swap_size = self.statechain_swap_size_map.get(&id);
+            if amount.is_empty() || swap_size.is_empty() {
+                db.remove_swap_registration(&id)?;
+            } else {
+                db.update_swap_registration(&id, amount[0], swap_size[0])?;
+            }
+            self.registrations_updated.remove(&id);
+        }
+
+        let swaps: Vec<Uuid> = self.swaps_updated.iter().cloned().collect();
+        for id in swaps {
+            match self.get_swap_data(&id) {
+                Some(swap_data) => db.update_swap(&swap_data)?,
+                None => db.remove_swap(&id)?,
+            }
+            self.swaps_updated.remove(&id);
+        }
+        Ok(())
+    }
+
+    /// Get the stored state of a swap
+    pub fn get_swap_data(&self, swap_id: &Uuid) -> Option<SwapData> {
+        self.get_swap_info(swap_id).map(|swap_info| SwapData {
+            swap_info,
+            out_addr_map: self.out_addr_map.get(swap_id).cloned(),
+            bst_e_prime_map: self.bst_e_prime_map.get(swap_id).cloned(),
+            bst_sig_map: self.bst_sig_map.get(swap_id).cloned(),
+            tb_sig_map: self.tb_sig_map.get(swap_id).cloned(),
+            bst_retrieved: self.bst_retrieved_map.get(swap_id).cloned(),
+            phase_start: self.phase_start_map.get(swap_id).cloned(),
+        })
+    }
+
+    fn insert_swap_data(&mut self, swap_data: SwapData) {
+        let swap_id = swap_data.swap_info.swap_token.id;
+        self.insert_swap_info(&swap_data.swap_info);
+        if let Some(v) = swap_data.out_addr_map {
+            self.out_addr_map.insert(swap_id, v);
+        }
+        if let Some(v) = swap_data.bst_e_prime_map {
+            self.bst_e_prime_map.insert(swap_id, v);
+        }
+        if let Some(v) = swap_data.bst_sig_map {
+            self.bst_sig_map.insert(swap_id, v);
+        }
+        if let Some(v) = swap_data.tb_sig_map {
+            self.tb_sig_map.insert(swap_id, v);
+        }
+        if let Some(v) = swap_data.bst_retrieved {
+            self.bst_retrieved_map.insert(swap_id, v);
+        }
+        //Swaps stored without a phase start time time out from the time they are loaded
+        if let Some(v) = swap_data.phase_start {
+            self.phase_start_map.insert(swap_id, v);
         }
     }
 
@@ -179,6 +304,23 @@ impl Scheduler {
             .insert(statechain_id.to_owned(), amount);
         self.statechain_swap_size_map
             .insert(statechain_id.to_owned(), swap_size);
+        self.registrations_updated.insert(statechain_id.to_owned());
+    }
+
+    /// Remove a pending swap registration, e.g. for a statechain that has been closed
+    pub fn deregister_statechain(&mut self, statechain_id: &Uuid) {
+        self.statechain_amount_map.delete(statechain_id);
+        self.statechain_swap_size_map.delete(statechain_id);
+        self.registrations_updated.insert(statechain_id.to_owned());
+    }
+
+    /// IDs of statechains registered for or taking part in a swap
+    pub fn get_swap_statechain_ids(&self) -> Vec<Uuid> {
+        let mut ids: HashSet<Uuid> = self.swap_id_map.keys().cloned().collect();
+        for (id, _) in self.statechain_amount_map.collect() {
+            ids.insert(id);
+        }
+        ids.into_iter().collect()
     }
 
     pub fn get_statechain_ids_by_amount(&self, amount: &u64) -> Vec<Uuid> {
@@ -205,6 +347,19 @@ impl Scheduler {
             .insert(swap_id.to_owned(), swap_info.status.to_owned());
         self.time_out_map
             .insert(swap_id.to_owned(), swap_info.swap_token.time_out);
+        self.phase_start_map.insert(swap_id.to_owned(), get_time_now());
+        self.swaps_updated.insert(swap_id.to_owned());
+        if let Some(events) = &self.events {
+            for id in &swap_info.swap_token.statechain_ids {
+                events.publish(
+                    &id.to_string(),
+                    StateEntityEvent::SwapStarted {
+                        statechain_id: id.to_owned(),
+                        swap_id: swap_id.to_owned(),
+                    },
+                );
+            }
+        }
     }
 
     pub fn remove_swap_info(&mut self, swap_id: &Uuid) -> Option<SwapInfo> {
@@ -221,6 +376,9 @@ impl Scheduler {
                     .insert(swap_id.to_owned(), i.swap_token.time_out);
                 self.bst_e_prime_map.remove(swap_id);
                 self.bst_sig_map.remove(swap_id);
+                self.bst_retrieved_map.remove(swap_id);
+                self.phase_start_map.remove(swap_id);
+                self.swaps_updated.insert(swap_id.to_owned());
                 Some(i)
             }
             None => None,
@@ -306,6 +464,7 @@ impl Scheduler {
                         //as a coherence check
                         assert!(self.statechain_swap_size_map.delete(&id).len() == 1);
                         assert!(self.statechain_amount_map.delete(&id).len() == 1);
+                        self.registrations_updated.insert(id);
                     }
                     info!("SCHEDULER: Created Swap ID: {}", swap_id);
                     debug!("SCHEDULER: Swap Info: {:?}", si);
@@ -343,6 +502,9 @@ impl Scheduler {
                         )?;
                         self.bst_sig_map.insert(swap_id, scid_bst_map);
                         swap_info.status = SwapStatus::Phase2;
+                        self.phase_start_map.insert(swap_id, get_time_now());
+                        self.swaps_updated.insert(swap_id);
+                        publish_swap_status(&self.events, &swap_id, Some(SwapStatus::Phase2));
                         info!("SCHEDULER: Swap ID: {} moved on to Phase2", swap_id);
                     }
                 }
@@ -361,6 +523,9 @@ impl Scheduler {
                     // Check if there are any unclaimed SCEAddresses
                     if sce_addr_list.rev_get(&None).len() == 0 {
                         swap_info.status = SwapStatus::Phase3;
+                        self.phase_start_map.insert(swap_id.to_owned(), get_time_now());
+                        self.swaps_updated.insert(swap_id.to_owned());
+                        publish_swap_status(&self.events, swap_id, Some(SwapStatus::Phase3));
                     }
                     info!("SCHEDULER: Swap ID: {} moved on to Phase3", swap_id);
                 }
@@ -375,6 +540,9 @@ impl Scheduler {
             Some(i) => match i.status {
                 SwapStatus::Phase3 => {
                     i.status = SwapStatus::Phase4;
+                    self.phase_start_map.insert(id.to_owned(), get_time_now());
+                    self.swaps_updated.insert(id.to_owned());
+                    publish_swap_status(&self.events, id, Some(SwapStatus::Phase4));
                     info!("SCHEDULER: Swap ID: {} moved to Phase4", id);
                 }
                 SwapStatus::Phase4 => {
@@ -408,6 +576,8 @@ impl Scheduler {
             Some(i) => match i.status {
                 SwapStatus::Phase4 => {
                     i.status = SwapStatus::End;
+                    self.swaps_updated.insert(id.to_owned());
+                    publish_swap_status(&self.events, id, Some(SwapStatus::End));
                     info!("SCHEDULER: Swap ID: {} moved to phase End", id);
                 }
                 SwapStatus::End => {
@@ -438,12 +608,101 @@ impl Scheduler {
         self.update_swaps()
     }
 
+    //Abort swaps that have been in phase 1, 2 or 3 for longer than the swap token time_out.
+    //Participants that completed the phase are returned to the pool of swap requests.
+    //Returns the state chain ids of the participants that caused the swap to fail. A swap that
+    //times out in phase 3 because the server failed to start the batch transfer fails nobody.
+    //Swaps in phase 4 are governed by the transfer batch lifetime.
+    pub fn expire_swaps(&mut self) -> Vec<Uuid> {
+        let now = get_time_now().timestamp();
+        let mut expired = Vec::<Uuid>::new();
+        for (swap_id, swap_info) in self.swap_info_map.iter() {
+            let start = match self.phase_start_map.get(swap_id) {
+                Some(t) => t.timestamp(),
+                None => continue,
+            };
+            if now - start <= swap_info.swap_token.time_out as i64 {
+                continue;
+            }
+            match swap_info.status {
+                SwapStatus::Phase1 | SwapStatus::Phase2 | SwapStatus::Phase3 => {
+                    expired.push(swap_id.to_owned())
+                }
+                _ => (),
+            }
+        }
+
+        let mut failed = Vec::<Uuid>::new();
+        for swap_id in expired {
+            let swap_info = match self.get_swap_info(&swap_id) {
+                Some(i) => i,
+                None => continue,
+            };
+            let statechain_ids = &swap_info.swap_token.statechain_ids;
+            let swap_failed: Vec<Uuid> = match swap_info.status {
+                //Failed to send SwapMsg1
+                SwapStatus::Phase1 => match self.bst_e_prime_map.get(&swap_id) {
+                    Some(e_prime_map) => statechain_ids
+                        .iter()
+                        .filter(|id| !e_prime_map.contains_key(id))
+                        .cloned()
+                        .collect(),
+                    None => statechain_ids.clone(),
+                },
+                //SwapMsg2 is sent anonymously, so a failure can only be attributed to
+                //participants that did not retrieve their blinded spend signature
+                SwapStatus::Phase2 => match self.bst_retrieved_map.get(&swap_id) {
+                    Some(retrieved) => statechain_ids
+                        .iter()
+                        .filter(|id| !retrieved.contains(id))
+                        .cloned()
+                        .collect(),
+                    None => statechain_ids.clone(),
+                },
+                //Every participant sent its transfer batch signature with SwapMsg1, and
+                //starting the batch transfer is up to the server. Only participants without a
+                //transfer batch signature can have caused the failure.
+                _ => {
+                    let signed: HashSet<String> = match self.tb_sig_map.get(&swap_id) {
+                        Some(sigs) => sigs.iter().map(|sig| sig.data.clone()).collect(),
+                        None => HashSet::new(),
+                    };
+                    statechain_ids
+                        .iter()
+                        .filter(|id| !signed.contains(&id.to_string()))
+                        .cloned()
+                        .collect()
+                }
+            };
+            self.remove_swap_info(&swap_id);
+            self.tb_sig_map.remove(&swap_id);
+            publish_swap_status(&self.events, &swap_id, None);
+
+            //Return the honest participants to the pool
+            for id in statechain_ids {
+                if !swap_failed.contains(id) {
+                    self.register_amount_swap_size(
+                        id,
+                        swap_info.swap_token.amount,
+                        statechain_ids.len() as u64,
+                    );
+                }
+            }
+            info!(
+                "SCHEDULER: Swap ID: {} timed out in {:?}. Failed state chains: {:?}",
+                swap_id, swap_info.status, swap_failed
+            );
+            failed.extend(swap_failed);
+        }
+        failed
+    }
+
     pub fn get_blinded_spend_signature(
-        &self,
+        &mut self,
         swap_id: &Uuid,
         statechain_id: &Uuid,
     ) -> Result<BlindedSpendSignature> {
-        match self.get_swap_status(swap_id) {
+        let bst = match self.get_swap_status(swap_id) {
             Some(SwapStatus::Phase1) => Err(SEError::SwapError(
                 "in phase 1, token not available".to_string(),
             )),
@@ -459,7 +718,16 @@ impl Scheduler {
                     "unknown swap id when getting blind spending token".to_string(),
                 )),
             },
+        }?;
+        //Record that this participant is able to send SwapMsg2
+        let retrieved = self
+            .bst_retrieved_map
+            .entry(swap_id.to_owned())
+            .or_insert(HashSet::new());
+        if retrieved.insert(statechain_id.to_owned()) {
+            self.swaps_updated.insert(swap_id.to_owned());
         }
+        Ok(bst)
     }
 }
 
@@ -513,16 +781,19 @@ impl Conductor for SCE {
                     };
                     self.transfer_batch_init(msg)?;
                     let _ = guard.transfer_started(swap_id)?;
+                    guard.persist(&*self.database)?;
                 }
                 SwapStatus::Phase4 => match self.get_transfer_batch_status(swap_id.to_owned()) {
                     Ok(res) => {
                         if res.finalized {
                             let _ = guard.transfer_ended(swap_id)?;
+                            guard.persist(&*self.database)?;
                         }
                     }
                     Err(e) => match e {
                         SEError::TransferBatchEnded(_) => {
                             let _ = guard.transfer_ended(swap_id)?;
+                            guard.persist(&*self.database)?;
                         }
                         _ => (),
                     },
@@ -545,8 +816,10 @@ impl Conductor for SCE {
         swap_id: &Uuid,
         statechain_id: &Uuid,
     ) -> Result<BlindedSpendSignature> {
-        let guard = self.scheduler.lock()?;
-        Ok(guard.get_blinded_spend_signature(swap_id, statechain_id)?)
+        let mut guard = self.scheduler.lock()?;
+        let bst = guard.get_blinded_spend_signature(swap_id, statechain_id)?;
+        guard.persist(&*self.database)?;
+        Ok(bst)
     }
 
     fn register_utxo(&self, register_utxo_msg: &RegisterUtxo) -> Result<()> {
@@ -554,11 +827,16 @@ impl Conductor for SCE {
         let key_id = &register_utxo_msg.statechain_id;
         let swap_size = &register_utxo_msg.swap_size;
         //Verify the signature
-        let _ = self.verify_statechain_sig(key_id, sig, None)?;
+        let sco = self.verify_statechain_sig(key_id, sig, None)?;
+        //Punished state chains cannot rejoin the pool until their lock expires
+        is_locked(sco.locked_until)?;
+        //Statecoins with an unconfirmed refresh tx cannot be transferred
+        self.check_no_refresh_pending(key_id)?;
         let sc_amount = self.database.get_statechain_amount(*key_id)?;
         let amount: u64 = sc_amount.amount as u64;
         let mut guard = self.scheduler.lock()?;
         let _ = guard.register_amount_swap_size(key_id, amount, *swap_size);
+        guard.persist(&*self.database)?;
 
         //increment swap histogram
         REG_SWAP_UTXOS.with_label_values(&[&swap_size.clone().to_string(),&amount.clone().to_string()]).inc();
@@ -642,6 +920,8 @@ impl Conductor for SCE {
                             .insert(swap_id.to_owned(), swaps_e_prime_list.clone());
                     }
                 };
+                guard.swaps_updated.insert(swap_id.to_owned());
+                guard.persist(&*self.database)?;
                 info!(
                     "CONDUTOR: swap_first_message complete for StateChain ID {} of Swap ID: {}",
                     swap_msg1.statechain_id, swap_id
@@ -725,6 +1005,8 @@ impl Conductor for SCE {
             let addr = unclaimed_addr_list.get(0).unwrap().clone();
             sce_address_bisetmap.insert(addr.clone(), claimed_nonce);
             sce_address_bisetmap.remove(&addr, &None);
+            guard.swaps_updated.insert(swap_id.to_owned());
+            guard.persist(&*self.database)?;
 
             info!(
                 "CONDUTOR: swap_second_message completed for claimed nonce {:?} of Swap ID: {}",
@@ -807,6 +1089,12 @@ pub fn get_swap_info(sc_entity: State<SCE>, swap_id: Json<SwapID>) -> Result<Jso
     }
 }
 
+// Swap endpoints are not session signed: each message is authorized by a signature of the
+// statecoin's proof key or by a blind spend token, and swap/second must not be linkable to a
+// session.
+
+// The blinded spend signature can only be unblinded with the blinding factor of the participant
+// that sent the swap/first message for the statecoin.
 #[openapi]
 /// # Get blinded spend token required for second message
 #[post("/swap/blinded-spend-signature", format = "json", data = "<bst_msg>")]
@@ -820,6 +1108,7 @@ pub fn get_blinded_spend_signature(
         .map(|x| Json(x))
 }
 
+// Authorized by the "SWAP" StateChainSig signed by the statecoin's proof key.
 #[openapi]
 /// # Phase 0 of coinswap: Notify conductor of desire to take part in a swap with signature to prove ownership of statecoin. 
 #[post("/swap/register-utxo", format = "json", data = "<register_utxo_msg>")]
@@ -833,6 +1122,7 @@ pub fn register_utxo(
     }
 }
 
+// Authorized by the swap token and transfer batch signatures of the statecoin's proof key.
 #[openapi]
 /// # Phase 1 of coinswap: Participants sign SwapToken and provide a statechain address and e_prime for blind spend token.
 #[post("/swap/first", format = "json", data = "<swap_msg1>")]
@@ -843,6 +1133,8 @@ pub fn swap_first_message(sc_entity: State<SCE>, swap_msg1: Json<SwapMsg1>) -> R
     }
 }
 
+// Authorized by the blind spend token only, so that the new address is not linked to the
+// statecoin sent in the swap.
 #[openapi]
 /// # Phase 2 of coinswap: Participants provide blind spend token and recieve address.
 #[post("/swap/second", format = "json", data = "<swap_msg2>")]
@@ -861,7 +1153,7 @@ pub fn swap_second_message(
 mod tests {
     use super::*;
     use crate::protocol::util::tests::test_sc_entity;
-    use crate::structs::{StateChainAmount, StateChainOwner};
+    use crate::structs::{StateChainAmount, StateChainOwner, SwapRegistration};
     use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
     use bitcoin::Address;
     use curv::{elliptic::curves::traits::ECScalar, FE};
@@ -921,6 +1213,11 @@ mod tests {
             bst_e_prime_map: HashMap::new(),
             bst_sig_map: HashMap::new(),
             tb_sig_map: HashMap::new(),
+            bst_retrieved_map: HashMap::new(),
+            phase_start_map: HashMap::new(),
+            registrations_updated: HashSet::new(),
+            swaps_updated: HashSet::new(),
+            events: None,
         }
     }
 
@@ -947,9 +1244,12 @@ mod tests {
         assert_eq!(scheduler.time_out_map.len(), 2);
 
         //Regsiter a new request for the amount 5, but require 6 to be in the swap
-        scheduler.register_amount_swap_size(&Uuid::new_v4(), 5, 6);
+        let registered_id = Uuid::new_v4();
+        scheduler.register_amount_swap_size(&registered_id, 5, 6);
         //Not enough participants to create swap
         scheduler.update_swap_info().unwrap();
+        //Registered statechains are in-swap
+        assert!(scheduler.get_swap_statechain_ids().contains(&registered_id));
         assert_eq!(scheduler.swap_id_map.len(), 7);
         assert_eq!(scheduler.swap_info_map.len(), 2);
         assert_eq!(scheduler.status_map.len(), 2);
@@ -985,6 +1285,182 @@ mod tests {
             6,
             "expected 6 unique state chain ids in the swap token"
         );
+        assert!(scheduler.get_swap_statechain_ids().contains(&sc_id));
+    }
+
+    #[test]
+    fn test_scheduler_persist_load() {
+        let mut scheduler = get_scheduler(vec![(3, 10), (3, 10), (3, 10), (3, 5)]);
+        scheduler.update_swap_info().unwrap();
+        let swap_id = scheduler.swap_id_map.iter().next().unwrap().1.to_owned();
+        let registered_id = scheduler.get_statechain_ids_by_amount(&5)[0];
+
+        let stored_swaps = Arc::new(Mutex::new(Vec::<SwapData>::new()));
+        let stored_swaps_update = stored_swaps.clone();
+        let removed_registrations = Arc::new(Mutex::new(Vec::<Uuid>::new()));
+        let removed_registrations_update = removed_registrations.clone();
+
+        // Phase start times are stored so that timeouts continue after a restart
+        let phase_start = get_time_now() - chrono::Duration::seconds(10);
+        scheduler.phase_start_map.insert(swap_id, phase_start);
+
+        let mut db = MockDatabase::new();
+        db.expect_update_swap().times(1).returning(move |swap_data| {
+            stored_swaps_update.lock().unwrap().push(swap_data.clone());
+            Ok(())
+        });
+        db.expect_remove_swap_registration()
+            .times(3)
+            .returning(move |id| {
+                removed_registrations_update.lock().unwrap().push(id.to_owned());
+                Ok(())
+            });
+        scheduler.persist(&db).unwrap();
+        assert!(!removed_registrations.lock().unwrap().contains(&registered_id));
+
+        // Nothing further to write
+        scheduler.persist(&db).unwrap();
+
+        let stored_swaps_get = stored_swaps.lock().unwrap().clone();
+        let mut db = MockDatabase::new();
+        db.expect_get_swap_registrations().returning(move || {
+            Ok(vec![SwapRegistration {
+                statechain_id: registered_id,
+                amount: 5,
+                swap_size: 3,
+            }])
+        });
+        db.expect_get_swaps()
+            .returning(move || Ok(stored_swaps_get.clone()));
+
+        let loaded = Scheduler::load(&db).unwrap();
+        assert_eq!(loaded.get_swap_status(&swap_id), Some(SwapStatus::Phase1));
+        assert_eq!(loaded.phase_start_map.get(&swap_id), Some(&phase_start));
+        assert_eq!(
+            loaded.get_swap_info(&swap_id).unwrap().swap_token.statechain_ids.len(),
+            3
+        );
+        assert_eq!(loaded.swap_id_map.len(), 3);
+        assert_eq!(loaded.get_statechain_ids_by_amount(&5), vec![registered_id]);
+        assert!(loaded.swaps_updated.is_empty());
+        assert!(loaded.registrations_updated.is_empty());
+    }
+
+    #[test]
+    fn test_scheduler_events() {
+        let mut scheduler = get_scheduler(vec![(3, 10), (3, 10), (3, 10)]);
+        let events = Arc::new(EventBus::new());
+        scheduler.set_event_bus(events.clone());
+        let statechain_id = scheduler.get_statechain_ids_by_amount(&10)[0];
+        let statechain_events = events.subscribe(&[statechain_id.to_string()]);
+
+        scheduler.update_swap_info().unwrap();
+        let swap_id = scheduler.get_swap_id(&statechain_id).unwrap();
+        assert_eq!(
+            statechain_events.try_recv().unwrap(),
+            StateEntityEvent::SwapStarted {
+                statechain_id,
+                swap_id
+            }
+        );
+
+        let swap_events = events.subscribe(&[swap_id.to_string()]);
+        scheduler.swap_info_map.get_mut(&swap_id).unwrap().status = SwapStatus::Phase3;
+        scheduler.transfer_started(&swap_id).unwrap();
+        scheduler.transfer_ended(&swap_id).unwrap();
+        for status in vec![SwapStatus::Phase4, SwapStatus::End] {
+            assert_eq!(
+                swap_events.try_recv().unwrap(),
+                StateEntityEvent::SwapStatus {
+                    swap_id,
+                    status: Some(status)
+                }
+            );
+        }
+        assert!(swap_events.try_recv().is_err());
+        assert!(statechain_events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_expire_swaps() {
+        let mut scheduler = get_scheduler(vec![(3, 10), (3, 10), (3, 10)]);
+        scheduler.update_swap_info().unwrap();
+        let swap_id = scheduler.swap_id_map.iter().next().unwrap().1.to_owned();
+        let statechain_ids = scheduler
+            .get_swap_info(&swap_id)
+            .unwrap()
+            .swap_token
+            .statechain_ids;
+
+        // Not yet timed out
+        assert!(scheduler.expire_swaps().is_empty());
+        assert!(scheduler.get_swap_info(&swap_id).is_some());
+
+        // Two participants send their first message, then the swap times out
+        let mut e_prime_map = HashMap::<Uuid, FE>::new();
+        e_prime_map.insert(statechain_ids[0], FE::new_random());
+        e_prime_map.insert(statechain_ids[1], FE::new_random());
+        scheduler.bst_e_prime_map.insert(swap_id, e_prime_map);
+        scheduler.phase_start_map.insert(
+            swap_id,
+            get_time_now() - chrono::Duration::seconds(DEFAULT_TIMEOUT as i64 + 1),
+        );
+
+        let failed = scheduler.expire_swaps();
+        assert_eq!(failed, vec![statechain_ids[2]]);
+        assert!(scheduler.get_swap_info(&swap_id).is_none());
+        assert!(scheduler.get_swap_id(&statechain_ids[0]).is_none());
+
+        // Honest participants are back in the pool, the failed one is not
+        let mut pool = scheduler.get_statechain_ids_by_amount(&10);
+        pool.sort();
+        let mut expected = vec![statechain_ids[0], statechain_ids[1]];
+        expected.sort();
+        assert_eq!(pool, expected);
+        assert!(scheduler.swaps_updated.contains(&swap_id));
+        assert!(scheduler.registrations_updated.contains(&statechain_ids[0]));
+
+        // A swap in phase 3 fails only the participants without a transfer batch signature
+        let mut scheduler = get_scheduler(vec![(3, 10), (3, 10), (3, 10)]);
+        scheduler.update_swap_info().unwrap();
+        let swap_id = scheduler.swap_id_map.iter().next().unwrap().1.to_owned();
+        let statechain_ids = scheduler
+            .get_swap_info(&swap_id)
+            .unwrap()
+            .swap_token
+            .statechain_ids;
+        scheduler.swap_info_map.get_mut(&swap_id).unwrap().status = SwapStatus::Phase3;
+        let tb_sig = |id: &Uuid| StateChainSig {
+            purpose: String::from("TRANSFER-BATCH"),
+            data: id.to_string(),
+            sig: String::new(),
+        };
+        let mut tb_sigs = HashSet::new();
+        tb_sigs.insert(tb_sig(&statechain_ids[0]));
+        tb_sigs.insert(tb_sig(&statechain_ids[1]));
+        scheduler.tb_sig_map.insert(swap_id, tb_sigs);
+        let timed_out = get_time_now() - chrono::Duration::seconds(DEFAULT_TIMEOUT as i64 + 1);
+        scheduler.phase_start_map.insert(swap_id, timed_out);
+        assert_eq!(scheduler.expire_swaps(), vec![statechain_ids[2]]);
+
+        // The server failing to start the batch transfer fails nobody
+        let mut scheduler = get_scheduler(vec![(3, 10), (3, 10), (3, 10)]);
+        scheduler.update_swap_info().unwrap();
+        let swap_id = scheduler.swap_id_map.iter().next().unwrap().1.to_owned();
+        scheduler.swap_info_map.get_mut(&swap_id).unwrap().status = SwapStatus::Phase3;
+        let tb_sigs = scheduler
+            .get_swap_info(&swap_id)
+            .unwrap()
+            .swap_token
+            .statechain_ids
+            .iter()
+            .map(tb_sig)
+            .collect();
+        scheduler.tb_sig_map.insert(swap_id, tb_sigs);
+        scheduler.phase_start_map.insert(swap_id, timed_out);
+        assert!(scheduler.expire_swaps().is_empty());
+        assert!(scheduler.get_swap_info(&swap_id).is_none());
+        assert_eq!(scheduler.get_statechain_ids_by_amount(&10).len(), 3);
     }
 
     #[test]
@@ -1084,6 +1560,9 @@ mod tests {
 
         db.expect_get_statechain_amount()
             .returning(move |_| Ok(statechain_amount.clone()));
+        db.expect_get_refresh_pending().returning(|_| Ok(None));
+        db.expect_update_swap_registration()
+            .returning(|_, _, _| Ok(()));
 
         let mut sc_entity = test_sc_entity(db);
         sc_entity.scheduler = Arc::new(Mutex::new(get_scheduler(vec![(3, 10), (3, 10), (3, 10)])));
@@ -1129,6 +1608,8 @@ mod tests {
 
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_update_swap().returning(|_| Ok(()));
+        db.expect_remove_swap_registration().returning(|_| Ok(()));
 
         let mut scheduler = get_scheduler(vec![(3, 10), (3, 10), (3, 10)]);
         scheduler.update_swap_info().unwrap();
@@ -1290,6 +1771,8 @@ mod tests {
     fn test_get_blinded_spend_token() {
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_update_swap().returning(|_| Ok(()));
+        db.expect_remove_swap_registration().returning(|_| Ok(()));
         let mut sc_entity = test_sc_entity(db);
         sc_entity.scheduler = Arc::new(Mutex::new(get_scheduler(vec![(3, 10), (3, 10), (3, 10)])));
         let mut guard = sc_entity.scheduler.lock().unwrap();
@@ -1360,6 +1843,8 @@ mod tests {
     fn test_swap_second_message() {
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_update_swap().returning(|_| Ok(()));
+        db.expect_remove_swap_registration().returning(|_| Ok(()));
         let mut sc_entity = test_sc_entity(db);
         sc_entity.scheduler = Arc::new(Mutex::new(get_scheduler(vec![(3, 10), (3, 10), (3, 10)])));
         let mut guard = sc_entity.scheduler.lock().unwrap();
@@ -5,17 +5,33 @@
 pub use super::super::Result;
 use crate::server::DEPOSITS_COUNT;
 extern crate shared_lib;
+use crate::chain::TxStatus;
 use crate::error::SEError;
 use crate::server::{StateChainEntity};
 use crate::storage::Storage;
 use crate::Database;
-use shared_lib::{state_chain::*, structs::*, util::FEE};
+use shared_lib::{
+    blinded_token::{BSTSenderData, BlindedSpendSignature, BlindedSpendToken},
+    pow,
+    state_chain::*,
+    structs::*,
+    util::FEE,
+};
 
-use bitcoin::PublicKey;
+use bitcoin::{Address, OutPoint, PublicKey, Transaction, Txid};
 use cfg_if::cfg_if;
+use curv::{arithmetic::traits::Converter, elliptic::curves::traits::ECScalar, BigInt, FE, GE};
+use rocket::request::{self, FromRequest, Request};
+use rocket::outcome::Outcome;
 use rocket::State;
 use rocket_contrib::json::Json;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use super::auth::SessionSigned;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 use rocket_okapi::openapi;
 
@@ -27,35 +43,244 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
+    }
+}
+
+// Window of deposit rate limits (seconds)
+const RATE_LIMIT_WINDOW: u64 = 60;
+// Time within which a proof of work challenge or deposit token request must be used (seconds)
+const REQUEST_LIFETIME: u64 = 600;
+// Maximum number of unused challenges, and of unused token requests
+const MAX_PENDING_REQUESTS: usize = 10000;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Requests within the rate limit window
+#[derive(Default)]
+struct RateLimit {
+    // Time and IP address of requests within the rate limit window, oldest first
+    requests: VecDeque<(u64, Option<IpAddr>)>,
+}
+
+impl RateLimit {
+    fn expire(&mut self, now: u64) {
+        while let Some(&(time, _)) = self.requests.front() {
+            if time + RATE_LIMIT_WINDOW > now {
+                break;
+            }
+            self.requests.pop_front();
+        }
+    }
+
+    /// Check there have been fewer than limit requests, and fewer than limit_ip requests from
+    /// ip, within the rate limit window. A limit of 0 is no limit.
+    fn check(&mut self, ip: Option<IpAddr>, limit: u32, limit_ip: u32, now: u64) -> Result<()> {
+        if limit == 0 && limit_ip == 0 {
+            return Ok(());
+        }
+        self.expire(now);
+        if limit > 0 && self.requests.len() >= limit as usize {
+            return Err(SEError::Generic(String::from(
+                "Deposit rate limit exceeded. Try again later.",
+            )));
+        }
+        if limit_ip > 0
+            && ip.is_some()
+            && self.requests.iter().filter(|(_, i)| *i == ip).count() >= limit_ip as usize
+        {
+            warn!("DEPOSIT: Rate limit exceeded by {:?}", ip);
+            return Err(SEError::Generic(String::from(
+                "Deposit rate limit exceeded. Try again later.",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Count a request from ip against the limits
+    fn record(&mut self, ip: Option<IpAddr>, limit: u32, limit_ip: u32, now: u64) {
+        if limit == 0 && limit_ip == 0 {
+            return;
+        }
+        self.expire(now);
+        self.requests.push_back((now, ip));
+    }
+}
+
+/// Deposit anti-spam state: recent deposit, challenge and token requests, issued proof of work
+/// challenges and deposit token requests awaiting payment
+pub struct DepositGate {
+    // Valid deposit requests
+    deposit_requests: RateLimit,
+    // Challenge requests
+    challenge_requests: RateLimit,
+    // Deposit token requests
+    token_init_requests: RateLimit,
+    // Time of issue of unused challenges
+    challenges: HashMap<String, u64>,
+    // Key blind signing deposit tokens
+    token_key: FE,
+    // Signing data and time of issue of token requests by token ID. Each is used to sign once.
+    token_requests: HashMap<Uuid, (BSTSenderData, u64)>,
+}
+
+impl DepositGate {
+    /// New DepositGate with a hex token key. A random key is used if empty.
+    pub fn new(token_key: &str) -> Result<Self> {
+        let token_key = match token_key.is_empty() {
+            true => FE::new_random(),
+            false => {
+                hex::decode(token_key)
+                    .map_err(|e| SEError::Generic(format!("Invalid deposit token key: {}", e)))?;
+                ECScalar::from(&BigInt::from_hex(token_key))
+            }
+        };
+        Ok(DepositGate {
+            deposit_requests: RateLimit::default(),
+            challenge_requests: RateLimit::default(),
+            token_init_requests: RateLimit::default(),
+            challenges: HashMap::new(),
+            token_key,
+            token_requests: HashMap::new(),
+        })
+    }
+
+    /// Record a deposit request from ip unless there have been limit requests, or limit_ip
+    /// requests from ip, within the rate limit window. A limit of 0 is no limit.
+    pub fn check_rate_limit(
+        &mut self,
+        ip: Option<IpAddr>,
+        limit: u32,
+        limit_ip: u32,
+        now: u64,
+    ) -> Result<()> {
+        self.deposit_requests.check(ip, limit, limit_ip, now)?;
+        self.deposit_requests.record(ip, limit, limit_ip, now);
+        Ok(())
+    }
+
+    /// Issue a new proof of work challenge to ip, within the rate limits of challenge requests
+    pub fn new_challenge(
+        &mut self,
+        ip: Option<IpAddr>,
+        limit: u32,
+        limit_ip: u32,
+        now: u64,
+    ) -> Result<String> {
+        self.challenge_requests.check(ip, limit, limit_ip, now)?;
+        self.challenges
+            .retain(|_, time| *time + REQUEST_LIFETIME > now);
+        if self.challenges.len() >= MAX_PENDING_REQUESTS {
+            return Err(SEError::Generic(String::from(
+                "Too many pending deposit challenges. Try again later.",
+            )));
+        }
+        self.challenge_requests.record(ip, limit, limit_ip, now);
+        let challenge = Uuid::new_v4().to_string();
+        self.challenges.insert(challenge.clone(), now);
+        Ok(challenge)
+    }
+
+    /// Use an issued challenge. Each challenge can be used once.
+    pub fn use_challenge(&mut self, challenge: &str, now: u64) -> Result<()> {
+        match self.challenges.remove(challenge) {
+            Some(time) if time + REQUEST_LIFETIME > now => Ok(()),
+            _ => Err(SEError::Generic(String::from(
+                "Unknown or expired deposit challenge.",
+            ))),
+        }
+    }
+
+    /// Start a new token request from ip, within the rate limits of token requests. Returns the
+    /// token ID and R' value to blind the token with.
+    pub fn new_token_request(
+        &mut self,
+        ip: Option<IpAddr>,
+        limit: u32,
+        limit_ip: u32,
+        now: u64,
+    ) -> Result<(Uuid, GE)> {
+        self.token_init_requests.check(ip, limit, limit_ip, now)?;
+        self.token_requests
+            .retain(|_, (_, time)| *time + REQUEST_LIFETIME > now);
+        if self.token_requests.len() >= MAX_PENDING_REQUESTS {
+            return Err(SEError::Generic(String::from(
+                "Too many pending deposit token requests. Try again later.",
+            )));
+        }
+        self.token_init_requests.record(ip, limit, limit_ip, now);
+        let token_id = Uuid::new_v4();
+        let bst_sender_data = BSTSenderData::from_key(self.token_key);
+        let r_prime = bst_sender_data.get_r_prime();
+        self.token_requests.insert(token_id, (bst_sender_data, now));
+        Ok((token_id, r_prime))
+    }
+
+    /// Remove a token request to sign it
+    pub fn take_token_request(&mut self, token_id: &Uuid, now: u64) -> Result<BSTSenderData> {
+        match self.token_requests.remove(token_id) {
+            Some((bst_sender_data, time)) if time + REQUEST_LIFETIME > now => Ok(bst_sender_data),
+            _ => Err(SEError::Generic(String::from(
+                "Unknown or expired deposit token request.",
+            ))),
+        }
+    }
+
+    /// Verify a token is signed by the token key
+    pub fn verify_token(&self, token: &BlindedSpendToken) -> Result<()> {
+        match BSTSenderData::from_key(self.token_key).verify_blind_spend_token(token.clone()) {
+            Ok(true) => Ok(()),
+            _ => Err(SEError::Generic(String::from("Invalid deposit token."))),
+        }
     }
 }
 
 /// StateChain Deposit protocol trait
 pub trait Deposit {
     /// API: Initiliase deposit protocol:
+    ///     - Check proof of work and deposit token if required
+    ///     - Check the deposit rate limits for client_ip
     ///     - Generate and return shared wallet ID
-    ///     - Can do auth or other DoS mitigation here
-    fn deposit_init(&self, deposit_msg1: DepositMsg1) -> Result<UserID>;
+    fn deposit_init(&self, deposit_msg1: DepositMsg1, client_ip: Option<IpAddr>) -> Result<UserID>;
+
+    /// API: Get a proof of work challenge to solve for deposit_init
+    fn deposit_challenge(&self, client_ip: Option<IpAddr>) -> Result<PowChallenge>;
+
+    /// API: Request a deposit token. Returns the token's blind signing data and the payment
+    /// required for it.
+    fn deposit_token_init(&self, client_ip: Option<IpAddr>) -> Result<DepositTokenInit>;
+
+    /// API: Blind sign a deposit token:
+    ///     - Check the payment tx has the required confirmations, pays the token fee to the fee
+    ///       address and has not been used
+    ///     - Sign the blinded token
+    fn deposit_token_sign(&self, deposit_token_msg: DepositTokenMsg) -> Result<BlindedSpendSignature>;
 
     /// API: Complete deposit protocol:
     ///     - Wait for confirmation of funding tx in blockchain
     ///     - Create StateChain DB object
     ///     - Update sparse merkle tree with new StateChain entry
     fn deposit_confirm(&self, deposit_msg2: DepositMsg2) -> Result<StatechainID>;
+
+    /// API: Complete several deposits funded by one transaction:
+    ///     - Ensure every back up tx is signed and spends a distinct output of the same funding tx
+    ///     - Wait for confirmation of funding tx in blockchain
+    ///     - Create a StateChain DB object for each deposit
+    ///     - Update sparse merkle tree with each new StateChain entry
+    fn deposit_confirm_batch(&self, deposit_batch_msg: DepositBatchMsg) -> Result<Vec<StatechainID>>;
 }
 
 impl Deposit for SCE {
-    fn deposit_init(&self, deposit_msg1: DepositMsg1) -> Result<UserID> {
+    fn deposit_init(&self, deposit_msg1: DepositMsg1, client_ip: Option<IpAddr>) -> Result<UserID> {
         // Generate shared wallet ID (user ID)
         let user_id = Uuid::new_v4();
 
-        // if Verification/PoW/authoriation failed {
-        //      warn!("Failed authorisation.")
-        //      Err(SEError::AuthError)
-        //  }
-
         // Check proof key is valid public key
         if let Err(_) = PublicKey::from_str(&deposit_msg1.proof_key) {
             return Err(SEError::Generic(String::from(
@@ -63,10 +288,23 @@ impl Deposit for SCE {
             )));
         };
 
+        // The lockbox only holds 2P-ECDSA key shares
+        if deposit_msg1.key_type == KeyType::Taproot && self.lockbox.active {
+            return Err(SEError::Generic(String::from(
+                "Taproot deposits are not supported by the lockbox.",
+            )));
+        }
+
+        self.check_deposit_pow(&deposit_msg1)?;
+        self.check_deposit_token_and_rate_limit(&deposit_msg1, client_ip)?;
+
         // Create DB entry for newly generated ID signalling that user has passed some
         // verification. For now use ID as 'password' to interact with state entity
         self.database
             .create_user_session(&user_id, &deposit_msg1.auth, &deposit_msg1.proof_key)?;
+        if deposit_msg1.key_type == KeyType::Taproot {
+            self.database.update_key_type(&user_id, &deposit_msg1.key_type)?;
+        }
 
         info!(
             "DEPOSIT: Protocol initiated. User ID generated: {}",
@@ -81,12 +319,253 @@ impl Deposit for SCE {
         Ok(UserID {id: user_id})
     }
 
+    fn deposit_challenge(&self, client_ip: Option<IpAddr>) -> Result<PowChallenge> {
+        let difficulty = self.config.deposit_pow_difficulty;
+        if difficulty == 0 {
+            return Ok(PowChallenge {
+                challenge: String::from(""),
+                difficulty,
+            });
+        }
+        Ok(PowChallenge {
+            challenge: self.deposit_gate.lock()?.new_challenge(
+                client_ip,
+                self.config.deposit_rate_limit,
+                self.config.deposit_rate_limit_ip,
+                now(),
+            )?,
+            difficulty,
+        })
+    }
+
+    fn deposit_token_init(&self, client_ip: Option<IpAddr>) -> Result<DepositTokenInit> {
+        if self.config.deposit_token_fee == 0 {
+            return Err(SEError::Generic(String::from(
+                "Deposit tokens are not required.",
+            )));
+        }
+        let (token_id, r_prime) = self.deposit_gate.lock()?.new_token_request(
+            client_ip,
+            self.config.deposit_rate_limit,
+            self.config.deposit_rate_limit_ip,
+            now(),
+        )?;
+        Ok(DepositTokenInit {
+            token_id,
+            r_prime,
+            fee: self.config.deposit_token_fee,
+            fee_address: self.config.fee_address.clone(),
+        })
+    }
+
+    fn deposit_token_sign(&self, deposit_token_msg: DepositTokenMsg) -> Result<BlindedSpendSignature> {
+        let fee = self.config.deposit_token_fee;
+        if fee == 0 {
+            return Err(SEError::Generic(String::from(
+                "Deposit tokens are not required.",
+            )));
+        }
+        let bst_sender_data = self
+            .deposit_gate
+            .lock()?
+            .take_token_request(&deposit_token_msg.token_id, now())?;
+
+        // Check payment
+        let txid = Txid::from_str(&deposit_token_msg.payment_txid)
+            .map_err(|e| SEError::Generic(format!("Invalid payment txid: {}", e)))?;
+        let fee_script = Address::from_str(&self.config.fee_address)
+            .map_err(|e| SEError::Generic(format!("Invalid fee address: {}", e)))?
+            .script_pubkey();
+        let payment_tx = {
+            let mut chain = self.chain.lock()?;
+            match chain.get_tx_status(&txid)? {
+                TxStatus::Confirmed(confirmations)
+                    if confirmations >= self.config.required_confirmation => {}
+                TxStatus::Unknown => {
+                    return Err(SEError::Generic(String::from(
+                        "Deposit token payment not found.",
+                    )))
+                }
+                _ => {
+                    return Err(SEError::Generic(String::from(
+                        "Deposit token payment insufficient confirmations.",
+                    )))
+                }
+            }
+            chain.get_transaction(&txid)?
+        };
+        let paid: u64 = payment_tx
+            .output
+            .iter()
+            .filter(|output| output.script_pubkey == fee_script)
+            .map(|output| output.value)
+            .sum();
+        if paid < fee {
+            return Err(SEError::Generic(format!(
+                "Deposit token payment of {} less than the fee of {}.",
+                paid, fee
+            )));
+        }
+        if !self.database.spend_token(&format!("payment:{}", txid))? {
+            return Err(SEError::Generic(String::from(
+                "Deposit token payment already used.",
+            )));
+        }
+
+        info!("DEPOSIT: Deposit token signed. Payment txid: {}", txid);
+        Ok(bst_sender_data.gen_blind_signature(deposit_token_msg.e_prime))
+    }
+
     fn deposit_confirm(&self, deposit_msg2: DepositMsg2) -> Result<StatechainID> {
         // let shared_key_id = deposit_msg2.shared_key_id.clone();
         let user_id = deposit_msg2.shared_key_id;
         self.check_user_auth(&user_id)?;
 
         // Get back up tx and proof key
+        let (tx_backup, proof_key) = self.get_signed_deposit_backup_tx(user_id)?;
+        let tx_kickoff = self.get_signed_deposit_kickoff_tx(user_id)?;
+        let funding_outpoint = deposit_funding_outpoint(&tx_backup, &tx_kickoff);
+
+        // Check that the funding transaction has the required number of confirmations
+        let confirmation = self.verify_tx_confirmed(&funding_outpoint.txid.to_string())?;
+
+        let statechain_id =
+            self.create_deposit_statechain(user_id, &tx_backup, &tx_kickoff, &proof_key)?;
+        self.database
+            .update_funding_confirmation(&statechain_id, &confirmation)?;
+
+        Ok(StatechainID {id: statechain_id})
+    }
+
+    fn deposit_confirm_batch(&self, deposit_batch_msg: DepositBatchMsg) -> Result<Vec<StatechainID>> {
+        let user_ids = deposit_batch_msg.shared_key_ids;
+        if user_ids.len() == 0 {
+            return Err(SEError::Generic(String::from(
+                "No Shared Key IDs in batch deposit.",
+            )));
+        }
+
+        // Every deposit must have a signed back up tx spending a distinct output of the same
+        // funding tx before any statechain is created
+        let mut deposits = vec![];
+        let mut funding_txid = None;
+        let mut funding_outpoints = HashSet::new();
+        for user_id in &user_ids {
+            self.check_user_auth(user_id)?;
+            let (tx_backup, proof_key) = self.get_signed_deposit_backup_tx(*user_id)?;
+            let tx_kickoff = self.get_signed_deposit_kickoff_tx(*user_id)?;
+            let funding_outpoint = deposit_funding_outpoint(&tx_backup, &tx_kickoff);
+            match funding_txid {
+                None => funding_txid = Some(funding_outpoint.txid),
+                Some(txid) => {
+                    if txid != funding_outpoint.txid {
+                        return Err(SEError::Generic(String::from(
+                            "Batch deposits must be funded by the same transaction.",
+                        )));
+                    }
+                }
+            }
+            if !funding_outpoints.insert(funding_outpoint) {
+                return Err(SEError::Generic(format!(
+                    "Funding output {} used by more than one deposit.",
+                    funding_outpoint
+                )));
+            }
+            deposits.push((*user_id, tx_backup, tx_kickoff, proof_key));
+        }
+
+        // Check that the funding transaction has the required number of confirmations
+        let confirmation = self.verify_tx_confirmed(&funding_txid.unwrap().to_string())?;
+
+        let mut statechain_ids = vec![];
+        for (user_id, tx_backup, tx_kickoff, proof_key) in deposits {
+            let statechain_id =
+                self.create_deposit_statechain(user_id, &tx_backup, &tx_kickoff, &proof_key)?;
+            self.database
+                .update_funding_confirmation(&statechain_id, &confirmation)?;
+            statechain_ids.push(StatechainID {id: statechain_id});
+        }
+
+        info!(
+            "DEPOSIT: Batch of {} deposits confirmed. User IDs: {:?}",
+            statechain_ids.len(),
+            user_ids
+        );
+
+        Ok(statechain_ids)
+    }
+}
+
+/// Funding tx outpoint of a deposit: the outpoint spent by its kick-off tx in relative locktime
+/// mode, otherwise the outpoint spent by its back up tx.
+fn deposit_funding_outpoint(tx_backup: &Transaction, tx_kickoff: &Option<Transaction>) -> OutPoint {
+    match tx_kickoff {
+        Some(tx_kickoff) => tx_kickoff.input[0].previous_output,
+        None => tx_backup.input[0].previous_output,
+    }
+}
+
+impl SCE {
+    /// Check the deposit request solves an issued challenge for its proof key, if proof of work
+    /// is required
+    fn check_deposit_pow(&self, deposit_msg1: &DepositMsg1) -> Result<()> {
+        let difficulty = self.config.deposit_pow_difficulty;
+        if difficulty == 0 {
+            return Ok(());
+        }
+        let solution = deposit_msg1.pow_solution.as_ref().ok_or(SEError::Generic(
+            String::from("Deposit proof of work required."),
+        ))?;
+        self.deposit_gate
+            .lock()?
+            .use_challenge(&solution.challenge, now())?;
+        if !pow::verify(
+            &solution.challenge,
+            &deposit_msg1.proof_key,
+            solution.nonce,
+            difficulty,
+        ) {
+            return Err(SEError::Generic(String::from(
+                "Invalid deposit proof of work.",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check the deposit request has a valid unspent token, if tokens are required, and is
+    /// within the rate limits for ip. Then spend the token and count the request. The gate is
+    /// locked throughout, so that only valid requests are counted and a rate limited request
+    /// keeps its token.
+    fn check_deposit_token_and_rate_limit(
+        &self,
+        deposit_msg1: &DepositMsg1,
+        ip: Option<IpAddr>,
+    ) -> Result<()> {
+        let (limit, limit_ip) = (
+            self.config.deposit_rate_limit,
+            self.config.deposit_rate_limit_ip,
+        );
+        let now = now();
+        let mut gate = self.deposit_gate.lock()?;
+        if self.config.deposit_token_fee == 0 {
+            return gate.check_rate_limit(ip, limit, limit_ip, now);
+        }
+        let token = deposit_msg1.token.as_ref().ok_or(SEError::Generic(String::from(
+            "Deposit token required.",
+        )))?;
+        gate.verify_token(token)?;
+        gate.deposit_requests.check(ip, limit, limit_ip, now)?;
+        if !self.database.spend_token(&format!("token:{}", token.get_msg()))? {
+            return Err(SEError::Generic(String::from(
+                "Deposit token already spent.",
+            )));
+        }
+        gate.deposit_requests.record(ip, limit, limit_ip, now);
+        Ok(())
+    }
+
+    /// Get back up tx and proof key of deposit, ensuring that the back up tx has been signed
+    fn get_signed_deposit_backup_tx(&self, user_id: Uuid) -> Result<(Transaction, String)> {
         let (tx_backup, proof_key) = self
             .database
             .get_backup_transaction_and_proof_key(user_id)?;
@@ -98,16 +577,45 @@ impl Deposit for SCE {
             )));
         }
 
-        // Check that the funding transaction has the required number of confirmations
-        self.verify_tx_confirmed(&tx_backup.input[0].previous_output.txid.to_string())?;
+        Ok((tx_backup, proof_key))
+    }
 
+    /// Get signed kick-off tx of deposit in relative locktime mode. Its back up tx was checked to
+    /// spend it in prepare_sign_tx. None if not in relative locktime mode.
+    fn get_signed_deposit_kickoff_tx(&self, user_id: Uuid) -> Result<Option<Transaction>> {
+        if !self.config.relative_locktime {
+            return Ok(None);
+        }
+
+        let tx_kickoff = self.database.get_user_kickoff_tx(user_id)?;
+        if tx_kickoff.input[0].witness.len() == 0 {
+            return Err(SEError::Generic(String::from(
+                "Signed Kick-off transaction not found.",
+            )));
+        }
+
+        Ok(Some(tx_kickoff))
+    }
+
+    /// Create StateChain for a confirmed deposit and add it to the sparse merkle tree
+    fn create_deposit_statechain(
+        &self,
+        user_id: Uuid,
+        tx_backup: &Transaction,
+        tx_kickoff: &Option<Transaction>,
+        proof_key: &String,
+    ) -> Result<Uuid> {
         // Create state chain DB object
         let statechain_id = Uuid::new_v4();
         let mut total = 0;
         for output in &tx_backup.output {
             total += output.value;
         }
-        let amount = (total + FEE) as i64;
+        // The kick-off tx pays a further FEE
+        let amount = match tx_kickoff {
+            Some(_) => (total + 2 * FEE) as i64,
+            None => (total + FEE) as i64,
+        };
         let state_chain = StateChain::new(proof_key.clone());
 
         // Insert into StateChain table
@@ -116,7 +624,17 @@ impl Deposit for SCE {
 
         // Insert into BackupTx table
         self.database
-            .create_backup_transaction(&statechain_id, &tx_backup)?;
+            .create_backup_transaction(&statechain_id, tx_backup)?;
+        self.database
+            .add_backup_tx_history(&statechain_id, tx_backup, proof_key)?;
+        if let Some(tx_kickoff) = tx_kickoff {
+            self.database
+                .create_kickoff_transaction(&statechain_id, tx_kickoff)?;
+        }
+        self.database.update_funding_outpoint(
+            &statechain_id,
+            &deposit_funding_outpoint(tx_backup, tx_kickoff),
+        )?;
 
         info!(
             "DEPOSIT: State Chain created. ID: {} For user ID: {}",
@@ -132,14 +650,8 @@ impl Deposit for SCE {
 
         // Update sparse merkle tree with new StateChain entry
         let (current_root, new_root) = self.update_smt(
-            &tx_backup
-                .input
-                .get(0)
-                .unwrap()
-                .previous_output
-                .txid
-                .to_string(),
-            &proof_key,
+            &smt_key(&deposit_funding_outpoint(tx_backup, tx_kickoff)),
+            proof_key,
         )?;
 
         info!(
@@ -151,15 +663,76 @@ impl Deposit for SCE {
             statechain_id, new_root, current_root
         );
 
-        Ok(StatechainID {id: statechain_id})
+        Ok(statechain_id)
+    }
+}
+
+/// IP address of the client, if known
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(ClientIp(request.client_ip()))
+    }
+}
+
+impl<'a, 'r> OpenApiFromRequest<'a, 'r> for ClientIp {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
     }
 }
 
 #[openapi]
 /// # Initiate a statechain deposit and generate a shared key ID
 #[post("/deposit/init", format = "json", data = "<deposit_msg1>")]
-pub fn deposit_init(sc_entity: State<SCE>, deposit_msg1: Json<DepositMsg1>) -> Result<Json<UserID>> {
-    match sc_entity.deposit_init(deposit_msg1.into_inner()) {
+pub fn deposit_init(
+    sc_entity: State<SCE>,
+    client_ip: ClientIp,
+    deposit_msg1: Json<DepositMsg1>,
+) -> Result<Json<UserID>> {
+    match sc_entity.deposit_init(deposit_msg1.into_inner(), client_ip.0) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get a proof of work challenge to solve to initiate a deposit
+#[get("/deposit/challenge", format = "json")]
+pub fn deposit_challenge(sc_entity: State<SCE>, client_ip: ClientIp) -> Result<Json<PowChallenge>> {
+    match sc_entity.deposit_challenge(client_ip.0) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Request a prepaid deposit token
+#[get("/deposit/token/init", format = "json")]
+pub fn deposit_token_init(
+    sc_entity: State<SCE>,
+    client_ip: ClientIp,
+) -> Result<Json<DepositTokenInit>> {
+    match sc_entity.deposit_token_init(client_ip.0) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Blind sign a deposit token once its payment has been made
+#[post("/deposit/token/sign", format = "json", data = "<deposit_token_msg>")]
+pub fn deposit_token_sign(
+    sc_entity: State<SCE>,
+    deposit_token_msg: Json<DepositTokenMsg>,
+) -> Result<Json<BlindedSpendSignature>> {
+    match sc_entity.deposit_token_sign(deposit_token_msg.into_inner()) {
         Ok(res) => return Ok(Json(res)),
         Err(e) => return Err(e),
     }
@@ -170,7 +743,7 @@ pub fn deposit_init(sc_entity: State<SCE>, deposit_msg1: Json<DepositMsg1>) -> R
 #[post("/deposit/confirm", format = "json", data = "<deposit_msg2>")]
 pub fn deposit_confirm(
     sc_entity: State<SCE>,
-    deposit_msg2: Json<DepositMsg2>,
+    deposit_msg2: SessionSigned<DepositMsg2>,
 ) -> Result<Json<StatechainID>> {
     match sc_entity.deposit_confirm(deposit_msg2.into_inner()) {
         Ok(res) => return Ok(Json(res)),
@@ -178,6 +751,19 @@ pub fn deposit_confirm(
     }
 }
 
+#[openapi]
+/// # Confirm several deposits funded by one transaction and retreive their statechain IDs
+#[post("/deposit/confirm-batch", format = "json", data = "<deposit_batch_msg>")]
+pub fn deposit_confirm_batch(
+    sc_entity: State<SCE>,
+    deposit_batch_msg: SessionSigned<DepositBatchMsg>,
+) -> Result<Json<Vec<StatechainID>>> {
+    match sc_entity.deposit_confirm_batch(deposit_batch_msg.into_inner()) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -185,22 +771,36 @@ pub mod tests {
         mocks,
         tests::{test_sc_entity, BACKUP_TX_NOT_SIGNED, BACKUP_TX_SIGNED},
     };
-    use bitcoin::Transaction;
+    use crate::chain::{memory::MemoryChain, ChainBackend};
+    use crate::server::Lockbox;
+    use bitcoin::{Transaction, TxOut};
+    use mockall::predicate;
+    use shared_lib::blinded_token::BSTRequestorData;
     use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn test_deposit_init() {
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
         db.expect_create_user_session().returning(|_, _, _| Ok(()));
+        db.expect_update_key_type()
+            .with(predicate::always(), predicate::eq(KeyType::Taproot))
+            .times(1)
+            .returning(|_, _| Ok(()));
 
-        let sc_entity = test_sc_entity(db);
+        let mut sc_entity = test_sc_entity(db);
+        // Only valid requests are counted against the rate limit
+        sc_entity.config.deposit_rate_limit = 2;
 
         // Invalid proof key
         match sc_entity.deposit_init(DepositMsg1 {
             auth: String::from("auth"),
             proof_key: String::from(""),
-        }) {
+            pow_solution: None,
+            token: None,
+            key_type: KeyType::Ecdsa,
+        }, None) {
             Ok(_) => assert!(false, "Expected failure."),
             Err(e) => assert!(e.to_string().contains("Proof key not in correct format.")),
         }
@@ -210,7 +810,10 @@ pub mod tests {
             proof_key: String::from(
                 "65aab40995d3ed5d03a0567b04819ff12641b84c17f5e9d5dd075571e18346",
             ),
-        }) {
+            pow_solution: None,
+            token: None,
+            key_type: KeyType::Ecdsa,
+        }, None) {
             Ok(_) => assert!(false, "Expected failure."),
             Err(e) => assert!(e.to_string().contains("Proof key not in correct format.")),
         }
@@ -220,9 +823,235 @@ pub mod tests {
                 auth: String::from("auth"),
                 proof_key: String::from(
                     "026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e"
-                )
+                ),
+                pow_solution: None,
+                token: None,
+                key_type: KeyType::Ecdsa,
+            }, None)
+            .is_ok());
+
+        // Taproot deposit stores the key type of the session
+        let taproot_msg1 = || DepositMsg1 {
+            auth: String::from("auth"),
+            proof_key: String::from(
+                "026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e",
+            ),
+            pow_solution: None,
+            token: None,
+            key_type: KeyType::Taproot,
+        };
+        assert!(sc_entity.deposit_init(taproot_msg1(), None).is_ok());
+
+        // The lockbox only holds 2P-ECDSA key shares
+        sc_entity.lockbox = Lockbox::new(&mockito::server_url(), 10, 0);
+        match sc_entity.deposit_init(taproot_msg1(), None) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("not supported by the lockbox")),
+        }
+
+        let mut ecdsa_msg1 = taproot_msg1();
+        ecdsa_msg1.key_type = KeyType::Ecdsa;
+        match sc_entity.deposit_init(ecdsa_msg1, None) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Deposit rate limit exceeded.")),
+        }
+    }
+
+    #[test]
+    fn test_deposit_gate() {
+        let mut gate = DepositGate::new("").unwrap();
+        let ip_1 = Some(IpAddr::from_str("127.0.0.1").unwrap());
+        let ip_2 = Some(IpAddr::from_str("127.0.0.2").unwrap());
+
+        // No limits
+        for _ in 0..10 {
+            assert!(gate.check_rate_limit(ip_1, 0, 0, 1000).is_ok());
+        }
+        // Per IP limit
+        assert!(gate.check_rate_limit(ip_1, 3, 2, 1000).is_ok());
+        assert!(gate.check_rate_limit(ip_1, 3, 2, 1000).is_ok());
+        match gate.check_rate_limit(ip_1, 3, 2, 1000) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Deposit rate limit exceeded.")),
+        }
+        // Global limit
+        assert!(gate.check_rate_limit(ip_2, 3, 2, 1000).is_ok());
+        assert!(gate.check_rate_limit(None, 3, 2, 1000).is_err());
+        // Limits reset after the window
+        assert!(gate.check_rate_limit(ip_1, 3, 2, 1000 + RATE_LIMIT_WINDOW).is_ok());
+
+        // Challenges can be used once before they expire
+        let challenge = gate.new_challenge(None, 0, 0, 1000).unwrap();
+        assert!(gate.use_challenge(&challenge, 1001).is_ok());
+        assert!(gate.use_challenge(&challenge, 1001).is_err());
+        let challenge = gate.new_challenge(None, 0, 0, 1000).unwrap();
+        assert!(gate.use_challenge(&challenge, 1000 + REQUEST_LIFETIME).is_err());
+        // Challenge requests are rate limited separately from deposits
+        assert!(gate.new_challenge(ip_1, 3, 1, 2000).is_ok());
+        assert!(gate.new_challenge(ip_1, 3, 1, 2000).is_err());
+        assert!(gate.new_challenge(ip_2, 3, 1, 2000).is_ok());
+        assert!(gate.check_rate_limit(ip_1, 3, 1, 2000).is_ok());
+        // Unused challenges are capped
+        while gate.challenges.len() < MAX_PENDING_REQUESTS {
+            gate.new_challenge(None, 0, 0, 2000).unwrap();
+        }
+        match gate.new_challenge(None, 0, 0, 2000) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Too many pending deposit challenges.")),
+        }
+        assert!(gate.new_challenge(None, 0, 0, 2000 + REQUEST_LIFETIME).is_ok());
+
+        // Tokens are signed once per request and verified with the token key
+        let (token_id, r_prime) = gate.new_token_request(None, 0, 0, 1000).unwrap();
+        let requestor = BSTRequestorData::setup(r_prime, &Uuid::new_v4().to_string()).unwrap();
+        let sender_data = gate.take_token_request(&token_id, 1001).unwrap();
+        assert!(gate.take_token_request(&token_id, 1001).is_err());
+        let signature = sender_data.gen_blind_signature(requestor.get_e_prime());
+        let token = requestor.make_blind_spend_token(requestor.unblind_signature(signature));
+        assert!(gate.verify_token(&token).is_ok());
+        assert!(DepositGate::new("").unwrap().verify_token(&token).is_err());
+        assert!(DepositGate::new("not hex").is_err());
+    }
+
+    #[test]
+    fn test_deposit_init_pow_token() {
+        let proof_key =
+            String::from("026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e");
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_create_user_session().returning(|_, _, _| Ok(()));
+        // Payments and tokens can be spent once
+        let spent = Arc::new(Mutex::new(HashSet::new()));
+        db.expect_spend_token()
+            .returning(move |token| Ok(spent.lock().unwrap().insert(token.clone())));
+
+        let mut sc_entity = test_sc_entity(db);
+        sc_entity.config.deposit_pow_difficulty = 8;
+        sc_entity.config.deposit_token_fee = 1000;
+        sc_entity.config.required_confirmation = 3;
+        let chain = MemoryChain::new();
+        *sc_entity.chain.lock().unwrap() = Box::new(chain.clone());
+
+        let deposit_msg1 = |pow_solution, token| DepositMsg1 {
+            auth: String::from("auth"),
+            proof_key: proof_key.clone(),
+            pow_solution,
+            token,
+            key_type: KeyType::Ecdsa,
+        };
+
+        // Proof of work
+        match sc_entity.deposit_init(deposit_msg1(None, None), None) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Deposit proof of work required.")),
+        }
+        let challenge = sc_entity.deposit_challenge(None).unwrap();
+        assert_eq!(challenge.difficulty, 8);
+        let solution = PowSolution {
+            nonce: pow::solve(&challenge.challenge, &proof_key, challenge.difficulty),
+            challenge: challenge.challenge,
+        };
+        match sc_entity.deposit_init(deposit_msg1(Some(solution.clone()), None), None) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Deposit token required.")),
+        }
+        // Challenge already used
+        match sc_entity.deposit_init(deposit_msg1(Some(solution), None), None) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Unknown or expired deposit challenge.")),
+        }
+        sc_entity.config.deposit_pow_difficulty = 0;
+
+        // Token paid for with a tx to the fee address
+        let token_init = sc_entity.deposit_token_init(None).unwrap();
+        assert_eq!(token_init.fee, 1000);
+        let requestor =
+            BSTRequestorData::setup(token_init.r_prime, &Uuid::new_v4().to_string()).unwrap();
+        let payment_tx = |value| Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value,
+                script_pubkey: Address::from_str(&token_init.fee_address)
+                    .unwrap()
+                    .script_pubkey(),
+            }],
+        };
+        let token_msg = |payment_tx: &Transaction| DepositTokenMsg {
+            token_id: token_init.token_id,
+            payment_txid: payment_tx.txid().to_string(),
+            e_prime: requestor.get_e_prime(),
+        };
+        let underpaid = payment_tx(999);
+        chain.clone().broadcast_transaction(&underpaid).unwrap();
+        for _ in 0..3 {
+            chain.mine_block();
+        }
+        match sc_entity.deposit_token_sign(token_msg(&underpaid)) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("less than the fee")),
+        }
+        // Request is used up by the failed attempt
+        let paid = payment_tx(1000);
+        chain.clone().broadcast_transaction(&paid).unwrap();
+        for _ in 0..3 {
+            chain.mine_block();
+        }
+        match sc_entity.deposit_token_sign(token_msg(&paid)) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Unknown or expired deposit token request.")),
+        }
+
+        let token_init = sc_entity.deposit_token_init(None).unwrap();
+        let requestor =
+            BSTRequestorData::setup(token_init.r_prime, &Uuid::new_v4().to_string()).unwrap();
+        let signature = sc_entity
+            .deposit_token_sign(DepositTokenMsg {
+                token_id: token_init.token_id,
+                payment_txid: paid.txid().to_string(),
+                e_prime: requestor.get_e_prime(),
             })
+            .unwrap();
+        let token = requestor.make_blind_spend_token(requestor.unblind_signature(signature));
+
+        // Payment can only be used once
+        let token_init_2 = sc_entity.deposit_token_init(None).unwrap();
+        match sc_entity.deposit_token_sign(DepositTokenMsg {
+            token_id: token_init_2.token_id,
+            payment_txid: paid.txid().to_string(),
+            e_prime: requestor.get_e_prime(),
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Deposit token payment already used.")),
+        }
+
+        // Payment must have the required confirmations
+        let unconfirmed = payment_tx(1001);
+        chain.clone().broadcast_transaction(&unconfirmed).unwrap();
+        chain.mine_block();
+        let token_init_3 = sc_entity.deposit_token_init(None).unwrap();
+        match sc_entity.deposit_token_sign(DepositTokenMsg {
+            token_id: token_init_3.token_id,
+            payment_txid: unconfirmed.txid().to_string(),
+            e_prime: requestor.get_e_prime(),
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("insufficient confirmations")),
+        }
+
+        assert!(sc_entity
+            .deposit_init(deposit_msg1(None, Some(token.clone())), None)
             .is_ok());
+        match sc_entity.deposit_init(deposit_msg1(None, Some(token)), None) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Deposit token already spent.")),
+        }
+        let invalid_token = Some(BlindedSpendToken::new_random());
+        match sc_entity.deposit_init(deposit_msg1(None, invalid_token), None) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Invalid deposit token.")),
+        }
     }
 
     #[test]
@@ -249,6 +1078,12 @@ pub mod tests {
         db.expect_create_statechain().returning(|_, _, _, _| Ok(()));
         db.expect_create_backup_transaction()
             .returning(|_, _| Ok(()));
+        db.expect_add_backup_tx_history()
+            .returning(|_, _, _| Ok(()));
+        db.expect_update_funding_outpoint()
+            .returning(|_, _| Ok(()));
+        db.expect_update_funding_confirmation()
+            .returning(|_, _| Ok(()));
         db.expect_update_statechain_id().returning(|_, _| Ok(()));
 
         let sc_entity = test_sc_entity(db);
@@ -271,4 +1106,81 @@ pub mod tests {
             })
             .is_ok());
     }
+
+    #[test]
+    fn test_deposit_confirm_batch() {
+        let user_ids = vec![Uuid::new_v4(), Uuid::new_v4()];
+        let proof_key =
+            String::from("026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e");
+        // Second deposit spends the next output of the same funding tx
+        let tx_backup_0 = serde_json::from_str::<Transaction>(&BACKUP_TX_SIGNED).unwrap();
+        let mut tx_backup_1 = tx_backup_0.clone();
+        tx_backup_1.input[0].previous_output.vout = 1;
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth().returning(move |id| Ok(id));
+        db.expect_root_get_current_id().returning(|| Ok(1 as i64));
+        db.expect_get_root().returning(|_| Ok(None));
+        db.expect_root_update().returning(|_| Ok(1));
+        let (user_0, tx_0, pk_0) = (user_ids[0], tx_backup_0.clone(), proof_key.clone());
+        db.expect_get_backup_transaction_and_proof_key()
+            .with(predicate::eq(user_0))
+            .returning(move |_| Ok((tx_0.clone(), pk_0.clone())));
+        // First return back up tx spending the same output, then a distinct output
+        let (user_1, tx_1, pk_1) = (user_ids[1], tx_backup_0.clone(), proof_key.clone());
+        db.expect_get_backup_transaction_and_proof_key()
+            .with(predicate::eq(user_1))
+            .times(1)
+            .returning(move |_| Ok((tx_1.clone(), pk_1.clone())));
+        let (tx_1, pk_1) = (tx_backup_1.clone(), proof_key.clone());
+        db.expect_get_backup_transaction_and_proof_key()
+            .with(predicate::eq(user_1))
+            .returning(move |_| Ok((tx_1.clone(), pk_1.clone())));
+        db.expect_create_statechain()
+            .times(2)
+            .returning(|_, _, _, _| Ok(()));
+        db.expect_create_backup_transaction()
+            .times(2)
+            .returning(|_, _| Ok(()));
+        db.expect_add_backup_tx_history()
+            .times(2)
+            .returning(|_, _, _| Ok(()));
+        db.expect_update_funding_outpoint()
+            .times(2)
+            .returning(|_, _| Ok(()));
+        db.expect_update_funding_confirmation()
+            .times(2)
+            .returning(|_, _| Ok(()));
+        db.expect_update_statechain_id()
+            .times(2)
+            .returning(|_, _| Ok(()));
+
+        let sc_entity = test_sc_entity(db);
+
+        match sc_entity.deposit_confirm_batch(DepositBatchMsg {
+            shared_key_ids: vec![],
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("No Shared Key IDs in batch deposit.")),
+        }
+
+        // Both deposits spend the same funding output
+        match sc_entity.deposit_confirm_batch(DepositBatchMsg {
+            shared_key_ids: user_ids.clone(),
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("used by more than one deposit.")),
+        }
+
+        // Clean protocol run
+        let _m = mocks::ms::post_commitment().create(); //Mainstay post commitment mock
+        let statechain_ids = sc_entity
+            .deposit_confirm_batch(DepositBatchMsg {
+                shared_key_ids: user_ids.clone(),
+            })
+            .unwrap();
+        assert_eq!(statechain_ids.len(), 2);
+        assert_ne!(statechain_ids[0].id, statechain_ids[1].id);
+    }
 }
@@ -9,7 +9,7 @@ use shared_lib::{
 };
 use super::requests::post_lb;
 
-use bitcoin::{hashes::sha256d, secp256k1::Signature, Transaction};
+use bitcoin::{hashes::sha256d, secp256k1::Signature};
 use cfg_if::cfg_if;
 use curv::{
     arithmetic::traits::Converter,
@@ -20,6 +20,7 @@ pub use kms::ecdsa::two_party::*;
 pub use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::*;
 use rocket::State;
 use rocket_contrib::json::Json;
+use super::auth::SessionSigned;
 use std::string::ToString;
 use uuid::Uuid;
 use rocket_okapi::openapi;
@@ -30,8 +31,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
     }
 }
 
@@ -73,7 +74,7 @@ impl Ecdsa for SCE {
         // call lockbox
         if self.lockbox.active {
             let path: &str = "ecdsa/keygen/first";
-            let (_id, key_gen_first_msg): (Uuid, party_one::KeyGenFirstMsg) = post_lb(&self.lockbox, path, &key_gen_msg1)?;
+            let (_id, key_gen_first_msg): (Uuid, party_one::KeyGenFirstMsg) = post_lb(&self.lockbox, &user_id, path, &key_gen_msg1)?;
             kg_first_msg = key_gen_first_msg;
         }
         else {
@@ -120,7 +121,7 @@ impl Ecdsa for SCE {
         // call lockbox
         if self.lockbox.active {
             let path: &str = "ecdsa/keygen/second";
-            let kg_party_one_second_message: party1::KeyGenParty1Message2 = post_lb(&self.lockbox, path, &key_gen_msg2)?;
+            let kg_party_one_second_message: party1::KeyGenParty1Message2 = post_lb(&self.lockbox, &key_gen_msg2.shared_key_id, path, &key_gen_msg2)?;
             kg_party_one_second_msg = kg_party_one_second_message;
         }
         else {
@@ -170,7 +171,7 @@ impl Ecdsa for SCE {
 
         if self.lockbox.active {
             let path: &str = "ecdsa/sign/first";
-            let sign_party_one_first_message: party_one::EphKeyGenFirstMsg = post_lb(&self.lockbox, path, &sign_msg1)?;
+            let sign_party_one_first_message: party_one::EphKeyGenFirstMsg = post_lb(&self.lockbox, &user_id, path, &sign_msg1)?;
             sign_party_one_first_msg = sign_party_one_first_message;
         }
         else {
@@ -222,11 +223,11 @@ impl Ecdsa for SCE {
             )));
         }
 
-        let mut ws: Vec<Vec<u8>>;
+        let ws: Vec<Vec<u8>>;
 
         if self.lockbox.active {
             let path: &str = "ecdsa/sign/second";
-            let witness: Vec<Vec<u8>> = post_lb(&self.lockbox, path, &sign_msg2)?;
+            let witness: Vec<Vec<u8>> = post_lb(&self.lockbox, &user_id, path, &sign_msg2)?;
             ws = witness;
         }
         else {
@@ -272,35 +273,7 @@ impl Ecdsa for SCE {
             ws = witness;
         }
 
-        // Get transaction which is being signed.
-        let mut tx: Transaction = match sign_msg2.sign_second_msg_request.protocol {
-            Protocol::Withdraw => db.get_tx_withdraw(user_id)?,
-            _ => db.get_user_backup_tx(user_id)?,
-        };
-
-        // Add signature to tx
-        tx.input[0].witness = ws.clone();
-
-        match sign_msg2.sign_second_msg_request.protocol {
-            Protocol::Withdraw => {
-                // Store signed withdraw tx in UserSession DB object
-                db.update_tx_withdraw(user_id, tx)?;
-
-                info!("WITHDRAW: Tx signed and stored. User ID: {}", user_id);
-                // Do not return withdraw tx witness until /withdraw/confirm is complete
-                ws = vec![];
-            }
-            _ => {
-                // Store signed backup tx in UserSession DB object
-                db.update_user_backup_tx(&user_id, tx)?;
-                info!(
-                    "DEPOSIT/TRANSFER: Backup Tx signed and stored. User: {}",
-                    user_id
-                );
-            }
-        };
-
-        Ok(ws)
+        self.store_signed_tx(&user_id, &sign_msg2.sign_second_msg_request.protocol, ws)
     }
 }
 
@@ -309,7 +282,7 @@ impl Ecdsa for SCE {
 #[post("/ecdsa/keygen/first", format = "json", data = "<key_gen_msg1>")]
 pub fn first_message(
     sc_entity: State<SCE>,
-    key_gen_msg1: Json<KeyGenMsg1>,
+    key_gen_msg1: SessionSigned<KeyGenMsg1>,
 ) -> Result<Json<KeyGenReply1>> {
     match sc_entity.first_message(key_gen_msg1.into_inner()) {
         Ok(res) => return Ok(Json(res)),
@@ -322,7 +295,7 @@ pub fn first_message(
 #[post("/ecdsa/keygen/second", format = "json", data = "<key_gen_msg2>")]
 pub fn second_message(
     sc_entity: State<SCE>,
-    key_gen_msg2: Json<KeyGenMsg2>,
+    key_gen_msg2: SessionSigned<KeyGenMsg2>,
 ) -> Result<Json<KeyGenReply2>> {
     match sc_entity.second_message(key_gen_msg2.into_inner()) {
         Ok(res) => return Ok(Json(res)),
@@ -335,7 +308,7 @@ pub fn second_message(
 #[post("/ecdsa/sign/first", format = "json", data = "<sign_msg1>")]
 pub fn sign_first(
     sc_entity: State<SCE>,
-    sign_msg1: Json<SignMsg1>,
+    sign_msg1: SessionSigned<SignMsg1>,
 ) -> Result<Json<SignReply1>> {
     match sc_entity.sign_first(sign_msg1.into_inner()) {
         Ok(res) => return Ok(Json(res)),
@@ -346,7 +319,7 @@ pub fn sign_first(
 #[openapi]
 /// # Second round of the 2P-ECDSA signing protocol: signature generation and verification
 #[post("/ecdsa/sign/second", format = "json", data = "<sign_msg2>")]
-pub fn sign_second(sc_entity: State<SCE>, sign_msg2: Json<SignMsg2>) -> Result<Json<Vec<Vec<u8>>>> {
+pub fn sign_second(sc_entity: State<SCE>, sign_msg2: SessionSigned<SignMsg2>) -> Result<Json<Vec<Vec<u8>>>> {
     match sc_entity.sign_second(sign_msg2.into_inner()) {
         Ok(res) => return Ok(Json(res)),
         Err(e) => return Err(e),
@@ -357,6 +330,7 @@ pub fn sign_second(sc_entity: State<SCE>, sign_msg2: Json<SignMsg2>) -> Result<J
 pub mod tests {
     use super::*;
     use crate::protocol::util::tests::test_sc_entity;
+    use crate::server::Lockbox;
     use shared_lib::structs::SignSecondMsgRequest;
     use crate::protocol::util::tests::BACKUP_TX_NOT_SIGNED;
     use bitcoin::Transaction;
@@ -379,8 +353,7 @@ pub mod tests {
 
         let mut sc_entity = test_sc_entity(db);
 
-        sc_entity.lockbox.active = true;
-        sc_entity.lockbox.endpoint = mockito::server_url();
+        sc_entity.lockbox = Lockbox::new(&mockito::server_url(), 10, 0);
 
         let kg_first_msg = party_one::KeyGenFirstMsg { pk_commitment: BigInt::from(0), zk_pok_commitment: BigInt::from(1) };
 
@@ -459,8 +432,7 @@ pub mod tests {
 
         let mut sc_entity = test_sc_entity(db);
 
-        sc_entity.lockbox.active = true;
-        sc_entity.lockbox.endpoint = mockito::server_url();
+        sc_entity.lockbox = Lockbox::new(&mockito::server_url(), 10, 0);
 
         let (eph_key_gen_first_message_party_two, _, _) =
             MasterKey2::sign_first_message();
@@ -0,0 +1,344 @@
+//! Events
+//!
+//! Push swap and transfer events to subscribed clients over a WebSocket, so that clients do not
+//! have to poll the conductor and transfer APIs. A client opens a connection to events_port and
+//! sends an EventSubscribeMsg signed by its proof keys. Once the subscription is authorised, events
+//! published to its topics are pushed to the client until it disconnects.
+//! The number of concurrent connections is capped by events_max_connections.
+
+pub use super::super::Result;
+use crate::error::SEError;
+use crate::server::StateChainEntity;
+use crate::Database;
+use monotree::database::Database as MonotreeDatabase;
+use shared_lib::events::{EventSubscribeMsg, StateEntityEvent};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::ops::Deref;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tungstenite::{Message, WebSocket};
+use uuid::Uuid;
+
+/// Interval (seconds) of pings sent to idle subscribers. Closed connections are dropped when a
+/// ping fails.
+const PING_INTERVAL: u64 = 30;
+/// Time (seconds) allowed for a new connection to send its subscription
+const SUBSCRIBE_TIMEOUT: u64 = 10;
+/// Time (seconds) a subscriber waits for an event before checking its connection for a close
+const EVENT_WAIT: u64 = 1;
+/// Time (milliseconds) a subscriber waits for a message from the client
+const READ_TIMEOUT: u64 = 10;
+
+type Subscribers = HashMap<String, Vec<(Uuid, Sender<StateEntityEvent>)>>;
+
+/// Event channels of subscribers by topic. Topics are statechain, swap and batch IDs and proof
+/// keys.
+#[derive(Debug, Default)]
+pub struct EventBus {
+    subscribers: Mutex<Subscribers>,
+}
+
+/// Receiver of the events of a subscription. The subscription is removed when it is dropped.
+pub struct Subscription<'a> {
+    bus: &'a EventBus,
+    id: Uuid,
+    topics: Vec<String>,
+    receiver: Receiver<StateEntityEvent>,
+}
+
+impl Deref for Subscription<'_> {
+    type Target = Receiver<StateEntityEvent>;
+
+    fn deref(&self) -> &Receiver<StateEntityEvent> {
+        &self.receiver
+    }
+}
+
+impl Drop for Subscription<'_> {
+    fn drop(&mut self) {
+        self.bus.unsubscribe(&self.id, &self.topics);
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Publishing must not fail a protocol request, so the subscriber map is used even if a
+    // thread panicked while holding the lock
+    fn subscribers(&self) -> MutexGuard<Subscribers> {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Receive events published to any of topics
+    pub fn subscribe(&self, topics: &[String]) -> Subscription {
+        let (sender, receiver) = channel();
+        let id = Uuid::new_v4();
+        let mut subscribers = self.subscribers();
+        for topic in topics {
+            subscribers
+                .entry(topic.to_owned())
+                .or_insert(Vec::new())
+                .push((id, sender.clone()));
+        }
+        Subscription {
+            bus: self,
+            id,
+            topics: topics.to_vec(),
+            receiver,
+        }
+    }
+
+    // Remove the subscription id from topics
+    fn unsubscribe(&self, id: &Uuid, topics: &[String]) {
+        let mut subscribers = self.subscribers();
+        for topic in topics {
+            if let Some(senders) = subscribers.get_mut(topic) {
+                senders.retain(|(sender_id, _)| sender_id != id);
+                if senders.is_empty() {
+                    subscribers.remove(topic);
+                }
+            }
+        }
+    }
+
+    /// Send event to the subscribers of topic. Subscriptions whose receiver has been dropped are
+    /// removed.
+    pub fn publish(&self, topic: &str, event: StateEntityEvent) {
+        let mut subscribers = self.subscribers();
+        if let Some(senders) = subscribers.get_mut(topic) {
+            senders.retain(|(_, sender)| sender.send(event.clone()).is_ok());
+            if senders.is_empty() {
+                subscribers.remove(topic);
+            }
+        }
+    }
+
+    /// Number of subscriptions to topic
+    pub fn subscriber_count(&self, topic: &str) -> usize {
+        self.subscribers().get(topic).map_or(0, |senders| senders.len())
+    }
+}
+
+fn ws_error(e: tungstenite::Error) -> SEError {
+    SEError::Generic(format!("Event stream error: {}", e))
+}
+
+fn send_event(socket: &mut WebSocket<TcpStream>, event: &StateEntityEvent) -> Result<()> {
+    let text = serde_json::to_string(event).map_err(|e| SEError::Generic(e.to_string()))?;
+    socket.write_message(Message::Text(text)).map_err(ws_error)
+}
+
+impl<T: Database + Send + Sync + 'static, D: MonotreeDatabase + Send + Sync + 'static>
+    StateChainEntity<T, D>
+{
+    /// Check a subscription is signed by each of its proof keys, is not a replay and that each
+    /// of its IDs is a statechain owned by one of the keys, or a swap or batch including one.
+    /// Returns the topics subscribed to.
+    pub fn authorise_subscription(&self, msg: &EventSubscribeMsg) -> Result<Vec<String>> {
+        if let Err(e) = msg.verify() {
+            warn!("EVENTS: Invalid subscription signature: {}", e);
+            return Err(SEError::AuthError);
+        }
+        if let Err(e) = self.request_nonces.lock()?.check(&msg.auth()) {
+            warn!("EVENTS: {}", e);
+            return Err(SEError::AuthError);
+        }
+        let proof_keys = &msg.subscription.proof_keys;
+        let mut topics = proof_keys.clone();
+        for id in &msg.subscription.ids {
+            if !self.is_subscription_owner(id, proof_keys)? {
+                warn!("EVENTS: Subscription to {} not signed by an owner", id);
+                return Err(SEError::AuthError);
+            }
+            topics.push(id.to_string());
+        }
+        Ok(topics)
+    }
+
+    // Whether id is a statechain owned by one of proof_keys or a swap or batch including one
+    fn is_subscription_owner(&self, id: &Uuid, proof_keys: &[String]) -> Result<bool> {
+        let is_owner = |statechain_id: &Uuid| match self.database.get_statechain(*statechain_id) {
+            Ok(state_chain) => match state_chain.get_tip() {
+                Ok(tip) => proof_keys.contains(&tip.data),
+                Err(_) => false,
+            },
+            Err(_) => false,
+        };
+        if is_owner(id) {
+            return Ok(true);
+        }
+        let swap_statechain_ids = self
+            .scheduler
+            .lock()?
+            .get_swap_info(id)
+            .map(|swap_info| swap_info.swap_token.statechain_ids);
+        if let Some(statechain_ids) = swap_statechain_ids {
+            return Ok(statechain_ids.iter().any(is_owner));
+        }
+        match self.database.get_transfer_batch_data(*id) {
+            Ok(tbd) => Ok(tbd.state_chains.iter().any(is_owner)),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Serve an event stream connection: read and authorise the subscription, then push events
+    /// until the client disconnects. The subscription is removed on return.
+    pub fn serve_event_stream(&self, stream: TcpStream) -> Result<()> {
+        let timeout = Some(Duration::from_secs(SUBSCRIBE_TIMEOUT));
+        if let Err(e) = stream
+            .set_read_timeout(timeout)
+            .and_then(|_| stream.set_write_timeout(timeout))
+        {
+            return Err(SEError::Generic(e.to_string()));
+        }
+        let mut socket = tungstenite::accept(stream)
+            .map_err(|e| SEError::Generic(format!("Event stream handshake failed: {}", e)))?;
+
+        let msg: EventSubscribeMsg = match socket.read_message().map_err(ws_error)? {
+            Message::Text(text) => {
+                serde_json::from_str(&text).map_err(|e| SEError::Generic(e.to_string()))?
+            }
+            _ => {
+                return Err(SEError::Generic(String::from(
+                    "Expected event subscription",
+                )))
+            }
+        };
+        let topics = match self.authorise_subscription(&msg) {
+            Ok(topics) => topics,
+            Err(e) => {
+                let _ = socket.close(None);
+                return Err(e);
+            }
+        };
+
+        // Subscribe before acknowledging so that no event after the acknowledgement is missed
+        let events = self.events.subscribe(&topics);
+        info!("EVENTS: Subscribed to {:?}", topics);
+        send_event(&mut socket, &StateEntityEvent::Subscribed { topics })?;
+
+        // The socket is read between events to detect closes and answer pings
+        if let Err(e) = socket
+            .get_mut()
+            .set_read_timeout(Some(Duration::from_millis(READ_TIMEOUT)))
+        {
+            return Err(SEError::Generic(e.to_string()));
+        }
+        let mut last_ping = Instant::now();
+        loop {
+            match events.recv_timeout(Duration::from_secs(EVENT_WAIT)) {
+                Ok(event) => send_event(&mut socket, &event)?,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            match socket.read_message() {
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(ref e))
+                    if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                Err(tungstenite::Error::ConnectionClosed)
+                | Err(tungstenite::Error::AlreadyClosed) => return Ok(()),
+                Err(e) => return Err(ws_error(e)),
+            }
+            if last_ping.elapsed() >= Duration::from_secs(PING_INTERVAL) {
+                socket
+                    .write_message(Message::Ping(Vec::new()))
+                    .map_err(ws_error)?;
+                last_ping = Instant::now();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use crate::MockDatabase;
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    use mockall::predicate;
+    use shared_lib::state_chain::StateChain;
+    use shared_lib::swap_data::SwapStatus;
+
+    #[test]
+    fn test_event_bus() {
+        let bus = EventBus::new();
+        let id = Uuid::new_v4().to_string();
+        let other_id = Uuid::new_v4().to_string();
+        let event = StateEntityEvent::SwapStatus {
+            swap_id: Uuid::new_v4(),
+            status: Some(SwapStatus::Phase2),
+        };
+
+        let receiver = bus.subscribe(&[id.clone(), other_id.clone()]);
+        let other_receiver = bus.subscribe(&[other_id.clone()]);
+        bus.publish(&id, event.clone());
+        assert_eq!(receiver.try_recv().unwrap(), event);
+        assert!(other_receiver.try_recv().is_err());
+        bus.publish(&other_id, event.clone());
+        assert_eq!(receiver.try_recv().unwrap(), event);
+        assert_eq!(other_receiver.try_recv().unwrap(), event);
+
+        // Dropped subscriptions are removed
+        assert_eq!(bus.subscriber_count(&other_id), 2);
+        drop(receiver);
+        assert_eq!(bus.subscriber_count(&other_id), 1);
+        assert_eq!(bus.subscriber_count(&id), 0);
+        bus.publish(&other_id, event.clone());
+        assert_eq!(other_receiver.try_recv().unwrap(), event);
+    }
+
+    #[test]
+    fn test_authorise_subscription() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let other_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&secp, &key).to_string();
+        let statechain_id = Uuid::new_v4();
+        let other_statechain_id = Uuid::new_v4();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_statechain()
+            .with(predicate::eq(statechain_id))
+            .returning(move |_| Ok(StateChain::new(proof_key.clone())));
+        db.expect_get_statechain()
+            .with(predicate::ne(statechain_id))
+            .returning(|_| Ok(StateChain::new(String::from("other proof key"))));
+        db.expect_get_transfer_batch_data()
+            .returning(|_| Err(SEError::Generic(String::from("not found"))));
+        let sc_entity = test_sc_entity(db);
+
+        // Owned statechain and the proof key are subscribed to
+        let msg = EventSubscribeMsg::new(&[key], &[statechain_id]).unwrap();
+        let topics = sc_entity.authorise_subscription(&msg).unwrap();
+        assert_eq!(
+            topics,
+            vec![msg.subscription.proof_keys[0].clone(), statechain_id.to_string()]
+        );
+
+        // Replayed
+        assert!(sc_entity.authorise_subscription(&msg).is_err());
+
+        // Statechain owned by another key
+        let msg = EventSubscribeMsg::new(&[key], &[other_statechain_id]).unwrap();
+        assert!(sc_entity.authorise_subscription(&msg).is_err());
+        let msg = EventSubscribeMsg::new(&[other_key], &[statechain_id]).unwrap();
+        assert!(sc_entity.authorise_subscription(&msg).is_err());
+
+        // Subscriptions to proof keys only need a signature
+        let msg = EventSubscribeMsg::new(&[other_key], &[]).unwrap();
+        assert_eq!(sc_entity.authorise_subscription(&msg).unwrap().len(), 1);
+
+        // Unknown swap or batch
+        let msg = EventSubscribeMsg::new(&[key], &[Uuid::new_v4()]).unwrap();
+        assert!(sc_entity.authorise_subscription(&msg).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+//! StateEntity Explorer
+//!
+//! StateEntity Explorer trait and implementation for StateChainEntity. Public listing, search
+//! and summaries of statechains.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::error::SEError;
+use crate::server::StateChainEntity;
+use crate::structs::{StateChainFilter, StateChainListing};
+use crate::Database;
+use shared_lib::{state_chain::get_time_now, structs::*, util::blocks_from_sequence};
+
+use cfg_if::cfg_if;
+use rocket::State;
+use rocket_contrib::json::Json;
+use rocket_okapi::openapi;
+use std::collections::HashSet;
+use std::str::FromStr;
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
+    }
+}
+
+// Statechains per page if not specified
+const DEFAULT_PER_PAGE: u64 = 20;
+// Maximum statechains per page
+const MAX_PER_PAGE: u64 = 100;
+
+/// StateChain Explorer trait
+pub trait Explorer {
+    /// API: List statechains matching filter, ordered by statechain ID, per_page at a time
+    fn list_statechains(
+        &self,
+        filter: StateChainFilter,
+        page: u64,
+        per_page: u64,
+    ) -> Result<StateChainListAPI>;
+
+    /// API: Get a summary of a statechain
+    fn get_statechain_summary(&self, statechain_id: Uuid) -> Result<StateChainSummaryAPI>;
+
+    /// API: Find statechains by current owner proof key or funding txid
+    fn search_statechains(&self, query: String) -> Result<Vec<StateChainSummaryAPI>>;
+}
+
+impl Explorer for SCE {
+    fn list_statechains(
+        &self,
+        mut filter: StateChainFilter,
+        page: u64,
+        per_page: u64,
+    ) -> Result<StateChainListAPI> {
+        let per_page = per_page.min(MAX_PER_PAGE);
+        let in_swap = self.scheduler.lock()?.get_swap_statechain_ids();
+        filter.in_swap = in_swap.clone();
+        let (listings, total) = self.database.get_statechain_listings(
+            &filter,
+            page.saturating_mul(per_page),
+            per_page,
+        )?;
+
+        let in_swap: HashSet<Uuid> = in_swap.into_iter().collect();
+        let height = self.get_explorer_height()?;
+        Ok(StateChainListAPI {
+            statechains: listings
+                .into_iter()
+                .map(|listing| self.statechain_summary(listing, &in_swap, height))
+                .collect(),
+            page,
+            per_page,
+            total,
+        })
+    }
+
+    fn get_statechain_summary(&self, statechain_id: Uuid) -> Result<StateChainSummaryAPI> {
+        let listing = self.database.get_statechain_listing(statechain_id)?;
+        let in_swap: HashSet<Uuid> = self
+            .scheduler
+            .lock()?
+            .get_swap_statechain_ids()
+            .into_iter()
+            .collect();
+        let height = self.get_explorer_height()?;
+        Ok(self.statechain_summary(listing, &in_swap, height))
+    }
+
+    fn search_statechains(&self, query: String) -> Result<Vec<StateChainSummaryAPI>> {
+        let statechain_ids = self.database.search_statechains(&query)?;
+        let mut summaries = vec![];
+        for statechain_id in statechain_ids {
+            summaries.push(self.get_statechain_summary(statechain_id)?);
+        }
+        Ok(summaries)
+    }
+}
+
+impl SCE {
+    // Current block height. None if the chain backend is unavailable, so that listings do not
+    // depend on it.
+    fn get_explorer_height(&self) -> Result<Option<u64>> {
+        match self.chain.lock()?.get_block_height() {
+            Ok(height) => Ok(Some(height)),
+            Err(e) => {
+                warn!("EXPLORER: Failed to get block height: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn statechain_summary(
+        &self,
+        listing: StateChainListing,
+        in_swap: &HashSet<Uuid>,
+        height: Option<u64>,
+    ) -> StateChainSummaryAPI {
+        let status = if listing.closed {
+            StateChainStatus::ClosedByBackup
+        } else if listing.amount == 0 {
+            StateChainStatus::Withdrawn
+        } else if in_swap.contains(&listing.id) {
+            StateChainStatus::InSwap
+        } else if listing.locked_until > get_time_now() {
+            StateChainStatus::Locked
+        } else {
+            StateChainStatus::Active
+        };
+
+        let locktime = match &listing.tx_backup {
+            Some(tx) if self.config.relative_locktime => match tx.input.get(0) {
+                Some(input) => blocks_from_sequence(&input.sequence).unwrap_or(0),
+                None => 0,
+            },
+            Some(tx) => tx.lock_time,
+            None => 0,
+        };
+        // Relative locktimes start once the kick-off tx confirms
+        let blocks_until_locktime = match status {
+            StateChainStatus::ClosedByBackup | StateChainStatus::Withdrawn => None,
+            _ if self.config.relative_locktime => Some(locktime),
+            _ => height.map(|height| locktime.saturating_sub(height as u32)),
+        };
+
+        let transfer_count = listing
+            .chain
+            .chain
+            .iter()
+            .filter(|state| match &state.next_state {
+                Some(sig) => sig.purpose.starts_with("TRANSFER"),
+                None => false,
+            })
+            .count() as u64;
+
+        StateChainSummaryAPI {
+            statechain_id: listing.id,
+            amount: listing.amount as u64,
+            status,
+            proof_key: match listing.chain.chain.last() {
+                Some(state) => state.data.clone(),
+                None => String::default(),
+            },
+            funding_txid: listing.funding_txid,
+            transfer_count,
+            locktime,
+            blocks_until_locktime,
+        }
+    }
+}
+
+#[openapi]
+/// # List statechains, filtered by amount and status (active, locked, in-swap, withdrawn or closed-by-backup), a page at a time
+#[get(
+    "/explorer/statechains?<page>&<per_page>&<min_amount>&<max_amount>&<status>",
+    format = "json"
+)]
+pub fn list_statechains(
+    sc_entity: State<SCE>,
+    page: Option<u64>,
+    per_page: Option<u64>,
+    min_amount: Option<u64>,
+    max_amount: Option<u64>,
+    status: Option<String>,
+) -> Result<Json<StateChainListAPI>> {
+    let status = match status {
+        Some(status) => Some(
+            StateChainStatus::from_str(&status).map_err(|e| SEError::Generic(e.to_string()))?,
+        ),
+        None => None,
+    };
+    let filter = StateChainFilter {
+        min_amount,
+        max_amount,
+        status,
+        in_swap: vec![],
+    };
+    match sc_entity.list_statechains(
+        filter,
+        page.unwrap_or(0),
+        per_page.unwrap_or(DEFAULT_PER_PAGE),
+    ) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get a summary of a statechain: status, owner, transfer count and blocks until the backup locktime
+#[get("/explorer/statechain/<statechain_id>", format = "json")]
+pub fn get_statechain_summary(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Json<StateChainSummaryAPI>> {
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain ID: {}", e)))?;
+    match sc_entity.get_statechain_summary(statechain_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Find statechains by current owner proof key or funding txid
+#[get("/explorer/search/<query>", format = "json")]
+pub fn search_statechains(
+    sc_entity: State<SCE>,
+    query: String,
+) -> Result<Json<Vec<StateChainSummaryAPI>>> {
+    match sc_entity.search_statechains(query) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::memory::MemoryChain;
+    use crate::protocol::util::tests::test_sc_entity;
+    use bitcoin::Transaction;
+    use chrono::Duration;
+    use mockall::predicate;
+    use shared_lib::state_chain::{State as StateChainState, StateChain, StateChainSig};
+
+    fn listing(id: Uuid, amount: i64, transfers: usize, locktime: u32) -> StateChainListing {
+        let mut chain = vec![];
+        for i in 0..transfers {
+            chain.push(StateChainState {
+                data: format!("proof key {}", i),
+                next_state: Some(StateChainSig {
+                    purpose: String::from("TRANSFER"),
+                    data: format!("proof key {}", i + 1),
+                    sig: String::default(),
+                }),
+            });
+        }
+        chain.push(StateChainState {
+            data: format!("proof key {}", transfers),
+            next_state: None,
+        });
+        StateChainListing {
+            id,
+            amount,
+            chain: StateChain { chain },
+            locked_until: get_time_now() - Duration::seconds(1),
+            funding_txid: Some(String::from(
+                "e0a97cb38e7e73617ef75a57eaf2841eb06833407c0eae08029bd04ea7e6115a",
+            )),
+            closed: false,
+            tx_backup: Some(Transaction {
+                version: 2,
+                lock_time: locktime,
+                input: vec![],
+                output: vec![],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_list_statechains() {
+        let active_id = Uuid::new_v4();
+        let locked_id = Uuid::new_v4();
+        let withdrawn_id = Uuid::new_v4();
+        let closed_id = Uuid::new_v4();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        // Pages are capped at MAX_PER_PAGE
+        db.expect_get_statechain_listings()
+            .with(
+                predicate::function(|filter: &StateChainFilter| {
+                    filter.min_amount == Some(1000) && filter.status.is_none()
+                }),
+                predicate::eq(2 * MAX_PER_PAGE),
+                predicate::eq(MAX_PER_PAGE),
+            )
+            .returning(move |_, _, _| {
+                let mut locked = listing(locked_id, 2000, 0, 150);
+                locked.locked_until = get_time_now() + Duration::seconds(60);
+                let mut closed = listing(closed_id, 0, 1, 150);
+                closed.closed = true;
+                Ok((
+                    vec![
+                        listing(active_id, 1000, 2, 150),
+                        locked,
+                        listing(withdrawn_id, 0, 1, 150),
+                        closed,
+                    ],
+                    204,
+                ))
+            });
+
+        let sc_entity = test_sc_entity(db);
+        let chain = MemoryChain::new();
+        chain.set_height(100);
+        *sc_entity.chain.lock().unwrap() = Box::new(chain);
+
+        let filter = StateChainFilter {
+            min_amount: Some(1000),
+            ..Default::default()
+        };
+        let list = sc_entity.list_statechains(filter, 2, 1000).unwrap();
+        assert_eq!(list.page, 2);
+        assert_eq!(list.per_page, MAX_PER_PAGE);
+        assert_eq!(list.total, 204);
+
+        let statuses: Vec<StateChainStatus> = list.statechains.iter().map(|s| s.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                StateChainStatus::Active,
+                StateChainStatus::Locked,
+                StateChainStatus::Withdrawn,
+                StateChainStatus::ClosedByBackup,
+            ]
+        );
+        let active = &list.statechains[0];
+        assert_eq!(active.statechain_id, active_id);
+        assert_eq!(active.amount, 1000);
+        assert_eq!(active.proof_key, "proof key 2");
+        assert_eq!(active.transfer_count, 2);
+        assert_eq!(active.locktime, 150);
+        assert_eq!(active.blocks_until_locktime, Some(50));
+        assert_eq!(list.statechains[2].blocks_until_locktime, None);
+    }
+
+    #[test]
+    fn test_search_statechains() {
+        let statechain_id = Uuid::new_v4();
+        let proof_key = String::from("proof key 1");
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_search_statechains()
+            .with(predicate::eq(proof_key.clone()))
+            .returning(move |_| Ok(vec![statechain_id]));
+        db.expect_search_statechains()
+            .with(predicate::ne(proof_key.clone()))
+            .returning(|_| Ok(vec![]));
+        db.expect_get_statechain_listing()
+            .with(predicate::eq(statechain_id))
+            .returning(move |id| Ok(listing(id, 1000, 1, 150)));
+
+        let sc_entity = test_sc_entity(db);
+        let chain = MemoryChain::new();
+        chain.set_height(200);
+        *sc_entity.chain.lock().unwrap() = Box::new(chain);
+
+        let summaries = sc_entity.search_statechains(proof_key.clone()).unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].statechain_id, statechain_id);
+        assert_eq!(summaries[0].proof_key, proof_key);
+        // Backup tx can already be broadcast
+        assert_eq!(summaries[0].blocks_until_locktime, Some(0));
+
+        assert!(sc_entity
+            .search_statechains(String::from("unknown"))
+            .unwrap()
+            .is_empty());
+
+        // In swap
+        sc_entity
+            .scheduler
+            .lock()
+            .unwrap()
+            .register_amount_swap_size(&statechain_id, 1000, 5);
+        assert_eq!(
+            sc_entity.get_statechain_summary(statechain_id).unwrap().status,
+            StateChainStatus::InSwap
+        );
+    }
+}
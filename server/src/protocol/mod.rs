@@ -1,7 +1,13 @@
+pub mod admin;
+pub mod auth;
 pub mod conductor;
 pub mod deposit;
 pub mod ecdsa;
+pub mod events;
+pub mod explorer;
+pub mod musig;
 pub mod ping;
+pub mod refresh;
 pub mod transfer;
 pub mod transfer_batch;
 pub mod util;
@@ -0,0 +1,390 @@
+//! MuSig2
+//!
+//! MuSig2 key generation and signing protocol trait and implementation for Taproot statecoins.
+//! The State Entity key share is generated at deposit or set to the key share s2 derived by
+//! transfer_receiver. Each signature takes two rounds: a nonce exchange, then an exchange of
+//! partial signatures of the sighash validated by prepare_sign_tx.
+
+pub use super::super::Result;
+
+use crate::error::{DBErrorType, SEError};
+use crate::Database;
+use crate::{server::StateChainEntity, structs::*};
+use shared_lib::{
+    musig::{nonce_gen, schnorr_verify, MuSigSession, PubNonce},
+    structs::{
+        KeyType, MuSigKeyGenMsg, MuSigKeyGenReply, MuSigSignMsg1, MuSigSignMsg2,
+        MuSigSignReply1, Protocol,
+    },
+};
+
+use bitcoin::hashes::{sha256d, Hash};
+use cfg_if::cfg_if;
+use curv::{
+    cryptographic_primitives::proofs::sigma_dlog::{DLogProof, ProveDLog},
+    elliptic::curves::traits::{ECPoint, ECScalar},
+    {FE, GE},
+};
+use rocket::State;
+use rocket_contrib::json::Json;
+use rocket_okapi::openapi;
+use super::auth::SessionSigned;
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
+    }
+}
+
+/// MuSig2 protocol trait
+pub trait MuSig {
+    /// API: Generate the State Entity key share of a Taproot shared key:
+    ///     - Verify the Owner's proof of knowledge of its key share
+    ///     - Generate a new key share, or use the key share set by the transfer protocol
+    ///     - Return the State Entity key share with its proof of knowledge
+    fn musig_keygen(&self, keygen_msg: MuSigKeyGenMsg) -> Result<MuSigKeyGenReply>;
+
+    /// API: First signing round: store the Owner's public nonces and return new State Entity
+    /// nonces
+    fn musig_sign_first(&self, sign_msg1: MuSigSignMsg1) -> Result<MuSigSignReply1>;
+
+    /// API: Second signing round:
+    ///     - Check the message is the sighash validated by prepare_sign_tx
+    ///     - Verify the Owner's partial signature and aggregate it with the State Entity's
+    ///     - Add the signature to the tx and store it
+    fn musig_sign_second(&self, sign_msg2: MuSigSignMsg2) -> Result<Vec<Vec<u8>>>;
+}
+
+impl MuSig for SCE {
+    fn musig_keygen(&self, keygen_msg: MuSigKeyGenMsg) -> Result<MuSigKeyGenReply> {
+        let user_id = keygen_msg.shared_key_id;
+        self.check_user_auth(&user_id)?;
+        self.check_musig_session(&user_id)?;
+        let db = &self.database;
+
+        match db.get_musig_key(user_id) {
+            Ok(_) => {
+                return Err(SEError::Generic(format!(
+                    "Key Generation already completed for ID {}",
+                    user_id
+                )))
+            }
+            Err(SEError::DBError(DBErrorType::NoDataForID, _)) => (),
+            Err(e) => return Err(e),
+        };
+
+        if DLogProof::verify(&keygen_msg.dlog_proof).is_err() {
+            return Err(SEError::Generic(String::from(
+                "Owner key share proof of knowledge is invalid.",
+            )));
+        }
+
+        // Generate shared key
+        let secret: FE = if keygen_msg.protocol == Protocol::Deposit {
+            ECScalar::new_random()
+        } else {
+            db.get_ecdsa_s2(user_id)?
+        };
+        let dlog_proof = DLogProof::prove(&secret);
+
+        db.update_musig_key(
+            &user_id,
+            &MuSigKey {
+                secret,
+                owner_pub: keygen_msg.dlog_proof.pk,
+            },
+        )?;
+        db.update_s1_pubkey(&user_id, &dlog_proof.pk)?;
+
+        info!("MUSIG: Key generation complete. Shared Key ID: {}", user_id);
+        Ok(MuSigKeyGenReply { dlog_proof })
+    }
+
+    fn musig_sign_first(&self, sign_msg1: MuSigSignMsg1) -> Result<MuSigSignReply1> {
+        let user_id = sign_msg1.shared_key_id;
+        self.check_user_auth(&user_id)?;
+        self.check_musig_session(&user_id)?;
+
+        // Ensure key generation is complete
+        self.database.get_musig_key(user_id)?;
+
+        let (sec_nonce, pub_nonce) = nonce_gen();
+        self.database.update_musig_nonces(
+            &user_id,
+            &Some(MuSigNonces {
+                sec_nonce,
+                owner_nonce: sign_msg1.pub_nonce,
+            }),
+        )?;
+
+        Ok(MuSigSignReply1 { pub_nonce })
+    }
+
+    fn musig_sign_second(&self, sign_msg2: MuSigSignMsg2) -> Result<Vec<Vec<u8>>> {
+        let user_id = sign_msg2.shared_key_id;
+        self.check_user_auth(&user_id)?;
+        self.check_musig_session(&user_id)?;
+        let db = &self.database;
+
+        // Nonces sign a single message. Delete them before anything is signed with them.
+        let nonces = match db.get_musig_nonces(user_id)? {
+            Some(nonces) => nonces,
+            None => {
+                return Err(SEError::SigningError(String::from(
+                    "No signing nonces. /musig/sign/first must be called first.",
+                )))
+            }
+        };
+        db.update_musig_nonces(&user_id, &None)?;
+
+        // Check message matches the validated sig hash for this user
+        let sig_hash: sha256d::Hash = db.get_sighash(user_id)?;
+        let message = hex::decode(&sign_msg2.message).unwrap_or_default();
+        if message[..] != sig_hash.into_inner()[..] {
+            return Err(SEError::SigningError(format!(
+                "Message to be signed does not match verified sig hash. \n{}, {}",
+                hex::encode(sig_hash.into_inner()),
+                sign_msg2.message
+            )));
+        }
+        let msg = sig_hash.into_inner();
+
+        let key = db.get_musig_key(user_id)?;
+        let g: GE = ECPoint::generator();
+        let p = key.owner_pub + g * key.secret;
+        let se_nonce = PubNonce {
+            r1: g * nonces.sec_nonce.k1,
+            r2: g * nonces.sec_nonce.k2,
+        };
+        let session = MuSigSession::new(&p, &nonces.owner_nonce.aggregate(&se_nonce), &msg);
+
+        if !session.partial_verify(&nonces.owner_nonce, &sign_msg2.partial_sig, &key.owner_pub) {
+            return Err(SEError::SigningError(String::from(
+                "Owner partial signature is invalid.",
+            )));
+        }
+        let partial_sig = session.partial_sign(&nonces.sec_nonce, &key.secret);
+        let sig = session.aggregate(&[sign_msg2.partial_sig, partial_sig]);
+        if !schnorr_verify(&sig, &session.q, &msg) {
+            return Err(SEError::SigningError(String::from(
+                "Signature validation failed.",
+            )));
+        }
+
+        // Key path spend witness. SIGHASH_DEFAULT signatures have no sighash type byte.
+        self.store_signed_tx(&user_id, &sign_msg2.protocol, vec![sig])
+    }
+}
+
+impl SCE {
+    /// Check a session holds a Taproot shared key. The lockbox only holds 2P-ECDSA key shares.
+    fn check_musig_session(&self, user_id: &Uuid) -> Result<()> {
+        if self.lockbox.active {
+            return Err(SEError::Generic(String::from(
+                "Taproot statecoins are not supported by the lockbox.",
+            )));
+        }
+        if self.database.get_key_type(*user_id)? != KeyType::Taproot {
+            return Err(SEError::Generic(String::from(
+                "Shared key is not a Taproot key.",
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[openapi]
+/// # MuSig2 key generation: exchange key shares and proofs of knowledge
+#[post("/musig/keygen", format = "json", data = "<keygen_msg>")]
+pub fn musig_keygen(
+    sc_entity: State<SCE>,
+    keygen_msg: SessionSigned<MuSigKeyGenMsg>,
+) -> Result<Json<MuSigKeyGenReply>> {
+    match sc_entity.musig_keygen(keygen_msg.into_inner()) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # First round of the MuSig2 signing protocol: exchange public nonces
+#[post("/musig/sign/first", format = "json", data = "<sign_msg1>")]
+pub fn musig_sign_first(
+    sc_entity: State<SCE>,
+    sign_msg1: SessionSigned<MuSigSignMsg1>,
+) -> Result<Json<MuSigSignReply1>> {
+    match sc_entity.musig_sign_first(sign_msg1.into_inner()) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Second round of the MuSig2 signing protocol: partial signature aggregation and verification
+#[post("/musig/sign/second", format = "json", data = "<sign_msg2>")]
+pub fn musig_sign_second(
+    sc_entity: State<SCE>,
+    sign_msg2: SessionSigned<MuSigSignMsg2>,
+) -> Result<Json<Vec<Vec<u8>>>> {
+    match sc_entity.musig_sign_second(sign_msg2.into_inner()) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::protocol::util::tests::{test_sc_entity, BACKUP_TX_NOT_SIGNED};
+    use bitcoin::Transaction;
+    use shared_lib::musig::taproot_tweak;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_musig_keygen_and_sign() {
+        let user_id = Uuid::new_v4();
+        let msg = sha256d::Hash::hash(b"sighash");
+        let tx: Transaction = serde_json::from_str(BACKUP_TX_NOT_SIGNED).unwrap();
+
+        let musig_key: Arc<Mutex<Option<MuSigKey>>> = Arc::new(Mutex::new(None));
+        let musig_nonces: Arc<Mutex<Option<MuSigNonces>>> = Arc::new(Mutex::new(None));
+        let signed_tx: Arc<Mutex<Option<Transaction>>> = Arc::new(Mutex::new(None));
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth().returning(|id| Ok(id));
+        db.expect_get_key_type().returning(|_| Ok(KeyType::Taproot));
+        let key = musig_key.clone();
+        db.expect_get_musig_key()
+            .returning(move |_| match key.lock().unwrap().clone() {
+                Some(key) => Ok(key),
+                None => Err(SEError::DBError(
+                    DBErrorType::NoDataForID,
+                    String::from("item not found"),
+                )),
+            });
+        let key = musig_key.clone();
+        db.expect_update_musig_key().returning(move |_, new_key| {
+            *key.lock().unwrap() = Some(new_key.clone());
+            Ok(())
+        });
+        db.expect_update_s1_pubkey().returning(|_, _| Ok(()));
+        let nonces = musig_nonces.clone();
+        db.expect_get_musig_nonces()
+            .returning(move |_| Ok(nonces.lock().unwrap().clone()));
+        let nonces = musig_nonces.clone();
+        db.expect_update_musig_nonces()
+            .returning(move |_, new_nonces| {
+                *nonces.lock().unwrap() = new_nonces.clone();
+                Ok(())
+            });
+        db.expect_get_sighash().returning(move |_| Ok(msg));
+        db.expect_get_user_backup_tx()
+            .returning(move |_| Ok(tx.clone()));
+        let stored = signed_tx.clone();
+        db.expect_update_user_backup_tx().returning(move |_, tx| {
+            *stored.lock().unwrap() = Some(tx);
+            Ok(())
+        });
+
+        let sc_entity = test_sc_entity(db);
+
+        // Key generation
+        let o: FE = ECScalar::new_random();
+        let keygen_msg = |dlog_proof: DLogProof| MuSigKeyGenMsg {
+            shared_key_id: user_id,
+            protocol: Protocol::Deposit,
+            dlog_proof,
+        };
+        let mut bad_proof = DLogProof::prove(&o);
+        bad_proof.pk = bad_proof.pk + bad_proof.pk;
+        match sc_entity.musig_keygen(keygen_msg(bad_proof)) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("proof of knowledge is invalid")),
+        }
+        let reply = sc_entity.musig_keygen(keygen_msg(DLogProof::prove(&o))).unwrap();
+        assert!(DLogProof::verify(&reply.dlog_proof).is_ok());
+        match sc_entity.musig_keygen(keygen_msg(DLogProof::prove(&o))) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Key Generation already completed")),
+        }
+        let g: GE = ECPoint::generator();
+        let p = g * o + reply.dlog_proof.pk;
+
+        // Signing
+        let sign = |message: &[u8; 32], corrupt: bool| -> Result<Vec<Vec<u8>>> {
+            let (sec_nonce, pub_nonce) = nonce_gen();
+            let reply1 = sc_entity.musig_sign_first(MuSigSignMsg1 {
+                shared_key_id: user_id,
+                pub_nonce,
+            })?;
+            let session = MuSigSession::new(&p, &pub_nonce.aggregate(&reply1.pub_nonce), message);
+            let mut partial_sig = session.partial_sign(&sec_nonce, &o);
+            if corrupt {
+                partial_sig = partial_sig + partial_sig;
+            }
+            sc_entity.musig_sign_second(MuSigSignMsg2 {
+                shared_key_id: user_id,
+                protocol: Protocol::Deposit,
+                message: hex::encode(message),
+                partial_sig,
+            })
+        };
+
+        // Only the validated sighash is signed
+        match sign(&[1; 32], false) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not match verified sig hash")),
+        }
+        match sign(&msg.into_inner(), true) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Owner partial signature is invalid.")),
+        }
+
+        // Signature of the Taproot output key is added to the stored tx
+        let witness = sign(&msg.into_inner(), false).unwrap();
+        assert_eq!(witness.len(), 1);
+        let (_, q) = taproot_tweak(&p);
+        assert!(schnorr_verify(&witness[0], &q, &msg.into_inner()));
+        assert_eq!(
+            signed_tx.lock().unwrap().clone().unwrap().input[0].witness,
+            witness
+        );
+
+        // Nonces are deleted once used
+        assert!(musig_nonces.lock().unwrap().is_none());
+        match sc_entity.musig_sign_second(MuSigSignMsg2 {
+            shared_key_id: user_id,
+            protocol: Protocol::Deposit,
+            message: hex::encode(msg.into_inner()),
+            partial_sig: ECScalar::new_random(),
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("No signing nonces")),
+        }
+    }
+
+    #[test]
+    fn test_musig_requires_taproot_session() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth().returning(|id| Ok(id));
+        db.expect_get_key_type().returning(|_| Ok(KeyType::Ecdsa));
+        let sc_entity = test_sc_entity(db);
+
+        let (_, pub_nonce) = nonce_gen();
+        match sc_entity.musig_sign_first(MuSigSignMsg1 {
+            shared_key_id: Uuid::new_v4(),
+            pub_nonce,
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("not a Taproot key")),
+        }
+    }
+}
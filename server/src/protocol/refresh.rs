@@ -0,0 +1,399 @@
+//! StateEntity Refresh
+//!
+//! StateEntity Refresh protocol trait and implementation for StateChainEntity. A refresh spends
+//! a statecoin's funding output to a new output of the same shared key with a new back up tx,
+//! resetting the back up tx locktime. Back up txs of previous owners spend the old funding
+//! output and are invalidated.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use super::withdraw::Withdraw;
+use shared_lib::{state_chain::*, structs::*};
+
+use crate::error::SEError;
+use crate::Database;
+use crate::{server::StateChainEntity, storage::Storage};
+use bitcoin::OutPoint;
+use cfg_if::cfg_if;
+use rocket::State;
+use rocket_contrib::json::Json;
+use super::auth::SessionSigned;
+use rocket_okapi::openapi;
+use uuid::Uuid;
+
+//Generics cannot be used in Rocket State, therefore we define the concrete
+//type of StateChainEntity here
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
+    }
+}
+
+/// StateChain Refresh protocol trait
+pub trait Refresh {
+    /// API: User request refresh of a statecoin:
+    ///     - Check StateChainSig validity and that it signs the owner's current proof key
+    ///     - Mark user as authorised to refresh
+    fn refresh_init(&self, refresh_msg1: RefreshMsg1) -> Result<()>;
+
+    /// API: Finish refresh:
+    ///     - Ensure refresh tx and new back up tx have been signed
+    ///     - Wait for confirmation of refresh tx in blockchain
+    ///     - Update StateChain, BackupTxs and sparse merkle tree with the new funding output
+    fn refresh_confirm(&self, refresh_msg2: RefreshMsg2) -> Result<()>;
+}
+
+impl Refresh for SCE {
+    fn refresh_init(&self, refresh_msg1: RefreshMsg1) -> Result<()> {
+        let user_id = refresh_msg1.shared_key_id;
+        self.check_user_auth(&user_id)?;
+
+        info!("REFRESH: Init. Shared Key ID: {}", user_id);
+
+        // Back up txs spend the kick-off tx and do not expire in relative locktime mode
+        if self.config.relative_locktime {
+            return Err(SEError::Generic(String::from(
+                "Statecoins do not expire in relative locktime mode.",
+            )));
+        }
+
+        let statechain_id = self.database.get_statechain_id(user_id)?;
+        self.check_no_transfer(&statechain_id)?;
+
+        // Owner must sign their current proof key
+        let sco = self.verify_statechain_sig(
+            &statechain_id,
+            &refresh_msg1.statechain_sig,
+            Some(user_id),
+        )?;
+        if refresh_msg1.statechain_sig.purpose != "REFRESH"
+            || refresh_msg1.statechain_sig.data != sco.chain.get_tip()?.data
+        {
+            return Err(SEError::Generic(String::from(
+                "StateChainSig does not sign a refresh to the current proof key.",
+            )));
+        }
+
+        // Mark UserSession as authorised for refresh
+        self.database
+            .update_refresh_sc_sig(&user_id, refresh_msg1.statechain_sig)?;
+
+        info!(
+            "REFRESH: Authorised. Shared Key ID: {}. State Chain: {}",
+            user_id, statechain_id
+        );
+
+        Ok(())
+    }
+
+    fn refresh_confirm(&self, refresh_msg2: RefreshMsg2) -> Result<()> {
+        let user_id = refresh_msg2.shared_key_id;
+        self.check_user_auth(&user_id)?;
+
+        info!("REFRESH: Confirm. Shared Key ID: {}", user_id);
+
+        // Get refresh data - Checking that refresh tx and statechain signature exists
+        let rcd = self.database.get_refresh_confirm_data(user_id)?;
+
+        // Ensure refresh tx and back up tx have been signed. The back up tx was checked to spend
+        // the refresh tx in prepare_sign_tx.
+        if rcd.tx_refresh.input[0].witness.len() == 0 {
+            return Err(SEError::Generic(String::from(
+                "Signed Refresh transaction not found.",
+            )));
+        }
+        let refresh_outpoint = OutPoint {
+            txid: rcd.tx_refresh.txid(),
+            vout: 0,
+        };
+        let tx_backup = self.database.get_user_backup_tx(user_id)?;
+        if tx_backup.input[0].previous_output != refresh_outpoint
+            || tx_backup.input[0].witness.len() == 0
+        {
+            return Err(SEError::Generic(String::from(
+                "Signed Back up transaction not found.",
+            )));
+        }
+
+        self.check_no_transfer(&rcd.statechain_id)?;
+        let sco = self.verify_statechain_sig(
+            &rcd.statechain_id,
+            &rcd.refresh_sc_sig,
+            Some(user_id),
+        )?;
+
+        // Check that the refresh transaction has the required number of confirmations
+        let confirmation = self.verify_tx_confirmed(&refresh_outpoint.txid.to_string())?;
+
+        // Add refresh to StateChain history. The proof key is unchanged.
+        let mut state_chain = sco.chain;
+        state_chain.add(rcd.refresh_sc_sig.clone())?;
+        self.database.update_statechain_amount(
+            &rcd.statechain_id,
+            state_chain,
+            rcd.tx_refresh.output[0].value,
+        )?;
+
+        // Replace back up tx of statechain
+        self.database
+            .update_backup_tx(&rcd.statechain_id, tx_backup.clone())?;
+        self.database.add_backup_tx_history(
+            &rcd.statechain_id,
+            &tx_backup,
+            &rcd.refresh_sc_sig.data,
+        )?;
+        // The refresh tx is now the funding tx
+        self.database
+            .update_funding_confirmation(&rcd.statechain_id, &confirmation)?;
+        self.database
+            .update_funding_outpoint(&rcd.statechain_id, &refresh_outpoint)?;
+        self.database.remove_refresh_pending(&rcd.statechain_id)?;
+        // Back up txs of aborted transfers spend the old funding output
+        self.database.remove_aborted_locktime(&rcd.statechain_id)?;
+
+        // Update sparse merkle tree with the new funding output
+        let (prev_root, new_root) =
+            self.update_smt(&smt_key(&refresh_outpoint), &rcd.refresh_sc_sig.data)?;
+
+        self.database.remove_refresh_data(&user_id)?;
+
+        debug!(
+            "REFRESH: State Chain ID: {}. New root: {:?}. Previous root: {:?}.",
+            rcd.statechain_id, &new_root, &prev_root
+        );
+        info!(
+            "REFRESH: Complete. Shared Key ID: {}. State Chain: {}. Funding outpoint: {}",
+            user_id, rcd.statechain_id, refresh_outpoint
+        );
+
+        Ok(())
+    }
+}
+
+impl SCE {
+    /// A statecoin cannot be refreshed while it is being transferred
+    fn check_no_transfer(&self, statechain_id: &Uuid) -> Result<()> {
+        if self.database.get_transfer_data(*statechain_id).is_ok() {
+            return Err(SEError::Generic(String::from(
+                "Statecoin cannot be refreshed during a transfer.",
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[openapi]
+/// # Initiate the refresh process: provide signed statechain
+#[post("/refresh/init", format = "json", data = "<refresh_msg1>")]
+pub fn refresh_init(sc_entity: State<SCE>, refresh_msg1: SessionSigned<RefreshMsg1>) -> Result<Json<()>> {
+    match sc_entity.refresh_init(refresh_msg1.into_inner()) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Complete the refresh process: confirm refresh transaction
+#[post("/refresh/confirm", format = "json", data = "<refresh_msg2>")]
+pub fn refresh_confirm(
+    sc_entity: State<SCE>,
+    refresh_msg2: SessionSigned<RefreshMsg2>,
+) -> Result<Json<()>> {
+    match sc_entity.refresh_confirm(refresh_msg2.into_inner()) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::DBErrorType,
+        protocol::util::{
+            mocks,
+            tests::{test_sc_entity, BACKUP_TX_SIGNED},
+        },
+        structs::{RefreshConfirmData, StateChainOwner},
+    };
+    use bitcoin::{Transaction, TxIn};
+    use chrono::Utc;
+    use mockall::predicate;
+    use shared_lib::util::keygen::generate_keypair;
+
+    // Proof key and StateChainSig signing it with the proof key's private key
+    fn refresh_sc_sigs(purposes: &[&str]) -> (String, Vec<StateChainSig>) {
+        let (priv_key, pub_key) = generate_keypair();
+        let proof_key = pub_key.to_string();
+        let sigs = purposes
+            .iter()
+            .map(|purpose| {
+                StateChainSig::new(&priv_key.key, &purpose.to_string(), &proof_key).unwrap()
+            })
+            .collect();
+        (proof_key, sigs)
+    }
+
+    fn no_transfer(db: &mut MockDatabase) {
+        db.expect_get_transfer_data().returning(|id| {
+            Err(SEError::DBError(DBErrorType::NoDataForID, id.to_string()))
+        });
+    }
+
+    #[test]
+    fn test_refresh_init() {
+        let user_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let (proof_key, sigs) = refresh_sc_sigs(&["REFRESH", "TRANSFER"]);
+        let (statechain_sig, transfer_sig) = (sigs[0].clone(), sigs[1].clone());
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth().returning(move |_| Ok(user_id));
+        db.expect_get_statechain_id()
+            .returning(move |_| Ok(statechain_id));
+        no_transfer(&mut db);
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id: user_id,
+                chain: StateChain::new(proof_key.clone()),
+            })
+        });
+        db.expect_update_refresh_sc_sig().returning(|_, _| Ok(()));
+
+        let mut sc_entity = test_sc_entity(db);
+
+        // Signature for another purpose
+        match sc_entity.refresh_init(RefreshMsg1 {
+            shared_key_id: user_id,
+            statechain_sig: transfer_sig,
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("StateChainSig does not sign a refresh to the current proof key.")),
+        }
+
+        assert!(sc_entity
+            .refresh_init(RefreshMsg1 {
+                shared_key_id: user_id,
+                statechain_sig: statechain_sig.clone(),
+            })
+            .is_ok());
+
+        // Not available in relative locktime mode
+        sc_entity.config.relative_locktime = true;
+        match sc_entity.refresh_init(RefreshMsg1 {
+            shared_key_id: user_id,
+            statechain_sig,
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("Statecoins do not expire in relative locktime mode.")),
+        }
+    }
+
+    #[test]
+    fn test_refresh_confirm() {
+        let user_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let (proof_key, sigs) = refresh_sc_sigs(&["REFRESH"]);
+        let statechain_sig = sigs[0].clone();
+
+        // Refresh tx spending the funding output of the current back up tx
+        let tx_refresh = serde_json::from_str::<Transaction>(&BACKUP_TX_SIGNED).unwrap();
+        let mut tx_refresh_unsigned = tx_refresh.clone();
+        tx_refresh_unsigned.input[0].witness = vec![];
+        let mut tx_backup = tx_refresh.clone();
+        tx_backup.input = vec![TxIn {
+            previous_output: OutPoint {
+                txid: tx_refresh.txid(),
+                vout: 0,
+            },
+            ..tx_refresh.input[0].clone()
+        }];
+        tx_backup.lock_time = 1000;
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth().returning(move |_| Ok(user_id));
+        db.expect_root_get_current_id().returning(|| Ok(1 as i64));
+        db.expect_get_root().returning(|_| Ok(None));
+        db.expect_root_update().returning(|_| Ok(1));
+        // First return unsigned refresh tx
+        let sig = statechain_sig.clone();
+        db.expect_get_refresh_confirm_data()
+            .times(1)
+            .returning(move |_| {
+                Ok(RefreshConfirmData {
+                    tx_refresh: tx_refresh_unsigned.clone(),
+                    refresh_sc_sig: sig.clone(),
+                    statechain_id,
+                })
+            });
+        let sig = statechain_sig.clone();
+        db.expect_get_refresh_confirm_data().returning(move |_| {
+            Ok(RefreshConfirmData {
+                tx_refresh: tx_refresh.clone(),
+                refresh_sc_sig: sig.clone(),
+                statechain_id,
+            })
+        });
+        db.expect_get_user_backup_tx()
+            .returning(move |_| Ok(tx_backup.clone()));
+        no_transfer(&mut db);
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id: user_id,
+                chain: StateChain::new(proof_key.clone()),
+            })
+        });
+        db.expect_update_statechain_amount()
+            .withf(|_, state_chain, amount| state_chain.chain.len() == 2 && *amount == 9000)
+            .returning(|_, _, _| Ok(()));
+        db.expect_update_backup_tx().returning(|_, _| Ok(()));
+        db.expect_add_backup_tx_history().returning(|_, _, _| Ok(()));
+        db.expect_update_funding_confirmation().returning(|_, _| Ok(()));
+        db.expect_update_funding_outpoint()
+            .with(predicate::eq(statechain_id), predicate::always())
+            .times(1)
+            .returning(|_, _| Ok(()));
+        db.expect_remove_refresh_pending()
+            .with(predicate::eq(statechain_id))
+            .times(1)
+            .returning(|_| Ok(()));
+        db.expect_remove_aborted_locktime()
+            .with(predicate::eq(statechain_id))
+            .times(1)
+            .returning(|_| Ok(()));
+        db.expect_remove_refresh_data().returning(|_| Ok(()));
+
+        let sc_entity = test_sc_entity(db);
+
+        // Refresh tx not signed error
+        match sc_entity.refresh_confirm(RefreshMsg2 {
+            shared_key_id: user_id,
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("Signed Refresh transaction not found.")),
+        }
+
+        // Clean protocol run
+        let _m = mocks::ms::post_commitment().create(); //Mainstay post commitment mock
+        assert!(sc_entity
+            .refresh_confirm(RefreshMsg2 {
+                shared_key_id: user_id
+            })
+            .is_ok());
+    }
+}
@@ -3,55 +3,289 @@
 //! Send requests and decode responses
 
 use floating_duration::TimeFormat;
+use reqwest::header::CONTENT_TYPE;
 use serde;
+use reqwest::header::HeaderMap;
+use shared_lib::request_auth::{RequestAuth, LOCKBOX_HEADERS, LOCKBOX_RESPONSE_HEADERS};
 use std::time::Instant;
+use uuid::Uuid;
 
 use crate::server::Lockbox;
 use super::super::Result;
 use crate::error::SEError;
 
-pub fn post_lb<T, V>(lockbox: &Lockbox, path: &str, body: T) -> Result<V>
+/// Post to the lockbox shard holding the key of user_id
+pub fn post_lb<T, V>(lockbox: &Lockbox, user_id: &Uuid, path: &str, body: T) -> Result<V>
 where
     T: serde::ser::Serialize,
     V: serde::de::DeserializeOwned,
 {
-    _post_lb(lockbox, path, body)
+    _post_lb(lockbox, user_id, path, body)
 }
 
-fn _post_lb<T, V>(lockbox: &Lockbox, path: &str, body: T) -> Result<V>
+fn _post_lb<T, V>(lockbox: &Lockbox, user_id: &Uuid, path: &str, body: T) -> Result<V>
 where
     T: serde::ser::Serialize,
     V: serde::de::DeserializeOwned,
 {
-    std::thread::sleep(std::time::Duration::from_millis(100));
     let start = Instant::now();
+    let nodes = lockbox.nodes(user_id);
+    let body = serde_json::to_vec(&body)
+        .map_err(|e| SEError::Generic(format!("Failed to serialize lockbox request: {}", e)))?;
 
-    // catch reqwest errors
-    let value = match lockbox.client.post(&format!("{}/{}", lockbox.endpoint, path)).json(&body).send() 
-    {
-        Ok(v) => {
-            //Reject responses that are too long
-            match v.content_length() {
-                Some(l) => {
-                    if l > 1000000 {
-                        info!("Lockbox POST value ignored because of size: {}", l);
-                        return Err(SEError::Generic(format!(
-                            "Lockbox POST value ignored because of size: {}",
-                            l
-                        )));
+    // Requests that fail to reach a node are retried on the shard's nodes in turn. Only nodes
+    // sharing the key store of the shard's first node are used, as keys are stateful.
+    let mut attempt = 0;
+    let value = loop {
+        let node = nodes[attempt as usize % nodes.len()];
+        let mut request = lockbox
+            .client
+            .post(&format!("{}/{}", node.endpoint, path))
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        // Sign each attempt so that retries are not rejected as replays
+        let mut request_nonce = None;
+        if let Some(key) = &lockbox.key {
+            let auth = RequestAuth::new(key, path, &body)?;
+            request_nonce = Some(auth.nonce.clone());
+            request = request
+                .header(LOCKBOX_HEADERS.signature, auth.signature)
+                .header(LOCKBOX_HEADERS.timestamp, auth.timestamp.to_string())
+                .header(LOCKBOX_HEADERS.nonce, auth.nonce);
+        }
+        match request.send() {
+            Ok(v) => {
+                //Reject responses that are too long
+                match v.content_length() {
+                    Some(l) => {
+                        if l > 1000000 {
+                            info!("Lockbox POST value ignored because of size: {}", l);
+                            return Err(SEError::Generic(format!(
+                                "Lockbox POST value ignored because of size: {}",
+                                l
+                            )));
+                        }
                     }
-                }
-                None => (),
-            };
+                    None => (),
+                };
 
-            let text = v.text()?;
+                // Lockbox errors are returned with an error status and a text body
+                if !v.status().is_success() {
+                    let status = v.status();
+                    return Err(SEError::Generic(format!(
+                        "Lockbox request {} failed ({}): {}",
+                        path,
+                        status,
+                        v.text()?
+                    )));
+                }
 
-            text
+                let headers = v.headers().clone();
+                let value = v.text()?;
+                if let (Some(pubkey), Some(request_nonce)) = (&lockbox.pubkey, &request_nonce) {
+                    verify_response(&pubkey.key, path, request_nonce, &headers, &value)?;
+                }
+                break value;
+            }
+            Err(e) => {
+                node.set_healthy(false);
+                if attempt >= lockbox.retries {
+                    return Err(SEError::from(e));
+                }
+                warn!("Lockbox request {} to {} failed: {}. Retrying.", path, node.endpoint, e);
+                attempt += 1;
+                std::thread::sleep(std::time::Duration::from_millis(100 * attempt as u64));
+            }
         }
-        Err(e) => return Err(SEError::from(e)),
     };
 
     info!("Lockbox request {}, took: {})", path, TimeFormat(start.elapsed()));
-    Ok(serde_json::from_str(value.as_str()).unwrap())
+    serde_json::from_str(value.as_str()).map_err(|e| {
+        SEError::Generic(format!("Invalid lockbox response to {}: {}", path, e))
+    })
+}
+
+/// Check that the response to a request to path with nonce request_nonce is signed by the
+/// lockbox key
+fn verify_response(
+    pubkey: &bitcoin::secp256k1::PublicKey,
+    path: &str,
+    request_nonce: &str,
+    headers: &HeaderMap,
+    body: &str,
+) -> Result<()> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let auth = RequestAuth::from_headers(
+        header(LOCKBOX_RESPONSE_HEADERS.signature),
+        header(LOCKBOX_RESPONSE_HEADERS.timestamp),
+        header(LOCKBOX_RESPONSE_HEADERS.nonce),
+    )?
+    .ok_or(SEError::Generic(format!(
+        "Lockbox response to {} not signed",
+        path
+    )))?;
+    auth.verify_response(pubkey, path, request_nonce, body.as_bytes())
+        .map_err(|e| {
+            SEError::Generic(format!(
+                "Invalid lockbox response signature to {}: {}",
+                path, e
+            ))
+        })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::network::constants::Network;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::util::key::{PrivateKey, PublicKey};
+    use mockito;
+    use reqwest::header::HeaderName;
+
+    #[test]
+    fn test_lockbox_shards() {
+        let lockbox = Lockbox::new("http://lb0a|http://lb0b, http://lb1,", 10, 2);
+        assert!(lockbox.active);
+        assert_eq!(lockbox.shards.len(), 2);
+        assert_eq!(lockbox.shards[0].len(), 2);
+        assert_eq!(lockbox.shards[1][0].endpoint, "http://lb1");
+
+        // Keys are pinned to shards and a transfer's new key is on the sender's shard
+        for _ in 0..10 {
+            let user_id = Uuid::new_v4();
+            let shard = lockbox.shard_index(&user_id);
+            assert_eq!(lockbox.shard_index(&user_id), shard);
+            assert_eq!(lockbox.shard_index(&lockbox.new_shared_key_id(&user_id)), shard);
+        }
+
+        assert!(!Lockbox::new("", 10, 2).active);
+    }
+
+    #[test]
+    fn test_post_lb_signed() {
+        let user_id = Uuid::new_v4();
+        let key_wif = PrivateKey {
+            compressed: true,
+            network: Network::Regtest,
+            key: SecretKey::from_slice(&[0xcd; 32]).unwrap(),
+        }
+        .to_wif();
+        let lockbox_pubkey = lockbox_key().1;
+        let mut lockbox = Lockbox::new(&mockito::server_url(), 10, 0);
+        // Both keys are required
+        assert!(lockbox.set_auth(&key_wif, "").is_err());
+        assert!(lockbox.set_auth(&key_wif, &lockbox_pubkey.to_string()).is_ok());
+        let _m = mockito::mock("POST", "/ecdsa/keygen/first")
+            .match_header(LOCKBOX_HEADERS.signature, mockito::Matcher::Any)
+            .match_header(LOCKBOX_HEADERS.timestamp, mockito::Matcher::Any)
+            .match_header(LOCKBOX_HEADERS.nonce, mockito::Matcher::Any)
+            .with_header("content-type", "application/json")
+            .with_body("[1]")
+            .create();
+
+        // Unsigned response is rejected
+        match post_lb::<&Uuid, Vec<u8>>(&lockbox, &user_id, "ecdsa/keygen/first", &user_id) {
+            Err(SEError::Generic(e)) => assert!(e.contains("not signed")),
+            _ => assert!(false, "expected unsigned response error"),
+        }
+
+        // Response signatures are checked in test_verify_response
+        lockbox.pubkey = None;
+        let res: Vec<u8> = post_lb(&lockbox, &user_id, "ecdsa/keygen/first", &user_id).unwrap();
+        assert_eq!(res, vec![1]);
+
+        // Unsigned request does not match
+        let lockbox = Lockbox::new(&mockito::server_url(), 10, 0);
+        assert!(post_lb::<&Uuid, Vec<u8>>(&lockbox, &user_id, "ecdsa/keygen/first", &user_id).is_err());
+    }
+
+    fn lockbox_key() -> (SecretKey, PublicKey) {
+        let key = SecretKey::from_slice(&[0xab; 32]).unwrap();
+        let pubkey = PrivateKey {
+            compressed: true,
+            network: Network::Regtest,
+            key,
+        }
+        .public_key(&Secp256k1::new());
+        (key, pubkey)
+    }
+
+    #[test]
+    fn test_verify_response() {
+        let (key, pubkey) = lockbox_key();
+        let auth = RequestAuth::new_response(&key, "ecdsa/sign/first", "nonce", b"[1]").unwrap();
+        let mut headers = HeaderMap::new();
+        for (name, value) in &[
+            (LOCKBOX_RESPONSE_HEADERS.signature, auth.signature.clone()),
+            (LOCKBOX_RESPONSE_HEADERS.timestamp, auth.timestamp.to_string()),
+            (LOCKBOX_RESPONSE_HEADERS.nonce, auth.nonce.clone()),
+        ] {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+
+        assert!(verify_response(&pubkey.key, "ecdsa/sign/first", "nonce", &headers, "[1]").is_ok());
+        // Response to another request or with another body
+        assert!(verify_response(&pubkey.key, "ecdsa/sign/first", "other", &headers, "[1]").is_err());
+        assert!(verify_response(&pubkey.key, "ecdsa/sign/first", "nonce", &headers, "[2]").is_err());
+        // Unsigned response
+        assert!(
+            verify_response(&pubkey.key, "ecdsa/sign/first", "nonce", &HeaderMap::new(), "[1]")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_post_lb_failover() {
+        let user_id = Uuid::new_v4();
+        // First node of the shard is unreachable
+        let lockbox = Lockbox::new(
+            &format!("http://127.0.0.1:1|{}", mockito::server_url()),
+            10,
+            1,
+        );
+        let _m = mockito::mock("POST", "/ecdsa/sign/first")
+            .with_header("content-type", "application/json")
+            .with_body("[1]")
+            .create();
+
+        // The second node is not used until it is known to share the first node's key store
+        assert!(!lockbox.shards[0][1].is_healthy());
+        assert!(post_lb::<&Uuid, Vec<u8>>(&lockbox, &user_id, "ecdsa/sign/first", &user_id).is_err());
+
+        // Second node reports another key store
+        lockbox.shards[0][0].set_key_store_id(Some(String::from("key-store-1")));
+        let ping = mockito::mock("GET", "/ping").with_body("key-store-2").create();
+        lockbox.check_health();
+        assert!(!lockbox.shards[0][0].is_healthy());
+        assert!(!lockbox.shards[0][1].is_healthy());
+        assert_eq!(lockbox.nodes(&user_id).len(), 1);
+
+        // Second node shares the key store
+        drop(ping);
+        let _p = mockito::mock("GET", "/ping").with_body("key-store-1").create();
+        lockbox.check_health();
+        assert!(lockbox.shards[0][1].is_healthy());
+        let res: Vec<u8> = post_lb(&lockbox, &user_id, "ecdsa/sign/first", &user_id).unwrap();
+        assert_eq!(res, vec![1]);
+        // Healthy nodes are tried first
+        assert_eq!(lockbox.nodes(&user_id)[0].endpoint, mockito::server_url());
+
+        // Retries exhausted
+        let lockbox = Lockbox::new(&format!("http://127.0.0.1:1|{}", mockito::server_url()), 10, 0);
+        assert!(post_lb::<&Uuid, Vec<u8>>(&lockbox, &user_id, "ecdsa/sign/first", &user_id).is_err());
+
+        // Error responses are not retried
+        let _m = mockito::mock("POST", "/ecdsa/sign/second")
+            .with_status(500)
+            .with_body("Signing Error")
+            .create();
+        let lockbox = Lockbox::new(&mockito::server_url(), 10, 2);
+        match post_lb::<&Uuid, Vec<u8>>(&lockbox, &user_id, "ecdsa/sign/second", &user_id) {
+            Err(SEError::Generic(e)) => assert!(e.contains("Signing Error")),
+            _ => assert!(false, "expected lockbox error"),
+        }
+    }
+}
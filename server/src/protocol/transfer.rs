@@ -7,7 +7,8 @@ extern crate shared_lib;
 extern crate reqwest;
 use crate::server::TRANSFERS_COUNT;
 use super::transfer_batch::transfer_batch_is_ended;
-use shared_lib::{ecies, ecies::WalletDecryptable, ecies::SelfEncryptable, state_chain::*, structs::*, util::transaction_deserialise};
+use super::withdraw::Withdraw;
+use shared_lib::{ecies, ecies::WalletDecryptable, ecies::SelfEncryptable, events::StateEntityEvent, musig::fe_negate, state_chain::*, structs::*, util::{blocks_from_sequence, transaction_deserialise}};
 use bitcoin::secp256k1::key::SecretKey;
 use bitcoin::secp256k1::PublicKey;
 use bitcoin::util::key::PrivateKey;
@@ -27,6 +28,7 @@ use curv::{
 };
 use rocket::State;
 use rocket_contrib::json::Json;
+use super::auth::SessionSigned;
 use std::str::FromStr;
 use uuid::Uuid;
 
@@ -36,8 +38,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
     }
 }
 
@@ -50,6 +52,8 @@ pub struct TransferFinalizeData {
     pub s2: FE,
     pub new_tx_backup_hex: String,
     pub batch_data: Option<BatchData>,
+    #[serde(default)]
+    pub key_type: KeyType,
 }
 
 /// StateChain Transfer protocol trait
@@ -79,6 +83,12 @@ pub trait Transfer {
 
     /// API: Get the transfer message 3 set by update_transfer_msg
     fn transfer_get_msg(&self, statechain_id: Uuid) -> Result<TransferMsg3>;
+
+    /// API: Cancel a transfer which the receiver has not completed:
+    ///     - Check StateChainSig validity and that it signs the pending transfer's receiver proof key
+    ///     - Record the locktime of the transfer's backup tx, which the receiver may hold
+    ///     - Remove transfer data so that the statecoin can be transferred again
+    fn transfer_cancel(&self, transfer_cancel_msg: TransferCancelMsg) -> Result<()>;
 }
 
 impl Transfer for SCE {
@@ -90,12 +100,15 @@ impl Transfer for SCE {
 
         // Get state_chain id
         let statechain_id = self.database.get_statechain_id(user_id)?;
+        self.check_no_refresh_pending(&statechain_id)?;
 
-        // Get back up tx and proof key
-        let (tx_backup,_) = self.database.get_backup_transaction_and_proof_key(user_id)?;
+        // Get current back up tx of the statechain. The UserSession back up tx may belong to an
+        // unfinished refresh.
+        let tx_backup = self.database.get_backup_transaction(statechain_id)?;
 
         // Check that the funding transaction has the required number of confirmations
-        self.verify_tx_confirmed(&tx_backup.input[0].previous_output.txid.to_string())?;
+        let funding_outpoint = self.get_funding_outpoint(&statechain_id, &tx_backup)?;
+        self.verify_tx_confirmed(&funding_outpoint.txid.to_string())?;
 
         // Check if transfer has already been completed (but not finalized)
         if self.database.transfer_is_completed(statechain_id) {
@@ -177,26 +190,45 @@ impl Transfer for SCE {
             )));
         }
 
+        let key_type = self.database.get_key_type(user_id)?;
+
         let s2: FE;
         let s2_pub: GE;
         if self.lockbox.active {
-            let ku_send = KUSendMsg {
+            if key_type == KeyType::Taproot {
+                return Err(SEError::Generic(String::from(
+                    "Taproot statecoins are not supported by the lockbox.",
+                )));
+            }
+            let mut ku_send = KUSendMsg {
                 user_id,
                 statechain_id,
-                x1: td.x1,
+                x1: FESer::from_fe(&td.x1),
                 t2: transfer_msg4.t2,
                 o2_pub: transfer_msg4.o2_pub,
             };
+            if let Some(pubkey) = &self.lockbox.pubkey {
+                if let Err(e) = ku_send.encrypt_with_pubkey(pubkey) {
+                    return Err(SEError::SharedLibError(format!("Failed to encrypt x1 for lockbox. Error: {}", e.to_string())));
+                }
+            }
             let path: &str = "ecdsa/keyupdate/first";
-            let ku_receive: KUReceiveMsg = post_lb(&self.lockbox, path, &ku_send)?;
+            let ku_receive: KUReceiveMsg = post_lb(&self.lockbox, &user_id, path, &ku_send)?;
             s2 = FE::zero();
             s2_pub = ku_receive.s2_pub;
         }
         else {
-            let kp = self.database.get_ecdsa_keypair(user_id)?;
-
-            // let x1 = transfer_data.x1;
-            let s1 = kp.party_1_private.get_private_key();
+            // Current SE key share s1 and owner public key share o1_pub
+            let (s1, o1_pub) = match key_type {
+                KeyType::Ecdsa => {
+                    let kp = self.database.get_ecdsa_keypair(user_id)?;
+                    (kp.party_1_private.get_private_key(), kp.party_2_public)
+                }
+                KeyType::Taproot => {
+                    let key = self.database.get_musig_key(user_id)?;
+                    (key.secret, key.owner_pub)
+                }
+            };
 
             let s1_priv = PrivateKey {
                 compressed: true,
@@ -220,15 +252,24 @@ impl Transfer for SCE {
                 }
             };
 
-            s2 = t2 * (td.x1.invert()) * s1;
-
             let g: GE = ECPoint::generator();
-            s2_pub = g * s2;
-
-            let p1_pub = kp.party_2_public * s1;
-            let p2_pub = transfer_msg4.o2_pub * s2;
+            let (p1_pub, p2_pub) = match key_type {
+                KeyType::Ecdsa => {
+                    s2 = t2 * (td.x1.invert()) * s1;
+                    s2_pub = g * s2;
+                    // P1 = o1_pub*s1, P2 = o2_pub*s2
+                    (o1_pub * s1, transfer_msg4.o2_pub * s2)
+                }
+                KeyType::Taproot => {
+                    // t2 = o1 + x1 - o2, so s2 = s1 + o1 - o2
+                    s2 = s1 + t2 + fe_negate(&td.x1);
+                    s2_pub = g * s2;
+                    // P1 = o1_pub + s1.G, P2 = o2_pub + s2.G
+                    (o1_pub + g * s1, transfer_msg4.o2_pub + s2_pub)
+                }
+            };
 
-            // Check P1 = o1_pub*s1 === p2 = o2_pub*s2
+            // Check P1 === P2
             if p1_pub != p2_pub {
                 error!("TRANSFER: Protocol failed. P1 != P2.");
                 return Err(SEError::Generic(String::from(
@@ -237,8 +278,9 @@ impl Transfer for SCE {
             }
         }
 
-        // Create user ID for new UserSession (receiver of transfer)
-        let new_shared_key_id = Uuid::new_v4();
+        // Create user ID for new UserSession (receiver of transfer). The key update is completed
+        // on the lockbox shard of the sender's key.
+        let new_shared_key_id = self.lockbox.new_shared_key_id(&user_id);
 
         let finalized_data = TransferFinalizeData {
             new_shared_key_id: new_shared_key_id.clone(),
@@ -247,6 +289,7 @@ impl Transfer for SCE {
             s2,
             new_tx_backup_hex: transfer_msg4.tx_backup_hex,
             batch_data: transfer_msg4.batch_data.clone(),
+            key_type,
         };
 
         // If batch transfer then mark StateChain as complete and store finalized data in TransferBatch table.
@@ -277,6 +320,16 @@ impl Transfer for SCE {
             self.transfer_finalize(&finalized_data)?;
         }
 
+        let batch_id = transfer_msg4.batch_data.map(|batch_data| batch_data.id);
+        let event = StateEntityEvent::TransferReceived {
+            statechain_id,
+            batch_id,
+        };
+        if let Some(batch_id) = batch_id {
+            self.events.publish(&batch_id.to_string(), event.clone());
+        }
+        self.events.publish(&statechain_id.to_string(), event);
+
         info!(
             "TRANSFER: Receiver side complete. State Chain ID: {}",
             new_shared_key_id
@@ -325,13 +378,18 @@ impl Transfer for SCE {
                 shared_key_id: new_user_id,
             };
             let path: &str = "ecdsa/keyupdate/second";
-            let _ku_receive: KUAttest = post_lb(&self.lockbox, path, &ku_send)?;
+            let _ku_receive: KUAttest = post_lb(&self.lockbox, &new_user_id, path, &ku_send)?;
         }
 
         let new_tx_backup_hex = transaction_deserialise(&finalized_data.new_tx_backup_hex)?;
 
         self.database
             .update_backup_tx(&statechain_id, new_tx_backup_hex.clone())?;
+        self.database.add_backup_tx_history(
+            &statechain_id,
+            &new_tx_backup_hex,
+            &finalized_data.statechain_sig.data,
+        )?;
 
         info!(
             "TRANSFER: Finalized. New shared key ID: {}. State Chain ID: {}",
@@ -340,13 +398,7 @@ impl Transfer for SCE {
 
         // Update sparse merkle tree with new StateChain entry
         let (prev_root, new_root) = self.update_smt(
-            &new_tx_backup_hex
-                .input
-                .get(0)
-                .unwrap()
-                .previous_output
-                .txid
-                .to_string(),
+            &smt_key(&self.get_funding_outpoint(&statechain_id, &new_tx_backup_hex)?),
             &state_chain
                 .chain
                 .last()
@@ -375,14 +427,153 @@ impl Transfer for SCE {
 
     /// API: Update the state entity database with transfer message 3
     fn transfer_update_msg(&self, transfer_msg3: TransferMsg3) -> Result<()> {
+        let user_id = transfer_msg3.shared_key_id;
+        self.check_user_auth(&user_id)?;
+
+        // Only the sender of a transfer in progress can set its message
+        let statechain_id = transfer_msg3.statechain_id;
+        if self.database.get_statechain_id(user_id)? != statechain_id {
+            return Err(SEError::Generic(format!(
+                "Shared Key ID {} does not own State Chain ID {}.",
+                user_id, statechain_id
+            )));
+        }
+        if self.database.get_transfer_data(statechain_id).is_err() {
+            return Err(SEError::Generic(format!(
+                "No transfer in progress for State Chain ID: {}.",
+                statechain_id
+            )));
+        }
+
         self.database
-            .update_transfer_msg(&transfer_msg3.statechain_id, &transfer_msg3)
+            .update_transfer_msg(&statechain_id, &transfer_msg3)?;
+        // Notify the receiver, subscribed to their proof key, and the sender
+        let event = StateEntityEvent::TransferMsg { statechain_id };
+        self.events.publish(
+            &transfer_msg3.rec_se_addr.proof_key.to_string(),
+            event.clone(),
+        );
+        self.events.publish(&statechain_id.to_string(), event);
+        Ok(())
     }
 
     /// API: Get the transfer message 3 set by update_transfer_msg
     fn transfer_get_msg(&self, statechain_id: Uuid) -> Result<TransferMsg3> {
         self.database.get_transfer_msg(&statechain_id)
     }
+
+    fn transfer_cancel(&self, transfer_cancel_msg: TransferCancelMsg) -> Result<()> {
+        let user_id = transfer_cancel_msg.shared_key_id;
+        self.check_user_auth(&user_id)?;
+
+        info!("TRANSFER: Cancel. Shared Key ID: {}", user_id);
+
+        let statechain_id = self.database.get_statechain_id(user_id)?;
+        let td = match self.database.get_transfer_data(statechain_id) {
+            Ok(td) => td,
+            Err(_) => {
+                return Err(SEError::Generic(format!(
+                    "No transfer in progress for State Chain ID: {}.",
+                    statechain_id
+                )))
+            }
+        };
+
+        // Batch transfers are cancelled when their batch ends
+        if td.statechain_sig.is_transfer_batch(None) {
+            return Err(SEError::Generic(String::from(
+                "Batch transfers cannot be cancelled.",
+            )));
+        }
+
+        // Owner must sign the receiver proof key of the transfer
+        if transfer_cancel_msg.statechain_sig.purpose != "CANCEL"
+            || transfer_cancel_msg.statechain_sig.data != td.statechain_sig.data
+        {
+            return Err(SEError::Generic(String::from(
+                "StateChainSig does not sign a cancel of the transfer in progress.",
+            )));
+        }
+        self.verify_statechain_sig(
+            &statechain_id,
+            &transfer_cancel_msg.statechain_sig,
+            Some(user_id),
+        )?;
+
+        abort_transfer(
+            &*self.database,
+            &statechain_id,
+            self.config.lh_decrement,
+            self.config.relative_locktime,
+        )?;
+
+        info!(
+            "TRANSFER: Cancelled. Shared Key ID: {}. State Chain ID: {}",
+            user_id, statechain_id
+        );
+
+        Ok(())
+    }
+}
+
+/// Locktime, or relative locktime in blocks, from which the backup tx of the next transfer of a
+/// statechain is decremented: the current backup tx locktime, or the backup tx locktime of a
+/// cancelled or expired transfer if lower.
+pub fn transfer_locktime_ceiling<T: Database>(
+    db: &T,
+    statechain_id: Uuid,
+    relative_locktime: bool,
+) -> Result<u32> {
+    let tx_backup = db.get_backup_transaction(statechain_id)?;
+    let locktime = if relative_locktime {
+        blocks_from_sequence(&tx_backup.input[0].sequence).unwrap_or(0)
+    } else {
+        tx_backup.lock_time
+    };
+    match db.get_aborted_locktime(statechain_id)? {
+        Some(aborted_locktime) if aborted_locktime < locktime => Ok(aborted_locktime),
+        _ => Ok(locktime),
+    }
+}
+
+/// Remove the transfer data of a transfer which the receiver has not completed. The sender may
+/// have co-signed a backup tx for the receiver with the decremented locktime, so the locktime is
+/// recorded and the next transfer's backup tx must be decremented from it.
+pub fn abort_transfer<T: Database>(
+    db: &T,
+    statechain_id: &Uuid,
+    lh_decrement: u32,
+    relative_locktime: bool,
+) -> Result<()> {
+    let locktime = transfer_locktime_ceiling(db, *statechain_id, relative_locktime)?;
+    db.update_aborted_locktime(statechain_id, locktime.saturating_sub(lh_decrement))?;
+    db.remove_transfer_data(statechain_id)
+}
+
+/// Remove transfers started more than transfer_expiry seconds ago which have not been completed
+/// by the receiver. Batch transfers are left to expire with their batch. Returns the IDs of the
+/// statechains whose transfers were removed.
+pub fn expire_transfers<T: Database>(
+    db: &T,
+    transfer_expiry: u64,
+    lh_decrement: u32,
+    relative_locktime: bool,
+) -> Result<Vec<Uuid>> {
+    let started_before = get_time_now() - chrono::Duration::seconds(transfer_expiry as i64);
+    let mut expired = vec![];
+    for statechain_id in db.get_transfers_started_before(&started_before)? {
+        let td = db.get_transfer_data(statechain_id)?;
+        if td.statechain_sig.is_transfer_batch(None) {
+            continue;
+        }
+        abort_transfer(db, &statechain_id, lh_decrement, relative_locktime)?;
+        info!(
+            "TRANSFER: Expired. State Chain ID: {}",
+            statechain_id
+        );
+        expired.push(statechain_id);
+    }
+    Ok(expired)
 }
 
 #[openapi]
@@ -390,7 +581,7 @@ impl Transfer for SCE {
 #[post("/transfer/sender", format = "json", data = "<transfer_msg1>")]
 pub fn transfer_sender(
     sc_entity: State<SCE>,
-    transfer_msg1: Json<TransferMsg1>,
+    transfer_msg1: SessionSigned<TransferMsg1>,
 ) -> Result<Json<TransferMsg2>> {
     match sc_entity.transfer_sender(transfer_msg1.into_inner()) {
         Ok(res) => return Ok(Json(res)),
@@ -398,6 +589,21 @@ pub fn transfer_sender(
     }
 }
 
+#[openapi]
+/// # Cancel a transfer which the receiver has not completed
+#[post("/transfer/cancel", format = "json", data = "<transfer_cancel_msg>")]
+pub fn transfer_cancel(
+    sc_entity: State<SCE>,
+    transfer_cancel_msg: SessionSigned<TransferCancelMsg>,
+) -> Result<Json<()>> {
+    match sc_entity.transfer_cancel(transfer_cancel_msg.into_inner()) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+// The receiver has no session for the sender's shared key ID. The SE public key share is not
+// secret: it only lets the receiver encrypt t2 to the SE.
 #[openapi]
 /// # Retreive the current SE public key share for t2 encryption
 #[post("/transfer/pubkey", format = "json", data = "<user_id>")]
@@ -411,6 +617,9 @@ pub fn transfer_get_pubkey(
     }
 }
 
+// The receiver has no session for the sender's shared key ID. The request must carry the
+// StateChainSig the sender signed to the receiver's proof key, and the key update fails unless
+// t2 was computed from t1, which is encrypted to the receiver.
 #[openapi]
 /// # Transfer completing by receiver: key share update and deletion
 #[post("/transfer/receiver", format = "json", data = "<transfer_msg4>")]
@@ -429,7 +638,7 @@ pub fn transfer_receiver(
 #[post("/transfer/update_msg", format = "json", data = "<transfer_msg3>")]
 pub fn transfer_update_msg(
     sc_entity: State<SCE>,
-    transfer_msg3: Json<TransferMsg3>,
+    transfer_msg3: SessionSigned<TransferMsg3>,
 ) -> Result<Json<()>> {
     match sc_entity.transfer_update_msg(transfer_msg3.into_inner()) {
         Ok(res) => return Ok(Json(res)),
@@ -454,13 +663,16 @@ pub fn transfer_get_msg(
 mod tests {
     use super::*;
     use crate::MockDatabase;
+    use crate::server::Lockbox;
     use crate::{
         error::DBErrorType,
         protocol::util::{
             mocks,
             tests::{test_sc_entity, BACKUP_TX_NOT_SIGNED},
         },
-        structs::{ECDSAKeypair, StateChainOwner, TransferData, TransferFinalizeBatchData},
+        structs::{
+            ECDSAKeypair, MuSigKey, StateChainOwner, TransferData, TransferFinalizeBatchData,
+        },
     };
     use chrono::{Duration, Utc};
     use mockall::predicate;
@@ -501,14 +713,15 @@ mod tests {
         db.expect_get_proof_key()
             .returning(move |_| Ok(pubkey.to_string()));
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
         db.expect_get_user_auth()
             .returning(move |_| Ok(shared_key_id));
         db.expect_get_statechain_id()
             .with(predicate::eq(shared_key_id))
             .returning(move |_| Ok(statechain_id));
-        db.expect_get_backup_transaction_and_proof_key()
-            .with(predicate::eq(shared_key_id))
-            .returning(move |_| Ok((tx_backup.clone(), no_sc_shared_key_id.to_string())));        
+        db.expect_get_backup_transaction()
+            .with(predicate::eq(statechain_id))
+            .returning(move |_| Ok(tx_backup.clone()));
         // userid does not own a state
         db.expect_get_statechain_id()
             .with(predicate::eq(no_sc_shared_key_id))
@@ -540,6 +753,7 @@ mod tests {
                     chain: serde_json::from_str::<StateChain>(&STATE_CHAIN.to_string()).unwrap(),
                 })
             });
+        db.expect_get_refresh_pending().returning(|_| Ok(None));
         db.expect_create_transfer().returning(|_, _, _| Ok(()));
         db.expect_update_transfer_msg().returning(|_, _| Ok(()));
 
@@ -588,6 +802,7 @@ mod tests {
 
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
         db.expect_get_user_auth()
             .returning(move |_| Ok(shared_key_id));
         db.expect_get_transfer_data()
@@ -603,6 +818,7 @@ mod tests {
                     x1,
                 })
             });
+        db.expect_get_key_type().returning(|_| Ok(KeyType::Ecdsa));
         db.expect_get_ecdsa_keypair()
             .with(predicate::eq(shared_key_id))
             .returning(|_| {
@@ -619,6 +835,7 @@ mod tests {
         db.expect_transfer_init_user_session()
             .returning(|_, _, _| Ok(()));
         db.expect_update_backup_tx().returning(|_, _| Ok(()));
+        db.expect_add_backup_tx_history().returning(|_, _, _| Ok(()));
         db.expect_remove_transfer_data().returning(|_| Ok(()));
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root().returning(|_| Ok(None));
@@ -640,6 +857,7 @@ mod tests {
                         )
                         .unwrap()
                     ),
+                    key_type: KeyType::Ecdsa,
                     batch_data: Some(BatchData {
                         id: shared_key_id,
                         commitment: String::default(),
@@ -722,6 +940,7 @@ mod tests {
 
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
         db.expect_get_user_auth()
             .returning(move |_| Ok(shared_key_id));
         db.expect_get_transfer_data()
@@ -737,6 +956,7 @@ mod tests {
                     x1,
                 })
             });
+        db.expect_get_key_type().returning(|_| Ok(KeyType::Ecdsa));
         db.expect_get_ecdsa_keypair()
             .with(predicate::eq(shared_key_id))
             .returning(|_| {
@@ -753,6 +973,7 @@ mod tests {
         db.expect_transfer_init_user_session()
             .returning(|_, _, _| Ok(()));
         db.expect_update_backup_tx().returning(|_, _| Ok(()));
+        db.expect_add_backup_tx_history().returning(|_, _, _| Ok(()));
         db.expect_remove_transfer_data().returning(|_| Ok(()));
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root().returning(|_| Ok(None));
@@ -772,6 +993,7 @@ mod tests {
                         &serde_json::from_str::<Transaction>(
                             &BACKUP_TX_NOT_SIGNED.to_string(),
                         ).unwrap()),
+                    key_type: KeyType::Ecdsa,
                     batch_data: Some(BatchData {
                         id: shared_key_id,
                         commitment: String::default(),
@@ -787,8 +1009,7 @@ mod tests {
         let mut sc_entity = test_sc_entity(db);
         let _m = mocks::ms::post_commitment().create(); //Mainstay post commitment mock
 
-        sc_entity.lockbox.active = true;
-        sc_entity.lockbox.endpoint = mockito::server_url();
+        sc_entity.lockbox = Lockbox::new(&mockito::server_url(), 10, 0);
 
         // simulate lockbox secret operations
         let kp = ECDSAKeypair {
@@ -855,4 +1076,285 @@ mod tests {
         // Expected successful run
         assert!(sc_entity.transfer_receiver(transfer_msg_4.clone()).is_ok());
     }
+
+    #[test]
+    fn test_transfer_receiver_taproot() {
+        let g: GE = ECPoint::generator();
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let (o1, s1, x1, o2): (FE, FE, FE, FE) = (
+            ECScalar::new_random(),
+            ECScalar::new_random(),
+            ECScalar::new_random(),
+            ECScalar::new_random(),
+        );
+        let transfer_msg_4 =
+            serde_json::from_str::<TransferMsg4>(&TRANSFER_MSG_4.to_string()).unwrap();
+        let statechain_sig = transfer_msg_4.statechain_sig.clone();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
+        db.expect_get_transfer_data().returning(move |_| {
+            Ok(TransferData {
+                statechain_id,
+                statechain_sig: statechain_sig.clone(),
+                x1,
+            })
+        });
+        db.expect_get_key_type().returning(|_| Ok(KeyType::Taproot));
+        db.expect_get_musig_key().returning(move |_| {
+            Ok(MuSigKey {
+                secret: s1,
+                owner_pub: g * o1,
+            })
+        });
+        db.expect_update_finalize_batch_data()
+            .returning(|_, _| Ok(()));
+        db.expect_get_transfer_batch_start_time()
+            .returning(|_| Ok(Utc::now().naive_utc()));
+        let sc_entity = test_sc_entity(db);
+
+        // Receiver key update t2 = t1 - o2 = o1 + x1 - o2, encrypted to S1
+        let s1_pub = bitcoin::util::key::PublicKey::from_slice(
+            &(g * s1).get_element().serialize(),
+        )
+        .unwrap();
+        let msg_4 = |t2: FE| {
+            let mut msg_4 = transfer_msg_4.clone();
+            msg_4.shared_key_id = shared_key_id;
+            msg_4.statechain_id = statechain_id;
+            msg_4.t2 = FESer::from_fe(&t2);
+            msg_4.o2_pub = g * o2;
+            msg_4.batch_data = Some(BatchData {
+                id: Uuid::new_v4(),
+                commitment: String::default(),
+            });
+            msg_4.encrypt_with_pubkey(&s1_pub).unwrap();
+            msg_4
+        };
+
+        // Multiplicative ECDSA key update is rejected
+        match sc_entity.transfer_receiver(msg_4(o1 * x1 * o2.invert())) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("P1 != P2")),
+        }
+
+        // Shared key is unchanged: o2 + s2 = o1 + s1
+        let transfer_msg_5 = sc_entity
+            .transfer_receiver(msg_4(o1 + x1 + fe_negate(&o2)))
+            .unwrap();
+        assert_eq!(g * o2 + transfer_msg_5.s2_pub, g * o1 + g * s1);
+    }
+
+    #[test]
+    fn test_transfer_cancel() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let (owner_priv, owner_pub) = shared_lib::util::keygen::generate_keypair();
+        let (receiver_priv, receiver_pub) = shared_lib::util::keygen::generate_keypair();
+        let transfer_sig = StateChainSig::new(
+            &owner_priv.key,
+            &"TRANSFER".to_string(),
+            &receiver_pub.to_string(),
+        )
+        .unwrap();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(move |_| Ok(shared_key_id));
+        db.expect_get_statechain_id()
+            .returning(move |_| Ok(statechain_id));
+        db.expect_get_transfer_data().returning(move |_| {
+            Ok(TransferData {
+                statechain_id,
+                statechain_sig: transfer_sig.clone(),
+                x1: FE::new_random(),
+            })
+        });
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id: shared_key_id,
+                chain: StateChain::new(owner_pub.to_string()),
+            })
+        });
+        let mut tx_backup = serde_json::from_str::<Transaction>(&BACKUP_TX_NOT_SIGNED).unwrap();
+        tx_backup.lock_time = 1000;
+        db.expect_get_backup_transaction()
+            .returning(move |_| Ok(tx_backup.clone()));
+        db.expect_get_aborted_locktime().returning(|_| Ok(None));
+        // The receiver may hold a backup tx with the decremented locktime
+        db.expect_update_aborted_locktime()
+            .with(predicate::eq(statechain_id), predicate::eq(900))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        db.expect_remove_transfer_data()
+            .with(predicate::eq(statechain_id))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let mut sc_entity = test_sc_entity(db);
+        sc_entity.config.lh_decrement = 100;
+        sc_entity.config.relative_locktime = false;
+
+        // Signed by the receiver instead of the owner
+        match sc_entity.transfer_cancel(TransferCancelMsg {
+            shared_key_id,
+            statechain_sig: StateChainSig::new(
+                &receiver_priv.key,
+                &"CANCEL".to_string(),
+                &receiver_pub.to_string(),
+            )
+            .unwrap(),
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("signature failed verification")),
+        }
+
+        // Cancel of a different transfer
+        match sc_entity.transfer_cancel(TransferCancelMsg {
+            shared_key_id,
+            statechain_sig: StateChainSig::new(
+                &owner_priv.key,
+                &"CANCEL".to_string(),
+                &owner_pub.to_string(),
+            )
+            .unwrap(),
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("StateChainSig does not sign a cancel of the transfer in progress.")),
+        }
+
+        assert!(sc_entity
+            .transfer_cancel(TransferCancelMsg {
+                shared_key_id,
+                statechain_sig: StateChainSig::new(
+                    &owner_priv.key,
+                    &"CANCEL".to_string(),
+                    &receiver_pub.to_string(),
+                )
+                .unwrap(),
+            })
+            .is_ok());
+    }
+
+    #[test]
+    fn test_expire_transfers() {
+        let statechain_ids = vec![Uuid::new_v4(), Uuid::new_v4()];
+        let (owner_priv, _) = shared_lib::util::keygen::generate_keypair();
+        let (_, receiver_pub) = shared_lib::util::keygen::generate_keypair();
+        let transfer_sig = StateChainSig::new(
+            &owner_priv.key,
+            &"TRANSFER".to_string(),
+            &receiver_pub.to_string(),
+        )
+        .unwrap();
+        let batch_sig = StateChainSig::new_transfer_batch_sig(
+            &owner_priv.key,
+            &Uuid::new_v4(),
+            &statechain_ids[1],
+        )
+        .unwrap();
+
+        let mut db = MockDatabase::new();
+        let ids = statechain_ids.clone();
+        db.expect_get_transfers_started_before()
+            .returning(move |_| Ok(ids.clone()));
+        let (id, sig) = (statechain_ids[0], transfer_sig.clone());
+        db.expect_get_transfer_data()
+            .with(predicate::eq(id))
+            .returning(move |_| {
+                Ok(TransferData {
+                    statechain_id: id,
+                    statechain_sig: sig.clone(),
+                    x1: FE::new_random(),
+                })
+            });
+        let (id, sig) = (statechain_ids[1], batch_sig.clone());
+        db.expect_get_transfer_data()
+            .with(predicate::eq(id))
+            .returning(move |_| {
+                Ok(TransferData {
+                    statechain_id: id,
+                    statechain_sig: sig.clone(),
+                    x1: FE::new_random(),
+                })
+            });
+        // Only the transfer which is not part of a batch is removed
+        db.expect_remove_transfer_data()
+            .with(predicate::eq(statechain_ids[0]))
+            .times(1)
+            .returning(|_| Ok(()));
+        // A previous transfer was aborted at locktime 900, below the current backup tx locktime
+        let mut tx_backup = serde_json::from_str::<Transaction>(&BACKUP_TX_NOT_SIGNED).unwrap();
+        tx_backup.lock_time = 1000;
+        db.expect_get_backup_transaction()
+            .with(predicate::eq(statechain_ids[0]))
+            .returning(move |_| Ok(tx_backup.clone()));
+        db.expect_get_aborted_locktime()
+            .with(predicate::eq(statechain_ids[0]))
+            .returning(|_| Ok(Some(900)));
+        db.expect_update_aborted_locktime()
+            .with(predicate::eq(statechain_ids[0]), predicate::eq(800))
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        assert_eq!(
+            expire_transfers(&db, 3600, 100, false).unwrap(),
+            vec![statechain_ids[0]]
+        );
+    }
+
+    #[test]
+    fn test_transfer_update_msg() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let other_statechain_id = Uuid::new_v4();
+        let (_, receiver_pub) = shared_lib::util::keygen::generate_keypair();
+        let msg = |statechain_id| TransferMsg3 {
+            shared_key_id,
+            t1: FESer::new_random(),
+            statechain_sig: StateChainSig::default(),
+            statechain_id,
+            tx_backup_psm: PrepareSignTxMsg::default(),
+            rec_se_addr: SCEAddress {
+                tx_backup_addr: None,
+                proof_key: receiver_pub.key,
+            },
+            key_type: KeyType::Ecdsa,
+        };
+
+        let mut db = MockDatabase::new();
+        db.expect_get_user_auth()
+            .returning(move |_| Ok(shared_key_id));
+        db.expect_get_statechain_id()
+            .with(predicate::eq(shared_key_id))
+            .returning(move |_| Ok(statechain_id));
+        db.expect_get_transfer_data()
+            .with(predicate::eq(statechain_id))
+            .returning(move |_| {
+                Ok(TransferData {
+                    statechain_id,
+                    statechain_sig: StateChainSig::default(),
+                    x1: FE::new_random(),
+                })
+            });
+        db.expect_update_transfer_msg()
+            .with(predicate::eq(statechain_id), predicate::always())
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let sc_entity = test_sc_entity(db);
+
+        // Message for a statechain not owned by the shared key
+        match sc_entity.transfer_update_msg(msg(other_statechain_id)) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not own State Chain ID")),
+        }
+
+        assert!(sc_entity.transfer_update_msg(msg(statechain_id)).is_ok());
+    }
 }
@@ -9,7 +9,7 @@ use super::transfer::Transfer;
 extern crate shared_lib;
 use crate::error::SEError;
 use crate::{server::StateChainEntity, Database};
-use shared_lib::{commitment::verify_commitment, state_chain::*, structs::*};
+use shared_lib::{commitment::verify_commitment, events::StateEntityEvent, state_chain::*, structs::*};
 
 use rocket_okapi::openapi;
 use cfg_if::cfg_if;
@@ -27,8 +27,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
     }
 }
 
@@ -115,6 +115,10 @@ impl BatchTransfer for SCE {
 
         self.database
             .update_transfer_batch_finalized(&batch_id, &true)?;
+        self.events.publish(
+            &batch_id.to_string(),
+            StateEntityEvent::TransferBatchFinalized { batch_id },
+        );
 
         Ok(())
     }
@@ -347,6 +351,7 @@ mod tests {
 
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
 
         let tfd : TransferFinalizeData = serde_json::from_str(TRANSFER_FINALIZE_DATA).unwrap();
 
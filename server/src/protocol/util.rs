@@ -4,36 +4,49 @@
 //! utility functions.
 
 pub use super::super::Result;
+use super::transfer::{abort_transfer, transfer_locktime_ceiling};
 use super::transfer_batch::{transfer_batch_is_ended, BatchTransfer};
+use super::withdraw::withdraw_input_index;
 extern crate shared_lib;
 use shared_lib::{
     mainstay::Attestable,
-    mocks::mock_electrum::MockElectrum,
+    musig::{get_shared_key_taproot_sighash, shared_key_script_pubkey},
     state_chain::*,
     structs::*,
-    util::{get_sighash, tx_withdraw_verify, transaction_deserialise},
+    util::{
+        blocks_from_sequence, get_p2wpkh_address, get_sighash, transaction_deserialise,
+        transaction_serialise, tx_withdraw_verify, tx_withdraw_verify_outputs,
+    },
     Root,
 };
 
+use shared_lib::events::StateEntityEvent;
+use shared_lib::request_auth::RequestAuth;
 use shared_lib::structs::Protocol;
 
 use rocket_okapi::openapi;
+use crate::chain::TxStatus;
 use crate::error::{DBErrorType, SEError};
 use crate::storage::Storage;
-use crate::{server::StateChainEntity, Database};
+use crate::{server::StateChainEntity, structs::FundingConfirmation, Database};
 use cfg_if::cfg_if;
 
-use electrumx_client::{electrumx_client::ElectrumxClient, interface::Electrumx};
 #[cfg(test)]
 use mockito::{mock, Matcher, Mock};
 pub use monotree::Proof;
 use rocket::State;
 use rocket_contrib::json::Json;
+use super::auth::SessionSigned;
 use std::str::FromStr;
 use uuid::Uuid;
-use bitcoin::OutPoint;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::{secp256k1, OutPoint, Script, Transaction, Txid};
+use curv::PK;
 
 const MAX_LOCKTIME: u32 = 500000000; // bitcoin tx nlocktime cutoff
+/// Blocks by which the locktime of a refresh back up tx may differ from the current block height
+/// plus lockheight_init
+const REFRESH_LOCKTIME_TOLERANCE: u64 = 1;
 
 //Generics cannot be used in Rocket State, therefore we define the concrete
 //type of StateChainEntity here
@@ -43,8 +56,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
     }
 }
 
@@ -84,10 +97,12 @@ impl Utilities for SCE {
     fn get_fees(&self) -> Result<StateEntityFeeInfoAPI> {
         Ok(StateEntityFeeInfoAPI {
             address: self.config.fee_address.clone(),
+            backup_fee_address: self.config.backup_fee_address().clone(),
             deposit: self.config.fee_deposit,
             withdraw: self.config.fee_withdraw,
             interval: self.config.lh_decrement,
             initlock: self.config.lockheight_init,
+            relative_locktime: self.config.relative_locktime,
         })
     }
 
@@ -215,10 +230,7 @@ impl Utilities for SCE {
         self.check_user_auth(&user_id)?;
 
         let tx = transaction_deserialise(&prepare_sign_msg.tx_hex)?;
-
-        // Verify unsigned withdraw tx to ensure co-sign will be signing the correct data
-        // calculate SE fee amount from rate
-        let withdraw_fee = (prepare_sign_msg.input_amounts[0] * self.config.fee_withdraw) / 10000 as u64;
+        let key_type = self.database.get_key_type(user_id)?;
 
         // Which protocol are we signing for?
         match prepare_sign_msg.protocol {
@@ -230,38 +242,59 @@ impl Utilities for SCE {
                     )));
                 }
 
+                // Input amounts are only bound by the sighash of the input being signed, so
+                // take the amount of every input in the batch from its statechain.
+                let batch = self.database.get_withdraw_batch(user_id)?;
+                if tx.input.len() != batch.len() {
+                    return Err(SEError::Generic(String::from(
+                        "Number of withdraw tx inputs != number of statecoins being withdrawn.",
+                    )));
+                }
+                let mut input_amounts = vec![0; tx.input.len()];
+                for batch_statechain_id in &batch {
+                    let tx_backup = self.database.get_backup_transaction(*batch_statechain_id)?;
+                    let index = withdraw_input_index(
+                        &tx,
+                        &self.get_funding_outpoint(batch_statechain_id, &tx_backup)?,
+                    )?;
+                    input_amounts[index] =
+                        self.database.get_statechain_amount(*batch_statechain_id)?.amount as u64;
+                }
+                if prepare_sign_msg.input_amounts != input_amounts {
+                    return Err(SEError::Generic(String::from(
+                        "Withdraw tx input amounts do not match statecoin amounts.",
+                    )));
+                }
+                // calculate SE fee amount from rate. A batch withdraw pays a single fee on the
+                // total value of its inputs.
+                let withdraw_fee =
+                    (input_amounts.iter().sum::<u64>() * self.config.fee_withdraw) / 10000 as u64;
+
                 tx_withdraw_verify(
                     &prepare_sign_msg,
                     &self.config.fee_address,
                     &withdraw_fee,
                 )?;
 
+                // The tx must pay exactly the outputs the owner's StateChainSig committed to
+                tx_withdraw_verify_outputs(
+                    &tx,
+                    &self.config.fee_address,
+                    &withdraw_fee,
+                    &self.database.get_withdraw_outputs(user_id)?,
+                )?;
+
                 let statechain_id = self.database.get_statechain_id(user_id)?;
                 let tx_backup = self.database.get_backup_transaction(statechain_id)?;
 
-                // Check funding txid UTXO info
-                let tx_backup_input = tx_backup.input.get(0).unwrap().previous_output.to_owned();
-                if tx
-                    .input
-                    .get(0)
-                    .unwrap()
-                    .previous_output
-                    .to_owned()
-                    != tx_backup_input
-                {
-                    return Err(SEError::Generic(String::from(
-                        "Incorrect withdraw transacton input.",
-                    )));
-                }
+                // Check funding txid UTXO info. Each shared key in a batch withdraw
+                // only signs the input spending its own statecoin.
+                let input_index =
+                    withdraw_input_index(&tx, &self.get_funding_outpoint(&statechain_id, &tx_backup)?)?;
 
                 // Update UserSession with withdraw tx info
-                let sig_hash = get_sighash(
-                    &tx,
-                    &0,
-                    &prepare_sign_msg.input_addrs[0],
-                    &prepare_sign_msg.input_amounts[0],
-                    &self.config.network,
-                );
+                let sig_hash =
+                    self.get_shared_key_sighash(&key_type, &tx, input_index, &prepare_sign_msg)?;
 
                 self.database.update_withdraw_tx_sighash(
                     &user_id,
@@ -270,7 +303,109 @@ impl Utilities for SCE {
                 )?;
 
                 info!(
-                    "WITHDRAW: Withdraw tx ready for signing. User ID: {:?}.",
+                    "WITHDRAW: Withdraw tx input {} ready for signing. User ID: {:?}.",
+                    input_index, user_id
+                );
+            }
+            Protocol::KickOff => {
+                if !self.config.relative_locktime {
+                    return Err(SEError::Generic(String::from(
+                        "Kick-off txs are only used in relative locktime mode.",
+                    )));
+                }
+
+                // Kick-off tx is signed once, during deposit
+                if let Ok(_) = self.database.get_statechain_id(user_id) {
+                    return Err(SEError::Generic(String::from(
+                        "Kick-off tx can only be signed during deposit.",
+                    )));
+                }
+
+                // Only one kick-off tx per deposit, so that every backup tx spends the same one
+                match self.database.get_user_kickoff_tx(user_id) {
+                    Ok(_) => {
+                        return Err(SEError::Generic(String::from(
+                            "Kick-off tx already signed for this deposit.",
+                        )))
+                    }
+                    Err(SEError::DBError(DBErrorType::NoDataForID, _)) => (),
+                    Err(e) => return Err(e),
+                }
+
+                // Verify unsigned kick-off tx spends the funding output back to the shared key
+                if tx.input.len() != 1
+                    || tx.output.len() != 1
+                    || prepare_sign_msg.input_addrs.len() != 1
+                    || prepare_sign_msg.input_amounts.len() != 1
+                {
+                    return Err(SEError::Generic(String::from(
+                        "Kick-off tx must have one input and one output.",
+                    )));
+                }
+                let p_script_pubkey =
+                    self.shared_key_script_pubkey(&key_type, &prepare_sign_msg.input_addrs[0])?;
+                if tx.output[0].script_pubkey != p_script_pubkey
+                    || tx.output[0].value > prepare_sign_msg.input_amounts[0]
+                {
+                    return Err(SEError::Generic(String::from(
+                        "Kick-off tx output does not pay to the shared key.",
+                    )));
+                }
+
+                let sig_hash = self.get_shared_key_sighash(&key_type, &tx, 0, &prepare_sign_msg)?;
+
+                self.database.update_sighash(&user_id, sig_hash)?;
+                self.database.update_user_kickoff_tx(&user_id, tx)?;
+
+                info!(
+                    "DEPOSIT: Kick-off tx ready for signing. Shared Key ID: {}.",
+                    user_id
+                );
+            }
+            Protocol::Refresh => {
+                // Verify refresh has been authorised via presense of refresh_sc_sig
+                if let Err(_) = self.database.has_refresh_sc_sig(user_id) {
+                    return Err(SEError::Generic(String::from(
+                        "Refresh has not been authorised. /refresh/init must be called first.",
+                    )));
+                }
+
+                // Verify unsigned refresh tx spends the funding output back to the shared key
+                if tx.input.len() != 1
+                    || tx.output.len() != 1
+                    || prepare_sign_msg.input_addrs.len() != 1
+                    || prepare_sign_msg.input_amounts.len() != 1
+                {
+                    return Err(SEError::Generic(String::from(
+                        "Refresh tx must have one input and one output.",
+                    )));
+                }
+
+                let statechain_id = self.database.get_statechain_id(user_id)?;
+                let tx_backup = self.database.get_backup_transaction(statechain_id)?;
+                if tx.input[0].previous_output != self.get_funding_outpoint(&statechain_id, &tx_backup)? {
+                    return Err(SEError::Generic(String::from(
+                        "Refresh tx does not spend the statecoin funding output.",
+                    )));
+                }
+
+                let p_script_pubkey =
+                    self.shared_key_script_pubkey(&key_type, &prepare_sign_msg.input_addrs[0])?;
+                if tx.output[0].script_pubkey != p_script_pubkey
+                    || tx.output[0].value > prepare_sign_msg.input_amounts[0]
+                {
+                    return Err(SEError::Generic(String::from(
+                        "Refresh tx output does not pay to the shared key.",
+                    )));
+                }
+
+                let sig_hash = self.get_shared_key_sighash(&key_type, &tx, 0, &prepare_sign_msg)?;
+
+                self.database.update_sighash(&user_id, sig_hash)?;
+                self.database.update_user_refresh_tx(&user_id, tx)?;
+
+                info!(
+                    "REFRESH: Refresh tx ready for signing. Shared Key ID: {}.",
                     user_id
                 );
             }
@@ -282,27 +417,34 @@ impl Utilities for SCE {
                     )));
                 }
 
-                //check that the locktime is height and not epoch
-                if (tx.lock_time as u32) >= MAX_LOCKTIME {
-                    return Err(SEError::Generic(String::from(
-                        "Backup tx locktime specified as Unix epoch time not block height.",
-                    )));
+                if self.config.relative_locktime {
+                    self.verify_backup_tx_sequence(&user_id, &prepare_sign_msg.protocol, &tx)?;
+                } else {
+                    //check that the locktime is height and not epoch
+                    if (tx.lock_time as u32) >= MAX_LOCKTIME {
+                        return Err(SEError::Generic(String::from(
+                            "Backup tx locktime specified as Unix epoch time not block height.",
+                        )));
+                    }
                 }
 
-                //check withdrawal fee is correctly set
+                //calculate SE fee amount from rate and check withdrawal fee is correctly set
+                let withdraw_fee = (prepare_sign_msg.input_amounts.iter().sum::<u64>()
+                    * self.config.fee_withdraw)
+                    / 10000 as u64;
                 tx_withdraw_verify(
                     &prepare_sign_msg,
-                    &self.config.fee_address,
+                    self.config.backup_fee_address(),
                     &withdraw_fee,
                 )?;
 
                 //for transfer (not deposit)
-                if prepare_sign_msg.protocol == Protocol::Transfer {
+                if prepare_sign_msg.protocol == Protocol::Transfer && !self.config.relative_locktime {
                     //verify transfer locktime is correct
                     let statechain_id = self.database.get_statechain_id(user_id)?;
-                    let current_tx_backup = self.database.get_backup_transaction(statechain_id)?;
+                    let locktime = transfer_locktime_ceiling(&*self.database, statechain_id, false)?;
 
-                    if (current_tx_backup.lock_time as u32) != (tx.lock_time as u32) + (self.config.lh_decrement as u32) {
+                    if locktime != (tx.lock_time as u32) + (self.config.lh_decrement as u32) {
                         return Err(SEError::Generic(String::from(
                             "Backup tx locktime not correctly decremented.",
                         )));
@@ -310,18 +452,19 @@ impl Utilities for SCE {
 
                 }
 
-                let sig_hash = get_sighash(
-                    &tx,
-                    &0,
-                    &prepare_sign_msg.input_addrs[0],
-                    &prepare_sign_msg.input_amounts[0],
-                    &self.config.network,
-                );
+                //for refresh, the new backup tx must spend the refresh tx
+                if prepare_sign_msg.protocol == Protocol::RefreshBackup {
+                    self.verify_refresh_backup_tx(&user_id, &tx)?;
+                }
+
+                let sig_hash = self.get_shared_key_sighash(&key_type, &tx, 0, &prepare_sign_msg)?;
 
                 self.database.update_sighash(&user_id, sig_hash)?;
 
-                // Only in deposit case add backup tx to UserSession
-                if prepare_sign_msg.protocol == Protocol::Deposit {
+                // Only in deposit and refresh case add backup tx to UserSession
+                if prepare_sign_msg.protocol == Protocol::Deposit
+                    || prepare_sign_msg.protocol == Protocol::RefreshBackup
+                {
                     self.database
                         .update_user_backup_tx(&user_id, tx)?;
                 }
@@ -360,6 +503,21 @@ pub fn get_statechain(
     }
 }
 
+#[openapi]
+/// # Get the backup tx closure of a statechain, if its funding output has been spent by a backup tx
+#[get("/info/statechain/closure/<statechain_id>", format = "json")]
+pub fn get_statechain_closure(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Json<Option<StateChainClosureAPI>>> {
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain ID: {}", e)))?;
+    match sc_entity.get_statechain_closure(statechain_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
 #[openapi]
 /// # Get the current Sparse Merkle Tree commitment root
 #[get("/info/root", format = "json")]
@@ -401,7 +559,7 @@ pub fn get_transfer_batch_status(
 #[post("/prepare-sign", format = "json", data = "<prepare_sign_msg>")]
 pub fn prepare_sign_tx(
     sc_entity: State<SCE>,
-    prepare_sign_msg: Json<PrepareSignTxMsg>,
+    prepare_sign_msg: SessionSigned<PrepareSignTxMsg>,
 ) -> Result<Json<()>> {
     match sc_entity.prepare_sign_tx(prepare_sign_msg.into_inner()) {
         Ok(res) => return Ok(Json(res)),
@@ -425,39 +583,302 @@ pub fn reset_test_dbs(sc_entity: State<SCE>) -> Result<Json<()>> {
 
 
 // Utily functions for StateChainEntity to be used throughout codebase.
+/// Lock a state chain for punishment_duration seconds
+pub fn punish_statechain<T: Database>(
+    db: &T,
+    statechain_id: Uuid,
+    punishment_duration: u64,
+) -> Result<()> {
+    let sc_locked_until = db.get_sc_locked_until(statechain_id)?;
+
+    if is_locked(sc_locked_until).is_err() {
+        return Err(SEError::Generic(String::from(
+            "State chain is already locked. This should not be possible.",
+        )));
+    }
+
+    db.update_locked_until(
+        &statechain_id,
+        &get_locked_until(punishment_duration as i64)?,
+    )?;
+
+    info!(
+        "PUNISHMENT: State Chain ID: {} locked for {}s.",
+        statechain_id, punishment_duration
+    );
+    Ok(())
+}
+
 impl SCE {
-    /// Query an Electrum Server for a transaction's confirmation status.
-    /// Return Ok() if confirmed or Error if not within configured confirmation number.
-    pub fn verify_tx_confirmed(&self, txid: &String) -> Result<()> {
-        let mut electrum: Box<dyn Electrumx> = if self.config.testing_mode {
-            Box::new(MockElectrum::new())
+    /// Add the witness of a co-signed input to the tx validated by prepare_sign_tx and store the
+    /// signed tx. Returns the witness, which for withdraw txs is withheld until /withdraw/confirm.
+    pub fn store_signed_tx(
+        &self,
+        user_id: &Uuid,
+        protocol: &Protocol,
+        witness: Vec<Vec<u8>>,
+    ) -> Result<Vec<Vec<u8>>> {
+        let db = &self.database;
+        let user_id = *user_id;
+
+        // Get transaction which is being signed.
+        let mut tx: Transaction = match protocol {
+            Protocol::Withdraw => db.get_tx_withdraw(user_id)?,
+            Protocol::KickOff => db.get_user_kickoff_tx(user_id)?,
+            Protocol::Refresh => db.get_user_refresh_tx(user_id)?,
+            _ => db.get_user_backup_tx(user_id)?,
+        };
+
+        // Add signature to tx. Withdraw txs may spend several statecoins so find the input
+        // spending this user's statecoin.
+        let input_index = match protocol {
+            Protocol::Withdraw => {
+                let statechain_id = db.get_statechain_id(user_id)?;
+                let tx_backup = db.get_backup_transaction(statechain_id)?;
+                withdraw_input_index(&tx, &self.get_funding_outpoint(&statechain_id, &tx_backup)?)?
+            }
+            _ => 0,
+        };
+        tx.input[input_index].witness = witness.clone();
+
+        match protocol {
+            Protocol::Withdraw => {
+                // Store signed withdraw tx in UserSession DB object
+                db.update_tx_withdraw(user_id, tx)?;
+
+                info!("WITHDRAW: Tx signed and stored. User ID: {}", user_id);
+                // Do not return withdraw tx witness until /withdraw/confirm is complete
+                return Ok(vec![]);
+            }
+            Protocol::KickOff => {
+                // Store signed kick-off tx in UserSession DB object
+                db.update_user_kickoff_tx(&user_id, tx)?;
+                info!("DEPOSIT: Kick-off Tx signed and stored. User: {}", user_id);
+            }
+            Protocol::Refresh => {
+                // The signed refresh tx can spend the funding output. Block transfers, withdraws
+                // and swaps of the statechain until /refresh/confirm.
+                let statechain_id = db.get_statechain_id(user_id)?;
+                db.update_refresh_pending(&statechain_id, &tx.txid())?;
+                // Store signed refresh tx in UserSession DB object
+                db.update_user_refresh_tx(&user_id, tx)?;
+                info!("REFRESH: Refresh Tx signed and stored. User: {}", user_id);
+            }
+            _ => {
+                // Store signed backup tx in UserSession DB object
+                db.update_user_backup_tx(&user_id, tx)?;
+                info!(
+                    "DEPOSIT/TRANSFER: Backup Tx signed and stored. User: {}",
+                    user_id
+                );
+            }
+        };
+
+        Ok(witness)
+    }
+
+    /// Script pubkey of the funding output of a shared key: P2WPKH for 2P-ECDSA keys and key path
+    /// only P2TR for MuSig2 keys
+    fn shared_key_script_pubkey(&self, key_type: &KeyType, input_addr: &PK) -> Result<Script> {
+        match key_type {
+            KeyType::Ecdsa => Ok(get_p2wpkh_address(input_addr, &self.config.network)?.script_pubkey()),
+            KeyType::Taproot => Ok(shared_key_script_pubkey(input_addr)?),
+        }
+    }
+
+    /// Sighash of input input_index of a tx spending the funding outputs of the shared keys
+    /// input_addrs: BIP143 for 2P-ECDSA keys and BIP341 for MuSig2 keys
+    fn get_shared_key_sighash(
+        &self,
+        key_type: &KeyType,
+        tx: &Transaction,
+        input_index: usize,
+        prepare_sign_msg: &PrepareSignTxMsg,
+    ) -> Result<sha256d::Hash> {
+        if input_index >= prepare_sign_msg.input_addrs.len()
+            || input_index >= prepare_sign_msg.input_amounts.len()
+        {
+            return Err(SEError::Generic(String::from(
+                "No signing address or amount for the input being signed.",
+            )));
+        }
+        match key_type {
+            KeyType::Ecdsa => Ok(get_sighash(
+                tx,
+                &input_index,
+                &prepare_sign_msg.input_addrs[input_index],
+                &prepare_sign_msg.input_amounts[input_index],
+                &self.config.network,
+            )),
+            KeyType::Taproot => Ok(sha256d::Hash::from_inner(get_shared_key_taproot_sighash(
+                tx,
+                &input_index,
+                &prepare_sign_msg.input_addrs,
+                &prepare_sign_msg.input_amounts,
+            )?)),
+        }
+    }
+
+    /// Outpoint of the funding tx output of a statechain, as stored on the statechain at deposit
+    /// and refresh. Statechains created before it was stored fall back to the tx spending it: in
+    /// relative locktime mode backup txs spend the statechain's kick-off tx, which spends the
+    /// funding tx output.
+    pub fn get_funding_outpoint(&self, statechain_id: &Uuid, tx_backup: &Transaction) -> Result<OutPoint> {
+        if let Some(outpoint) = self.database.get_funding_outpoint(*statechain_id)? {
+            return Ok(outpoint);
+        }
+        if self.config.relative_locktime {
+            let tx_kickoff = self.database.get_kickoff_transaction(*statechain_id)?;
+            return Ok(tx_kickoff.input[0].previous_output);
+        }
+        Ok(tx_backup.input[0].previous_output)
+    }
+
+    /// A statechain cannot be transferred, withdrawn or swapped once its refresh tx has been
+    /// signed until /refresh/confirm completes, as the owner holds a refresh tx spending the
+    /// funding output and a back up tx spending the refresh tx.
+    pub fn check_no_refresh_pending(&self, statechain_id: &Uuid) -> Result<()> {
+        if let Some(txid) = self.database.get_refresh_pending(*statechain_id)? {
+            return Err(SEError::Generic(format!(
+                "Statecoin has a pending refresh. Refresh tx {} must be confirmed first.",
+                txid
+            )));
+        }
+        Ok(())
+    }
+
+    /// Verify the relative locktime of a backup tx spending a kick-off tx. A deposit backup tx
+    /// must spend the deposit's kick-off tx and a transfer backup tx must spend the same kick-off
+    /// tx as the current backup tx with its relative locktime decremented.
+    fn verify_backup_tx_sequence(&self, user_id: &Uuid, protocol: &Protocol, tx: &Transaction) -> Result<()> {
+        // BIP68 relative locktimes require tx version 2
+        if tx.version < 2 || tx.input.len() != 1 {
+            return Err(SEError::Generic(String::from(
+                "Backup tx must be version 2 with a single input.",
+            )));
+        }
+        let blocks = match blocks_from_sequence(&tx.input[0].sequence) {
+            Some(blocks) => blocks,
+            None => {
+                return Err(SEError::Generic(String::from(
+                    "Backup tx nSequence is not a relative locktime in blocks.",
+                )))
+            }
+        };
+
+        if *protocol == Protocol::Deposit {
+            let tx_kickoff = match self.database.get_user_kickoff_tx(*user_id) {
+                Ok(tx_kickoff) => tx_kickoff,
+                Err(_) => {
+                    return Err(SEError::Generic(String::from(
+                        "Kick-off tx must be signed before the backup tx.",
+                    )))
+                }
+            };
+            if tx.input[0].previous_output != (OutPoint { txid: tx_kickoff.txid(), vout: 0 }) {
+                return Err(SEError::Generic(String::from(
+                    "Backup tx does not spend the kick-off tx.",
+                )));
+            }
+            if blocks != self.config.lockheight_init {
+                return Err(SEError::Generic(String::from(
+                    "Backup tx relative locktime is not the initial locktime.",
+                )));
+            }
         } else {
-            Box::new(ElectrumxClient::new(self.config.electrum_server.clone()).unwrap())
+            let statechain_id = self.database.get_statechain_id(*user_id)?;
+            let current_tx_backup = self.database.get_backup_transaction(statechain_id)?;
+            if tx.input[0].previous_output != current_tx_backup.input[0].previous_output {
+                return Err(SEError::Generic(String::from(
+                    "Backup tx does not spend the kick-off tx.",
+                )));
+            }
+            let current_blocks = transfer_locktime_ceiling(&*self.database, statechain_id, true)?;
+            if current_blocks != blocks + self.config.lh_decrement {
+                return Err(SEError::Generic(String::from(
+                    "Backup tx relative locktime not correctly decremented.",
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify that the backup tx of a refresh spends the signed refresh tx with the initial
+    /// locktime from the current block height. The owner's chain tip may differ from the SE's by
+    /// REFRESH_LOCKTIME_TOLERANCE blocks.
+    fn verify_refresh_backup_tx(&self, user_id: &Uuid, tx: &Transaction) -> Result<()> {
+        let tx_refresh = match self.database.get_user_refresh_tx(*user_id) {
+            Ok(tx_refresh) => tx_refresh,
+            Err(_) => {
+                return Err(SEError::Generic(String::from(
+                    "Refresh tx must be signed before the backup tx.",
+                )))
+            }
         };
+        if tx.input.len() != 1
+            || tx.input[0].previous_output != (OutPoint { txid: tx_refresh.txid(), vout: 0 })
+        {
+            return Err(SEError::Generic(String::from(
+                "Backup tx does not spend the refresh tx.",
+            )));
+        }
 
+        let init_locktime = self.chain.lock()?.get_block_height()? + self.config.lockheight_init as u64;
+        let lock_time = tx.lock_time as u64;
+        if lock_time + REFRESH_LOCKTIME_TOLERANCE < init_locktime
+            || lock_time > init_locktime + REFRESH_LOCKTIME_TOLERANCE
+        {
+            return Err(SEError::Generic(format!(
+                "Refresh backup tx locktime must be the initial locktime {}.",
+                init_locktime
+            )));
+        }
+        Ok(())
+    }
+
+    /// Query the chain backend for a transaction's confirmation status.
+    /// Return the block containing the tx if confirmed or Error if not within configured
+    /// confirmation number.
+    pub fn verify_tx_confirmed(&self, txid: &String) -> Result<FundingConfirmation> {
         info!(
             "DEPOSIT: Verifying funding transaction confirmation. Txid: {}",
             txid
         );
 
-        match electrum.get_transaction_conf_status(txid.clone(), false) {
-            Ok(res) => {
-                // Check for tx confs. If none after 10*(block time) then return error.
-                if res.confirmations.is_none() {
-                    return Err(SEError::Generic(String::from(
-                        "Funding Transaction not confirmed.",
-                    )));
-                } 
-                else if res.confirmations.unwrap() < self.config.required_confirmation {
+        let txid = match Txid::from_str(txid) {
+            Ok(txid) => txid,
+            Err(_) => {
+                return Err(SEError::Generic(String::from(
+                    "Funding Transaction not found.",
+                )))
+            }
+        };
+        let mut chain = self.chain.lock()?;
+        let status = chain.get_tx_status(&txid);
+
+        match status {
+            Ok(TxStatus::Confirmed(confirmations)) => {
+                if confirmations < self.config.required_confirmation {
                     return Err(SEError::Generic(String::from(
                         "Funding Transaction insufficient confirmations.",
-                    )));                    
+                    )));
                 }
                 else {
-                    return Ok(());
+                    // Block is re-verified until final in case of a reorg
+                    return Ok(FundingConfirmation {
+                        txid,
+                        block: chain.get_tx_block(&txid)?,
+                        locked_until: None,
+                    });
                 }
             }
-            Err(_) => {
+            // Check for tx confs. If none after 10*(block time) then return error.
+            Ok(TxStatus::Unconfirmed) => {
+                return Err(SEError::Generic(String::from(
+                    "Funding Transaction not confirmed.",
+                )));
+            }
+            Ok(TxStatus::Unknown) | Err(_) => {
                 return Err(SEError::Generic(String::from(
                     "Funding Transaction not found.",
                 )));
@@ -467,30 +888,50 @@ impl SCE {
 
     // Set state chain time-out
     pub fn state_chain_punish(&self, statechain_id: Uuid) -> Result<()> {
-        let sc_locked_until = self.database.get_sc_locked_until(statechain_id)?;
+        punish_statechain(
+            &*self.database,
+            statechain_id,
+            self.config.punishment_duration,
+        )
+    }
 
-        if is_locked(sc_locked_until).is_err() {
-            return Err(SEError::Generic(String::from(
-                "State chain is already locked. This should not be possible.",
-            )));
+    /// Check the session user_id exists. This does not authenticate the request: callers are
+    /// only reached through SessionSigned routes, which check the request is signed by the
+    /// session's proof key with check_session_auth.
+    pub fn check_user_auth(&self, user_id: &Uuid) -> Result<()> {
+        // check session id is in DB
+        if let Err(_) = self.database.get_user_auth(*user_id) {
+            return Err(SEError::AuthError);
         }
-
-        self.database.update_locked_until(
-            &statechain_id,
-            &get_locked_until(self.config.punishment_duration as i64)?,
-        )?;
-
-        info!(
-            "PUNISHMENT: State Chain ID: {} locked for {}s.",
-            statechain_id, self.config.punishment_duration
-        );
         Ok(())
     }
 
-    /// Check if user has passed authentication.
-    pub fn check_user_auth(&self, user_id: &Uuid) -> Result<()> {
-        // check authorisation id is in DB (and TOOD: check password?)
-        if let Err(_) = self.database.get_user_auth(*user_id) {
+    /// Check a request to path is signed by the proof key of each session in user_ids, in
+    /// order, and is not a replay.
+    pub fn check_session_auth(
+        &self,
+        user_ids: &[Uuid],
+        auth: &RequestAuth,
+        path: &str,
+        body: &[u8],
+    ) -> Result<()> {
+        let mut proof_keys = Vec::new();
+        for user_id in user_ids {
+            let proof_key = match self.database.get_proof_key(*user_id) {
+                Ok(k) => k,
+                Err(_) => return Err(SEError::AuthError),
+            };
+            match secp256k1::PublicKey::from_str(&proof_key) {
+                Ok(k) => proof_keys.push(k),
+                Err(_) => return Err(SEError::AuthError),
+            }
+        }
+        if let Err(e) = auth.verify_multi(&proof_keys, path, body) {
+            warn!("AUTH: Invalid session signature: {}", e);
+            return Err(SEError::AuthError);
+        }
+        if let Err(e) = self.request_nonces.lock()?.check(auth) {
+            warn!("AUTH: {}", e);
             return Err(SEError::AuthError);
         }
         Ok(())
@@ -500,16 +941,23 @@ impl SCE {
         let tbd = self.database.get_transfer_batch_data(batch_id)?;
         let mut finalized = tbd.finalized;
         if !finalized {
+            // Batches cancelled by the operator end before their lifetime and are not finalized
+            let ended = !tbd.punished_state_chains.is_empty();
+            if ended && !transfer_batch_is_ended(tbd.start_time, self.config.batch_lifetime as i64) {
+                return Err(SEError::TransferBatchEnded(String::from("Cancelled")));
+            }
             // Attempt to finalize transfers - will fail with Err if not all ready to be finalized
-            match self.finalize_batch(batch_id){
-                Ok(_) => {
+            if !ended {
+                match self.finalize_batch(batch_id) {
+                    Ok(_) => {
                         info!(
-                        "TRANSFER_BATCH: All transfers complete in batch. Finalized. ID: {}.",
-                        batch_id
+                            "TRANSFER_BATCH: All transfers complete in batch. Finalized. ID: {}.",
+                            batch_id
                         );
                         finalized = true;
-                    },
-                Err(_) => (),
+                    }
+                    Err(_) => (),
+                }
             }
             // Check batch is still within lifetime
             if transfer_batch_is_ended(tbd.start_time, self.config.batch_lifetime as i64) {
@@ -524,8 +972,15 @@ impl SCE {
                         self.state_chain_punish(statechain_id.clone())?;
                         punished_state_chains.push(statechain_id.clone());
 
-                        // Remove TransferData involved. Ignore failed update err since Transfer data may not exist.
-                        let _ = self.database.remove_transfer_data(&statechain_id);
+                        // Remove TransferData involved. Transfer data may not exist.
+                        if self.database.get_transfer_data(statechain_id).is_ok() {
+                            abort_transfer(
+                                &*self.database,
+                                &statechain_id,
+                                self.config.lh_decrement,
+                                self.config.relative_locktime,
+                            )?;
+                        }
 
                         info!(
                             "TRANSFER_BATCH: Transfer data deleted. State Chain ID: {}.",
@@ -534,7 +989,14 @@ impl SCE {
                     }
 
                     self.database
-                        .update_punished(&batch_id, punished_state_chains)?;
+                        .update_punished(&batch_id, punished_state_chains.clone())?;
+                    self.events.publish(
+                        &batch_id.to_string(),
+                        StateEntityEvent::TransferBatchEnded {
+                            batch_id,
+                            punished_state_chains,
+                        },
+                    );
 
                     info!(
                         "TRANSFER_BATCH: Punished all state chains in failed batch. ID: {}.",
@@ -609,7 +1071,7 @@ impl<T: Database + Send + Sync + 'static, D: monotree::Database + Send + Sync +
             Commitment, CommitmentIndexed, CommitmentInfo, MainstayAPIError,
         };
 
-        let db = &self.database;
+        let db = &*self.database;
 
         fn update_db_from_ci<U: Database>(db: &U, ci: &CommitmentInfo) -> Result<Option<Root>> {
             let mut root = Root::from_commitment_info(ci);
@@ -770,20 +1232,53 @@ impl<T: Database + Send + Sync + 'static, D: monotree::Database + Send + Sync +
                         utxo: OutPoint::null(),
                         chain: state_chain.chain.chain,
                         locktime: 0 as u32,
+                        tx_kickoff_hex: None,
+                        aborted_locktime: None,
                     }});
                 }
             }
 
+        // Closed by a confirmed backup tx: the funding output has been spent
+        if state_chain.amount == 0 {
+            return Ok({StateChainDataAPI {
+                amount: 0,
+                utxo: OutPoint::null(),
+                chain: state_chain.chain.chain,
+                locktime: 0 as u32,
+                tx_kickoff_hex: None,
+                aborted_locktime: None,
+            }});
+        }
+
         let tx_backup = self.database.get_backup_transaction(statechain_id)?;
+        let aborted_locktime = self.database.get_aborted_locktime(statechain_id)?;
+
+        if self.config.relative_locktime {
+            let tx_kickoff = self.database.get_kickoff_transaction(statechain_id)?;
+            return Ok({StateChainDataAPI {
+                amount: state_chain.amount as u64,
+                utxo: tx_kickoff.input.get(0).unwrap().previous_output,
+                chain: state_chain.chain.chain,
+                locktime: blocks_from_sequence(&tx_backup.input[0].sequence).unwrap_or(0),
+                tx_kickoff_hex: Some(transaction_serialise(&tx_kickoff)),
+                aborted_locktime,
+            }});
+        }
 
         return Ok({StateChainDataAPI {
             amount: state_chain.amount as u64,
             utxo: tx_backup.input.get(0).unwrap().previous_output,
             chain: state_chain.chain.chain,
             locktime: tx_backup.lock_time,
+            tx_kickoff_hex: None,
+            aborted_locktime,
         }});
     }
 
+    fn get_statechain_closure(&self, statechain_id: Uuid) -> Result<Option<StateChainClosureAPI>> {
+        self.database.get_statechain_closure(statechain_id)
+    }
+
     //fn authorise_withdrawal(&self, user_id: &Uuid, signature: StateChainSig) -> Result<()>;
 
     // /withdraw/confirm
@@ -892,13 +1387,25 @@ pub mod tests {
     pub static STATE_CHAIN: &str = "{\"chain\":[{\"data\":\"026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e\",\"next_state\":null}]}";
     pub static STATE_CHAIN_SIG: &str = "{ \"purpose\": \"TRANSFER\", \"data\": \"026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e\", \"sig\": \"3045022100abe02f0d1918aca36b634eb1af8a4e0714f3f699fb425de65cc661e538da3f2002200a538a22df665a95adb739ff6bb592b152dba5613602c453c58adf70858f05f6\"}";
 
-    pub fn test_sc_entity(db: MockDatabase) -> SCE {
+    pub fn test_sc_entity(mut db: MockDatabase) -> SCE {
+        db.expect_get_swap_registrations().returning(|| Ok(vec![]));
+        db.expect_get_swaps().returning(|| Ok(vec![]));
         let mut sc_entity = SCE::load(db, MemoryDB::new("")).unwrap();
         sc_entity.config.testing_mode = true;
         sc_entity.config.mainstay = Some(mainstay::MainstayConfig::mock_from_url(&test_url()));
         sc_entity
     }
 
+    #[test]
+    fn test_sc_entity_load_swaps_error() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_swap_registrations().returning(|| Ok(vec![]));
+        db.expect_get_swaps()
+            .returning(|| Err(SEError::Generic(String::from("invalid swap data"))));
+        assert!(SCE::load(db, MemoryDB::new("")).is_err());
+    }
+
     fn test_url() -> String {
         String::from(&mockito::server_url())
     }
@@ -1007,4 +1514,537 @@ pub mod tests {
 
         assert_eq!(new_root.hash(), hash_exp, "new root incorrect");
     }
+
+    #[test]
+    fn test_prepare_sign_tx_relative_locktime() {
+        use bitcoin::Address;
+        use mockall::predicate;
+        use shared_lib::util::{tx_backup_build_relative, tx_kickoff_build};
+
+        let kickoff_user_id = Uuid::new_v4();
+        let deposit_user_id = Uuid::new_v4();
+        let transfer_user_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let pk: curv::PK = serde_json::from_str(
+            "\"026cc37050561379a66a863f8ca273c2b29e935cad06bc7f5e6b83a03e0bbff1e6\"",
+        )
+        .unwrap();
+        let p_address = get_p2wpkh_address(&pk, &String::from("regtest")).unwrap();
+        let fee_address = String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x");
+
+        let tx_kickoff = tx_kickoff_build(&OutPoint::default(), &p_address, &10000).unwrap();
+        let kickoff_amount = tx_kickoff.output[0].value;
+        // Withdraw fee of 40 basis points of the kick-off output
+        let withdraw_fee = kickoff_amount * 40 / 10000;
+        let tx_backup =
+            tx_backup_build_relative(&tx_kickoff, &p_address, &10000, &withdraw_fee, &fee_address)
+                .unwrap();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
+        db.expect_get_user_auth().returning(|id| Ok(id));
+        db.expect_get_statechain_id()
+            .with(predicate::ne(transfer_user_id))
+            .returning(|_| {
+                Err(SEError::DBError(
+                    DBErrorType::NoDataForID,
+                    String::from("item not found"),
+                ))
+            });
+        db.expect_get_statechain_id()
+            .with(predicate::eq(transfer_user_id))
+            .returning(move |_| Ok(statechain_id));
+        db.expect_get_key_type().returning(|_| Ok(KeyType::Ecdsa));
+        // Kick-off tx of kickoff_user_id is not yet signed
+        db.expect_get_user_kickoff_tx()
+            .with(predicate::eq(kickoff_user_id))
+            .returning(|_| {
+                Err(SEError::DBError(
+                    DBErrorType::NoDataForID,
+                    String::from("item not found"),
+                ))
+            });
+        let tx_kickoff_clone = tx_kickoff.clone();
+        db.expect_get_user_kickoff_tx()
+            .with(predicate::ne(kickoff_user_id))
+            .returning(move |_| Ok(tx_kickoff_clone.clone()));
+        let tx_backup_clone = tx_backup.clone();
+        db.expect_get_backup_transaction()
+            .returning(move |_| Ok(tx_backup_clone.clone()));
+        db.expect_get_aborted_locktime().returning(|_| Ok(None));
+        db.expect_update_sighash().returning(|_, _| Ok(()));
+        db.expect_update_user_kickoff_tx().returning(|_, _| Ok(()));
+        db.expect_update_user_backup_tx().returning(|_, _| Ok(()));
+
+        let mut sc_entity = test_sc_entity(db);
+        sc_entity.config.fee_address = fee_address.clone();
+        sc_entity.config.fee_withdraw = 40;
+        sc_entity.config.lh_decrement = 100;
+        sc_entity.config.lockheight_init = 10000;
+
+        let kickoff_msg = PrepareSignTxMsg {
+            shared_key_id: kickoff_user_id,
+            protocol: Protocol::KickOff,
+            tx_hex: transaction_serialise(&tx_kickoff),
+            input_addrs: vec![pk],
+            input_amounts: vec![10000],
+            proof_key: None,
+        };
+        let backup_msg = |user_id: Uuid, protocol: Protocol, tx: &Transaction| PrepareSignTxMsg {
+            shared_key_id: user_id,
+            protocol,
+            tx_hex: transaction_serialise(tx),
+            input_addrs: vec![pk],
+            input_amounts: vec![kickoff_amount],
+            proof_key: None,
+        };
+
+        // Kick-off txs are only signed in relative locktime mode
+        match sc_entity.prepare_sign_tx(kickoff_msg.clone()) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("only used in relative locktime mode")),
+        }
+        sc_entity.config.relative_locktime = true;
+        assert!(sc_entity.prepare_sign_tx(kickoff_msg.clone()).is_ok());
+
+        // Kick-off tx must pay to the shared key
+        let mut kickoff_msg_bad = kickoff_msg.clone();
+        let mut tx_kickoff_bad = tx_kickoff.clone();
+        tx_kickoff_bad.output[0].script_pubkey =
+            Address::from_str(&fee_address).unwrap().script_pubkey();
+        kickoff_msg_bad.tx_hex = transaction_serialise(&tx_kickoff_bad);
+        match sc_entity.prepare_sign_tx(kickoff_msg_bad) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not pay to the shared key")),
+        }
+
+        // Kick-off tx can not be re-signed once the statechain exists
+        let mut kickoff_msg_transfer = kickoff_msg.clone();
+        kickoff_msg_transfer.shared_key_id = transfer_user_id;
+        match sc_entity.prepare_sign_tx(kickoff_msg_transfer) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("only be signed during deposit")),
+        }
+
+        // A second kick-off tx can not be signed for the same deposit
+        let mut kickoff_msg_second = kickoff_msg.clone();
+        kickoff_msg_second.shared_key_id = deposit_user_id;
+        match sc_entity.prepare_sign_tx(kickoff_msg_second) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Kick-off tx already signed")),
+        }
+
+        // Deposit backup tx spends the kick-off tx with a relative locktime
+        assert!(sc_entity
+            .prepare_sign_tx(backup_msg(deposit_user_id, Protocol::Deposit, &tx_backup))
+            .is_ok());
+        let mut tx_backup_absolute = tx_backup.clone();
+        tx_backup_absolute.input[0].sequence = 0xFFFFFFFF;
+        match sc_entity.prepare_sign_tx(backup_msg(
+            deposit_user_id,
+            Protocol::Deposit,
+            &tx_backup_absolute,
+        )) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("not a relative locktime in blocks")),
+        }
+        let mut tx_backup_funding = tx_backup.clone();
+        tx_backup_funding.input[0].previous_output = OutPoint::default();
+        match sc_entity.prepare_sign_tx(backup_msg(
+            deposit_user_id,
+            Protocol::Deposit,
+            &tx_backup_funding,
+        )) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not spend the kick-off tx")),
+        }
+
+        // Deposit backup tx relative locktime is lockheight_init
+        let tx_backup_short =
+            tx_backup_build_relative(&tx_kickoff, &p_address, &100, &withdraw_fee, &fee_address)
+                .unwrap();
+        match sc_entity.prepare_sign_tx(backup_msg(
+            deposit_user_id,
+            Protocol::Deposit,
+            &tx_backup_short,
+        )) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("not the initial locktime")),
+        }
+
+        // Transfer backup tx relative locktime is decremented by lh_decrement
+        let tx_backup_transfer =
+            tx_backup_build_relative(&tx_kickoff, &p_address, &9900, &withdraw_fee, &fee_address)
+                .unwrap();
+        assert!(sc_entity
+            .prepare_sign_tx(backup_msg(transfer_user_id, Protocol::Transfer, &tx_backup_transfer))
+            .is_ok());
+        let tx_backup_transfer_bad =
+            tx_backup_build_relative(&tx_kickoff, &p_address, &9950, &withdraw_fee, &fee_address)
+                .unwrap();
+        match sc_entity.prepare_sign_tx(backup_msg(
+            transfer_user_id,
+            Protocol::Transfer,
+            &tx_backup_transfer_bad,
+        )) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("not correctly decremented")),
+        }
+    }
+
+    #[test]
+    fn test_prepare_sign_tx_withdraw_batch_amounts() {
+        use crate::structs::StateChainAmount;
+        use bitcoin::{Address, TxIn, TxOut};
+        use mockall::predicate;
+
+        let user_id = Uuid::new_v4();
+        let statechain_ids = vec![Uuid::new_v4(), Uuid::new_v4()];
+        let pk: curv::PK = serde_json::from_str(
+            "\"026cc37050561379a66a863f8ca273c2b29e935cad06bc7f5e6b83a03e0bbff1e6\"",
+        )
+        .unwrap();
+        let fee_address = String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x");
+
+        // Each statecoin of 10000 is funded by a different output
+        let funding_outpoints: Vec<OutPoint> = (0..2)
+            .map(|vout| OutPoint { txid: Txid::default(), vout })
+            .collect();
+        let tx_withdraw = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: funding_outpoints
+                .iter()
+                .map(|outpoint| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: bitcoin::Script::new(),
+                    sequence: 0xFFFFFFFF,
+                    witness: vec![],
+                })
+                .collect(),
+            output: vec![
+                TxOut {
+                    value: 19000,
+                    script_pubkey: Address::from_str(
+                        "bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8",
+                    )
+                    .unwrap()
+                    .script_pubkey(),
+                },
+                // Withdraw fee of 40 basis points of the batch value
+                TxOut {
+                    value: 80,
+                    script_pubkey: Address::from_str(&fee_address).unwrap().script_pubkey(),
+                },
+            ],
+        };
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
+        db.expect_get_user_auth().returning(|id| Ok(id));
+        db.expect_has_withdraw_sc_sig().returning(|_| Ok(()));
+        db.expect_get_key_type().returning(|_| Ok(KeyType::Ecdsa));
+        let batch = statechain_ids.clone();
+        db.expect_get_withdraw_batch()
+            .returning(move |_| Ok(batch.clone()));
+        db.expect_get_withdraw_outputs().returning(|_| {
+            Ok(vec![WithdrawOutput {
+                address: String::from("bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8"),
+                amount: 19000,
+            }])
+        });
+        let statechain_id = statechain_ids[0];
+        db.expect_get_statechain_id()
+            .returning(move |_| Ok(statechain_id));
+        for (statechain_id, outpoint) in statechain_ids.iter().zip(funding_outpoints.iter()) {
+            let mut tx_backup = tx_withdraw.clone();
+            tx_backup.input = vec![tx_withdraw.input[outpoint.vout as usize].clone()];
+            db.expect_get_backup_transaction()
+                .with(predicate::eq(*statechain_id))
+                .returning(move |_| Ok(tx_backup.clone()));
+            db.expect_get_statechain_amount()
+                .with(predicate::eq(*statechain_id))
+                .returning(|_| {
+                    Ok(StateChainAmount {
+                        chain: serde_json::from_str::<StateChain>(STATE_CHAIN).unwrap(),
+                        amount: 10000,
+                    })
+                });
+        }
+        db.expect_update_withdraw_tx_sighash()
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut sc_entity = test_sc_entity(db);
+        sc_entity.config.fee_address = fee_address.clone();
+        sc_entity.config.fee_withdraw = 40;
+
+        let withdraw_msg = |input_amounts: Vec<u64>| PrepareSignTxMsg {
+            shared_key_id: user_id,
+            protocol: Protocol::Withdraw,
+            tx_hex: transaction_serialise(&tx_withdraw),
+            input_addrs: vec![pk, pk],
+            input_amounts,
+            proof_key: None,
+        };
+
+        // Under-reporting the other input of the batch would lower the fee
+        match sc_entity.prepare_sign_tx(withdraw_msg(vec![10000, 0])) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("Withdraw tx input amounts do not match statecoin amounts.")),
+        }
+
+        assert!(sc_entity
+            .prepare_sign_tx(withdraw_msg(vec![10000, 10000]))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_prepare_sign_tx_taproot() {
+        use bitcoin::Address;
+        use mockall::predicate;
+        use shared_lib::util::tx_kickoff_build;
+
+        let user_id = Uuid::new_v4();
+        let pk: curv::PK = serde_json::from_str(
+            "\"026cc37050561379a66a863f8ca273c2b29e935cad06bc7f5e6b83a03e0bbff1e6\"",
+        )
+        .unwrap();
+        let p_address = get_p2wpkh_address(&pk, &String::from("regtest")).unwrap();
+
+        // Kick-off tx spending the P2TR funding output back to the shared key
+        let mut tx_kickoff = tx_kickoff_build(&OutPoint::default(), &p_address, &10000).unwrap();
+        tx_kickoff.output[0].script_pubkey = shared_key_script_pubkey(&pk).unwrap();
+        let sig_hash = sha256d::Hash::from_inner(
+            get_shared_key_taproot_sighash(&tx_kickoff, &0, &vec![pk], &vec![10000]).unwrap(),
+        );
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
+        db.expect_get_user_auth().returning(|id| Ok(id));
+        db.expect_get_statechain_id().returning(|_| {
+            Err(SEError::DBError(
+                DBErrorType::NoDataForID,
+                String::from("item not found"),
+            ))
+        });
+        db.expect_get_key_type().returning(|_| Ok(KeyType::Taproot));
+        db.expect_get_user_kickoff_tx().returning(|_| {
+            Err(SEError::DBError(
+                DBErrorType::NoDataForID,
+                String::from("item not found"),
+            ))
+        });
+        db.expect_update_sighash()
+            .with(predicate::eq(user_id), predicate::eq(sig_hash))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        db.expect_update_user_kickoff_tx().returning(|_, _| Ok(()));
+
+        let mut sc_entity = test_sc_entity(db);
+        sc_entity.config.relative_locktime = true;
+
+        let kickoff_msg = |tx: &Transaction| PrepareSignTxMsg {
+            shared_key_id: user_id,
+            protocol: Protocol::KickOff,
+            tx_hex: transaction_serialise(tx),
+            input_addrs: vec![pk],
+            input_amounts: vec![10000],
+            proof_key: None,
+        };
+
+        // The P2WPKH output of the key is not the shared key output of a Taproot statecoin
+        let tx_kickoff_p2wpkh =
+            tx_kickoff_build(&OutPoint::default(), &p_address, &10000).unwrap();
+        match sc_entity.prepare_sign_tx(kickoff_msg(&tx_kickoff_p2wpkh)) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not pay to the shared key")),
+        }
+        let mut tx_kickoff_fee = tx_kickoff.clone();
+        tx_kickoff_fee.output[0].script_pubkey =
+            Address::from_str("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x")
+                .unwrap()
+                .script_pubkey();
+        match sc_entity.prepare_sign_tx(kickoff_msg(&tx_kickoff_fee)) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not pay to the shared key")),
+        }
+
+        // BIP341 sighash is stored for signing
+        assert!(sc_entity.prepare_sign_tx(kickoff_msg(&tx_kickoff)).is_ok());
+    }
+
+    #[test]
+    fn test_verify_tx_confirmed() {
+        use crate::chain::memory::MemoryChain;
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db);
+        sc_entity.config.required_confirmation = 3;
+        let chain = MemoryChain::new();
+        *sc_entity.chain.lock().unwrap() = Box::new(chain.clone());
+
+        let txid = String::from("e0a97cb38e7e73617ef75a57eaf2841eb06833407c0eae08029bd04ea7e6115a");
+        match sc_entity.verify_tx_confirmed(&txid) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Funding Transaction not found.")),
+        }
+
+        chain.set_tx_status(&Txid::from_str(&txid).unwrap(), TxStatus::Unconfirmed);
+        match sc_entity.verify_tx_confirmed(&txid) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Funding Transaction not confirmed.")),
+        }
+
+        chain.mine_block();
+        chain.mine_block();
+        match sc_entity.verify_tx_confirmed(&txid) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("insufficient confirmations")),
+        }
+
+        chain.mine_block();
+        assert!(sc_entity.verify_tx_confirmed(&txid).is_ok());
+    }
+
+    #[test]
+    fn test_verify_refresh_backup_tx() {
+        use crate::chain::memory::MemoryChain;
+        use bitcoin::TxIn;
+
+        let user_id = Uuid::new_v4();
+        let tx_refresh = serde_json::from_str::<Transaction>(&BACKUP_TX_SIGNED).unwrap();
+        let mut tx_backup = tx_refresh.clone();
+        tx_backup.input = vec![TxIn {
+            previous_output: OutPoint {
+                txid: tx_refresh.txid(),
+                vout: 0,
+            },
+            ..tx_refresh.input[0].clone()
+        }];
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_refresh_tx()
+            .returning(move |_| Ok(tx_refresh.clone()));
+        let mut sc_entity = test_sc_entity(db);
+        sc_entity.config.lockheight_init = 100;
+        let chain = MemoryChain::new();
+        chain.set_height(500);
+        *sc_entity.chain.lock().unwrap() = Box::new(chain.clone());
+
+        // Backup tx must spend the refresh tx
+        let mut tx_backup_other = tx_backup.clone();
+        tx_backup_other.input[0].previous_output.vout = 1;
+        tx_backup_other.lock_time = 600;
+        match sc_entity.verify_refresh_backup_tx(&user_id, &tx_backup_other) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Backup tx does not spend the refresh tx.")),
+        }
+
+        // Locktime must be the current height plus lockheight_init
+        let tolerance = REFRESH_LOCKTIME_TOLERANCE as u32;
+        for lock_time in &[498, 599 - tolerance, 601 + tolerance, 10000] {
+            tx_backup.lock_time = *lock_time;
+            match sc_entity.verify_refresh_backup_tx(&user_id, &tx_backup) {
+                Ok(_) => assert!(false, "Expected failure."),
+                Err(e) => assert!(e
+                    .to_string()
+                    .contains("Refresh backup tx locktime must be the initial locktime 600.")),
+            }
+        }
+        for lock_time in &[600 - tolerance, 600, 600 + tolerance] {
+            tx_backup.lock_time = *lock_time;
+            assert!(sc_entity.verify_refresh_backup_tx(&user_id, &tx_backup).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_check_no_refresh_pending() {
+        use mockall::predicate;
+
+        let statechain_id = Uuid::new_v4();
+        let refreshed_statechain_id = Uuid::new_v4();
+        let tx_refresh = serde_json::from_str::<Transaction>(&BACKUP_TX_SIGNED).unwrap();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_refresh_pending()
+            .with(predicate::eq(statechain_id))
+            .returning(|_| Ok(None));
+        db.expect_get_refresh_pending()
+            .with(predicate::eq(refreshed_statechain_id))
+            .returning(move |_| Ok(Some(tx_refresh.txid())));
+        let sc_entity = test_sc_entity(db);
+
+        assert!(sc_entity.check_no_refresh_pending(&statechain_id).is_ok());
+        match sc_entity.check_no_refresh_pending(&refreshed_statechain_id) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Statecoin has a pending refresh.")),
+        }
+    }
+
+    #[test]
+    fn test_check_session_auth() {
+        use mockall::predicate;
+
+        let user_id = Uuid::new_v4();
+        let user_id_2 = Uuid::new_v4();
+        let (privkey, pubkey) = shared_lib::util::keygen::generate_keypair();
+        let (privkey_2, pubkey_2) = shared_lib::util::keygen::generate_keypair();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_proof_key()
+            .with(predicate::eq(user_id))
+            .returning(move |_| Ok(pubkey.to_string()));
+        db.expect_get_proof_key()
+            .with(predicate::eq(user_id_2))
+            .returning(move |_| Ok(pubkey_2.to_string()));
+        db.expect_get_proof_key()
+            .returning(|id| Err(SEError::DBError(DBErrorType::NoDataForID, id.to_string())));
+        let sc_entity = test_sc_entity(db);
+
+        let path = "/ecdsa/sign/first";
+        let body = format!("{{\"shared_key_id\":\"{}\"}}", user_id);
+        let auth = RequestAuth::new(&privkey.key, path, body.as_bytes()).unwrap();
+        assert!(sc_entity
+            .check_session_auth(&[user_id], &auth, path, body.as_bytes())
+            .is_ok());
+        // Replay
+        match sc_entity.check_session_auth(&[user_id], &auth, path, body.as_bytes()) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("User authorisation failed")),
+        }
+
+        // Signed by another session's proof key
+        let auth = RequestAuth::new(&privkey_2.key, path, body.as_bytes()).unwrap();
+        assert!(sc_entity
+            .check_session_auth(&[user_id], &auth, path, body.as_bytes())
+            .is_err());
+
+        // Unknown session
+        let auth = RequestAuth::new(&privkey.key, path, body.as_bytes()).unwrap();
+        assert!(sc_entity
+            .check_session_auth(&[Uuid::new_v4()], &auth, path, body.as_bytes())
+            .is_err());
+
+        // Requests in several sessions are signed by each proof key
+        let path = "/withdraw/confirm";
+        let auth = RequestAuth::new_multi(&[privkey.key, privkey_2.key], path, body.as_bytes())
+            .unwrap();
+        assert!(sc_entity
+            .check_session_auth(&[user_id, user_id_2], &auth, path, body.as_bytes())
+            .is_ok());
+        let auth = RequestAuth::new(&privkey.key, path, body.as_bytes()).unwrap();
+        assert!(sc_entity
+            .check_session_auth(&[user_id, user_id_2], &auth, path, body.as_bytes())
+            .is_err());
+    }
 }
@@ -4,17 +4,20 @@
 
 pub use super::super::Result;
 extern crate shared_lib;
-use crate::structs::StateChainOwner;
+use crate::structs::{StateChainOwner, WithdrawConfirmData};
 use crate::server::WITHDRAWALS_COUNT;
-use shared_lib::{state_chain::*, structs::*};
+use shared_lib::{state_chain::*, structs::*, util::withdraw_outputs_commitment};
 
 use rocket::State;
 use rocket_contrib::json::Json;
+use super::auth::SessionSigned;
 
 use crate::error::SEError;
 use crate::Database;
 use crate::{server::StateChainEntity, storage::Storage};
+use bitcoin::{OutPoint, Transaction};
 use cfg_if::cfg_if;
+use std::collections::HashSet;
 use uuid::Uuid;
 use rocket_okapi::openapi;
 
@@ -24,8 +27,22 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::StorageDatabase;
+        type SCE = StateChainEntity::<StorageDatabase, StorageDatabase>;
+    }
+}
+
+/// Find the index of the withdraw tx input which spends the statecoin funding output.
+pub fn withdraw_input_index(tx: &Transaction, funding_outpoint: &OutPoint) -> Result<usize> {
+    match tx
+        .input
+        .iter()
+        .position(|input| input.previous_output == *funding_outpoint)
+    {
+        Some(index) => Ok(index),
+        None => Err(SEError::Generic(String::from(
+            "Incorrect withdraw transacton input.",
+        ))),
     }
 }
 
@@ -38,16 +55,17 @@ pub trait Withdraw {
         user_id: Option<Uuid>,
     ) -> Result<StateChainOwner>;
 
-    /// User request withdraw:
-    ///     - Check StateChainSig validity
-    ///     - Mark user as authorised to withdraw
+    /// User request withdraw of one or more statecoins:
+    ///     - Check StateChainSig validity for each statecoin
+    ///     - Check StateChainSigs commit to the withdraw outputs
+    ///     - Mark users as authorised to withdraw
     fn withdraw_init(&self, withdraw_msg1: WithdrawMsg1) -> Result<()>;
 
     /// Finish withdrawal:
-    ///     - Ensure withdraw tx has been signed
-    ///     - Update UserSession, StateChain and Sparse merkle tree
-    ///     - Return withdraw tx signature
-    fn withdraw_confirm(&self, withdraw_msg2: WithdrawMsg2) -> Result<Vec<Vec<u8>>>;
+    ///     - Ensure every input of the withdraw tx has been signed
+    ///     - Update UserSession, StateChain and Sparse merkle tree for each statecoin
+    ///     - Return withdraw tx signatures in input order
+    fn withdraw_confirm(&self, withdraw_msg2: WithdrawMsg2) -> Result<Vec<Vec<Vec<u8>>>>;
 }
 
 impl Withdraw for SCE {
@@ -85,77 +103,202 @@ impl Withdraw for SCE {
     }
 
     fn withdraw_init(&self, withdraw_msg1: WithdrawMsg1) -> Result<()> {
-        let user_id = withdraw_msg1.shared_key_id;
-        self.check_user_auth(&user_id)?;
-
-        info!("WITHDRAW: Init. Shared Key ID: {}", user_id);
+        let user_ids = withdraw_msg1.shared_key_ids;
+        if user_ids.len() == 0 || user_ids.len() != withdraw_msg1.statechain_sigs.len() {
+            return Err(SEError::Generic(String::from(
+                "Withdraw requires one StateChainSig per Shared Key ID.",
+            )));
+        }
+        if user_ids.iter().collect::<HashSet<_>>().len() != user_ids.len() {
+            return Err(SEError::Generic(String::from(
+                "Duplicate Shared Key ID in withdraw request.",
+            )));
+        }
 
-        let statechain_id = self.database.get_statechain_id(user_id)?;
+        if withdraw_msg1.outputs.len() == 0 {
+            return Err(SEError::Generic(String::from("No withdraw outputs.")));
+        }
+        let outputs_commitment = withdraw_outputs_commitment(&withdraw_msg1.outputs);
+
+        info!("WITHDRAW: Init. Shared Key IDs: {:?}", user_ids);
+
+        // Verify every statechain before authorising any of them
+        let mut statechain_ids = vec![];
+        let mut key_types = HashSet::new();
+        for (user_id, statechain_sig) in user_ids.iter().zip(withdraw_msg1.statechain_sigs.iter())
+        {
+            self.check_user_auth(user_id)?;
+
+            // Taproot sighashes commit to the script pubkeys of every input, which are derived
+            // with the key type of the statecoin being signed for
+            key_types.insert(self.database.get_key_type(*user_id)?);
+            if key_types.len() > 1 {
+                return Err(SEError::Generic(String::from(
+                    "Withdraw batch cannot mix ECDSA and Taproot statecoins.",
+                )));
+            }
 
-        self.verify_statechain_sig(
-            &statechain_id,
-            &withdraw_msg1.statechain_sig,
-            Some(user_id),
-        )?;
+            // Owner must have signed the withdraw outputs
+            if statechain_sig.data != outputs_commitment {
+                return Err(SEError::Generic(format!(
+                    "StateChainSig does not commit to withdraw outputs. Shared Key ID: {}",
+                    user_id
+                )));
+            }
 
-        // Mark UserSession as authorised for withdrawal
+            let statechain_id = self.database.get_statechain_id(*user_id)?;
 
-        self.database
-            .update_withdraw_sc_sig(&user_id, withdraw_msg1.statechain_sig)?;
+            self.verify_statechain_sig(&statechain_id, statechain_sig, Some(*user_id))?;
+            self.check_no_refresh_pending(&statechain_id)?;
+            statechain_ids.push(statechain_id);
+        }
 
-        info!(
-            "WITHDRAW: Authorised. Shared Key ID: {}. State Chain: {}",
-            user_id, statechain_id
-        );
+        // Mark UserSessions as authorised for withdrawal. Each session records the whole
+        // batch and the committed outputs so the withdraw tx can be checked against them.
+        for ((user_id, statechain_sig), statechain_id) in user_ids
+            .iter()
+            .zip(withdraw_msg1.statechain_sigs.into_iter())
+            .zip(statechain_ids.iter())
+        {
+            self.database
+                .update_withdraw_sc_sig(
+                    user_id,
+                    statechain_sig,
+                    statechain_ids.clone(),
+                    withdraw_msg1.outputs.clone(),
+                )?;
+
+            info!(
+                "WITHDRAW: Authorised. Shared Key ID: {}. State Chain: {}",
+                user_id, statechain_id
+            );
+        }
 
         Ok(())
     }
 
-    fn withdraw_confirm(&self, withdraw_msg2: WithdrawMsg2) -> Result<Vec<Vec<u8>>> {
-        let user_id = withdraw_msg2.shared_key_id;
-        info!("WITHDRAW: Confirm. Shared Key ID: {}", user_id.to_string());
+    fn withdraw_confirm(&self, withdraw_msg2: WithdrawMsg2) -> Result<Vec<Vec<Vec<u8>>>> {
+        let user_ids = withdraw_msg2.shared_key_ids;
+        info!("WITHDRAW: Confirm. Shared Key IDs: {:?}", user_ids);
+        if user_ids.len() == 0 {
+            return Err(SEError::Generic(String::from(
+                "No Shared Key IDs in withdraw confirm request.",
+            )));
+        }
 
         // Get withdraw data - Checking that withdraw tx and statechain signature exists
-        let wcd = self.database.get_withdraw_confirm_data(user_id)?;
+        let mut wcds = vec![];
+        for user_id in &user_ids {
+            wcds.push(self.database.get_withdraw_confirm_data(*user_id)?);
+        }
+
+        // Every statecoin must be withdrawn by the same tx, with one input each
+        let txid = wcds[0].tx_withdraw.txid();
+        let num_inputs = wcds[0].tx_withdraw.input.len();
+        if num_inputs != wcds.len() {
+            return Err(SEError::Generic(String::from(
+                "Number of withdraw tx inputs != number of statecoins being withdrawn.",
+            )));
+        }
 
         // Ensure withdraw tx has been signed. i,e, that prepare-sign-tx has been completed.
-        if wcd.tx_withdraw.input[0].witness.len() == 0 {
+        // Each UserSession holds a copy of the tx with only its own input signed.
+        let mut witnesses: Vec<Vec<Vec<u8>>> = vec![vec![]; num_inputs];
+        for wcd in &wcds {
+            if wcd.tx_withdraw.txid() != txid {
+                return Err(SEError::Generic(String::from(
+                    "Statecoins are not being withdrawn by the same transaction.",
+                )));
+            }
+            match wcd
+                .tx_withdraw
+                .input
+                .iter()
+                .position(|input| input.witness.len() != 0)
+            {
+                Some(index) => witnesses[index] = wcd.tx_withdraw.input[index].witness.clone(),
+                None => {
+                    return Err(SEError::Generic(String::from(
+                        "Signed Back up transaction not found.",
+                    )))
+                }
+            }
+        }
+
+        // Every input must be signed before any statechain is closed
+        if witnesses.iter().any(|witness| witness.len() == 0) {
+            return Err(SEError::Generic(String::from(
+                "Not all withdraw transaction inputs have been signed.",
+            )));
+        }
+
+        // The whole batch authorised in withdraw_init must be confirmed together
+        let batch: HashSet<Uuid> = self
+            .database
+            .get_withdraw_batch(user_ids[0])?
+            .into_iter()
+            .collect();
+        if batch.len() != wcds.len()
+            || wcds.iter().any(|wcd| !batch.contains(&wcd.statechain_id))
+        {
             return Err(SEError::Generic(String::from(
-                "Signed Back up transaction not found.",
+                "Withdraw confirm does not match the authorised withdraw batch.",
             )));
         }
 
-        // Get statechain and update with final StateChainSig
-        let mut state_chain: StateChain = self.database.get_statechain(wcd.statechain_id)?;
+        // Add the final StateChainSig to every statechain before closing any of them, so
+        // that an invalid statechain cannot leave the batch partially closed.
+        let mut state_chains = vec![];
+        for wcd in &wcds {
+            let mut state_chain: StateChain = self.database.get_statechain(wcd.statechain_id)?;
+            state_chain.add(wcd.withdraw_sc_sig.to_owned())?;
+            state_chains.push(state_chain);
+        }
 
-        state_chain.add(wcd.withdraw_sc_sig.to_owned())?;
+        for ((user_id, wcd), state_chain) in user_ids.iter().zip(wcds.iter()).zip(state_chains) {
+            self.withdraw_close_statechain(user_id, wcd, state_chain)?;
+        }
 
+        Ok(witnesses)
+    }
+}
+
+impl SCE {
+    /// Close the statechain of a signed withdrawal. The statechain has already had the final
+    /// StateChainSig added.
+    fn withdraw_close_statechain(
+        &self,
+        user_id: &Uuid,
+        wcd: &WithdrawConfirmData,
+        state_chain: StateChain,
+    ) -> Result<()> {
         self.database
             .update_statechain_amount(&wcd.statechain_id, state_chain, 0)?;
 
         // Remove statechain_id from user session to signal end of session
-        self.database.remove_statechain_id(&user_id)?;
+        self.database.remove_statechain_id(user_id)?;
 
         //increment withdrawals metric
         WITHDRAWALS_COUNT.inc();
 
-        // Update sparse merkle tree
+        // Update sparse merkle tree with the input spending this statecoin. The entry is the
+        // withdraw outputs commitment signed by the owner.
+        let signed_input = wcd
+            .tx_withdraw
+            .input
+            .iter()
+            .find(|input| input.witness.len() != 0)
+            .unwrap();
         let (prev_root, new_root) = self.update_smt(
-            &wcd.tx_withdraw
-                .input
-                .get(0)
-                .unwrap()
-                .previous_output
-                .txid
-                .to_string(),
-            &withdraw_msg2.address,
+            &smt_key(&signed_input.previous_output),
+            &wcd.withdraw_sc_sig.data,
         )?;
 
         //remove backup tx from the backup db
         self.database.remove_backup_tx(&wcd.statechain_id)?;
 
         info!(
-            "WITHDRAW: Address included in sparse merkle tree. State Chain ID: {}",
+            "WITHDRAW: Outputs included in sparse merkle tree. State Chain ID: {}",
             wcd.statechain_id
         );
         debug!(
@@ -169,14 +312,14 @@ impl Withdraw for SCE {
             wcd.statechain_id
         );
 
-        Ok(wcd.tx_withdraw.input[0].clone().witness)
+        Ok(())
     }
 }
 
 #[openapi]
 /// # Initiate the withdrawal process: provide signed statechain
 #[post("/withdraw/init", format = "json", data = "<withdraw_msg1>")]
-pub fn withdraw_init(sc_entity: State<SCE>, withdraw_msg1: Json<WithdrawMsg1>) -> Result<Json<()>> {
+pub fn withdraw_init(sc_entity: State<SCE>, withdraw_msg1: SessionSigned<WithdrawMsg1>) -> Result<Json<()>> {
     match sc_entity.withdraw_init(withdraw_msg1.into_inner()) {
         Ok(res) => return Ok(Json(res)),
         Err(e) => return Err(e),
@@ -188,8 +331,8 @@ pub fn withdraw_init(sc_entity: State<SCE>, withdraw_msg1: Json<WithdrawMsg1>) -
 #[post("/withdraw/confirm", format = "json", data = "<withdraw_msg2>")]
 pub fn withdraw_confirm(
     sc_entity: State<SCE>,
-    withdraw_msg2: Json<WithdrawMsg2>,
-) -> Result<Json<Vec<Vec<u8>>>> {
+    withdraw_msg2: SessionSigned<WithdrawMsg2>,
+) -> Result<Json<Vec<Vec<Vec<u8>>>>> {
     match sc_entity.withdraw_confirm(withdraw_msg2.into_inner()) {
         Ok(res) => return Ok(Json(res)),
         Err(e) => return Err(e),
@@ -208,27 +351,43 @@ mod tests {
         },
         structs::{StateChainOwner, WithdrawConfirmData},
     };
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
     use chrono::{Duration, Utc};
     use mockall::predicate;
     use std::str::FromStr;
     use uuid::Uuid;
 
     // Data from a run of transfer protocol.
-    static WITHDRAW_MSG_1: &str = "{\"shared_key_id\":\"ad8cb891-ce91-447d-9192-bd105f3de602\",\"statechain_sig\":{\"purpose\":\"WITHDRAW\",\"data\":\"bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8\",\"sig\":\"304402201abaa7f64b50e8a75ca840a2be6317b501e3b5b5abd057465c165c9b872799f4022000d8e36734857237cab323c7244dd5249295b51905b43bf4e93396b58317d872\"}}";
+    static WITHDRAW_MSG_1: &str = "{\"shared_key_ids\":[\"ad8cb891-ce91-447d-9192-bd105f3de602\"],\"statechain_sigs\":[{\"purpose\":\"WITHDRAW\",\"data\":\"bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8\",\"sig\":\"304402201abaa7f64b50e8a75ca840a2be6317b501e3b5b5abd057465c165c9b872799f4022000d8e36734857237cab323c7244dd5249295b51905b43bf4e93396b58317d872\"}],\"outputs\":[{\"address\":\"bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8\",\"amount\":9000}]}";
     static STATE_CHAIN_ID: &str = "2b41ff74-510d-4fe7-90a6-714a26a137da";
     static STATE_CHAIN: &str = "{\"chain\":[{\"data\":\"026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e\",\"next_state\":null}]}";
     static STATE_CHAIN_SIG: &str = "{\"purpose\":\"WITHDRAW\",\"data\":\"bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8\",\"sig\":\"304402201abaa7f64b50e8a75ca840a2be6317b501e3b5b5abd057465c165c9b872799f4022000d8e36734857237cab323c7244dd5249295b51905b43bf4e93396b58317d872\"}";
 
     #[test]
     fn itegration_test_withdraw_init() {
-        let withdraw_msg_1 = serde_json::from_str::<WithdrawMsg1>(WITHDRAW_MSG_1).unwrap();
-        let shared_key_id = withdraw_msg_1.shared_key_id;
+        let mut withdraw_msg_1 = serde_json::from_str::<WithdrawMsg1>(WITHDRAW_MSG_1).unwrap();
+        // Owner signs the commitment to the withdraw outputs
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&Secp256k1::new(), &proof_key_priv);
+        withdraw_msg_1.statechain_sigs = vec![StateChainSig::new(
+            &proof_key_priv,
+            &String::from("WITHDRAW"),
+            &withdraw_outputs_commitment(&withdraw_msg_1.outputs),
+        )
+        .unwrap()];
+        let shared_key_id = withdraw_msg_1.shared_key_ids[0];
         let statechain_id = Uuid::from_str(STATE_CHAIN_ID).unwrap();
+        let taproot_shared_key_id = Uuid::new_v4();
 
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
         db.expect_get_user_auth()
             .returning(move |_| Ok(shared_key_id));
+        db.expect_get_key_type()
+            .with(predicate::eq(taproot_shared_key_id))
+            .returning(|_| Ok(KeyType::Taproot));
+        db.expect_get_key_type().returning(|_| Ok(KeyType::Ecdsa));
         db.expect_get_statechain_id()
             .with(predicate::eq(shared_key_id))
             .returning(move |_| Ok(statechain_id));
@@ -248,7 +407,7 @@ mod tests {
                 Ok(StateChainOwner {
                     locked_until: Utc::now().naive_utc() + Duration::seconds(5),
                     owner_id: shared_key_id,
-                    chain: serde_json::from_str::<StateChain>(STATE_CHAIN).unwrap(),
+                    chain: StateChain::new(proof_key.to_string()),
                 })
             });
         db.expect_get_statechain_owner()
@@ -257,20 +416,42 @@ mod tests {
                 Ok(StateChainOwner {
                     locked_until: Utc::now().naive_utc(),
                     owner_id: shared_key_id,
-                    chain: serde_json::from_str::<StateChain>(STATE_CHAIN).unwrap(),
+                    chain: StateChain::new(proof_key.to_string()),
                 })
             });
-        db.expect_update_withdraw_sc_sig().returning(|_, _| Ok(()));
+        db.expect_get_refresh_pending().returning(|_| Ok(None));
+        db.expect_update_withdraw_sc_sig().returning(|_, _, _, _| Ok(()));
 
         let sc_entity = test_sc_entity(db);
 
         // user does not own State Chain
         let mut msg_1_wrong_shared_key = withdraw_msg_1.clone();
-        msg_1_wrong_shared_key.shared_key_id = statechain_id;
+        msg_1_wrong_shared_key.shared_key_ids = vec![statechain_id];
         match sc_entity.withdraw_init(msg_1_wrong_shared_key.clone()) {
             Ok(_) => assert!(false, "Expected failure."),
             Err(e) => assert!(e.to_string().contains("DB Error: No data for identifier.")),
         }
+        // Missing StateChainSig
+        let mut msg_1_missing_sig = withdraw_msg_1.clone();
+        msg_1_missing_sig.shared_key_ids.push(Uuid::new_v4());
+        match sc_entity.withdraw_init(msg_1_missing_sig) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("Withdraw requires one StateChainSig per Shared Key ID.")),
+        }
+        // StateChainSig does not commit to outputs
+        let mut msg_1_other_outputs = withdraw_msg_1.clone();
+        msg_1_other_outputs.outputs.push(WithdrawOutput {
+            address: "bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x".to_string(),
+            amount: 1000,
+        });
+        match sc_entity.withdraw_init(msg_1_other_outputs) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("StateChainSig does not commit to withdraw outputs.")),
+        }
         // Sc locked
         match sc_entity.withdraw_init(withdraw_msg_1.clone()) {
             Ok(_) => assert!(false, "Expected failure."),
@@ -278,6 +459,18 @@ mod tests {
                 .to_string()
                 .contains("SharedLibError Error: Error: State Chain locked for 1 minutes.")),
         }
+        // ECDSA and Taproot statecoins in one batch
+        let mut msg_1_mixed = withdraw_msg_1.clone();
+        msg_1_mixed.shared_key_ids.push(taproot_shared_key_id);
+        msg_1_mixed
+            .statechain_sigs
+            .push(withdraw_msg_1.statechain_sigs[0].clone());
+        match sc_entity.withdraw_init(msg_1_mixed) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("Withdraw batch cannot mix ECDSA and Taproot statecoins.")),
+        }
 
         // Expect successful run
         assert!(sc_entity.withdraw_init(withdraw_msg_1.clone()).is_ok());
@@ -286,15 +479,15 @@ mod tests {
     #[test]
     fn itegration_test_withdraw_confirm() {
         let withdraw_msg_1 = serde_json::from_str::<WithdrawMsg1>(WITHDRAW_MSG_1).unwrap();
-        let shared_key_id = withdraw_msg_1.shared_key_id;
+        let shared_key_id = withdraw_msg_1.shared_key_ids[0];
         let withdraw_msg_2 = WithdrawMsg2 {
-            shared_key_id,
-            address: "bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8".to_string(),
+            shared_key_ids: vec![shared_key_id],
         };
         let statechain_id = Uuid::from_str(STATE_CHAIN_ID).unwrap();
 
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
         db.expect_get_user_auth()
             .returning(move |_| Ok(shared_key_id));
         db.expect_get_withdraw_confirm_data()
@@ -319,6 +512,8 @@ mod tests {
                 statechain_id,
             })
         });
+        db.expect_get_withdraw_batch()
+            .returning(move |_| Ok(vec![statechain_id]));
         db.expect_get_statechain()
             .returning(move |_| Ok(serde_json::from_str::<StateChain>(STATE_CHAIN).unwrap()));
         db.expect_update_statechain_amount()
@@ -343,4 +538,127 @@ mod tests {
         // Expect successful run
         assert!(sc_entity.withdraw_confirm(withdraw_msg_2.clone()).is_ok());
     }
+
+    #[test]
+    fn test_withdraw_confirm_batch() {
+        let shared_key_ids = vec![Uuid::new_v4(), Uuid::new_v4()];
+        let statechain_ids = vec![Uuid::new_v4(), Uuid::new_v4()];
+        let withdraw_msg_2 = WithdrawMsg2 {
+            shared_key_ids: shared_key_ids.clone(),
+        };
+
+        // Two input withdraw tx. Each UserSession holds a copy with only its own input signed.
+        let tx_signed: Transaction = serde_json::from_str(&BACKUP_TX_SIGNED).unwrap();
+        let mut second_input = tx_signed.input[0].clone();
+        second_input.previous_output.vout = 1;
+        let mut tx_unsigned = tx_signed.clone();
+        tx_unsigned.input.push(second_input);
+        for input in tx_unsigned.input.iter_mut() {
+            input.witness = vec![];
+        }
+        let mut tx_signed_0 = tx_unsigned.clone();
+        tx_signed_0.input[0].witness = tx_signed.input[0].witness.clone();
+        let mut tx_signed_1 = tx_unsigned.clone();
+        tx_signed_1.input[1].witness = tx_signed.input[0].witness.clone();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_funding_outpoint().returning(|_| Ok(None));
+        let (key_0, sc_0, tx_0) = (shared_key_ids[0], statechain_ids[0], tx_signed_0.clone());
+        db.expect_get_withdraw_confirm_data()
+            .with(predicate::eq(key_0))
+            .returning(move |_| {
+                Ok(WithdrawConfirmData {
+                    tx_withdraw: tx_0.clone(),
+                    withdraw_sc_sig: serde_json::from_str::<StateChainSig>(STATE_CHAIN_SIG)
+                        .unwrap(),
+                    statechain_id: sc_0,
+                })
+            });
+        // Second input is unsigned on the first call
+        let (key_1, sc_1) = (shared_key_ids[1], statechain_ids[1]);
+        let tx_1_unsigned = tx_unsigned.clone();
+        db.expect_get_withdraw_confirm_data()
+            .with(predicate::eq(key_1))
+            .times(1)
+            .returning(move |_| {
+                Ok(WithdrawConfirmData {
+                    tx_withdraw: tx_1_unsigned.clone(),
+                    withdraw_sc_sig: serde_json::from_str::<StateChainSig>(STATE_CHAIN_SIG)
+                        .unwrap(),
+                    statechain_id: sc_1,
+                })
+            });
+        let tx_1 = tx_signed_1.clone();
+        db.expect_get_withdraw_confirm_data()
+            .with(predicate::eq(key_1))
+            .returning(move |_| {
+                Ok(WithdrawConfirmData {
+                    tx_withdraw: tx_1.clone(),
+                    withdraw_sc_sig: serde_json::from_str::<StateChainSig>(STATE_CHAIN_SIG)
+                        .unwrap(),
+                    statechain_id: sc_1,
+                })
+            });
+        // The first confirm with both inputs signed omits a statecoin of the batch
+        let batch = statechain_ids.clone();
+        db.expect_get_withdraw_batch()
+            .times(1)
+            .returning(move |_| Ok(vec![batch[0], batch[1], Uuid::new_v4()]));
+        let batch = statechain_ids.clone();
+        db.expect_get_withdraw_batch()
+            .returning(move |_| Ok(batch.clone()));
+        db.expect_get_statechain()
+            .times(2)
+            .returning(move |_| Ok(serde_json::from_str::<StateChain>(STATE_CHAIN).unwrap()));
+        db.expect_update_statechain_amount()
+            .times(2)
+            .returning(|_, _, _| Ok(()));
+        db.expect_remove_statechain_id().times(2).returning(|_| Ok(()));
+        db.expect_root_get_current_id().returning(|| Ok(1 as i64));
+        db.expect_get_root().returning(|_| Ok(None));
+        db.expect_root_update().returning(|_| Ok(1));
+        db.expect_remove_backup_tx().times(2).returning(|_| Ok(()));
+
+        let sc_entity = test_sc_entity(db);
+        let _m = mocks::ms::post_commitment().create(); //Mainstay post commitment mock
+
+        // No statechain is closed until every input is signed
+        match sc_entity.withdraw_confirm(withdraw_msg_2.clone()) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("Signed Back up transaction not found.")),
+        }
+
+        // No statechain is closed unless the whole authorised batch is confirmed
+        match sc_entity.withdraw_confirm(withdraw_msg_2.clone()) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("Withdraw confirm does not match the authorised withdraw batch.")),
+        }
+
+        // Expect successful run returning a witness per input
+        let witnesses = sc_entity.withdraw_confirm(withdraw_msg_2.clone()).unwrap();
+        assert_eq!(witnesses.len(), 2);
+        assert_eq!(witnesses[0], tx_signed.input[0].witness);
+        assert_eq!(witnesses[1], tx_signed.input[0].witness);
+    }
+
+    #[test]
+    fn test_withdraw_input_index() {
+        let tx_backup: Transaction = serde_json::from_str(&BACKUP_TX_NOT_SIGNED).unwrap();
+        let funding_outpoint = tx_backup.input[0].previous_output;
+        let mut tx_withdraw = tx_backup.clone();
+        assert_eq!(withdraw_input_index(&tx_withdraw, &funding_outpoint).unwrap(), 0);
+
+        let mut other_input = tx_withdraw.input[0].clone();
+        other_input.previous_output.vout = 1;
+        tx_withdraw.input.insert(0, other_input.clone());
+        assert_eq!(withdraw_input_index(&tx_withdraw, &funding_outpoint).unwrap(), 1);
+
+        tx_withdraw.input = vec![other_input];
+        assert!(withdraw_input_index(&tx_withdraw, &funding_outpoint).is_err());
+    }
 }
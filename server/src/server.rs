@@ -1,9 +1,14 @@
+use super::chain::{get_chain_backend, memory::MemoryChain, Chain, TxStatus};
 use super::protocol::conductor::Scheduler;
+use super::protocol::deposit::DepositGate;
+use super::protocol::events::EventBus;
+use super::protocol::transfer::expire_transfers;
+use super::protocol::util::punish_statechain;
 use super::protocol::*;
 use crate::config::Config;
 use crate::structs::StateChainOwner;
 use crate::Database;
-use shared_lib::{mainstay, state_chain::StateChainSig, swap_data::*};
+use shared_lib::{mainstay, request_auth::ReplayGuard, state_chain::StateChainSig, swap_data::*};
 
 use log::LevelFilter;
 use log4rs::append::file::FileAppender;
@@ -28,6 +33,12 @@ use rocket_prometheus::{
 };
 use reqwest;
 use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::net::{IpAddr, TcpListener};
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::util::key::{PrivateKey, PublicKey};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use uuid::Uuid;
 
@@ -44,6 +55,30 @@ pub static TRANSFERS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
     IntCounter::new("transfer_counter", "Total completed transfers")
         .expect("Could not create lazy IntCounter")
 });
+pub static SUPERSEDED_BACKUPS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new("superseded_backup_counter", "Previous owners' backup txs seen in the mempool or chain")
+        .expect("Could not create lazy IntCounter")
+});
+pub static EXPIRED_TRANSFERS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new("gc_expired_transfers", "Transfers not completed by the receiver in time")
+        .expect("Could not create lazy IntCounter")
+});
+pub static EXPIRED_SESSIONS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new("gc_expired_sessions", "User sessions expired without a statechain")
+        .expect("Could not create lazy IntCounter")
+});
+pub static PURGED_ECDSA_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new("gc_purged_ecdsa", "ECDSA key material of expired user sessions purged")
+        .expect("Could not create lazy IntCounter")
+});
+pub static ARCHIVED_BATCHES_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new("gc_archived_batches", "Batch transfers moved to the archive")
+        .expect("Could not create lazy IntCounter")
+});
+pub static REMOVED_BACKUP_TXS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new("gc_removed_backup_txs", "Backup txs of missing or withdrawn statechains removed")
+        .expect("Could not create lazy IntCounter")
+});
 pub static REG_SWAP_UTXOS: Lazy<IntCounterVec> = Lazy::new(|| {
     IntCounterVec::new(opts!("reg_swap_utxos", "Registered utxos by group size and amount"), &["size","amount"])
         .expect("Could not create lazy IntGaugeVec")
@@ -51,23 +86,170 @@ pub static REG_SWAP_UTXOS: Lazy<IntCounterVec> = Lazy::new(|| {
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+/// Lockbox node. Nodes of the same shard share a key store, so each can serve requests for any
+/// key in the shard.
+#[derive(Debug, Clone)]
+pub struct LockboxNode {
+    pub endpoint: String,
+    healthy: Arc<AtomicBool>,
+    /// Key store ID last reported by the node
+    key_store_id: Arc<Mutex<Option<String>>>,
+}
+
+impl LockboxNode {
+    pub fn new(endpoint: &str, healthy: bool) -> LockboxNode {
+        LockboxNode {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            healthy: Arc::new(AtomicBool::new(healthy)),
+            key_store_id: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn get_key_store_id(&self) -> Option<String> {
+        self.key_store_id.lock().unwrap().clone()
+    }
+
+    pub fn set_key_store_id(&self, key_store_id: Option<String>) {
+        *self.key_store_id.lock().unwrap() = key_store_id;
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+
+    pub fn set_healthy(&self, healthy: bool) {
+        if self.healthy.swap(healthy, Ordering::SeqCst) != healthy {
+            match healthy {
+                true => info!("LOCKBOX: Node {} is healthy", self.endpoint),
+                false => warn!("LOCKBOX: Node {} is unhealthy", self.endpoint),
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Lockbox {
     pub client: reqwest::blocking::Client,
-    pub endpoint: String,
+    /// Shards of the key space, each a list of nodes in order of preference
+    pub shards: Vec<Vec<LockboxNode>>,
+    /// Number of times a failed request is retried on the shard's nodes
+    pub retries: u32,
+    /// Key signing requests
+    pub key: Option<SecretKey>,
+    /// Lockbox key that secret request fields are encrypted to
+    pub pubkey: Option<PublicKey>,
     pub active: bool,
 }
 
 impl Lockbox {
-    pub fn new(endpoint: String) -> Lockbox {
-        let client = reqwest::blocking::Client::new();
-        let active = endpoint.len() > 0;
-        let lb = Lockbox {
+    /// Create a lockbox client from a comma separated list of shards. Each shard is a `|`
+    /// separated list of node URLs. Requests time out after timeout seconds. The first node of
+    /// a shard holds its keys: the other nodes are used once a health check has shown that they
+    /// share its key store.
+    pub fn new(endpoints: &str, timeout: u64, retries: u32) -> Lockbox {
+        // The client pools connections to each node
+        let client = reqwest::blocking::Client::builder()
+            .timeout(std::time::Duration::from_secs(timeout))
+            .build()
+            .expect("Failed to build lockbox client");
+        let shards: Vec<Vec<LockboxNode>> = endpoints
+            .split(',')
+            .map(|shard| {
+                shard
+                    .split('|')
+                    .map(|e| e.trim())
+                    .filter(|e| e.len() > 0)
+                    .enumerate()
+                    .map(|(i, e)| LockboxNode::new(e, i == 0))
+                    .collect::<Vec<LockboxNode>>()
+            })
+            .filter(|shard| shard.len() > 0)
+            .collect();
+        let active = shards.len() > 0;
+        Lockbox {
             client,
-            endpoint,
+            shards,
+            retries,
+            key: None,
+            pubkey: None,
             active,
-        };
-        lb
+        }
+    }
+
+    /// Set the WIF key signing requests and the hex lockbox public key, which secrets are
+    /// encrypted to and responses must be signed by. Both are required if the lockbox is used.
+    pub fn set_auth(&mut self, key_wif: &str, pubkey: &str) -> Result<()> {
+        if !key_wif.is_empty() {
+            self.key = Some(PrivateKey::from_wif(key_wif)?.key);
+        }
+        if !pubkey.is_empty() {
+            self.pubkey = Some(PublicKey::from_str(pubkey)?);
+        }
+        if self.active && (self.key.is_none() || self.pubkey.is_none()) {
+            return Err("lockbox_key and lockbox_pubkey must be set to use the lockbox".into());
+        }
+        Ok(())
+    }
+
+    /// Index of the shard holding the key of user_id
+    pub fn shard_index(&self, user_id: &Uuid) -> usize {
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&user_id.as_bytes()[..8]);
+        (u64::from_be_bytes(prefix) % self.shards.len() as u64) as usize
+    }
+
+    /// Nodes of the shard holding the key of user_id: healthy nodes, then the first node if
+    /// it is unhealthy. Other unhealthy nodes may not share the key store and are not used.
+    pub fn nodes(&self, user_id: &Uuid) -> Vec<&LockboxNode> {
+        let shard = &self.shards[self.shard_index(user_id)];
+        let mut nodes: Vec<&LockboxNode> = shard.iter().filter(|n| n.is_healthy()).collect();
+        if !shard[0].is_healthy() {
+            nodes.push(&shard[0]);
+        }
+        nodes
+    }
+
+    /// Generate a new shared key ID on the same shard as user_id. A transfer moves the key
+    /// share of the sender to the receiver's new key within the sender's shard.
+    pub fn new_shared_key_id(&self, user_id: &Uuid) -> Uuid {
+        loop {
+            let new_user_id = Uuid::new_v4();
+            if !self.active || self.shard_index(&new_user_id) == self.shard_index(user_id) {
+                return new_user_id;
+            }
+        }
+    }
+
+    /// Ping each node and update its health. Nodes other than the first of a shard are healthy
+    /// only if they report the key store ID last reported by the first node.
+    pub fn check_health(&self) {
+        for shard in &self.shards {
+            for (i, node) in shard.iter().enumerate() {
+                let key_store_id =
+                    match self.client.get(&format!("{}/ping", node.endpoint)).send() {
+                        Ok(res) if res.status().is_success() => {
+                            res.text().ok().map(|id| id.trim().to_string()).filter(|id| id.len() > 0)
+                        }
+                        _ => None,
+                    };
+                if i == 0 {
+                    node.set_healthy(key_store_id.is_some());
+                    if key_store_id.is_some() {
+                        node.set_key_store_id(key_store_id);
+                    }
+                    continue;
+                }
+                let shared = key_store_id.is_some() && key_store_id == shard[0].get_key_store_id();
+                if key_store_id.is_some() && !shared {
+                    error!(
+                        "LOCKBOX: Node {} does not share the key store of node {}. Not used.",
+                        node.endpoint, shard[0].endpoint
+                    );
+                }
+                node.set_key_store_id(key_store_id);
+                node.set_healthy(shared);
+            }
+        }
     }
 }
 
@@ -76,10 +258,39 @@ pub struct StateChainEntity<
     D: MonotreeDatabase + Send + Sync + 'static,
 > {
     pub config: Config,
-    pub database: T,
+    pub database: Arc<T>,
     pub smt: Arc<Mutex<Monotree<D, Blake3>>>,
     pub scheduler: Arc<Mutex<Scheduler>>,
     pub lockbox: Lockbox,
+    pub chain: Arc<Mutex<Chain>>,
+    /// Nonces of recent session requests
+    pub request_nonces: Arc<Mutex<ReplayGuard>>,
+    /// Deposit rate limits, proof of work challenges and token requests
+    pub deposit_gate: Arc<Mutex<DepositGate>>,
+    /// Subscriptions to swap and transfer events
+    pub events: Arc<EventBus>,
+}
+
+// Clones share the database, SMT, scheduler, chain backend, request nonces, deposit gate and
+// event subscriptions
+impl<
+        T: Database + Send + Sync + 'static,
+        D: MonotreeDatabase + Send + Sync + 'static,
+    > Clone for StateChainEntity<T, D>
+{
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            database: self.database.clone(),
+            smt: self.smt.clone(),
+            scheduler: self.scheduler.clone(),
+            lockbox: self.lockbox.clone(),
+            chain: self.chain.clone(),
+            request_nonces: self.request_nonces.clone(),
+            deposit_gate: self.deposit_gate.clone(),
+            events: self.events.clone(),
+        }
+    }
 }
 
 impl<
@@ -90,7 +301,6 @@ impl<
     pub fn load(mut db: T, mut db_smt: D) -> Result<StateChainEntity<T, D>> {
         // Get config as defaults, Settings.toml and env vars
         let config_rs = Config::load()?;
-        let lockbox_url = config_rs.lockbox.clone();
         db.set_connection_from_config(&config_rs)?;
         db_smt.set_connection_from_config(&config_rs)?;
 
@@ -99,28 +309,185 @@ impl<
             hasher: Blake3::new(),
         };
 
+        // Resume any swaps that were in progress when the server was stopped. Starting with an
+        // empty scheduler would lose them and overwrite the stored swaps on the next persist.
+        let mut scheduler = Scheduler::load(&db)?;
+        let events = Arc::new(EventBus::new());
+        scheduler.set_event_bus(events.clone());
+
+        // In testing mode all txs are treated as confirmed
+        let chain: Chain = if config_rs.testing_mode {
+            let memory_chain = MemoryChain::new();
+            memory_chain
+                .set_default_tx_status(TxStatus::Confirmed(config_rs.required_confirmation));
+            Box::new(memory_chain)
+        } else {
+            get_chain_backend(&config_rs)?
+        };
+
+        let mut lockbox = Lockbox::new(
+            &config_rs.lockbox,
+            config_rs.lockbox_timeout,
+            config_rs.lockbox_retries,
+        );
+        lockbox.set_auth(&config_rs.lockbox_key, &config_rs.lockbox_pubkey)?;
+
+        if config_rs.deposit_token_fee > 0 && config_rs.deposit_token_key.is_empty() {
+            warn!("DEPOSIT: No deposit token key set. Tokens will not be valid after a restart.");
+        }
+        let deposit_gate = DepositGate::new(&config_rs.deposit_token_key)?;
+
         let sce = Self {
             config: config_rs,
-            database: db,
+            database: Arc::new(db),
             smt: Arc::new(Mutex::new(smt)),
-            scheduler: Arc::new(Mutex::new(Scheduler::new())),
-            lockbox: Lockbox::new(lockbox_url),
+            scheduler: Arc::new(Mutex::new(scheduler)),
+            lockbox,
+            chain: Arc::new(Mutex::new(chain)),
+            request_nonces: Arc::new(Mutex::new(ReplayGuard::default())),
+            deposit_gate: Arc::new(Mutex::new(deposit_gate)),
+            events,
         };
 
-        Self::start_conductor_thread(sce.scheduler.clone());
+        Self::start_conductor_thread(
+            sce.scheduler.clone(),
+            sce.database.clone(),
+            sce.config.punishment_duration,
+        );
+        if sce.config.transfer_expiry > 0 {
+            Self::start_transfer_expiry_thread(
+                sce.database.clone(),
+                sce.config.transfer_expiry,
+                sce.config.lh_decrement,
+                sce.config.relative_locktime,
+            );
+        }
+        if sce.lockbox.active {
+            Self::start_lockbox_health_thread(sce.lockbox.clone());
+        }
         Ok(sce)
     }
 
-    pub fn start_conductor_thread(scheduler: Arc<Mutex<Scheduler>>) -> std::thread::JoinHandle<()> {
+    pub fn start_conductor_thread(
+        scheduler: Arc<Mutex<Scheduler>>,
+        database: Arc<T>,
+        punishment_duration: u64,
+    ) -> std::thread::JoinHandle<()> {
         std::thread::spawn(move || loop {
             let mut guard = scheduler.lock().unwrap();
+            for statechain_id in guard.expire_swaps() {
+                if let Err(e) = punish_statechain(&*database, statechain_id, punishment_duration) {
+                    error!("{}", &e.to_string());
+                }
+            }
             if let Err(e) = guard.update_swap_info() {
                 error!("{}", &e.to_string());
             }
+            if let Err(e) = guard.persist(&*database) {
+                error!("{}", &e.to_string());
+            }
             drop(guard);
             std::thread::sleep(std::time::Duration::from_secs(10));
         })
     }
+
+    /// Periodically remove transfers which have not been completed within transfer_expiry seconds
+    pub fn start_transfer_expiry_thread(
+        database: Arc<T>,
+        transfer_expiry: u64,
+        lh_decrement: u32,
+        relative_locktime: bool,
+    ) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(std::time::Duration::from_secs(60));
+            match expire_transfers(&*database, transfer_expiry, lh_decrement, relative_locktime) {
+                Ok(expired) => EXPIRED_TRANSFERS_COUNT.inc_by(expired.len() as _),
+                Err(e) => error!("{}", &e.to_string()),
+            }
+        })
+    }
+
+    /// Periodically remove data left behind by abandoned protocols
+    pub fn start_gc_thread(sce: Self) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(std::time::Duration::from_secs(sce.config.gc_interval));
+            match sce.collect_garbage() {
+                Ok(counts) => {
+                    EXPIRED_SESSIONS_COUNT.inc_by(counts.sessions as _);
+                    PURGED_ECDSA_COUNT.inc_by(counts.ecdsa as _);
+                    ARCHIVED_BATCHES_COUNT.inc_by(counts.batches as _);
+                    REMOVED_BACKUP_TXS_COUNT.inc_by(counts.backup_txs as _);
+                }
+                Err(e) => error!("GC: {}", &e.to_string()),
+            }
+        })
+    }
+
+    /// Periodically ping lockbox nodes. Requests go to healthy nodes of a shard first, and only
+    /// to nodes sharing the key store of the shard's first node.
+    pub fn start_lockbox_health_thread(lockbox: Lockbox) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            lockbox.check_health();
+            std::thread::sleep(std::time::Duration::from_secs(30));
+        })
+    }
+
+    /// Periodically check the funding txs and outputs of active statechains: re-verify funding
+    /// tx confirmations for reorgs, alert on superseded backup txs and close statechains whose
+    /// funding output has been spent by a backup tx
+    pub fn start_backup_monitor_thread(sce: Self) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            let mut alerted = HashSet::new();
+            loop {
+                std::thread::sleep(std::time::Duration::from_secs(60));
+                if let Err(e) = sce.verify_funding_confirmations() {
+                    error!("{}", &e.to_string());
+                }
+                if let Err(e) = sce.check_superseded_backups(&mut alerted) {
+                    error!("{}", &e.to_string());
+                }
+                if let Err(e) = sce.close_spent_statechains() {
+                    error!("{}", &e.to_string());
+                }
+            }
+        })
+    }
+
+    /// Accept event stream connections on port, serving each on its own thread
+    pub fn start_event_thread(sce: Self, port: u16) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            let listener = match TcpListener::bind((sce.config.rocket.address.as_str(), port)) {
+                Ok(l) => l,
+                Err(e) => {
+                    error!("EVENTS: Failed to listen on port {}: {}", port, e);
+                    return;
+                }
+            };
+            info!("EVENTS: Listening on port {}", port);
+            let connections = Arc::new(AtomicUsize::new(0));
+            let max_connections = sce.config.events_max_connections as usize;
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if connections.fetch_add(1, Ordering::SeqCst) >= max_connections {
+                            connections.fetch_sub(1, Ordering::SeqCst);
+                            warn!("EVENTS: Connection limit of {} reached", max_connections);
+                            continue;
+                        }
+                        let sce = sce.clone();
+                        let connections = connections.clone();
+                        std::thread::spawn(move || {
+                            if let Err(e) = sce.serve_event_stream(stream) {
+                                warn!("EVENTS: {}", &e.to_string());
+                            }
+                            connections.fetch_sub(1, Ordering::SeqCst);
+                        });
+                    }
+                    Err(e) => warn!("EVENTS: Failed to accept connection: {}", e),
+                }
+            }
+        })
+    }
 }
 
 #[catch(500)]
@@ -133,6 +500,11 @@ fn bad_request() -> &'static str {
     "Bad request"
 }
 
+#[catch(401)]
+fn unauthorized() -> &'static str {
+    "Authentication Error: User authorisation failed"
+}
+
 #[catch(404)]
 fn not_found(req: &Request) -> String {
     format!("Unknown route '{}'.", req.uri())
@@ -168,6 +540,9 @@ pub fn get_server<
         info!("Server running in testing mode.");
         // reset dbs
         sc_entity.database.reset()?;
+        let mut scheduler = Scheduler::new();
+        scheduler.set_event_bus(sc_entity.events.clone());
+        *sc_entity.scheduler.lock().unwrap() = scheduler;
     }
 
     match mainstay_config {
@@ -186,16 +561,20 @@ pub fn get_server<
     prometheus.registry().register(Box::new(WITHDRAWALS_COUNT.clone())).unwrap();
     prometheus.registry().register(Box::new(TRANSFERS_COUNT.clone())).unwrap();
     prometheus.registry().register(Box::new(REG_SWAP_UTXOS.clone())).unwrap();
+    prometheus.registry().register(Box::new(SUPERSEDED_BACKUPS_COUNT.clone())).unwrap();
+    prometheus.registry().register(Box::new(EXPIRED_TRANSFERS_COUNT.clone())).unwrap();
+    prometheus.registry().register(Box::new(EXPIRED_SESSIONS_COUNT.clone())).unwrap();
+    prometheus.registry().register(Box::new(PURGED_ECDSA_COUNT.clone())).unwrap();
+    prometheus.registry().register(Box::new(ARCHIVED_BATCHES_COUNT.clone())).unwrap();
+    prometheus.registry().register(Box::new(REMOVED_BACKUP_TXS_COUNT.clone())).unwrap();
 
     let rocket_config = get_rocket_config(&sc_entity.config);
 
-    let bitcoind = sc_entity.config.bitcoind.clone();
-
     if sc_entity.config.watch_only {
         info!("Server running in watch-only mode.");
-        thread::spawn(|| watch_node(bitcoind));
+        thread::spawn(|| watch_node());
         let rock = rocket::custom(rocket_config)
-            .register(catchers![internal_error, not_found, bad_request])
+            .register(catchers![internal_error, not_found, bad_request, unauthorized])
             .mount(
                 "/",
                 routes![
@@ -204,12 +583,20 @@ pub fn get_server<
             );
         Ok(rock)
     } else {
-        // if bitcoind path supplied, run watching
+        // if bitcoind path supplied, run watching with the configured chain backend
         if sc_entity.config.bitcoind.is_empty() == false {
-            thread::spawn(|| watch_node(bitcoind));
+            thread::spawn(|| watch_node());
+            StateChainEntity::start_backup_monitor_thread(sc_entity.clone());
         }
-        let rock = rocket::custom(rocket_config)
-            .register(catchers![internal_error, not_found, bad_request])
+        if sc_entity.config.events_port > 0 {
+            StateChainEntity::start_event_thread(sc_entity.clone(), sc_entity.config.events_port);
+        }
+        if sc_entity.config.gc_interval > 0 {
+            StateChainEntity::start_gc_thread(sc_entity.clone());
+        }
+        let admin_enabled = !sc_entity.config.admin_pubkey.is_empty();
+        let mut rock = rocket::custom(rocket_config)
+            .register(catchers![internal_error, not_found, bad_request, unauthorized])
             .attach(prometheus.clone())
             .mount(
                 "/",
@@ -221,26 +608,40 @@ pub fn get_server<
                 "/", 
                 routes_with_openapi![
                     util::get_statechain,
+                    util::get_statechain_closure,
                     util::get_smt_root,
                     util::get_smt_proof,
                     util::get_fees,
                     util::prepare_sign_tx,
                     util::get_transfer_batch_status,  
+                    explorer::list_statechains,
+                    explorer::get_statechain_summary,
+                    explorer::search_statechains,
                     ecdsa::first_message,
                     ecdsa::second_message,
                     ecdsa::sign_first,
                     ecdsa::sign_second,
+                    musig::musig_keygen,
+                    musig::musig_sign_first,
+                    musig::musig_sign_second,
                     deposit::deposit_init,
+                    deposit::deposit_challenge,
+                    deposit::deposit_token_init,
+                    deposit::deposit_token_sign,
                     deposit::deposit_confirm,
+                    deposit::deposit_confirm_batch,
                     transfer::transfer_sender,
                     transfer::transfer_receiver,
                     transfer::transfer_update_msg,
                     transfer::transfer_get_msg,
                     transfer::transfer_get_pubkey,
+                    transfer::transfer_cancel,
                     transfer_batch::transfer_batch_init,
                     transfer_batch::transfer_reveal_nonce,
                     withdraw::withdraw_init,
                     withdraw::withdraw_confirm,
+                    refresh::refresh_init,
+                    refresh::refresh_confirm,
                     conductor::poll_utxo,
                     conductor::poll_swap,
                     conductor::get_swap_info,
@@ -254,6 +655,22 @@ pub fn get_server<
             .mount("/metrics", prometheus)
             .manage(sc_entity);
 
+        // Admin routes are not documented in the OpenAPI spec
+        if admin_enabled {
+            info!("Admin API enabled.");
+            rock = rock.mount(
+                "/",
+                routes![
+                    admin::list_user_sessions,
+                    admin::get_admin_statechain,
+                    admin::lock_statechain,
+                    admin::cancel_transfer_batch,
+                    admin::get_scheduler_state,
+                    admin::reattest_smt_root,
+                ],
+            );
+        }
+
         Ok(rock)
     }
 }
@@ -304,9 +721,13 @@ pub fn get_postgres_url(
 
 //Mock all the traits implemented by StateChainEntity so that they can
 //be called from MockStateChainEntity
+use crate::protocol::admin::Admin;
 use crate::protocol::conductor::Conductor;
 use crate::protocol::deposit::Deposit;
 use crate::protocol::ecdsa::Ecdsa;
+use crate::protocol::explorer::Explorer;
+use crate::protocol::musig::MuSig;
+use crate::protocol::refresh::Refresh;
 use crate::protocol::transfer::{Transfer, TransferFinalizeData};
 use crate::protocol::transfer_batch::BatchTransfer;
 use crate::protocol::util::{Proof, Utilities};
@@ -320,11 +741,28 @@ use shared_lib::structs::*;
 mock! {
     StateChainEntity{}
     trait Deposit {
-        fn deposit_init(&self, deposit_msg1: DepositMsg1) -> deposit::Result<UserID>;
+        fn deposit_init(
+            &self,
+            deposit_msg1: DepositMsg1,
+            client_ip: Option<IpAddr>,
+        ) -> deposit::Result<UserID>;
+        fn deposit_challenge(&self, client_ip: Option<IpAddr>) -> deposit::Result<PowChallenge>;
+        fn deposit_token_init(
+            &self,
+            client_ip: Option<IpAddr>,
+        ) -> deposit::Result<DepositTokenInit>;
+        fn deposit_token_sign(
+            &self,
+            deposit_token_msg: DepositTokenMsg,
+        ) -> deposit::Result<BlindedSpendSignature>;
         fn deposit_confirm(
             &self,
             deposit_msg2: DepositMsg2,
         ) -> deposit::Result<StatechainID>;
+        fn deposit_confirm_batch(
+            &self,
+            deposit_batch_msg: DepositBatchMsg,
+        ) -> deposit::Result<Vec<StatechainID>>;
     }
     trait Ecdsa {
         fn master_key(&self, user_id: Uuid) -> ecdsa::Result<()>;
@@ -349,6 +787,22 @@ mock! {
             sign_msg2: SignMsg2,
         ) -> ecdsa::Result<Vec<Vec<u8>>>;
     }
+    trait MuSig {
+        fn musig_keygen(
+            &self,
+            keygen_msg: MuSigKeyGenMsg,
+        ) -> musig::Result<MuSigKeyGenReply>;
+
+        fn musig_sign_first(
+            &self,
+            sign_msg1: MuSigSignMsg1,
+        ) -> musig::Result<MuSigSignReply1>;
+
+        fn musig_sign_second(
+            &self,
+            sign_msg2: MuSigSignMsg2,
+        ) -> musig::Result<Vec<Vec<u8>>>;
+    }
     trait Conductor {
         fn poll_utxo(&self, statechain_id: &Uuid) -> conductor::Result<SwapID>;
         fn poll_swap(&self, swap_id: &Uuid) -> conductor::Result<Option<SwapStatus>>;
@@ -379,6 +833,7 @@ mock! {
         ) -> transfer::Result<()>;
         fn transfer_update_msg(&self, transfer_msg3: TransferMsg3) -> transfer::Result<()>;
         fn transfer_get_msg(&self, statechain_id: Uuid) -> transfer::Result<TransferMsg3>;
+        fn transfer_cancel(&self, transfer_cancel_msg: TransferCancelMsg) -> transfer::Result<()>;
     }
     trait BatchTransfer {
         fn transfer_batch_init(
@@ -420,7 +875,41 @@ mock! {
         fn withdraw_confirm(
             &self,
             withdraw_msg2: WithdrawMsg2,
-        ) -> withdraw::Result<Vec<Vec<u8>>>;
+        ) -> withdraw::Result<Vec<Vec<Vec<u8>>>>;
+    }
+    trait Refresh{
+        fn refresh_init(
+            &self,
+            refresh_msg1: RefreshMsg1,
+        ) -> refresh::Result<()>;
+        fn refresh_confirm(
+            &self,
+            refresh_msg2: RefreshMsg2,
+        ) -> refresh::Result<()>;
+    }
+    trait Explorer {
+        fn list_statechains(
+            &self,
+            filter: crate::structs::StateChainFilter,
+            page: u64,
+            per_page: u64,
+        ) -> explorer::Result<StateChainListAPI>;
+        fn get_statechain_summary(
+            &self,
+            statechain_id: Uuid,
+        ) -> explorer::Result<StateChainSummaryAPI>;
+        fn search_statechains(
+            &self,
+            query: String,
+        ) -> explorer::Result<Vec<StateChainSummaryAPI>>;
+    }
+    trait Admin {
+        fn list_user_sessions(&self, page: u64, per_page: u64) -> admin::Result<UserSessionListAPI>;
+        fn get_admin_statechain(&self, statechain_id: Uuid) -> admin::Result<AdminStateChainAPI>;
+        fn lock_statechain(&self, lock_msg: AdminLockMsg) -> admin::Result<()>;
+        fn cancel_transfer_batch(&self, cancel_msg: AdminCancelBatchMsg) -> admin::Result<()>;
+        fn get_scheduler_state(&self) -> admin::Result<serde_json::Value>;
+        fn reattest_smt_root(&self) -> admin::Result<storage::Root>;
     }
     trait Storage{
         fn update_smt(&self, funding_txid: &String, proof_key: &String)
@@ -430,6 +919,7 @@ mock! {
         fn get_root(&self, id: i64) -> storage::Result<Option<storage::Root>>;
         fn update_root(&self, root: &storage::Root) -> storage::Result<i64>;
         fn get_statechain_data_api(&self,statechain_id: Uuid) -> storage::Result<StateChainDataAPI>;
+        fn get_statechain_closure(&self, statechain_id: Uuid) -> storage::Result<Option<StateChainClosureAPI>>;
         fn get_statechain(&self, statechain_id: Uuid) -> storage::Result<storage::StateChain>;
     }
 }
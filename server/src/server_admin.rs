@@ -0,0 +1,200 @@
+//! Server Admin
+//!
+//! Command line client of the state entity admin API. Requests are signed by the admin key,
+//! read from MERC_ADMIN_KEY or --key as a hex private key, whose public key is the server's
+//! admin_pubkey.
+
+extern crate bitcoin;
+extern crate clap;
+extern crate reqwest;
+extern crate serde;
+extern crate serde_json;
+extern crate shared_lib;
+extern crate uuid;
+
+use bitcoin::secp256k1::SecretKey;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use shared_lib::request_auth::{RequestAuth, ADMIN_HEADERS};
+use shared_lib::structs::{AdminCancelBatchMsg, AdminLockMsg};
+use std::str::FromStr;
+use uuid::Uuid;
+
+type Result<T> = std::result::Result<T, String>;
+
+struct AdminClient {
+    client: reqwest::blocking::Client,
+    endpoint: String,
+    key: SecretKey,
+}
+
+impl AdminClient {
+    fn get(&self, path: &str) -> Result<serde_json::Value> {
+        self.send(reqwest::Method::GET, path, vec![])
+    }
+
+    fn post<T: serde::Serialize>(&self, path: &str, body: Option<&T>) -> Result<serde_json::Value> {
+        let body = match body {
+            Some(b) => serde_json::to_vec(b).map_err(|e| e.to_string())?,
+            None => vec![],
+        };
+        self.send(reqwest::Method::POST, path, body)
+    }
+
+    // The signature covers the path, including any query string, and the exact body sent
+    fn send(&self, method: reqwest::Method, path: &str, body: Vec<u8>) -> Result<serde_json::Value> {
+        let auth = RequestAuth::new(&self.key, path, &body).map_err(|e| e.to_string())?;
+        let resp = self
+            .client
+            .request(method, &format!("{}{}", self.endpoint, path))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(reqwest::header::ACCEPT, "application/json")
+            .header(ADMIN_HEADERS.signature, auth.signature)
+            .header(ADMIN_HEADERS.timestamp, auth.timestamp.to_string())
+            .header(ADMIN_HEADERS.nonce, auth.nonce)
+            .body(body)
+            .send()
+            .map_err(|e| e.to_string())?;
+        let status = resp.status();
+        let text = resp.text().map_err(|e| e.to_string())?;
+        if !status.is_success() {
+            return Err(format!("{}: {}", status, text));
+        }
+        serde_json::from_str(&text).map_err(|e| format!("Invalid response {}: {}", text, e))
+    }
+}
+
+fn uuid_arg(matches: &ArgMatches, name: &str) -> Result<Uuid> {
+    let value = matches.value_of(name).unwrap_or_default();
+    Uuid::from_str(value).map_err(|e| format!("Invalid {} {}: {}", name, value, e))
+}
+
+fn u64_arg(matches: &ArgMatches, name: &str, default: u64) -> Result<u64> {
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid {} {}: {}", name, value, e)),
+        None => Ok(default),
+    }
+}
+
+fn run(matches: ArgMatches) -> Result<serde_json::Value> {
+    let key = match matches.value_of("key") {
+        Some(key) => key.to_string(),
+        None => std::env::var("MERC_ADMIN_KEY")
+            .map_err(|_| String::from("Admin key not set: use MERC_ADMIN_KEY or --key"))?,
+    };
+    let key = SecretKey::from_str(&key).map_err(|e| format!("Invalid admin key: {}", e))?;
+    let admin = AdminClient {
+        client: reqwest::blocking::Client::new(),
+        endpoint: matches
+            .value_of("endpoint")
+            .unwrap_or_default()
+            .trim_end_matches('/')
+            .to_string(),
+        key,
+    };
+
+    match matches.subcommand() {
+        ("sessions", Some(m)) => admin.get(&format!(
+            "/admin/sessions?page={}&per_page={}",
+            u64_arg(m, "page", 0)?,
+            u64_arg(m, "per-page", 20)?
+        )),
+        ("statechain", Some(m)) => {
+            admin.get(&format!("/admin/statechain/{}", uuid_arg(m, "statechain-id")?))
+        }
+        ("lock", Some(m)) => admin.post(
+            "/admin/statechain/lock",
+            Some(&AdminLockMsg {
+                statechain_id: uuid_arg(m, "statechain-id")?,
+                seconds: u64_arg(m, "seconds", 0)?,
+            }),
+        ),
+        ("unlock", Some(m)) => admin.post(
+            "/admin/statechain/lock",
+            Some(&AdminLockMsg {
+                statechain_id: uuid_arg(m, "statechain-id")?,
+                seconds: 0,
+            }),
+        ),
+        ("cancel-batch", Some(m)) => admin.post(
+            "/admin/transfer/batch/cancel",
+            Some(&AdminCancelBatchMsg {
+                batch_id: uuid_arg(m, "batch-id")?,
+                punish: m.is_present("punish"),
+            }),
+        ),
+        ("scheduler", Some(_)) => admin.get("/admin/scheduler"),
+        ("attest-root", Some(_)) => admin.post::<()>("/admin/smt/attest", None),
+        _ => Err(String::from("Unknown command")),
+    }
+}
+
+fn main() {
+    let statechain_id = Arg::with_name("statechain-id").required(true);
+    let matches = App::new("server-admin")
+        .about("State entity admin API client")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("endpoint")
+                .long("endpoint")
+                .takes_value(true)
+                .default_value("http://localhost:8000")
+                .help("State entity URL"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .takes_value(true)
+                .help("Hex admin private key. Defaults to MERC_ADMIN_KEY"),
+        )
+        .subcommand(
+            SubCommand::with_name("sessions")
+                .about("List user sessions")
+                .arg(Arg::with_name("page").long("page").takes_value(true))
+                .arg(Arg::with_name("per-page").long("per-page").takes_value(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("statechain")
+                .about("Show a statechain's owner, lock, backup tx, transfer and swap")
+                .arg(statechain_id.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("lock")
+                .about("Lock a statechain")
+                .arg(statechain_id.clone())
+                .arg(Arg::with_name("seconds").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("unlock")
+                .about("Unlock a statechain")
+                .arg(statechain_id),
+        )
+        .subcommand(
+            SubCommand::with_name("cancel-batch")
+                .about("End a stuck batch transfer and remove its swap")
+                .arg(Arg::with_name("batch-id").required(true))
+                .arg(
+                    Arg::with_name("punish")
+                        .long("punish")
+                        .help("Punish the batch statechains instead of unlocking them"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("scheduler").about("Show the conductor's scheduler"))
+        .subcommand(
+            SubCommand::with_name("attest-root")
+                .about("Attest the current SMT root to mainstay again"),
+        )
+        .get_matches();
+
+    match run(matches) {
+        Ok(value) => println!(
+            "{}",
+            serde_json::to_string_pretty(&value).unwrap_or_else(|_| value.to_string())
+        ),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,806 @@
+//! Storage backend
+//!
+//! Database of the backend selected by storage.db_backend in the config: "postgres" (default) or
+//! "sqlite". The state entity's type is fixed at compile time, so each call is forwarded to the
+//! selected database.
+
+use super::super::Result;
+use bitcoin::hashes::sha256d;
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+
+use crate::error::SEError;
+use crate::protocol::transfer::TransferFinalizeData;
+use crate::{structs::*, Database, Hash, PGDatabase, SqliteDatabase, StorageDatabase};
+use chrono::NaiveDateTime;
+use curv::{FE, GE};
+use kms::ecdsa::two_party::*;
+use monotree::database::Database as MonotreeDatabase;
+use monotree::Errors;
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party_two};
+use rocket_contrib::databases::r2d2;
+use rocket_contrib::databases::r2d2_postgres::PostgresConnectionManager;
+use shared_lib::state_chain::*;
+use shared_lib::structs::{
+    KeyType, StateChainClosureAPI, TransferMsg3, UserSessionAPI, WithdrawOutput,
+};
+use shared_lib::Root;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Name of the postgres backend in the storage config
+pub const POSTGRES: &str = "postgres";
+/// Name of the sqlite backend in the storage config
+pub const SQLITE: &str = "sqlite";
+
+/// Call a trait method on the selected database
+macro_rules! dispatch {
+    ($self:ident, $trait:ident::$method:ident($($arg:expr),*)) => {
+        match $self {
+            StorageDatabase::Postgres(db) => $trait::$method(db, $($arg),*),
+            StorageDatabase::Sqlite(db) => $trait::$method(db, $($arg),*),
+        }
+    };
+}
+
+impl StorageDatabase {
+    /// Name of the selected backend
+    pub fn backend(&self) -> &'static str {
+        match self {
+            StorageDatabase::Postgres(_) => POSTGRES,
+            StorageDatabase::Sqlite(_) => SQLITE,
+        }
+    }
+}
+
+impl Database for StorageDatabase {
+    // Postgres until a connection is set from the config
+    fn get_new() -> Self {
+        StorageDatabase::Postgres(<PGDatabase as Database>::get_new())
+    }
+
+    fn from_pool(pool: r2d2::Pool<PostgresConnectionManager>) -> Self {
+        StorageDatabase::Postgres(PGDatabase::from_pool(pool))
+    }
+
+    fn set_connection_from_config(&mut self, config: &crate::config::Config) -> Result<()> {
+        match config.storage.db_backend.as_str() {
+            POSTGRES => {
+                if let StorageDatabase::Sqlite(_) = self {
+                    *self = StorageDatabase::Postgres(<PGDatabase as Database>::get_new());
+                }
+            }
+            SQLITE => {
+                if let StorageDatabase::Postgres(_) = self {
+                    *self = StorageDatabase::Sqlite(<SqliteDatabase as Database>::get_new());
+                }
+            }
+            backend => {
+                return Err(SEError::Generic(format!(
+                    "Unknown storage backend: {}",
+                    backend
+                )))
+            }
+        }
+        dispatch!(self, Database::set_connection_from_config(config))
+    }
+
+    fn set_connection(&mut self, url: &String) -> Result<()> {
+        dispatch!(self, Database::set_connection(url))
+    }
+
+    fn get_user_auth(&self, user_id: Uuid) -> Result<Uuid> {
+        dispatch!(self, Database::get_user_auth(user_id))
+    }
+
+    fn has_withdraw_sc_sig(&self, user_id: Uuid) -> Result<()> {
+        dispatch!(self, Database::has_withdraw_sc_sig(user_id))
+    }
+
+    fn update_withdraw_sc_sig(
+        &self,
+        user_id: &Uuid,
+        sig: StateChainSig,
+        statechain_ids: Vec<Uuid>,
+        outputs: Vec<WithdrawOutput>,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_withdraw_sc_sig(user_id, sig, statechain_ids, outputs)
+        )
+    }
+
+    fn get_withdraw_batch(&self, user_id: Uuid) -> Result<Vec<Uuid>> {
+        dispatch!(self, Database::get_withdraw_batch(user_id))
+    }
+
+    fn get_withdraw_outputs(&self, user_id: Uuid) -> Result<Vec<WithdrawOutput>> {
+        dispatch!(self, Database::get_withdraw_outputs(user_id))
+    }
+
+    fn update_withdraw_tx_sighash(
+        &self,
+        user_id: &Uuid,
+        sig_hash: Hash,
+        tx: Transaction,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_withdraw_tx_sighash(user_id, sig_hash, tx)
+        )
+    }
+
+    fn update_sighash(&self, user_id: &Uuid, sig_hash: Hash) -> Result<()> {
+        dispatch!(self, Database::update_sighash(user_id, sig_hash))
+    }
+
+    fn update_s1_pubkey(&self, user_id: &Uuid, pubkey: &GE) -> Result<()> {
+        dispatch!(self, Database::update_s1_pubkey(user_id, pubkey))
+    }
+
+    fn get_s1_pubkey(&self, user_id: &Uuid) -> Result<GE> {
+        dispatch!(self, Database::get_s1_pubkey(user_id))
+    }
+
+    fn update_key_type(&self, user_id: &Uuid, key_type: &KeyType) -> Result<()> {
+        dispatch!(self, Database::update_key_type(user_id, key_type))
+    }
+
+    fn get_key_type(&self, user_id: Uuid) -> Result<KeyType> {
+        dispatch!(self, Database::get_key_type(user_id))
+    }
+
+    fn update_musig_key(&self, user_id: &Uuid, key: &MuSigKey) -> Result<()> {
+        dispatch!(self, Database::update_musig_key(user_id, key))
+    }
+
+    fn get_musig_key(&self, user_id: Uuid) -> Result<MuSigKey> {
+        dispatch!(self, Database::get_musig_key(user_id))
+    }
+
+    fn update_musig_nonces(&self, user_id: &Uuid, nonces: &Option<MuSigNonces>) -> Result<()> {
+        dispatch!(self, Database::update_musig_nonces(user_id, nonces))
+    }
+
+    fn get_musig_nonces(&self, user_id: Uuid) -> Result<Option<MuSigNonces>> {
+        dispatch!(self, Database::get_musig_nonces(user_id))
+    }
+
+    fn update_user_backup_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()> {
+        dispatch!(self, Database::update_user_backup_tx(user_id, tx))
+    }
+
+    fn get_user_backup_tx(&self, user_id: Uuid) -> Result<Transaction> {
+        dispatch!(self, Database::get_user_backup_tx(user_id))
+    }
+
+    fn update_backup_tx(&self, statechain_id: &Uuid, tx: Transaction) -> Result<()> {
+        dispatch!(self, Database::update_backup_tx(statechain_id, tx))
+    }
+
+    fn update_user_kickoff_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()> {
+        dispatch!(self, Database::update_user_kickoff_tx(user_id, tx))
+    }
+
+    fn get_user_kickoff_tx(&self, user_id: Uuid) -> Result<Transaction> {
+        dispatch!(self, Database::get_user_kickoff_tx(user_id))
+    }
+
+    fn get_withdraw_confirm_data(&self, user_id: Uuid) -> Result<WithdrawConfirmData> {
+        dispatch!(self, Database::get_withdraw_confirm_data(user_id))
+    }
+
+    fn has_refresh_sc_sig(&self, user_id: Uuid) -> Result<()> {
+        dispatch!(self, Database::has_refresh_sc_sig(user_id))
+    }
+
+    fn update_refresh_sc_sig(&self, user_id: &Uuid, sig: StateChainSig) -> Result<()> {
+        dispatch!(self, Database::update_refresh_sc_sig(user_id, sig))
+    }
+
+    fn update_user_refresh_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()> {
+        dispatch!(self, Database::update_user_refresh_tx(user_id, tx))
+    }
+
+    fn get_user_refresh_tx(&self, user_id: Uuid) -> Result<Transaction> {
+        dispatch!(self, Database::get_user_refresh_tx(user_id))
+    }
+
+    fn get_refresh_confirm_data(&self, user_id: Uuid) -> Result<RefreshConfirmData> {
+        dispatch!(self, Database::get_refresh_confirm_data(user_id))
+    }
+
+    fn remove_refresh_data(&self, user_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::remove_refresh_data(user_id))
+    }
+
+    fn root_update(&self, rt: &Root) -> Result<i64> {
+        dispatch!(self, Database::root_update(rt))
+    }
+
+    fn root_insert(&self, root: Root) -> Result<u64> {
+        dispatch!(self, Database::root_insert(root))
+    }
+
+    fn root_get_current_id(&self) -> Result<i64> {
+        dispatch!(self, Database::root_get_current_id())
+    }
+
+    fn get_root(&self, id: i64) -> Result<Option<Root>> {
+        dispatch!(self, Database::get_root(id))
+    }
+
+    fn get_confirmed_smt_root(&self) -> Result<Option<Root>> {
+        dispatch!(self, Database::get_confirmed_smt_root())
+    }
+
+    fn get_statechain_id(&self, user_id: Uuid) -> Result<Uuid> {
+        dispatch!(self, Database::get_statechain_id(user_id))
+    }
+
+    fn update_statechain_id(&self, user_id: &Uuid, statechain_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::update_statechain_id(user_id, statechain_id))
+    }
+
+    fn get_statechain_amount(&self, statechain_id: Uuid) -> Result<StateChainAmount> {
+        dispatch!(self, Database::get_statechain_amount(statechain_id))
+    }
+
+    fn update_statechain_amount(
+        &self,
+        statechain_id: &Uuid,
+        state_chain: StateChain,
+        amount: u64,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_statechain_amount(statechain_id, state_chain, amount)
+        )
+    }
+
+    fn create_statechain(
+        &self,
+        statechain_id: &Uuid,
+        user_id: &Uuid,
+        state_chain: &StateChain,
+        amount: &i64,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::create_statechain(statechain_id, user_id, state_chain, amount)
+        )
+    }
+
+    fn get_statechain(&self, statechain_id: Uuid) -> Result<StateChain> {
+        dispatch!(self, Database::get_statechain(statechain_id))
+    }
+
+    fn update_statechain_owner(
+        &self,
+        statechain_id: &Uuid,
+        state_chain: StateChain,
+        new_user_id: &Uuid,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_statechain_owner(statechain_id, state_chain, new_user_id)
+        )
+    }
+
+    fn remove_statechain_id(&self, user_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::remove_statechain_id(user_id))
+    }
+
+    fn create_backup_transaction(
+        &self,
+        statechain_id: &Uuid,
+        tx_backup: &Transaction,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::create_backup_transaction(statechain_id, tx_backup)
+        )
+    }
+
+    fn get_current_backup_txs(&self, locktime: i64) -> Result<Vec<BackupTxID>> {
+        dispatch!(self, Database::get_current_backup_txs(locktime))
+    }
+
+    fn remove_backup_tx(&self, statechain_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::remove_backup_tx(statechain_id))
+    }
+
+    fn update_cpfp_tx(&self, statechain_id: &Uuid, tx: &Transaction) -> Result<()> {
+        dispatch!(self, Database::update_cpfp_tx(statechain_id, tx))
+    }
+
+    fn get_cpfp_tx(&self, statechain_id: &Uuid) -> Result<Option<Transaction>> {
+        dispatch!(self, Database::get_cpfp_tx(statechain_id))
+    }
+
+    fn update_backup_confirmation(
+        &self,
+        statechain_id: &Uuid,
+        block: &Option<(u64, BlockHash)>,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_backup_confirmation(statechain_id, block)
+        )
+    }
+
+    fn get_backup_confirmation(&self, statechain_id: &Uuid) -> Result<Option<(u64, BlockHash)>> {
+        dispatch!(self, Database::get_backup_confirmation(statechain_id))
+    }
+
+    fn get_backup_transaction(&self, statechain_id: Uuid) -> Result<Transaction> {
+        dispatch!(self, Database::get_backup_transaction(statechain_id))
+    }
+
+    fn create_kickoff_transaction(
+        &self,
+        statechain_id: &Uuid,
+        tx_kickoff: &Transaction,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::create_kickoff_transaction(statechain_id, tx_kickoff)
+        )
+    }
+
+    fn get_kickoff_transaction(&self, statechain_id: Uuid) -> Result<Transaction> {
+        dispatch!(self, Database::get_kickoff_transaction(statechain_id))
+    }
+
+    fn get_backup_transaction_and_proof_key(&self, user_id: Uuid) -> Result<(Transaction, String)> {
+        dispatch!(
+            self,
+            Database::get_backup_transaction_and_proof_key(user_id)
+        )
+    }
+
+    fn get_proof_key(&self, user_id: Uuid) -> Result<String> {
+        dispatch!(self, Database::get_proof_key(user_id))
+    }
+
+    fn get_sc_locked_until(&self, statechain_id: Uuid) -> Result<NaiveDateTime> {
+        dispatch!(self, Database::get_sc_locked_until(statechain_id))
+    }
+
+    fn update_locked_until(&self, statechain_id: &Uuid, time: &NaiveDateTime) -> Result<()> {
+        dispatch!(self, Database::update_locked_until(statechain_id, time))
+    }
+
+    fn get_transfer_batch_data(&self, batch_id: Uuid) -> Result<TransferBatchData> {
+        dispatch!(self, Database::get_transfer_batch_data(batch_id))
+    }
+
+    fn has_transfer_batch_id(&self, batch_id: Uuid) -> bool {
+        dispatch!(self, Database::has_transfer_batch_id(batch_id))
+    }
+
+    fn get_transfer_batch_id(&self, batch_id: Uuid) -> Result<Uuid> {
+        dispatch!(self, Database::get_transfer_batch_id(batch_id))
+    }
+
+    fn get_punished_state_chains(&self, batch_id: Uuid) -> Result<Vec<Uuid>> {
+        dispatch!(self, Database::get_punished_state_chains(batch_id))
+    }
+
+    fn create_transfer(
+        &self,
+        statechain_id: &Uuid,
+        statechain_sig: &StateChainSig,
+        x1: &FE,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::create_transfer(statechain_id, statechain_sig, x1)
+        )
+    }
+
+    fn update_transfer_msg(&self, statechain_id: &Uuid, msg: &TransferMsg3) -> Result<()> {
+        dispatch!(self, Database::update_transfer_msg(statechain_id, msg))
+    }
+
+    fn get_transfer_msg(&self, statechain_id: &Uuid) -> Result<TransferMsg3> {
+        dispatch!(self, Database::get_transfer_msg(statechain_id))
+    }
+
+    fn create_transfer_batch_data(&self, batch_id: &Uuid, state_chains: Vec<Uuid>) -> Result<()> {
+        dispatch!(
+            self,
+            Database::create_transfer_batch_data(batch_id, state_chains)
+        )
+    }
+
+    fn get_transfer_data(&self, statechain_id: Uuid) -> Result<TransferData> {
+        dispatch!(self, Database::get_transfer_data(statechain_id))
+    }
+
+    fn remove_transfer_data(&self, statechain_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::remove_transfer_data(statechain_id))
+    }
+
+    fn add_backup_tx_history(
+        &self,
+        statechain_id: &Uuid,
+        tx: &Transaction,
+        proof_key: &String,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::add_backup_tx_history(statechain_id, tx, proof_key)
+        )
+    }
+
+    fn get_backup_tx_history(&self, statechain_id: Uuid) -> Result<Vec<BackupTxRecord>> {
+        dispatch!(self, Database::get_backup_tx_history(statechain_id))
+    }
+
+    fn get_active_statechain_ids(&self) -> Result<Vec<Uuid>> {
+        dispatch!(self, Database::get_active_statechain_ids())
+    }
+
+    fn update_funding_confirmation(
+        &self,
+        statechain_id: &Uuid,
+        confirmation: &FundingConfirmation,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_funding_confirmation(statechain_id, confirmation)
+        )
+    }
+
+    fn get_funding_confirmation(&self, statechain_id: Uuid) -> Result<Option<FundingConfirmation>> {
+        dispatch!(self, Database::get_funding_confirmation(statechain_id))
+    }
+
+    fn remove_funding_confirmation(&self, statechain_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::remove_funding_confirmation(statechain_id))
+    }
+
+    fn get_funding_confirmation_ids(&self) -> Result<Vec<Uuid>> {
+        dispatch!(self, Database::get_funding_confirmation_ids())
+    }
+
+    fn update_funding_outpoint(&self, statechain_id: &Uuid, outpoint: &OutPoint) -> Result<()> {
+        dispatch!(self, Database::update_funding_outpoint(statechain_id, outpoint))
+    }
+
+    fn get_funding_outpoint(&self, statechain_id: Uuid) -> Result<Option<OutPoint>> {
+        dispatch!(self, Database::get_funding_outpoint(statechain_id))
+    }
+
+    fn update_refresh_pending(&self, statechain_id: &Uuid, txid: &Txid) -> Result<()> {
+        dispatch!(self, Database::update_refresh_pending(statechain_id, txid))
+    }
+
+    fn get_refresh_pending(&self, statechain_id: Uuid) -> Result<Option<Txid>> {
+        dispatch!(self, Database::get_refresh_pending(statechain_id))
+    }
+
+    fn remove_refresh_pending(&self, statechain_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::remove_refresh_pending(statechain_id))
+    }
+
+    fn update_aborted_locktime(&self, statechain_id: &Uuid, locktime: u32) -> Result<()> {
+        dispatch!(self, Database::update_aborted_locktime(statechain_id, locktime))
+    }
+
+    fn get_aborted_locktime(&self, statechain_id: Uuid) -> Result<Option<u32>> {
+        dispatch!(self, Database::get_aborted_locktime(statechain_id))
+    }
+
+    fn remove_aborted_locktime(&self, statechain_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::remove_aborted_locktime(statechain_id))
+    }
+
+    fn update_statechain_closure(
+        &self,
+        statechain_id: &Uuid,
+        closure: &StateChainClosureAPI,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_statechain_closure(statechain_id, closure)
+        )
+    }
+
+    fn get_statechain_closure(&self, statechain_id: Uuid) -> Result<Option<StateChainClosureAPI>> {
+        dispatch!(self, Database::get_statechain_closure(statechain_id))
+    }
+
+    fn get_statechain_listings(
+        &self,
+        filter: &StateChainFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(Vec<StateChainListing>, u64)> {
+        dispatch!(
+            self,
+            Database::get_statechain_listings(filter, offset, limit)
+        )
+    }
+
+    fn get_statechain_listing(&self, statechain_id: Uuid) -> Result<StateChainListing> {
+        dispatch!(self, Database::get_statechain_listing(statechain_id))
+    }
+
+    fn search_statechains(&self, query: &String) -> Result<Vec<Uuid>> {
+        dispatch!(self, Database::search_statechains(query))
+    }
+
+    fn get_user_sessions(&self, offset: u64, limit: u64) -> Result<(Vec<UserSessionAPI>, u64)> {
+        dispatch!(self, Database::get_user_sessions(offset, limit))
+    }
+
+    fn get_transfers_started_before(&self, time: &NaiveDateTime) -> Result<Vec<Uuid>> {
+        dispatch!(self, Database::get_transfers_started_before(time))
+    }
+
+    fn get_unfunded_user_sessions(&self, created_before: &NaiveDateTime) -> Result<Vec<Uuid>> {
+        dispatch!(self, Database::get_unfunded_user_sessions(created_before))
+    }
+
+    fn remove_user_session(&self, user_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::remove_user_session(user_id))
+    }
+
+    fn remove_ecdsa(&self, user_id: &Uuid) -> Result<bool> {
+        dispatch!(self, Database::remove_ecdsa(user_id))
+    }
+
+    fn get_transfer_batches_started_before(&self, time: &NaiveDateTime) -> Result<Vec<Uuid>> {
+        dispatch!(self, Database::get_transfer_batches_started_before(time))
+    }
+
+    fn archive_transfer_batch(&self, batch_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::archive_transfer_batch(batch_id))
+    }
+
+    fn remove_orphaned_backup_txs(&self) -> Result<u64> {
+        dispatch!(self, Database::remove_orphaned_backup_txs())
+    }
+
+    fn transfer_is_completed(&self, statechain_id: Uuid) -> bool {
+        dispatch!(self, Database::transfer_is_completed(statechain_id))
+    }
+
+    fn get_ecdsa_master(&self, user_id: Uuid) -> Result<Option<String>> {
+        dispatch!(self, Database::get_ecdsa_master(user_id))
+    }
+
+    fn get_ecdsa_witness_keypair(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(party_one::CommWitness, party_one::EcKeyPair)> {
+        dispatch!(self, Database::get_ecdsa_witness_keypair(user_id))
+    }
+
+    fn get_ecdsa_s2(&self, user_id: Uuid) -> Result<FE> {
+        dispatch!(self, Database::get_ecdsa_s2(user_id))
+    }
+
+    fn update_keygen_first_msg(
+        &self,
+        user_id: &Uuid,
+        key_gen_first_msg: &party_one::KeyGenFirstMsg,
+        comm_witness: party_one::CommWitness,
+        ec_key_pair: party_one::EcKeyPair,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_keygen_first_msg(
+                user_id,
+                key_gen_first_msg,
+                comm_witness,
+                ec_key_pair
+            )
+        )
+    }
+
+    fn update_keygen_second_msg(
+        &self,
+        user_id: &Uuid,
+        party2_public: GE,
+        paillier_key_pair: party_one::PaillierKeyPair,
+        party_one_private: party_one::Party1Private,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_keygen_second_msg(
+                user_id,
+                party2_public,
+                paillier_key_pair,
+                party_one_private
+            )
+        )
+    }
+
+    fn init_ecdsa(&self, user_id: &Uuid) -> Result<u64> {
+        dispatch!(self, Database::init_ecdsa(user_id))
+    }
+
+    fn get_ecdsa_party_1_private(&self, user_id: Uuid) -> Result<party_one::Party1Private> {
+        dispatch!(self, Database::get_ecdsa_party_1_private(user_id))
+    }
+
+    fn get_ecdsa_keypair(&self, user_id: Uuid) -> Result<ECDSAKeypair> {
+        dispatch!(self, Database::get_ecdsa_keypair(user_id))
+    }
+
+    fn update_punished(&self, batch_id: &Uuid, punished_state_chains: Vec<Uuid>) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_punished(batch_id, punished_state_chains)
+        )
+    }
+
+    fn get_transfer_batch_start_time(&self, batch_id: &Uuid) -> Result<NaiveDateTime> {
+        dispatch!(self, Database::get_transfer_batch_start_time(batch_id))
+    }
+
+    fn get_batch_transfer_statechain_ids(&self, batch_id: &Uuid) -> Result<HashSet<Uuid>> {
+        dispatch!(self, Database::get_batch_transfer_statechain_ids(batch_id))
+    }
+
+    fn get_finalize_batch_data(&self, batch_id: Uuid) -> Result<TransferFinalizeBatchData> {
+        dispatch!(self, Database::get_finalize_batch_data(batch_id))
+    }
+
+    fn get_sc_finalize_batch_data(&self, statechain_id: &Uuid) -> Result<TransferFinalizeData> {
+        dispatch!(self, Database::get_sc_finalize_batch_data(statechain_id))
+    }
+
+    fn update_finalize_batch_data(
+        &self,
+        statechain_id: &Uuid,
+        finalized_data: &TransferFinalizeData,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_finalize_batch_data(statechain_id, finalized_data)
+        )
+    }
+
+    fn update_transfer_batch_finalized(&self, batch_id: &Uuid, b_finalized: &bool) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_transfer_batch_finalized(batch_id, b_finalized)
+        )
+    }
+
+    fn get_statechain_owner(&self, statechain_id: Uuid) -> Result<StateChainOwner> {
+        dispatch!(self, Database::get_statechain_owner(statechain_id))
+    }
+
+    fn create_user_session(&self, user_id: &Uuid, auth: &String, proof_key: &String) -> Result<()> {
+        dispatch!(
+            self,
+            Database::create_user_session(user_id, auth, proof_key)
+        )
+    }
+
+    fn spend_token(&self, token: &String) -> Result<bool> {
+        dispatch!(self, Database::spend_token(token))
+    }
+
+    fn transfer_init_user_session(
+        &self,
+        new_user_id: &Uuid,
+        statechain_id: &Uuid,
+        finalized_data: TransferFinalizeData,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::transfer_init_user_session(new_user_id, statechain_id, finalized_data)
+        )
+    }
+
+    fn update_ecdsa_sign_first(
+        &self,
+        user_id: Uuid,
+        eph_key_gen_first_message_party_two: party_two::EphKeyGenFirstMsg,
+        eph_ec_key_pair_party1: party_one::EphEcKeyPair,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_ecdsa_sign_first(
+                user_id,
+                eph_key_gen_first_message_party_two,
+                eph_ec_key_pair_party1
+            )
+        )
+    }
+
+    fn get_ecdsa_sign_second_input(&self, user_id: Uuid) -> Result<ECDSASignSecondInput> {
+        dispatch!(self, Database::get_ecdsa_sign_second_input(user_id))
+    }
+
+    fn get_tx_withdraw(&self, user_id: Uuid) -> Result<Transaction> {
+        dispatch!(self, Database::get_tx_withdraw(user_id))
+    }
+
+    fn update_tx_withdraw(&self, user_id: Uuid, tx: Transaction) -> Result<()> {
+        dispatch!(self, Database::update_tx_withdraw(user_id, tx))
+    }
+
+    fn reset(&self) -> Result<()> {
+        dispatch!(self, Database::reset())
+    }
+
+    fn init(&self) -> Result<()> {
+        dispatch!(self, Database::init())
+    }
+
+    fn get_ecdsa_master_key_input(&self, user_id: Uuid) -> Result<ECDSAMasterKeyInput> {
+        dispatch!(self, Database::get_ecdsa_master_key_input(user_id))
+    }
+
+    fn update_ecdsa_master(&self, user_id: &Uuid, master_key: MasterKey1) -> Result<()> {
+        dispatch!(self, Database::update_ecdsa_master(user_id, master_key))
+    }
+
+    fn get_sighash(&self, user_id: Uuid) -> Result<sha256d::Hash> {
+        dispatch!(self, Database::get_sighash(user_id))
+    }
+
+    fn update_swap_registration(
+        &self,
+        statechain_id: &Uuid,
+        amount: u64,
+        swap_size: u64,
+    ) -> Result<()> {
+        dispatch!(
+            self,
+            Database::update_swap_registration(statechain_id, amount, swap_size)
+        )
+    }
+
+    fn remove_swap_registration(&self, statechain_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::remove_swap_registration(statechain_id))
+    }
+
+    fn get_swap_registrations(&self) -> Result<Vec<SwapRegistration>> {
+        dispatch!(self, Database::get_swap_registrations())
+    }
+
+    fn update_swap(&self, swap_data: &SwapData) -> Result<()> {
+        dispatch!(self, Database::update_swap(swap_data))
+    }
+
+    fn remove_swap(&self, swap_id: &Uuid) -> Result<()> {
+        dispatch!(self, Database::remove_swap(swap_id))
+    }
+
+    fn get_swaps(&self) -> Result<Vec<SwapData>> {
+        dispatch!(self, Database::get_swaps())
+    }
+}
+
+impl MonotreeDatabase for StorageDatabase {
+    fn new(dbname: &str) -> Self {
+        StorageDatabase::Postgres(<PGDatabase as MonotreeDatabase>::new(dbname))
+    }
+
+    fn get(&mut self, key: &[u8]) -> std::result::Result<Option<Vec<u8>>, Errors> {
+        dispatch!(self, MonotreeDatabase::get(key))
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> std::result::Result<(), Errors> {
+        dispatch!(self, MonotreeDatabase::put(key, value))
+    }
+
+    fn delete(&mut self, key: &[u8]) -> std::result::Result<(), Errors> {
+        dispatch!(self, MonotreeDatabase::delete(key))
+    }
+
+    fn init_batch(&mut self) -> std::result::Result<(), Errors> {
+        dispatch!(self, MonotreeDatabase::init_batch())
+    }
+
+    fn finish_batch(&mut self) -> std::result::Result<(), Errors> {
+        dispatch!(self, MonotreeDatabase::finish_batch())
+    }
+}
@@ -3,7 +3,7 @@
 //! Postgres DB access and update tools.
 
 use super::super::Result;
-use bitcoin::Transaction;
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
 pub type Hash = bitcoin::hashes::sha256d::Hash;
 
 use crate::protocol::transfer::TransferFinalizeData;
@@ -27,11 +27,16 @@ use rocket_contrib::databases::r2d2;
 use rocket_contrib::databases::r2d2_postgres::{PostgresConnectionManager, TlsMode};
 use shared_lib::mainstay::CommitmentInfo;
 use shared_lib::state_chain::*;
-use shared_lib::structs::TransferMsg3;
+use shared_lib::structs::{
+    KeyType, SCEAddress, StateChainClosureAPI, StateChainStatus, TransferMsg3, UserSessionAPI,
+    WithdrawOutput,
+};
+use shared_lib::swap_data::SwapInfo;
 use shared_lib::Root;
 use shared_lib::util::transaction_deserialise;
 use rocket_okapi::JsonSchema;
 
+use bisetmap::BisetMap;
 use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
@@ -65,9 +70,13 @@ pub enum Table {
     StateChain,
     Transfer,
     TransferBatch,
+    TransferBatchArchive,
     Root,
     BackupTxs,
     Smt,
+    SwapRegistration,
+    Swap,
+    SpentToken,
 }
 impl Table {
     pub fn to_string(&self) -> String {
@@ -97,12 +106,21 @@ pub enum Column {
     ProofKey,
     StateChainId,
     TxBackup,
+    TxKickOff,
     LockTime,
     TxWithdraw,
     SigHash,
     S2,
     S1PubKey,
     WithdrawScSig,
+    WithdrawBatch,
+    WithdrawOutputs,
+    KeyType,
+    MuSigKey,
+    MuSigNonces,
+    RefreshScSig,
+    TxRefresh,
+    CreatedAt,
 
     // StateChain
     // Id,
@@ -112,10 +130,20 @@ pub enum Column {
     OwnerId,
     TransferFinalizeData,
     TransferReady,
+    BackupTxHistory,
+    Closure,
+    FundingConfirmation,
+    FundingTxid,
+    FundingOutpoint,
+    RefreshPending,
+    AbortedLocktime,
 
     // BackupTxs
     //Id,
     // TxBackup,
+    // TxKickOff,
+    TxCpfp,
+    BackupConfirmation,
 
     // Transfer
     // Id,
@@ -151,6 +179,21 @@ pub enum Column {
     // Smt
     Key,
     // Value
+
+    // SwapRegistration
+    // Id,
+    // Amount,
+    SwapSize,
+
+    // Swap
+    // Id,
+    SwapInfo,
+    OutAddrMap,
+    BstEPrimeMap,
+    BstSigMap,
+    TbSigMap,
+    BstRetrieved,
+    PhaseStart,
 }
 
 impl Column {
@@ -159,6 +202,33 @@ impl Column {
     }
 }
 
+/// Columns added to tables after their first release, with their Postgres and SQLite types.
+/// make_tables adds them to the tables of existing DBs.
+pub const ADDED_COLUMNS: &[(Table, Column, &str, &str)] = &[
+    (Table::UserSession, Column::WithdrawBatch, "varchar", "text"),
+    (Table::UserSession, Column::WithdrawOutputs, "varchar", "text"),
+    (Table::UserSession, Column::KeyType, "varchar", "text"),
+    (Table::UserSession, Column::MuSigKey, "varchar", "text"),
+    (Table::UserSession, Column::MuSigNonces, "varchar", "text"),
+    (Table::UserSession, Column::TxKickOff, "varchar", "text"),
+    (Table::UserSession, Column::RefreshScSig, "varchar", "text"),
+    (Table::UserSession, Column::TxRefresh, "varchar", "text"),
+    (Table::UserSession, Column::CreatedAt, "timestamp", "text"),
+    (Table::StateChain, Column::BackupTxHistory, "varchar", "text"),
+    (Table::StateChain, Column::Closure, "varchar", "text"),
+    (Table::StateChain, Column::FundingConfirmation, "varchar", "text"),
+    (Table::StateChain, Column::ProofKey, "varchar", "text"),
+    (Table::StateChain, Column::FundingTxid, "varchar", "text"),
+    (Table::StateChain, Column::FundingOutpoint, "varchar", "text"),
+    (Table::StateChain, Column::RefreshPending, "varchar", "text"),
+    (Table::StateChain, Column::AbortedLocktime, "int8", "integer"),
+    (Table::Transfer, Column::StartTime, "timestamp", "text"),
+    (Table::BackupTxs, Column::TxKickOff, "varchar", "text"),
+    (Table::BackupTxs, Column::TxCpfp, "varchar", "text"),
+    (Table::BackupTxs, Column::BackupConfirmation, "varchar", "text"),
+    (Table::Swap, Column::PhaseStart, "varchar", "text"),
+];
+
 impl PGDatabase {
     fn get_postgres_connection_pool(
         rocket_url: &String,
@@ -241,9 +311,18 @@ impl PGDatabase {
                 s1pubkey varchar,
                 sighash varchar,
                 withdrawscsig varchar,
+                withdrawbatch varchar,
+                withdrawoutputs varchar,
+                keytype varchar,
+                musigkey varchar,
+                musignonces varchar,
                 txwithdraw varchar,
                 proofkey varchar,
                 txbackup varchar,
+                txkickoff varchar,
+                refreshscsig varchar,
+                txrefresh varchar,
+                createdat timestamp,
                 PRIMARY KEY (id)
             );",
                 Table::UserSession.to_string(),
@@ -285,6 +364,14 @@ impl PGDatabase {
                 lockeduntil timestamp,
                 transferfinalizedata varchar,
                 transferready bool,
+                backuptxhistory varchar,
+                closure varchar,
+                fundingconfirmation varchar,
+                proofkey varchar,
+                fundingtxid varchar,
+                fundingoutpoint varchar,
+                refreshpending varchar,
+                abortedlocktime int8,
                 PRIMARY KEY (id)
             );",
                 Table::StateChain.to_string(),
@@ -300,6 +387,7 @@ impl PGDatabase {
                 statechainsig varchar,
                 x1 varchar,
                 transfermsg varchar,
+                starttime timestamp,
                 PRIMARY KEY (id)
             );",
                 Table::Transfer.to_string(),
@@ -323,6 +411,23 @@ impl PGDatabase {
             &[],
         )?;
 
+        // Ended batch transfers moved out of TransferBatch by garbage collection
+        self.database_w()?.execute(
+            &format!(
+                "
+            CREATE TABLE IF NOT EXISTS {} (
+                id uuid NOT NULL,
+                starttime timestamp,
+                statechains varchar,
+                punishedstatechains varchar,
+                finalized bool,
+                PRIMARY KEY (id)
+            );",
+                Table::TransferBatchArchive.to_string(),
+            ),
+            &[],
+        )?;
+
         self.database_w()?.execute(
             &format!(
                 "
@@ -343,7 +448,10 @@ impl PGDatabase {
             CREATE TABLE IF NOT EXISTS {} (
                 id uuid NOT NULL,
                 txbackup varchar,
+                txkickoff varchar,
                 locktime int8,
+                txcpfp varchar,
+                backupconfirmation varchar,
                 PRIMARY KEY (id)
             );",
                 Table::BackupTxs.to_string(),
@@ -364,6 +472,111 @@ impl PGDatabase {
             &[],
         )?;
 
+        self.database_w()?.execute(
+            &format!(
+                "
+            CREATE TABLE IF NOT EXISTS {} (
+                id uuid NOT NULL,
+                amount int8,
+                swapsize int8,
+                PRIMARY KEY (id)
+            );",
+                Table::SwapRegistration.to_string(),
+            ),
+            &[],
+        )?;
+
+        self.database_w()?.execute(
+            &format!(
+                "
+            CREATE TABLE IF NOT EXISTS {} (
+                id uuid NOT NULL,
+                swapinfo varchar,
+                outaddrmap varchar,
+                bsteprimemap varchar,
+                bstsigmap varchar,
+                tbsigmap varchar,
+                bstretrieved varchar,
+                phasestart varchar,
+                PRIMARY KEY (id)
+            );",
+                Table::Swap.to_string(),
+            ),
+            &[],
+        )?;
+
+        self.database_w()?.execute(
+            &format!(
+                "
+            CREATE TABLE IF NOT EXISTS {} (
+                id varchar,
+                PRIMARY KEY (id)
+            );",
+                Table::SpentToken.to_string(),
+            ),
+            &[],
+        )?;
+
+        self.migrate_tables()?;
+
+        // Explorer queries
+        for column in &["amount", "proofkey", "fundingtxid"] {
+            self.database_w()?.execute(
+                &format!(
+                    "CREATE INDEX IF NOT EXISTS statechain_{}_idx ON {} ({});",
+                    column,
+                    Table::StateChain.to_string(),
+                    column,
+                ),
+                &[],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Add the columns missing from tables created by earlier versions and fill in those that
+    /// can be derived from existing rows
+    fn migrate_tables(&self) -> Result<()> {
+        for (table, column, column_type, _) in ADDED_COLUMNS {
+            self.database_w()?.execute(
+                &format!(
+                    "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {};",
+                    table.to_string(),
+                    column.to_string(),
+                    column_type,
+                ),
+                &[],
+            )?;
+        }
+
+        // The proof key of a statechain is the data of its tip
+        let mut proof_keys = vec![];
+        {
+            let dbw = self.database_w()?;
+            let statement = dbw.prepare(&format!(
+                "SELECT id, chain FROM {} WHERE proofkey IS NULL AND chain IS NOT NULL",
+                Table::StateChain.to_string(),
+            ))?;
+            let rows = statement.query(&[])?;
+            for row in &rows {
+                let id: Uuid = row.get("id");
+                let state_chain: StateChain = Self::deser(row.get("chain"))?;
+                proof_keys.push((id, state_chain.get_tip()?.data));
+            }
+        }
+        for (id, proof_key) in proof_keys {
+            self.update(&id, Table::StateChain, vec![Column::ProofKey], vec![&proof_key])?;
+        }
+
+        // Sessions created before their creation time was stored expire as if created now
+        self.database_w()?.execute(
+            &format!(
+                "UPDATE {} SET createdat = $1 WHERE createdat IS NULL",
+                Table::UserSession.to_string(),
+            ),
+            &[&get_time_now()],
+        )?;
         Ok(())
     }
 
@@ -393,15 +606,19 @@ impl PGDatabase {
         self.database_w()?.execute(
             &format!(
                 "
-            TRUNCATE {},{},{},{},{},{},{},{} RESTART IDENTITY;",
+            TRUNCATE {},{},{},{},{},{},{},{},{},{},{},{} RESTART IDENTITY;",
                 Table::UserSession.to_string(),
                 Table::Ecdsa.to_string(),
                 Table::StateChain.to_string(),
                 Table::Transfer.to_string(),
                 Table::TransferBatch.to_string(),
+                Table::TransferBatchArchive.to_string(),
                 Table::Root.to_string(),
                 Table::BackupTxs.to_string(),
                 Table::Smt.to_string(),
+                Table::SwapRegistration.to_string(),
+                Table::Swap.to_string(),
+                Table::SpentToken.to_string(),
             ),
             &[],
         )?;
@@ -432,6 +649,23 @@ impl PGDatabase {
         }
     }
 
+    /// Read a statechain from a row of an explorer query
+    fn statechain_listing(row: &Row) -> Result<StateChainListing> {
+        let tx_backup: Option<String> = row.get("txbackup");
+        Ok(StateChainListing {
+            id: row.get("id"),
+            amount: row.get("amount"),
+            chain: Self::deser(row.get("chain"))?,
+            locked_until: row.get("lockeduntil"),
+            funding_txid: row.get("fundingtxid"),
+            closed: row.get("closed"),
+            tx_backup: match tx_backup {
+                Some(tx) => Some(Self::deser(tx)?),
+                None => None,
+            },
+        })
+    }
+
     /// Create new item in table
     pub fn insert(&self, id: &Uuid, table: Table) -> Result<u64> {
         let dbw = self.database_w()?;
@@ -455,6 +689,15 @@ impl PGDatabase {
         Ok(())
     }
 
+    /// Remove row in table if it exists
+    pub fn remove_if_exists(&self, id: &Uuid, table: Table) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement =
+            dbw.prepare(&format!("DELETE FROM {} WHERE id = $1;", table.to_string()))?;
+        statement.execute(&[&id])?;
+        Ok(())
+    }
+
     /// Returns str list of column names for SQL UPDATE prepare statement.
     fn update_columns_str(&self, cols: Vec<Column>) -> String {
         let cols_len = cols.len();
@@ -702,15 +945,37 @@ impl Database for PGDatabase {
         }
     }
 
-    fn update_withdraw_sc_sig(&self, user_id: &Uuid, sig: StateChainSig) -> Result<()> {
+    fn update_withdraw_sc_sig(
+        &self,
+        user_id: &Uuid,
+        sig: StateChainSig,
+        statechain_ids: Vec<Uuid>,
+        outputs: Vec<WithdrawOutput>,
+    ) -> Result<()> {
         self.update(
             user_id,
             Table::UserSession,
-            vec![Column::WithdrawScSig],
-            vec![&Self::ser(sig)?],
+            vec![
+                Column::WithdrawScSig,
+                Column::WithdrawBatch,
+                Column::WithdrawOutputs,
+            ],
+            vec![
+                &Self::ser(sig)?,
+                &Self::ser(statechain_ids)?,
+                &Self::ser(outputs)?,
+            ],
         )
     }
 
+    fn get_withdraw_batch(&self, user_id: Uuid) -> Result<Vec<Uuid>> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::WithdrawBatch])?)
+    }
+
+    fn get_withdraw_outputs(&self, user_id: Uuid) -> Result<Vec<WithdrawOutput>> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::WithdrawOutputs])?)
+    }
+
     fn update_s1_pubkey(&self, user_id: &Uuid, pubkey: &GE) -> Result<()> {
         self.update(
             user_id,
@@ -726,6 +991,57 @@ impl Database for PGDatabase {
         Ok(pubkey)
     }
 
+    fn update_key_type(&self, user_id: &Uuid, key_type: &KeyType) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::KeyType],
+            vec![&Self::ser(key_type)?],
+        )
+    }
+
+    fn get_key_type(&self, user_id: Uuid) -> Result<KeyType> {
+        match self.get_1::<String>(user_id, Table::UserSession, vec![Column::KeyType]) {
+            Ok(key_type_str) => Self::deser(key_type_str),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(KeyType::Ecdsa),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn update_musig_key(&self, user_id: &Uuid, key: &MuSigKey) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::MuSigKey],
+            vec![&Self::ser(key)?],
+        )
+    }
+
+    fn get_musig_key(&self, user_id: Uuid) -> Result<MuSigKey> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::MuSigKey])?)
+    }
+
+    fn update_musig_nonces(&self, user_id: &Uuid, nonces: &Option<MuSigNonces>) -> Result<()> {
+        let nonces_str = match nonces {
+            Some(n) => Some(Self::ser(n)?),
+            None => None,
+        };
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::MuSigNonces],
+            vec![&nonces_str],
+        )
+    }
+
+    fn get_musig_nonces(&self, user_id: Uuid) -> Result<Option<MuSigNonces>> {
+        match self.get_1::<String>(user_id, Table::UserSession, vec![Column::MuSigNonces]) {
+            Ok(nonces_str) => Ok(Some(Self::deser(nonces_str)?)),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
     fn update_withdraw_tx_sighash(
         &self,
         user_id: &Uuid,
@@ -773,11 +1089,24 @@ impl Database for PGDatabase {
         self.update(
             statechain_id,
             Table::BackupTxs,
-            vec![Column::TxBackup,Column::LockTime],
-            vec![&Self::ser(tx)?,&(locktime as i64)],
+            vec![Column::TxBackup,Column::LockTime,Column::TxCpfp,Column::BackupConfirmation],
+            vec![&Self::ser(tx)?,&(locktime as i64),&Option::<String>::None,&Option::<String>::None],
+        )
+    }
+
+    fn update_user_kickoff_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::TxKickOff],
+            vec![&Self::ser(tx)?],
         )
     }
 
+    fn get_user_kickoff_tx(&self, user_id: Uuid) -> Result<Transaction> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::TxKickOff])?)
+    }
+
     fn get_withdraw_confirm_data(&self, user_id: Uuid) -> Result<WithdrawConfirmData> {
         let (tx_withdraw_str, withdraw_sc_sig_str, statechain_id) = self
             .get_3::<String, String, Uuid>(
@@ -798,6 +1127,64 @@ impl Database for PGDatabase {
         })
     }
 
+    fn has_refresh_sc_sig(&self, user_id: Uuid) -> Result<()> {
+        match self.get_1::<String>(user_id, Table::UserSession, vec![Column::RefreshScSig]) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn update_refresh_sc_sig(&self, user_id: &Uuid, sig: StateChainSig) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::RefreshScSig],
+            vec![&Self::ser(sig)?],
+        )
+    }
+
+    fn update_user_refresh_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::TxRefresh],
+            vec![&Self::ser(tx)?],
+        )
+    }
+
+    fn get_user_refresh_tx(&self, user_id: Uuid) -> Result<Transaction> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::TxRefresh])?)
+    }
+
+    fn get_refresh_confirm_data(&self, user_id: Uuid) -> Result<RefreshConfirmData> {
+        let (tx_refresh_str, refresh_sc_sig_str, statechain_id) = self
+            .get_3::<String, String, Uuid>(
+                user_id,
+                Table::UserSession,
+                vec![
+                    Column::TxRefresh,
+                    Column::RefreshScSig,
+                    Column::StateChainId,
+                ],
+            )?;
+        let tx_refresh: Transaction = Self::deser(tx_refresh_str)?;
+        let refresh_sc_sig: StateChainSig = Self::deser(refresh_sc_sig_str)?;
+        Ok(RefreshConfirmData {
+            tx_refresh,
+            refresh_sc_sig,
+            statechain_id,
+        })
+    }
+
+    fn remove_refresh_data(&self, user_id: &Uuid) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::TxRefresh, Column::RefreshScSig],
+            vec![&Option::<String>::None, &Option::<String>::None],
+        )
+    }
+
     /// Update root value in DB. Update root with ID or insert new DB item.
     fn root_update(&self, rt: &Root) -> Result<i64> {
         let mut root = rt.clone();
@@ -892,6 +1279,52 @@ impl Database for PGDatabase {
         self.remove(statechain_id, Table::BackupTxs)
     }
 
+    fn update_cpfp_tx(&self, statechain_id: &Uuid, tx: &Transaction) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxCpfp],
+            vec![&Self::ser(tx)?],
+        )
+    }
+
+    fn get_cpfp_tx(&self, statechain_id: &Uuid) -> Result<Option<Transaction>> {
+        match self.get_1::<String>(*statechain_id, Table::BackupTxs, vec![Column::TxCpfp]) {
+            Ok(tx_cpfp_str) => Ok(Some(Self::deser(tx_cpfp_str)?)),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn update_backup_confirmation(
+        &self,
+        statechain_id: &Uuid,
+        block: &Option<(u64, BlockHash)>,
+    ) -> Result<()> {
+        let block_str = match block {
+            Some(b) => Some(Self::ser(b)?),
+            None => None,
+        };
+        self.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::BackupConfirmation],
+            vec![&block_str],
+        )
+    }
+
+    fn get_backup_confirmation(&self, statechain_id: &Uuid) -> Result<Option<(u64, BlockHash)>> {
+        match self.get_1::<String>(
+            *statechain_id,
+            Table::BackupTxs,
+            vec![Column::BackupConfirmation],
+        ) {
+            Ok(block_str) => Ok(Some(Self::deser(block_str)?)),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Get root with given ID
     fn get_root(&self, id: i64) -> Result<Option<Root>> {
         if id == 0 {
@@ -985,11 +1418,12 @@ impl Database for PGDatabase {
         state_chain: StateChain,
         amount: u64,
     ) -> Result<()> {
+        let proof_key = state_chain.get_tip()?.data;
         self.update(
             statechain_id,
             Table::StateChain,
-            vec![Column::Chain, Column::Amount],
-            vec![&Self::ser(state_chain)?, &(amount as i64)], // signals withdrawn funds
+            vec![Column::Chain, Column::Amount, Column::ProofKey],
+            vec![&Self::ser(state_chain)?, &(amount as i64), &proof_key], // signals withdrawn funds
         )
     }
 
@@ -1009,12 +1443,14 @@ impl Database for PGDatabase {
                 Column::Amount,
                 Column::LockedUntil,
                 Column::OwnerId,
+                Column::ProofKey,
             ],
             vec![
                 &Self::ser(state_chain.to_owned())?,
                 amount,
                 &get_time_now(),
                 &user_id.to_owned(),
+                &state_chain.get_tip()?.data,
             ],
         )
     }
@@ -1035,11 +1471,12 @@ impl Database for PGDatabase {
         state_chain: StateChain,
         new_user_id: &Uuid,
     ) -> Result<()> {
+        let proof_key = state_chain.get_tip()?.data;
         self.update(
             statechain_id,
             Table::StateChain,
-            vec![Column::Chain, Column::OwnerId],
-            vec![&Self::ser(state_chain)?, &new_user_id],
+            vec![Column::Chain, Column::OwnerId, Column::ProofKey],
+            vec![&Self::ser(state_chain)?, &new_user_id, &proof_key],
         )
     }
 
@@ -1075,6 +1512,26 @@ impl Database for PGDatabase {
         Ok(tx_backup)
     }
 
+    fn create_kickoff_transaction(
+        &self,
+        statechain_id: &Uuid,
+        tx_kickoff: &Transaction,
+    ) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxKickOff],
+            vec![&Self::ser(tx_kickoff.clone())?],
+        )
+    }
+
+    fn get_kickoff_transaction(&self, statechain_id: Uuid) -> Result<Transaction> {
+        let tx_kickoff_str =
+            self.get_1::<String>(statechain_id, Table::BackupTxs, vec![Column::TxKickOff])?;
+        let tx_kickoff: Transaction = Self::deser(tx_kickoff_str)?;
+        Ok(tx_kickoff)
+    }
+
     fn get_proof_key(&self, user_id: Uuid) -> Result<String> {
         let proof_key =
             self.get_1::<String>(user_id, Table::UserSession, vec![Column::ProofKey])?;
@@ -1153,10 +1610,11 @@ impl Database for PGDatabase {
         self.update(
             statechain_id,
             Table::Transfer,
-            vec![Column::StateChainSig, Column::X1],
+            vec![Column::StateChainSig, Column::X1, Column::StartTime],
             vec![
                 &Self::ser(statechain_sig.to_owned())?,
                 &Self::ser(x1.to_owned())?,
+                &get_time_now(),
             ],
         )
     }
@@ -1224,6 +1682,420 @@ impl Database for PGDatabase {
         self.remove(statechain_id, Table::Transfer)
     }
 
+    fn add_backup_tx_history(
+        &self,
+        statechain_id: &Uuid,
+        tx: &Transaction,
+        proof_key: &String,
+    ) -> Result<()> {
+        let mut history = self.get_backup_tx_history(*statechain_id)?;
+        history.push(BackupTxRecord {
+            tx: tx.clone(),
+            proof_key: proof_key.clone(),
+        });
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::BackupTxHistory],
+            vec![&Self::ser(history)?],
+        )
+    }
+
+    fn get_backup_tx_history(&self, statechain_id: Uuid) -> Result<Vec<BackupTxRecord>> {
+        match self.get_1::<String>(statechain_id, Table::StateChain, vec![Column::BackupTxHistory]) {
+            Ok(history_str) => Self::deser(history_str),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(vec![]),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_active_statechain_ids(&self) -> Result<Vec<Uuid>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT id FROM {} WHERE amount > 0",
+            Table::StateChain.to_string(),
+        ))?;
+        let rows = statement.query(&[])?;
+        let mut statechain_ids = vec![];
+        for row in &rows {
+            statechain_ids.push(row.get("id"));
+        }
+        Ok(statechain_ids)
+    }
+
+    fn update_funding_confirmation(
+        &self,
+        statechain_id: &Uuid,
+        confirmation: &FundingConfirmation,
+    ) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::FundingConfirmation, Column::FundingTxid],
+            vec![&Self::ser(confirmation)?, &confirmation.txid.to_string()],
+        )
+    }
+
+    fn get_funding_confirmation(&self, statechain_id: Uuid) -> Result<Option<FundingConfirmation>> {
+        match self.get_1::<String>(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::FundingConfirmation],
+        ) {
+            Ok(confirmation_str) => Ok(Some(Self::deser(confirmation_str)?)),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn remove_funding_confirmation(&self, statechain_id: &Uuid) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::FundingConfirmation],
+            vec![&Option::<String>::None],
+        )
+    }
+
+    fn get_funding_confirmation_ids(&self) -> Result<Vec<Uuid>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT id FROM {} WHERE fundingconfirmation IS NOT NULL",
+            Table::StateChain.to_string(),
+        ))?;
+        let rows = statement.query(&[])?;
+        let mut statechain_ids = vec![];
+        for row in &rows {
+            statechain_ids.push(row.get("id"));
+        }
+        Ok(statechain_ids)
+    }
+
+    fn update_funding_outpoint(&self, statechain_id: &Uuid, outpoint: &OutPoint) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::FundingOutpoint],
+            vec![&Self::ser(outpoint)?],
+        )
+    }
+
+    fn get_funding_outpoint(&self, statechain_id: Uuid) -> Result<Option<OutPoint>> {
+        match self.get_1::<String>(statechain_id, Table::StateChain, vec![Column::FundingOutpoint]) {
+            Ok(outpoint_str) => Ok(Some(Self::deser(outpoint_str)?)),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn update_refresh_pending(&self, statechain_id: &Uuid, txid: &Txid) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::RefreshPending],
+            vec![&Self::ser(txid)?],
+        )
+    }
+
+    fn get_refresh_pending(&self, statechain_id: Uuid) -> Result<Option<Txid>> {
+        match self.get_1::<String>(statechain_id, Table::StateChain, vec![Column::RefreshPending]) {
+            Ok(txid_str) => Ok(Some(Self::deser(txid_str)?)),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn remove_refresh_pending(&self, statechain_id: &Uuid) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::RefreshPending],
+            vec![&Option::<String>::None],
+        )
+    }
+
+    fn update_aborted_locktime(&self, statechain_id: &Uuid, locktime: u32) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::AbortedLocktime],
+            vec![&(locktime as i64)],
+        )
+    }
+
+    fn get_aborted_locktime(&self, statechain_id: Uuid) -> Result<Option<u32>> {
+        match self.get_1::<i64>(statechain_id, Table::StateChain, vec![Column::AbortedLocktime]) {
+            Ok(locktime) => Ok(Some(locktime as u32)),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn remove_aborted_locktime(&self, statechain_id: &Uuid) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::AbortedLocktime],
+            vec![&Option::<i64>::None],
+        )
+    }
+
+    fn update_statechain_closure(
+        &self,
+        statechain_id: &Uuid,
+        closure: &StateChainClosureAPI,
+    ) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Closure],
+            vec![&Self::ser(closure)?],
+        )
+    }
+
+    fn get_statechain_closure(&self, statechain_id: Uuid) -> Result<Option<StateChainClosureAPI>> {
+        match self.get_1::<String>(statechain_id, Table::StateChain, vec![Column::Closure]) {
+            Ok(closure_str) => Ok(Some(Self::deser(closure_str)?)),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_statechain_listings(
+        &self,
+        filter: &StateChainFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(Vec<StateChainListing>, u64)> {
+        let min_amount = filter.min_amount.map(|a| a as i64);
+        let max_amount = filter.max_amount.map(|a| a as i64);
+        let now = get_time_now();
+        let mut params: Vec<&dyn ToSql> = vec![];
+        let mut conditions = vec![String::from("s.chain IS NOT NULL")];
+        if let Some(min_amount) = &min_amount {
+            params.push(min_amount);
+            conditions.push(format!("s.amount >= ${}", params.len()));
+        }
+        if let Some(max_amount) = &max_amount {
+            params.push(max_amount);
+            conditions.push(format!("s.amount <= ${}", params.len()));
+        }
+        if let Some(status) = filter.status {
+            let open = "s.closure IS NULL AND s.amount > 0";
+            match status {
+                StateChainStatus::ClosedByBackup => {
+                    conditions.push(String::from("s.closure IS NOT NULL"))
+                }
+                StateChainStatus::Withdrawn => {
+                    conditions.push(String::from("s.closure IS NULL AND s.amount = 0"))
+                }
+                StateChainStatus::InSwap => {
+                    params.push(&filter.in_swap);
+                    conditions.push(format!("{} AND s.id = ANY(${})", open, params.len()));
+                }
+                StateChainStatus::Locked | StateChainStatus::Active => {
+                    params.push(&filter.in_swap);
+                    params.push(&now);
+                    let op = match status {
+                        StateChainStatus::Locked => ">",
+                        _ => "<=",
+                    };
+                    conditions.push(format!(
+                        "{} AND NOT s.id = ANY(${}) AND s.lockeduntil {} ${}",
+                        open,
+                        params.len() - 1,
+                        op,
+                        params.len()
+                    ));
+                }
+            }
+        }
+        let conditions = conditions.join(" AND ");
+
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT COUNT(*) AS total FROM {} s WHERE {}",
+            Table::StateChain.to_string(),
+            conditions,
+        ))?;
+        let rows = statement.query(&params)?;
+        let total: i64 = match rows.iter().next() {
+            Some(row) => row.get("total"),
+            None => 0,
+        };
+
+        let statement = dbr.prepare(&format!(
+            "SELECT s.id, s.amount, s.chain, s.lockeduntil, s.fundingtxid, \
+             s.closure IS NOT NULL AS closed, b.txbackup \
+             FROM {} s LEFT JOIN {} b ON b.id = s.id WHERE {} ORDER BY s.id LIMIT {} OFFSET {}",
+            Table::StateChain.to_string(),
+            Table::BackupTxs.to_string(),
+            conditions,
+            limit,
+            offset,
+        ))?;
+        let rows = statement.query(&params)?;
+        let mut listings = vec![];
+        for row in &rows {
+            listings.push(Self::statechain_listing(&row)?);
+        }
+        Ok((listings, total as u64))
+    }
+
+    fn get_statechain_listing(&self, statechain_id: Uuid) -> Result<StateChainListing> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT s.id, s.amount, s.chain, s.lockeduntil, s.fundingtxid, \
+             s.closure IS NOT NULL AS closed, b.txbackup \
+             FROM {} s LEFT JOIN {} b ON b.id = s.id WHERE s.id = $1 AND s.chain IS NOT NULL",
+            Table::StateChain.to_string(),
+            Table::BackupTxs.to_string(),
+        ))?;
+        let rows = statement.query(&[&statechain_id])?;
+        let listing = match rows.iter().next() {
+            Some(row) => Self::statechain_listing(&row)?,
+            None => return Err(SEError::DBError(NoDataForID, statechain_id.to_string())),
+        };
+        Ok(listing)
+    }
+
+    fn search_statechains(&self, query: &String) -> Result<Vec<Uuid>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT id FROM {} WHERE proofkey = $1 OR fundingtxid = $1 ORDER BY id",
+            Table::StateChain.to_string(),
+        ))?;
+        let rows = statement.query(&[query])?;
+        let mut statechain_ids = vec![];
+        for row in &rows {
+            statechain_ids.push(row.get("id"));
+        }
+        Ok(statechain_ids)
+    }
+
+    fn get_user_sessions(&self, offset: u64, limit: u64) -> Result<(Vec<UserSessionAPI>, u64)> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT COUNT(*) AS total FROM {}",
+            Table::UserSession.to_string(),
+        ))?;
+        let rows = statement.query(&[])?;
+        let total: i64 = match rows.iter().next() {
+            Some(row) => row.get("total"),
+            None => 0,
+        };
+
+        let statement = dbr.prepare(&format!(
+            "SELECT id, statechainid, proofkey, \
+             withdrawscsig IS NOT NULL AS withdrawpending, \
+             refreshscsig IS NOT NULL AS refreshpending \
+             FROM {} ORDER BY id LIMIT {} OFFSET {}",
+            Table::UserSession.to_string(),
+            limit,
+            offset,
+        ))?;
+        let rows = statement.query(&[])?;
+        let mut sessions = vec![];
+        for row in &rows {
+            sessions.push(UserSessionAPI {
+                id: row.get("id"),
+                statechain_id: row.get("statechainid"),
+                proof_key: row.get("proofkey"),
+                withdraw_pending: row.get("withdrawpending"),
+                refresh_pending: row.get("refreshpending"),
+            });
+        }
+        Ok((sessions, total as u64))
+    }
+
+    fn get_unfunded_user_sessions(&self, created_before: &NaiveDateTime) -> Result<Vec<Uuid>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT id FROM {} WHERE statechainid IS NULL AND createdat < $1",
+            Table::UserSession.to_string(),
+        ))?;
+        let rows = statement.query(&[created_before])?;
+        let mut user_ids = vec![];
+        for row in &rows {
+            user_ids.push(row.get("id"));
+        }
+        Ok(user_ids)
+    }
+
+    fn remove_user_session(&self, user_id: &Uuid) -> Result<()> {
+        self.remove(user_id, Table::UserSession)
+    }
+
+    fn remove_ecdsa(&self, user_id: &Uuid) -> Result<bool> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "DELETE FROM {} WHERE id = $1;",
+            Table::Ecdsa.to_string()
+        ))?;
+        Ok(statement.execute(&[user_id])? > 0)
+    }
+
+    fn get_transfer_batches_started_before(&self, time: &NaiveDateTime) -> Result<Vec<Uuid>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT id FROM {} WHERE starttime < $1",
+            Table::TransferBatch.to_string(),
+        ))?;
+        let rows = statement.query(&[time])?;
+        let mut batch_ids = vec![];
+        for row in &rows {
+            batch_ids.push(row.get("id"));
+        }
+        Ok(batch_ids)
+    }
+
+    fn archive_transfer_batch(&self, batch_id: &Uuid) -> Result<()> {
+        let dbw = self.database_w()?;
+        let transaction = dbw.transaction()?;
+        transaction.execute(
+            &format!(
+                "INSERT INTO {} SELECT * FROM {} WHERE id = $1 ON CONFLICT DO NOTHING;",
+                Table::TransferBatchArchive.to_string(),
+                Table::TransferBatch.to_string(),
+            ),
+            &[batch_id],
+        )?;
+        if transaction.execute(
+            &format!("DELETE FROM {} WHERE id = $1;", Table::TransferBatch.to_string()),
+            &[batch_id],
+        )? == 0
+        {
+            return Err(SEError::DBError(UpdateFailed, batch_id.to_string()));
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn remove_orphaned_backup_txs(&self) -> Result<u64> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "DELETE FROM {} b WHERE NOT EXISTS \
+             (SELECT 1 FROM {} s WHERE s.id = b.id AND s.amount > 0);",
+            Table::BackupTxs.to_string(),
+            Table::StateChain.to_string(),
+        ))?;
+        Ok(statement.execute(&[])?)
+    }
+
+    fn get_transfers_started_before(&self, time: &NaiveDateTime) -> Result<Vec<Uuid>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT id FROM {} WHERE starttime < $1",
+            Table::Transfer.to_string(),
+        ))?;
+        let rows = statement.query(&[time])?;
+        let mut statechain_ids = vec![];
+        for row in &rows {
+            statechain_ids.push(row.get("id"));
+        }
+        Ok(statechain_ids)
+    }
+
     fn transfer_is_completed(&self, statechain_id: Uuid) -> bool {
         self.get_1::<Uuid>(statechain_id, Table::Transfer, vec![Column::Id])
             .is_ok()
@@ -1474,11 +2346,20 @@ impl Database for PGDatabase {
         self.update(
             user_id,
             Table::UserSession,
-            vec![Column::Authentication, Column::ProofKey],
-            vec![&auth.clone(), &proof_key.to_owned()],
+            vec![Column::Authentication, Column::ProofKey, Column::CreatedAt],
+            vec![&auth.clone(), &proof_key.to_owned(), &get_time_now()],
         )
     }
 
+    fn spend_token(&self, token: &String) -> Result<bool> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id) VALUES ($1) ON CONFLICT DO NOTHING;",
+            Table::SpentToken.to_string()
+        ))?;
+        Ok(statement.execute(&[token])? == 1)
+    }
+
     // Create new UserSession to allow new owner to generate shared wallet
     fn transfer_init_user_session(
         &self,
@@ -1496,6 +2377,7 @@ impl Database for PGDatabase {
                 Column::TxBackup,
                 Column::StateChainId,
                 Column::S2,
+                Column::KeyType,
             ],
             vec![
                 &String::from("auth"),
@@ -1503,6 +2385,7 @@ impl Database for PGDatabase {
                 &Self::ser(transaction_deserialise(&finalized_data.new_tx_backup_hex)?)?,
                 &statechain_id,
                 &Self::ser(finalized_data.s2)?,
+                &Self::ser(finalized_data.key_type)?,
             ],
         )
     }
@@ -1568,4 +2451,106 @@ impl Database for PGDatabase {
             vec![&Self::ser(tx)?],
         )
     }
+
+    fn update_swap_registration(
+        &self,
+        statechain_id: &Uuid,
+        amount: u64,
+        swap_size: u64,
+    ) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, amount, swapsize) VALUES ($1,$2,$3)
+            ON CONFLICT (id) DO UPDATE SET amount = $2, swapsize = $3",
+            Table::SwapRegistration.to_string()
+        ))?;
+        statement.execute(&[statechain_id, &(amount as i64), &(swap_size as i64)])?;
+        Ok(())
+    }
+
+    fn remove_swap_registration(&self, statechain_id: &Uuid) -> Result<()> {
+        self.remove_if_exists(statechain_id, Table::SwapRegistration)
+    }
+
+    fn get_swap_registrations(&self) -> Result<Vec<SwapRegistration>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {}",
+            Table::SwapRegistration.to_string(),
+        ))?;
+        let rows = statement.query(&[])?;
+        let mut registrations = Vec::new();
+        for row in &rows {
+            let statechain_id: Uuid = row.get("id");
+            let amount: i64 = row.get("amount");
+            let swap_size: i64 = row.get("swapsize");
+            registrations.push(SwapRegistration {
+                statechain_id,
+                amount: amount as u64,
+                swap_size: swap_size as u64,
+            });
+        }
+        Ok(registrations)
+    }
+
+    fn update_swap(&self, swap_data: &SwapData) -> Result<()> {
+        // BisetMap is stored as a list of (SCEAddress, claimed nonce) pairs
+        let out_addr_vec = swap_data.out_addr_map.as_ref().map(|m| m.flat_collect());
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, swapinfo, outaddrmap, bsteprimemap, bstsigmap, tbsigmap, bstretrieved,
+            phasestart)
+            VALUES ($1,$2,$3,$4,$5,$6,$7,$8)
+            ON CONFLICT (id) DO UPDATE SET swapinfo = $2, outaddrmap = $3, bsteprimemap = $4,
+            bstsigmap = $5, tbsigmap = $6, bstretrieved = $7, phasestart = $8",
+            Table::Swap.to_string()
+        ))?;
+        statement.execute(&[
+            &swap_data.swap_info.swap_token.id,
+            &Self::ser(&swap_data.swap_info)?,
+            &Self::ser(out_addr_vec)?,
+            &Self::ser(&swap_data.bst_e_prime_map)?,
+            &Self::ser(&swap_data.bst_sig_map)?,
+            &Self::ser(&swap_data.tb_sig_map)?,
+            &Self::ser(&swap_data.bst_retrieved)?,
+            &Self::ser(&swap_data.phase_start)?,
+        ])?;
+        Ok(())
+    }
+
+    fn remove_swap(&self, swap_id: &Uuid) -> Result<()> {
+        self.remove_if_exists(swap_id, Table::Swap)
+    }
+
+    fn get_swaps(&self) -> Result<Vec<SwapData>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!("SELECT * FROM {}", Table::Swap.to_string(),))?;
+        let rows = statement.query(&[])?;
+        let mut swaps = Vec::new();
+        for row in &rows {
+            let swap_info: SwapInfo = Self::deser(row.get("swapinfo"))?;
+            let out_addr_vec: Option<Vec<(SCEAddress, Option<Uuid>)>> =
+                Self::deser(row.get("outaddrmap"))?;
+            let out_addr_map = out_addr_vec.map(|v| {
+                let m = BisetMap::<SCEAddress, Option<Uuid>>::new();
+                for (addr, nonce) in v {
+                    m.insert(addr, nonce);
+                }
+                m
+            });
+            swaps.push(SwapData {
+                swap_info,
+                out_addr_map,
+                bst_e_prime_map: Self::deser(row.get("bsteprimemap"))?,
+                bst_sig_map: Self::deser(row.get("bstsigmap"))?,
+                tb_sig_map: Self::deser(row.get("tbsigmap"))?,
+                bst_retrieved: Self::deser(row.get("bstretrieved"))?,
+                phase_start: match row.get::<_, Option<String>>("phasestart") {
+                    Some(v) => Self::deser(v)?,
+                    None => None,
+                },
+            });
+        }
+        Ok(swaps)
+    }
 }
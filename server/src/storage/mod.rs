@@ -1,5 +1,7 @@
+pub mod backend;
 pub mod db;
 pub mod monotree;
+pub mod sqlite;
 pub use super::Result;
 
 use rocket::http::{ContentType, Status};
@@ -122,6 +124,9 @@ pub trait Storage {
     //Returns locked until time, owner id, state chain
     fn get_statechain_data_api(&self, statechain_id: Uuid) -> Result<StateChainDataAPI>;
 
+    //Returns the backup tx closure of the statechain, if it has been closed
+    fn get_statechain_closure(&self, statechain_id: Uuid) -> Result<Option<StateChainClosureAPI>>;
+
     //fn authorise_withdrawal(&self, user_id: &Uuid, signature: StateChainSig) -> Result<()>;
 
     // /withdraw/confirm
@@ -313,9 +313,20 @@ impl Database for MemoryDB {
         &self,
         _user_id: &uuid::Uuid,
         _sig: shared_lib::state_chain::StateChainSig,
+        _statechain_ids: Vec<uuid::Uuid>,
+        _outputs: Vec<shared_lib::structs::WithdrawOutput>,
     ) -> crate::Result<()> {
         unimplemented!()
     }
+    fn get_withdraw_batch(&self, _user_id: uuid::Uuid) -> crate::Result<Vec<uuid::Uuid>> {
+        unimplemented!()
+    }
+    fn get_withdraw_outputs(
+        &self,
+        _user_id: uuid::Uuid,
+    ) -> crate::Result<Vec<shared_lib::structs::WithdrawOutput>> {
+        unimplemented!()
+    }
     fn update_withdraw_tx_sighash(
         &self,
         _user_id: &uuid::Uuid,
@@ -333,6 +344,39 @@ impl Database for MemoryDB {
     fn get_s1_pubkey(&self, _user_id: &uuid::Uuid) -> crate::Result<crate::GE> {
         unimplemented!()   
     }
+    fn update_key_type(
+        &self,
+        _user_id: &uuid::Uuid,
+        _key_type: &shared_lib::structs::KeyType,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_key_type(&self, _user_id: uuid::Uuid) -> crate::Result<shared_lib::structs::KeyType> {
+        unimplemented!()
+    }
+    fn update_musig_key(
+        &self,
+        _user_id: &uuid::Uuid,
+        _key: &crate::structs::MuSigKey,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_musig_key(&self, _user_id: uuid::Uuid) -> crate::Result<crate::structs::MuSigKey> {
+        unimplemented!()
+    }
+    fn update_musig_nonces(
+        &self,
+        _user_id: &uuid::Uuid,
+        _nonces: &Option<crate::structs::MuSigNonces>,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_musig_nonces(
+        &self,
+        _user_id: uuid::Uuid,
+    ) -> crate::Result<Option<crate::structs::MuSigNonces>> {
+        unimplemented!()
+    }
     fn update_user_backup_tx(
         &self,
         _user_id: &uuid::Uuid,
@@ -623,6 +667,57 @@ impl Database for MemoryDB {
     ) -> crate::Result<()> {
         unimplemented!()
     }
+    fn spend_token(&self, _token: &String) -> crate::Result<bool> {
+        unimplemented!()
+    }
+    fn get_statechain_listings(
+        &self,
+        _filter: &crate::structs::StateChainFilter,
+        _offset: u64,
+        _limit: u64,
+    ) -> crate::Result<(Vec<crate::structs::StateChainListing>, u64)> {
+        unimplemented!()
+    }
+    fn get_statechain_listing(
+        &self,
+        _statechain_id: uuid::Uuid,
+    ) -> crate::Result<crate::structs::StateChainListing> {
+        unimplemented!()
+    }
+    fn search_statechains(&self, _query: &String) -> crate::Result<Vec<uuid::Uuid>> {
+        unimplemented!()
+    }
+    fn get_unfunded_user_sessions(
+        &self,
+        _created_before: &chrono::NaiveDateTime,
+    ) -> crate::Result<Vec<uuid::Uuid>> {
+        unimplemented!()
+    }
+    fn remove_user_session(&self, _user_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn remove_ecdsa(&self, _user_id: &uuid::Uuid) -> crate::Result<bool> {
+        unimplemented!()
+    }
+    fn get_transfer_batches_started_before(
+        &self,
+        _time: &chrono::NaiveDateTime,
+    ) -> crate::Result<Vec<uuid::Uuid>> {
+        unimplemented!()
+    }
+    fn archive_transfer_batch(&self, _batch_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn remove_orphaned_backup_txs(&self) -> crate::Result<u64> {
+        unimplemented!()
+    }
+    fn get_user_sessions(
+        &self,
+        _offset: u64,
+        _limit: u64,
+    ) -> crate::Result<(Vec<shared_lib::structs::UserSessionAPI>, u64)> {
+        unimplemented!()
+    }
     fn transfer_init_user_session(
         &self,
         _new_user_id: &uuid::Uuid,
@@ -677,4 +772,202 @@ impl Database for MemoryDB {
     fn get_sighash(&self, _user_id: uuid::Uuid) -> crate::Result<bitcoin::hashes::sha256d::Hash> {
         unimplemented!()
     }
+    fn update_swap_registration(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _amount: u64,
+        _swap_size: u64,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn remove_swap_registration(&self, _statechain_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_swap_registrations(&self) -> crate::Result<Vec<crate::structs::SwapRegistration>> {
+        unimplemented!()
+    }
+    fn update_swap(&self, _swap_data: &crate::structs::SwapData) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn remove_swap(&self, _swap_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_swaps(&self) -> crate::Result<Vec<crate::structs::SwapData>> {
+        unimplemented!()
+    }
+    fn update_user_kickoff_tx(
+        &self,
+        _user_id: &uuid::Uuid,
+        _tx: bitcoin::Transaction,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_user_kickoff_tx(&self, _user_id: uuid::Uuid) -> crate::Result<bitcoin::Transaction> {
+        unimplemented!()
+    }
+    fn create_kickoff_transaction(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _tx_kickoff: &bitcoin::Transaction,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_kickoff_transaction(
+        &self,
+        _statechain_id: uuid::Uuid,
+    ) -> crate::Result<bitcoin::Transaction> {
+        unimplemented!()
+    }
+    fn has_refresh_sc_sig(&self, _user_id: uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn update_refresh_sc_sig(
+        &self,
+        _user_id: &uuid::Uuid,
+        _sig: shared_lib::state_chain::StateChainSig,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn update_user_refresh_tx(
+        &self,
+        _user_id: &uuid::Uuid,
+        _tx: bitcoin::Transaction,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_user_refresh_tx(&self, _user_id: uuid::Uuid) -> crate::Result<bitcoin::Transaction> {
+        unimplemented!()
+    }
+    fn get_refresh_confirm_data(
+        &self,
+        _user_id: uuid::Uuid,
+    ) -> crate::Result<crate::structs::RefreshConfirmData> {
+        unimplemented!()
+    }
+    fn remove_refresh_data(&self, _user_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_transfers_started_before(
+        &self,
+        _time: &chrono::NaiveDateTime,
+    ) -> crate::Result<Vec<uuid::Uuid>> {
+        unimplemented!()
+    }
+    fn update_cpfp_tx(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _tx: &bitcoin::Transaction,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_cpfp_tx(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Option<bitcoin::Transaction>> {
+        unimplemented!()
+    }
+    fn add_backup_tx_history(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _tx: &bitcoin::Transaction,
+        _proof_key: &String,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_backup_tx_history(
+        &self,
+        _statechain_id: uuid::Uuid,
+    ) -> crate::Result<Vec<crate::structs::BackupTxRecord>> {
+        unimplemented!()
+    }
+    fn get_active_statechain_ids(&self) -> crate::Result<Vec<uuid::Uuid>> {
+        unimplemented!()
+    }
+    fn update_statechain_closure(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _closure: &shared_lib::structs::StateChainClosureAPI,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_statechain_closure(
+        &self,
+        _statechain_id: uuid::Uuid,
+    ) -> crate::Result<Option<shared_lib::structs::StateChainClosureAPI>> {
+        unimplemented!()
+    }
+    fn update_backup_confirmation(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _block: &Option<(u64, bitcoin::BlockHash)>,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_backup_confirmation(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Option<(u64, bitcoin::BlockHash)>> {
+        unimplemented!()
+    }
+    fn update_funding_confirmation(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _confirmation: &crate::structs::FundingConfirmation,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_funding_confirmation(
+        &self,
+        _statechain_id: uuid::Uuid,
+    ) -> crate::Result<Option<crate::structs::FundingConfirmation>> {
+        unimplemented!()
+    }
+    fn remove_funding_confirmation(&self, _statechain_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_funding_confirmation_ids(&self) -> crate::Result<Vec<uuid::Uuid>> {
+        unimplemented!()
+    }
+    fn update_funding_outpoint(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _outpoint: &bitcoin::OutPoint,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_funding_outpoint(
+        &self,
+        _statechain_id: uuid::Uuid,
+    ) -> crate::Result<Option<bitcoin::OutPoint>> {
+        unimplemented!()
+    }
+    fn update_refresh_pending(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _txid: &bitcoin::Txid,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_refresh_pending(
+        &self,
+        _statechain_id: uuid::Uuid,
+    ) -> crate::Result<Option<bitcoin::Txid>> {
+        unimplemented!()
+    }
+    fn remove_refresh_pending(&self, _statechain_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn update_aborted_locktime(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _locktime: u32,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_aborted_locktime(&self, _statechain_id: uuid::Uuid) -> crate::Result<Option<u32>> {
+        unimplemented!()
+    }
+    fn remove_aborted_locktime(&self, _statechain_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
 }
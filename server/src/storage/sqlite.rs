@@ -0,0 +1,2488 @@
+//! SQLite
+//!
+//! Embedded SQLite implementation of the DB, holding the same tables as the Postgres DB in a
+//! single file. SQLite has no schemas or uuid and timestamp types, so tables are named without
+//! their schema and IDs and times are stored as text.
+
+use super::super::Result;
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+
+use crate::protocol::transfer::TransferFinalizeData;
+use crate::storage::db::{Column, HDPos, Table, ADDED_COLUMNS};
+use crate::{
+    error::{
+        DBErrorType::{ConnectionFailed, NoDataForID, UpdateFailed},
+        SEError,
+    },
+    structs::*,
+    Database, Hash, PGDatabase, PGDatabaseSmt, SqliteDatabase,
+};
+use bitcoin::hashes::sha256d;
+use chrono::NaiveDateTime;
+use curv::{FE, GE};
+use kms::ecdsa::two_party::*;
+use monotree::database::{Database as MonotreeDatabase, MemCache};
+use monotree::Errors;
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_one::Party1Private;
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party_two};
+use rocket_contrib::databases::r2d2;
+use rocket_contrib::databases::r2d2_postgres::PostgresConnectionManager;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{Connection, OptionalExtension, Row, ToSql, NO_PARAMS};
+use shared_lib::mainstay::CommitmentInfo;
+use shared_lib::state_chain::*;
+use shared_lib::structs::{
+    KeyType, SCEAddress, StateChainClosureAPI, StateChainStatus, TransferMsg3, UserSessionAPI,
+    WithdrawOutput,
+};
+use shared_lib::swap_data::SwapInfo;
+use shared_lib::util::transaction_deserialise;
+use shared_lib::Root;
+
+use bisetmap::BisetMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Time (seconds) a connection waits for a lock on the database file held by another
+/// connection, such as the watcher's
+const BUSY_TIMEOUT: u64 = 10;
+
+/// Uuid stored as text
+struct SqlUuid(Uuid);
+
+impl ToSql for SqlUuid {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0.to_string()))
+    }
+}
+
+impl FromSql for SqlUuid {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Uuid::parse_str(value.as_str()?)
+            .map(SqlUuid)
+            .map_err(|_| FromSqlError::InvalidType)
+    }
+}
+
+/// Name of table in the SQLite DB
+pub fn table_name(table: &Table) -> String {
+    format!("{:?}", table)
+}
+
+/// Returns str list of column names for SQL SELECT query statement.
+fn get_columns_str(cols: &[Column]) -> String {
+    cols.iter()
+        .map(|col| col.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Returns str list of column names for SQL UPDATE prepare statement.
+fn update_columns_str(cols: &[Column]) -> String {
+    cols.iter()
+        .enumerate()
+        .map(|(i, col)| format!("{}=?{}", col.to_string(), i + 1))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Add ids to params. Returns the list of their placeholders for an SQL IN condition.
+fn push_id_params<'a>(params: &mut Vec<&'a dyn ToSql>, ids: &'a [SqlUuid]) -> String {
+    let mut placeholders = vec![];
+    for id in ids {
+        params.push(id);
+        placeholders.push(format!("?{}", params.len()));
+    }
+    format!("({})", placeholders.join(","))
+}
+
+fn smt_error<E: ToString>(e: E) -> Errors {
+    Errors::new(&e.to_string())
+}
+
+impl SqliteDatabase {
+    fn open(path: &str) -> Result<Connection> {
+        let connection = match Connection::open(path) {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(SEError::DBError(
+                    ConnectionFailed,
+                    format!("Failed to open SQLite database {}: {}", path, e),
+                ))
+            }
+        };
+        connection.busy_timeout(Duration::from_secs(BUSY_TIMEOUT))?;
+        // Readers do not block the writer
+        connection.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+        Ok(connection)
+    }
+
+    pub fn connection(&self) -> Result<MutexGuard<Connection>> {
+        match &self.connection {
+            Some(c) => match c.lock() {
+                Ok(c) => Ok(c),
+                Err(e) => Err(SEError::DBError(
+                    ConnectionFailed,
+                    format!("Failed to get SQLite connection: {}", e),
+                )),
+            },
+            None => Err(SEError::DBError(
+                ConnectionFailed,
+                "Failed to get SQLite connection: connection not set".to_string(),
+            )),
+        }
+    }
+
+    /// Build DB tables
+    pub fn make_tables(&self) -> Result<()> {
+        let conn = self.connection()?;
+
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {} (
+                id text NOT NULL,
+                statechainid text,
+                authentication text,
+                s2 text,
+                s1pubkey text,
+                sighash text,
+                withdrawscsig text,
+                withdrawbatch text,
+                withdrawoutputs text,
+                keytype text,
+                musigkey text,
+                musignonces text,
+                txwithdraw text,
+                proofkey text,
+                txbackup text,
+                txkickoff text,
+                refreshscsig text,
+                txrefresh text,
+                createdat text,
+                PRIMARY KEY (id)
+            );",
+            table_name(&Table::UserSession),
+        ))?;
+
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {} (
+                id text NOT NULL,
+                keygenfirstmsg text,
+                commwitness text,
+                eckeypair text,
+                party2public text,
+                paillierkeypair text,
+                party1private text,
+                party1masterkey text,
+                pos text,
+                epheckeypair text,
+                ephkeygenfirstmsg text,
+                complete integer NOT NULL DEFAULT 0,
+                PRIMARY KEY (id)
+            );",
+            table_name(&Table::Ecdsa),
+        ))?;
+
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {} (
+                id text NOT NULL,
+                chain text,
+                amount integer,
+                ownerid text,
+                lockeduntil text,
+                transferfinalizedata text,
+                transferready integer,
+                backuptxhistory text,
+                closure text,
+                fundingconfirmation text,
+                proofkey text,
+                fundingtxid text,
+                fundingoutpoint text,
+                refreshpending text,
+                abortedlocktime integer,
+                PRIMARY KEY (id)
+            );",
+            table_name(&Table::StateChain),
+        ))?;
+
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {} (
+                id text NOT NULL,
+                statechainsig text,
+                x1 text,
+                transfermsg text,
+                starttime text,
+                PRIMARY KEY (id)
+            );",
+            table_name(&Table::Transfer),
+        ))?;
+
+        for table in &[Table::TransferBatch, Table::TransferBatchArchive] {
+            conn.execute_batch(&format!(
+                "
+            CREATE TABLE IF NOT EXISTS {} (
+                id text NOT NULL,
+                starttime text,
+                statechains text,
+                punishedstatechains text,
+                finalized integer,
+                PRIMARY KEY (id)
+            );",
+                table_name(table),
+            ))?;
+        }
+
+        // Root IDs are assigned in order, starting from 1 in an empty table
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {} (
+                id integer PRIMARY KEY,
+                value text,
+                commitmentinfo text
+            );",
+            table_name(&Table::Root),
+        ))?;
+
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {} (
+                id text NOT NULL,
+                txbackup text,
+                txkickoff text,
+                locktime integer,
+                txcpfp text,
+                backupconfirmation text,
+                PRIMARY KEY (id)
+            );",
+            table_name(&Table::BackupTxs),
+        ))?;
+
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {} (
+                key blob,
+                value blob,
+                PRIMARY KEY (key)
+            );",
+            table_name(&Table::Smt),
+        ))?;
+
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {} (
+                id text NOT NULL,
+                amount integer,
+                swapsize integer,
+                PRIMARY KEY (id)
+            );",
+            table_name(&Table::SwapRegistration),
+        ))?;
+
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {} (
+                id text NOT NULL,
+                swapinfo text,
+                outaddrmap text,
+                bsteprimemap text,
+                bstsigmap text,
+                tbsigmap text,
+                bstretrieved text,
+                phasestart text,
+                PRIMARY KEY (id)
+            );",
+            table_name(&Table::Swap),
+        ))?;
+
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {} (
+                id text NOT NULL,
+                PRIMARY KEY (id)
+            );",
+            table_name(&Table::SpentToken),
+        ))?;
+
+        Self::migrate_tables(&conn)?;
+
+        // Explorer queries
+        for column in &["amount", "proofkey", "fundingtxid"] {
+            conn.execute_batch(&format!(
+                "CREATE INDEX IF NOT EXISTS statechain_{}_idx ON {} ({});",
+                column,
+                table_name(&Table::StateChain),
+                column,
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// Add the columns missing from tables created by earlier versions and fill in those that
+    /// can be derived from existing rows
+    fn migrate_tables(conn: &Connection) -> Result<()> {
+        // SQLite has no ADD COLUMN IF NOT EXISTS
+        for (table, column, _, column_type) in ADDED_COLUMNS {
+            let mut statement =
+                conn.prepare(&format!("PRAGMA table_info({})", table_name(table)))?;
+            let mut rows = statement.query(NO_PARAMS)?;
+            let mut exists = false;
+            while let Some(row) = rows.next()? {
+                exists |= row.get::<_, String>(1)?.eq_ignore_ascii_case(&column.to_string());
+            }
+            if !exists {
+                conn.execute_batch(&format!(
+                    "ALTER TABLE {} ADD COLUMN {} {};",
+                    table_name(table),
+                    column.to_string(),
+                    column_type,
+                ))?;
+            }
+        }
+
+        // The proof key of a statechain is the data of its tip
+        let mut proof_keys = vec![];
+        let mut statement = conn.prepare(&format!(
+            "SELECT id, chain FROM {} WHERE proofkey IS NULL AND chain IS NOT NULL",
+            table_name(&Table::StateChain),
+        ))?;
+        let mut rows = statement.query(NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            let state_chain: StateChain = Self::deser(row.get::<_, String>(1)?)?;
+            proof_keys.push((row.get::<_, SqlUuid>(0)?, state_chain.get_tip()?.data));
+        }
+        for (id, proof_key) in proof_keys {
+            conn.execute(
+                &format!(
+                    "UPDATE {} SET proofkey = ?1 WHERE id = ?2",
+                    table_name(&Table::StateChain),
+                ),
+                &[&proof_key as &dyn ToSql, &id],
+            )?;
+        }
+
+        // Sessions created before their creation time was stored expire as if created now
+        conn.execute(
+            &format!(
+                "UPDATE {} SET createdat = ?1 WHERE createdat IS NULL",
+                table_name(&Table::UserSession),
+            ),
+            &[&get_time_now()],
+        )?;
+        Ok(())
+    }
+
+    /// Delete all rows of all tables
+    fn truncate_tables(&self) -> Result<()> {
+        let conn = self.connection()?;
+        for table in &[
+            Table::UserSession,
+            Table::Ecdsa,
+            Table::StateChain,
+            Table::Transfer,
+            Table::TransferBatch,
+            Table::TransferBatchArchive,
+            Table::Root,
+            Table::BackupTxs,
+            Table::Smt,
+            Table::SwapRegistration,
+            Table::Swap,
+            Table::SpentToken,
+        ] {
+            conn.execute(&format!("DELETE FROM {};", table_name(table)), NO_PARAMS)?;
+        }
+        Ok(())
+    }
+
+    /// Serialize data into string. Reuses the Postgres serialization.
+    pub fn ser<T>(data: T) -> Result<String>
+    where
+        T: serde::ser::Serialize,
+    {
+        PGDatabase::ser(data)
+    }
+
+    /// Deserialize custom type data from string. Reverse of ser().
+    pub fn deser<T>(data: String) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        PGDatabase::deser(data)
+    }
+
+    /// Read a statechain from a row of an explorer query
+    fn statechain_listing(row: &Row) -> Result<StateChainListing> {
+        let id: SqlUuid = row.get("id")?;
+        let tx_backup: Option<String> = row.get("txbackup")?;
+        Ok(StateChainListing {
+            id: id.0,
+            amount: row.get("amount")?,
+            chain: Self::deser(row.get("chain")?)?,
+            locked_until: row.get("lockeduntil")?,
+            funding_txid: row.get("fundingtxid")?,
+            closed: row.get("closed")?,
+            tx_backup: match tx_backup {
+                Some(tx) => Some(Self::deser(tx)?),
+                None => None,
+            },
+        })
+    }
+
+    /// Get the IDs returned by query
+    fn get_ids(&self, query: &str, params: &[&dyn ToSql]) -> Result<Vec<Uuid>> {
+        let conn = self.connection()?;
+        let mut statement = conn.prepare(query)?;
+        let mut rows = statement.query(params)?;
+        let mut ids = vec![];
+        while let Some(row) = rows.next()? {
+            ids.push(row.get::<_, SqlUuid>(0)?.0);
+        }
+        Ok(ids)
+    }
+
+    /// Create new item in table
+    pub fn insert(&self, id: &Uuid, table: Table) -> Result<u64> {
+        let conn = self.connection()?;
+        let rows = conn.execute(
+            &format!("INSERT INTO {} (id) VALUES (?1)", table_name(&table)),
+            &[&SqlUuid(*id)],
+        )?;
+        Ok(rows as u64)
+    }
+
+    /// Remove row in table
+    pub fn remove(&self, id: &Uuid, table: Table) -> Result<()> {
+        let conn = self.connection()?;
+        if conn.execute(
+            &format!("DELETE FROM {} WHERE id = ?1;", table_name(&table)),
+            &[&SqlUuid(*id)],
+        )? == 0
+        {
+            return Err(SEError::DBError(UpdateFailed, id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Remove row in table if it exists
+    pub fn remove_if_exists(&self, id: &Uuid, table: Table) -> Result<()> {
+        let conn = self.connection()?;
+        conn.execute(
+            &format!("DELETE FROM {} WHERE id = ?1;", table_name(&table)),
+            &[&SqlUuid(*id)],
+        )?;
+        Ok(())
+    }
+
+    /// Update items in table for some ID with SQLite data types (String, int, bool,
+    /// chrono::NaiveDateTime).
+    pub fn update<'a>(
+        &self,
+        id: &Uuid,
+        table: Table,
+        column: Vec<Column>,
+        data: Vec<&'a dyn ToSql>,
+    ) -> Result<()> {
+        let num_items = column.len();
+        let sql_id = SqlUuid(*id);
+        let mut params: Vec<&dyn ToSql> = data;
+        params.push(&sql_id);
+
+        let conn = self.connection()?;
+        if conn.execute(
+            &format!(
+                "UPDATE {} SET {} WHERE id = ?{}",
+                table_name(&table),
+                update_columns_str(&column),
+                num_items + 1
+            ),
+            &params,
+        )? == 0
+        {
+            return Err(SEError::DBError(UpdateFailed, id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Read the row of table for some ID. Err if ID not found.
+    fn get_row<T, F>(&self, id: &Uuid, table: Table, column: &[Column], read: F) -> Result<T>
+    where
+        F: FnOnce(&Row) -> Result<T>,
+    {
+        let conn = self.connection()?;
+        match conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM {} WHERE id = ?1",
+                    get_columns_str(column),
+                    table_name(&table)
+                ),
+                &[&SqlUuid(*id)],
+                |row| Ok(read(row)),
+            )
+            .optional()?
+        {
+            Some(res) => res,
+            None => Err(SEError::DBError(NoDataForID, id.to_string())),
+        }
+    }
+
+    /// Get item at index of row. Err if the item is empty or of another type.
+    fn get_item<T: FromSql>(row: &Row, index: usize, id: &Uuid, column: Column) -> Result<T> {
+        match row.get(index) {
+            Ok(v) => Ok(v),
+            Err(_) => Err(SEError::DBErrorWC(NoDataForID, id.to_string(), column)),
+        }
+    }
+
+    /// Get 1 item from row in table. Err if ID not found or data item empty.
+    pub fn get_1<T: FromSql>(&self, id: Uuid, table: Table, column: Vec<Column>) -> Result<T> {
+        self.get_row(&id, table, &column, |row| {
+            Self::get_item(row, 0, &id, column[0])
+        })
+    }
+
+    /// Get 2 items from row in table. Err if ID not found or data item empty.
+    pub fn get_2<T: FromSql, U: FromSql>(
+        &self,
+        id: Uuid,
+        table: Table,
+        column: Vec<Column>,
+    ) -> Result<(T, U)> {
+        self.get_row(&id, table, &column, |row| {
+            Ok((
+                Self::get_item(row, 0, &id, column[0])?,
+                Self::get_item(row, 1, &id, column[1])?,
+            ))
+        })
+    }
+
+    /// Get 3 items from row in table. Err if ID not found or data item empty.
+    pub fn get_3<T: FromSql, U: FromSql, V: FromSql>(
+        &self,
+        id: Uuid,
+        table: Table,
+        column: Vec<Column>,
+    ) -> Result<(T, U, V)> {
+        self.get_row(&id, table, &column, |row| {
+            Ok((
+                Self::get_item(row, 0, &id, column[0])?,
+                Self::get_item(row, 1, &id, column[1])?,
+                Self::get_item(row, 2, &id, column[2])?,
+            ))
+        })
+    }
+
+    /// Get 4 items from row in table. Err if ID not found or data item empty.
+    pub fn get_4<T: FromSql, U: FromSql, V: FromSql, W: FromSql>(
+        &self,
+        id: Uuid,
+        table: Table,
+        column: Vec<Column>,
+    ) -> Result<(T, U, V, W)> {
+        self.get_row(&id, table, &column, |row| {
+            Ok((
+                Self::get_item(row, 0, &id, column[0])?,
+                Self::get_item(row, 1, &id, column[1])?,
+                Self::get_item(row, 2, &id, column[2])?,
+                Self::get_item(row, 3, &id, column[3])?,
+            ))
+        })
+    }
+
+    /// Get an item that may be empty. None if the ID is not found or the item is empty.
+    fn get_opt<T: serde::de::DeserializeOwned>(
+        &self,
+        id: Uuid,
+        table: Table,
+        column: Column,
+    ) -> Result<Option<T>> {
+        match self.get_1::<Option<String>>(id, table, vec![column]) {
+            Ok(Some(item)) => Ok(Some(Self::deser(item)?)),
+            Ok(None) | Err(SEError::DBError(NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Database for SqliteDatabase {
+    fn init(&self) -> Result<()> {
+        self.make_tables()
+    }
+
+    fn from_pool(_pool: r2d2::Pool<PostgresConnectionManager>) -> Self {
+        unimplemented!("SQLite databases are not connected through a Postgres pool")
+    }
+
+    fn get_new() -> Self {
+        Self {
+            connection: None,
+            smt: PGDatabaseSmt {
+                table_name: table_name(&Table::Smt),
+                cache: MemCache::new(),
+                batch_on: false,
+                batch: HashMap::new(),
+            },
+        }
+    }
+
+    fn set_connection_from_config(&mut self, config: &crate::config::Config) -> Result<()> {
+        self.set_connection(&config.storage.db_path)
+    }
+
+    fn set_connection(&mut self, url: &String) -> Result<()> {
+        self.connection = Some(Mutex::new(Self::open(url)?));
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.truncate_tables()
+    }
+
+    fn get_user_auth(&self, user_id: Uuid) -> Result<Uuid> {
+        Ok(self
+            .get_1::<SqlUuid>(user_id, Table::UserSession, vec![Column::Id])?
+            .0)
+    }
+
+    fn has_withdraw_sc_sig(&self, user_id: Uuid) -> Result<()> {
+        self.get_1::<String>(user_id, Table::UserSession, vec![Column::WithdrawScSig])?;
+        Ok(())
+    }
+
+    fn update_withdraw_sc_sig(
+        &self,
+        user_id: &Uuid,
+        sig: StateChainSig,
+        statechain_ids: Vec<Uuid>,
+        outputs: Vec<WithdrawOutput>,
+    ) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![
+                Column::WithdrawScSig,
+                Column::WithdrawBatch,
+                Column::WithdrawOutputs,
+            ],
+            vec![
+                &Self::ser(sig)?,
+                &Self::ser(statechain_ids)?,
+                &Self::ser(outputs)?,
+            ],
+        )
+    }
+
+    fn get_withdraw_batch(&self, user_id: Uuid) -> Result<Vec<Uuid>> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::WithdrawBatch])?)
+    }
+
+    fn get_withdraw_outputs(&self, user_id: Uuid) -> Result<Vec<WithdrawOutput>> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::WithdrawOutputs])?)
+    }
+
+    fn update_s1_pubkey(&self, user_id: &Uuid, pubkey: &GE) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::S1PubKey],
+            vec![&Self::ser(pubkey)?],
+        )
+    }
+
+    fn get_s1_pubkey(&self, user_id: &Uuid) -> Result<GE> {
+        Self::deser(self.get_1(*user_id, Table::UserSession, vec![Column::S1PubKey])?)
+    }
+
+    fn update_key_type(&self, user_id: &Uuid, key_type: &KeyType) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::KeyType],
+            vec![&Self::ser(key_type)?],
+        )
+    }
+
+    fn get_key_type(&self, user_id: Uuid) -> Result<KeyType> {
+        Ok(self
+            .get_opt(user_id, Table::UserSession, Column::KeyType)?
+            .unwrap_or(KeyType::Ecdsa))
+    }
+
+    fn update_musig_key(&self, user_id: &Uuid, key: &MuSigKey) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::MuSigKey],
+            vec![&Self::ser(key)?],
+        )
+    }
+
+    fn get_musig_key(&self, user_id: Uuid) -> Result<MuSigKey> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::MuSigKey])?)
+    }
+
+    fn update_musig_nonces(&self, user_id: &Uuid, nonces: &Option<MuSigNonces>) -> Result<()> {
+        let nonces_str = match nonces {
+            Some(n) => Some(Self::ser(n)?),
+            None => None,
+        };
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::MuSigNonces],
+            vec![&nonces_str],
+        )
+    }
+
+    fn get_musig_nonces(&self, user_id: Uuid) -> Result<Option<MuSigNonces>> {
+        self.get_opt(user_id, Table::UserSession, Column::MuSigNonces)
+    }
+
+    fn update_withdraw_tx_sighash(
+        &self,
+        user_id: &Uuid,
+        sig_hash: Hash,
+        tx: Transaction,
+    ) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::SigHash, Column::TxWithdraw],
+            vec![&Self::ser(sig_hash)?, &Self::ser(tx)?],
+        )
+    }
+
+    fn update_sighash(&self, user_id: &Uuid, sig_hash: Hash) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::SigHash],
+            vec![&Self::ser(sig_hash)?],
+        )
+    }
+
+    fn get_sighash(&self, user_id: Uuid) -> Result<sha256d::Hash> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::SigHash])?)
+    }
+
+    fn update_user_backup_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::TxBackup],
+            vec![&Self::ser(tx)?],
+        )
+    }
+
+    fn get_user_backup_tx(&self, user_id: Uuid) -> Result<Transaction> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::TxBackup])?)
+    }
+
+    fn update_backup_tx(&self, statechain_id: &Uuid, tx: Transaction) -> Result<()> {
+        let locktime = tx.lock_time as i64;
+        self.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![
+                Column::TxBackup,
+                Column::LockTime,
+                Column::TxCpfp,
+                Column::BackupConfirmation,
+            ],
+            vec![
+                &Self::ser(tx)?,
+                &locktime,
+                &Option::<String>::None,
+                &Option::<String>::None,
+            ],
+        )
+    }
+
+    fn update_user_kickoff_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::TxKickOff],
+            vec![&Self::ser(tx)?],
+        )
+    }
+
+    fn get_user_kickoff_tx(&self, user_id: Uuid) -> Result<Transaction> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::TxKickOff])?)
+    }
+
+    fn get_withdraw_confirm_data(&self, user_id: Uuid) -> Result<WithdrawConfirmData> {
+        let (tx_withdraw_str, withdraw_sc_sig_str, statechain_id) = self
+            .get_3::<String, String, SqlUuid>(
+                user_id,
+                Table::UserSession,
+                vec![
+                    Column::TxWithdraw,
+                    Column::WithdrawScSig,
+                    Column::StateChainId,
+                ],
+            )?;
+        Ok(WithdrawConfirmData {
+            tx_withdraw: Self::deser(tx_withdraw_str)?,
+            withdraw_sc_sig: Self::deser(withdraw_sc_sig_str)?,
+            statechain_id: statechain_id.0,
+        })
+    }
+
+    fn has_refresh_sc_sig(&self, user_id: Uuid) -> Result<()> {
+        self.get_1::<String>(user_id, Table::UserSession, vec![Column::RefreshScSig])?;
+        Ok(())
+    }
+
+    fn update_refresh_sc_sig(&self, user_id: &Uuid, sig: StateChainSig) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::RefreshScSig],
+            vec![&Self::ser(sig)?],
+        )
+    }
+
+    fn update_user_refresh_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::TxRefresh],
+            vec![&Self::ser(tx)?],
+        )
+    }
+
+    fn get_user_refresh_tx(&self, user_id: Uuid) -> Result<Transaction> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::TxRefresh])?)
+    }
+
+    fn get_refresh_confirm_data(&self, user_id: Uuid) -> Result<RefreshConfirmData> {
+        let (tx_refresh_str, refresh_sc_sig_str, statechain_id) = self
+            .get_3::<String, String, SqlUuid>(
+                user_id,
+                Table::UserSession,
+                vec![
+                    Column::TxRefresh,
+                    Column::RefreshScSig,
+                    Column::StateChainId,
+                ],
+            )?;
+        Ok(RefreshConfirmData {
+            tx_refresh: Self::deser(tx_refresh_str)?,
+            refresh_sc_sig: Self::deser(refresh_sc_sig_str)?,
+            statechain_id: statechain_id.0,
+        })
+    }
+
+    fn remove_refresh_data(&self, user_id: &Uuid) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::TxRefresh, Column::RefreshScSig],
+            vec![&Option::<String>::None, &Option::<String>::None],
+        )
+    }
+
+    /// Update root value in DB. Update root with ID or insert new DB item.
+    fn root_update(&self, rt: &Root) -> Result<i64> {
+        let mut root = rt.clone();
+        // Get previous ID, or use the one specified in root to update an existing root with mainstay proof
+        let id = match root.id() {
+            //This will update an existing root in the db
+            Some(id) => match self.get_root(id)? {
+                None => {
+                    return Err(SEError::Generic(format!(
+                        "error updating existing root - root not found with id {}",
+                        id
+                    )))
+                }
+                Some(r) => {
+                    if r.hash() != root.hash() {
+                        return Err(SEError::Generic(format!("error updating existing root - hashes do not match: existing: {} update: {}", r, root)));
+                    }
+                    id
+                }
+            },
+            //new root, update id
+            None => match self.root_get_current_id() {
+                Ok(id) => id + 1,
+                Err(_) => 1, // No roots in DB
+            },
+        };
+
+        // Insert new root
+        root.set_id(&id);
+        self.root_insert(root.clone())?;
+
+        debug!("Updated root at id {} with value: {:?}", id, root);
+        Ok(id)
+    }
+
+    /// Insert a Root into root table
+    fn root_insert(&self, root: Root) -> Result<u64> {
+        let ci = root.commitment_info().clone();
+        let conn = self.connection()?;
+        let rows = conn.execute(
+            &format!(
+                "INSERT INTO {} (value, commitmentinfo) VALUES (?1,?2)",
+                table_name(&Table::Root)
+            ),
+            &[&Self::ser(root.hash())?, &Self::ser(ci)?],
+        )?;
+        Ok(rows as u64)
+    }
+
+    /// Get Id of current Root
+    fn root_get_current_id(&self) -> Result<i64> {
+        let conn = self.connection()?;
+        let id: Option<i64> = conn.query_row(
+            &format!("SELECT MAX(id) FROM {}", table_name(&Table::Root)),
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+        Ok(id.unwrap_or(0))
+    }
+
+    /// Get vector of backup transactions that have nlocktimes less than or equal to the supplied locktime (lockheight)
+    fn get_current_backup_txs(&self, locktime: i64) -> Result<Vec<BackupTxID>> {
+        let conn = self.connection()?;
+        let mut statement = conn.prepare(&format!(
+            "SELECT id, txbackup FROM {} WHERE locktime <= ?1",
+            table_name(&Table::BackupTxs)
+        ))?;
+        let mut rows = statement.query(&[&locktime])?;
+        let mut txs: Vec<BackupTxID> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let id: SqlUuid = row.get("id")?;
+            txs.push(BackupTxID {
+                tx: Self::deser(row.get("txbackup")?)?,
+                id: id.0,
+            });
+        }
+        Ok(txs)
+    }
+
+    // remove confirmed backup transaction from db
+    fn remove_backup_tx(&self, statechain_id: &Uuid) -> Result<()> {
+        self.remove(statechain_id, Table::BackupTxs)
+    }
+
+    fn update_cpfp_tx(&self, statechain_id: &Uuid, tx: &Transaction) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxCpfp],
+            vec![&Self::ser(tx)?],
+        )
+    }
+
+    fn get_cpfp_tx(&self, statechain_id: &Uuid) -> Result<Option<Transaction>> {
+        self.get_opt(*statechain_id, Table::BackupTxs, Column::TxCpfp)
+    }
+
+    fn update_backup_confirmation(
+        &self,
+        statechain_id: &Uuid,
+        block: &Option<(u64, BlockHash)>,
+    ) -> Result<()> {
+        let block_str = match block {
+            Some(b) => Some(Self::ser(b)?),
+            None => None,
+        };
+        self.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::BackupConfirmation],
+            vec![&block_str],
+        )
+    }
+
+    fn get_backup_confirmation(&self, statechain_id: &Uuid) -> Result<Option<(u64, BlockHash)>> {
+        self.get_opt(*statechain_id, Table::BackupTxs, Column::BackupConfirmation)
+    }
+
+    /// Get root with given ID
+    fn get_root(&self, id: i64) -> Result<Option<Root>> {
+        if id == 0 {
+            return Ok(None);
+        }
+        let conn = self.connection()?;
+        let (value, commitment_info) = match conn
+            .query_row(
+                &format!(
+                    "SELECT value, commitmentinfo FROM {} WHERE id = ?1",
+                    table_name(&Table::Root)
+                ),
+                &[&id],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?
+        {
+            Some(r) => r,
+            None => return Err(SEError::DBError(NoDataForID, format!("Root id: {}", id))),
+        };
+        let root = Root::from(
+            Some(id),
+            Self::deser(value)?,
+            &Self::deser::<Option<CommitmentInfo>>(commitment_info)?,
+        )?;
+        Ok(Some(root))
+    }
+
+    /// Find the latest confirmed root
+    fn get_confirmed_smt_root(&self) -> Result<Option<Root>> {
+        let current_id = self.root_get_current_id()?;
+        for id in (1..=current_id).rev() {
+            if let Some(r) = self.get_root(id)? {
+                if r.is_confirmed() {
+                    return Ok(Some(r));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_statechain_id(&self, user_id: Uuid) -> Result<Uuid> {
+        Ok(self
+            .get_1::<SqlUuid>(user_id, Table::UserSession, vec![Column::StateChainId])?
+            .0)
+    }
+
+    fn update_statechain_id(&self, user_id: &Uuid, statechain_id: &Uuid) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::StateChainId],
+            vec![&SqlUuid(*statechain_id)],
+        )
+    }
+
+    fn get_statechain_amount(&self, statechain_id: Uuid) -> Result<StateChainAmount> {
+        let (amount, state_chain_str) = self.get_2::<i64, String>(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Amount, Column::Chain],
+        )?;
+        Ok(StateChainAmount {
+            chain: Self::deser(state_chain_str)?,
+            amount,
+        })
+    }
+
+    fn update_statechain_amount(
+        &self,
+        statechain_id: &Uuid,
+        state_chain: StateChain,
+        amount: u64,
+    ) -> Result<()> {
+        let proof_key = state_chain.get_tip()?.data;
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Chain, Column::Amount, Column::ProofKey],
+            vec![&Self::ser(state_chain)?, &(amount as i64), &proof_key], // signals withdrawn funds
+        )
+    }
+
+    fn create_statechain(
+        &self,
+        statechain_id: &Uuid,
+        user_id: &Uuid,
+        state_chain: &StateChain,
+        amount: &i64,
+    ) -> Result<()> {
+        self.insert(statechain_id, Table::StateChain)?;
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![
+                Column::Chain,
+                Column::Amount,
+                Column::LockedUntil,
+                Column::OwnerId,
+                Column::ProofKey,
+            ],
+            vec![
+                &Self::ser(state_chain.to_owned())?,
+                amount,
+                &get_time_now(),
+                &SqlUuid(*user_id),
+                &state_chain.get_tip()?.data,
+            ],
+        )
+    }
+
+    fn get_statechain(&self, statechain_id: Uuid) -> Result<StateChain> {
+        Self::deser(self.get_1(statechain_id, Table::StateChain, vec![Column::Chain])?)
+    }
+
+    fn update_statechain_owner(
+        &self,
+        statechain_id: &Uuid,
+        state_chain: StateChain,
+        new_user_id: &Uuid,
+    ) -> Result<()> {
+        let proof_key = state_chain.get_tip()?.data;
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Chain, Column::OwnerId, Column::ProofKey],
+            vec![&Self::ser(state_chain)?, &SqlUuid(*new_user_id), &proof_key],
+        )
+    }
+
+    // Remove statechain_id from user session to signal end of session
+    fn remove_statechain_id(&self, user_id: &Uuid) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::StateChainId],
+            vec![&SqlUuid(Uuid::nil())],
+        )
+    }
+
+    fn create_backup_transaction(
+        &self,
+        statechain_id: &Uuid,
+        tx_backup: &Transaction,
+    ) -> Result<()> {
+        let locktime = tx_backup.lock_time as i64;
+        self.insert(statechain_id, Table::BackupTxs)?;
+        self.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxBackup, Column::LockTime],
+            vec![&Self::ser(tx_backup.clone())?, &locktime],
+        )
+    }
+
+    fn get_backup_transaction(&self, statechain_id: Uuid) -> Result<Transaction> {
+        Self::deser(self.get_1(statechain_id, Table::BackupTxs, vec![Column::TxBackup])?)
+    }
+
+    fn create_kickoff_transaction(
+        &self,
+        statechain_id: &Uuid,
+        tx_kickoff: &Transaction,
+    ) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxKickOff],
+            vec![&Self::ser(tx_kickoff.clone())?],
+        )
+    }
+
+    fn get_kickoff_transaction(&self, statechain_id: Uuid) -> Result<Transaction> {
+        Self::deser(self.get_1(statechain_id, Table::BackupTxs, vec![Column::TxKickOff])?)
+    }
+
+    fn get_proof_key(&self, user_id: Uuid) -> Result<String> {
+        self.get_1::<String>(user_id, Table::UserSession, vec![Column::ProofKey])
+    }
+
+    fn get_backup_transaction_and_proof_key(&self, user_id: Uuid) -> Result<(Transaction, String)> {
+        let (tx_backup_str, proof_key) = self.get_2::<String, String>(
+            user_id,
+            Table::UserSession,
+            vec![Column::TxBackup, Column::ProofKey],
+        )?;
+        Ok((Self::deser(tx_backup_str)?, proof_key))
+    }
+
+    fn get_sc_locked_until(&self, statechain_id: Uuid) -> Result<NaiveDateTime> {
+        self.get_1::<NaiveDateTime>(statechain_id, Table::StateChain, vec![Column::LockedUntil])
+    }
+
+    fn update_locked_until(&self, statechain_id: &Uuid, time: &NaiveDateTime) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::LockedUntil],
+            vec![time],
+        )
+    }
+
+    fn get_transfer_batch_data(&self, batch_id: Uuid) -> Result<TransferBatchData> {
+        let (state_chains_str, start_time, finalized, punished_state_chains_str) = self
+            .get_4::<String, NaiveDateTime, bool, String>(
+            batch_id,
+            Table::TransferBatch,
+            vec![
+                Column::StateChains,
+                Column::StartTime,
+                Column::Finalized,
+                Column::PunishedStateChains,
+            ],
+        )?;
+        Ok(TransferBatchData {
+            state_chains: Self::deser(state_chains_str)?,
+            start_time,
+            finalized,
+            punished_state_chains: Self::deser(punished_state_chains_str)?,
+        })
+    }
+
+    fn has_transfer_batch_id(&self, batch_id: Uuid) -> bool {
+        self.get_transfer_batch_id(batch_id).is_ok()
+    }
+
+    fn get_transfer_batch_id(&self, batch_id: Uuid) -> Result<Uuid> {
+        Ok(self
+            .get_1::<SqlUuid>(batch_id, Table::TransferBatch, vec![Column::Id])?
+            .0)
+    }
+
+    fn get_punished_state_chains(&self, batch_id: Uuid) -> Result<Vec<Uuid>> {
+        Self::deser(self.get_1(
+            batch_id,
+            Table::TransferBatch,
+            vec![Column::PunishedStateChains],
+        )?)
+    }
+
+    fn create_transfer(
+        &self,
+        statechain_id: &Uuid,
+        statechain_sig: &StateChainSig,
+        x1: &FE,
+    ) -> Result<()> {
+        // Create Transfer table entry
+        self.insert(statechain_id, Table::Transfer)?;
+        self.update(
+            statechain_id,
+            Table::Transfer,
+            vec![Column::StateChainSig, Column::X1, Column::StartTime],
+            vec![
+                &Self::ser(statechain_sig.to_owned())?,
+                &Self::ser(x1.to_owned())?,
+                &get_time_now(),
+            ],
+        )
+    }
+
+    fn update_transfer_msg(&self, statechain_id: &Uuid, msg: &TransferMsg3) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::Transfer,
+            vec![Column::TransferMsg],
+            vec![&Self::ser(msg.to_owned())?],
+        )
+    }
+
+    fn get_transfer_msg(&self, statechain_id: &Uuid) -> Result<TransferMsg3> {
+        Self::deser(self.get_1(*statechain_id, Table::Transfer, vec![Column::TransferMsg])?)
+    }
+
+    fn create_transfer_batch_data(&self, batch_id: &Uuid, state_chains: Vec<Uuid>) -> Result<()> {
+        self.insert(batch_id, Table::TransferBatch)?;
+        self.update(
+            batch_id,
+            Table::TransferBatch,
+            vec![
+                Column::StartTime,
+                Column::StateChains,
+                Column::PunishedStateChains,
+                Column::Finalized,
+            ],
+            vec![
+                &get_time_now(),
+                &Self::ser(state_chains)?,
+                &Self::ser(Vec::<String>::new())?,
+                &false,
+            ],
+        )
+    }
+
+    fn get_transfer_data(&self, statechain_id: Uuid) -> Result<TransferData> {
+        let (statechain_id, statechain_sig_str, x1_str) = self.get_3::<SqlUuid, String, String>(
+            statechain_id,
+            Table::Transfer,
+            vec![Column::Id, Column::StateChainSig, Column::X1],
+        )?;
+        Ok(TransferData {
+            statechain_id: statechain_id.0,
+            statechain_sig: Self::deser(statechain_sig_str)?,
+            x1: Self::deser(x1_str)?,
+        })
+    }
+
+    fn remove_transfer_data(&self, statechain_id: &Uuid) -> Result<()> {
+        self.remove(statechain_id, Table::Transfer)
+    }
+
+    fn add_backup_tx_history(
+        &self,
+        statechain_id: &Uuid,
+        tx: &Transaction,
+        proof_key: &String,
+    ) -> Result<()> {
+        let mut history = self.get_backup_tx_history(*statechain_id)?;
+        history.push(BackupTxRecord {
+            tx: tx.clone(),
+            proof_key: proof_key.clone(),
+        });
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::BackupTxHistory],
+            vec![&Self::ser(history)?],
+        )
+    }
+
+    fn get_backup_tx_history(&self, statechain_id: Uuid) -> Result<Vec<BackupTxRecord>> {
+        Ok(self
+            .get_opt(statechain_id, Table::StateChain, Column::BackupTxHistory)?
+            .unwrap_or_default())
+    }
+
+    fn get_active_statechain_ids(&self) -> Result<Vec<Uuid>> {
+        self.get_ids(
+            &format!(
+                "SELECT id FROM {} WHERE amount > 0",
+                table_name(&Table::StateChain)
+            ),
+            &[],
+        )
+    }
+
+    fn update_funding_confirmation(
+        &self,
+        statechain_id: &Uuid,
+        confirmation: &FundingConfirmation,
+    ) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::FundingConfirmation, Column::FundingTxid],
+            vec![&Self::ser(confirmation)?, &confirmation.txid.to_string()],
+        )
+    }
+
+    fn get_funding_confirmation(&self, statechain_id: Uuid) -> Result<Option<FundingConfirmation>> {
+        self.get_opt(
+            statechain_id,
+            Table::StateChain,
+            Column::FundingConfirmation,
+        )
+    }
+
+    fn remove_funding_confirmation(&self, statechain_id: &Uuid) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::FundingConfirmation],
+            vec![&Option::<String>::None],
+        )
+    }
+
+    fn get_funding_confirmation_ids(&self) -> Result<Vec<Uuid>> {
+        self.get_ids(
+            &format!(
+                "SELECT id FROM {} WHERE fundingconfirmation IS NOT NULL",
+                table_name(&Table::StateChain)
+            ),
+            &[],
+        )
+    }
+
+    fn update_funding_outpoint(&self, statechain_id: &Uuid, outpoint: &OutPoint) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::FundingOutpoint],
+            vec![&Self::ser(outpoint)?],
+        )
+    }
+
+    fn get_funding_outpoint(&self, statechain_id: Uuid) -> Result<Option<OutPoint>> {
+        self.get_opt(statechain_id, Table::StateChain, Column::FundingOutpoint)
+    }
+
+    fn update_refresh_pending(&self, statechain_id: &Uuid, txid: &Txid) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::RefreshPending],
+            vec![&Self::ser(txid)?],
+        )
+    }
+
+    fn get_refresh_pending(&self, statechain_id: Uuid) -> Result<Option<Txid>> {
+        self.get_opt(statechain_id, Table::StateChain, Column::RefreshPending)
+    }
+
+    fn remove_refresh_pending(&self, statechain_id: &Uuid) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::RefreshPending],
+            vec![&Option::<String>::None],
+        )
+    }
+
+    fn update_aborted_locktime(&self, statechain_id: &Uuid, locktime: u32) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::AbortedLocktime],
+            vec![&(locktime as i64)],
+        )
+    }
+
+    fn get_aborted_locktime(&self, statechain_id: Uuid) -> Result<Option<u32>> {
+        match self.get_1::<Option<i64>>(statechain_id, Table::StateChain, vec![Column::AbortedLocktime]) {
+            Ok(locktime) => Ok(locktime.map(|locktime| locktime as u32)),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn remove_aborted_locktime(&self, statechain_id: &Uuid) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::AbortedLocktime],
+            vec![&Option::<i64>::None],
+        )
+    }
+
+    fn update_statechain_closure(
+        &self,
+        statechain_id: &Uuid,
+        closure: &StateChainClosureAPI,
+    ) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Closure],
+            vec![&Self::ser(closure)?],
+        )
+    }
+
+    fn get_statechain_closure(&self, statechain_id: Uuid) -> Result<Option<StateChainClosureAPI>> {
+        self.get_opt(statechain_id, Table::StateChain, Column::Closure)
+    }
+
+    fn get_statechain_listings(
+        &self,
+        filter: &StateChainFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(Vec<StateChainListing>, u64)> {
+        let min_amount = filter.min_amount.map(|a| a as i64);
+        let max_amount = filter.max_amount.map(|a| a as i64);
+        let in_swap: Vec<SqlUuid> = filter.in_swap.iter().map(|id| SqlUuid(*id)).collect();
+        let now = get_time_now();
+        let mut params: Vec<&dyn ToSql> = vec![];
+        let mut conditions = vec![String::from("s.chain IS NOT NULL")];
+        if let Some(min_amount) = &min_amount {
+            params.push(min_amount);
+            conditions.push(format!("s.amount >= ?{}", params.len()));
+        }
+        if let Some(max_amount) = &max_amount {
+            params.push(max_amount);
+            conditions.push(format!("s.amount <= ?{}", params.len()));
+        }
+        if let Some(status) = filter.status {
+            let open = "s.closure IS NULL AND s.amount > 0";
+            match status {
+                StateChainStatus::ClosedByBackup => {
+                    conditions.push(String::from("s.closure IS NOT NULL"))
+                }
+                StateChainStatus::Withdrawn => {
+                    conditions.push(String::from("s.closure IS NULL AND s.amount = 0"))
+                }
+                StateChainStatus::InSwap => {
+                    let ids = push_id_params(&mut params, &in_swap);
+                    conditions.push(format!("{} AND s.id IN {}", open, ids));
+                }
+                StateChainStatus::Locked | StateChainStatus::Active => {
+                    let ids = push_id_params(&mut params, &in_swap);
+                    params.push(&now);
+                    let op = match status {
+                        StateChainStatus::Locked => ">",
+                        _ => "<=",
+                    };
+                    conditions.push(format!(
+                        "{} AND s.id NOT IN {} AND s.lockeduntil {} ?{}",
+                        open,
+                        ids,
+                        op,
+                        params.len()
+                    ));
+                }
+            }
+        }
+        let conditions = conditions.join(" AND ");
+
+        let conn = self.connection()?;
+        let total: i64 = conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM {} s WHERE {}",
+                table_name(&Table::StateChain),
+                conditions,
+            ),
+            &params,
+            |row| row.get(0),
+        )?;
+
+        let mut statement = conn.prepare(&format!(
+            "SELECT s.id AS id, s.amount AS amount, s.chain AS chain, \
+             s.lockeduntil AS lockeduntil, s.fundingtxid AS fundingtxid, \
+             s.closure IS NOT NULL AS closed, b.txbackup AS txbackup \
+             FROM {} s LEFT JOIN {} b ON b.id = s.id WHERE {} ORDER BY s.id LIMIT {} OFFSET {}",
+            table_name(&Table::StateChain),
+            table_name(&Table::BackupTxs),
+            conditions,
+            limit,
+            offset,
+        ))?;
+        let mut rows = statement.query(&params)?;
+        let mut listings = vec![];
+        while let Some(row) = rows.next()? {
+            listings.push(Self::statechain_listing(row)?);
+        }
+        Ok((listings, total as u64))
+    }
+
+    fn get_statechain_listing(&self, statechain_id: Uuid) -> Result<StateChainListing> {
+        let conn = self.connection()?;
+        let mut statement = conn.prepare(&format!(
+            "SELECT s.id AS id, s.amount AS amount, s.chain AS chain, \
+             s.lockeduntil AS lockeduntil, s.fundingtxid AS fundingtxid, \
+             s.closure IS NOT NULL AS closed, b.txbackup AS txbackup \
+             FROM {} s LEFT JOIN {} b ON b.id = s.id WHERE s.id = ?1 AND s.chain IS NOT NULL",
+            table_name(&Table::StateChain),
+            table_name(&Table::BackupTxs),
+        ))?;
+        let mut rows = statement.query(&[&SqlUuid(statechain_id)])?;
+        let listing = match rows.next()? {
+            Some(row) => Self::statechain_listing(row)?,
+            None => return Err(SEError::DBError(NoDataForID, statechain_id.to_string())),
+        };
+        Ok(listing)
+    }
+
+    fn search_statechains(&self, query: &String) -> Result<Vec<Uuid>> {
+        self.get_ids(
+            &format!(
+                "SELECT id FROM {} WHERE proofkey = ?1 OR fundingtxid = ?1 ORDER BY id",
+                table_name(&Table::StateChain)
+            ),
+            &[query],
+        )
+    }
+
+    fn get_user_sessions(&self, offset: u64, limit: u64) -> Result<(Vec<UserSessionAPI>, u64)> {
+        let conn = self.connection()?;
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM {}", table_name(&Table::UserSession)),
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+
+        let mut statement = conn.prepare(&format!(
+            "SELECT id, statechainid, proofkey, \
+             withdrawscsig IS NOT NULL AS withdrawpending, \
+             refreshscsig IS NOT NULL AS refreshpending \
+             FROM {} ORDER BY id LIMIT {} OFFSET {}",
+            table_name(&Table::UserSession),
+            limit,
+            offset,
+        ))?;
+        let mut rows = statement.query(NO_PARAMS)?;
+        let mut sessions = vec![];
+        while let Some(row) = rows.next()? {
+            let id: SqlUuid = row.get("id")?;
+            let statechain_id: Option<SqlUuid> = row.get("statechainid")?;
+            sessions.push(UserSessionAPI {
+                id: id.0,
+                statechain_id: statechain_id.map(|id| id.0),
+                proof_key: row.get("proofkey")?,
+                withdraw_pending: row.get("withdrawpending")?,
+                refresh_pending: row.get("refreshpending")?,
+            });
+        }
+        Ok((sessions, total as u64))
+    }
+
+    fn get_unfunded_user_sessions(&self, created_before: &NaiveDateTime) -> Result<Vec<Uuid>> {
+        self.get_ids(
+            &format!(
+                "SELECT id FROM {} WHERE statechainid IS NULL AND createdat < ?1",
+                table_name(&Table::UserSession)
+            ),
+            &[created_before],
+        )
+    }
+
+    fn remove_user_session(&self, user_id: &Uuid) -> Result<()> {
+        self.remove(user_id, Table::UserSession)
+    }
+
+    fn remove_ecdsa(&self, user_id: &Uuid) -> Result<bool> {
+        let conn = self.connection()?;
+        let rows = conn.execute(
+            &format!("DELETE FROM {} WHERE id = ?1;", table_name(&Table::Ecdsa)),
+            &[&SqlUuid(*user_id)],
+        )?;
+        Ok(rows > 0)
+    }
+
+    fn get_transfer_batches_started_before(&self, time: &NaiveDateTime) -> Result<Vec<Uuid>> {
+        self.get_ids(
+            &format!(
+                "SELECT id FROM {} WHERE starttime < ?1",
+                table_name(&Table::TransferBatch)
+            ),
+            &[time],
+        )
+    }
+
+    fn archive_transfer_batch(&self, batch_id: &Uuid) -> Result<()> {
+        let mut conn = self.connection()?;
+        let transaction = conn.transaction()?;
+        transaction.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {} SELECT * FROM {} WHERE id = ?1;",
+                table_name(&Table::TransferBatchArchive),
+                table_name(&Table::TransferBatch),
+            ),
+            &[&SqlUuid(*batch_id)],
+        )?;
+        if transaction.execute(
+            &format!(
+                "DELETE FROM {} WHERE id = ?1;",
+                table_name(&Table::TransferBatch)
+            ),
+            &[&SqlUuid(*batch_id)],
+        )? == 0
+        {
+            return Err(SEError::DBError(UpdateFailed, batch_id.to_string()));
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn remove_orphaned_backup_txs(&self) -> Result<u64> {
+        let backup_txs = table_name(&Table::BackupTxs);
+        let conn = self.connection()?;
+        let rows = conn.execute(
+            &format!(
+                "DELETE FROM {} WHERE NOT EXISTS \
+                 (SELECT 1 FROM {} s WHERE s.id = {}.id AND s.amount > 0);",
+                backup_txs,
+                table_name(&Table::StateChain),
+                backup_txs,
+            ),
+            NO_PARAMS,
+        )?;
+        Ok(rows as u64)
+    }
+
+    fn get_transfers_started_before(&self, time: &NaiveDateTime) -> Result<Vec<Uuid>> {
+        self.get_ids(
+            &format!(
+                "SELECT id FROM {} WHERE starttime < ?1",
+                table_name(&Table::Transfer)
+            ),
+            &[time],
+        )
+    }
+
+    fn transfer_is_completed(&self, statechain_id: Uuid) -> bool {
+        self.get_1::<SqlUuid>(statechain_id, Table::Transfer, vec![Column::Id])
+            .is_ok()
+    }
+
+    fn get_ecdsa_master(&self, user_id: Uuid) -> Result<Option<String>> {
+        self.get_1::<Option<String>>(user_id, Table::Ecdsa, vec![Column::Party1MasterKey])
+    }
+
+    //kms::ecdsa::two_party::MasterKey1
+    fn update_ecdsa_master(&self, user_id: &Uuid, master_key: MasterKey1) -> Result<()> {
+        self.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::Party1MasterKey],
+            vec![&Self::ser(master_key)?],
+        )
+    }
+
+    fn get_ecdsa_master_key_input(&self, user_id: Uuid) -> Result<ECDSAMasterKeyInput> {
+        let (party2_public_str, paillier_key_pair_str, party_one_private_str, comm_witness_str) =
+            self.get_4::<String, String, String, String>(
+                user_id,
+                Table::Ecdsa,
+                vec![
+                    Column::Party2Public,
+                    Column::PaillierKeyPair,
+                    Column::Party1Private,
+                    Column::CommWitness,
+                ],
+            )?;
+        Ok(ECDSAMasterKeyInput {
+            party2_public: Self::deser(party2_public_str)?,
+            paillier_key_pair: Self::deser(paillier_key_pair_str)?,
+            party_one_private: Self::deser(party_one_private_str)?,
+            comm_witness: Self::deser(comm_witness_str)?,
+        })
+    }
+
+    fn get_ecdsa_witness_keypair(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(party_one::CommWitness, party_one::EcKeyPair)> {
+        let (comm_witness_str, ec_key_pair_str) = self.get_2::<String, String>(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::CommWitness, Column::EcKeyPair],
+        )?;
+        Ok((
+            Self::deser(comm_witness_str)?,
+            Self::deser(ec_key_pair_str)?,
+        ))
+    }
+
+    fn get_ecdsa_s2(&self, user_id: Uuid) -> Result<FE> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::S2])?)
+    }
+
+    fn update_keygen_first_msg(
+        &self,
+        user_id: &Uuid,
+        key_gen_first_msg: &party_one::KeyGenFirstMsg,
+        comm_witness: party_one::CommWitness,
+        ec_key_pair: party_one::EcKeyPair,
+    ) -> Result<()> {
+        self.update(
+            user_id,
+            Table::Ecdsa,
+            vec![
+                Column::POS,
+                Column::KeyGenFirstMsg,
+                Column::CommWitness,
+                Column::EcKeyPair,
+            ],
+            vec![
+                &Self::ser(HDPos { pos: 0u32 })?,
+                &Self::ser(key_gen_first_msg.to_owned())?,
+                &Self::ser(comm_witness)?,
+                &Self::ser(ec_key_pair)?,
+            ],
+        )
+    }
+
+    fn update_keygen_second_msg(
+        &self,
+        user_id: &Uuid,
+        party2_public: GE,
+        paillier_key_pair: party_one::PaillierKeyPair,
+        party_one_private: party_one::Party1Private,
+    ) -> Result<()> {
+        self.update(
+            user_id,
+            Table::Ecdsa,
+            vec![
+                Column::Party2Public,
+                Column::PaillierKeyPair,
+                Column::Party1Private,
+            ],
+            vec![
+                &Self::ser(party2_public)?,
+                &Self::ser(paillier_key_pair)?,
+                &Self::ser(party_one_private)?,
+            ],
+        )
+    }
+
+    fn init_ecdsa(&self, user_id: &Uuid) -> Result<u64> {
+        self.insert(user_id, Table::Ecdsa)
+    }
+
+    fn get_ecdsa_party_1_private(&self, user_id: Uuid) -> Result<party_one::Party1Private> {
+        Self::deser(self.get_1(user_id, Table::Ecdsa, vec![Column::Party1Private])?)
+    }
+
+    fn get_ecdsa_keypair(&self, user_id: Uuid) -> Result<ECDSAKeypair> {
+        let (party_1_private_str, party_2_public_str) = self.get_2::<String, String>(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::Party1Private, Column::Party2Public],
+        )?;
+        let party_1_private: Party1Private = Self::deser(party_1_private_str)?;
+        let party_2_public: GE = Self::deser(party_2_public_str)?;
+        Ok(ECDSAKeypair {
+            party_1_private,
+            party_2_public,
+        })
+    }
+
+    fn update_punished(&self, batch_id: &Uuid, punished_state_chains: Vec<Uuid>) -> Result<()> {
+        self.update(
+            batch_id,
+            Table::TransferBatch,
+            vec![Column::PunishedStateChains],
+            vec![&Self::ser(punished_state_chains)?],
+        )
+    }
+
+    fn get_transfer_batch_start_time(&self, batch_id: &Uuid) -> Result<NaiveDateTime> {
+        self.get_1::<NaiveDateTime>(*batch_id, Table::TransferBatch, vec![Column::StartTime])
+    }
+
+    fn get_batch_transfer_statechain_ids(&self, batch_id: &Uuid) -> Result<HashSet<Uuid>> {
+        Self::deser(self.get_1(*batch_id, Table::TransferBatch, vec![Column::StateChains])?)
+    }
+
+    fn get_finalize_batch_data(&self, batch_id: Uuid) -> Result<TransferFinalizeBatchData> {
+        let mut finalized_data_vec = vec![];
+        for id in self.get_batch_transfer_statechain_ids(&batch_id)? {
+            let finalize_data = self.get_sc_finalize_batch_data(&id)?;
+            // Check the batch id
+            match finalize_data.batch_data {
+                Some(ref bd) if bd.id != batch_id => {
+                    return Err(SEError::DBError(
+                        NoDataForID,
+                        format!("batch_id required:{}, found:{}", batch_id, bd.id),
+                    ))
+                }
+                Some(_) => finalized_data_vec.push(finalize_data),
+                None => return Err(SEError::DBError(NoDataForID, String::from("no batch data"))),
+            }
+        }
+
+        Ok(TransferFinalizeBatchData {
+            finalized_data_vec,
+            start_time: self.get_transfer_batch_start_time(&batch_id)?,
+        })
+    }
+
+    fn update_finalize_batch_data(
+        &self,
+        statechain_id: &Uuid,
+        finalized_data: &TransferFinalizeData,
+    ) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::TransferFinalizeData],
+            vec![&Self::ser(finalized_data)?],
+        )
+    }
+
+    fn get_sc_finalize_batch_data(&self, statechain_id: &Uuid) -> Result<TransferFinalizeData> {
+        Self::deser(self.get_1(
+            *statechain_id,
+            Table::StateChain,
+            vec![Column::TransferFinalizeData],
+        )?)
+    }
+
+    fn update_transfer_batch_finalized(&self, batch_id: &Uuid, b_finalized: &bool) -> Result<()> {
+        self.update(
+            batch_id,
+            Table::TransferBatch,
+            vec![Column::Finalized],
+            vec![b_finalized],
+        )
+    }
+
+    fn get_statechain_owner(&self, statechain_id: Uuid) -> Result<StateChainOwner> {
+        let (locked_until, owner_id, state_chain_str) = self
+            .get_3::<NaiveDateTime, SqlUuid, String>(
+                statechain_id,
+                Table::StateChain,
+                vec![Column::LockedUntil, Column::OwnerId, Column::Chain],
+            )?;
+        Ok(StateChainOwner {
+            locked_until,
+            owner_id: owner_id.0,
+            chain: Self::deser(state_chain_str)?,
+        })
+    }
+
+    // Create DB entry for newly generated ID signalling that user has passed some
+    // verification. For now use ID as 'password' to interact with state entity
+    fn create_user_session(&self, user_id: &Uuid, auth: &String, proof_key: &String) -> Result<()> {
+        self.insert(user_id, Table::UserSession)?;
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::Authentication, Column::ProofKey, Column::CreatedAt],
+            vec![auth, proof_key, &get_time_now()],
+        )
+    }
+
+    fn spend_token(&self, token: &String) -> Result<bool> {
+        let conn = self.connection()?;
+        let rows = conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {} (id) VALUES (?1);",
+                table_name(&Table::SpentToken)
+            ),
+            &[token],
+        )?;
+        Ok(rows == 1)
+    }
+
+    // Create new UserSession to allow new owner to generate shared wallet
+    fn transfer_init_user_session(
+        &self,
+        new_user_id: &Uuid,
+        statechain_id: &Uuid,
+        finalized_data: TransferFinalizeData,
+    ) -> Result<()> {
+        self.insert(new_user_id, Table::UserSession)?;
+        self.update(
+            new_user_id,
+            Table::UserSession,
+            vec![
+                Column::Authentication,
+                Column::ProofKey,
+                Column::TxBackup,
+                Column::StateChainId,
+                Column::S2,
+                Column::KeyType,
+            ],
+            vec![
+                &String::from("auth"),
+                &finalized_data.statechain_sig.data.to_owned(),
+                &Self::ser(transaction_deserialise(&finalized_data.new_tx_backup_hex)?)?,
+                &SqlUuid(*statechain_id),
+                &Self::ser(finalized_data.s2)?,
+                &Self::ser(finalized_data.key_type)?,
+            ],
+        )
+    }
+
+    fn update_ecdsa_sign_first(
+        &self,
+        user_id: Uuid,
+        eph_key_gen_first_message_party_two: party_two::EphKeyGenFirstMsg,
+        eph_ec_key_pair_party1: party_one::EphEcKeyPair,
+    ) -> Result<()> {
+        self.update(
+            &user_id,
+            Table::Ecdsa,
+            vec![Column::EphKeyGenFirstMsg, Column::EphEcKeyPair],
+            vec![
+                &Self::ser(eph_key_gen_first_message_party_two)?,
+                &Self::ser(eph_ec_key_pair_party1)?,
+            ],
+        )
+    }
+
+    fn get_ecdsa_sign_second_input(&self, user_id: Uuid) -> Result<ECDSASignSecondInput> {
+        let (shared_key_str, eph_ec_key_pair_party1_str, eph_key_gen_first_message_party_two_str) =
+            self.get_3::<String, String, String>(
+                user_id,
+                Table::Ecdsa,
+                vec![
+                    Column::Party1MasterKey,
+                    Column::EphEcKeyPair,
+                    Column::EphKeyGenFirstMsg,
+                ],
+            )?;
+        Ok(ECDSASignSecondInput {
+            shared_key: Self::deser(shared_key_str)?,
+            eph_ec_key_pair_party1: Self::deser(eph_ec_key_pair_party1_str)?,
+            eph_key_gen_first_message_party_two: Self::deser(
+                eph_key_gen_first_message_party_two_str,
+            )?,
+        })
+    }
+
+    fn get_tx_withdraw(&self, user_id: Uuid) -> Result<Transaction> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::TxWithdraw])?)
+    }
+
+    fn update_tx_withdraw(&self, user_id: Uuid, tx: Transaction) -> Result<()> {
+        self.update(
+            &user_id,
+            Table::UserSession,
+            vec![Column::TxWithdraw],
+            vec![&Self::ser(tx)?],
+        )
+    }
+
+    fn update_swap_registration(
+        &self,
+        statechain_id: &Uuid,
+        amount: u64,
+        swap_size: u64,
+    ) -> Result<()> {
+        let conn = self.connection()?;
+        conn.execute(
+            &format!(
+                "INSERT INTO {} (id, amount, swapsize) VALUES (?1,?2,?3)
+                ON CONFLICT (id) DO UPDATE SET amount = ?2, swapsize = ?3",
+                table_name(&Table::SwapRegistration)
+            ),
+            &[
+                &SqlUuid(*statechain_id) as &dyn ToSql,
+                &(amount as i64),
+                &(swap_size as i64),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_swap_registration(&self, statechain_id: &Uuid) -> Result<()> {
+        self.remove_if_exists(statechain_id, Table::SwapRegistration)
+    }
+
+    fn get_swap_registrations(&self) -> Result<Vec<SwapRegistration>> {
+        let conn = self.connection()?;
+        let mut statement = conn.prepare(&format!(
+            "SELECT id, amount, swapsize FROM {}",
+            table_name(&Table::SwapRegistration)
+        ))?;
+        let mut rows = statement.query(NO_PARAMS)?;
+        let mut registrations = Vec::new();
+        while let Some(row) = rows.next()? {
+            let statechain_id: SqlUuid = row.get("id")?;
+            let amount: i64 = row.get("amount")?;
+            let swap_size: i64 = row.get("swapsize")?;
+            registrations.push(SwapRegistration {
+                statechain_id: statechain_id.0,
+                amount: amount as u64,
+                swap_size: swap_size as u64,
+            });
+        }
+        Ok(registrations)
+    }
+
+    fn update_swap(&self, swap_data: &SwapData) -> Result<()> {
+        // BisetMap is stored as a list of (SCEAddress, claimed nonce) pairs
+        let out_addr_vec = swap_data.out_addr_map.as_ref().map(|m| m.flat_collect());
+        let conn = self.connection()?;
+        conn.execute(
+            &format!(
+                "INSERT INTO {} (id, swapinfo, outaddrmap, bsteprimemap, bstsigmap, tbsigmap, bstretrieved,
+                phasestart)
+                VALUES (?1,?2,?3,?4,?5,?6,?7,?8)
+                ON CONFLICT (id) DO UPDATE SET swapinfo = ?2, outaddrmap = ?3, bsteprimemap = ?4,
+                bstsigmap = ?5, tbsigmap = ?6, bstretrieved = ?7, phasestart = ?8",
+                table_name(&Table::Swap)
+            ),
+            &[
+                &SqlUuid(swap_data.swap_info.swap_token.id) as &dyn ToSql,
+                &Self::ser(&swap_data.swap_info)?,
+                &Self::ser(out_addr_vec)?,
+                &Self::ser(&swap_data.bst_e_prime_map)?,
+                &Self::ser(&swap_data.bst_sig_map)?,
+                &Self::ser(&swap_data.tb_sig_map)?,
+                &Self::ser(&swap_data.bst_retrieved)?,
+                &Self::ser(&swap_data.phase_start)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_swap(&self, swap_id: &Uuid) -> Result<()> {
+        self.remove_if_exists(swap_id, Table::Swap)
+    }
+
+    fn get_swaps(&self) -> Result<Vec<SwapData>> {
+        let conn = self.connection()?;
+        let mut statement = conn.prepare(&format!(
+            "SELECT swapinfo, outaddrmap, bsteprimemap, bstsigmap, tbsigmap, bstretrieved, phasestart
+            FROM {}",
+            table_name(&Table::Swap)
+        ))?;
+        let mut rows = statement.query(NO_PARAMS)?;
+        let mut swaps = Vec::new();
+        while let Some(row) = rows.next()? {
+            let swap_info: SwapInfo = Self::deser(row.get("swapinfo")?)?;
+            let out_addr_vec: Option<Vec<(SCEAddress, Option<Uuid>)>> =
+                Self::deser(row.get("outaddrmap")?)?;
+            let out_addr_map = out_addr_vec.map(|v| {
+                let m = BisetMap::<SCEAddress, Option<Uuid>>::new();
+                for (addr, nonce) in v {
+                    m.insert(addr, nonce);
+                }
+                m
+            });
+            swaps.push(SwapData {
+                swap_info,
+                out_addr_map,
+                bst_e_prime_map: Self::deser(row.get("bsteprimemap")?)?,
+                bst_sig_map: Self::deser(row.get("bstsigmap")?)?,
+                tb_sig_map: Self::deser(row.get("tbsigmap")?)?,
+                bst_retrieved: Self::deser(row.get("bstretrieved")?)?,
+                phase_start: match row.get::<_, Option<String>>("phasestart")? {
+                    Some(v) => Self::deser(v)?,
+                    None => None,
+                },
+            });
+        }
+        Ok(swaps)
+    }
+}
+
+// SQLite Monotree implementation. SMT nodes are stored as blobs.
+impl MonotreeDatabase for SqliteDatabase {
+    // Dummy function not used in SQLite. The connection is opened by set_connection_from_config.
+    fn new(_dbname: &str) -> Self {
+        <Self as Database>::get_new()
+    }
+
+    /// Monotree get
+    fn get(&mut self, key: &[u8]) -> std::result::Result<Option<Vec<u8>>, Errors> {
+        if self.smt.cache.contains(key) {
+            return self.smt.cache.get(key);
+        }
+        let conn = self.connection().map_err(smt_error)?;
+        conn.query_row(
+            &format!("SELECT value FROM {} WHERE key = ?1", self.smt.table_name),
+            &[key],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(smt_error)
+    }
+
+    /// Monotree put
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> std::result::Result<(), Errors> {
+        self.smt.cache.put(key, value.to_owned())?;
+        if self.smt.batch_on {
+            self.smt.batch.insert(key.to_vec(), value);
+            return Ok(());
+        }
+        let conn = self.connection().map_err(smt_error)?;
+        conn.execute(
+            &format!(
+                "INSERT INTO {} (key, value) VALUES (?1,?2)
+                ON CONFLICT (key) DO UPDATE SET value = excluded.value;",
+                self.smt.table_name
+            ),
+            &[key, &value[..]],
+        )
+        .map_err(smt_error)?;
+        Ok(())
+    }
+
+    /// Monotree delete
+    fn delete(&mut self, key: &[u8]) -> std::result::Result<(), Errors> {
+        self.smt.cache.delete(key)?;
+        if self.smt.batch_on {
+            self.smt.batch.remove(key);
+            return Ok(());
+        }
+        let conn = self.connection().map_err(smt_error)?;
+        conn.execute(
+            &format!("DELETE FROM {} WHERE key = ?1;", self.smt.table_name),
+            &[key],
+        )
+        .map_err(smt_error)?;
+        Ok(())
+    }
+
+    /// Monotree init_batch
+    fn init_batch(&mut self) -> std::result::Result<(), Errors> {
+        self.smt.batch = HashMap::new();
+        self.smt.cache.clear();
+        self.smt.batch_on = true;
+        Ok(())
+    }
+
+    /// Monotree finish_batch. The batch is written in one transaction.
+    fn finish_batch(&mut self) -> std::result::Result<(), Errors> {
+        self.smt.batch_on = false;
+        if self.smt.batch.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.connection().map_err(smt_error)?;
+        let transaction = conn.transaction().map_err(smt_error)?;
+        for (key, value) in &self.smt.batch {
+            transaction
+                .execute(
+                    &format!(
+                        "INSERT INTO {} (key, value) VALUES (?1,?2)
+                        ON CONFLICT (key) DO UPDATE SET value = excluded.value;",
+                        self.smt.table_name
+                    ),
+                    &[key, value],
+                )
+                .map_err(smt_error)?;
+        }
+        transaction.commit().map_err(smt_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use monotree::hasher::{Blake3, Hasher};
+    use monotree::Monotree;
+
+    fn test_db() -> SqliteDatabase {
+        let mut db = <SqliteDatabase as Database>::get_new();
+        db.set_connection(&String::from(":memory:")).unwrap();
+        db.init().unwrap();
+        db
+    }
+
+    fn statechain(proof_key: &str) -> StateChain {
+        StateChain::new(proof_key.to_string())
+    }
+
+    #[test]
+    fn test_migrate_tables() {
+        let mut db = <SqliteDatabase as Database>::get_new();
+        db.set_connection(&String::from(":memory:")).unwrap();
+        let statechain_id = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+        {
+            // Tables as created by the first release
+            let conn = db.connection().unwrap();
+            conn.execute_batch(&format!(
+                "
+            CREATE TABLE {} (
+                id text NOT NULL,
+                chain text,
+                amount integer,
+                ownerid text,
+                lockeduntil text,
+                transferfinalizedata text,
+                transferready integer,
+                PRIMARY KEY (id)
+            );
+            CREATE TABLE {} (
+                id text NOT NULL,
+                statechainid text,
+                authentication text,
+                proofkey text,
+                txbackup text,
+                PRIMARY KEY (id)
+            );",
+                table_name(&Table::StateChain),
+                table_name(&Table::UserSession),
+            ))
+            .unwrap();
+            conn.execute(
+                &format!(
+                    "INSERT INTO {} (id, chain) VALUES (?1, ?2)",
+                    table_name(&Table::StateChain)
+                ),
+                &[
+                    &SqlUuid(statechain_id) as &dyn ToSql,
+                    &SqliteDatabase::ser(statechain("proof key")).unwrap(),
+                ],
+            )
+            .unwrap();
+            conn.execute(
+                &format!("INSERT INTO {} (id) VALUES (?1)", table_name(&Table::UserSession)),
+                &[&SqlUuid(user_id)],
+            )
+            .unwrap();
+        }
+
+        // Columns are added once and the proof key is set from the tip of the statechain
+        db.init().unwrap();
+        db.init().unwrap();
+        assert_eq!(
+            db.search_statechains(&String::from("proof key")).unwrap(),
+            vec![statechain_id]
+        );
+        assert_eq!(db.get_key_type(user_id).unwrap(), KeyType::Ecdsa);
+        let later = get_time_now() + chrono::Duration::seconds(1);
+        assert_eq!(db.get_unfunded_user_sessions(&later).unwrap(), vec![user_id]);
+    }
+
+    #[test]
+    fn test_user_session() {
+        let db = test_db();
+        let user_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        db.create_user_session(&user_id, &String::from("auth"), &String::from("proof key"))
+            .unwrap();
+        assert_eq!(db.get_user_auth(user_id).unwrap(), user_id);
+        assert_eq!(db.get_proof_key(user_id).unwrap(), "proof key");
+        // Items not yet set are errors
+        assert!(db.get_statechain_id(user_id).is_err());
+        assert!(db.has_withdraw_sc_sig(user_id).is_err());
+        assert!(db.get_user_auth(Uuid::new_v4()).is_err());
+
+        db.update_statechain_id(&user_id, &statechain_id).unwrap();
+        assert_eq!(db.get_statechain_id(user_id).unwrap(), statechain_id);
+        let (sessions, total) = db.get_user_sessions(0, 10).unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(sessions[0].statechain_id, Some(statechain_id));
+        assert_eq!(sessions[0].withdraw_pending, false);
+
+        // Unfunded sessions
+        let unfunded_user_id = Uuid::new_v4();
+        db.create_user_session(&unfunded_user_id, &String::new(), &String::new())
+            .unwrap();
+        db.init_ecdsa(&unfunded_user_id).unwrap();
+        let later = get_time_now() + chrono::Duration::seconds(1);
+        assert_eq!(
+            db.get_unfunded_user_sessions(&later).unwrap(),
+            vec![unfunded_user_id]
+        );
+        assert!(db.remove_ecdsa(&unfunded_user_id).unwrap());
+        assert!(!db.remove_ecdsa(&unfunded_user_id).unwrap());
+        db.remove_user_session(&unfunded_user_id).unwrap();
+        assert!(db.remove_user_session(&unfunded_user_id).is_err());
+    }
+
+    #[test]
+    fn test_statechain_and_backup_tx() {
+        let db = test_db();
+        let statechain_id = Uuid::new_v4();
+        let owner_id = Uuid::new_v4();
+        db.create_statechain(&statechain_id, &owner_id, &statechain("key1"), &1000)
+            .unwrap();
+        let owner = db.get_statechain_owner(statechain_id).unwrap();
+        assert_eq!(owner.owner_id, owner_id);
+        assert_eq!(owner.chain.get_tip().unwrap().data, "key1");
+        assert_eq!(
+            db.get_statechain_amount(statechain_id).unwrap().amount,
+            1000
+        );
+        assert_eq!(db.get_active_statechain_ids().unwrap(), vec![statechain_id]);
+        assert_eq!(
+            db.search_statechains(&String::from("key1")).unwrap(),
+            vec![statechain_id]
+        );
+        // Empty items are None
+        assert!(db.get_statechain_closure(statechain_id).unwrap().is_none());
+        assert!(db
+            .get_funding_confirmation(statechain_id)
+            .unwrap()
+            .is_none());
+        assert!(db.get_backup_tx_history(statechain_id).unwrap().is_empty());
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: 500,
+            input: vec![],
+            output: vec![],
+        };
+        db.create_backup_transaction(&statechain_id, &tx).unwrap();
+        assert_eq!(db.get_backup_transaction(statechain_id).unwrap(), tx);
+        assert!(db.get_cpfp_tx(&statechain_id).unwrap().is_none());
+        assert_eq!(db.get_current_backup_txs(499).unwrap().len(), 0);
+        assert_eq!(db.get_current_backup_txs(500).unwrap()[0].id, statechain_id);
+        db.add_backup_tx_history(&statechain_id, &tx, &String::from("key1"))
+            .unwrap();
+        assert_eq!(db.get_backup_tx_history(statechain_id).unwrap().len(), 1);
+
+        // Listed with its backup tx until withdrawn
+        let (listings, total) = db
+            .get_statechain_listings(&StateChainFilter::default(), 0, 10)
+            .unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(listings[0].tx_backup, Some(tx));
+        let filter = StateChainFilter {
+            status: Some(StateChainStatus::InSwap),
+            in_swap: vec![statechain_id],
+            ..Default::default()
+        };
+        assert_eq!(db.get_statechain_listings(&filter, 0, 10).unwrap().1, 1);
+        let filter = StateChainFilter {
+            status: Some(StateChainStatus::Active),
+            ..Default::default()
+        };
+        assert_eq!(db.get_statechain_listings(&filter, 0, 10).unwrap().1, 1);
+        let filter = StateChainFilter {
+            min_amount: Some(1001),
+            ..Default::default()
+        };
+        assert_eq!(db.get_statechain_listings(&filter, 0, 10).unwrap().1, 0);
+
+        assert_eq!(db.remove_orphaned_backup_txs().unwrap(), 0);
+        db.update_statechain_amount(&statechain_id, statechain("key2"), 0)
+            .unwrap();
+        assert!(db.get_active_statechain_ids().unwrap().is_empty());
+        assert_eq!(db.remove_orphaned_backup_txs().unwrap(), 1);
+        assert!(db.get_backup_transaction(statechain_id).is_err());
+    }
+
+    #[test]
+    fn test_transfer_batch_archive() {
+        let db = test_db();
+        let batch_id = Uuid::new_v4();
+        let state_chains = vec![Uuid::new_v4(), Uuid::new_v4()];
+        db.create_transfer_batch_data(&batch_id, state_chains.clone())
+            .unwrap();
+        assert!(db.has_transfer_batch_id(batch_id));
+        db.update_punished(&batch_id, vec![state_chains[0]])
+            .unwrap();
+        let tbd = db.get_transfer_batch_data(batch_id).unwrap();
+        assert_eq!(tbd.state_chains.len(), 2);
+        assert_eq!(tbd.punished_state_chains, vec![state_chains[0]]);
+        assert!(!tbd.finalized);
+
+        let later = get_time_now() + chrono::Duration::seconds(1);
+        assert_eq!(
+            db.get_transfer_batches_started_before(&later).unwrap(),
+            vec![batch_id]
+        );
+        db.archive_transfer_batch(&batch_id).unwrap();
+        assert!(!db.has_transfer_batch_id(batch_id));
+        assert!(db.archive_transfer_batch(&batch_id).is_err());
+    }
+
+    #[test]
+    fn test_root() {
+        let db = test_db();
+        assert_eq!(db.root_get_current_id().unwrap(), 0);
+        assert!(db.get_confirmed_smt_root().unwrap().is_none());
+
+        let root = Root::from_random();
+        assert_eq!(db.root_update(&root).unwrap(), 1);
+        assert_eq!(db.root_update(&Root::from_random()).unwrap(), 2);
+        assert_eq!(db.root_get_current_id().unwrap(), 2);
+        assert_eq!(db.get_root(1).unwrap().unwrap().hash(), root.hash());
+
+        // IDs restart after a reset
+        db.reset().unwrap();
+        assert_eq!(db.root_update(&root).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_spend_token() {
+        let db = test_db();
+        let token = String::from("token");
+        assert!(db.spend_token(&token).unwrap());
+        assert!(!db.spend_token(&token).unwrap());
+    }
+
+    #[test]
+    fn test_monotree_sqlite_tree() {
+        let mut tree = Monotree {
+            db: test_db(),
+            hasher: Blake3::new(),
+        };
+        let keys: &[monotree::Hash] = &[[3; 32], [4; 32], [5; 32]];
+        let leaves: &[monotree::Hash] = &[[6; 32], [7; 32], [8; 32]];
+
+        let root = tree.insert(None, &keys[0], &leaves[0]).unwrap();
+        assert_eq!(Some(leaves[0]), tree.get(root.as_ref(), &keys[0]).unwrap());
+
+        // Batch
+        let root = tree
+            .inserts(root.as_ref(), &keys[1..], &leaves[1..])
+            .unwrap();
+        let res = tree.gets(root.as_ref(), keys).unwrap();
+        assert!(res.contains(&Some(leaves[1])));
+        assert!(res.contains(&Some(leaves[2])));
+
+        // Nodes are read from the database
+        tree.db.smt.cache.clear();
+        assert_eq!(Some(leaves[2]), tree.get(root.as_ref(), &keys[2]).unwrap());
+
+        let root = tree.removes(root.as_ref(), keys).unwrap();
+        assert_eq!(None, root);
+        assert_eq!(None, tree.get(root.as_ref(), &keys[0]).unwrap());
+    }
+}
@@ -6,7 +6,7 @@ mod tests {
     use rocket::http::{ContentType, Status};
     use rocket::local::Client;
     use shared_lib::structs::{
-        DepositMsg1, KeyGenMsg1, Protocol, SmtProofMsgAPI, StateEntityFeeInfoAPI,
+        DepositMsg1, KeyGenMsg1, KeyType, Protocol, SmtProofMsgAPI, StateEntityFeeInfoAPI,
     };
     use shared_lib::{mainstay, Root};
 
@@ -29,6 +29,9 @@ mod tests {
         let deposit_msg1 = DepositMsg1 {
             auth: String::from("auth"),
             proof_key: String::from("proof key"),
+            pow_solution: None,
+            token: None,
+            key_type: KeyType::Ecdsa,
         };
         let body = serde_json::to_string(&deposit_msg1).unwrap();
         let mut response = client
@@ -1,11 +1,22 @@
 pub use super::Result;
 extern crate shared_lib;
 use crate::config::Config;
-use std::{thread, time};
+use std::{fs, thread, time};
+use std::str::FromStr;
 use crate::Database;
-use bitcoincore_rpc::Error;
-use bitcoin::consensus;
-use jsonrpc;
+use crate::chain::{get_chain_backend, ChainBackend, TxStatus};
+use crate::server::{StateChainEntity, SUPERSEDED_BACKUPS_COUNT};
+use crate::storage::Storage;
+use crate::structs::BackupTxRecord;
+use bitcoin::{Address, PrivateKey, Transaction, Txid};
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::blockdata::transaction::SigHashType;
+use shared_lib::{error::SharedLibError, state_chain::smt_key, structs::StateChainClosureAPI, util::{blocks_from_sequence, tx_cpfp_build, FEE}};
+use monotree::database::Database as MonotreeDatabase;
+use chrono::{NaiveDate, NaiveDateTime};
+use std::collections::HashSet;
+use uuid::Uuid;
 use cfg_if::cfg_if;
 
 cfg_if! {
@@ -16,7 +27,141 @@ cfg_if! {
     }
 }
 
-pub fn watch_node(rpc_path: String) -> Result<()> {
+/// Minimum relay fee rate (sat/vbyte)
+const MIN_RELAY_FEE_RATE: u64 = 1;
+/// Virtual size of a signed CPFP child tx: one P2WPKH input and one P2WPKH output
+const CPFP_CHILD_VSIZE: u64 = 110;
+
+/// WIF of the cpfp_address key from cpfp_key, which is set in the environment, or else from
+/// cpfp_key_file. None if neither is set.
+fn load_cpfp_key_wif(config: &Config) -> Option<String> {
+    if !config.cpfp_key.is_empty() {
+        return Some(config.cpfp_key.clone());
+    }
+    if config.cpfp_key_file.is_empty() {
+        return None;
+    }
+    match fs::read_to_string(&config.cpfp_key_file) {
+        Ok(wif) => Some(wif.trim().to_string()),
+        Err(e) => {
+            warn!(
+                "WATCH: Failed to read cpfp_key_file {}, backup tx fee bumping disabled: {}",
+                config.cpfp_key_file, e
+            );
+            None
+        }
+    }
+}
+
+/// Get the cpfp_address key used to sign CPFP child txs, if fee bumping is enabled. The key is
+/// hot, so cpfp_address only receives the fee outputs of backup txs and never fee_address
+/// revenue.
+fn get_cpfp_key(config: &Config) -> Option<PrivateKey> {
+    if config.cpfp_address.is_empty() {
+        return None;
+    }
+    if config.cpfp_address == config.fee_address {
+        warn!("WATCH: cpfp_address is fee_address, backup tx fee bumping disabled");
+        return None;
+    }
+    let cpfp_key_wif = load_cpfp_key_wif(config)?;
+    let cpfp_key = match PrivateKey::from_wif(&cpfp_key_wif) {
+        Ok(k) => k,
+        Err(e) => {
+            warn!("WATCH: Invalid cpfp_key, backup tx fee bumping disabled: {}", e);
+            return None;
+        }
+    };
+    let cpfp_key_addr =
+        Address::p2wpkh(&cpfp_key.public_key(&Secp256k1::new()), cpfp_key.network);
+    match (cpfp_key_addr, Address::from_str(&config.cpfp_address)) {
+        (Ok(ref key_addr), Ok(ref cpfp_addr)) if key_addr == cpfp_addr => Some(cpfp_key),
+        _ => {
+            warn!("WATCH: cpfp_key does not match cpfp_address, backup tx fee bumping disabled");
+            None
+        }
+    }
+}
+
+/// Fee rate (sat/vbyte) from a chain backend estimate, at least the min relay fee rate
+pub fn fee_rate_from_estimate(estimate: Option<u64>) -> u64 {
+    match estimate {
+        Some(fee_rate) => std::cmp::max(fee_rate, MIN_RELAY_FEE_RATE),
+        None => MIN_RELAY_FEE_RATE,
+    }
+}
+
+/// Fee a CPFP child must pay for the package of it and the backup tx to reach fee_rate.
+/// Backup txs pay the fixed FEE (see tx_backup_build).
+pub fn cpfp_child_fee(tx_backup: &Transaction, fee_rate: &u64) -> u64 {
+    let package_vsize = (tx_backup.get_weight() as u64 + 3) / 4 + CPFP_CHILD_VSIZE;
+    std::cmp::max(
+        (package_vsize * fee_rate).saturating_sub(FEE),
+        CPFP_CHILD_VSIZE * MIN_RELAY_FEE_RATE,
+    )
+}
+
+/// Fee paid by a CPFP child spending an output of tx_backup
+pub fn cpfp_fee_paid(tx_backup: &Transaction, tx_cpfp: &Transaction) -> u64 {
+    let vout = tx_cpfp.input[0].previous_output.vout as usize;
+    tx_backup.output[vout].value - tx_cpfp.output[0].value
+}
+
+/// Fee of a new CPFP child for tx_backup at fee_rate, or None if the backup tx and its current
+/// child (if any) already pay enough. A replacement child must also pay for its own relay (BIP125).
+pub fn cpfp_fee_bump(
+    tx_backup: &Transaction,
+    tx_cpfp: Option<&Transaction>,
+    fee_rate: &u64,
+) -> Option<u64> {
+    let fee_required = cpfp_child_fee(tx_backup, fee_rate);
+    match tx_cpfp {
+        Some(tx_cpfp) => {
+            let fee_paid = cpfp_fee_paid(tx_backup, tx_cpfp);
+            if fee_required >= fee_paid + CPFP_CHILD_VSIZE * MIN_RELAY_FEE_RATE {
+                Some(fee_required)
+            } else {
+                None
+            }
+        }
+        None => {
+            let backup_vsize = (tx_backup.get_weight() as u64 + 3) / 4;
+            if backup_vsize * fee_rate > FEE {
+                Some(fee_required)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Build CPFP child spending the fee output of tx_backup and sign it with the cpfp_address key
+pub fn cpfp_tx_sign(
+    tx_backup: &Transaction,
+    cpfp_key: &PrivateKey,
+    fee: &u64,
+) -> Result<Transaction> {
+    let secp = Secp256k1::new();
+    let pub_key = cpfp_key.public_key(&secp);
+    let cpfp_addr = Address::p2wpkh(&pub_key, cpfp_key.network).map_err(SharedLibError::from)?;
+    let mut tx_cpfp = tx_cpfp_build(tx_backup, &cpfp_addr.to_string(), fee)?;
+
+    // BIP143 script code of a P2WPKH output is the P2PKH script of its key
+    let vout = tx_cpfp.input[0].previous_output.vout as usize;
+    let sighash = SigHashCache::new(&tx_cpfp).signature_hash(
+        0,
+        &Address::p2pkh(&pub_key, cpfp_key.network).script_pubkey(),
+        tx_backup.output[vout].value,
+        SigHashType::All,
+    );
+    let msg = Message::from_slice(&sighash[..])?;
+    let mut sig = secp.sign(&msg, &cpfp_key.key).serialize_der().to_vec();
+    sig.push(SigHashType::All as u8);
+    tx_cpfp.input[0].witness = vec![sig, pub_key.to_bytes()];
+    Ok(tx_cpfp)
+}
+
+pub fn watch_node() -> Result<()> {
 
     let config_rs = Config::load().unwrap();
 
@@ -25,77 +170,148 @@ pub fn watch_node(rpc_path: String) -> Result<()> {
             use crate::MockDatabase;
             let mut tx_db = MockDatabase::new();
         } else {
-            use crate::PGDatabase;
-            let mut tx_db = PGDatabase::get_new();
+            use crate::StorageDatabase;
+            let mut tx_db = StorageDatabase::get_new();
         }
     }
 
     //set db connection
     tx_db.set_connection_from_config(&config_rs)?;
 
+    //key for fee bumping broadcast backup txs by spending their fee output (CPFP)
+    let cpfp_key = get_cpfp_key(&config_rs);
+
     //check interval 
     let interval = time::Duration::from_millis(SCAN_INTERVAL);
-    let rpc_path_parts: Vec<&str> = rpc_path.split('@').collect();
-    if rpc_path_parts.len() != 2 {
-        panic!("Invalid bitcoind RPC path")
-    };
 
-    let rpc_cred: Vec<&str> = rpc_path_parts[0].split(':').collect();
-    if rpc_cred.len() != 2 {
-        panic!("Invalid bitcoind RPC credentials")
-    };
+    let mut chain = get_chain_backend(&config_rs)?;
 
-    cfg_if! {
-        if #[cfg(any(test,feature="mockbitcoinrpc"))]{
-            use shared_lib::mocks::mock_rpc_client::MockBitcoinClient;
-            let mut rpc = MockBitcoinClient::new();
-        } else {
-            use bitcoincore_rpc::{Auth, Client, RpcApi};
-            let rpc = Client::new(rpc_path_parts[1].to_string(),
-                          Auth::UserPass(rpc_cred[0].to_string(),
-                                         rpc_cred[1].to_string())).unwrap();
+    // main watch loop
+    loop {
+        if let Err(e) = watch_scan(
+            &mut *chain,
+            &tx_db,
+            &cpfp_key,
+            config_rs.cpfp_target,
+            config_rs.reorg_depth,
+        ) {
+            info!("WATCH: Error scanning backup txs {}", e);
+        }
+
+        thread::sleep(interval);
+
+        cfg_if! {
+            if #[cfg(any(test))]{
+                if true {
+                    return Ok(());
+                }
+            }
         }
     }
+}
 
-    // main watch loop
-    loop {
-        // get current block height
-        let bestblockcount = rpc.get_block_count();
-        let blocks = bestblockcount.unwrap() as i64;
+/// Broadcast stored backup txs that are now valid, fee bump them while unconfirmed and remove
+/// them once they have reorg_depth confirmations. Until then the block containing a backup tx
+/// is tracked and it is rebroadcast if a reorg removes it from the chain.
+pub fn watch_scan<T: Database>(
+    chain: &mut dyn ChainBackend,
+    tx_db: &T,
+    cpfp_key: &Option<PrivateKey>,
+    cpfp_target: u16,
+    reorg_depth: u32,
+) -> Result<()> {
+    // get current block height
+    let blocks = chain.get_block_height()? as i64;
 
-        debug!("WATCH: Bitcoin block height {}", blocks);
+    debug!("WATCH: Bitcoin block height {}", blocks);
 
-        //get all backup transactions with loctimes less than or equal to the current block height
-        let txs = tx_db.get_current_backup_txs(blocks).unwrap();
+    //get all backup transactions with loctimes less than or equal to the current block height.
+    //In relative locktime mode backup txs have no nLocktime and are rejected until their
+    //kick-off tx has been broadcast and confirmed for the relative locktime.
+    let txs = tx_db.get_current_backup_txs(blocks)?;
 
-        debug!("WATCH: Stored backup txs now valid {}", txs.len().to_string() );
+    debug!("WATCH: Stored backup txs now valid {}", txs.len().to_string() );
 
-        //loop over txs
-        for tx in &txs {
-            debug!("WATCH: TxID: {}",consensus::encode::serialize_hex(&tx.tx.txid()));
+    //current fee rate for backup tx packages
+    let fee_rate = match (cpfp_key, txs.is_empty()) {
+        (Some(_), false) => match chain.estimate_fee_rate(cpfp_target) {
+            Ok(estimate) => fee_rate_from_estimate(estimate),
+            Err(e) => {
+                info!("WATCH: Error estimating fee rate {}", e);
+                MIN_RELAY_FEE_RATE
+            }
+        },
+        _ => MIN_RELAY_FEE_RATE,
+    };
 
-            let txinfo = rpc.send_raw_transaction(&consensus::serialize(&tx.tx));
+    //loop over txs
+    for tx in &txs {
+        debug!("WATCH: TxID: {}", tx.tx.txid());
 
-            match txinfo {
+        let status = chain.get_tx_status(&tx.tx.txid());
+
+        //a backup tx seen confirmed that is no longer confirmed has been reorged out
+        let confirmed_block = tx_db.get_backup_confirmation(&tx.id)?;
+        let reorged = match status {
+            Ok(TxStatus::Unconfirmed) | Ok(TxStatus::Unknown) => confirmed_block.is_some(),
+            _ => false,
+        };
+        if reorged {
+            warn!(
+                "WATCH: Reorg: backup txid {} no longer confirmed in block {}",
+                tx.tx.txid(),
+                confirmed_block.unwrap().1
+            );
+            tx_db.update_backup_confirmation(&tx.id, &None)?;
+        }
+
+        match status {
+            Ok(TxStatus::Confirmed(confirmations)) => {
+                //track the block containing the backup tx
+                let block = match chain.get_tx_block(&tx.tx.txid())? {
+                    Some(block) => block,
+                    // Reorged out since its status was read
+                    None => continue,
+                };
+                if let Some(prev_block) = confirmed_block {
+                    if prev_block != block {
+                        warn!(
+                            "WATCH: Reorg: backup txid {} moved from block {} to block {}",
+                            tx.tx.txid(),
+                            prev_block.1,
+                            block.1
+                        );
+                    }
+                }
+                if confirmed_block != Some(block) {
+                    tx_db.update_backup_confirmation(&tx.id, &Some(block))?;
+                }
+                if confirmations < reorg_depth {
+                    debug!(
+                        "WATCH: Backup txid {} confirmed in block {}. Confirmations: {}",
+                        tx.tx.txid(),
+                        block.1,
+                        confirmations
+                    );
+                    continue;
+                }
+                // transaction final - remove from backup DB
+                tx_db.remove_backup_tx(&tx.id)?;
+                info!(
+                    "Backup txid {} already confirmed. ID {} removed from BackupTx database.",
+                    tx.tx.txid(),
+                    tx.id
+                );
+                continue;
+            }
+            Ok(TxStatus::Unconfirmed) => (),
+            Ok(TxStatus::Unknown) => match chain.broadcast_transaction(&tx.tx) {
                 Ok(ret) => {
                     info!(
                         "Backup transaction txid {} successfully broadcast.",
                         ret
                     );
-                    continue;
                 }
-                Err(Error::JsonRpc(jsonrpc::error::Error::Rpc(ref rpcerr)))
-                    if rpcerr.code == -27 =>  // "transaction already in block chain"
-                        {
-                            // transaction successfully confirmed - remove from backup DB
-                            tx_db.remove_backup_tx(&tx.id)?;
-                            info!(
-                                "Backup txid {} already confirmed. ID {} removed from BackupTx database.",
-                                tx.tx.txid(),
-                                tx.id
-                            );
-                            continue;
-                        }
                 Err(e) => {
                     info!(
                         "Error sending backup tx {} {}",
@@ -103,18 +319,381 @@ pub fn watch_node(rpc_path: String) -> Result<()> {
                     );
                     continue;
                 }
+            },
+            Err(e) => {
+                info!(
+                    "Error getting status of backup tx {} {}",
+                    tx.tx.txid(),e
+                );
+                continue;
             }
         }
 
-        thread::sleep(interval);
+        //backup tx is unconfirmed: fee bump it with a CPFP child until it confirms
+        let cpfp_key = match cpfp_key {
+            Some(k) => k,
+            None => continue,
+        };
+        let tx_cpfp = tx_db.get_cpfp_tx(&tx.id)?;
+        let fee = match cpfp_fee_bump(&tx.tx, tx_cpfp.as_ref(), &fee_rate) {
+            Some(f) => f,
+            None => {
+                //current child pays enough - make sure it is still in the mempool
+                if let Some(tx_cpfp) = tx_cpfp {
+                    if let Ok(TxStatus::Unknown) = chain.get_tx_status(&tx_cpfp.txid()) {
+                        let _ = chain.broadcast_transaction(&tx_cpfp);
+                    }
+                }
+                continue;
+            }
+        };
+        let tx_cpfp = match cpfp_tx_sign(&tx.tx, cpfp_key, &fee) {
+            Ok(tx_cpfp) => tx_cpfp,
+            Err(e) => {
+                info!("Unable to fee bump backup tx {} {}", tx.tx.txid(), e);
+                continue;
+            }
+        };
+        match chain.broadcast_transaction(&tx_cpfp) {
+            Ok(ret) => {
+                tx_db.update_cpfp_tx(&tx.id, &tx_cpfp)?;
+                info!(
+                    "CPFP txid {} paying {} sat broadcast for backup txid {}.",
+                    ret,
+                    fee,
+                    tx.tx.txid()
+                );
+            }
+            Err(e) => {
+                info!(
+                    "Error sending CPFP tx for backup tx {} {}",
+                    tx.tx.txid(),e
+                );
+            }
+        }
+    }
+    Ok(())
+}
 
-        cfg_if! {
-            if #[cfg(any(test))]{
-                if true {
-                    return Ok(());
+/// Find the confirmed backup tx in a statechain's history that spent the current backup tx's
+/// input, and its number of confirmations. Only backup txs spending the same output as the
+/// current one can have spent it.
+pub fn find_closing_backup_tx<'a>(
+    chain: &mut dyn ChainBackend,
+    history: &'a Vec<BackupTxRecord>,
+) -> Result<Option<(&'a BackupTxRecord, u32)>> {
+    let current = match history.last() {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let outpoint = current.tx.input[0].previous_output;
+    if !chain.is_outpoint_spent(&outpoint)? {
+        return Ok(None);
+    }
+    // Newest first: owners' backup txs have decreasing locktimes
+    for record in history.iter().rev() {
+        if record.tx.input[0].previous_output != outpoint {
+            continue;
+        }
+        if let TxStatus::Confirmed(confirmations) = chain.get_tx_status(&record.tx.txid())? {
+            return Ok(Some((record, confirmations)));
+        }
+    }
+    Ok(None)
+}
+
+/// Lock time of a statechain whose funding tx has been reorged out: until it confirms again
+pub fn reorg_locked_until() -> NaiveDateTime {
+    NaiveDate::from_ymd(9999, 12, 31).and_hms(0, 0, 0)
+}
+
+/// Backup txs of previous owners that can still spend the current backup tx's input. Older
+/// backup txs have higher locktimes, but become valid before the current owner's is confirmed.
+pub fn superseded_backup_txs(history: &Vec<BackupTxRecord>) -> Vec<&BackupTxRecord> {
+    let current = match history.last() {
+        Some(r) => r,
+        None => return vec![],
+    };
+    history[..history.len() - 1]
+        .iter()
+        .filter(|r| r.tx.input[0].previous_output == current.tx.input[0].previous_output)
+        .collect()
+}
+
+/// Whether a backup tx can be included in the next block: its absolute locktime has passed or,
+/// in relative locktime mode, the kick-off tx it spends has enough confirmations.
+pub fn backup_tx_is_valid(
+    chain: &mut dyn ChainBackend,
+    tx_backup: &Transaction,
+    relative_locktime: bool,
+) -> Result<bool> {
+    if relative_locktime {
+        let blocks = blocks_from_sequence(&tx_backup.input[0].sequence).unwrap_or(0);
+        return match chain.get_tx_status(&tx_backup.input[0].previous_output.txid)? {
+            TxStatus::Confirmed(confirmations) => Ok(confirmations >= blocks),
+            _ => Ok(false),
+        };
+    }
+    Ok(tx_backup.lock_time as u64 <= chain.get_block_height()?)
+}
+
+impl<
+        T: Database + Send + Sync + 'static,
+        D: Database + MonotreeDatabase + Send + Sync + 'static,
+    > StateChainEntity<T, D>
+{
+    /// Check all active statechains for previous owners' backup txs in the mempool or chain.
+    /// alerted holds the txids of superseded backup txs already reported.
+    pub fn check_superseded_backups(&self, alerted: &mut HashSet<Txid>) -> Result<()> {
+        for statechain_id in self.database.get_active_statechain_ids()? {
+            if let Err(e) = self.check_superseded_backup(&statechain_id, alerted) {
+                warn!(
+                    "WATCH: Error checking superseded backup txs of statechain {}: {}",
+                    statechain_id, e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Alert if a previous owner's backup tx for a statechain is in the mempool or chain, and
+    /// broadcast the current owner's backup tx if it is valid. Returns the superseded txid found.
+    pub fn check_superseded_backup(
+        &self,
+        statechain_id: &Uuid,
+        alerted: &mut HashSet<Txid>,
+    ) -> Result<Option<Txid>> {
+        let history = self.database.get_backup_tx_history(*statechain_id)?;
+        let current = match history.last() {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+
+        let mut chain = self.chain.lock()?;
+        let mut superseded = None;
+        for record in superseded_backup_txs(&history) {
+            match chain.get_tx_status(&record.tx.txid()) {
+                Ok(TxStatus::Unknown) => (),
+                Ok(status) => {
+                    superseded = Some((record, status));
+                    break;
                 }
+                Err(e) => debug!(
+                    "WATCH: Error getting status of backup tx {} {}",
+                    record.tx.txid(),
+                    e
+                ),
             }
         }
+        let (record, status) = match superseded {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        let txid = record.tx.txid();
+        if alerted.insert(txid) {
+            SUPERSEDED_BACKUPS_COUNT.inc();
+            error!(
+                "WATCH: ALERT: Superseded backup txid {} of statechain {} ({:?}). Signed for previous owner {}.",
+                txid, statechain_id, status, record.proof_key
+            );
+        }
+
+        // Once the superseded backup tx confirms the statechain is closed
+        if status != TxStatus::Unconfirmed {
+            return Ok(Some(txid));
+        }
+        if chain.get_tx_status(&current.tx.txid())? != TxStatus::Unknown {
+            return Ok(Some(txid));
+        }
+        if backup_tx_is_valid(&mut **chain, &current.tx, self.config.relative_locktime)? {
+            match chain.broadcast_transaction(&current.tx) {
+                Ok(ret) => info!(
+                    "WATCH: Current backup txid {} of statechain {} broadcast.",
+                    ret, statechain_id
+                ),
+                Err(e) => warn!(
+                    "WATCH: Error sending current backup tx {} of statechain {} {}",
+                    current.tx.txid(),
+                    statechain_id,
+                    e
+                ),
+            }
+        }
+        Ok(Some(txid))
+    }
+
+    /// Re-verify the blocks containing statechain funding txs that are not yet final
+    pub fn verify_funding_confirmations(&self) -> Result<()> {
+        for statechain_id in self.database.get_funding_confirmation_ids()? {
+            if let Err(e) = self.verify_funding_confirmation(&statechain_id) {
+                warn!(
+                    "WATCH: Error verifying funding tx of statechain {}: {}",
+                    statechain_id, e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that a statechain's funding tx is still in the block it confirmed in. If a reorg
+    /// leaves it with fewer than the required confirmations the statechain is locked until it
+    /// confirms again. Tracking stops once the tx has reorg_depth confirmations.
+    pub fn verify_funding_confirmation(&self, statechain_id: &Uuid) -> Result<()> {
+        let mut confirmation = match self.database.get_funding_confirmation(*statechain_id)? {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+
+        let mut chain = self.chain.lock()?;
+        if let Some((height, block_hash)) = confirmation.block {
+            if chain.get_block_hash(height)? == block_hash {
+                let confirmations = (chain.get_block_height()? + 1).saturating_sub(height);
+                drop(chain);
+                if confirmations >= self.config.reorg_depth as u64 {
+                    self.database.remove_funding_confirmation(statechain_id)?;
+                }
+                return Ok(());
+            }
+        }
+
+        // Block reorged out or tx waiting to confirm again
+        let (confirmations, block) = match chain.get_tx_status(&confirmation.txid)? {
+            // Reorged out if its block is no longer found
+            TxStatus::Confirmed(c) => match chain.get_tx_block(&confirmation.txid)? {
+                Some(block) => (c, Some(block)),
+                None => (0, None),
+            },
+            _ => (0, None),
+        };
+        drop(chain);
+
+        if confirmations >= self.config.required_confirmation {
+            match confirmation.locked_until.take() {
+                Some(locked_until) => {
+                    self.database
+                        .update_locked_until(statechain_id, &locked_until)?;
+                    info!(
+                        "WATCH: Funding txid {} of statechain {} confirmed again. Statechain unlocked.",
+                        confirmation.txid, statechain_id
+                    );
+                }
+                None => warn!(
+                    "WATCH: Reorg: funding txid {} of statechain {} moved to block {}",
+                    confirmation.txid,
+                    statechain_id,
+                    block.unwrap().1
+                ),
+            }
+            confirmation.block = block;
+        } else {
+            if confirmation.locked_until.is_none() {
+                confirmation.locked_until =
+                    Some(self.database.get_sc_locked_until(*statechain_id)?);
+                self.database
+                    .update_locked_until(statechain_id, &reorg_locked_until())?;
+                warn!(
+                    "WATCH: Reorg: funding txid {} of statechain {} has {} confirmations. Statechain locked.",
+                    confirmation.txid, statechain_id, confirmations
+                );
+            }
+            confirmation.block = None;
+        }
+        self.database
+            .update_funding_confirmation(statechain_id, &confirmation)
+    }
+
+    /// Close all active statechains whose funding output has been spent by a final backup tx
+    pub fn close_spent_statechains(&self) -> Result<()> {
+        for statechain_id in self.database.get_active_statechain_ids()? {
+            if let Err(e) = self.close_if_spent(&statechain_id) {
+                warn!("WATCH: Error checking closure of statechain {}: {}", statechain_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Close a statechain if its funding output has been spent by a backup tx with reorg_depth
+    /// confirmations. Returns the closure record if it was closed.
+    pub fn close_if_spent(&self, statechain_id: &Uuid) -> Result<Option<StateChainClosureAPI>> {
+        let history = self.database.get_backup_tx_history(*statechain_id)?;
+        let current = match history.last() {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+
+        let (closing, height) = {
+            let mut chain = self.chain.lock()?;
+            let closing = match find_closing_backup_tx(&mut **chain, &history)? {
+                // Closing is final: wait until the backup tx is safe from reorgs
+                Some((r, confirmations)) => {
+                    if confirmations < self.config.reorg_depth {
+                        debug!(
+                            "WATCH: Statechain {} spent by backup txid {}. Confirmations: {}",
+                            statechain_id,
+                            r.tx.txid(),
+                            confirmations
+                        );
+                        return Ok(None);
+                    }
+                    r
+                }
+                None => {
+                    // Spent by a tx unknown to the SE, e.g. an unfinished refresh or withdrawal
+                    if chain.is_outpoint_spent(&current.tx.input[0].previous_output)? {
+                        warn!(
+                            "WATCH: Funding output of statechain {} spent by unknown tx",
+                            statechain_id
+                        );
+                    }
+                    return Ok(None);
+                }
+            };
+            (closing, chain.get_block_height()?)
+        };
+
+        let closing_txid = closing.tx.txid();
+        let closure = StateChainClosureAPI {
+            txid: closing_txid.to_string(),
+            proof_key: closing.proof_key.clone(),
+            current_owner: closing_txid == current.tx.txid(),
+            height,
+        };
+
+        // Read before the backup tx is removed below, which holds the kick-off tx of
+        // statechains created before the funding outpoint was stored on the statechain
+        let funding_outpoint = self.get_funding_outpoint(statechain_id, &current.tx).ok();
+
+        let statechain_amount = self.database.get_statechain_amount(*statechain_id)?;
+        self.database
+            .update_statechain_amount(statechain_id, statechain_amount.chain, 0)?;
+        self.database
+            .update_statechain_closure(statechain_id, &closure)?;
+
+        // The watcher may already have removed a confirmed backup tx
+        let _ = self.database.remove_backup_tx(statechain_id);
+        let _ = self.database.remove_transfer_data(statechain_id);
+
+        // Record the closing tx in the sparse merkle tree in place of the owner's proof key
+        match funding_outpoint {
+            Some(outpoint) => {
+                self.update_smt(&smt_key(&outpoint), &closure.txid)?;
+            }
+            None => warn!(
+                "WATCH: Funding outpoint of statechain {} not found. Sparse merkle tree not updated.",
+                statechain_id
+            ),
+        }
+
+        self.scheduler
+            .lock()?
+            .deregister_statechain(statechain_id);
+
+        info!(
+            "WATCH: Statechain {} closed by backup txid {}. Current owner: {}",
+            statechain_id, closure.txid, closure.current_owner
+        );
+        Ok(Some(closure))
     }
 }
 
@@ -126,7 +705,8 @@ pub mod tests {
     use crate::MockDatabase;
     use uuid::Uuid;
     use crate::structs::BackupTxID;
-    use shared_lib::{mocks::mock_rpc_client::MockBitcoinClient};
+    use crate::chain::memory::MemoryChain;
+    use mockall::predicate;
     use bitcoin::consensus::encode;
 
     #[test]
@@ -140,18 +720,402 @@ pub mod tests {
         let id_1 = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
         let id_2 = Uuid::from_str("93ad2134-ffd3-869d-beef-8da52c985aa1").unwrap();
 
-        let backup_1 = BackupTxID { tx: backup_tx_1, id: id_1 };
-        let backup_2 = BackupTxID { tx: backup_tx_2, id: id_2 };
+        let backup_1 = BackupTxID { tx: backup_tx_1.clone(), id: id_1 };
+        let backup_2 = BackupTxID { tx: backup_tx_2.clone(), id: id_2 };
         let mut backup_txs: Vec<BackupTxID> = Vec::new();
         backup_txs.push(backup_1);
         backup_txs.push(backup_2);
 
         let mut db = MockDatabase::new();
-        db.expect_get_current_backup_txs().returning(move |_| {Ok(backup_txs.clone())});
-        db.expect_remove_backup_tx().returning(|_| Ok(()));
-        let mut rpc = MockBitcoinClient::new();
+        db.expect_get_current_backup_txs()
+            .with(predicate::eq(147 as i64))
+            .returning(move |_| {Ok(backup_txs.clone())});
+        db.expect_remove_backup_tx()
+            .with(predicate::eq(id_1))
+            .times(1)
+            .returning(|_| Ok(()));
+        db.expect_get_backup_confirmation().returning(|_| Ok(None));
+        db.expect_update_backup_confirmation()
+            .with(predicate::eq(id_1), predicate::always())
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mut chain = MemoryChain::new();
+        chain.set_height(147);
+        chain.set_tx_status(&backup_tx_1.txid(), TxStatus::Confirmed(1));
+
+        // Confirmed backup tx is removed and the other is broadcast
+        watch_scan(&mut chain, &db, &None, 6, 1).unwrap();
+        assert_eq!(chain.get_broadcast_txs(), vec![backup_tx_2.clone()]);
+        assert_eq!(chain.get_tx_status(&backup_tx_2.txid()).unwrap(), TxStatus::Unconfirmed);
+
+    }
+
+    #[test]
+    fn test_watch_reorg() {
+        use std::sync::{Arc, Mutex};
+
+        let backup_tx_raw: Vec::<u8> = hex::decode("020000000001010a742dc732ef1ea6a71c042b7fa212457b52438ba5c3b8552b8a4fd74e86a0f601000000171600147a91e5a412a6a826897067654fffb1557741285efeffffff0240860f240100000017a9140dbb4870526bb96a42ebe19dc86d84a34addc5d48700e1f5050000000017a9141040c0c1b81e2e00aec47ef01c2d3a6116ca513d8702483045022100e7d13322ee719ae8fb7775cafec98137d8d3c42e340cda7750679a06308744f602206154f097a7bc625c688db343633cdafa9e48455d90630d21edf8e036faa0ddbf0121034ea2ae3c24aea00b262c557675d82b66d9aa0f2bc14dfa7d82d1983efb0456c984000000").unwrap();
+        let backup_tx: Transaction = encode::deserialize(&backup_tx_raw).unwrap();
+        let id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let backup_txs = vec![BackupTxID { tx: backup_tx.clone(), id }];
+
+        // Stored block of the backup tx
+        let confirmed_block = Arc::new(Mutex::new(None));
+        let confirmed_block_get = confirmed_block.clone();
+        let confirmed_block_update = confirmed_block.clone();
+
+        let mut db = MockDatabase::new();
+        db.expect_get_current_backup_txs()
+            .returning(move |_| Ok(backup_txs.clone()));
+        db.expect_get_backup_confirmation()
+            .returning(move |_| Ok(*confirmed_block_get.lock().unwrap()));
+        db.expect_update_backup_confirmation()
+            .returning(move |_, block| {
+                *confirmed_block_update.lock().unwrap() = *block;
+                Ok(())
+            });
+        db.expect_remove_backup_tx()
+            .with(predicate::eq(id))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let mut chain = MemoryChain::new();
+        chain.set_height(147);
+        watch_scan(&mut chain, &db, &None, 6, 3).unwrap();
+        assert_eq!(chain.get_broadcast_txs(), vec![backup_tx.clone()]);
+
+        // Confirmed: block recorded but not yet final
+        chain.mine_block();
+        watch_scan(&mut chain, &db, &None, 6, 3).unwrap();
+        let block = chain.get_tx_block(&backup_tx.txid()).unwrap();
+        assert!(block.is_some());
+        assert_eq!(*confirmed_block.lock().unwrap(), block);
+
+        // Reorged out: block cleared
+        chain.reorg(1);
+        watch_scan(&mut chain, &db, &None, 6, 3).unwrap();
+        assert_eq!(*confirmed_block.lock().unwrap(), None);
+
+        // Confirmed again in a new block and removed once final
+        chain.mine_block();
+        chain.mine_block();
+        watch_scan(&mut chain, &db, &None, 6, 3).unwrap();
+        assert_ne!(*confirmed_block.lock().unwrap(), block);
+        chain.mine_block();
+        watch_scan(&mut chain, &db, &None, 6, 3).unwrap();
+    }
+
+    #[test]
+    fn test_cpfp_fee_bump() {
+        use bitcoin::OutPoint;
+        use shared_lib::util::{keygen::generate_keypair, tx_backup_build};
+
+        let (cpfp_key, cpfp_pub_key) = generate_keypair();
+        let cpfp_addr = Address::p2wpkh(&cpfp_pub_key, cpfp_key.network).unwrap();
+        let (_, b_pub_key) = generate_keypair();
+        let b_addr = Address::p2wpkh(&b_pub_key, cpfp_key.network).unwrap();
+        let tx_backup = tx_backup_build(
+            &OutPoint::default(),
+            &b_addr,
+            &1000000,
+            &0,
+            &40000,
+            &cpfp_addr.to_string(),
+        )
+        .unwrap();
+
+        // At least the min relay fee rate
+        assert_eq!(fee_rate_from_estimate(Some(20)), 20);
+        assert_eq!(fee_rate_from_estimate(Some(0)), MIN_RELAY_FEE_RATE);
+        assert_eq!(fee_rate_from_estimate(None), MIN_RELAY_FEE_RATE);
+
+        // Backup tx FEE already covers a low fee rate
+        assert_eq!(cpfp_fee_bump(&tx_backup, None, &1), None);
+
+        // High fee rate: child pays for the package
+        let fee = cpfp_fee_bump(&tx_backup, None, &50).unwrap();
+        assert_eq!(fee, cpfp_child_fee(&tx_backup, &50));
+        let tx_cpfp = cpfp_tx_sign(&tx_backup, &cpfp_key, &fee).unwrap();
+        assert_eq!(tx_cpfp.input[0].previous_output.txid, tx_backup.txid());
+        assert_eq!(tx_cpfp.input[0].witness.len(), 2);
+        assert_eq!(tx_cpfp.output[0].script_pubkey, cpfp_addr.script_pubkey());
+        assert_eq!(cpfp_fee_paid(&tx_backup, &tx_cpfp), fee);
+        assert!((tx_cpfp.get_weight() as u64 + 3) / 4 <= CPFP_CHILD_VSIZE);
+
+        // Child already pays enough at the same fee rate
+        assert_eq!(cpfp_fee_bump(&tx_backup, Some(&tx_cpfp), &50), None);
+        // Fee rate rises: replacement child must pay more
+        let fee_rebump = cpfp_fee_bump(&tx_backup, Some(&tx_cpfp), &100).unwrap();
+        assert!(fee_rebump >= fee + CPFP_CHILD_VSIZE * MIN_RELAY_FEE_RATE);
+
+        // Fee output too small to pay the fee
+        assert!(cpfp_tx_sign(&tx_backup, &cpfp_key, &40000).is_err());
+    }
+
+    #[test]
+    fn test_get_cpfp_key() {
+        use shared_lib::util::keygen::generate_keypair;
+
+        let (cpfp_key, cpfp_pub_key) = generate_keypair();
+        let cpfp_addr = Address::p2wpkh(&cpfp_pub_key, cpfp_key.network).unwrap();
+        let mut config = Config::default();
+        config.cpfp_key = cpfp_key.to_wif();
+
+        // Fee bumping needs a dedicated address
+        assert!(get_cpfp_key(&config).is_none());
+        config.cpfp_address = config.fee_address.clone();
+        assert!(get_cpfp_key(&config).is_none());
+
+        config.cpfp_address = cpfp_addr.to_string();
+        assert_eq!(get_cpfp_key(&config).map(|k| k.to_wif()), Some(cpfp_key.to_wif()));
+        assert_eq!(config.backup_fee_address(), &cpfp_addr.to_string());
+
+        // Key of another address
+        let (other_key, _) = generate_keypair();
+        config.cpfp_key = other_key.to_wif();
+        assert!(get_cpfp_key(&config).is_none());
+    }
+
+    // Backup txs of a previous owner (locktime 200) and the current owner (locktime 190)
+    // spending the funding outpoint
+    fn test_backup_tx_history(funding_outpoint: &bitcoin::OutPoint) -> Vec<BackupTxRecord> {
+        use shared_lib::util::{keygen::generate_keypair, tx_backup_build};
+
+        let (fee_key, fee_pub_key) = generate_keypair();
+        let fee_addr = Address::p2wpkh(&fee_pub_key, fee_key.network).unwrap();
+        let mut history = vec![];
+        for (i, proof_key) in vec!["proof_key_1", "proof_key_2"].iter().enumerate() {
+            let (_, b_pub_key) = generate_keypair();
+            let b_addr = Address::p2wpkh(&b_pub_key, fee_key.network).unwrap();
+            let tx = tx_backup_build(
+                funding_outpoint,
+                &b_addr,
+                &1000000,
+                &(200 - i as u32 * 10),
+                &40000,
+                &fee_addr.to_string(),
+            )
+            .unwrap();
+            history.push(BackupTxRecord { tx, proof_key: proof_key.to_string() });
+        }
+        history
+    }
+
+    #[test]
+    fn test_close_if_spent() {
+        use bitcoin::OutPoint;
+        use crate::protocol::util::{mocks, tests::{test_sc_entity, STATE_CHAIN}};
+        use crate::structs::StateChainAmount;
+        use shared_lib::state_chain::StateChain;
+
+        let statechain_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let funding_outpoint = OutPoint {
+            txid: bitcoin::Txid::default(),
+            vout: 1,
+        };
+        let history = test_backup_tx_history(&funding_outpoint);
+        let old_txid = history[0].tx.txid();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let history_clone = history.clone();
+        db.expect_get_backup_tx_history()
+            .with(predicate::eq(statechain_id))
+            .returning(move |_| Ok(history_clone.clone()));
+        db.expect_get_statechain_amount().returning(|_| {
+            Ok(StateChainAmount {
+                chain: serde_json::from_str::<StateChain>(STATE_CHAIN).unwrap(),
+                amount: 1000000,
+            })
+        });
+        db.expect_update_statechain_amount()
+            .withf(|_, _, amount| *amount == 0)
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        db.expect_update_statechain_closure()
+            .withf(move |_, closure| {
+                closure.txid == old_txid.to_string()
+                    && closure.proof_key == "proof_key_1"
+                    && !closure.current_owner
+                    && closure.height == 300
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        // The SMT key is the funding outpoint stored on the statechain
+        db.expect_get_funding_outpoint()
+            .with(predicate::eq(statechain_id))
+            .times(1)
+            .returning(move |_| Ok(Some(funding_outpoint)));
+        db.expect_remove_backup_tx().times(1).returning(|_| Ok(()));
+        db.expect_remove_transfer_data().times(1).returning(|_| Ok(()));
+        db.expect_root_get_current_id().returning(|| Ok(1 as i64));
+        db.expect_get_root().returning(|_| Ok(None));
+        db.expect_root_update().times(1).returning(|_| Ok(1));
+
+        let sc_entity = test_sc_entity(db);
+        let _m = mocks::ms::post_commitment().create(); //Mainstay post commitment mock
+        let chain = MemoryChain::new();
+        chain.set_height(300);
+        *sc_entity.chain.lock().unwrap() = Box::new(chain.clone());
+        sc_entity
+            .scheduler
+            .lock()
+            .unwrap()
+            .register_amount_swap_size(&statechain_id, 1000000, 3);
+
+        // Funding output unspent
+        assert_eq!(sc_entity.close_if_spent(&statechain_id).unwrap(), None);
+
+        // Spent by a tx the SE did not sign
+        chain.set_spent(&funding_outpoint);
+        assert_eq!(sc_entity.close_if_spent(&statechain_id).unwrap(), None);
+
+        // Spent by the previous owner's backup tx: closed once it has reorg_depth confirmations
+        chain.set_tx_status(&old_txid, TxStatus::Confirmed(1));
+        assert_eq!(sc_entity.close_if_spent(&statechain_id).unwrap(), None);
+        chain.set_tx_status(&old_txid, TxStatus::Confirmed(sc_entity.config.reorg_depth));
+        let closure = sc_entity.close_if_spent(&statechain_id).unwrap().unwrap();
+        assert_eq!(closure.txid, old_txid.to_string());
+        assert!(!closure.current_owner);
+        assert!(sc_entity
+            .scheduler
+            .lock()
+            .unwrap()
+            .get_statechain_ids_by_amount(&1000000)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_check_superseded_backup() {
+        use bitcoin::OutPoint;
+        use crate::protocol::util::tests::test_sc_entity;
+
+        let statechain_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let funding_outpoint = OutPoint {
+            txid: bitcoin::Txid::default(),
+            vout: 1,
+        };
+        let mut history = test_backup_tx_history(&funding_outpoint);
+        // Backup tx spending an earlier funding output, replaced by a refresh
+        let mut refreshed = history[0].clone();
+        refreshed.tx.input[0].previous_output.vout = 0;
+        history.insert(0, refreshed.clone());
+        assert_eq!(superseded_backup_txs(&history), vec![&history[1]]);
+        let old_txid = history[1].tx.txid();
+        let current_tx = history[2].tx.clone();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_backup_tx_history()
+            .returning(move |_| Ok(history.clone()));
+
+        let sc_entity = test_sc_entity(db);
+        let chain = MemoryChain::new();
+        chain.set_height(150);
+        *sc_entity.chain.lock().unwrap() = Box::new(chain.clone());
+        let mut alerted = HashSet::new();
+
+        // Only the refreshed backup tx has been seen: its output is no longer the funding output
+        chain.set_tx_status(&refreshed.tx.txid(), TxStatus::Confirmed(1));
+        assert_eq!(sc_entity.check_superseded_backup(&statechain_id, &mut alerted).unwrap(), None);
+
+        // Superseded backup tx in the mempool before the current backup tx is valid
+        chain.set_tx_status(&old_txid, TxStatus::Unconfirmed);
+        assert_eq!(
+            sc_entity.check_superseded_backup(&statechain_id, &mut alerted).unwrap(),
+            Some(old_txid)
+        );
+        assert!(alerted.contains(&old_txid));
+        assert!(chain.get_broadcast_txs().is_empty());
+
+        // Current backup tx valid: broadcast once
+        chain.set_height(190);
+        sc_entity.check_superseded_backup(&statechain_id, &mut alerted).unwrap();
+        sc_entity.check_superseded_backup(&statechain_id, &mut alerted).unwrap();
+        assert_eq!(chain.get_broadcast_txs(), vec![current_tx]);
+        assert_eq!(alerted.len(), 1);
+    }
+
+    #[test]
+    fn test_verify_funding_confirmation() {
+        use crate::protocol::util::tests::test_sc_entity;
+        use crate::structs::FundingConfirmation;
+        use std::sync::{Arc, Mutex};
+
+        let statechain_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let txid = Txid::from_str("e0a97cb38e7e73617ef75a57eaf2841eb06833407c0eae08029bd04ea7e6115a").unwrap();
+        let chain = MemoryChain::new();
+        chain.set_height(100);
+        chain.set_tx_status(&txid, TxStatus::Unconfirmed);
+        chain.mine_block();
+
+        let initial_locked_until = NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let confirmation = Arc::new(Mutex::new(Some(FundingConfirmation {
+            txid,
+            block: chain.clone().get_tx_block(&txid).unwrap(),
+            locked_until: None,
+        })));
+        let locked_until = Arc::new(Mutex::new(initial_locked_until));
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let confirmation_get = confirmation.clone();
+        db.expect_get_funding_confirmation()
+            .returning(move |_| Ok(confirmation_get.lock().unwrap().clone()));
+        let confirmation_update = confirmation.clone();
+        db.expect_update_funding_confirmation()
+            .returning(move |_, c| {
+                *confirmation_update.lock().unwrap() = Some(c.clone());
+                Ok(())
+            });
+        let confirmation_remove = confirmation.clone();
+        db.expect_remove_funding_confirmation()
+            .times(1)
+            .returning(move |_| {
+                *confirmation_remove.lock().unwrap() = None;
+                Ok(())
+            });
+        let locked_until_get = locked_until.clone();
+        db.expect_get_sc_locked_until()
+            .returning(move |_| Ok(*locked_until_get.lock().unwrap()));
+        let locked_until_update = locked_until.clone();
+        db.expect_update_locked_until()
+            .returning(move |_, time| {
+                *locked_until_update.lock().unwrap() = *time;
+                Ok(())
+            });
+
+        let mut sc_entity = test_sc_entity(db);
+        sc_entity.config.required_confirmation = 1;
+        sc_entity.config.reorg_depth = 3;
+        *sc_entity.chain.lock().unwrap() = Box::new(chain.clone());
+
+        // Still in the same block
+        sc_entity.verify_funding_confirmation(&statechain_id).unwrap();
+        assert!(confirmation.lock().unwrap().is_some());
+        assert_eq!(*locked_until.lock().unwrap(), initial_locked_until);
+
+        // Reorged out: statechain locked
+        chain.reorg(1);
+        sc_entity.verify_funding_confirmation(&statechain_id).unwrap();
+        let reorged = confirmation.lock().unwrap().clone().unwrap();
+        assert_eq!(reorged.block, None);
+        assert_eq!(reorged.locked_until, Some(initial_locked_until));
+        assert_eq!(*locked_until.lock().unwrap(), reorg_locked_until());
 
-        assert_eq!(rpc.get_block_count().unwrap(), 147 as u64);
+        // Confirmed again: lock restored
+        chain.mine_block();
+        sc_entity.verify_funding_confirmation(&statechain_id).unwrap();
+        let confirmed = confirmation.lock().unwrap().clone().unwrap();
+        assert!(confirmed.block.is_some());
+        assert_eq!(confirmed.locked_until, None);
+        assert_eq!(*locked_until.lock().unwrap(), initial_locked_until);
 
+        // Final: no longer tracked
+        chain.mine_block();
+        chain.mine_block();
+        sc_entity.verify_funding_confirmation(&statechain_id).unwrap();
+        assert!(confirmation.lock().unwrap().is_none());
     }
 }
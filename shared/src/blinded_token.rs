@@ -117,8 +117,13 @@ pub struct BSTSenderData {
 impl BSTSenderData {
     /// Generate new BSTSenderData for Swap
     pub fn setup() -> Self {
+        Self::from_key(FE::new_random())
+    }
+
+    /// Generate new BSTSenderData for a long lived signing key. k must not be reused, so new
+    /// BSTSenderData is required for each signature.
+    pub fn from_key(x: FE) -> Self {
         let p: GE = ECPoint::generator(); // gen
-        let x = FE::new_random(); // priv
         let q = p * x; //pub
         let (k, r_prime) = signer_gen_r_prime();
         BSTSenderData { x, q, k, r_prime }
@@ -305,4 +310,25 @@ mod tests {
 
         assert!(sender.verify_blind_spend_token(blind_spend_token).unwrap());
     }
+
+    #[test]
+    fn test_blind_sign_from_key() {
+        let x = FE::new_random();
+        let msg = "Message".to_string();
+        let sender = BSTSenderData::from_key(x);
+        let requestor = BSTRequestorData::setup(sender.r_prime, &msg).unwrap();
+        let blind_sig = sender.gen_blind_signature(requestor.e_prime);
+        let blind_spend_token =
+            requestor.make_blind_spend_token(requestor.unblind_signature(blind_sig));
+
+        // Verified by the same key with a different k
+        let verifier = BSTSenderData::from_key(x);
+        assert_ne!(verifier.r_prime, sender.r_prime);
+        assert!(verifier
+            .verify_blind_spend_token(blind_spend_token.clone())
+            .unwrap());
+        assert!(!BSTSenderData::setup()
+            .verify_blind_spend_token(blind_spend_token)
+            .unwrap());
+    }
 }
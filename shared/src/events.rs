@@ -0,0 +1,146 @@
+//! Events
+//!
+//! Events pushed by the state entity to subscribed clients. A client subscribes to topics, which
+//! are statechain, swap and batch IDs it is taking part in and proof keys it owns, with a
+//! subscription signed by the proof keys. It then receives swap phase changes, incoming transfer
+//! messages and batch results for those topics as they happen.
+
+use crate::error::SharedLibError;
+use crate::request_auth::RequestAuth;
+use crate::swap_data::SwapStatus;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use std::str::FromStr;
+use uuid::Uuid;
+
+type Result<T> = std::result::Result<T, SharedLibError>;
+
+/// Path signed by event subscriptions
+pub const EVENTS_SUBSCRIBE_PATH: &str = "events/subscribe";
+
+/// Event pushed to subscribers of a topic
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum StateEntityEvent {
+    /// Subscription accepted
+    Subscribed { topics: Vec<String> },
+    /// The statechain has been included in a swap
+    SwapStarted { statechain_id: Uuid, swap_id: Uuid },
+    /// The swap moved to a new phase, or was removed if None
+    SwapStatus {
+        swap_id: Uuid,
+        status: Option<SwapStatus>,
+    },
+    /// A transfer message for the statechain is available from transfer_get_msg
+    TransferMsg { statechain_id: Uuid },
+    /// The receiver completed their side of the transfer of the statechain
+    TransferReceived {
+        statechain_id: Uuid,
+        batch_id: Option<Uuid>,
+    },
+    /// All transfers of the batch are complete and have been finalized
+    TransferBatchFinalized { batch_id: Uuid },
+    /// The batch lifetime ended before all transfers completed
+    TransferBatchEnded {
+        batch_id: Uuid,
+        punished_state_chains: Vec<Uuid>,
+    },
+}
+
+/// Topics of an event subscription
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EventSubscription {
+    /// Proof keys signing the subscription. Events addressed to each key are received.
+    pub proof_keys: Vec<String>,
+    /// Statechain, swap and batch IDs. Each must involve a statechain owned by one of the keys.
+    pub ids: Vec<Uuid>,
+}
+
+/// Client -> State entity: first message of an event stream
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EventSubscribeMsg {
+    pub subscription: EventSubscription,
+    /// Comma separated signatures, one per proof key
+    pub signature: String,
+    pub timestamp: u64,
+    pub nonce: String,
+}
+
+impl EventSubscribeMsg {
+    /// Subscribe to ids and to events addressed to the public keys of keys, signed by keys
+    pub fn new(keys: &[SecretKey], ids: &[Uuid]) -> Result<Self> {
+        let secp = Secp256k1::new();
+        let subscription = EventSubscription {
+            proof_keys: keys
+                .iter()
+                .map(|key| PublicKey::from_secret_key(&secp, key).to_string())
+                .collect(),
+            ids: ids.to_vec(),
+        };
+        let auth =
+            RequestAuth::new_multi(keys, EVENTS_SUBSCRIBE_PATH, &serde_json::to_vec(&subscription)?)?;
+        Ok(EventSubscribeMsg {
+            subscription,
+            signature: auth.signature,
+            timestamp: auth.timestamp,
+            nonce: auth.nonce,
+        })
+    }
+
+    pub fn auth(&self) -> RequestAuth {
+        RequestAuth {
+            signature: self.signature.clone(),
+            timestamp: self.timestamp,
+            nonce: self.nonce.clone(),
+        }
+    }
+
+    /// Verify the subscription is signed by each of its proof keys. Returns the keys.
+    pub fn verify(&self) -> Result<Vec<PublicKey>> {
+        let keys = self
+            .subscription
+            .proof_keys
+            .iter()
+            .map(|key| PublicKey::from_str(key))
+            .collect::<std::result::Result<Vec<PublicKey>, _>>()?;
+        if keys.is_empty() {
+            return Err(SharedLibError::Generic(String::from(
+                "Event subscription has no proof keys",
+            )));
+        }
+        self.auth().verify_multi(
+            &keys,
+            EVENTS_SUBSCRIBE_PATH,
+            &serde_json::to_vec(&self.subscription)?,
+        )?;
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_subscribe_msg() {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let other_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let ids = vec![Uuid::new_v4()];
+
+        let msg = EventSubscribeMsg::new(&[key, other_key], &ids).unwrap();
+        let keys = msg.verify().unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(msg.subscription.proof_keys[0], keys[0].to_string());
+
+        // Survives serialization
+        let msg: EventSubscribeMsg =
+            serde_json::from_str(&serde_json::to_string(&msg).unwrap()).unwrap();
+        assert!(msg.verify().is_ok());
+
+        // Subscription cannot be changed
+        let mut changed = msg.clone();
+        changed.subscription.ids.push(Uuid::new_v4());
+        assert!(changed.verify().is_err());
+        let mut changed = msg.clone();
+        changed.subscription.proof_keys.pop();
+        assert!(changed.verify().is_err());
+    }
+}
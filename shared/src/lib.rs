@@ -32,8 +32,12 @@ pub mod mocks;
 pub mod blinded_token;
 pub mod commitment;
 pub mod ecies;
+pub mod events;
 pub mod error;
 pub mod mainstay;
+pub mod musig;
+pub mod pow;
+pub mod request_auth;
 pub mod state_chain;
 pub mod structs;
 pub mod swap_data;
@@ -363,7 +363,7 @@ impl Commitment {
 }
 
 //Mainstay configuration
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MainstayConfig {
     url: String,
     position: u64,
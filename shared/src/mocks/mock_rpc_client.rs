@@ -1,4 +1,6 @@
 // Mock bitcoin-rpc interface
+use bitcoin::Amount;
+use bitcoincore_rpc::json::{EstimateMode, EstimateSmartFeeResult};
 use bitcoincore_rpc::Error;
 extern crate hex;
 
@@ -23,4 +25,11 @@ impl MockBitcoinClient {
         }
         Ok("".to_string())
     }
+    pub fn estimate_smart_fee(&mut self, _conf_target: u16, _estimate_mode: Option<EstimateMode>) -> Result<EstimateSmartFeeResult,Error> {
+        Ok(EstimateSmartFeeResult {
+            fee_rate: Some(Amount::from_sat(10000)),
+            errors: None,
+            blocks: 6,
+        })
+    }
 }
@@ -0,0 +1,550 @@
+//! MuSig2
+//!
+//! Two-party MuSig2 Schnorr signing of Taproot key path spends. The shared key P = O + S is the
+//! sum of the owner and State Entity key shares, each proven with a DLogProof at key generation,
+//! and the statecoin is paid to the P2TR output key Q = P + t.G with no script tree.
+
+use super::Result;
+use crate::error::SharedLibError;
+
+use bitcoin::{
+    blockdata::{opcodes, script::Builder},
+    consensus::encode::serialize,
+    hashes::{sha256, Hash, HashEngine},
+    Network, Script, Transaction, TxOut,
+};
+use curv::{
+    arithmetic::traits::Converter,
+    elliptic::curves::traits::{ECPoint, ECScalar},
+    BigInt, FE, GE, PK,
+};
+
+/// Characters of the bech32 alphabet
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+/// BIP350: bech32m checksum constant
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+/// BIP340 tagged hash: sha256(sha256(tag) || sha256(tag) || data)
+pub fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash[..]);
+    engine.input(&tag_hash[..]);
+    engine.input(data);
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// Scalar of a 32 byte big endian integer, reduced modulo the curve order
+fn fe_from_bytes(bytes: &[u8]) -> FE {
+    ECScalar::from(&BigInt::from_hex(&hex::encode(bytes)))
+}
+
+/// 32 byte big endian encoding of a scalar
+pub fn fe_to_bytes(fe: &FE) -> [u8; 32] {
+    let vec = BigInt::to_vec(&fe.to_big_int());
+    let mut bytes = [0u8; 32];
+    bytes[32 - vec.len()..].copy_from_slice(&vec);
+    bytes
+}
+
+/// Additive inverse of a scalar
+pub fn fe_negate(fe: &FE) -> FE {
+    let minus_one: FE = ECScalar::from(&(FE::q() - BigInt::from(1)));
+    *fe * minus_one
+}
+
+fn fe_one() -> FE {
+    ECScalar::from(&BigInt::from(1))
+}
+
+fn fe_is_zero(fe: &FE) -> bool {
+    fe.to_big_int() == BigInt::from(0)
+}
+
+/// BIP340 x-only encoding of a point
+pub fn xonly(point: &GE) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&point.get_element().serialize()[1..]);
+    bytes
+}
+
+/// True if the Y coordinate of a point is even
+pub fn has_even_y(point: &GE) -> bool {
+    point.get_element().serialize()[0] == 0x02
+}
+
+/// The point with the given x coordinate and an even Y coordinate
+pub fn lift_x(x: &[u8]) -> Result<GE> {
+    if x.len() != 32 {
+        return Err(SharedLibError::FormatError(String::from(
+            "x-only public key must be 32 bytes.",
+        )));
+    }
+    let mut bytes = vec![0x02];
+    bytes.extend_from_slice(x);
+    GE::from_bytes(&bytes).map_err(|_| {
+        SharedLibError::FormatError(String::from("x-only public key is not on the curve."))
+    })
+}
+
+/// Point of a public key
+pub fn ge_from_pk(pk: &PK) -> Result<GE> {
+    GE::from_bytes(&pk.serialize())
+        .map_err(|_| SharedLibError::FormatError(String::from("Invalid public key.")))
+}
+
+/// 1 if the Y coordinate of a point is even, otherwise -1
+fn parity_coef(point: &GE) -> FE {
+    match has_even_y(point) {
+        true => fe_one(),
+        false => fe_negate(&fe_one()),
+    }
+}
+
+/// BIP341 key path only tweak of an internal key P: returns (t, Q) with t = H_TapTweak(P) and
+/// Q = lift_x(P) + t.G
+pub fn taproot_tweak(p: &GE) -> (FE, GE) {
+    let t = fe_from_bytes(&tagged_hash("TapTweak", &xonly(p)));
+    let g: GE = ECPoint::generator();
+    let q = *p * parity_coef(p) + g * t;
+    (t, q)
+}
+
+/// P2TR script pubkey of an output key Q: OP_1 <x-only Q>
+pub fn p2tr_script_pubkey(q: &GE) -> Script {
+    Builder::new()
+        .push_opcode(opcodes::all::OP_PUSHNUM_1)
+        .push_slice(&xonly(q))
+        .into_script()
+}
+
+/// P2TR script pubkey of the Taproot output of a shared key P
+pub fn shared_key_script_pubkey(p: &PK) -> Result<Script> {
+    let (_, q) = taproot_tweak(&ge_from_pk(p)?);
+    Ok(p2tr_script_pubkey(&q))
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GEN: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk: u32 = 1;
+    for value in values {
+        let top = chk >> 25;
+        chk = (chk & 0x1ffffff) << 5 ^ (*value as u32);
+        for (i, gen) in GEN.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= gen;
+            }
+        }
+    }
+    chk
+}
+
+/// Regroup 8 bit bytes into 5 bit groups, padding the last group with zeros
+fn to_base32(data: &[u8]) -> Vec<u8> {
+    let mut groups = vec![];
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        acc = (acc << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            groups.push(((acc >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        groups.push(((acc << (5 - bits)) & 31) as u8);
+    }
+    groups
+}
+
+/// BIP350 bech32m P2TR address of an output key Q. bitcoin 0.25 only encodes witness v0
+/// addresses, so the address is encoded here.
+pub fn p2tr_address(q: &GE, network: &String) -> Result<String> {
+    let hrp = match network
+        .parse::<Network>()
+        .map_err(|_| SharedLibError::FormatError(format!("Invalid network: {}", network)))?
+    {
+        Network::Bitcoin => "bc",
+        Network::Regtest => "bcrt",
+        _ => "tb",
+    };
+    let mut data = vec![1u8];
+    data.extend(to_base32(&xonly(q)));
+
+    let mut values: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|b| b & 31));
+    values.extend(&data);
+    values.extend(&[0u8; 6]);
+    let checksum = bech32_polymod(&values) ^ BECH32M_CONST;
+
+    let mut address = format!("{}1", hrp);
+    for group in data {
+        address.push(BECH32_CHARSET[group as usize] as char);
+    }
+    for i in 0..6 {
+        address.push(BECH32_CHARSET[((checksum >> (5 * (5 - i))) & 31) as usize] as char);
+    }
+    Ok(address)
+}
+
+/// BIP341 key path sighash, with SIGHASH_DEFAULT and no annex, of input input_index of tx.
+/// prevouts are the outputs spent by every input of tx.
+pub fn get_taproot_sighash(
+    tx: &Transaction,
+    input_index: usize,
+    prevouts: &Vec<TxOut>,
+) -> Result<[u8; 32]> {
+    if prevouts.len() != tx.input.len() || input_index >= tx.input.len() {
+        return Err(SharedLibError::FormatError(String::from(
+            "Taproot sighash requires the spent output of every input.",
+        )));
+    }
+    let sha = |data: Vec<u8>| sha256::Hash::hash(&data).into_inner();
+
+    let mut prevouts_data = vec![];
+    let mut sequences_data = vec![];
+    for input in &tx.input {
+        prevouts_data.extend(serialize(&input.previous_output));
+        sequences_data.extend(&input.sequence.to_le_bytes());
+    }
+    let mut amounts_data = vec![];
+    let mut script_pubkeys_data = vec![];
+    for prevout in prevouts {
+        amounts_data.extend(&prevout.value.to_le_bytes());
+        script_pubkeys_data.extend(serialize(&prevout.script_pubkey));
+    }
+    let mut outputs_data = vec![];
+    for output in &tx.output {
+        outputs_data.extend(serialize(output));
+    }
+
+    // Epoch 0 and hash type SIGHASH_DEFAULT
+    let mut msg = vec![0x00, 0x00];
+    msg.extend(&tx.version.to_le_bytes());
+    msg.extend(&tx.lock_time.to_le_bytes());
+    msg.extend(&sha(prevouts_data));
+    msg.extend(&sha(amounts_data));
+    msg.extend(&sha(script_pubkeys_data));
+    msg.extend(&sha(sequences_data));
+    msg.extend(&sha(outputs_data));
+    // Key path spend without annex
+    msg.push(0x00);
+    msg.extend(&(input_index as u32).to_le_bytes());
+    Ok(tagged_hash("TapSighash", &msg))
+}
+
+/// BIP341 sighash of input tx_index of a tx whose inputs spend the P2TR outputs of the shared
+/// keys input_addrs, with amounts input_amounts
+pub fn get_shared_key_taproot_sighash(
+    tx: &Transaction,
+    tx_index: &usize,
+    input_addrs: &Vec<PK>,
+    input_amounts: &Vec<u64>,
+) -> Result<[u8; 32]> {
+    if input_addrs.len() != input_amounts.len() {
+        return Err(SharedLibError::FormatError(String::from(
+            "Number of signing addresses != number of input amounts.",
+        )));
+    }
+    let mut prevouts = vec![];
+    for (input_addr, amount) in input_addrs.iter().zip(input_amounts.iter()) {
+        prevouts.push(TxOut {
+            value: *amount,
+            script_pubkey: shared_key_script_pubkey(input_addr)?,
+        });
+    }
+    get_taproot_sighash(tx, *tx_index, &prevouts)
+}
+
+/// BIP340 verification of a 64 byte Schnorr signature of msg by the x-only key of q
+pub fn schnorr_verify(sig: &[u8], q: &GE, msg: &[u8; 32]) -> bool {
+    if sig.len() != 64 {
+        return false;
+    }
+    let s_int = BigInt::from_hex(&hex::encode(&sig[32..]));
+    if s_int >= FE::q() {
+        return false;
+    }
+    let p = match lift_x(&xonly(q)) {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+    let s: FE = ECScalar::from(&s_int);
+    let mut data = sig[..32].to_vec();
+    data.extend(&xonly(&p));
+    data.extend(msg);
+    let e = fe_from_hash_data("BIP0340/challenge", &data);
+    if fe_is_zero(&s) || fe_is_zero(&e) {
+        return false;
+    }
+    // R = s.G - e.P
+    let g: GE = ECPoint::generator();
+    let sg = g * s;
+    let ep = p * e;
+    if sg == ep {
+        return false;
+    }
+    let r = sg + p * fe_negate(&e);
+    has_even_y(&r) && xonly(&r)[..] == sig[..32]
+}
+
+fn fe_from_hash_data(tag: &str, data: &[u8]) -> FE {
+    fe_from_bytes(&tagged_hash(tag, data))
+}
+
+/// Secret signing nonces of one party. Nonces must only be used for a single signature.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SecNonce {
+    pub k1: FE,
+    pub k2: FE,
+}
+
+/// Public signing nonces of one party
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PubNonce {
+    pub r1: GE,
+    pub r2: GE,
+}
+
+impl PubNonce {
+    /// Sum of the public nonces of both parties
+    pub fn aggregate(&self, other: &PubNonce) -> PubNonce {
+        PubNonce {
+            r1: self.r1 + other.r1,
+            r2: self.r2 + other.r2,
+        }
+    }
+}
+
+/// Generate a fresh pair of signing nonces
+pub fn nonce_gen() -> (SecNonce, PubNonce) {
+    let g: GE = ECPoint::generator();
+    let k1: FE = ECScalar::new_random();
+    let k2: FE = ECScalar::new_random();
+    (SecNonce { k1, k2 }, PubNonce { r1: g * k1, r2: g * k2 })
+}
+
+/// Values of a MuSig2 signing session common to both parties
+#[derive(Debug, Clone)]
+pub struct MuSigSession {
+    /// Taproot output key
+    pub q: GE,
+    /// Aggregate nonce point with even Y coordinate
+    pub r: GE,
+    /// Taproot tweak
+    t: FE,
+    /// Nonce coefficient b
+    b: FE,
+    /// Challenge e
+    e: FE,
+    /// Negates the nonces if the aggregate nonce has an odd Y coordinate
+    nonce_coef: FE,
+    /// Negates the key shares if the internal or output key has an odd Y coordinate
+    key_coef: FE,
+    /// Negates the tweak if the output key has an odd Y coordinate
+    tweak_coef: FE,
+}
+
+impl MuSigSession {
+    /// Session for signing msg with the shared key p and the aggregate nonce of both parties
+    pub fn new(p: &GE, agg_nonce: &PubNonce, msg: &[u8; 32]) -> Self {
+        let (t, q) = taproot_tweak(p);
+
+        let mut data = agg_nonce.r1.get_element().serialize().to_vec();
+        data.extend(&agg_nonce.r2.get_element().serialize()[..]);
+        data.extend(&xonly(&q));
+        data.extend(msg);
+        let b = fe_from_hash_data("MuSig/noncecoef", &data);
+
+        let r = agg_nonce.r1 + agg_nonce.r2 * b;
+        let mut data = xonly(&r).to_vec();
+        data.extend(&xonly(&q));
+        data.extend(msg);
+        let e = fe_from_hash_data("BIP0340/challenge", &data);
+
+        let tweak_coef = parity_coef(&q);
+        MuSigSession {
+            q,
+            r: r * parity_coef(&r),
+            t,
+            b,
+            e,
+            nonce_coef: parity_coef(&r),
+            key_coef: parity_coef(p) * tweak_coef,
+            tweak_coef,
+        }
+    }
+
+    /// Partial signature with the key share x
+    pub fn partial_sign(&self, sec_nonce: &SecNonce, x: &FE) -> FE {
+        (sec_nonce.k1 + sec_nonce.k2 * self.b) * self.nonce_coef + *x * self.e * self.key_coef
+    }
+
+    /// Verify a partial signature of the party with public nonces pub_nonce and public key share
+    /// x_pub
+    pub fn partial_verify(&self, pub_nonce: &PubNonce, partial_sig: &FE, x_pub: &GE) -> bool {
+        if fe_is_zero(partial_sig) {
+            return false;
+        }
+        let g: GE = ECPoint::generator();
+        let r = (pub_nonce.r1 + pub_nonce.r2 * self.b) * self.nonce_coef;
+        g * *partial_sig == r + *x_pub * (self.e * self.key_coef)
+    }
+
+    /// 64 byte BIP340 signature from the partial signatures of both parties
+    pub fn aggregate(&self, partial_sigs: &[FE]) -> Vec<u8> {
+        let mut s = self.e * self.t * self.tweak_coef;
+        for partial_sig in partial_sigs {
+            s = s + *partial_sig;
+        }
+        let mut sig = xonly(&self.r).to_vec();
+        sig.extend(&fe_to_bytes(&s));
+        sig
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_p2tr_address() {
+        // BIP350 test vector: the output key is the x coordinate of G
+        let g: GE = ECPoint::generator();
+        assert_eq!(
+            hex::encode(p2tr_script_pubkey(&g).as_bytes()),
+            "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+        );
+        assert_eq!(
+            p2tr_address(&g, &String::from("bitcoin")).unwrap(),
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+        );
+    }
+
+    #[test]
+    fn test_taproot_tweak() {
+        // BIP86 test vector m/86'/0'/0'/0/0
+        let p = lift_x(
+            &hex::decode("cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115")
+                .unwrap(),
+        )
+        .unwrap();
+        let (_, q) = taproot_tweak(&p);
+        assert_eq!(
+            hex::encode(xonly(&q)),
+            "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c"
+        );
+        assert_eq!(
+            p2tr_address(&q, &String::from("bitcoin")).unwrap(),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+        // The tweak only depends on the x coordinate of the internal key
+        let (_, q_neg) = taproot_tweak(&(p * fe_negate(&fe_one())));
+        assert_eq!(q, q_neg);
+    }
+
+    #[test]
+    fn test_schnorr_verify() {
+        // BIP340 test vector 0
+        let g: GE = ECPoint::generator();
+        let three: FE = ECScalar::from(&BigInt::from(3));
+        let q = g * three;
+        let msg = [0u8; 32];
+        let mut sig = hex::decode(
+            "e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca8215\
+             25f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0",
+        )
+        .unwrap();
+        assert!(schnorr_verify(&sig, &q, &msg));
+        assert!(!schnorr_verify(&sig, &q, &[1u8; 32]));
+        sig[63] ^= 1;
+        assert!(!schnorr_verify(&sig, &q, &msg));
+    }
+
+    #[test]
+    fn test_musig_sign() {
+        let g: GE = ECPoint::generator();
+        for _ in 0..8 {
+            // Key shares of the owner and State Entity, and the shared key
+            let o1: FE = ECScalar::new_random();
+            let s1: FE = ECScalar::new_random();
+            let p = g * o1 + g * s1;
+            let msg = tagged_hash("test", &fe_to_bytes(&o1));
+
+            let (o_sec_nonce, o_pub_nonce) = nonce_gen();
+            let (s_sec_nonce, s_pub_nonce) = nonce_gen();
+            let session = MuSigSession::new(&p, &o_pub_nonce.aggregate(&s_pub_nonce), &msg);
+
+            let s_o = session.partial_sign(&o_sec_nonce, &o1);
+            let s_s = session.partial_sign(&s_sec_nonce, &s1);
+            assert!(session.partial_verify(&o_pub_nonce, &s_o, &(g * o1)));
+            assert!(session.partial_verify(&s_pub_nonce, &s_s, &(g * s1)));
+            assert!(!session.partial_verify(&o_pub_nonce, &s_s, &(g * o1)));
+
+            let sig = session.aggregate(&[s_o, s_s]);
+            assert!(schnorr_verify(&sig, &session.q, &msg));
+            assert!(!schnorr_verify(&sig, &session.q, &[0u8; 32]));
+
+            // Additive key update on transfer keeps the shared key
+            let x1: FE = ECScalar::new_random();
+            let o2: FE = ECScalar::new_random();
+            let t2 = o1 + x1 + fe_negate(&o2);
+            let s2 = s1 + t2 + fe_negate(&x1);
+            assert_eq!(g * o2 + g * s2, p);
+        }
+    }
+
+    #[test]
+    fn test_get_taproot_sighash() {
+        let g: GE = ECPoint::generator();
+        let tx: Transaction = serde_json::from_str("{\"version\":2,\"lock_time\":0,\"input\":[{\"previous_output\":\"faaaa0920fbaefae9c98a57cdace0deffa96cc64a651851bdd167f397117397c:0\",\"script_sig\":\"\",\"sequence\":4294967295,\"witness\":[]}],\"output\":[{\"value\":9000,\"script_pubkey\":\"00148fc32525487d2cb7323c960bdfb0a5ee6a364738\"}]}").unwrap();
+        let prevout = TxOut {
+            value: 10000,
+            script_pubkey: p2tr_script_pubkey(&g),
+        };
+        let sighash = get_taproot_sighash(&tx, 0, &vec![prevout.clone()]).unwrap();
+
+        // The sighash commits to the spent amount and script pubkey
+        let mut other_amount = prevout.clone();
+        other_amount.value = 0;
+        assert_ne!(sighash, get_taproot_sighash(&tx, 0, &vec![other_amount]).unwrap());
+        let mut other_script = prevout.clone();
+        other_script.script_pubkey = p2tr_script_pubkey(&(g + g));
+        assert_ne!(sighash, get_taproot_sighash(&tx, 0, &vec![other_script]).unwrap());
+
+        assert!(get_taproot_sighash(&tx, 1, &vec![prevout.clone()]).is_err());
+        assert!(get_taproot_sighash(&tx, 0, &vec![]).is_err());
+    }
+
+    #[test]
+    fn test_get_taproot_sighash_bip341_vectors() {
+        // BIP341 wallet test vectors: keyPathSpending input 4, the SIGHASH_DEFAULT spend
+        // without annex
+        let tx: Transaction = bitcoin::consensus::encode::deserialize(&hex::decode("02000000097de20cbff686da83a54981d2b9bab3586f4ca7e48f57f5b55963115f3b334e9c010000000000000000d7b7cab57b1393ace2d064f4d4a2cb8af6def61273e127517d44759b6dafdd990000000000fffffffff8e1f583384333689228c5d28eac13366be082dc57441760d957275419a418420000000000fffffffff0689180aa63b30cb162a73c6d2a38b7eeda2a83ece74310fda0843ad604853b0100000000feffffffaa5202bdf6d8ccd2ee0f0202afbbb7461d9264a25e5bfd3c5a52ee1239e0ba6c0000000000feffffff956149bdc66faa968eb2be2d2faa29718acbfe3941215893a2a3446d32acd050000000000000000000e664b9773b88c09c32cb70a2a3e4da0ced63b7ba3b22f848531bbb1d5d5f4c94010000000000000000e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7eadfd4eabf0000000000ffffffffa778eb6a263dc090464cd125c466b5a99667720b1c110468831d058aa1b82af10100000000ffffffff0200ca9a3b000000001976a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac807840cb0000000020ac9a87f5594be208f8532db38cff670c450ed2fea8fcdefcc9a663f78bab962b0065cd1d").unwrap()).unwrap();
+        let utxos_spent = vec![
+            ("512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343", 420000000),
+            ("5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3", 462000000),
+            ("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac", 294000000),
+            ("5120e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e", 504000000),
+            ("512091b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605", 630000000),
+            ("00147dd65592d0ab2fe0d0257d571abf032cd9db93dc", 378000000),
+            ("512075169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831", 672000000),
+            ("5120712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5", 546000000),
+            ("512077e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220", 588000000),
+        ];
+        let prevouts: Vec<TxOut> = utxos_spent
+            .iter()
+            .map(|(script_pubkey, amount)| TxOut {
+                value: *amount,
+                script_pubkey: Script::from(hex::decode(script_pubkey).unwrap()),
+            })
+            .collect();
+
+        assert_eq!(
+            hex::encode(get_taproot_sighash(&tx, 4, &prevouts).unwrap()),
+            "4f900a0bae3f1446fd48490c2958b5a023228f01661cda3496a11da502a7f7ef"
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! Proof of Work
+//!
+//! Hashcash style proof of work. A solution to a challenge is a nonce for which the sha256 hash
+//! of the challenge, the data the solution is bound to and the nonce has at least difficulty
+//! leading zero bits.
+
+use bitcoin::hashes::{sha256, Hash};
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in bytes {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Check nonce solves challenge for data
+pub fn verify(challenge: &str, data: &str, nonce: u64, difficulty: u32) -> bool {
+    let hash = sha256::Hash::hash(format!("{}:{}:{}", challenge, data, nonce).as_bytes());
+    leading_zero_bits(&hash.into_inner()) >= difficulty
+}
+
+/// Find a nonce solving challenge for data. Takes 2^difficulty hashes on average.
+pub fn solve(challenge: &str, data: &str, difficulty: u32) -> u64 {
+    let mut nonce = 0;
+    while !verify(challenge, data, nonce, difficulty) {
+        nonce += 1;
+    }
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pow() {
+        assert_eq!(leading_zero_bits(&[0, 0x1f, 0xff]), 11);
+        assert_eq!(leading_zero_bits(&[0, 0]), 16);
+
+        let nonce = solve("challenge", "proof key", 16);
+        assert!(verify("challenge", "proof key", nonce, 16));
+        assert!(verify("challenge", "proof key", nonce, 0));
+        // Solutions are bound to the challenge and data
+        assert!(!verify("other challenge", "proof key", nonce, 16));
+        assert!(!verify("challenge", "other proof key", nonce, 16));
+    }
+}
@@ -0,0 +1,364 @@
+//! Request Auth
+//!
+//! Signed request authentication, used for state entity requests to the lockbox and the
+//! lockbox's responses, for user requests within a session, which are signed by the session's
+//! proof key, and for operator requests to the admin API. The signer signs the path and body of
+//! each request together with a timestamp and a random nonce. The receiver rejects requests with
+//! an invalid signature, an expired timestamp or a nonce it has already seen.
+
+use crate::error::SharedLibError;
+use crate::structs::{
+    DepositBatchMsg, DepositMsg2, KeyGenMsg1, KeyGenMsg2, MuSigKeyGenMsg, MuSigSignMsg1,
+    MuSigSignMsg2, PrepareSignTxMsg, RefreshMsg1, RefreshMsg2, SignMsg1, SignMsg2,
+    TransferCancelMsg, TransferMsg1, TransferMsg3, WithdrawMsg1, WithdrawMsg2,
+};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey, Signature};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+type Result<T> = std::result::Result<T, SharedLibError>;
+
+/// Names of the headers carrying request authentication data
+pub struct AuthHeaders {
+    pub signature: &'static str,
+    pub timestamp: &'static str,
+    pub nonce: &'static str,
+}
+
+/// Headers of state entity requests to the lockbox
+pub const LOCKBOX_HEADERS: AuthHeaders = AuthHeaders {
+    signature: "X-Lockbox-Signature",
+    timestamp: "X-Lockbox-Timestamp",
+    nonce: "X-Lockbox-Nonce",
+};
+
+/// Headers of lockbox responses, signed by the lockbox key
+pub const LOCKBOX_RESPONSE_HEADERS: AuthHeaders = AuthHeaders {
+    signature: "X-Lockbox-Response-Signature",
+    timestamp: "X-Lockbox-Response-Timestamp",
+    nonce: "X-Lockbox-Response-Nonce",
+};
+
+/// Headers of user requests signed by session proof keys
+pub const SESSION_HEADERS: AuthHeaders = AuthHeaders {
+    signature: "X-Session-Signature",
+    timestamp: "X-Session-Timestamp",
+    nonce: "X-Session-Nonce",
+};
+
+/// Headers of operator requests to the admin API
+pub const ADMIN_HEADERS: AuthHeaders = AuthHeaders {
+    signature: "X-Admin-Signature",
+    timestamp: "X-Admin-Timestamp",
+    nonce: "X-Admin-Nonce",
+};
+
+/// Maximum difference (seconds) between a request's timestamp and the lockbox's clock
+pub const MAX_REQUEST_AGE: u64 = 60;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Shared key IDs of the sessions a request is made in, read from its shared_key_id or
+/// shared_key_ids field. A body with both fields is ambiguous and has no session IDs.
+pub fn session_ids(body: &serde_json::Value) -> Vec<Uuid> {
+    let ids = match (body.get("shared_key_id"), body.get("shared_key_ids")) {
+        (Some(id), None) => vec![id],
+        (None, Some(serde_json::Value::Array(ids))) => ids.iter().collect(),
+        _ => vec![],
+    };
+    ids.iter()
+        .filter_map(|id| id.as_str().and_then(|id| Uuid::parse_str(id).ok()))
+        .collect()
+}
+
+/// A request made within one or more sessions. The State Entity authenticates the request
+/// against the sessions returned by the deserialized message, which are the sessions its
+/// handler acts on.
+pub trait SessionRequest {
+    /// Shared key IDs of the sessions the request is made in
+    fn session_ids(&self) -> Vec<Uuid>;
+}
+
+macro_rules! impl_session_request {
+    ($($msg:ty),*) => {
+        $(impl SessionRequest for $msg {
+            fn session_ids(&self) -> Vec<Uuid> {
+                vec![self.shared_key_id]
+            }
+        })*
+    };
+}
+
+impl_session_request!(
+    KeyGenMsg1,
+    KeyGenMsg2,
+    SignMsg1,
+    SignMsg2,
+    MuSigKeyGenMsg,
+    MuSigSignMsg1,
+    MuSigSignMsg2,
+    PrepareSignTxMsg,
+    DepositMsg2,
+    TransferMsg1,
+    TransferCancelMsg,
+    TransferMsg3,
+    RefreshMsg1,
+    RefreshMsg2
+);
+
+impl SessionRequest for DepositBatchMsg {
+    fn session_ids(&self) -> Vec<Uuid> {
+        self.shared_key_ids.clone()
+    }
+}
+
+impl SessionRequest for WithdrawMsg1 {
+    fn session_ids(&self) -> Vec<Uuid> {
+        self.shared_key_ids.clone()
+    }
+}
+
+impl SessionRequest for WithdrawMsg2 {
+    fn session_ids(&self) -> Vec<Uuid> {
+        self.shared_key_ids.clone()
+    }
+}
+
+/// Authentication data sent in the headers of a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestAuth {
+    /// Comma separated signatures, one per signing key
+    pub signature: String,
+    pub timestamp: u64,
+    pub nonce: String,
+}
+
+impl RequestAuth {
+    /// Create message to be signed
+    fn to_message(path: &str, timestamp: u64, nonce: &str, body: &[u8]) -> Result<Message> {
+        let mut data =
+            format!("{}:{}:{}:", path.trim_start_matches('/'), timestamp, nonce).into_bytes();
+        data.extend_from_slice(body);
+        let hash = sha256::Hash::hash(&data);
+        Ok(Message::from_slice(&hash)?)
+    }
+
+    /// Sign a request to path
+    pub fn new(key: &SecretKey, path: &str, body: &[u8]) -> Result<Self> {
+        Self::new_multi(std::slice::from_ref(key), path, body)
+    }
+
+    /// Sign a request to path with several keys
+    pub fn new_multi(keys: &[SecretKey], path: &str, body: &[u8]) -> Result<Self> {
+        let timestamp = now();
+        let nonce = hex::encode(rand::random::<[u8; 16]>());
+        let message = Self::to_message(path, timestamp, &nonce, body)?;
+        let secp = Secp256k1::new();
+        let sigs: Vec<String> = keys
+            .iter()
+            .map(|key| secp.sign(&message, key).to_string())
+            .collect();
+        Ok(RequestAuth {
+            signature: sigs.join(","),
+            timestamp,
+            nonce,
+        })
+    }
+
+    // A response is signed with the path and nonce of the request, so that it cannot be replayed
+    // as the response to another request
+    fn response_path(path: &str, request_nonce: &str) -> String {
+        format!("{}#{}", path.trim_start_matches('/'), request_nonce)
+    }
+
+    /// Sign the response to a request to path with nonce request_nonce
+    pub fn new_response(
+        key: &SecretKey,
+        path: &str,
+        request_nonce: &str,
+        body: &[u8],
+    ) -> Result<Self> {
+        Self::new(key, &Self::response_path(path, request_nonce), body)
+    }
+
+    /// Verify the signature of the response to a request to path with nonce request_nonce
+    pub fn verify_response(
+        &self,
+        key: &PublicKey,
+        path: &str,
+        request_nonce: &str,
+        body: &[u8],
+    ) -> Result<()> {
+        self.verify(key, &Self::response_path(path, request_nonce), body)
+    }
+
+    /// Read authentication headers. Returns None if any are missing.
+    pub fn from_headers(
+        signature: Option<&str>,
+        timestamp: Option<&str>,
+        nonce: Option<&str>,
+    ) -> Result<Option<Self>> {
+        match (signature, timestamp, nonce) {
+            (Some(signature), Some(timestamp), Some(nonce)) => Ok(Some(RequestAuth {
+                signature: signature.to_string(),
+                timestamp: timestamp.parse::<u64>().map_err(|e| {
+                    SharedLibError::FormatError(format!("Invalid request timestamp: {}", e))
+                })?,
+                nonce: nonce.to_string(),
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// Verify the signature of a request to path
+    pub fn verify(&self, key: &PublicKey, path: &str, body: &[u8]) -> Result<()> {
+        self.verify_multi(std::slice::from_ref(key), path, body)
+    }
+
+    /// Verify a request to path has a signature by each key, in order
+    pub fn verify_multi(&self, keys: &[PublicKey], path: &str, body: &[u8]) -> Result<()> {
+        let sigs: Vec<&str> = self.signature.split(',').collect();
+        if sigs.len() != keys.len() {
+            return Err(SharedLibError::Generic(format!(
+                "Expected {} request signatures, found {}",
+                keys.len(),
+                sigs.len()
+            )));
+        }
+        let message = Self::to_message(path, self.timestamp, &self.nonce, body)?;
+        let secp = Secp256k1::new();
+        for (sig, key) in sigs.iter().zip(keys.iter()) {
+            secp.verify(&message, &Signature::from_str(sig)?, key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Nonces of requests accepted within the last MAX_REQUEST_AGE seconds
+#[derive(Debug, Default)]
+pub struct ReplayGuard {
+    nonces: HashMap<String, u64>,
+}
+
+impl ReplayGuard {
+    /// Check that a request is recent and has not been seen before, and record its nonce
+    pub fn check(&mut self, auth: &RequestAuth) -> Result<()> {
+        let now = now();
+        if auth.timestamp + MAX_REQUEST_AGE < now || auth.timestamp > now + MAX_REQUEST_AGE {
+            return Err(SharedLibError::Generic(format!(
+                "Request timestamp {} expired",
+                auth.timestamp
+            )));
+        }
+        // Older nonces are rejected by their timestamp
+        self.nonces
+            .retain(|_, timestamp| *timestamp + MAX_REQUEST_AGE >= now);
+        if self.nonces.contains_key(&auth.nonce) {
+            return Err(SharedLibError::Generic(format!(
+                "Request nonce {} replayed",
+                auth.nonce
+            )));
+        }
+        self.nonces.insert(auth.nonce.clone(), auth.timestamp);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_ids() {
+        let id = Uuid::new_v4();
+        let id2 = Uuid::new_v4();
+        assert_eq!(session_ids(&serde_json::json!({ "shared_key_id": id })), vec![id]);
+        assert_eq!(
+            session_ids(&serde_json::json!({ "shared_key_ids": [id, id2], "address": "" })),
+            vec![id, id2]
+        );
+        assert!(session_ids(&serde_json::json!({ "proof_key": "" })).is_empty());
+        // Ambiguous bodies name no sessions
+        assert!(session_ids(&serde_json::json!({ "shared_key_id": id, "shared_key_ids": [id2] }))
+            .is_empty());
+
+        // Typed messages return the sessions their handlers act on
+        let withdraw_msg2: WithdrawMsg2 = serde_json::from_value(
+            serde_json::json!({ "shared_key_id": id, "shared_key_ids": [id2] }),
+        )
+        .unwrap();
+        assert_eq!(SessionRequest::session_ids(&withdraw_msg2), vec![id2]);
+        let deposit_msg2 = DepositMsg2 { shared_key_id: id };
+        assert_eq!(SessionRequest::session_ids(&deposit_msg2), vec![id]);
+    }
+
+    #[test]
+    fn test_request_auth() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[0xcd; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&secp, &key);
+        let body = b"{\"shared_key_id\":\"001203c9-93f0-46f9-abda-0678c891b2d3\"}";
+
+        let auth = RequestAuth::new(&key, "ecdsa/sign/first", body).unwrap();
+        assert!(auth.verify(&pubkey, "/ecdsa/sign/first", body).is_ok());
+        // Signature covers path and body
+        assert!(auth.verify(&pubkey, "ecdsa/sign/second", body).is_err());
+        assert!(auth.verify(&pubkey, "ecdsa/sign/first", b"{}").is_err());
+        let other_key = SecretKey::from_slice(&[0xab; 32]).unwrap();
+        assert!(auth
+            .verify(&PublicKey::from_secret_key(&secp, &other_key), "ecdsa/sign/first", body)
+            .is_err());
+
+        let timestamp = auth.timestamp.to_string();
+        let from_headers = RequestAuth::from_headers(
+            Some(&auth.signature),
+            Some(&timestamp),
+            Some(&auth.nonce),
+        )
+        .unwrap();
+        assert_eq!(from_headers, Some(auth.clone()));
+        assert_eq!(
+            RequestAuth::from_headers(None, Some(&timestamp), Some(&auth.nonce)).unwrap(),
+            None
+        );
+
+        // One signature per key
+        let auth_multi = RequestAuth::new_multi(&[key, other_key], "withdraw/init", body).unwrap();
+        let other_pubkey = PublicKey::from_secret_key(&secp, &other_key);
+        assert!(auth_multi.verify_multi(&[pubkey, other_pubkey], "withdraw/init", body).is_ok());
+        assert!(auth_multi.verify_multi(&[other_pubkey, pubkey], "withdraw/init", body).is_err());
+        assert!(auth_multi.verify(&pubkey, "withdraw/init", body).is_err());
+
+        // Responses are bound to the request's path and nonce
+        let response = RequestAuth::new_response(&key, "/ecdsa/sign/first", &auth.nonce, b"[1]")
+            .unwrap();
+        assert!(response
+            .verify_response(&pubkey, "ecdsa/sign/first", &auth.nonce, b"[1]")
+            .is_ok());
+        assert!(response
+            .verify_response(&pubkey, "ecdsa/sign/first", &auth_multi.nonce, b"[1]")
+            .is_err());
+        assert!(response
+            .verify_response(&pubkey, "ecdsa/sign/second", &auth.nonce, b"[1]")
+            .is_err());
+        assert!(response
+            .verify_response(&pubkey, "ecdsa/sign/first", &auth.nonce, b"[2]")
+            .is_err());
+
+        // Replayed and expired requests are rejected
+        let mut replay_guard = ReplayGuard::default();
+        assert!(replay_guard.check(&auth).is_ok());
+        assert!(replay_guard.check(&auth).is_err());
+        let mut expired = RequestAuth::new(&key, "ecdsa/sign/first", body).unwrap();
+        expired.timestamp -= MAX_REQUEST_AGE + 1;
+        assert!(replay_guard.check(&expired).is_err());
+    }
+}
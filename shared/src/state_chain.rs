@@ -17,6 +17,7 @@ use crate::Verifiable;
 use bitcoin::{
     hashes::{sha256, Hash},
     secp256k1::{Message, PublicKey, Secp256k1, SecretKey, Signature},
+    OutPoint,
 };
 use monotree::{
     hasher::{Blake3, Hasher},
@@ -134,8 +135,8 @@ impl State {
 #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone, Default, Hash, Eq)]
 #[schemars(example = "Self::example")]
 pub struct StateChainSig {
-    /// Purpose: "TRANSFER", "TRANSFER-BATCH" or "WITHDRAW"
-    pub purpose: String, // "TRANSFER", "TRANSFER-BATCH" or "WITHDRAW"
+    /// Purpose: "TRANSFER", "TRANSFER-BATCH", "WITHDRAW", "REFRESH" or "CANCEL"
+    pub purpose: String, // "TRANSFER", "TRANSFER-BATCH", "WITHDRAW", "REFRESH" or "CANCEL"
     /// The new owner proof public key (if transfer) or address (if withdrawal)    
     pub data: String,    // proof key, state chain id or address
     /// Current owner signature (DER encoded). 
@@ -205,6 +206,16 @@ impl StateChainSig {
     }
 }
 
+/// Sparse Merkle Tree key of the statecoin funded by outpoint. Statecoins at vout 0 are keyed
+/// by their funding txid. Statecoins at other outputs of the same funding tx are keyed by the
+/// hash of their outpoint so that they do not collide.
+pub fn smt_key(outpoint: &OutPoint) -> String {
+    if outpoint.vout == 0 {
+        return outpoint.txid.to_string();
+    }
+    sha256::Hash::hash(outpoint.to_string().as_bytes()).to_string()
+}
+
 /// Insert new statechain entry into Sparse Merkle Tree and return proof
 pub fn update_statechain_smt<D: monotree::database::Database>(
     tree: Arc<Mutex<Monotree<D, Blake3>>>,
@@ -353,4 +364,17 @@ mod tests {
                 .unwrap();
         assert!(verify_statechain_smt(&root, &proof_key, &sc_smt_proof2));
     }
+
+    #[test]
+    fn test_smt_key() {
+        let txid = bitcoin::Txid::from_str(
+            "c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e",
+        )
+        .unwrap();
+        let key_0 = smt_key(&OutPoint { txid, vout: 0 });
+        let key_1 = smt_key(&OutPoint { txid, vout: 1 });
+        assert_eq!(key_0, txid.to_string());
+        assert_ne!(key_0[..32], key_1[..32]);
+        assert_eq!(key_1.len(), 64);
+    }
 }
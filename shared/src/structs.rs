@@ -10,11 +10,15 @@ use kms::ecdsa::two_party::{party1,party2};
 use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one,party_two};
 
 use bitcoin::{secp256k1::PublicKey, Address};
+use chrono::NaiveDateTime;
 use std::{collections::HashSet, fmt};
 use uuid::Uuid;
 use rocket_okapi::JsonSchema;
 use schemars;
 
+use crate::blinded_token::BlindedSpendToken;
+use crate::musig::PubNonce;
+use crate::error::SharedLibError;
 use crate::ecies;
 use crate::{util::transaction_serialise, ecies::{Encryptable, SelfEncryptable, WalletDecryptable}};
 
@@ -24,6 +28,9 @@ pub enum Protocol {
     Deposit,
     Transfer,
     Withdraw,
+    KickOff,
+    Refresh,
+    RefreshBackup,
 }
 
 // API structs
@@ -92,6 +99,9 @@ impl FESer {
 pub struct StateEntityFeeInfoAPI {
     /// The Bitcoin address that the SE fee must be paid to
     pub address: String, // Receive address for fee payments
+    /// The Bitcoin address that the SE fee output of backup transactions must pay, which the SE
+    /// spends to fee bump broadcast backup transactions
+    pub backup_fee_address: String,
     /// The deposit fee, which is specified as a proportion of the deposit amount in basis points
     pub deposit: u64,    // basis points
     /// The withdrawal fee, which is specified as a proportion of the deposit amount in basis points
@@ -100,16 +110,21 @@ pub struct StateEntityFeeInfoAPI {
     pub interval: u32,   // locktime decrement interval in blocks
     /// The initial nLocktime from the current blockheight for the first backup
     pub initlock: u32,   // inital backup locktime
+    /// Backup transactions spend a kick-off transaction with a relative (nSequence) locktime.
+    /// interval and initlock are then numbers of blocks after the kick-off transaction confirms.
+    pub relative_locktime: bool,
 }
 
 impl StateEntityFeeInfoAPI{
     pub fn example() -> Self{
         Self{
             address: "bc1qzvv6yfeg0navfkrxpqc0fjdsu9ey4qgqqsarq4".to_string(),
+            backup_fee_address: "bc1qzvv6yfeg0navfkrxpqc0fjdsu9ey4qgqqsarq4".to_string(),
             deposit: 0,
             withdraw: 300,
             interval: 144,
             initlock: 14400,
+            relative_locktime: false,
         }
     }
 }
@@ -118,8 +133,8 @@ impl fmt::Display for StateEntityFeeInfoAPI {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Fee address: {},\nDeposit fee rate: {}\nWithdrawal fee rate: {}\nLock interval: {}\nInitial lock: {}",
-            self.address, self.deposit, self.withdraw, self.interval, self.initlock
+            "Fee address: {},\nBackup fee address: {},\nDeposit fee rate: {}\nWithdrawal fee rate: {}\nLock interval: {}\nInitial lock: {}\nRelative locktime: {}",
+            self.address, self.backup_fee_address, self.deposit, self.withdraw, self.interval, self.initlock, self.relative_locktime
         )
     }
 }
@@ -158,8 +173,14 @@ pub struct StateChainDataAPI {
     pub amount: u64,
     /// The statechain of owner proof keys and signatures
     pub chain: Vec<State>,
-    /// The current owner nLocktime
+    /// The current owner nLocktime, or relative locktime in blocks if backup txs spend a kick-off tx
     pub locktime: u32,  // the curent owner nlocktime
+    /// Hex encoding of the signed kick-off transaction if backup txs spend a kick-off tx
+    pub tx_kickoff_hex: Option<String>,
+    /// Backup tx locktime of a cancelled or expired transfer if lower than locktime. The next
+    /// transfer backup tx locktime is decremented from it.
+    #[serde(default)]
+    pub aborted_locktime: Option<u32>,
 }
 
 impl StateChainDataAPI {
@@ -169,10 +190,101 @@ impl StateChainDataAPI {
             amount: 1000000,
             chain: vec![State::example()],
             locktime: 712903,
+            tx_kickoff_hex: None,
+            aborted_locktime: None,
         }
     }
 }
 
+/// /info/statechain/closure return struct. A statechain is closed when a backup tx spending
+/// its funding output confirms.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct StateChainClosureAPI {
+    /// Txid of the confirmed backup tx
+    pub txid: String,
+    /// Proof key of the owner the backup tx was signed for
+    pub proof_key: String,
+    /// True if the backup tx was the current owner's
+    pub current_owner: bool,
+    /// Block height at which the closure was detected
+    pub height: u64,
+}
+
+/// Status of a statechain listed by the explorer
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+pub enum StateChainStatus {
+    /// Owned and available for transfer
+    Active,
+    /// Locked after a failed swap or transfer batch
+    Locked,
+    /// Registered for or taking part in a swap
+    InSwap,
+    /// Withdrawn by its owner
+    Withdrawn,
+    /// Closed by a confirmed backup tx
+    ClosedByBackup,
+}
+
+impl StateChainStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StateChainStatus::Active => "active",
+            StateChainStatus::Locked => "locked",
+            StateChainStatus::InSwap => "in-swap",
+            StateChainStatus::Withdrawn => "withdrawn",
+            StateChainStatus::ClosedByBackup => "closed-by-backup",
+        }
+    }
+}
+
+impl FromStr for StateChainStatus {
+    type Err = SharedLibError;
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "active" => Ok(StateChainStatus::Active),
+            "locked" => Ok(StateChainStatus::Locked),
+            "in-swap" => Ok(StateChainStatus::InSwap),
+            "withdrawn" => Ok(StateChainStatus::Withdrawn),
+            "closed-by-backup" => Ok(StateChainStatus::ClosedByBackup),
+            _ => Err(SharedLibError::FormatError(format!(
+                "Unknown statechain status: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// /explorer/statechain return struct. Summary of a statechain.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct StateChainSummaryAPI {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    /// The value of the statecoin (in satoshis)
+    pub amount: u64,
+    pub status: StateChainStatus,
+    /// Proof key of the current owner, or withdrawal address once withdrawn
+    pub proof_key: String,
+    /// Txid of the deposit or latest refresh tx
+    pub funding_txid: Option<String>,
+    /// Number of transfers of the statechain
+    pub transfer_count: u64,
+    /// The current owner nLocktime, or relative locktime in blocks if backup txs spend a kick-off tx
+    pub locktime: u32,
+    /// Blocks until the current owner's backup tx can be broadcast. None once withdrawn or closed.
+    pub blocks_until_locktime: Option<u32>,
+}
+
+/// /explorer/statechains return struct. A page of statechains.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct StateChainListAPI {
+    pub statechains: Vec<StateChainSummaryAPI>,
+    /// Page number, from 0
+    pub page: u64,
+    pub per_page: u64,
+    /// Number of statechains matching the query across all pages
+    pub total: u64,
+}
+
 /// /info/transfer-batch return struct
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct TransferBatchDataAPI {
@@ -195,7 +307,9 @@ pub struct PKDef(Vec<u8>);
 // PrepareSignTx structs
 
 /// Struct contains data necessary to caluculate backup tx's input sighash('s). This is required
-/// by Server before co-signing is performed for validation of tx.
+/// by Server before co-signing is performed for validation of tx. Withdraw txs may have any
+/// outputs so long as they include the State Entity fee output and do not spend more than the
+/// input amounts.
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct PrepareSignTxMsg {
     /// The shared key ID
@@ -340,6 +454,76 @@ pub struct SignSecondMsgRequest {
     pub party_two_sign_message: party2::SignMessage,
 }
 
+// MuSig2 co-signing algorithm structs
+
+#[derive(JsonSchema)]
+#[schemars(remote = "PubNonce")]
+pub struct PubNonceDef(String);
+
+/// Type of the shared key of a statecoin
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    /// 2P-ECDSA shared key with a P2WPKH funding output
+    Ecdsa,
+    /// MuSig2 shared key with a P2TR key path funding output
+    Taproot,
+}
+
+impl Default for KeyType {
+    fn default() -> Self {
+        KeyType::Ecdsa
+    }
+}
+
+/// Owner -> State Entity
+/// Owner key share O = o.G with its proof of knowledge. The shared key is P = O + S.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct MuSigKeyGenMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    pub protocol: Protocol,
+    #[schemars(with = "DLogProofDef")]
+    pub dlog_proof: DLogProof,
+}
+
+/// State Entity -> Owner
+/// State Entity key share S = s.G with its proof of knowledge
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct MuSigKeyGenReply {
+    #[schemars(with = "DLogProofDef")]
+    pub dlog_proof: DLogProof,
+}
+
+/// Owner -> State Entity
+/// Owner public nonces for the next signature
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct MuSigSignMsg1 {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    #[schemars(with = "PubNonceDef")]
+    pub pub_nonce: PubNonce,
+}
+
+/// State Entity -> Owner
+/// State Entity public nonces for the next signature
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct MuSigSignReply1 {
+    #[schemars(with = "PubNonceDef")]
+    pub pub_nonce: PubNonce,
+}
+
+/// Owner -> State Entity
+/// Owner partial signature of the hex encoded BIP341 sighash validated by prepare-sign-tx
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct MuSigSignMsg2 {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    pub protocol: Protocol,
+    pub message: String,
+    #[schemars(with = "FEDef")]
+    pub partial_sig: FE,
+}
+
 // Deposit algorithm structs
 
 /// Client -> SE
@@ -347,6 +531,52 @@ pub struct SignSecondMsgRequest {
 pub struct DepositMsg1 {
     pub auth: String,
     pub proof_key: String,
+    /// Solution of a deposit challenge, if the SE requires proof of work
+    pub pow_solution: Option<PowSolution>,
+    /// Prepaid deposit token, if the SE requires one
+    pub token: Option<BlindedSpendToken>,
+    /// Type of shared key to generate
+    #[serde(default)]
+    pub key_type: KeyType,
+}
+
+/// SE -> Client
+/// Proof of work challenge. A difficulty of 0 means no proof of work is required.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct PowChallenge {
+    pub challenge: String,
+    pub difficulty: u32,
+}
+
+/// Client -> SE
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct PowSolution {
+    pub challenge: String,
+    pub nonce: u64,
+}
+
+/// SE -> Client
+/// Blind signing data for a new deposit token and the payment it requires
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct DepositTokenInit {
+    #[schemars(with = "UuidDef")]
+    pub token_id: Uuid,
+    #[schemars(with = "GEDef")]
+    pub r_prime: GE,
+    /// Payment required for a token (satoshis). 0 if deposit tokens are not required.
+    pub fee: u64,
+    pub fee_address: String,
+}
+
+/// Client -> SE
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct DepositTokenMsg {
+    #[schemars(with = "UuidDef")]
+    pub token_id: Uuid,
+    /// Tx paying the token fee to the fee address
+    pub payment_txid: String,
+    #[schemars(with = "FEDef")]
+    pub e_prime: FE,
 }
 
 /// Client -> SE
@@ -356,6 +586,14 @@ pub struct DepositMsg2 {
     pub shared_key_id: Uuid,
 }
 
+/// Client -> SE
+/// Confirm several deposits funded by outputs of a single funding tx
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct DepositBatchMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_ids: Vec<Uuid>,
+}
+
 #[derive(JsonSchema)]
 #[schemars(remote = "Address")]
 pub struct AddressDef(String);
@@ -383,6 +621,16 @@ pub struct TransferMsg1 {
     pub statechain_sig: StateChainSig,
 }
 
+/// Sender -> SE
+/// Cancel a transfer which the receiver has not completed. The StateChainSig has purpose
+/// "CANCEL" and signs the receiver proof key of the transfer being cancelled.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct TransferCancelMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    pub statechain_sig: StateChainSig,
+}
+
 #[derive(JsonSchema)]
 #[schemars(remote = "ecies::PublicKey")]
 pub struct PublicKeyDef(Vec<u8>);
@@ -399,12 +647,15 @@ pub struct TransferMsg2 {
 pub struct TransferMsg3 {
     #[schemars(with = "UuidDef")]
     pub shared_key_id: Uuid,
-    pub t1: FESer, // t1 = o1x1
+    pub t1: FESer, // t1 = o1x1, or o1 + x1 for Taproot keys
     pub statechain_sig: StateChainSig,
     #[schemars(with = "UuidDef")]
     pub statechain_id: Uuid,
     pub tx_backup_psm: PrepareSignTxMsg,
     pub rec_se_addr: SCEAddress, // receivers state entity address (btc address and proof key)
+    /// Type of the statecoin shared key. Determines the key update t1.
+    #[serde(default)]
+    pub key_type: KeyType,
 }
 
 #[derive(JsonSchema)]
@@ -442,7 +693,9 @@ pub struct TransferMsg4 {
 pub struct KUSendMsg {
     pub user_id: Uuid,
     pub statechain_id: Uuid,
-    pub x1: FE,
+    /// Encrypted to the lockbox public key
+    pub x1: FESer,
+    /// Encrypted to the public key of the sender's private key share
     pub t2: FESer,
     pub o2_pub: GE,
 }
@@ -502,9 +755,40 @@ pub struct BatchData {
 }
 
 // Withdraw algorithm structs
+/// Withdraw tx output paying amount to address
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct WithdrawOutput {
+    pub address: String,
+    pub amount: u64,
+}
+
 /// Owner -> State Entity
+/// Withdraw one or more statecoins in a single transaction. The i'th StateChainSig
+/// authorises withdrawal of the statecoin owned by the i'th shared key.
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct WithdrawMsg1 {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_ids: Vec<Uuid>,
+    pub statechain_sigs: Vec<StateChainSig>,
+    /// Outputs of the withdraw tx, excluding the State Entity fee output. Each
+    /// StateChainSig signs the withdraw_outputs_commitment of these outputs.
+    pub outputs: Vec<WithdrawOutput>,
+}
+
+/// Owner -> State Entity
+/// The sparse merkle tree entry of each withdrawn statecoin is the withdraw outputs
+/// commitment signed in its StateChainSig.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct WithdrawMsg2 {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_ids: Vec<Uuid>,
+}
+
+/// Owner -> State Entity
+/// Request refresh of a statecoin. The StateChainSig has purpose "REFRESH" and signs the
+/// owner's current proof key.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct RefreshMsg1 {
     #[schemars(with = "UuidDef")]
     pub shared_key_id: Uuid,
     pub statechain_sig: StateChainSig,
@@ -512,10 +796,70 @@ pub struct WithdrawMsg1 {
 
 /// Owner -> State Entity
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
-pub struct WithdrawMsg2 {
+pub struct RefreshMsg2 {
     #[schemars(with = "UuidDef")]
     pub shared_key_id: Uuid,
-    pub address: String,
+}
+
+// Admin API structs
+
+/// A user session listed by the admin API
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UserSessionAPI {
+    pub id: Uuid,
+    /// Statechain of the session, once deposited or transferred to
+    pub statechain_id: Option<Uuid>,
+    pub proof_key: Option<String>,
+    /// True if a withdrawal has been authorised but not confirmed
+    pub withdraw_pending: bool,
+    /// True if a refresh has been authorised but not confirmed
+    pub refresh_pending: bool,
+}
+
+/// /admin/sessions return struct. A page of user sessions.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserSessionListAPI {
+    pub sessions: Vec<UserSessionAPI>,
+    /// Page number, from 0
+    pub page: u64,
+    pub per_page: u64,
+    /// Number of user sessions across all pages
+    pub total: u64,
+}
+
+/// /admin/statechain return struct
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdminStateChainAPI {
+    pub statechain_id: Uuid,
+    /// User session of the current owner
+    pub owner_id: Uuid,
+    pub amount: u64,
+    pub chain: Vec<State>,
+    pub locked_until: NaiveDateTime,
+    /// Backup tx of the current owner
+    pub tx_backup: Option<Transaction>,
+    /// StateChainSig of a transfer started by the owner and not yet completed. The State
+    /// Entity's key share of the transfer is not returned.
+    pub transfer_sig: Option<StateChainSig>,
+    /// Swap the statechain is registered in, if any
+    pub swap_id: Option<Uuid>,
+}
+
+/// Admin -> State Entity
+/// Lock a statechain for seconds, or unlock it if seconds is 0
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdminLockMsg {
+    pub statechain_id: Uuid,
+    pub seconds: u64,
+}
+
+/// Admin -> State Entity
+/// End a batch transfer before its lifetime. Its statechains are punished if punish is set and
+/// unlocked otherwise.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdminCancelBatchMsg {
+    pub batch_id: Uuid,
+    pub punish: bool,
 }
 
 impl Default for TransferMsg5 {
@@ -599,6 +943,20 @@ impl WalletDecryptable for &mut TransferMsg2 {
     }
 }
 
+impl Encryptable for KUSendMsg {}
+impl SelfEncryptable for KUSendMsg {
+    fn decrypt(&mut self, privkey: &crate::ecies::PrivateKey) -> crate::ecies::Result<()> {
+        self.x1.decrypt(privkey)
+    }
+
+    fn encrypt_with_pubkey(
+        &mut self,
+        pubkey: &crate::ecies::PublicKey,
+    ) -> crate::ecies::Result<()> {
+        self.x1.encrypt_with_pubkey(pubkey)
+    }
+}
+
 impl Encryptable for TransferMsg3 {}
 impl SelfEncryptable for TransferMsg3 {
     fn decrypt(&mut self, privkey: &crate::ecies::PrivateKey) -> crate::ecies::Result<()> {
@@ -708,6 +1066,7 @@ mod tests {
                 ),
                 proof_key: PublicKey::from_secret_key(&secp, &SecretKey::new(&mut rng)),
             },
+            key_type: KeyType::Ecdsa,
         };
 
         let msg_clone = msg.clone();
@@ -749,4 +1108,18 @@ mod tests {
         msg_ref.decrypt(&priv_k).unwrap();
         assert_eq!(msg_ref, &msg_clone);
     }
+
+    #[test]
+    fn test_statechain_status_from_str() {
+        for status in vec![
+            StateChainStatus::Active,
+            StateChainStatus::Locked,
+            StateChainStatus::InSwap,
+            StateChainStatus::Withdrawn,
+            StateChainStatus::ClosedByBackup,
+        ] {
+            assert_eq!(StateChainStatus::from_str(status.as_str()).unwrap(), status);
+        }
+        assert!(StateChainStatus::from_str("spent").is_err());
+    }
 }
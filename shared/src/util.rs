@@ -4,13 +4,14 @@
 
 use super::Result;
 use crate::error::SharedLibError;
-use crate::structs::PrepareSignTxMsg;
+use crate::structs::{PrepareSignTxMsg, WithdrawOutput};
 #[cfg(test)]
 use crate::Verifiable;
+#[cfg(test)]
+use bitcoin::Txid;
 
 use bitcoin::{
     hashes::sha256d::Hash,
-    Txid,
     {util::bip143::SigHashCache, OutPoint},
     {Address, Network, Transaction, TxIn, TxOut}, consensus,
 };
@@ -23,6 +24,12 @@ pub const RBF: u32 = 0xffffffff - 2;
 pub const DUSTLIMIT: u64 = 100;
 /// Temporary - fees should be calculated dynamically
 pub const FEE: u64 = 1000;
+/// BIP68: if set, the input nSequence does not encode a relative locktime
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// BIP68: if set, the relative locktime is in units of 512 seconds rather than blocks
+const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+/// BIP68: bits of the input nSequence holding the relative locktime value
+const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
 
 pub fn reverse_hex_str(hex_str: String) -> Result<String> {
     if hex_str.len() % 2 != 0 {
@@ -59,6 +66,21 @@ pub fn transaction_deserialise(ser: &String) -> Result<Transaction> {
     }
 }
 
+/// Get the p2wpkh address of a (shared) public key
+pub fn get_p2wpkh_address(address_pk: &PK, network: &String) -> Result<Address> {
+    let pk_btc = bitcoin::secp256k1::PublicKey::from_slice(&address_pk.serialize())?;
+    let network = network
+        .parse::<Network>()
+        .map_err(|_| SharedLibError::FormatError(format!("Invalid network: {}", network)))?;
+    Ok(bitcoin::Address::p2wpkh(
+        &bitcoin::util::key::PublicKey {
+            compressed: true,
+            key: pk_btc,
+        },
+        network,
+    )?)
+}
+
 /// Get sig hash for some transaction input.
 /// Arguments: tx, index of input, address being spent from and amount
 pub fn get_sighash(
@@ -99,21 +121,98 @@ pub fn tx_withdraw_verify(
             "Withdraw tx number of signing addresses != number of input amounts.",
         )));
     }
-    // Check fee info
     let tx = transaction_deserialise(&tx_psm.tx_hex)?;
-    if tx.output[1].script_pubkey != Address::from_str(fee_address)?.script_pubkey() {
+    if tx.input.len() != tx_psm.input_addrs.len() {
+        return Err(SharedLibError::FormatError(String::from(
+            "Withdraw tx number of inputs != number of signing addresses.",
+        )));
+    }
+    // Check fee info. The fee output may be at any position and withdraw outputs may also
+    // pay to the fee address.
+    let fee_script = Address::from_str(fee_address)?.script_pubkey();
+    let fee_outputs: Vec<&TxOut> = tx
+        .output
+        .iter()
+        .filter(|output| output.script_pubkey == fee_script)
+        .collect();
+    if fee_outputs.len() == 0 {
         return Err(SharedLibError::FormatError(String::from(
             "Incorrect State Entity fee address.",
         )));
     }
-    if tx.output[1].value != fee_withdraw.to_owned() {
+    if !fee_outputs
+        .iter()
+        .any(|output| output.value == fee_withdraw.to_owned())
+    {
         return Err(SharedLibError::FormatError(String::from(
             "Incorrect State Entity fee.",
         )));
     }
+    // Check outputs do not spend more than the inputs
+    let total_in: u64 = tx_psm.input_amounts.iter().sum();
+    let total_out: u64 = tx.output.iter().map(|output| output.value).sum();
+    if total_out > total_in {
+        return Err(SharedLibError::FormatError(String::from(
+            "Tx outputs value greater than inputs value.",
+        )));
+    }
+    Ok(())
+}
+
+/// Check the outputs of a withdraw tx other than the State Entity fee output are exactly the
+/// withdraw outputs, in order. Only the first output paying fee_withdraw to the fee address is
+/// taken as the fee output, so a withdraw output to the fee address must still be committed to.
+pub fn tx_withdraw_verify_outputs(
+    tx: &Transaction,
+    fee_address: &String,
+    fee_withdraw: &u64,
+    outputs: &Vec<WithdrawOutput>,
+) -> Result<()> {
+    let fee_script = Address::from_str(fee_address)?.script_pubkey();
+    let fee_index = match tx.output.iter().position(|output| {
+        output.script_pubkey == fee_script && output.value == fee_withdraw.to_owned()
+    }) {
+        Some(index) => index,
+        None => {
+            return Err(SharedLibError::FormatError(String::from(
+                "Incorrect State Entity fee.",
+            )))
+        }
+    };
+    let tx_outputs: Vec<&TxOut> = tx
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != fee_index)
+        .map(|(_, output)| output)
+        .collect();
+    if tx_outputs.len() != outputs.len() {
+        return Err(SharedLibError::FormatError(String::from(
+            "Withdraw tx outputs do not match the committed withdraw outputs.",
+        )));
+    }
+    for (tx_output, output) in tx_outputs.iter().zip(outputs.iter()) {
+        if tx_output.script_pubkey != Address::from_str(&output.address)?.script_pubkey()
+            || tx_output.value != output.amount
+        {
+            return Err(SharedLibError::FormatError(String::from(
+                "Withdraw tx outputs do not match the committed withdraw outputs.",
+            )));
+        }
+    }
     Ok(())
 }
 
+/// Commitment to a list of withdraw outputs signed in the StateChainSig of a withdrawal.
+/// Every output commits to both its address and its amount.
+pub fn withdraw_outputs_commitment(outputs: &Vec<WithdrawOutput>) -> String {
+    outputs
+        .iter()
+        .map(|output| format!("{}:{}", output.address, output.amount))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
 /// Build funding tx spending inputs to p2wpkh address P for amount A
 pub fn tx_funding_build(
     inputs: &Vec<TxIn>,
@@ -124,22 +223,49 @@ pub fn tx_funding_build(
     change_addr: &String,
     change_amount: &u64,
 ) -> Result<Transaction> {
-    if FEE + fee >= *amount {
+    tx_funding_build_batch(
+        inputs,
+        &vec![(p_address.to_owned(), *amount)],
+        fee,
+        fee_addr,
+        change_addr,
+        change_amount,
+    )
+}
+
+/// Build funding tx spending inputs to each of the p2wpkh addresses P_i for amount A_i. The
+/// i'th shared key output is at vout i.
+pub fn tx_funding_build_batch(
+    inputs: &Vec<TxIn>,
+    p_outputs: &Vec<(String, u64)>,
+    fee: &u64,
+    fee_addr: &String,
+    change_addr: &String,
+    change_amount: &u64,
+) -> Result<Transaction> {
+    if p_outputs.len() == 0 {
+        return Err(SharedLibError::FormatError(String::from(
+            "No shared key outputs in funding tx.",
+        )));
+    }
+    let amount: u64 = p_outputs.iter().map(|(_, amount)| amount).sum();
+    if FEE + fee >= amount {
         return Err(SharedLibError::FormatError(String::from(
             "Not enough value to cover fee.",
         )));
     }
 
-    let mut outputs = vec![
-        TxOut {
+    let mut outputs = vec![];
+    for (p_address, amount) in p_outputs {
+        outputs.push(TxOut {
             script_pubkey: Address::from_str(p_address)?.script_pubkey(),
             value: *amount,
-        },
-        TxOut {
-            script_pubkey: Address::from_str(change_addr)?.script_pubkey(),
-            value: *change_amount - FEE,
-        },
-    ];
+        });
+    }
+    outputs.push(TxOut {
+        script_pubkey: Address::from_str(change_addr)?.script_pubkey(),
+        value: *change_amount - FEE,
+    });
 
     if *fee != 0 {
         outputs.push(
@@ -158,9 +284,9 @@ pub fn tx_funding_build(
     Ok(tx_0)
 }
 
-/// Build backup tx spending P output of funding tx to given backup address
+/// Build backup tx spending P output of funding tx at funding_outpoint to given backup address
 pub fn tx_backup_build(
-    funding_txid: &Txid,
+    funding_outpoint: &OutPoint,
     b_address: &Address,
     amount: &u64,
     locktime: &u32,
@@ -174,10 +300,7 @@ pub fn tx_backup_build(
     }
 
     let txin = TxIn {
-        previous_output: OutPoint {
-            txid: *funding_txid,
-            vout: 0,
-        },
+        previous_output: *funding_outpoint,
         sequence: 0xFFFFFFFF,
         witness: Vec::new(),
         script_sig: bitcoin::Script::default(),
@@ -201,11 +324,156 @@ pub fn tx_backup_build(
     Ok(tx_b)
 }
 
+/// Encode a BIP68 relative locktime of some number of blocks as an input nSequence
+pub fn sequence_from_blocks(blocks: &u32) -> Result<u32> {
+    if *blocks > SEQUENCE_LOCKTIME_MASK {
+        return Err(SharedLibError::FormatError(format!(
+            "Relative locktime of {} blocks exceeds maximum of {}.",
+            blocks, SEQUENCE_LOCKTIME_MASK
+        )));
+    }
+    Ok(*blocks)
+}
+
+/// Decode the number of blocks of a BIP68 relative locktime from an input nSequence.
+/// Returns None if the nSequence disables relative locktime or specifies a time in seconds.
+pub fn blocks_from_sequence(sequence: &u32) -> Option<u32> {
+    if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0
+        || sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0
+    {
+        return None;
+    }
+    Some(sequence & SEQUENCE_LOCKTIME_MASK)
+}
+
+/// Build kick-off tx spending P output of funding tx at funding_outpoint to a new P output.
+/// Back up txs spend the kick-off tx with a relative locktime, so the statecoin does not expire
+/// until the kick-off tx is broadcast.
+pub fn tx_kickoff_build(
+    funding_outpoint: &OutPoint,
+    p_address: &Address,
+    amount: &u64,
+) -> Result<Transaction> {
+    if FEE >= *amount {
+        return Err(SharedLibError::FormatError(String::from(
+            "Not enough value to cover fee.",
+        )));
+    }
+
+    let txin = TxIn {
+        previous_output: *funding_outpoint,
+        sequence: 0xFFFFFFFF,
+        witness: Vec::new(),
+        script_sig: bitcoin::Script::default(),
+    };
+
+    let tx_k = Transaction {
+        input: vec![txin],
+        output: vec![TxOut {
+            script_pubkey: p_address.script_pubkey(),
+            value: amount - FEE,
+        }],
+        lock_time: 0,
+        version: 2,
+    };
+    Ok(tx_k)
+}
+
+/// Build backup tx spending P output of kick-off tx to given backup address. The backup tx is
+/// valid 'blocks' blocks after the kick-off tx is confirmed.
+pub fn tx_backup_build_relative(
+    tx_kickoff: &Transaction,
+    b_address: &Address,
+    blocks: &u32,
+    fee: &u64,
+    fee_addr: &String,
+) -> Result<Transaction> {
+    let kickoff_outpoint = OutPoint {
+        txid: tx_kickoff.txid(),
+        vout: 0,
+    };
+    let mut tx_b = tx_backup_build(
+        &kickoff_outpoint,
+        b_address,
+        &tx_kickoff.output[0].value,
+        &0,
+        fee,
+        fee_addr,
+    )?;
+    tx_b.input[0].sequence = sequence_from_blocks(blocks)?;
+    Ok(tx_b)
+}
+
+/// Build CPFP child tx spending the State Entity fee output of a broadcast backup tx back to
+/// fee_addr, paying 'fee'. The child signals RBF so that it can be replaced to bump the fee again.
+pub fn tx_cpfp_build(tx_backup: &Transaction, fee_addr: &String, fee: &u64) -> Result<Transaction> {
+    let fee_script = Address::from_str(fee_addr)?.script_pubkey();
+    let vout = match tx_backup
+        .output
+        .iter()
+        .position(|output| output.script_pubkey == fee_script)
+    {
+        Some(v) => v,
+        None => {
+            return Err(SharedLibError::FormatError(String::from(
+                "Backup tx has no output to fee address.",
+            )))
+        }
+    };
+    let value = tx_backup.output[vout].value;
+    if *fee + DUSTLIMIT >= value {
+        return Err(SharedLibError::FormatError(String::from(
+            "Not enough value to cover fee.",
+        )));
+    }
+
+    let txin = TxIn {
+        previous_output: OutPoint {
+            txid: tx_backup.txid(),
+            vout: vout as u32,
+        },
+        sequence: RBF,
+        witness: Vec::new(),
+        script_sig: bitcoin::Script::default(),
+    };
+
+    let tx_c = Transaction {
+        input: vec![txin],
+        output: vec![TxOut {
+            script_pubkey: fee_script,
+            value: value - fee,
+        }],
+        lock_time: 0,
+        version: 2,
+    };
+    Ok(tx_c)
+}
+
 /// Build withdraw tx spending funding tx to:
 ///     - amount-fee to receive address, and
 ///     - amount 'fee' to State Entity fee address 'fee_addr'
 pub fn tx_withdraw_build(
-    funding_txid: &Txid,
+    funding_outpoint: &OutPoint,
+    rec_se_address: &Address,
+    amount: &u64,
+    fee: &u64,
+    fee_addr: &String,
+) -> Result<Transaction> {
+    tx_withdraw_build_batch(
+        &vec![*funding_outpoint],
+        rec_se_address,
+        amount,
+        fee,
+        fee_addr,
+    )
+}
+
+/// Build withdraw tx spending each of the funding outpoints to:
+///     - total amount-fee to receive address, and
+///     - amount 'fee' to State Entity fee address 'fee_addr'
+/// 'amount' and 'fee' are the totals over all inputs.
+pub fn tx_withdraw_build_batch(
+    funding_outpoints: &Vec<OutPoint>,
     rec_se_address: &Address,
     amount: &u64,
     fee: &u64,
@@ -217,30 +485,70 @@ pub fn tx_withdraw_build(
         )));
     }
 
-    let txin = TxIn {
-        previous_output: OutPoint {
-            txid: *funding_txid,
-            vout: 0,
-        },
-        sequence: 0xFFFFFFFF,
-        witness: Vec::new(),
-        script_sig: bitcoin::Script::default(),
-    };
+    tx_withdraw_build_outputs(
+        funding_outpoints,
+        &vec![WithdrawOutput {
+            address: rec_se_address.to_string(),
+            amount: amount - *fee - FEE,
+        }],
+        fee,
+        fee_addr,
+    )
+}
+
+/// Build withdraw tx spending each of the funding outpoints to:
+///     - each of 'outputs', and
+///     - amount 'fee' to State Entity fee address 'fee_addr'
+pub fn tx_withdraw_build_outputs(
+    funding_outpoints: &Vec<OutPoint>,
+    outputs: &Vec<WithdrawOutput>,
+    fee: &u64,
+    fee_addr: &String,
+) -> Result<Transaction> {
+    if funding_outpoints.len() == 0 {
+        return Err(SharedLibError::FormatError(String::from(
+            "No funding transactions to withdraw.",
+        )));
+    }
+    if outputs.len() == 0 {
+        return Err(SharedLibError::FormatError(String::from(
+            "No withdraw outputs.",
+        )));
+    }
+
+    let inputs = funding_outpoints
+        .iter()
+        .map(|outpoint| TxIn {
+            previous_output: *outpoint,
+            sequence: 0xFFFFFFFF,
+            witness: Vec::new(),
+            script_sig: bitcoin::Script::default(),
+        })
+        .collect();
+
+    let mut tx_outputs = vec![];
+    for output in outputs {
+        if output.amount < DUSTLIMIT {
+            return Err(SharedLibError::FormatError(format!(
+                "Withdraw output to {} below dust limit.",
+                output.address
+            )));
+        }
+        tx_outputs.push(TxOut {
+            script_pubkey: Address::from_str(&output.address)?.script_pubkey(),
+            value: output.amount,
+        });
+    }
+    tx_outputs.push(TxOut {
+        script_pubkey: Address::from_str(fee_addr)?.script_pubkey(),
+        value: *fee,
+    });
 
     let tx_0 = Transaction {
         version: 2,
         lock_time: 0,
-        input: vec![txin.clone()],
-        output: vec![
-            TxOut {
-                script_pubkey: rec_se_address.script_pubkey(),
-                value: amount - *fee - FEE,
-            },
-            TxOut {
-                script_pubkey: Address::from_str(fee_addr)?.script_pubkey(),
-                value: *fee,
-            },
-        ],
+        input: inputs,
+        output: tx_outputs,
     };
     Ok(tx_0)
 }
@@ -333,6 +641,217 @@ pub mod tests {
         // println!("{}", serde_json::to_string_pretty(&tx_1).unwrap());
     }
 
+    #[test]
+    fn test_tx_funding_build_batch() {
+        let fee_addr = String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x");
+        let change_addr = String::from("bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8");
+        let inputs = vec![TxIn {
+            previous_output: OutPoint::default(),
+            sequence: RBF,
+            witness: Vec::new(),
+            script_sig: Script::new(),
+        }];
+        let p_outputs = vec![
+            (fee_addr.clone(), 10000),
+            (change_addr.clone(), 20000),
+        ];
+
+        assert!(
+            tx_funding_build_batch(&inputs, &vec![], &100, &fee_addr, &change_addr, &5000).is_err()
+        );
+
+        let tx =
+            tx_funding_build_batch(&inputs, &p_outputs, &100, &fee_addr, &change_addr, &5000)
+                .unwrap();
+        // Shared key outputs first, then change and fee
+        assert_eq!(tx.output.len(), 4);
+        assert_eq!(tx.output[0].value, 10000);
+        assert_eq!(tx.output[1].value, 20000);
+        assert_eq!(tx.output[2].value, 5000 - FEE);
+        assert_eq!(tx.output[3].value, 100);
+    }
+
+    #[test]
+    fn test_tx_backup_build_relative() {
+        let (_, pub_key) = generate_keypair();
+        let p_address = Address::p2wpkh(&pub_key, NETWORK).unwrap();
+        let fee_addr = String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x");
+
+        let tx_k = tx_kickoff_build(&OutPoint::default(), &p_address, &10000).unwrap();
+        assert_eq!(tx_k.output.len(), 1);
+        assert_eq!(tx_k.output[0].value, 10000 - FEE);
+        assert_eq!(tx_k.output[0].script_pubkey, p_address.script_pubkey());
+
+        let tx_b = tx_backup_build_relative(&tx_k, &p_address, &144, &100, &fee_addr).unwrap();
+        assert_eq!(tx_b.input[0].previous_output.txid, tx_k.txid());
+        assert_eq!(tx_b.input[0].previous_output.vout, 0);
+        assert_eq!(tx_b.lock_time, 0);
+        assert_eq!(blocks_from_sequence(&tx_b.input[0].sequence), Some(144));
+        assert_eq!(tx_b.output[0].value, 10000 - 2 * FEE - 100);
+
+        // Relative locktime must fit in the nSequence
+        assert!(tx_backup_build_relative(&tx_k, &p_address, &0x10000, &100, &fee_addr).is_err());
+        // Disabled or time based relative locktimes are not block heights
+        assert_eq!(blocks_from_sequence(&0xFFFFFFFF), None);
+        assert_eq!(blocks_from_sequence(&(SEQUENCE_LOCKTIME_TYPE_FLAG | 144)), None);
+    }
+
+    #[test]
+    fn test_tx_cpfp_build() {
+        let (_, pub_key) = generate_keypair();
+        let b_address = Address::p2wpkh(&pub_key, NETWORK).unwrap();
+        let fee_addr = String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x");
+
+        let tx_b =
+            tx_backup_build(&OutPoint::default(), &b_address, &100000, &0, &5000, &fee_addr)
+                .unwrap();
+        let tx_c = tx_cpfp_build(&tx_b, &fee_addr, &2000).unwrap();
+        assert_eq!(tx_c.input.len(), 1);
+        assert_eq!(tx_c.input[0].previous_output.txid, tx_b.txid());
+        assert_eq!(tx_c.input[0].previous_output.vout, 1);
+        assert_eq!(tx_c.input[0].sequence, RBF);
+        assert_eq!(tx_c.output.len(), 1);
+        assert_eq!(tx_c.output[0].value, 3000);
+        assert_eq!(tx_c.output[0].script_pubkey, tx_b.output[1].script_pubkey);
+
+        // Fee must leave a non-dust output
+        assert!(tx_cpfp_build(&tx_b, &fee_addr, &5000).is_err());
+        // Backup tx must pay the fee address
+        let (_, other_pub_key) = generate_keypair();
+        let other_addr = Address::p2wpkh(&other_pub_key, NETWORK).unwrap().to_string();
+        assert!(tx_cpfp_build(&tx_b, &other_addr, &2000).is_err());
+    }
+
+    #[test]
+    fn test_tx_withdraw_build_batch() {
+        let (_, pub_key) = generate_keypair();
+        let rec_address = Address::p2wpkh(&pub_key, NETWORK).unwrap();
+        let fee_addr = String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x");
+        let funding_txid =
+            Txid::from_str("e0a97cb38e7e73617ef75a57eaf2841eb06833407c0eae08029bd04ea7e6115a")
+                .unwrap();
+        // Two statecoins from the same funding tx and one from another
+        let funding_outpoints = vec![
+            OutPoint { txid: funding_txid, vout: 0 },
+            OutPoint { txid: funding_txid, vout: 1 },
+            OutPoint {
+                txid: Txid::from_str(
+                    "a3a0b73a9b0a0a3cb5d2b44a2ebfbb8b93cb1c25e3ae7da8c4d3ef14fa6fba81",
+                )
+                .unwrap(),
+                vout: 0,
+            },
+        ];
+
+        assert!(
+            tx_withdraw_build_batch(&vec![], &rec_address, &100000, &100, &fee_addr).is_err()
+        );
+        assert!(
+            tx_withdraw_build_batch(&funding_outpoints, &rec_address, &1000, &100, &fee_addr)
+                .is_err()
+        );
+
+        let tx =
+            tx_withdraw_build_batch(&funding_outpoints, &rec_address, &100000, &100, &fee_addr)
+                .unwrap();
+        assert_eq!(tx.input.len(), 3);
+        for (input, outpoint) in tx.input.iter().zip(funding_outpoints.iter()) {
+            assert_eq!(input.previous_output, *outpoint);
+        }
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[0].value, 100000 - 100 - FEE);
+        assert_eq!(tx.output[1].value, 100);
+    }
+
+    #[test]
+    fn test_tx_withdraw_build_outputs() {
+        let (_, pub_key) = generate_keypair();
+        let rec_address = Address::p2wpkh(&pub_key, NETWORK).unwrap();
+        let fee_addr = String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x");
+        let funding_outpoints = vec![OutPoint {
+            txid: Txid::from_str(
+                "e0a97cb38e7e73617ef75a57eaf2841eb06833407c0eae08029bd04ea7e6115a",
+            )
+            .unwrap(),
+            vout: 0,
+        }];
+        let outputs = vec![
+            WithdrawOutput {
+                address: rec_address.to_string(),
+                amount: 40000,
+            },
+            WithdrawOutput {
+                address: String::from("bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8"),
+                amount: 50000,
+            },
+        ];
+
+        let tx = tx_withdraw_build_outputs(&funding_outpoints, &outputs, &100, &fee_addr).unwrap();
+        assert_eq!(tx.output.len(), 3);
+        assert_eq!(tx.output[0].value, 40000);
+        assert_eq!(tx.output[1].value, 50000);
+        assert_eq!(tx.output[2].value, 100);
+
+        let mut tx_psm = PrepareSignTxMsg::default();
+        tx_psm.tx_hex = transaction_serialise(&tx);
+        tx_psm.input_addrs = vec![PK::from_slice(&pub_key.key.serialize()).unwrap()];
+        tx_psm.input_amounts = vec![100000];
+        assert!(tx_withdraw_verify(&tx_psm, &fee_addr, &100).is_ok());
+        // Incorrect fee
+        assert!(tx_withdraw_verify(&tx_psm, &fee_addr, &200).is_err());
+        // Outputs greater than inputs
+        tx_psm.input_amounts = vec![90000];
+        assert!(tx_withdraw_verify(&tx_psm, &fee_addr, &100).is_err());
+
+        // Non-fee outputs must be exactly the committed outputs
+        assert!(tx_withdraw_verify_outputs(&tx, &fee_addr, &100, &outputs).is_ok());
+        assert!(tx_withdraw_verify_outputs(&tx, &fee_addr, &200, &outputs).is_err());
+        assert!(tx_withdraw_verify_outputs(&tx, &fee_addr, &100, &outputs[..1].to_vec()).is_err());
+        let mut other_amounts = outputs.clone();
+        other_amounts[1].amount = 49000;
+        assert!(tx_withdraw_verify_outputs(&tx, &fee_addr, &100, &other_amounts).is_err());
+        let mut reordered = outputs.clone();
+        reordered.reverse();
+        assert!(tx_withdraw_verify_outputs(&tx, &fee_addr, &100, &reordered).is_err());
+
+        // A committed output to the fee address is not taken as the fee output
+        let mut fee_addr_outputs = outputs.clone();
+        fee_addr_outputs[1].address = fee_addr.clone();
+        let tx_fee_addr =
+            tx_withdraw_build_outputs(&funding_outpoints, &fee_addr_outputs, &100, &fee_addr)
+                .unwrap();
+        tx_psm.tx_hex = transaction_serialise(&tx_fee_addr);
+        tx_psm.input_amounts = vec![100000];
+        assert!(tx_withdraw_verify(&tx_psm, &fee_addr, &100).is_ok());
+        assert!(
+            tx_withdraw_verify_outputs(&tx_fee_addr, &fee_addr, &100, &fee_addr_outputs).is_ok()
+        );
+        assert!(tx_withdraw_verify_outputs(
+            &tx_fee_addr,
+            &fee_addr,
+            &100,
+            &fee_addr_outputs[..1].to_vec()
+        )
+        .is_err());
+
+        // Dust output
+        let mut dust_outputs = outputs.clone();
+        dust_outputs[1].amount = DUSTLIMIT - 1;
+        assert!(tx_withdraw_build_outputs(&funding_outpoints, &dust_outputs, &100, &fee_addr).is_err());
+
+        assert_eq!(
+            withdraw_outputs_commitment(&outputs[..1].to_vec()),
+            format!("{}:40000", rec_address)
+        );
+        assert_eq!(
+            withdraw_outputs_commitment(&outputs),
+            format!(
+                "{}:40000,bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8:50000",
+                rec_address
+            )
+        );
+    }
+
     #[test]
     fn sign() {
         let secp = Secp256k1::new();